@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type EventLevel = "Info" | "Warning" | "Error";
\ No newline at end of file
+export type EventLevel = "Info" | "Warning" | "Error" | "Critical";
\ No newline at end of file
@@ -7,4 +7,4 @@ import type { TimeRange } from "./TimeRange";
 import type { UserEventKind } from "./UserEventKind";
 import type { UserId } from "./UserId";
 
-export interface EventQuery { event_levels: Array<EventLevel> | null, event_types: Array<EventType> | null, instance_event_types: Array<InstanceEventKind> | null, user_event_types: Array<UserEventKind> | null, event_user_ids: Array<UserId> | null, event_instance_ids: Array<InstanceUuid> | null, bearer_token: string | null, time_range: TimeRange | null, }
\ No newline at end of file
+export interface EventQuery { event_levels: Array<EventLevel> | null, event_types: Array<EventType> | null, instance_event_types: Array<InstanceEventKind> | null, user_event_types: Array<UserEventKind> | null, event_user_ids: Array<UserId> | null, event_instance_ids: Array<InstanceUuid> | null, bearer_token: string | null, time_range: TimeRange | null, notifications_only: boolean, }
\ No newline at end of file
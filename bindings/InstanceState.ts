@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type InstanceState = "Starting" | "Running" | "Stopping" | "Stopped" | "Error";
\ No newline at end of file
+export type InstanceState = "Starting" | "Running" | "Stopping" | "Stopped" | "Error" | "Crashed" | "Installing";
\ No newline at end of file
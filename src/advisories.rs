@@ -0,0 +1,401 @@
+//! Known vulnerabilities of game server versions, e.g. Log4Shell, and how to mitigate them.
+//!
+//! A dataset of advisories is bundled with the core, and a newer revision can be fetched online
+//! and is kept in the stores directory. Instances on an affected version list the advisory in
+//! their info until it is mitigated.
+
+use std::{
+    cmp::Ordering,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tracing::info;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::{version_switch::VersionChange, MinecraftInstance},
+    traits::{
+        t_configurable::{Game, MinecraftVariant, TConfigurable},
+        TInstance,
+    },
+};
+
+const BUNDLED_ADVISORIES: &str = include_str!("advisories.json");
+/// where the dataset is refreshed from unless another url is given
+pub const ADVISORY_FEED_URL: &str =
+    "https://raw.githubusercontent.com/Lodestone-Team/lodestone_core/main/src/advisories.json";
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TS, JsonSchema,
+)]
+#[ts(export)]
+pub enum AdvisorySeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// One step of a mitigation, the steps of a mitigation are applied in order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS, JsonSchema)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum MitigationStep {
+    /// add a flag to the java command line of the server
+    JvmFlag { flag: String },
+    /// download a file into the instance directory
+    DownloadFile {
+        url: String,
+        file_name: String,
+        sha1: String,
+    },
+    /// switch the server to a patched version
+    ChangeVersion { version: String },
+}
+
+impl MitigationStep {
+    fn validate(&self) -> Result<(), Error> {
+        let valid = match self {
+            MitigationStep::JvmFlag { flag } => {
+                flag.starts_with("-D") && !flag.contains(char::is_whitespace)
+            }
+            MitigationStep::DownloadFile {
+                url,
+                file_name,
+                sha1,
+            } => {
+                url.starts_with("https://")
+                    && !file_name.is_empty()
+                    && !file_name.starts_with('.')
+                    && !file_name.contains(['/', '\\'])
+                    && sha1.len() == 40
+                    && sha1.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            MitigationStep::ChangeVersion { version } => parse_version(version).is_some(),
+        };
+        if !valid {
+            return Err(eyre!("Invalid mitigation step {self:?}").into());
+        }
+        Ok(())
+    }
+}
+
+/// The versions, inclusive, affected by an advisory and how to mitigate it on them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct AffectedVersions {
+    /// the variants affected, all of them if empty
+    #[serde(default)]
+    pub variants: Vec<MinecraftVariant>,
+    pub min_version: String,
+    pub max_version: String,
+    pub steps: Vec<MitigationStep>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct Advisory {
+    /// the CVE id, if there is one
+    pub id: String,
+    pub title: String,
+    pub severity: AdvisorySeverity,
+    pub description: String,
+    pub url: Option<String>,
+    pub affected: Vec<AffectedVersions>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct AdvisoryDataset {
+    /// bumped on every change, a dataset only replaces one of a lower revision
+    pub revision: u32,
+    pub advisories: Vec<Advisory>,
+}
+
+impl AdvisoryDataset {
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let dataset: AdvisoryDataset =
+            serde_json::from_str(json).context("Failed to parse advisory dataset")?;
+        for advisory in &dataset.advisories {
+            for affected in &advisory.affected {
+                if parse_version(&affected.min_version).is_none()
+                    || parse_version(&affected.max_version).is_none()
+                {
+                    return Err(
+                        eyre!("Advisory {} has an invalid version range", advisory.id).into(),
+                    );
+                }
+                for step in &affected.steps {
+                    step.validate()?;
+                }
+            }
+        }
+        Ok(dataset)
+    }
+}
+
+/// An advisory an instance is affected by and hasn't mitigated, with the steps to mitigate it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS, JsonSchema)]
+#[ts(export)]
+pub struct InstanceAdvisory {
+    pub id: String,
+    pub title: String,
+    pub severity: AdvisorySeverity,
+    pub description: String,
+    pub url: Option<String>,
+    pub mitigation: Vec<MitigationStep>,
+}
+
+/// The numeric components of a release version, `None` for snapshots and the like
+fn parse_version(version: &str) -> Option<Vec<u32>> {
+    version
+        .split('.')
+        .map(|component| component.parse().ok())
+        .collect()
+}
+
+/// Compare versions component by component, a missing component being 0
+fn compare_versions(a: &[u32], b: &[u32]) -> Ordering {
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+impl AffectedVersions {
+    fn contains(&self, variant: &MinecraftVariant, version: &[u32]) -> bool {
+        let (min_version, max_version) = match (
+            parse_version(&self.min_version),
+            parse_version(&self.max_version),
+        ) {
+            (Some(min_version), Some(max_version)) => (min_version, max_version),
+            _ => return false,
+        };
+        (self.variants.is_empty() || self.variants.contains(variant))
+            && compare_versions(version, &min_version).is_ge()
+            && compare_versions(version, &max_version).is_le()
+    }
+
+    /// Whether the flags of the mitigation are all on the command line, a mitigation without
+    /// flags is only done once the version is out of the range
+    fn is_mitigated(&self, cmd_args: &[String]) -> bool {
+        let mut flags = self
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                MitigationStep::JvmFlag { flag } => Some(flag),
+                _ => None,
+            })
+            .peekable();
+        flags.peek().is_some() && flags.all(|flag| cmd_args.contains(flag))
+    }
+}
+
+impl AdvisoryDataset {
+    fn for_instance(
+        &self,
+        game: &Game,
+        version: &str,
+        cmd_args: &[String],
+    ) -> Vec<InstanceAdvisory> {
+        let variant = match game {
+            Game::MinecraftJava { variant } => variant,
+            _ => return Vec::new(),
+        };
+        let version = match parse_version(version) {
+            Some(version) => version,
+            None => return Vec::new(),
+        };
+        self.advisories
+            .iter()
+            .filter_map(|advisory| {
+                let affected = advisory
+                    .affected
+                    .iter()
+                    .find(|affected| affected.contains(variant, &version))?;
+                (!affected.is_mitigated(cmd_args)).then(|| InstanceAdvisory {
+                    id: advisory.id.clone(),
+                    title: advisory.title.clone(),
+                    severity: advisory.severity,
+                    description: advisory.description.clone(),
+                    url: advisory.url.clone(),
+                    mitigation: affected.steps.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+lazy_static! {
+    static ref DATASET: RwLock<Arc<AdvisoryDataset>> = RwLock::new(Arc::new(
+        AdvisoryDataset::parse(BUNDLED_ADVISORIES).expect("The bundled advisories are invalid")
+    ));
+}
+
+pub fn dataset() -> Arc<AdvisoryDataset> {
+    DATASET.read().unwrap().clone()
+}
+
+/// Use `dataset` if it is newer than the current one, returns whether it was
+fn replace_dataset(dataset: AdvisoryDataset) -> bool {
+    let mut current = DATASET.write().unwrap();
+    if dataset.revision <= current.revision {
+        return false;
+    }
+    *current = Arc::new(dataset);
+    true
+}
+
+/// The advisories an instance is affected by and hasn't mitigated
+pub fn for_instance(game: &Game, version: &str, cmd_args: &[String]) -> Vec<InstanceAdvisory> {
+    dataset().for_instance(game, version, cmd_args)
+}
+
+/// Use the dataset fetched last time if it is newer than the bundled one
+pub async fn load_cached(path: &Path) -> Result<(), Error> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let dataset = AdvisoryDataset::parse(&crate::util::fs::read_to_string(path).await?)?;
+    if replace_dataset(dataset) {
+        info!(
+            "Using advisory dataset revision {}",
+            self::dataset().revision
+        );
+    }
+    Ok(())
+}
+
+/// Fetch the dataset at `url` and keep it at `path` if it is newer than the current one.
+/// Returns the revision in use
+pub async fn refresh(http: &reqwest::Client, url: &str, path: &Path) -> Result<u32, Error> {
+    let json = http
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to fetch the advisory dataset")?
+        .text()
+        .await
+        .context("Failed to fetch the advisory dataset")?;
+    let dataset = AdvisoryDataset::parse(&json)?;
+    if dataset.revision > self::dataset().revision {
+        crate::util::fs::write_all(path, &json).await?;
+        replace_dataset(dataset);
+    }
+    Ok(self::dataset().revision)
+}
+
+/// Apply the mitigation of an advisory the instance is affected by. The server has to be
+/// stopped for a version change, and restarted for flags to take effect
+pub async fn mitigate(instance: &mut MinecraftInstance, advisory_id: &str) -> Result<(), Error> {
+    let advisory = instance
+        .advisories()
+        .await
+        .into_iter()
+        .find(|advisory| advisory.id == advisory_id)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("The instance is not affected by {advisory_id}, or it is mitigated"),
+        })?;
+    for step in advisory.mitigation {
+        match step {
+            MitigationStep::JvmFlag { flag } => instance.add_jvm_flag(&flag).await?,
+            MitigationStep::DownloadFile {
+                url,
+                file_name,
+                sha1,
+            } => {
+                let bytes = reqwest::get(&url)
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .context(format!("Failed to download {url}"))?
+                    .bytes()
+                    .await
+                    .context(format!("Failed to download {url}"))?;
+                if format!("{:x}", Sha1::digest(&bytes)) != sha1.to_lowercase() {
+                    return Err(eyre!("{file_name} doesn't match its checksum").into());
+                }
+                crate::util::fs::write_all(instance.path().await.join(&file_name), bytes).await?;
+            }
+            MitigationStep::ChangeVersion { version } => {
+                instance
+                    .switch_version(VersionChange {
+                        version,
+                        flavour: None,
+                        allow_downgrade: false,
+                    })
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdvisoryDataset, MitigationStep, BUNDLED_ADVISORIES};
+    use crate::traits::t_configurable::{Game, MinecraftVariant};
+
+    #[test]
+    fn test_for_instance() {
+        let dataset = AdvisoryDataset::parse(BUNDLED_ADVISORIES).unwrap();
+        let paper = Game::MinecraftJava {
+            variant: MinecraftVariant::Paper,
+        };
+        let advisories = dataset.for_instance(&paper, "1.16.5", &[]);
+        assert_eq!(advisories.len(), 1);
+        assert_eq!(advisories[0].id, "CVE-2021-44228");
+        assert_eq!(advisories[0].mitigation.len(), 2);
+
+        assert_eq!(
+            dataset.for_instance(&paper, "1.18", &[])[0].mitigation,
+            [MitigationStep::ChangeVersion {
+                version: "1.18.1".to_string()
+            }]
+        );
+        assert!(dataset.for_instance(&paper, "1.18.1", &[]).is_empty());
+        assert!(dataset.for_instance(&paper, "1.6.4", &[]).is_empty());
+        assert!(dataset.for_instance(&paper, "21w39a", &[]).is_empty());
+        assert!(dataset
+            .for_instance(&Game::MinecraftBedrock, "1.16.5", &[])
+            .is_empty());
+
+        // mitigated once the flag is on the command line
+        assert!(dataset
+            .for_instance(
+                &paper,
+                "1.17",
+                &["-Dlog4j2.formatMsgNoLookups=true".to_string()]
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_unsafe_steps() {
+        let dataset = |step: &str| {
+            format!(
+                r#"{{"revision":2,"advisories":[{{"id":"x","title":"x","severity":"Low","description":"x","url":null,
+                "affected":[{{"min_version":"1.0","max_version":"1.1","steps":[{step}]}}]}}]}}"#
+            )
+        };
+        assert!(
+            AdvisoryDataset::parse(&dataset(r#"{"type":"JvmFlag","flag":"-Dfoo=bar"}"#)).is_ok()
+        );
+        assert!(AdvisoryDataset::parse(&dataset(
+            r#"{"type":"JvmFlag","flag":"-javaagent:evil.jar"}"#
+        ))
+        .is_err());
+        assert!(AdvisoryDataset::parse(&dataset(
+            r#"{"type":"DownloadFile","url":"https://example.com/a.jar","file_name":"../a.jar","sha1":"02937d122c86ce73319ef9975b58896fc1b491d1"}"#
+        ))
+        .is_err());
+    }
+}
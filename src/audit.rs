@@ -0,0 +1,114 @@
+use axum::{
+    extract::{OriginalUri, State},
+    http::{header, Method, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    events::{AuditEvent, CausedBy, Event, EventInner},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+fn is_mutating(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+/// The segment following `instance`, e.g. the uuid of `/instance/:uuid/start`
+pub(crate) fn instance_segment(path: &str) -> Option<&str> {
+    let mut segments = path.split('/');
+    segments.find(|segment| *segment == "instance")?;
+    segments.next().filter(|segment| !segment.is_empty())
+}
+
+/// Record every mutating API call as an audit event, with who made it and how it ended
+pub async fn record_audit_event<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().clone();
+    if !is_mutating(&method) {
+        return next.run(request).await;
+    }
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string());
+    let user = match token {
+        Some(token) => state.users_manager.read().await.try_auth(&token),
+        None => None,
+    };
+    // resolved before the call, the instance is gone once it is deleted
+    let instance_uuid = match instance_segment(&path) {
+        Some(segment) => {
+            let instance_uuid = InstanceUuid::from(segment.to_string());
+            state
+                .instances
+                .contains_key(&instance_uuid)
+                .then_some(instance_uuid)
+        }
+        None => None,
+    };
+
+    let response = next.run(request).await;
+
+    let audit_event = AuditEvent {
+        user_id: user.as_ref().map(|user| user.uid.clone()),
+        method: method.to_string(),
+        path,
+        instance_uuid,
+        status: response.status().as_u16(),
+    };
+    state.event_broadcaster.send(Event {
+        details: format!(
+            "{} {} {} ({})",
+            user.as_ref()
+                .map(|user| user.username.as_str())
+                .unwrap_or("Anonymous"),
+            audit_event.method,
+            audit_event.path,
+            audit_event.status
+        ),
+        event_inner: EventInner::AuditEvent(audit_event),
+        snowflake: Snowflake::default(),
+        caused_by: match user {
+            Some(user) => CausedBy::User {
+                user_id: user.uid,
+                user_name: user.username,
+            },
+            None => CausedBy::Unknown,
+        },
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::instance_segment;
+
+    #[test]
+    fn test_instance_segment() {
+        assert_eq!(
+            instance_segment("/api/v1/instance/INSTANCE_A/start"),
+            Some("INSTANCE_A")
+        );
+        assert_eq!(
+            instance_segment("/api/v1/instance/INSTANCE_A"),
+            Some("INSTANCE_A")
+        );
+        assert_eq!(instance_segment("/api/v1/instance/"), None);
+        assert_eq!(instance_segment("/api/v1/user/create"), None);
+    }
+}
@@ -0,0 +1,144 @@
+use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    types::{InstanceUuid, Snowflake},
+};
+
+use super::{user::UserAction, user_id::UserId};
+
+/// the longest a grant can last, in seconds
+pub const MAX_GRANT_DURATION: i64 = 30 * 24 * 60 * 60;
+
+/// An instance action that can be granted for a limited time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS, JsonSchema)]
+#[ts(export)]
+pub enum GrantableAction {
+    ViewInstance,
+    StartInstance,
+    StopInstance,
+    AccessConsole,
+    AccessSetting,
+    ReadResource,
+    WriteResource,
+    AccessMacro,
+    ReadInstanceFile,
+    WriteInstanceFile,
+}
+
+impl GrantableAction {
+    /// The instance action `action` is and the instance it is on, `None` for global actions
+    pub fn of(action: &UserAction) -> Option<(GrantableAction, &InstanceUuid)> {
+        match action {
+            UserAction::ViewInstance(uuid) => Some((GrantableAction::ViewInstance, uuid)),
+            UserAction::StartInstance(uuid) => Some((GrantableAction::StartInstance, uuid)),
+            UserAction::StopInstance(uuid) => Some((GrantableAction::StopInstance, uuid)),
+            UserAction::AccessConsole(uuid) => Some((GrantableAction::AccessConsole, uuid)),
+            UserAction::AccessSetting(uuid) => Some((GrantableAction::AccessSetting, uuid)),
+            UserAction::ReadResource(uuid) => Some((GrantableAction::ReadResource, uuid)),
+            UserAction::WriteResource(uuid) => Some((GrantableAction::WriteResource, uuid)),
+            UserAction::AccessMacro(Some(uuid)) => Some((GrantableAction::AccessMacro, uuid)),
+            UserAction::ReadInstanceFile(uuid) => Some((GrantableAction::ReadInstanceFile, uuid)),
+            UserAction::WriteInstanceFile(uuid) => Some((GrantableAction::WriteInstanceFile, uuid)),
+            _ => None,
+        }
+    }
+}
+
+/// Temporary access to an instance, it stops applying on its own once it expires
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct AccessGrant {
+    pub id: Snowflake,
+    pub instance_uuid: InstanceUuid,
+    /// viewing the instance is always granted along with these
+    pub actions: Vec<GrantableAction>,
+    pub granted_by: UserId,
+    pub creation_time: i64,
+    /// unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+impl AccessGrant {
+    pub fn new(
+        instance_uuid: InstanceUuid,
+        actions: Vec<GrantableAction>,
+        granted_by: UserId,
+        duration_secs: i64,
+    ) -> Result<Self, Error> {
+        if duration_secs <= 0 || duration_secs > MAX_GRANT_DURATION {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "A grant must last between 1 second and {MAX_GRANT_DURATION} seconds"
+                ),
+            });
+        }
+        let now = chrono::Utc::now().timestamp();
+        Ok(Self {
+            id: Snowflake::default(),
+            instance_uuid,
+            actions,
+            granted_by,
+            creation_time: now,
+            expires_at: now + duration_secs,
+        })
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Whether the grant allows `action` at `now`
+    pub fn allows(&self, action: &UserAction, now: i64) -> bool {
+        if self.is_expired(now) {
+            return false;
+        }
+        let (granted, instance_uuid) = match GrantableAction::of(action) {
+            Some(action) => action,
+            // global actions are never granted temporarily
+            None => return false,
+        };
+        if granted == GrantableAction::ViewInstance {
+            return self.instance_uuid == *instance_uuid;
+        }
+        self.instance_uuid == *instance_uuid && self.actions.contains(&granted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessGrant, GrantableAction};
+    use crate::{auth::user::UserAction, types::InstanceUuid};
+
+    #[test]
+    fn test_grant_allows() {
+        let instance: InstanceUuid = "INSTANCE_a".to_string().into();
+        let other: InstanceUuid = "INSTANCE_b".to_string().into();
+        let grant = AccessGrant::new(
+            instance.clone(),
+            vec![GrantableAction::AccessConsole],
+            "USER_owner".to_string().into(),
+            60,
+        )
+        .unwrap();
+        let now = grant.creation_time;
+        assert!(grant.allows(&UserAction::AccessConsole(instance.clone()), now));
+        assert!(grant.allows(&UserAction::ViewInstance(instance.clone()), now));
+        assert!(!grant.allows(&UserAction::StopInstance(instance.clone()), now));
+        assert!(!grant.allows(&UserAction::AccessConsole(other), now));
+        assert!(!grant.allows(&UserAction::CreateInstance, now));
+        assert!(!grant.allows(&UserAction::AccessConsole(instance), now + 60));
+
+        assert!(AccessGrant::new(
+            "INSTANCE_a".to_string().into(),
+            vec![],
+            "USER_owner".to_string().into(),
+            0
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use crate::{
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+};
+
+use super::{access_grant::GrantableAction, user::UserAction};
+
+/// tells keys apart from access tokens
+pub const API_KEY_PREFIX: &str = "lodestone_";
+/// a user can't create keys past this many
+pub const MAX_API_KEYS_PER_USER: usize = 32;
+
+/// A long lived token for scripts, sent as a bearer token like an access token.
+///
+/// Made of the key id and a secret, only a hash of the secret is stored. The secret is random
+/// enough that a plain hash is as good as a password hash, and much cheaper to check on every
+/// request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct ApiKeyToken(String);
+
+impl ApiKeyToken {
+    fn new(key_id: Snowflake) -> (Self, String) {
+        let secret = rand_alphanumeric(48);
+        let hashed_secret = hash_secret(&secret);
+        (
+            Self(format!("{API_KEY_PREFIX}{}_{secret}", key_id.to_string())),
+            hashed_secret,
+        )
+    }
+
+    pub fn is_api_key(token: &str) -> bool {
+        token.starts_with(API_KEY_PREFIX)
+    }
+
+    /// The id of the key, as a string
+    pub fn key_id(&self) -> Option<&str> {
+        self.0
+            .strip_prefix(API_KEY_PREFIX)?
+            .split_once('_')
+            .map(|(key_id, _)| key_id)
+    }
+
+    fn secret(&self) -> &str {
+        self.0
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+            .map(|(_, secret)| secret)
+            .unwrap_or("")
+    }
+}
+
+impl From<&str> for ApiKeyToken {
+    fn from(token: &str) -> Self {
+        Self(token.to_string())
+    }
+}
+
+impl AsRef<str> for ApiKeyToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// A global action a key can be allowed, managing users and permissions never is
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[ts(export)]
+pub enum ApiKeyGlobalAction {
+    CreateInstance,
+    DeleteInstance,
+    ReadGlobalFile,
+    WriteGlobalFile,
+}
+
+/// What a key allows, on top of what its user can do
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct ApiKeyScope {
+    /// viewing the instances is always allowed along with these
+    pub actions: Vec<GrantableAction>,
+    /// the instances the key is limited to, every instance if `None`
+    pub instances: Option<HashSet<InstanceUuid>>,
+    pub global_actions: Vec<ApiKeyGlobalAction>,
+}
+
+impl ApiKeyScope {
+    pub fn allows(&self, action: &UserAction) -> bool {
+        if let Some((granted, instance_uuid)) = GrantableAction::of(action) {
+            let in_scope = self
+                .instances
+                .as_ref()
+                .map_or(true, |instances| instances.contains(instance_uuid));
+            return in_scope
+                && (granted == GrantableAction::ViewInstance || self.actions.contains(&granted));
+        }
+        let global_action = match action {
+            UserAction::CreateInstance => ApiKeyGlobalAction::CreateInstance,
+            UserAction::DeleteInstance => ApiKeyGlobalAction::DeleteInstance,
+            UserAction::ReadGlobalFile => ApiKeyGlobalAction::ReadGlobalFile,
+            UserAction::WriteGlobalFile => ApiKeyGlobalAction::WriteGlobalFile,
+            _ => return false,
+        };
+        self.global_actions.contains(&global_action)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub id: Snowflake,
+    pub name: String,
+    hashed_secret: String,
+    pub scope: ApiKeyScope,
+    pub creation_time: i64,
+}
+
+impl ApiKey {
+    pub fn new(name: String, scope: ApiKeyScope) -> (Self, ApiKeyToken) {
+        let id = Snowflake::default();
+        let (token, hashed_secret) = ApiKeyToken::new(id);
+        (
+            Self {
+                id,
+                name,
+                hashed_secret,
+                scope,
+                creation_time: chrono::Utc::now().timestamp(),
+            },
+            token,
+        )
+    }
+
+    pub fn verify(&self, token: &ApiKeyToken) -> bool {
+        token.key_id() == Some(self.id.to_string().as_str())
+            && hash_secret(token.secret()) == self.hashed_secret
+    }
+}
+
+/// The key a request was authenticated with
+#[derive(Clone, Debug)]
+pub struct ApiKeyAccess {
+    pub key_id: Snowflake,
+    pub scope: ApiKeyScope,
+    /// the key belongs to the owner, who can do anything the scope allows
+    pub of_owner: bool,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PublicApiKey {
+    pub id: Snowflake,
+    pub name: String,
+    pub scope: ApiKeyScope,
+    pub creation_time: i64,
+}
+
+impl From<&ApiKey> for PublicApiKey {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name.clone(),
+            scope: key.scope.clone(),
+            creation_time: key.creation_time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{ApiKey, ApiKeyGlobalAction, ApiKeyScope, ApiKeyToken};
+    use crate::{
+        auth::{access_grant::GrantableAction, user::UserAction},
+        types::InstanceUuid,
+    };
+
+    #[test]
+    fn test_api_key() {
+        let instance: InstanceUuid = "INSTANCE_a".to_string().into();
+        let other: InstanceUuid = "INSTANCE_b".to_string().into();
+        let (key, token) = ApiKey::new(
+            "ci".to_string(),
+            ApiKeyScope {
+                actions: vec![GrantableAction::WriteInstanceFile],
+                instances: Some(HashSet::from([instance.clone()])),
+                global_actions: vec![ApiKeyGlobalAction::CreateInstance],
+            },
+        );
+        assert!(ApiKeyToken::is_api_key(token.as_ref()));
+        assert_eq!(token.key_id(), Some(key.id.to_string().as_str()));
+        assert!(key.verify(&token));
+        assert!(!key.verify(&ApiKeyToken::from(
+            format!("lodestone_{}_wrong", key.id.to_string()).as_str()
+        )));
+
+        assert!(key
+            .scope
+            .allows(&UserAction::WriteInstanceFile(instance.clone())));
+        assert!(key
+            .scope
+            .allows(&UserAction::ViewInstance(instance.clone())));
+        assert!(!key.scope.allows(&UserAction::StopInstance(instance)));
+        assert!(!key.scope.allows(&UserAction::WriteInstanceFile(other)));
+        assert!(key.scope.allows(&UserAction::CreateInstance));
+        assert!(!key.scope.allows(&UserAction::DeleteInstance));
+        assert!(!key.scope.allows(&UserAction::ManageUser));
+    }
+}
@@ -1,6 +1,11 @@
+pub mod access_grant;
+pub mod api_key;
 pub mod hashed_password;
 pub mod jwt_token;
+pub mod password_reset;
 pub mod permission;
+pub mod session;
+pub mod two_factor;
 pub mod user;
 pub mod user_id;
 pub mod user_secrets;
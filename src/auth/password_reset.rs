@@ -0,0 +1,87 @@
+use argon2::{Argon2, PasswordVerifier};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{types::Snowflake, util::rand_alphanumeric};
+
+use super::hashed_password::{hash_password, HashedPassword};
+
+/// how long the token of a password reset can be used, in seconds
+pub const PASSWORD_RESET_LIFETIME: i64 = 24 * 60 * 60;
+
+/// Lets a user whose password was reset by an owner choose a new one, once.
+///
+/// Made of the reset id and a secret, only a hash of the secret is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct PasswordResetToken(String);
+
+impl PasswordResetToken {
+    /// The id of the reset the token is for, as a string
+    pub fn reset_id(&self) -> Option<&str> {
+        self.0.split_once('.').map(|(reset_id, _)| reset_id)
+    }
+
+    fn secret(&self) -> &str {
+        self.0
+            .split_once('.')
+            .map(|(_, secret)| secret)
+            .unwrap_or("")
+    }
+}
+
+/// A pending password reset of a user, their old password no longer works meanwhile
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PasswordReset {
+    pub id: Snowflake,
+    hashed_secret: HashedPassword,
+    /// unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+impl PasswordReset {
+    pub fn new(now: i64) -> (Self, PasswordResetToken) {
+        let id = Snowflake::default();
+        let secret = rand_alphanumeric(48);
+        (
+            Self {
+                id,
+                hashed_secret: hash_password(&secret),
+                expires_at: now + PASSWORD_RESET_LIFETIME,
+            },
+            PasswordResetToken(format!("{}.{secret}", id.to_string())),
+        )
+    }
+
+    pub fn verify(&self, token: &PasswordResetToken, now: i64) -> bool {
+        if now >= self.expires_at || token.reset_id() != Some(self.id.to_string().as_str()) {
+            return false;
+        }
+        let hash = match argon2::PasswordHash::new(self.hashed_secret.as_ref()) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        Argon2::default()
+            .verify_password(token.secret().as_bytes(), &hash)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PasswordReset, PasswordResetToken, PASSWORD_RESET_LIFETIME};
+
+    #[test]
+    fn test_verify() {
+        let (reset, token) = PasswordReset::new(1000);
+        assert!(reset.verify(&token, 1000));
+        assert!(!reset.verify(&token, 1000 + PASSWORD_RESET_LIFETIME));
+
+        let forged = PasswordResetToken(format!("{}.forged", token.reset_id().unwrap()));
+        assert!(!reset.verify(&forged, 1000));
+
+        let (_, other_token) = PasswordReset::new(1000);
+        assert!(!reset.verify(&other_token, 1000));
+    }
+}
@@ -1,10 +1,11 @@
 use std::collections::HashSet;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::types::InstanceUuid;
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, TS, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, TS, Debug, JsonSchema)]
 #[ts(export)]
 pub struct UserPermission {
     pub can_view_instance: HashSet<InstanceUuid>,
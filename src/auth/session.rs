@@ -0,0 +1,156 @@
+use argon2::{Argon2, PasswordVerifier};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{types::Snowflake, util::rand_alphanumeric};
+
+use super::hashed_password::{hash_password, HashedPassword};
+
+/// how long an access token is accepted, in seconds
+pub const ACCESS_TOKEN_LIFETIME: i64 = 60 * 60;
+/// how long a session lasts without being refreshed, in seconds
+pub const SESSION_LIFETIME: i64 = 30 * 24 * 60 * 60;
+/// the oldest sessions of a user are ended past this many
+pub const MAX_SESSIONS_PER_USER: usize = 32;
+
+/// Exchanged for a new access token once the current one expires.
+///
+/// Made of the session id and a secret, only a hash of the secret is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(transparent)]
+#[ts(export)]
+pub struct RefreshToken(String);
+
+impl RefreshToken {
+    fn new(session_id: Snowflake) -> (Self, HashedPassword) {
+        let secret = rand_alphanumeric(48);
+        let hashed_secret = hash_password(&secret);
+        (
+            Self(format!("{}.{secret}", session_id.to_string())),
+            hashed_secret,
+        )
+    }
+
+    /// The id of the session the token refreshes, as a string
+    pub fn session_id(&self) -> Option<&str> {
+        self.0.split_once('.').map(|(session_id, _)| session_id)
+    }
+
+    fn secret(&self) -> &str {
+        self.0
+            .split_once('.')
+            .map(|(_, secret)| secret)
+            .unwrap_or("")
+    }
+}
+
+impl AsRef<str> for RefreshToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A login of a user, access tokens are only accepted while their session is active
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub id: Snowflake,
+    hashed_refresh_secret: HashedPassword,
+    pub user_agent: Option<String>,
+    pub creation_time: i64,
+    pub last_refreshed: i64,
+    /// unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+impl Session {
+    pub fn new(user_agent: Option<String>) -> (Self, RefreshToken) {
+        let id = Snowflake::default();
+        let (refresh_token, hashed_refresh_secret) = RefreshToken::new(id);
+        let now = chrono::Utc::now().timestamp();
+        (
+            Self {
+                id,
+                hashed_refresh_secret,
+                user_agent,
+                creation_time: now,
+                last_refreshed: now,
+                expires_at: now + SESSION_LIFETIME,
+            },
+            refresh_token,
+        )
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn verify_refresh_token(&self, refresh_token: &RefreshToken, now: i64) -> bool {
+        if self.is_expired(now) || refresh_token.session_id() != Some(self.id.to_string().as_str())
+        {
+            return false;
+        }
+        let hash = match argon2::PasswordHash::new(self.hashed_refresh_secret.as_ref()) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        Argon2::default()
+            .verify_password(refresh_token.secret().as_bytes(), &hash)
+            .is_ok()
+    }
+
+    /// Replace the refresh token and extend the session, the previous refresh token stops working
+    pub fn refresh(&mut self, now: i64) -> RefreshToken {
+        let (refresh_token, hashed_refresh_secret) = RefreshToken::new(self.id);
+        self.hashed_refresh_secret = hashed_refresh_secret;
+        self.last_refreshed = now;
+        self.expires_at = now + SESSION_LIFETIME;
+        refresh_token
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PublicSession {
+    pub id: Snowflake,
+    pub user_agent: Option<String>,
+    pub creation_time: i64,
+    pub last_refreshed: i64,
+    pub expires_at: i64,
+}
+
+impl From<&Session> for PublicSession {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id,
+            user_agent: session.user_agent.clone(),
+            creation_time: session.creation_time,
+            last_refreshed: session.last_refreshed,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RefreshToken, Session};
+
+    #[test]
+    fn test_refresh_token() {
+        let (mut session, refresh_token) = Session::new(Some("test".to_string()));
+        let now = session.creation_time;
+        assert_eq!(
+            refresh_token.session_id(),
+            Some(session.id.to_string().as_str())
+        );
+        assert!(session.verify_refresh_token(&refresh_token, now));
+        assert!(!session.verify_refresh_token(
+            &RefreshToken(format!("{}.wrong", session.id.to_string())),
+            now
+        ));
+        assert!(!session.verify_refresh_token(&refresh_token, session.expires_at));
+
+        let new_refresh_token = session.refresh(now + 10);
+        assert!(!session.verify_refresh_token(&refresh_token, now + 10));
+        assert!(session.verify_refresh_token(&new_refresh_token, now + 10));
+    }
+}
@@ -0,0 +1,220 @@
+//! Time-based one-time passwords (RFC 6238) as a second factor, compatible with the usual
+//! authenticator apps: HMAC-SHA1, 6 digits, 30 second steps.
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use ts_rs::TS;
+use url::Url;
+
+use crate::util::rand_alphanumeric;
+
+use super::hashed_password::{hash_password, HashedPassword};
+
+/// seconds a code is valid for
+const TOTP_PERIOD: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// codes of the steps right before and after the current one are accepted, for clock drift
+const ALLOWED_DRIFT: u64 = 1;
+const SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The second factor of a user
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    /// base32, as entered in authenticator apps
+    secret: String,
+    /// `false` until a code was confirmed, a pending secret is not asked for on login
+    pub enabled: bool,
+    /// single use codes for when the authenticator is lost
+    recovery_codes: Vec<HashedPassword>,
+    /// the step of the last code accepted, so a code can't be replayed
+    last_step: u64,
+}
+
+/// What an authenticator app needs to generate codes, shown once while setting up
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    /// `otpauth://` uri to render as a QR code
+    pub otpauth_uri: String,
+}
+
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10_u32.pow(TOTP_DIGITS)
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl TwoFactor {
+    /// A new pending second factor with a random secret
+    pub fn new() -> Self {
+        let mut secret = [0_u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+        Self {
+            secret: BASE32_NOPAD.encode(&secret),
+            enabled: false,
+            recovery_codes: Vec::new(),
+            last_step: 0,
+        }
+    }
+
+    pub fn enrollment(&self, issuer: &str, username: &str) -> TwoFactorEnrollment {
+        let mut otpauth_uri = Url::parse("otpauth://totp/").expect("Valid base uri");
+        otpauth_uri.set_path(&format!("{issuer}:{username}"));
+        otpauth_uri
+            .query_pairs_mut()
+            .append_pair("secret", &self.secret)
+            .append_pair("issuer", issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &TOTP_DIGITS.to_string())
+            .append_pair("period", &TOTP_PERIOD.to_string());
+        TwoFactorEnrollment {
+            secret: self.secret.clone(),
+            otpauth_uri: otpauth_uri.to_string(),
+        }
+    }
+
+    /// The step of the code of `now` matching `code`, if any
+    fn matching_step(&self, code: &str, now: u64) -> Option<u64> {
+        let code: u32 = match code.trim() {
+            code if code.len() == TOTP_DIGITS as usize => code.parse().ok()?,
+            _ => return None,
+        };
+        let key = BASE32_NOPAD.decode(self.secret.as_bytes()).ok()?;
+        let step = now / TOTP_PERIOD;
+        (step.saturating_sub(ALLOWED_DRIFT)..=step + ALLOWED_DRIFT)
+            .find(|step| hotp(&key, *step) == code)
+    }
+
+    /// Check a code of the authenticator, `now` is a unix timestamp in seconds
+    pub fn verify_code(&mut self, code: &str, now: u64) -> bool {
+        match self.matching_step(code, now) {
+            Some(step) if step > self.last_step => {
+                self.last_step = step;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check and use up a recovery code
+    pub fn use_recovery_code(&mut self, code: &str) -> bool {
+        let code = normalize_recovery_code(code);
+        if code.is_empty() {
+            return false;
+        }
+        match self
+            .recovery_codes
+            .iter()
+            .position(|hashed| hashed == code.as_str())
+        {
+            Some(index) => {
+                self.recovery_codes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check a code of the authenticator or a recovery code
+    pub fn verify(&mut self, code: &str, now: u64) -> bool {
+        self.verify_code(code, now) || self.use_recovery_code(code)
+    }
+
+    /// Replace the recovery codes, returning the new ones in clear for the user to write down
+    pub fn regenerate_recovery_codes(&mut self) -> Vec<String> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let code = rand_alphanumeric(10).to_lowercase();
+                format!("{}-{}", &code[..5], &code[5..])
+            })
+            .collect();
+        self.recovery_codes = codes
+            .iter()
+            .map(|code| hash_password(normalize_recovery_code(code)))
+            .collect();
+        codes
+    }
+
+    pub fn remaining_recovery_codes(&self) -> usize {
+        self.recovery_codes.len()
+    }
+}
+
+/// The code of the authenticator at `now`, for tests standing in for one
+#[cfg(test)]
+pub(crate) fn totp_code(secret: &str, now: u64) -> String {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    format!("{:06}", hotp(&key, now / TOTP_PERIOD))
+}
+
+impl Default for TwoFactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE32_NOPAD;
+
+    use super::{hotp, TwoFactor, TOTP_PERIOD};
+
+    #[test]
+    fn test_totp() {
+        // test vectors of RFC 6238, truncated to 6 digits
+        let key = b"12345678901234567890";
+        assert_eq!(hotp(key, 59 / TOTP_PERIOD), 287082);
+        assert_eq!(hotp(key, 1111111109 / TOTP_PERIOD), 81804);
+        assert_eq!(hotp(key, 2000000000 / TOTP_PERIOD), 279037);
+
+        let mut two_factor = TwoFactor {
+            secret: BASE32_NOPAD.encode(key),
+            enabled: true,
+            recovery_codes: Vec::new(),
+            last_step: 0,
+        };
+        assert!(!two_factor.verify_code("287083", 59));
+        assert!(two_factor.verify_code("081804", 1111111109 + TOTP_PERIOD));
+        // a code is only accepted once
+        assert!(!two_factor.verify_code("081804", 1111111109));
+        assert!(two_factor.verify_code(" 279037 ", 2000000000));
+
+        let enrollment = two_factor.enrollment("My Core", "steve");
+        assert!(enrollment
+            .otpauth_uri
+            .starts_with("otpauth://totp/My%20Core:steve?secret="));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let mut two_factor = TwoFactor::new();
+        let codes = two_factor.regenerate_recovery_codes();
+        assert_eq!(two_factor.remaining_recovery_codes(), codes.len());
+        assert!(two_factor.verify(&codes[0].to_uppercase(), 0));
+        assert!(!two_factor.verify(&codes[0], 0));
+        assert!(two_factor.use_recovery_code(&codes[1].replace('-', "")));
+        assert!(!two_factor.use_recovery_code(""));
+        assert_eq!(two_factor.remaining_recovery_codes(), codes.len() - 2);
+    }
+}
@@ -11,21 +11,37 @@ use ts_rs::TS;
 use crate::{
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
-    events::{CausedBy, Event, EventInner, UserEvent, UserEventInner},
+    events::{
+        CausedBy, Event, EventInner, SecurityEvent, SecurityEventInner, UserEvent, UserEventInner,
+    },
+    notifications::NotificationPreferences,
     types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
 };
 
 use super::{
+    access_grant::AccessGrant,
+    api_key::{
+        ApiKey, ApiKeyAccess, ApiKeyScope, ApiKeyToken, PublicApiKey, MAX_API_KEYS_PER_USER,
+    },
     hashed_password::{hash_password, HashedPassword},
     jwt_token::JwtToken,
+    password_reset::{PasswordReset, PasswordResetToken},
     permission::UserPermission,
+    session::{RefreshToken, Session, ACCESS_TOKEN_LIFETIME, MAX_SESSIONS_PER_USER},
+    two_factor::{TwoFactor, TwoFactorEnrollment},
     user_id::UserId,
     user_secrets::UserSecret,
 };
 
+/// the error of a login without the code of a user's second factor, clients ask for the code on it
+pub const TWO_FACTOR_CODE_REQUIRED: &str = "A two factor code is required";
+
 #[derive(Deserialize, Serialize)]
 pub struct Claim {
     pub uid: UserId,
+    /// the session the token belongs to, the token is rejected once the session ends
+    pub sid: Snowflake,
     pub exp: usize,
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -37,6 +53,23 @@ pub struct User {
     pub is_admin: bool,
     pub permissions: UserPermission,
     pub secret: UserSecret,
+    /// temporary access on top of `permissions`, expired grants are ignored
+    #[serde(default)]
+    pub grants: Vec<AccessGrant>,
+    #[serde(default)]
+    pub sessions: Vec<Session>,
+    #[serde(default)]
+    pub two_factor: Option<TwoFactor>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    #[serde(default)]
+    pub notification_preferences: NotificationPreferences,
+    /// set while the password is reset by an owner and the user hasn't chosen a new one yet
+    #[serde(default)]
+    pub password_reset: Option<PasswordReset>,
+    /// the key the user was authenticated with, `None` for an access token
+    #[serde(skip)]
+    pub api_key: Option<ApiKeyAccess>,
 }
 
 impl User {
@@ -55,8 +88,22 @@ impl User {
             is_admin,
             permissions,
             secret: UserSecret::default(),
+            grants: Vec::new(),
+            sessions: Vec::new(),
+            two_factor: None,
+            api_keys: Vec::new(),
+            notification_preferences: NotificationPreferences::default(),
+            password_reset: None,
+            api_key: None,
         }
     }
+
+    pub fn has_two_factor(&self) -> bool {
+        self.two_factor
+            .as_ref()
+            .is_some_and(|two_factor| two_factor.enabled)
+    }
+
     fn get_permission_level(&self) -> u8 {
         if self.is_owner {
             u8::MAX
@@ -107,9 +154,26 @@ impl User {
     }
 
     pub fn can_perform_action(&self, action: &UserAction) -> bool {
+        if let Some(api_key) = &self.api_key {
+            if !api_key.scope.allows(action) {
+                return false;
+            }
+            if api_key.of_owner {
+                return true;
+            }
+        }
         if self.is_owner {
             return true;
         }
+        self.has_permission(action) || self.has_grant(action)
+    }
+
+    fn has_grant(&self, action: &UserAction) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        self.grants.iter().any(|grant| grant.allows(action, now))
+    }
+
+    fn has_permission(&self, action: &UserAction) -> bool {
         match action {
             UserAction::ViewInstance(instance_id) => {
                 self.is_admin || self.permissions.can_view_instance.contains(instance_id)
@@ -175,6 +239,17 @@ impl User {
         }
     }
 
+    /// Fail unless the user is the owner, `action` completes "Only the owner can"
+    pub fn try_owner(&self, action: &str) -> Result<(), Error> {
+        if self.is_owner {
+            return Ok(());
+        }
+        Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can {action}"),
+        })
+    }
+
     pub fn try_action(&self, action: &UserAction) -> Result<(), Error> {
         if self.can_perform_action(action) {
             Ok(())
@@ -245,16 +320,22 @@ impl User {
             }
             // TODO!,
             EventInner::ProgressionEvent(_progression_event) => true,
+            EventInner::SystemEvent(_) => true,
+            EventInner::SecurityEvent(_) => self.is_owner,
+            EventInner::AuditEvent(_) => self.is_owner,
+            EventInner::JobEvent(job_event) => job_event.job.visible_to(self),
         }
     }
 
-    pub fn create_jwt(&self) -> Result<JwtToken, Error> {
+    /// Create an access token for one of the user's sessions
+    pub fn create_jwt(&self, session_id: Snowflake) -> Result<JwtToken, Error> {
         let exp = chrono::Utc::now()
-            .checked_add_signed(chrono::Duration::days(60))
+            .checked_add_signed(chrono::Duration::seconds(ACCESS_TOKEN_LIFETIME))
             .ok_or_else(|| eyre!("Failed to create JWT token"))?
             .timestamp();
         let claim = Claim {
             uid: self.uid.clone(),
+            sid: session_id,
             exp: exp as usize,
         };
 
@@ -292,6 +373,18 @@ pub struct PublicUser {
     pub is_owner: bool,
     pub is_admin: bool,
     pub permissions: UserPermission,
+    /// only the grants that have not expired
+    pub grants: Vec<AccessGrant>,
+    pub two_factor_enabled: bool,
+}
+
+fn active_grants(grants: &[AccessGrant]) -> Vec<AccessGrant> {
+    let now = chrono::Utc::now().timestamp();
+    grants
+        .iter()
+        .filter(|grant| !grant.is_expired(now))
+        .cloned()
+        .collect()
 }
 
 impl From<&User> for PublicUser {
@@ -302,6 +395,8 @@ impl From<&User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions.clone(),
+            grants: active_grants(&user.grants),
+            two_factor_enabled: user.has_two_factor(),
         }
     }
 }
@@ -313,7 +408,9 @@ impl From<User> for PublicUser {
             username: user.username,
             is_owner: user.is_owner,
             is_admin: user.is_admin,
+            two_factor_enabled: user.has_two_factor(),
             permissions: user.permissions,
+            grants: active_grants(&user.grants),
         }
     }
 }
@@ -323,6 +420,8 @@ pub struct UsersManager {
     event_broadcaster: EventBroadcaster,
     users: HashMap<UserId, User>,
     path_to_users: PathBuf,
+    /// users without a second factor can only set one up, set by the owner
+    two_factor_required: bool,
 }
 
 impl UsersManager {
@@ -335,6 +434,7 @@ impl UsersManager {
             event_broadcaster,
             users,
             path_to_users,
+            two_factor_required: false,
         }
     }
     pub async fn load_users(&mut self) -> Result<(), Error> {
@@ -376,7 +476,7 @@ impl UsersManager {
         Ok(())
     }
 
-    async fn write_to_file(&self) -> Result<(), Error> {
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
         let mut file = tokio::fs::File::create(&self.path_to_users)
             .await
             .context(format!(
@@ -399,7 +499,7 @@ impl UsersManager {
     pub async fn add_user(&mut self, user: User, caused_by: CausedBy) -> Result<(), Error> {
         if self.get_user_by_username(&user.username).is_some() {
             return Err(Error {
-                kind: ErrorKind::BadRequest,
+                kind: ErrorKind::Conflict,
                 source: eyre!("Username already exist"),
             });
         }
@@ -454,23 +554,18 @@ impl UsersManager {
         Ok(user)
     }
 
+    /// End every session of the user, their access and refresh tokens stop working
     pub async fn logout_user(
         &mut self,
         uid: impl AsRef<UserId>,
         caused_by: CausedBy,
     ) -> Result<(), Error> {
-        let old_secret = self
-            .users
-            .get_mut(uid.as_ref())
-            .ok_or_else(|| Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User id not found"),
-            })?
-            .secret
-            .clone();
-        if let Some(user) = self.users.get_mut(uid.as_ref()) {
-            user.secret = UserSecret::default();
-        }
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_secret = std::mem::take(&mut user.secret);
+        let old_sessions = std::mem::take(&mut user.sessions);
 
         match self.write_to_file().await {
             Ok(_) => {
@@ -487,7 +582,8 @@ impl UsersManager {
             }
             Err(e) => {
                 if let Some(user) = self.users.get_mut(uid.as_ref()) {
-                    user.secret = old_secret
+                    user.secret = old_secret;
+                    user.sessions = old_sessions;
                 }
                 Err(e)
             }
@@ -541,6 +637,7 @@ impl UsersManager {
         }
     }
 
+    /// Set the password of a user and end all their sessions, checking `old_password` first if given
     pub async fn change_password(
         &mut self,
         uid: impl AsRef<UserId>,
@@ -548,51 +645,311 @@ impl UsersManager {
         password: String,
         caused_by: CausedBy,
     ) -> Result<(), Error> {
-        let old_data = self
-            .users
-            .get_mut(uid.as_ref())
-            .ok_or_else(|| Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User id not found"),
-            })?
-            .hashed_psw
-            .clone();
+        if password.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The password can't be empty"),
+            });
+        }
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
         if let Some(old_password) = old_password {
             Argon2::default()
                 .verify_password(
                     old_password.as_ref().as_bytes(),
-                    &argon2::PasswordHash::new(old_data.as_ref()).unwrap(),
+                    &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
                 )
                 .map_err(|_| Error {
                     kind: ErrorKind::Unauthorized,
                     source: eyre!("Credential mismatch"),
                 })?;
         }
-        if let Some(user) = self.users.get_mut(uid.as_ref()) {
-            user.hashed_psw = hash_password(password);
-        }
+        let old_data = std::mem::replace(&mut user.hashed_psw, hash_password(password));
+        let old_reset = user.password_reset.take();
+        let details = format!("Password of {} changed", user.username);
         match self.write_to_file().await {
             Ok(_) => {
-                self.event_broadcaster.send(Event {
-                    event_inner: EventInner::UserEvent(UserEvent {
-                        user_id: uid.as_ref().to_owned(),
-                        user_event_inner: UserEventInner::UserLoggedOut,
-                    }),
-                    details: "".to_string(),
-                    snowflake: Snowflake::default(),
-                    caused_by: caused_by.clone(),
-                });
+                self.send_security_event(
+                    Some(uid.as_ref().to_owned()),
+                    SecurityEventInner::PasswordChanged,
+                    details,
+                    caused_by.clone(),
+                );
                 self.logout_user(uid, caused_by).await
             }
             Err(e) => {
                 if let Some(user) = self.users.get_mut(uid.as_ref()) {
                     user.hashed_psw = old_data;
+                    user.password_reset = old_reset;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace the password of a user with one nobody knows and end all their sessions, the user
+    /// chooses a new password with the returned token through `complete_password_reset`.
+    ///
+    /// A reset that expires unused leaves the user unable to log in until they are reset again.
+    pub async fn reset_password(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        caused_by: CausedBy,
+    ) -> Result<(PasswordResetToken, i64), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let (reset, token) = PasswordReset::new(chrono::Utc::now().timestamp());
+        let expires_at = reset.expires_at;
+        let old_data =
+            std::mem::replace(&mut user.hashed_psw, hash_password(rand_alphanumeric(48)));
+        let old_reset = user.password_reset.replace(reset);
+        let details = format!("Password of {} reset", user.username);
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.send_security_event(
+                    Some(uid.as_ref().to_owned()),
+                    SecurityEventInner::PasswordResetIssued { expires_at },
+                    details,
+                    caused_by.clone(),
+                );
+                self.logout_user(uid, caused_by).await?;
+                Ok((token, expires_at))
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.hashed_psw = old_data;
+                    user.password_reset = old_reset;
                 }
                 Err(e)
             }
         }
     }
 
+    /// Choose a new password with the token of a reset and start a session, like `login`.
+    ///
+    /// The token is single use. Users with a second factor also need a code of it, a wrong code
+    /// ends the reset so the token can't be used to guess codes.
+    pub async fn complete_password_reset(
+        &mut self,
+        token: &PasswordResetToken,
+        password: String,
+        two_factor_code: Option<&str>,
+        user_agent: Option<String>,
+    ) -> Result<(User, JwtToken, RefreshToken), Error> {
+        if password.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The password can't be empty"),
+            });
+        }
+        let invalid = || Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Invalid or expired password reset token"),
+        };
+        let reset_id = token.reset_id().ok_or_else(invalid)?;
+        let now = chrono::Utc::now().timestamp();
+        let user = self
+            .users
+            .values()
+            .find(|user| {
+                user.password_reset
+                    .as_ref()
+                    .is_some_and(|reset| reset.id.to_string() == reset_id)
+            })
+            .cloned()
+            .ok_or_else(invalid)?;
+        if !user
+            .password_reset
+            .as_ref()
+            .is_some_and(|reset| reset.verify(token, now))
+        {
+            return Err(invalid());
+        }
+        if let Err(e) = self.check_two_factor_code(&user, two_factor_code) {
+            if e.source.to_string() != TWO_FACTOR_CODE_REQUIRED {
+                if let Some(user) = self.users.get_mut(&user.uid) {
+                    user.password_reset = None;
+                }
+                self.write_to_file().await?;
+            }
+            return Err(e);
+        }
+        let old_user = user.clone();
+        if let Some(user) = self.users.get_mut(&user.uid) {
+            user.hashed_psw = hash_password(password);
+            user.password_reset = None;
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.users.insert(old_user.uid.clone(), old_user);
+            return Err(e);
+        }
+        let caused_by = CausedBy::User {
+            user_id: user.uid.clone(),
+            user_name: user.username.clone(),
+        };
+        self.send_security_event(
+            Some(user.uid.clone()),
+            SecurityEventInner::PasswordChanged,
+            format!("{} chose a new password after a reset", user.username),
+            caused_by.clone(),
+        );
+        let (token, refresh_token) = self
+            .create_session(&user.uid, user_agent, caused_by)
+            .await?;
+        Ok((user, token, refresh_token))
+    }
+
+    pub fn two_factor_required(&self) -> bool {
+        self.two_factor_required
+    }
+
+    pub fn set_two_factor_required(&mut self, two_factor_required: bool) {
+        self.two_factor_required = two_factor_required;
+    }
+
+    /// Replace the second factor of a user and write it, putting the old one back if that fails
+    async fn replace_two_factor(
+        &mut self,
+        uid: &UserId,
+        two_factor: Option<TwoFactor>,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_two_factor = std::mem::replace(&mut user.two_factor, two_factor);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid) {
+                user.two_factor = old_two_factor;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn set_notification_preferences(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        notification_preferences: NotificationPreferences,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_notification_preferences =
+            std::mem::replace(&mut user.notification_preferences, notification_preferences);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.notification_preferences = old_notification_preferences;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The enabled second factor of a user, after checking `code` against it
+    fn verified_two_factor(&self, uid: &UserId, code: &str) -> Result<TwoFactor, Error> {
+        let mut two_factor = self
+            .users
+            .get(uid)
+            .and_then(|user| user.two_factor.clone())
+            .filter(|two_factor| two_factor.enabled)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Two factor authentication is not set up"),
+            })?;
+        if !two_factor.verify(code, chrono::Utc::now().timestamp() as u64) {
+            return Err(Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Wrong two factor code"),
+            });
+        }
+        Ok(two_factor)
+    }
+
+    /// Start setting up a second factor with a new secret, it is only asked for on login once
+    /// confirmed with `confirm_two_factor`
+    pub async fn begin_two_factor_enrollment(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        issuer: &str,
+    ) -> Result<TwoFactorEnrollment, Error> {
+        let user = self.users.get(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.has_two_factor() {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Two factor authentication is already set up, disable it first"),
+            });
+        }
+        let two_factor = TwoFactor::new();
+        let enrollment = two_factor.enrollment(issuer, &user.username);
+        self.replace_two_factor(uid.as_ref(), Some(two_factor))
+            .await?;
+        Ok(enrollment)
+    }
+
+    /// Enable the pending second factor with a code of the authenticator, returns the recovery codes
+    pub async fn confirm_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: &str,
+    ) -> Result<Vec<String>, Error> {
+        let mut two_factor = self
+            .users
+            .get(uid.as_ref())
+            .and_then(|user| user.two_factor.clone())
+            .filter(|two_factor| !two_factor.enabled)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("There is no two factor authentication being set up"),
+            })?;
+        if !two_factor.verify_code(code, chrono::Utc::now().timestamp() as u64) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Wrong two factor code, check the clock of the device"),
+            });
+        }
+        two_factor.enabled = true;
+        let recovery_codes = two_factor.regenerate_recovery_codes();
+        self.replace_two_factor(uid.as_ref(), Some(two_factor))
+            .await?;
+        Ok(recovery_codes)
+    }
+
+    /// Replace the recovery codes of a user, `code` being a current one or a code of the
+    /// authenticator
+    pub async fn regenerate_recovery_codes(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: &str,
+    ) -> Result<Vec<String>, Error> {
+        let mut two_factor = self.verified_two_factor(uid.as_ref(), code)?;
+        let recovery_codes = two_factor.regenerate_recovery_codes();
+        self.replace_two_factor(uid.as_ref(), Some(two_factor))
+            .await?;
+        Ok(recovery_codes)
+    }
+
+    /// Remove the second factor of a user. `code` is checked against it unless `None`, for an
+    /// owner resetting the second factor of a user who lost it
+    pub async fn disable_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(code) = code {
+            self.verified_two_factor(uid.as_ref(), code)?;
+        }
+        self.replace_two_factor(uid.as_ref(), None).await
+    }
+
     pub fn get_user_by_username(&self, username: impl AsRef<str>) -> Option<User> {
         self.users
             .values()
@@ -624,13 +981,21 @@ impl UsersManager {
                     event_inner: EventInner::UserEvent(UserEvent {
                         user_id: uid.as_ref().to_owned(),
                         user_event_inner: UserEventInner::PermissionChanged {
-                            new_permissions: Box::new(new_permissions),
+                            new_permissions: Box::new(new_permissions.clone()),
                         },
                     }),
                     details: "".to_string(),
                     snowflake: Snowflake::default(),
-                    caused_by,
+                    caused_by: caused_by.clone(),
                 });
+                self.send_security_event(
+                    Some(uid.as_ref().to_owned()),
+                    SecurityEventInner::PermissionChanged {
+                        new_permissions: Box::new(new_permissions),
+                    },
+                    "Permissions changed".to_string(),
+                    caused_by,
+                );
                 Ok(())
             }
             Err(e) => {
@@ -642,52 +1007,522 @@ impl UsersManager {
         }
     }
 
+    /// Give a user temporary access, the grants of the user that expired are dropped
+    pub async fn add_grant(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        grant: AccessGrant,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.is_owner {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The owner already has every permission"),
+            });
+        }
+        let old_grants = user.grants.clone();
+        user.grants = active_grants(&user.grants);
+        user.grants.push(grant.clone());
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::AccessGranted {
+                            grant: Box::new(grant),
+                        },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.grants = old_grants;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn revoke_grant(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        grant_id: &Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_grants = user.grants.clone();
+        user.grants.retain(|grant| grant.id != *grant_id);
+        if user.grants.len() == old_grants.len() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Grant not found"),
+            });
+        }
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::AccessRevoked {
+                            grant_id: *grant_id,
+                        },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.grants = old_grants;
+                }
+                Err(e)
+            }
+        }
+    }
+
     pub fn try_auth(&self, token: &str) -> Option<User> {
+        self.try_auth_for_two_factor_setup(token)
+            .filter(|user| !self.two_factor_required || user.has_two_factor())
+    }
+
+    /// Like `try_auth`, but also lets in the users who still have to set up a second factor when
+    /// one is required, so they can
+    pub fn try_auth_for_two_factor_setup(&self, token: &str) -> Option<User> {
+        if ApiKeyToken::is_api_key(token) {
+            return self.try_auth_api_key(&ApiKeyToken::from(token));
+        }
         let claimed_uid = decode_no_verify(token)?;
         let claimed_requester = self.users.get(&claimed_uid)?;
-        let requester_uid = decode_token(token, &claimed_requester.secret)?;
-        if claimed_uid != requester_uid {
+        let claim = decode_token(token, &claimed_requester.secret)?;
+        if claimed_uid != claim.uid {
+            return None;
+        }
+        let now = chrono::Utc::now().timestamp();
+        if !claimed_requester
+            .sessions
+            .iter()
+            .any(|session| session.id == claim.sid && !session.is_expired(now))
+        {
             return None;
         }
         Some(claimed_requester.to_owned())
     }
 
+    /// The user of the key, only able to do what the key allows. The owner's keys don't make
+    /// them the owner, so none of what only the owner can do is possible with a key
+    fn try_auth_api_key(&self, token: &ApiKeyToken) -> Option<User> {
+        let key_id = token.key_id()?;
+        let (user, key) = self.users.values().find_map(|user| {
+            user.api_keys
+                .iter()
+                .find(|key| key.id.to_string() == key_id)
+                .map(|key| (user, key))
+        })?;
+        if !key.verify(token) {
+            return None;
+        }
+        let mut user = user.clone();
+        user.api_key = Some(ApiKeyAccess {
+            key_id: key.id,
+            scope: key.scope.clone(),
+            of_owner: user.is_owner,
+        });
+        user.is_owner = false;
+        Some(user)
+    }
+
     pub fn try_auth_or_err(&self, token: &str) -> Result<User, Error> {
-        self.try_auth(token).ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Unauthorized"),
-        })
+        let user = self
+            .try_auth_for_two_factor_setup(token)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Unauthorized"),
+            })?;
+        if self.two_factor_required && !user.has_two_factor() {
+            return Err(Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Two factor authentication must be set up to use this core"),
+            });
+        }
+        Ok(user)
     }
 
-    pub fn login(
-        &self,
-        username: impl AsRef<str>,
-        password: impl AsRef<str>,
-    ) -> Result<JwtToken, Error> {
-        let user = self.get_user_by_username(username).ok_or_else(|| Error {
+    /// Check the code of the second factor of a user who has one, the code can't be used again
+    /// once the user is written
+    fn check_two_factor_code(
+        &mut self,
+        user: &User,
+        two_factor_code: Option<&str>,
+    ) -> Result<(), Error> {
+        if !user.has_two_factor() {
+            return Ok(());
+        }
+        let code = two_factor_code.ok_or_else(|| Error {
             kind: ErrorKind::Unauthorized,
-            source: eyre!("Credential mismatch"),
+            source: eyre!(TWO_FACTOR_CODE_REQUIRED),
         })?;
-        Argon2::default()
-            .verify_password(
-                password.as_ref().as_bytes(),
-                &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
-            )
-            .map_err(|_| Error {
+        let mut two_factor = user.two_factor.clone().unwrap_or_default();
+        if !two_factor.verify(code, chrono::Utc::now().timestamp() as u64) {
+            self.send_security_event(
+                Some(user.uid.clone()),
+                SecurityEventInner::SecondFactorFailed,
+                format!("Wrong two factor code for {}", user.username),
+                CausedBy::Unknown,
+            );
+            return Err(Error {
                 kind: ErrorKind::Unauthorized,
                 source: eyre!("Credential mismatch"),
-            })?;
-        user.create_jwt()
+            });
+        }
+        // written along with the new session, so used codes can't be replayed
+        if let Some(user) = self.users.get_mut(&user.uid) {
+            user.two_factor = Some(two_factor);
+        }
+        Ok(())
+    }
+
+    /// Check the credentials and start a new session.
+    ///
+    /// Users with a second factor also need a code of their authenticator or a recovery code.
+    pub async fn login(
+        &mut self,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+        two_factor_code: Option<&str>,
+        user_agent: Option<String>,
+    ) -> Result<(JwtToken, RefreshToken), Error> {
+        let username = username.as_ref();
+        let mismatch = || Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Credential mismatch"),
+        };
+        let user = match self.get_user_by_username(username) {
+            Some(user) => user,
+            None => {
+                self.send_security_event(
+                    None,
+                    SecurityEventInner::LoginFailed {
+                        username: username.to_string(),
+                    },
+                    format!("Failed login attempt for unknown user {username}"),
+                    CausedBy::Unknown,
+                );
+                return Err(mismatch());
+            }
+        };
+        if Argon2::default()
+            .verify_password(
+                password.as_ref().as_bytes(),
+                &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
+            )
+            .is_err()
+        {
+            self.send_security_event(
+                Some(user.uid.clone()),
+                SecurityEventInner::LoginFailed {
+                    username: username.to_string(),
+                },
+                format!("Failed login attempt for {username}"),
+                CausedBy::Unknown,
+            );
+            return Err(mismatch());
+        }
+        self.check_two_factor_code(&user, two_factor_code)?;
+        let now = chrono::Utc::now().timestamp();
+        let is_new_device = !user
+            .sessions
+            .iter()
+            .any(|session| !session.is_expired(now) && session.user_agent == user_agent);
+        let caused_by = CausedBy::User {
+            user_id: user.uid.clone(),
+            user_name: user.username.clone(),
+        };
+        let tokens = self
+            .create_session(&user.uid, user_agent.clone(), caused_by.clone())
+            .await?;
+        if is_new_device {
+            self.send_security_event(
+                Some(user.uid.clone()),
+                SecurityEventInner::NewDeviceLogin {
+                    user_agent: user_agent.clone(),
+                },
+                format!(
+                    "{} logged in from a new device ({})",
+                    user.username,
+                    user_agent.as_deref().unwrap_or("unknown user agent")
+                ),
+                caused_by,
+            );
+        }
+        Ok(tokens)
+    }
+
+    fn send_security_event(
+        &self,
+        user_id: Option<UserId>,
+        security_event_inner: SecurityEventInner,
+        details: String,
+        caused_by: CausedBy,
+    ) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::SecurityEvent(SecurityEvent {
+                user_id,
+                security_event_inner,
+            }),
+            details,
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+    }
+
+    /// Start a session for the user, returns its access token and refresh token
+    pub async fn create_session(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        user_agent: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<(JwtToken, RefreshToken), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let old_sessions = user.sessions.clone();
+        let now = chrono::Utc::now().timestamp();
+        user.sessions.retain(|session| !session.is_expired(now));
+        let (session, refresh_token) = Session::new(user_agent);
+        let session_id = session.id;
+        user.sessions.push(session);
+        if user.sessions.len() > MAX_SESSIONS_PER_USER {
+            let excess = user.sessions.len() - MAX_SESSIONS_PER_USER;
+            user.sessions.drain(..excess);
+        }
+        let token = user.create_jwt(session_id)?;
+        let details = format!("Session created for {}", user.username);
+        match self.write_to_file().await {
+            Ok(()) => {
+                self.send_security_event(
+                    Some(uid.as_ref().to_owned()),
+                    SecurityEventInner::TokenCreated { session_id },
+                    details,
+                    caused_by,
+                );
+                Ok((token, refresh_token))
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.sessions = old_sessions;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Exchange a refresh token for a new access token and refresh token of the same session.
+    ///
+    /// The refresh token is single use, the one passed in stops working.
+    pub async fn refresh_session(
+        &mut self,
+        refresh_token: &RefreshToken,
+    ) -> Result<(User, JwtToken, RefreshToken), Error> {
+        let invalid = || Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Invalid or expired refresh token"),
+        };
+        let session_id = refresh_token.session_id().ok_or_else(invalid)?;
+        let now = chrono::Utc::now().timestamp();
+        let user = self
+            .users
+            .values_mut()
+            .find(|user| {
+                user.sessions
+                    .iter()
+                    .any(|session| session.id.to_string() == session_id)
+            })
+            .ok_or_else(invalid)?;
+        let session = user
+            .sessions
+            .iter_mut()
+            .find(|session| session.id.to_string() == session_id)
+            .ok_or_else(invalid)?;
+        if !session.verify_refresh_token(refresh_token, now) {
+            return Err(invalid());
+        }
+        let old_session = session.clone();
+        let new_refresh_token = session.refresh(now);
+        let session_id = session.id;
+        let user = user.clone();
+        let token = user.create_jwt(session_id)?;
+        match self.write_to_file().await {
+            Ok(()) => Ok((user, token, new_refresh_token)),
+            Err(e) => {
+                if let Some(session) = self
+                    .users
+                    .get_mut(&user.uid)
+                    .and_then(|user| user.sessions.iter_mut().find(|s| s.id == session_id))
+                {
+                    *session = old_session;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// End one session of the user, e.g. a device they lost
+    pub async fn revoke_session(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        session_id: &Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let index = user
+            .sessions
+            .iter()
+            .position(|session| &session.id == session_id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Session not found"),
+            })?;
+        let session = user.sessions.remove(index);
+        match self.write_to_file().await {
+            Ok(()) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::SessionRevoked {
+                            session_id: *session_id,
+                        },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.sessions.insert(index, session);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a key for the user, the token is only ever returned here
+    pub async fn create_api_key(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        name: String,
+        scope: ApiKeyScope,
+        caused_by: CausedBy,
+    ) -> Result<(PublicApiKey, ApiKeyToken), Error> {
+        if name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The name of a key can't be empty"),
+            });
+        }
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.api_keys.len() >= MAX_API_KEYS_PER_USER {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A user can't have more than {MAX_API_KEYS_PER_USER} keys"),
+            });
+        }
+        let (key, token) = ApiKey::new(name, scope);
+        let public_key = PublicApiKey::from(&key);
+        let details = format!("Key {} created for {}", key.name, user.username);
+        user.api_keys.push(key);
+        match self.write_to_file().await {
+            Ok(()) => {
+                self.send_security_event(
+                    Some(uid.as_ref().to_owned()),
+                    SecurityEventInner::ApiKeyCreated {
+                        key_id: public_key.id,
+                        name: public_key.name.clone(),
+                    },
+                    details,
+                    caused_by,
+                );
+                Ok((public_key, token))
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.api_keys.pop();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn revoke_api_key(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        key_id: &Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let index = user
+            .api_keys
+            .iter()
+            .position(|key| &key.id == key_id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Key not found"),
+            })?;
+        let key = user.api_keys.remove(index);
+        match self.write_to_file().await {
+            Ok(()) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::ApiKeyRevoked { key_id: *key_id },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.api_keys.insert(index, key);
+                }
+                Err(e)
+            }
+        }
     }
 }
 
-fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<UserId> {
+fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<Claim> {
     match jsonwebtoken::decode::<Claim>(
         token,
         &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref().as_bytes()),
         &Validation::new(Algorithm::HS512),
     ) {
-        Ok(t) => Some(t.claims.uid),
+        Ok(t) => Some(t.claims),
         Err(_) => None,
     }
 }
@@ -705,6 +1540,154 @@ fn decode_no_verify(token: &str) -> Option<UserId> {
     }
 }
 
+/// What to do with an imported user whose username is already taken
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, TS, Default)]
+#[ts(export)]
+pub enum UserImportConflict {
+    /// keep the existing user
+    #[default]
+    Skip,
+    /// replace the password, admin flag and permissions of the existing user
+    Overwrite,
+    /// import the user under a new username
+    Rename,
+}
+
+#[derive(Serialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct UserImportReport {
+    pub imported: Vec<String>,
+    pub overwritten: Vec<String>,
+    /// original username and the username it was imported as
+    pub renamed: Vec<(String, String)>,
+    pub skipped: Vec<String>,
+}
+
+impl UsersManager {
+    /// Merge users exported from another core
+    ///
+    /// Imported users keep their password but get a fresh secret and no keys, so tokens and keys
+    /// issued by the other core are not valid here. The owner of the other core is imported as
+    /// an admin, and the local owner is never touched.
+    pub async fn import_users(
+        &mut self,
+        users: impl IntoIterator<Item = User>,
+        on_conflict: UserImportConflict,
+        caused_by: CausedBy,
+    ) -> Result<UserImportReport, Error> {
+        let old_users = self.users.clone();
+        let mut report = UserImportReport::default();
+        let mut created = Vec::new();
+        let mut updated = Vec::new();
+        for mut user in users {
+            let is_admin = user.is_admin || user.is_owner;
+            match self.get_user_by_username(&user.username) {
+                Some(existing) => match on_conflict {
+                    UserImportConflict::Skip => {
+                        report.skipped.push(user.username);
+                        continue;
+                    }
+                    UserImportConflict::Overwrite if existing.is_owner => {
+                        report.skipped.push(user.username);
+                        continue;
+                    }
+                    UserImportConflict::Overwrite => {
+                        let existing = self
+                            .users
+                            .get_mut(&existing.uid)
+                            .expect("user was just found");
+                        existing.hashed_psw = user.hashed_psw;
+                        existing.is_admin = is_admin;
+                        existing.permissions = user.permissions;
+                        existing.secret = UserSecret::default();
+                        existing.sessions.clear();
+                        existing.api_keys.clear();
+                        existing.password_reset = None;
+                        updated.push(existing.clone());
+                        report.overwritten.push(user.username);
+                        continue;
+                    }
+                    UserImportConflict::Rename => {
+                        let original = user.username.clone();
+                        let mut suffix = 1;
+                        user.username = format!("{original}_imported");
+                        while self.get_user_by_username(&user.username).is_some() {
+                            suffix += 1;
+                            user.username = format!("{original}_imported{suffix}");
+                        }
+                        report.renamed.push((original, user.username.clone()));
+                    }
+                },
+                None => report.imported.push(user.username.clone()),
+            }
+            if self.users.contains_key(&user.uid) {
+                user.uid = UserId::default();
+            }
+            user.is_owner = false;
+            user.is_admin = is_admin;
+            user.secret = UserSecret::default();
+            user.sessions.clear();
+            user.api_keys.clear();
+            // a reset issued by the other core, and grants to its instances
+            user.password_reset = None;
+            user.grants.clear();
+            created.push(user.uid.clone());
+            self.users.insert(user.uid.clone(), user);
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.users = old_users;
+            return Err(e);
+        }
+        for uid in created {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid,
+                    user_event_inner: UserEventInner::UserCreated,
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: caused_by.clone(),
+            });
+        }
+        for user in updated {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: user.uid,
+                    user_event_inner: UserEventInner::PermissionChanged {
+                        new_permissions: Box::new(user.permissions),
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: caused_by.clone(),
+            });
+        }
+        if let CausedBy::User { user_id, .. } = &caused_by {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: user_id.clone(),
+                    user_event_inner: UserEventInner::UsersImported {
+                        imported: report.imported.len() as u32,
+                        overwritten: report.overwritten.len() as u32,
+                        renamed: report.renamed.len() as u32,
+                        skipped: report.skipped.len() as u32,
+                    },
+                }),
+                details: format!(
+                    "Imported {} users, overwrote {}, renamed {} and skipped {}",
+                    report.imported.len(),
+                    report.overwritten.len(),
+                    report.renamed.len(),
+                    report.skipped.len()
+                ),
+                snowflake: Snowflake::default(),
+                caused_by,
+            });
+        }
+        Ok(report)
+    }
+}
+
 impl AsRef<HashMap<UserId, User>> for UsersManager {
     fn as_ref(&self) -> &HashMap<UserId, User> {
         &self.users
@@ -713,6 +1696,105 @@ impl AsRef<HashMap<UserId, User>> for UsersManager {
 
 mod tests {
 
+    #[tokio::test]
+    async fn test_import_users() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_import_users")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let alice = User::new(
+            "alice".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        users_manager
+            .add_user(alice.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let other_owner = User::new(
+            "owner".to_string(),
+            "other",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let other_alice = User::new(
+            "alice".to_string(),
+            "other",
+            false,
+            true,
+            UserPermission::default(),
+        );
+        let mut bob = User::new(
+            "bob".to_string(),
+            "other",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        bob.password_reset = Some(PasswordReset::new(chrono::Utc::now().timestamp()).0);
+
+        let report = users_manager
+            .import_users(
+                vec![other_owner.clone(), other_alice.clone(), bob.clone()],
+                UserImportConflict::Overwrite,
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.imported, vec!["bob".to_string()]);
+        assert_eq!(report.overwritten, vec!["alice".to_string()]);
+        assert_eq!(report.skipped, vec!["owner".to_string()]);
+        // the local owner is left alone
+        users_manager
+            .login("owner", "12345", None, None)
+            .await
+            .unwrap();
+        users_manager
+            .login("alice", "other", None, None)
+            .await
+            .unwrap();
+        assert!(users_manager.get_user(&alice.uid).unwrap().is_admin);
+        let imported_bob = users_manager.get_user_by_username("bob").unwrap();
+        assert_ne!(imported_bob.secret, bob.secret);
+        assert!(imported_bob.password_reset.is_none());
+
+        let report = users_manager
+            .import_users(
+                vec![other_owner],
+                UserImportConflict::Rename,
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            report.renamed,
+            vec![("owner".to_string(), "owner_imported".to_string())]
+        );
+        let imported_owner = users_manager
+            .get_user_by_username("owner_imported")
+            .unwrap();
+        assert!(!imported_owner.is_owner);
+        assert!(imported_owner.is_admin);
+    }
+
     #[tokio::test]
     async fn test_login() {
         use super::*;
@@ -734,7 +1816,10 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "12345").unwrap();
+        users_manager
+            .login("test_user1", "12345", None, None)
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
@@ -758,7 +1843,11 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "12345").unwrap();
+        let (token, refresh_token) = users_manager
+            .login("test_user1", "12345", None, None)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_some());
 
         users_manager
             .change_password(
@@ -770,7 +1859,279 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "54321").unwrap();
+        // tokens issued before the change no longer work
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+        assert!(users_manager.refresh_session(&refresh_token).await.is_err());
+        users_manager
+            .login("test_user1", "54321", None, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_password_reset() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_password_reset")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let alice = User::new(
+            "alice".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(alice.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let (token, _) = users_manager
+            .login("alice", "12345", None, None)
+            .await
+            .unwrap();
+
+        let (reset_token, _) = users_manager
+            .reset_password(&alice.uid, CausedBy::System)
+            .await
+            .unwrap();
+        // the old password and sessions stop working right away
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+        assert!(users_manager
+            .login("alice", "12345", None, None)
+            .await
+            .is_err());
+
+        let (user, token, _) = users_manager
+            .complete_password_reset(&reset_token, "54321".to_string(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(user.uid, alice.uid);
+        assert!(users_manager.try_auth(token.as_ref()).is_some());
+        users_manager
+            .login("alice", "54321", None, None)
+            .await
+            .unwrap();
+        // single use
+        assert!(users_manager
+            .complete_password_reset(&reset_token, "abcde".to_string(), None, None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_sessions").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let (token, refresh_token) = users_manager
+            .login("test_user1", "12345", None, Some("laptop".to_string()))
+            .await
+            .unwrap();
+        let (other_token, _) = users_manager
+            .login("test_user1", "12345", None, Some("phone".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            users_manager
+                .get_user(&test_user1.uid)
+                .unwrap()
+                .sessions
+                .len(),
+            2
+        );
+
+        let (user, refreshed_token, new_refresh_token) =
+            users_manager.refresh_session(&refresh_token).await.unwrap();
+        assert_eq!(user.uid, test_user1.uid);
+        assert!(users_manager.try_auth(refreshed_token.as_ref()).is_some());
+        // refresh tokens are single use
+        assert!(users_manager.refresh_session(&refresh_token).await.is_err());
+
+        let session_id = user
+            .sessions
+            .iter()
+            .find(|session| session.user_agent.as_deref() == Some("laptop"))
+            .unwrap()
+            .id;
+        users_manager
+            .revoke_session(&test_user1.uid, &session_id, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+        assert!(users_manager.try_auth(refreshed_token.as_ref()).is_none());
+        assert!(users_manager
+            .refresh_session(&new_refresh_token)
+            .await
+            .is_err());
+        assert!(users_manager.try_auth(other_token.as_ref()).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_two_factor() {
+        use super::super::two_factor::totp_code;
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_two_factor")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let alice = User::new(
+            "alice".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        users_manager
+            .add_user(alice.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let enrollment = users_manager
+            .begin_two_factor_enrollment(&owner.uid, "Lodestone")
+            .await
+            .unwrap();
+        // not asked for until confirmed
+        users_manager
+            .login("owner", "12345", None, None)
+            .await
+            .unwrap();
+        let now = chrono::Utc::now().timestamp() as u64;
+        assert!(users_manager
+            .confirm_two_factor(&owner.uid, "000000x")
+            .await
+            .is_err());
+        let recovery_codes = users_manager
+            .confirm_two_factor(&owner.uid, &totp_code(&enrollment.secret, now))
+            .await
+            .unwrap();
+        assert!(users_manager
+            .begin_two_factor_enrollment(&owner.uid, "Lodestone")
+            .await
+            .is_err());
+
+        assert!(users_manager
+            .login("owner", "12345", None, None)
+            .await
+            .is_err());
+        let next_code = totp_code(&enrollment.secret, now + 30);
+        let (token, _) = users_manager
+            .login("owner", "12345", Some(&next_code), None)
+            .await
+            .unwrap();
+        // codes are single use
+        assert!(users_manager
+            .login("owner", "12345", Some(&next_code), None)
+            .await
+            .is_err());
+        users_manager
+            .login("owner", "12345", Some(&recovery_codes[0]), None)
+            .await
+            .unwrap();
+        assert!(users_manager
+            .login("owner", "12345", Some(&recovery_codes[0]), None)
+            .await
+            .is_err());
+
+        // once required, users without a second factor can only set one up
+        let (alice_token, _) = users_manager
+            .login("alice", "12345", None, None)
+            .await
+            .unwrap();
+        users_manager.set_two_factor_required(true);
+        assert!(users_manager.try_auth(token.as_ref()).is_some());
+        assert!(users_manager.try_auth(alice_token.as_ref()).is_none());
+        assert!(users_manager
+            .try_auth_for_two_factor_setup(alice_token.as_ref())
+            .is_some());
+
+        users_manager
+            .disable_two_factor(&owner.uid, Some(&recovery_codes[1]))
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth() {
+        use super::*;
+        use crate::auth::access_grant::GrantableAction;
+        let temp_dir = tempdir::TempDir::new("test_api_key_auth")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager = UsersManager::new(tx, HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(owner.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let instance: InstanceUuid = "INSTANCE_a".to_string().into();
+        let (key, token) = users_manager
+            .create_api_key(
+                &owner.uid,
+                "ci".to_string(),
+                ApiKeyScope {
+                    actions: vec![GrantableAction::WriteResource],
+                    instances: None,
+                    global_actions: Vec::new(),
+                },
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+
+        let requester = users_manager.try_auth(token.as_ref()).unwrap();
+        assert_eq!(requester.uid, owner.uid);
+        // the owner's keys can't do what only the owner can
+        assert!(!requester.is_owner);
+        assert!(requester.can_perform_action(&UserAction::WriteResource(instance.clone())));
+        assert!(!requester.can_perform_action(&UserAction::StopInstance(instance)));
+        assert!(!requester.can_perform_action(&UserAction::ManageUser));
+        assert!(users_manager
+            .try_auth(&format!("{}x", token.as_ref()))
+            .is_none());
+
+        users_manager
+            .revoke_api_key(&owner.uid, &key.id, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
     }
 
     #[tokio::test]
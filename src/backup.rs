@@ -0,0 +1,594 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{LocalResult, TimeZone, Utc};
+use color_eyre::eyre::{eyre, Context, ContextCompat};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue},
+    global_settings::GlobalSettings,
+    instance_map::InstanceMap,
+    jobs::{JobKind, Jobs, NewJob},
+    prelude::path_to_backups,
+    schedule::WallClockSchedule,
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+};
+
+static RETENTION_POLICY_FILE_NAME: &str = "retention_policy.json";
+static SCHEDULE_FILE_NAME: &str = "schedule.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+pub struct BackupEntry {
+    pub id: Snowflake,
+    pub instance_uuid: InstanceUuid,
+    pub name: String,
+    pub creation_time: i64,
+    /// size of the compressed archive in bytes
+    pub size: u64,
+    pub caused_by: CausedBy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, Default, PartialEq, Eq)]
+#[ts(export)]
+pub struct BackupRetentionPolicy {
+    /// Maximum number of backups to keep, the oldest ones are removed first
+    pub max_backups: Option<u32>,
+    /// Backups older than this are removed
+    pub max_age_days: Option<u32>,
+}
+
+impl BackupRetentionPolicy {
+    /// A limit of 0 would remove every backup, including the one just taken
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_backups == Some(0) || self.max_age_days == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Retention limits must be at least 1, leave them unset to keep every backup"
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn path_to_instance_backups(instance_uuid: &InstanceUuid) -> PathBuf {
+    path_to_backups().join(instance_uuid.as_ref())
+}
+
+pub(crate) fn path_to_archive(instance_uuid: &InstanceUuid, id: &Snowflake) -> PathBuf {
+    path_to_instance_backups(instance_uuid).join(format!("{}.tar.gz", id.to_string()))
+}
+
+pub(crate) fn path_to_metadata(instance_uuid: &InstanceUuid, id: &Snowflake) -> PathBuf {
+    path_to_instance_backups(instance_uuid).join(format!("{}.json", id.to_string()))
+}
+
+/// Compress the content of `src` into a gzipped tarball at `dest`.
+fn archive_dir(src: &Path, dest: &Path) -> Result<(), Error> {
+    let file = std::fs::File::create(dest)
+        .context(format!("Failed to create archive at {}", dest.display()))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(".", src)
+        .context(format!("Failed to archive {}", src.display()))?;
+    builder
+        .into_inner()
+        .context("Failed to finish archive")?
+        .finish()
+        .context("Failed to finish archive")?;
+    Ok(())
+}
+
+fn unarchive_to_dir(src: &Path, dest: &Path) -> Result<(), Error> {
+    let file =
+        std::fs::File::open(src).context(format!("Failed to open archive {}", src.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    archive.set_overwrite(true);
+    archive
+        .unpack(dest)
+        .context(format!("Failed to extract archive {}", src.display()))?;
+    Ok(())
+}
+
+/// List all backups of an instance, newest first
+pub async fn list_backups(instance_uuid: &InstanceUuid) -> Result<Vec<BackupEntry>, Error> {
+    let path_to_instance_backups = path_to_instance_backups(instance_uuid);
+    if !path_to_instance_backups.exists() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(&path_to_instance_backups)
+        .await
+        .context(format!(
+            "Failed to read backup directory {}",
+            path_to_instance_backups.display()
+        ))?;
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .context("Failed to read backup directory entry")?
+    {
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new("json"))
+            || path.file_name() == Some(OsStr::new(RETENTION_POLICY_FILE_NAME))
+            || path.file_name() == Some(OsStr::new(SCHEDULE_FILE_NAME))
+        {
+            continue;
+        }
+        match crate::util::fs::read_to_string(&path).await.and_then(|s| {
+            serde_json::from_str::<BackupEntry>(&s)
+                .context(format!(
+                    "Failed to parse backup metadata {}",
+                    path.display()
+                ))
+                .map_err(Error::from)
+        }) {
+            Ok(backup) => ret.push(backup),
+            Err(e) => error!("Skipping malformed backup metadata : {e}"),
+        }
+    }
+    ret.sort_by(|a, b| b.creation_time.cmp(&a.creation_time));
+    Ok(ret)
+}
+
+pub async fn get_backup(
+    instance_uuid: &InstanceUuid,
+    id: &Snowflake,
+) -> Result<BackupEntry, Error> {
+    let path_to_metadata = path_to_metadata(instance_uuid, id);
+    if !path_to_metadata.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Backup not found"),
+        });
+    }
+    let content = crate::util::fs::read_to_string(&path_to_metadata).await?;
+    Ok(serde_json::from_str(&content).context(format!(
+        "Failed to parse backup metadata {}",
+        path_to_metadata.display()
+    ))?)
+}
+
+async fn create_backup_inner(
+    instance_uuid: &InstanceUuid,
+    instance_path: &Path,
+    name: String,
+    caused_by: CausedBy,
+) -> Result<BackupEntry, Error> {
+    let id = Snowflake::default();
+    let path_to_instance_backups = path_to_instance_backups(instance_uuid);
+    crate::util::fs::create_dir_all(&path_to_instance_backups).await?;
+    let path_to_archive = path_to_archive(instance_uuid, &id);
+    tokio::task::spawn_blocking({
+        let instance_path = instance_path.to_owned();
+        let path_to_archive = path_to_archive.clone();
+        move || archive_dir(&instance_path, &path_to_archive)
+    })
+    .await
+    .context("Failed to spawn blocking task")?
+    .map_err(|e| {
+        let _ = std::fs::remove_file(&path_to_archive);
+        e
+    })?;
+    let size = tokio::fs::metadata(&path_to_archive)
+        .await
+        .context(format!(
+            "Failed to get metadata of {}",
+            path_to_archive.display()
+        ))?
+        .len();
+    let backup = BackupEntry {
+        id,
+        instance_uuid: instance_uuid.clone(),
+        name,
+        creation_time: chrono::Utc::now().timestamp(),
+        size,
+        caused_by,
+    };
+    crate::util::fs::write_all(
+        path_to_metadata(instance_uuid, &id),
+        serde_json::to_string_pretty(&backup).context("Failed to serialize backup metadata")?,
+    )
+    .await?;
+    Ok(backup)
+}
+
+/// Snapshot the instance directory into a compressed archive.
+///
+/// Emits a progression event for the duration of the backup, and applies the retention policy
+/// of the instance once the backup is written.
+pub async fn create_backup(
+    instance_uuid: InstanceUuid,
+    instance_name: String,
+    instance_path: PathBuf,
+    name: Option<String>,
+    event_broadcaster: EventBroadcaster,
+    caused_by: CausedBy,
+) -> Result<BackupEntry, Error> {
+    let name = name.unwrap_or_else(|| {
+        format!(
+            "{instance_name} {}",
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+        )
+    });
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Backing up {instance_name}"),
+        None,
+        Some(ProgressionStartValue::InstanceBackup {
+            instance_uuid: instance_uuid.clone(),
+        }),
+        caused_by.clone(),
+    );
+    event_broadcaster.send(progression_start_event);
+    match create_backup_inner(&instance_uuid, &instance_path, name, caused_by).await {
+        Ok(backup) => {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Backup complete"),
+                Some(ProgressionEndValue::InstanceBackup(backup.clone())),
+            ));
+            let _ = apply_retention_policy(&instance_uuid).await.map_err(|e| {
+                error!("Failed to apply backup retention policy for {instance_uuid} : {e}");
+            });
+            Ok(backup)
+        }
+        Err(e) => {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Backup failed: {e}")),
+                None,
+            ));
+            Err(e)
+        }
+    }
+}
+
+pub async fn delete_backup(instance_uuid: &InstanceUuid, id: &Snowflake) -> Result<(), Error> {
+    // make sure the backup exists
+    get_backup(instance_uuid, id).await?;
+    crate::util::fs::remove_file(path_to_metadata(instance_uuid, id)).await?;
+    crate::util::fs::remove_file(path_to_archive(instance_uuid, id)).await
+}
+
+/// Replace the content of `instance_path` with the content of a backup.
+///
+/// The backup is first extracted next to the instance directory, then swapped in with a rename
+/// so the instance is never left half restored. The instance must be stopped beforehand.
+pub async fn restore_backup_files(
+    instance_uuid: &InstanceUuid,
+    id: &Snowflake,
+    instance_path: &Path,
+) -> Result<(), Error> {
+    get_backup(instance_uuid, id).await?;
+    let path_to_archive = path_to_archive(instance_uuid, id);
+    if !path_to_archive.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Backup archive is missing"),
+        });
+    }
+    let dir_name = instance_path
+        .file_name()
+        .context("Instance path has no directory name")?
+        .to_string_lossy()
+        .to_string();
+    // stage next to the instance so the final rename stays on the same filesystem
+    let staging_path =
+        instance_path.with_file_name(format!(".{dir_name}.restore-{}", id.to_string()));
+    let old_path = instance_path.with_file_name(format!(".{dir_name}.old-{}", id.to_string()));
+    if staging_path.exists() {
+        crate::util::fs::remove_dir_all(&staging_path).await?;
+    }
+    crate::util::fs::create_dir_all(&staging_path).await?;
+    let unarchive_result = tokio::task::spawn_blocking({
+        let staging_path = staging_path.clone();
+        move || unarchive_to_dir(&path_to_archive, &staging_path)
+    })
+    .await
+    .context("Failed to spawn blocking task")?;
+    if let Err(e) = unarchive_result {
+        let _ = crate::util::fs::remove_dir_all(&staging_path).await;
+        return Err(e);
+    }
+
+    crate::util::fs::rename(instance_path, &old_path).await?;
+    if let Err(e) = crate::util::fs::rename(&staging_path, instance_path).await {
+        // put the original files back
+        crate::util::fs::rename(&old_path, instance_path).await?;
+        let _ = crate::util::fs::remove_dir_all(&staging_path).await;
+        return Err(e);
+    }
+    let _ = crate::util::fs::remove_dir_all(&old_path)
+        .await
+        .map_err(|e| error!("Failed to clean up old instance files after restore : {e}"));
+    Ok(())
+}
+
+pub async fn read_retention_policy(
+    instance_uuid: &InstanceUuid,
+) -> Result<BackupRetentionPolicy, Error> {
+    let path = path_to_instance_backups(instance_uuid).join(RETENTION_POLICY_FILE_NAME);
+    if !path.is_file() {
+        return Ok(BackupRetentionPolicy::default());
+    }
+    let content = crate::util::fs::read_to_string(&path).await?;
+    Ok(serde_json::from_str(&content).context(format!(
+        "Failed to parse backup retention policy {}",
+        path.display()
+    ))?)
+}
+
+pub async fn write_retention_policy(
+    instance_uuid: &InstanceUuid,
+    policy: &BackupRetentionPolicy,
+) -> Result<(), Error> {
+    policy.validate()?;
+    let path_to_instance_backups = path_to_instance_backups(instance_uuid);
+    crate::util::fs::create_dir_all(&path_to_instance_backups).await?;
+    crate::util::fs::write_all(
+        path_to_instance_backups.join(RETENTION_POLICY_FILE_NAME),
+        serde_json::to_string_pretty(policy)
+            .context("Failed to serialize backup retention policy")?,
+    )
+    .await
+}
+
+/// The times of day backups are taken at, on top of the backup period of the instance
+pub async fn read_backup_schedule(
+    instance_uuid: &InstanceUuid,
+) -> Result<Option<WallClockSchedule>, Error> {
+    let path = path_to_instance_backups(instance_uuid).join(SCHEDULE_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = crate::util::fs::read_to_string(&path).await?;
+    Ok(serde_json::from_str(&content).context(format!(
+        "Failed to parse backup schedule {}",
+        path.display()
+    ))?)
+}
+
+pub async fn write_backup_schedule(
+    instance_uuid: &InstanceUuid,
+    schedule: Option<&WallClockSchedule>,
+) -> Result<(), Error> {
+    let path_to_instance_backups = path_to_instance_backups(instance_uuid);
+    let path = path_to_instance_backups.join(SCHEDULE_FILE_NAME);
+    match schedule {
+        Some(schedule) => {
+            schedule.validate()?;
+            crate::util::fs::create_dir_all(&path_to_instance_backups).await?;
+            crate::util::fs::write_all(
+                path,
+                serde_json::to_string_pretty(schedule)
+                    .context("Failed to serialize backup schedule")?,
+            )
+            .await
+        }
+        None if path.is_file() => crate::util::fs::remove_file(&path).await,
+        None => Ok(()),
+    }
+}
+
+/// The backups of `backups`, newest first, that fall outside of `policy`
+pub fn expired_backups<'a>(
+    policy: &BackupRetentionPolicy,
+    backups: &'a [BackupEntry],
+    now: i64,
+) -> Vec<&'a BackupEntry> {
+    backups
+        .iter()
+        .enumerate()
+        .filter(|(idx, backup)| {
+            let over_count = policy
+                .max_backups
+                .map_or(false, |max_backups| *idx >= max_backups as usize);
+            let over_age = policy.max_age_days.map_or(false, |max_age_days| {
+                now - backup.creation_time > max_age_days as i64 * 24 * 60 * 60
+            });
+            over_count || over_age
+        })
+        .map(|(_, backup)| backup)
+        .collect()
+}
+
+/// Remove the backups that fall outside of the instance's retention policy
+pub async fn apply_retention_policy(instance_uuid: &InstanceUuid) -> Result<(), Error> {
+    let policy = read_retention_policy(instance_uuid).await?;
+    let backups = list_backups(instance_uuid).await?;
+    for backup in expired_backups(&policy, &backups, chrono::Utc::now().timestamp()) {
+        info!(
+            "Removing backup {} of {instance_uuid} per retention policy",
+            backup.name
+        );
+        delete_backup(instance_uuid, &backup.id).await?;
+    }
+    Ok(())
+}
+
+/// Backs up every instance whose backup period has passed or whose backup schedule came due
+/// since its last backup, each backup runs as a job
+pub async fn backup_scheduler_task(
+    instances: InstanceMap,
+    event_broadcaster: EventBroadcaster,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+    jobs: Jobs,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let mut scheduled = Vec::new();
+        for (uuid, instance) in instances.snapshot() {
+            let schedule = match read_backup_schedule(&uuid).await {
+                Ok(schedule) => schedule,
+                Err(e) => {
+                    error!("Failed to read the backup schedule of {uuid} : {e}");
+                    None
+                }
+            };
+            let backup_period = instance.backup_period().await;
+            if backup_period.is_some() || schedule.is_some() {
+                scheduled.push((
+                    uuid,
+                    instance.name().await,
+                    instance.path().await,
+                    instance.creation_time().await,
+                    backup_period,
+                    schedule,
+                ));
+            }
+        }
+        let default_timezone = global_settings.lock().await.default_timezone();
+        for (uuid, name, path, creation_time, backup_period, schedule) in scheduled {
+            let last_backup_time = match list_backups(&uuid).await {
+                Ok(backups) => backups
+                    .first()
+                    .map_or(creation_time, |backup| backup.creation_time),
+                Err(e) => {
+                    error!("Failed to list backups for {uuid} : {e}");
+                    continue;
+                }
+            };
+            let now = Utc::now();
+            let period_due = backup_period.map_or(false, |backup_period| {
+                now.timestamp() - last_backup_time >= backup_period as i64
+            });
+            let schedule_due = match (&schedule, Utc.timestamp_opt(last_backup_time, 0)) {
+                (Some(schedule), LocalResult::Single(last_backup_time)) => schedule
+                    .is_due(last_backup_time, now, default_timezone)
+                    .unwrap_or_else(|e| {
+                        error!("Invalid backup schedule of {uuid} : {e}");
+                        false
+                    }),
+                _ => false,
+            };
+            if !period_due && !schedule_due {
+                continue;
+            }
+            let job = jobs.start(NewJob {
+                kind: JobKind::Backup,
+                name: format!("Scheduled backup of {name}"),
+                instance_uuid: Some(uuid.clone()),
+                caused_by: CausedBy::System,
+                cancellable: false,
+            });
+            let result = create_backup(
+                uuid.clone(),
+                name,
+                path,
+                None,
+                event_broadcaster.clone(),
+                CausedBy::System,
+            )
+            .await;
+            job.finish(&result);
+            if let Err(e) = result {
+                error!("Scheduled backup for {uuid} failed : {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        archive_dir, expired_backups, unarchive_to_dir, BackupEntry, BackupRetentionPolicy,
+    };
+    use crate::{
+        events::CausedBy,
+        types::{InstanceUuid, Snowflake},
+    };
+
+    #[test]
+    fn test_expired_backups() {
+        let day = 24 * 60 * 60;
+        let backups: Vec<BackupEntry> = (0..4)
+            .map(|age| BackupEntry {
+                id: Snowflake::default(),
+                instance_uuid: InstanceUuid::default(),
+                name: format!("{age} days old"),
+                creation_time: 100 * day - age * day,
+                size: 0,
+                caused_by: CausedBy::System,
+            })
+            .collect();
+        let names = |policy: BackupRetentionPolicy| {
+            expired_backups(&policy, &backups, 100 * day)
+                .iter()
+                .map(|backup| backup.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert!(names(BackupRetentionPolicy::default()).is_empty());
+        assert_eq!(
+            names(BackupRetentionPolicy {
+                max_backups: Some(3),
+                max_age_days: None,
+            }),
+            ["3 days old"]
+        );
+        assert_eq!(
+            names(BackupRetentionPolicy {
+                max_backups: Some(3),
+                max_age_days: Some(1),
+            }),
+            ["2 days old", "3 days old"]
+        );
+        assert!(BackupRetentionPolicy {
+            max_backups: Some(0),
+            max_age_days: None,
+        }
+        .validate()
+        .is_err());
+        assert!(BackupRetentionPolicy {
+            max_backups: Some(1),
+            max_age_days: Some(1),
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let src = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(src.path().join("world").join("region")).unwrap();
+        std::fs::write(src.path().join("server.properties"), "server-port=25565").unwrap();
+        std::fs::write(
+            src.path().join("world").join("region").join("r.0.0.mca"),
+            [0u8, 1, 2, 3],
+        )
+        .unwrap();
+
+        let archive = tempfile::tempdir().unwrap();
+        let path_to_archive = archive.path().join("backup.tar.gz");
+        archive_dir(src.path(), &path_to_archive).unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        unarchive_to_dir(&path_to_archive, dest.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dest.path().join("server.properties")).unwrap(),
+            "server-port=25565"
+        );
+        assert_eq!(
+            std::fs::read(dest.path().join("world").join("region").join("r.0.0.mca")).unwrap(),
+            vec![0u8, 1, 2, 3]
+        );
+    }
+}
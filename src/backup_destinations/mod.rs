@@ -0,0 +1,658 @@
+//! Remote destinations for backups, S3 compatible object storage or an SFTP server.
+//!
+//! A backup is kept on a destination as `{instance_uuid}/{backup_id}.tar.gz` next to its
+//! metadata `{instance_uuid}/{backup_id}.json`, the same layout as the local backups. Each
+//! destination has a retention policy of its own, applied after every upload.
+
+pub mod s3;
+pub mod sftp;
+
+use std::{path::PathBuf, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use self::{s3::S3Config, sftp::SftpConfig};
+use crate::{
+    backup::{self, BackupEntry, BackupRetentionPolicy},
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, ProgressionEndValue, ProgressionEventInner},
+    types::{InstanceUuid, Snowflake},
+    util::{format_byte, rand_alphanumeric},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum DestinationTarget {
+    S3(S3Config),
+    Sftp(SftpConfig),
+}
+
+impl DestinationTarget {
+    fn validate(&self) -> Result<(), Error> {
+        match self {
+            DestinationTarget::S3(config) => config.validate(),
+            DestinationTarget::Sftp(config) => config.validate(),
+        }
+    }
+
+    /// Upload a file, `on_progress` is called with the bytes sent so far and the size of the
+    /// file where the destination can tell
+    async fn put_file(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        path: &std::path::Path,
+        on_progress: impl Fn(u64, u64) + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        match self {
+            DestinationTarget::S3(config) => config.put_file(http, key, path, on_progress).await,
+            DestinationTarget::Sftp(config) => config.put_file(key, path).await,
+        }
+    }
+
+    async fn put_bytes(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        bytes: Vec<u8>,
+    ) -> Result<(), Error> {
+        match self {
+            DestinationTarget::S3(config) => config.put_bytes(http, key, bytes).await,
+            DestinationTarget::Sftp(config) => config.put_bytes(key, bytes).await,
+        }
+    }
+
+    async fn get_file(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        dest: &std::path::Path,
+    ) -> Result<(), Error> {
+        match self {
+            DestinationTarget::S3(config) => config.get_file(http, key, dest).await,
+            DestinationTarget::Sftp(config) => config.get_file(key, dest).await,
+        }
+    }
+
+    async fn get_bytes(&self, http: &reqwest::Client, key: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            DestinationTarget::S3(config) => config.get_bytes(http, key).await,
+            DestinationTarget::Sftp(config) => config.get_bytes(key).await,
+        }
+    }
+
+    async fn delete(&self, http: &reqwest::Client, key: &str) -> Result<(), Error> {
+        match self {
+            DestinationTarget::S3(config) => config.delete(http, key).await,
+            DestinationTarget::Sftp(config) => config.delete(key).await,
+        }
+    }
+
+    async fn list(&self, http: &reqwest::Client, dir: &str) -> Result<Vec<String>, Error> {
+        match self {
+            DestinationTarget::S3(config) => config.list(http, dir).await,
+            DestinationTarget::Sftp(config) => config.list(dir).await,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct BackupDestinationConfig {
+    pub name: String,
+    /// when updating, an empty S3 secret keeps the current one
+    pub target: DestinationTarget,
+    /// upload every new backup of every instance
+    #[serde(default)]
+    pub automatic: bool,
+    #[serde(default)]
+    pub retention: BackupRetentionPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct BackupDestination {
+    pub id: String,
+    pub name: String,
+    pub target: DestinationTarget,
+    pub automatic: bool,
+    pub retention: BackupRetentionPolicy,
+    pub created_at: i64,
+}
+
+impl BackupDestination {
+    /// Without the secrets, to be handed out through the API
+    fn redacted(&self) -> Self {
+        let mut destination = self.clone();
+        if let DestinationTarget::S3(config) = &mut destination.target {
+            config.secret_access_key.clear();
+        }
+        destination
+    }
+}
+
+fn archive_key(instance_uuid: &InstanceUuid, backup_id: &Snowflake) -> String {
+    format!("{instance_uuid}/{}.tar.gz", backup_id.to_string())
+}
+
+fn metadata_key(instance_uuid: &InstanceUuid, backup_id: &Snowflake) -> String {
+    format!("{instance_uuid}/{}.json", backup_id.to_string())
+}
+
+/// The backup destinations, persisted in the stores directory
+pub struct BackupDestinations {
+    path_to_store: PathBuf,
+    destinations: Vec<BackupDestination>,
+    http: reqwest::Client,
+}
+
+impl BackupDestinations {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            destinations: Vec::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.destinations = Vec::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read backup destinations file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.destinations = serde_json::from_slice(&content).context(format!(
+            "Failed to parse backup destinations file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.destinations)
+                .context("Failed to serialize backup destinations")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write backup destinations file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn http(&self) -> reqwest::Client {
+        self.http.clone()
+    }
+
+    /// Every destination, without their secrets
+    pub fn destinations(&self) -> Vec<BackupDestination> {
+        self.destinations
+            .iter()
+            .map(BackupDestination::redacted)
+            .collect()
+    }
+
+    /// A destination with its secrets, to talk to it
+    pub fn destination(&self, id: &str) -> Result<BackupDestination, Error> {
+        self.destinations
+            .iter()
+            .find(|destination| destination.id == id)
+            .cloned()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Backup destination not found"),
+            })
+    }
+
+    fn automatic_destinations(&self) -> Vec<BackupDestination> {
+        self.destinations
+            .iter()
+            .filter(|destination| destination.automatic)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn add_destination(
+        &mut self,
+        config: BackupDestinationConfig,
+    ) -> Result<BackupDestination, Error> {
+        if config.name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The name of a backup destination cannot be empty"),
+            });
+        }
+        config.target.validate()?;
+        let destination = BackupDestination {
+            id: rand_alphanumeric(16),
+            name: config.name,
+            target: config.target,
+            automatic: config.automatic,
+            retention: config.retention,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.destinations.push(destination.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.destinations.pop();
+            return Err(e);
+        }
+        Ok(destination.redacted())
+    }
+
+    pub async fn update_destination(
+        &mut self,
+        id: &str,
+        config: BackupDestinationConfig,
+    ) -> Result<BackupDestination, Error> {
+        if config.name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The name of a backup destination cannot be empty"),
+            });
+        }
+        let index = self
+            .destinations
+            .iter()
+            .position(|destination| destination.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Backup destination not found"),
+            })?;
+        let mut target = config.target;
+        if let (DestinationTarget::S3(new), DestinationTarget::S3(old)) =
+            (&mut target, &self.destinations[index].target)
+        {
+            if new.secret_access_key.is_empty() {
+                new.secret_access_key = old.secret_access_key.clone();
+            }
+        }
+        target.validate()?;
+        let old_destination = self.destinations[index].clone();
+        let destination = BackupDestination {
+            name: config.name,
+            target,
+            automatic: config.automatic,
+            retention: config.retention,
+            ..old_destination.clone()
+        };
+        self.destinations[index] = destination.clone();
+        if let Err(e) = self.write_to_file().await {
+            self.destinations[index] = old_destination;
+            return Err(e);
+        }
+        Ok(destination.redacted())
+    }
+
+    /// Forget a destination, the backups on it are left alone
+    pub async fn remove_destination(&mut self, id: &str) -> Result<(), Error> {
+        let index = self
+            .destinations
+            .iter()
+            .position(|destination| destination.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Backup destination not found"),
+            })?;
+        let destination = self.destinations.remove(index);
+        if let Err(e) = self.write_to_file().await {
+            self.destinations.insert(index, destination);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// The backups of an instance on a destination, newest first
+pub async fn list_remote_backups(
+    http: &reqwest::Client,
+    destination: &BackupDestination,
+    instance_uuid: &InstanceUuid,
+) -> Result<Vec<BackupEntry>, Error> {
+    let names = destination
+        .target
+        .list(http, instance_uuid.as_ref())
+        .await?;
+    let mut backups = Vec::new();
+    for name in names.iter().filter(|name| name.ends_with(".json")) {
+        let key = format!("{instance_uuid}/{name}");
+        match destination
+            .target
+            .get_bytes(http, &key)
+            .await
+            .and_then(|bytes| {
+                serde_json::from_slice::<BackupEntry>(&bytes)
+                    .context(format!("Failed to parse backup metadata {key}"))
+                    .map_err(Error::from)
+            }) {
+            Ok(backup) => backups.push(backup),
+            Err(e) => error!("Skipping malformed remote backup metadata : {e}"),
+        }
+    }
+    backups.sort_by(|a, b| b.creation_time.cmp(&a.creation_time));
+    Ok(backups)
+}
+
+pub async fn delete_remote_backup(
+    http: &reqwest::Client,
+    destination: &BackupDestination,
+    instance_uuid: &InstanceUuid,
+    backup_id: &Snowflake,
+) -> Result<(), Error> {
+    // the metadata goes first so a half deleted backup isn't listed
+    destination
+        .target
+        .delete(http, &metadata_key(instance_uuid, backup_id))
+        .await?;
+    destination
+        .target
+        .delete(http, &archive_key(instance_uuid, backup_id))
+        .await
+}
+
+/// Remove the backups of an instance that fall outside of the retention policy of a destination
+pub async fn apply_remote_retention_policy(
+    http: &reqwest::Client,
+    destination: &BackupDestination,
+    instance_uuid: &InstanceUuid,
+) -> Result<(), Error> {
+    if destination.retention == BackupRetentionPolicy::default() {
+        return Ok(());
+    }
+    let backups = list_remote_backups(http, destination, instance_uuid).await?;
+    for backup in backup::expired_backups(
+        &destination.retention,
+        &backups,
+        chrono::Utc::now().timestamp(),
+    ) {
+        info!(
+            "Removing backup {} of {instance_uuid} from {} per retention policy",
+            backup.name, destination.name
+        );
+        delete_remote_backup(http, destination, instance_uuid, &backup.id).await?;
+    }
+    Ok(())
+}
+
+/// Upload a local backup to a destination, then apply the retention policy of the destination.
+///
+/// Emits a progression event for the duration of the upload, with the percentage sent where
+/// the destination can tell.
+pub async fn upload_backup(
+    http: &reqwest::Client,
+    destination: &BackupDestination,
+    backup: &BackupEntry,
+    event_broadcaster: EventBroadcaster,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Uploading backup {} to {}", backup.name, destination.name),
+        Some(100.0),
+        None,
+        caused_by,
+    );
+    event_broadcaster.send(progression_start_event);
+    let result: Result<(), Error> = async {
+        let path_to_archive = backup::path_to_archive(&backup.instance_uuid, &backup.id);
+        if !path_to_archive.is_file() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Backup archive is missing"),
+            });
+        }
+        let (progress_tx, mut progress_rx) = tokio::sync::watch::channel((0, 0));
+        let upload = destination.target.put_file(
+            http,
+            &archive_key(&backup.instance_uuid, &backup.id),
+            &path_to_archive,
+            move |sent, size| {
+                let _ = progress_tx.send((sent, size));
+            },
+        );
+        tokio::pin!(upload);
+        let mut reported_percent = 0;
+        loop {
+            tokio::select! {
+                result = &mut upload => break result?,
+                changed = progress_rx.changed() => {
+                    if changed.is_err() {
+                        // the upload is done with the progress, wait for the answer
+                        break (&mut upload).await?;
+                    }
+                    let (sent, size) = *progress_rx.borrow();
+                    let percent = (sent * 100).checked_div(size).unwrap_or(0);
+                    if percent > reported_percent {
+                        event_broadcaster.send(Event::new_progression_event_update(
+                            &event_id,
+                            format!("Uploaded {} of {}", format_byte(sent), format_byte(size)),
+                            (percent - reported_percent) as f64,
+                        ));
+                        reported_percent = percent;
+                    }
+                }
+            }
+        }
+        // the metadata goes last so a half uploaded backup isn't listed
+        destination
+            .target
+            .put_bytes(
+                http,
+                &metadata_key(&backup.instance_uuid, &backup.id),
+                serde_json::to_vec_pretty(backup).context("Failed to serialize backup metadata")?,
+            )
+            .await
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Backup uploaded"),
+                None,
+            ));
+            if let Err(e) =
+                apply_remote_retention_policy(http, destination, &backup.instance_uuid).await
+            {
+                error!(
+                    "Failed to apply the retention policy of {} for {} : {e}",
+                    destination.name, backup.instance_uuid
+                );
+            }
+            Ok(())
+        }
+        Err(e) => {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Uploading backup failed: {e}")),
+                None,
+            ));
+            Err(e)
+        }
+    }
+}
+
+/// Download a backup from a destination into the local backups of the instance, so it can be
+/// restored like any other. Does nothing if the backup is still kept locally.
+pub async fn fetch_backup(
+    http: &reqwest::Client,
+    destination: &BackupDestination,
+    instance_uuid: &InstanceUuid,
+    backup_id: &Snowflake,
+) -> Result<BackupEntry, Error> {
+    if let Ok(backup) = backup::get_backup(instance_uuid, backup_id).await {
+        return Ok(backup);
+    }
+    let backup: BackupEntry = serde_json::from_slice(
+        &destination
+            .target
+            .get_bytes(http, &metadata_key(instance_uuid, backup_id))
+            .await?,
+    )
+    .context("Failed to parse remote backup metadata")?;
+    if backup.id != *backup_id || backup.instance_uuid != *instance_uuid {
+        return Err(eyre!("The remote backup metadata is for another backup").into());
+    }
+    let path_to_archive = backup::path_to_archive(instance_uuid, backup_id);
+    if let Some(parent) = path_to_archive.parent() {
+        crate::util::fs::create_dir_all(parent).await?;
+    }
+    if let Err(e) = destination
+        .target
+        .get_file(
+            http,
+            &archive_key(instance_uuid, backup_id),
+            &path_to_archive,
+        )
+        .await
+    {
+        let _ = tokio::fs::remove_file(&path_to_archive).await;
+        return Err(e);
+    }
+    crate::util::fs::write_all(
+        backup::path_to_metadata(instance_uuid, backup_id),
+        serde_json::to_string_pretty(&backup).context("Failed to serialize backup metadata")?,
+    )
+    .await?;
+    Ok(backup)
+}
+
+/// Uploads every new backup to the automatic destinations
+pub async fn backup_upload_task(
+    mut event_receiver: Receiver<Event>,
+    backup_destinations: Arc<Mutex<BackupDestinations>>,
+    event_broadcaster: EventBroadcaster,
+) {
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Backup upload task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let backup = match &event.event_inner {
+            EventInner::ProgressionEvent(progression_event) => {
+                match progression_event.progression_event_inner() {
+                    ProgressionEventInner::ProgressionEnd {
+                        success: true,
+                        inner: Some(ProgressionEndValue::InstanceBackup(backup)),
+                        ..
+                    } => backup.clone(),
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        let (http, destinations) = {
+            let backup_destinations = backup_destinations.lock().await;
+            (
+                backup_destinations.http(),
+                backup_destinations.automatic_destinations(),
+            )
+        };
+        for destination in destinations {
+            let http = http.clone();
+            let backup = backup.clone();
+            let event_broadcaster = event_broadcaster.clone();
+            tokio::spawn(async move {
+                if let Err(e) = upload_backup(
+                    &http,
+                    &destination,
+                    &backup,
+                    event_broadcaster,
+                    CausedBy::System,
+                )
+                .await
+                {
+                    error!(
+                        "Failed to upload backup {} to {} : {e}",
+                        backup.name, destination.name
+                    );
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{s3::S3Config, BackupDestinationConfig, BackupDestinations, DestinationTarget};
+    use crate::backup::BackupRetentionPolicy;
+
+    fn s3(secret_access_key: &str) -> DestinationTarget {
+        DestinationTarget::S3(S3Config {
+            endpoint: "http://minio.lan:9000".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "backups".to_string(),
+            prefix: String::new(),
+            access_key_id: "lodestone".to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            path_style: true,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_backup_destinations() {
+        let temp_dir = tempdir::TempDir::new("test_backup_destinations").unwrap();
+        let path = temp_dir.path().join("backup_destinations.json");
+        let mut backup_destinations = BackupDestinations::new(path.clone());
+        let added = backup_destinations
+            .add_destination(BackupDestinationConfig {
+                name: "MinIO".to_string(),
+                target: s3("secret"),
+                automatic: true,
+                retention: BackupRetentionPolicy::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(added.target, s3(""));
+
+        // an empty secret keeps the current one
+        backup_destinations
+            .update_destination(
+                &added.id,
+                BackupDestinationConfig {
+                    name: "MinIO".to_string(),
+                    target: s3(""),
+                    automatic: false,
+                    retention: BackupRetentionPolicy {
+                        max_backups: Some(3),
+                        max_age_days: None,
+                    },
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut backup_destinations = BackupDestinations::new(path);
+        backup_destinations.load_from_file().await.unwrap();
+        let destination = backup_destinations.destination(&added.id).unwrap();
+        assert_eq!(destination.target, s3("secret"));
+        assert!(!destination.automatic);
+        assert_eq!(destination.retention.max_backups, Some(3));
+        assert!(backup_destinations.automatic_destinations().is_empty());
+        assert_eq!(backup_destinations.destinations()[0].target, s3(""));
+
+        backup_destinations
+            .remove_destination(&added.id)
+            .await
+            .unwrap();
+        assert!(backup_destinations.destination(&added.id).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+//! A minimal client for S3 compatible object storage, signing requests with AWS signature
+//! version 4.
+
+use std::{collections::BTreeMap, path::Path};
+
+use color_eyre::eyre::{eyre, Context, ContextCompat};
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+/// payloads are streamed, so they aren't hashed into the signature
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://minio.lan:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// prepended to every key, e.g. `lodestone/backups`
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    /// empty when read through the API
+    pub secret_access_key: String,
+    /// address the bucket in the path instead of the host, needed by most self hosted stores
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+}
+
+/// Percent encode as AWS expects, everything but the unreserved characters
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], message: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+struct CanonicalRequest<'a> {
+    method: &'a str,
+    uri: &'a str,
+    query: &'a str,
+    /// the signed headers, with lowercase names
+    headers: &'a BTreeMap<String, String>,
+    payload_hash: &'a str,
+}
+
+impl CanonicalRequest<'_> {
+    fn signed_headers(&self) -> String {
+        self.headers.keys().cloned().collect::<Vec<_>>().join(";")
+    }
+
+    fn signature(&self, secret_access_key: &str, region: &str, amz_date: &str) -> String {
+        let date = &amz_date[..8];
+        let canonical_headers: String = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{canonical_headers}\n{}\n{}",
+            self.method,
+            self.uri,
+            self.query,
+            self.signed_headers(),
+            self.payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{date}/{region}/s3/aws4_request\n{:x}",
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = hmac_sha256(format!("AWS4{secret_access_key}").as_bytes(), date);
+        let key = hmac_sha256(&key, region);
+        let key = hmac_sha256(&key, "s3");
+        let key = hmac_sha256(&key, "aws4_request");
+        hmac_sha256(&key, &string_to_sign)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+impl S3Config {
+    pub fn validate(&self) -> Result<(), Error> {
+        match url::Url::parse(&self.endpoint) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {}
+            _ => {
+                return Err(Error::bad_request(
+                    "The endpoint must be an http or https url",
+                ))
+            }
+        }
+        if self.region.is_empty() || self.bucket.is_empty() || self.access_key_id.is_empty() {
+            return Err(Error::bad_request(
+                "The region, bucket and access key id are required",
+            ));
+        }
+        if !self.prefix.chars().all(is_key_char) {
+            return Err(Error::bad_request(
+                "The prefix can only contain letters, digits, '-', '_', '.' and '/'",
+            ));
+        }
+        Ok(())
+    }
+
+    fn key(&self, key: &str) -> String {
+        let prefix = self.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}/{key}")
+        }
+    }
+
+    /// The url of `key`, or of the bucket if `key` is empty
+    fn url(&self, key: &str) -> Result<url::Url, Error> {
+        let mut url = url::Url::parse(&self.endpoint).context("Invalid S3 endpoint")?;
+        let path = if self.path_style {
+            format!("/{}/{}", self.bucket, uri_encode(key, false))
+        } else {
+            let host = url.host_str().context("The S3 endpoint has no host")?;
+            url.set_host(Some(&format!("{}.{host}", self.bucket)))
+                .context("Invalid bucket name")?;
+            format!("/{}", uri_encode(key, false))
+        };
+        url.set_path(&path);
+        Ok(url)
+    }
+
+    fn request(
+        &self,
+        http: &reqwest::Client,
+        method: reqwest::Method,
+        url: url::Url,
+        query: &[(&str, &str)],
+    ) -> reqwest::RequestBuilder {
+        let mut url = url;
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        url.set_query((!canonical_query.is_empty()).then_some(canonical_query.as_str()));
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let headers = BTreeMap::from([
+            ("host".to_string(), host),
+            (
+                "x-amz-content-sha256".to_string(),
+                UNSIGNED_PAYLOAD.to_string(),
+            ),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ]);
+        let canonical_request = CanonicalRequest {
+            method: method.as_str(),
+            uri: url.path(),
+            query: &canonical_query,
+            headers: &headers,
+            payload_hash: UNSIGNED_PAYLOAD,
+        };
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}/{}/s3/aws4_request, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            &amz_date[..8],
+            self.region,
+            canonical_request.signed_headers(),
+            canonical_request.signature(&self.secret_access_key, &self.region, &amz_date),
+        );
+        http.request(method, url)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("x-amz-date", amz_date)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+    }
+
+    async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let response = request
+            .send()
+            .await
+            .context("Failed to reach the object storage")?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(eyre!("The object storage answered {status} : {body}").into());
+        }
+        Ok(response)
+    }
+
+    /// Upload a file, `on_progress` is called with the bytes sent so far and the size of the file
+    pub async fn put_file(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        path: &Path,
+        on_progress: impl Fn(u64, u64) + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .context(format!("Failed to open {}", path.display()))?;
+        let size = file
+            .metadata()
+            .await
+            .context(format!("Failed to get metadata of {}", path.display()))?
+            .len();
+        let mut sent = 0;
+        let stream = tokio_util::io::ReaderStream::new(file).inspect_ok(move |chunk| {
+            sent += chunk.len() as u64;
+            on_progress(sent, size);
+        });
+        Self::send(
+            self.request(http, reqwest::Method::PUT, self.url(&self.key(key))?, &[])
+                .header(reqwest::header::CONTENT_LENGTH, size)
+                .body(reqwest::Body::wrap_stream(stream)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn put_bytes(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        bytes: Vec<u8>,
+    ) -> Result<(), Error> {
+        Self::send(
+            self.request(http, reqwest::Method::PUT, self.url(&self.key(key))?, &[])
+                .body(bytes),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_file(
+        &self,
+        http: &reqwest::Client,
+        key: &str,
+        dest: &Path,
+    ) -> Result<(), Error> {
+        let mut response =
+            Self::send(self.request(http, reqwest::Method::GET, self.url(&self.key(key))?, &[]))
+                .await?;
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .context(format!("Failed to create {}", dest.display()))?;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context("Failed to download from the object storage")?
+        {
+            file.write_all(&chunk)
+                .await
+                .context(format!("Failed to write to {}", dest.display()))?;
+        }
+        file.flush()
+            .await
+            .context(format!("Failed to write to {}", dest.display()))?;
+        Ok(())
+    }
+
+    pub async fn get_bytes(&self, http: &reqwest::Client, key: &str) -> Result<Vec<u8>, Error> {
+        Ok(
+            Self::send(self.request(http, reqwest::Method::GET, self.url(&self.key(key))?, &[]))
+                .await?
+                .bytes()
+                .await
+                .context("Failed to download from the object storage")?
+                .to_vec(),
+        )
+    }
+
+    pub async fn delete(&self, http: &reqwest::Client, key: &str) -> Result<(), Error> {
+        Self::send(self.request(
+            http,
+            reqwest::Method::DELETE,
+            self.url(&self.key(key))?,
+            &[],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// The names of the objects directly under `dir`
+    pub async fn list(&self, http: &reqwest::Client, dir: &str) -> Result<Vec<String>, Error> {
+        let prefix = format!("{}/", self.key(dir));
+        let mut names = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = Self::send(self.request(http, reqwest::Method::GET, self.url("")?, &query))
+                .await?
+                .text()
+                .await
+                .context("Failed to read the object list")?;
+            let listing = parse_list_objects(&body);
+            names.extend(listing.keys.iter().filter_map(|key| {
+                key.strip_prefix(&prefix)
+                    .filter(|name| !name.is_empty() && !name.contains('/'))
+                    .map(str::to_string)
+            }));
+            match listing.next_continuation_token {
+                Some(token) if listing.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(names)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ListObjects {
+    keys: Vec<String>,
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The values of every `<tag>` element, the answers of S3 are flat enough not to need an XML
+/// parser
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                values.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    values
+}
+
+fn parse_list_objects(xml: &str) -> ListObjects {
+    ListObjects {
+        keys: xml_values(xml, "Key")
+            .into_iter()
+            .map(xml_unescape)
+            .collect(),
+        is_truncated: xml_values(xml, "IsTruncated").first() == Some(&"true"),
+        next_continuation_token: xml_values(xml, "NextContinuationToken")
+            .first()
+            .map(|token| xml_unescape(token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{parse_list_objects, uri_encode, CanonicalRequest, S3Config};
+
+    #[test]
+    fn test_signature() {
+        // the GET Object example of the AWS signature version 4 documentation
+        let headers = BTreeMap::from([
+            (
+                "host".to_string(),
+                "examplebucket.s3.amazonaws.com".to_string(),
+            ),
+            ("range".to_string(), "bytes=0-9".to_string()),
+            (
+                "x-amz-content-sha256".to_string(),
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+            ),
+            ("x-amz-date".to_string(), "20130524T000000Z".to_string()),
+        ]);
+        let request = CanonicalRequest {
+            method: "GET",
+            uri: "/test.txt",
+            query: "",
+            headers: &headers,
+            payload_hash: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        };
+        assert_eq!(
+            request.signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "us-east-1",
+                "20130524T000000Z"
+            ),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    #[test]
+    fn test_url() {
+        let mut config = S3Config {
+            endpoint: "https://s3.eu-west-1.amazonaws.com".to_string(),
+            region: "eu-west-1".to_string(),
+            bucket: "backups".to_string(),
+            prefix: "/lodestone/".to_string(),
+            access_key_id: "key".to_string(),
+            secret_access_key: "secret".to_string(),
+            path_style: false,
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.url(&config.key("uuid/1.tar.gz")).unwrap().as_str(),
+            "https://backups.s3.eu-west-1.amazonaws.com/lodestone/uuid/1.tar.gz"
+        );
+        config.endpoint = "http://minio.lan:9000".to_string();
+        config.path_style = true;
+        assert_eq!(
+            config.url("").unwrap().as_str(),
+            "http://minio.lan:9000/backups/"
+        );
+        config.prefix = "a b".to_string();
+        assert!(config.validate().is_err());
+        assert_eq!(uri_encode("a b/c~", false), "a%20b/c~");
+        assert_eq!(uri_encode("a/b", true), "a%2Fb");
+    }
+
+    #[test]
+    fn test_parse_list_objects() {
+        let listing = parse_list_objects(
+            "<ListBucketResult><IsTruncated>true</IsTruncated>\
+             <Contents><Key>lodestone/uuid/1.json</Key><Size>12</Size></Contents>\
+             <Contents><Key>lodestone/uuid/a&amp;b.json</Key></Contents>\
+             <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>\
+             </ListBucketResult>",
+        );
+        assert_eq!(
+            listing.keys,
+            ["lodestone/uuid/1.json", "lodestone/uuid/a&b.json"]
+        );
+        assert!(listing.is_truncated);
+        assert_eq!(
+            listing.next_continuation_token.as_deref(),
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=")
+        );
+    }
+}
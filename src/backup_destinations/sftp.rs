@@ -0,0 +1,254 @@
+//! Backups over SFTP, through the `sftp` client of the host in batch mode so the keys and known
+//! hosts of the host are used as they are.
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use color_eyre::eyre::{eyre, Context, ContextCompat};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use ts_rs::TS;
+
+use crate::{error::Error, prelude::path_to_tmp};
+
+/// long enough to move a large backup over a slow link
+const SFTP_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+fn default_port() -> u16 {
+    22
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub user: String,
+    /// the private key to log in with, it must not have a passphrase
+    pub identity_file: PathBuf,
+    /// the directory the backups are kept in, relative to the home of `user` unless absolute
+    pub remote_dir: String,
+}
+
+/// Paths are quoted in the batch file, so they can't hold quotes or line breaks
+fn is_quotable(path: &str) -> bool {
+    !path.contains(['"', '\\', '\n', '\r'])
+}
+
+fn quote(path: &str) -> Result<String, Error> {
+    if !is_quotable(path) {
+        return Err(eyre!("{path} cannot be used in an SFTP batch").into());
+    }
+    Ok(format!("\"{path}\""))
+}
+
+impl SftpConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.host.is_empty()
+            || self.host.starts_with('-')
+            || self.host.contains(|c: char| c.is_whitespace() || c == '@')
+        {
+            return Err(Error::bad_request("Invalid host"));
+        }
+        if self.user.is_empty()
+            || self.user.starts_with('-')
+            || self.user.contains(|c: char| c.is_whitespace() || c == '@')
+        {
+            return Err(Error::bad_request("Invalid user"));
+        }
+        if !self.identity_file.is_absolute() {
+            return Err(Error::bad_request(
+                "The identity file must be an absolute path",
+            ));
+        }
+        if self.remote_dir.is_empty() || !is_quotable(&self.remote_dir) {
+            return Err(Error::bad_request(
+                "The remote directory cannot be empty or hold quotes",
+            ));
+        }
+        Ok(())
+    }
+
+    fn remote_path(&self, key: &str) -> String {
+        let remote_dir = self.remote_dir.trim_end_matches('/');
+        if key.is_empty() {
+            remote_dir.to_string()
+        } else {
+            format!("{remote_dir}/{key}")
+        }
+    }
+
+    /// Run a batch of commands, returns what they printed without the echoed commands
+    async fn run(&self, commands: &[String]) -> Result<Vec<String>, Error> {
+        let mut child = tokio::process::Command::new("sftp")
+            .arg("-b")
+            .arg("-")
+            .arg("-P")
+            .arg(self.port.to_string())
+            .arg("-i")
+            .arg(&self.identity_file)
+            .args([
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "StrictHostKeyChecking=accept-new",
+            ])
+            .arg(format!("{}@{}", self.user, self.host))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to run sftp, is it installed?")?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("Failed to open the stdin of sftp")?;
+        stdin
+            .write_all(format!("{}\n", commands.join("\n")).as_bytes())
+            .await
+            .context("Failed to write to sftp")?;
+        // closing stdin ends the batch
+        drop(stdin);
+        let output = tokio::time::timeout(SFTP_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| eyre!("sftp timed out"))?
+            .context("Failed to run sftp")?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "sftp to {} failed : {}",
+                self.host,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.starts_with("sftp>") && !line.trim().is_empty())
+            .map(|line| line.trim().to_string())
+            .collect())
+    }
+
+    /// `-mkdir` for every directory leading to `key`, failures are ignored as most exist already
+    fn mkdir_commands(&self, key: &str) -> Result<Vec<String>, Error> {
+        let mut commands = Vec::new();
+        let mut dir = self.remote_dir.trim_end_matches('/').to_string();
+        commands.push(format!("-mkdir {}", quote(&dir)?));
+        if let Some((parents, _)) = key.rsplit_once('/') {
+            for component in parents.split('/') {
+                dir = format!("{dir}/{component}");
+                commands.push(format!("-mkdir {}", quote(&dir)?));
+            }
+        }
+        Ok(commands)
+    }
+
+    pub async fn put_file(&self, key: &str, path: &std::path::Path) -> Result<(), Error> {
+        let mut commands = self.mkdir_commands(key)?;
+        commands.push(format!(
+            "put {} {}",
+            quote(&path.to_string_lossy())?,
+            quote(&self.remote_path(key))?
+        ));
+        self.run(&commands).await?;
+        Ok(())
+    }
+
+    pub async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> Result<(), Error> {
+        crate::util::fs::create_dir_all(path_to_tmp()).await?;
+        let tmp = tempfile::tempdir_in(path_to_tmp())
+            .context("Failed to create a temporary directory")?;
+        let path = tmp.path().join("upload");
+        crate::util::fs::write_all(&path, bytes).await?;
+        self.put_file(key, &path).await
+    }
+
+    pub async fn get_file(&self, key: &str, dest: &std::path::Path) -> Result<(), Error> {
+        self.run(&[format!(
+            "get {} {}",
+            quote(&self.remote_path(key))?,
+            quote(&dest.to_string_lossy())?
+        )])
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_bytes(&self, key: &str) -> Result<Vec<u8>, Error> {
+        crate::util::fs::create_dir_all(path_to_tmp()).await?;
+        let tmp = tempfile::tempdir_in(path_to_tmp())
+            .context("Failed to create a temporary directory")?;
+        let path = tmp.path().join("download");
+        self.get_file(key, &path).await?;
+        Ok(tokio::fs::read(&path)
+            .await
+            .context(format!("Failed to read {}", path.display()))?)
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.run(&[format!("rm {}", quote(&self.remote_path(key))?)])
+            .await?;
+        Ok(())
+    }
+
+    /// The names of the entries directly under `dir`, none if it doesn't exist
+    pub async fn list(&self, dir: &str) -> Result<Vec<String>, Error> {
+        let lines = self
+            .run(&[format!("-ls -1 {}", quote(&self.remote_path(dir))?)])
+            .await?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| line.rsplit('/').next())
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::SftpConfig;
+
+    fn config(remote_dir: &str) -> SftpConfig {
+        SftpConfig {
+            host: "backup.lan".to_string(),
+            port: 22,
+            user: "lodestone".to_string(),
+            identity_file: PathBuf::from("/home/lodestone/.ssh/id_ed25519"),
+            remote_dir: remote_dir.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config("backups").validate().is_ok());
+        assert!(config("back\"ups").validate().is_err());
+        assert!(SftpConfig {
+            host: "-oProxyCommand=sh".to_string(),
+            ..config("backups")
+        }
+        .validate()
+        .is_err());
+        assert!(SftpConfig {
+            identity_file: PathBuf::from("id_ed25519"),
+            ..config("backups")
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_mkdir_commands() {
+        assert_eq!(
+            config("/srv/backups/")
+                .mkdir_commands("uuid/id.tar.gz")
+                .unwrap(),
+            ["-mkdir \"/srv/backups\"", "-mkdir \"/srv/backups/uuid\""]
+        );
+        assert_eq!(
+            config("backups").remote_path("uuid/id.json"),
+            "backups/uuid/id.json"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::User,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    types::{InstanceUuid, Snowflake},
+};
+
+const MAX_PATTERNS: usize = 64;
+const MAX_PATTERN_LEN: usize = 256;
+
+/// Which console commands users other than the owner can send to an instance through the API.
+/// Patterns are regexes searched for in the command with its leading `/` removed, so `^op\b`
+/// denies `/op Steve` and `op Steve`. A command is denied if it matches a deny pattern, or if
+/// there are allow patterns and it matches none of them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default, TS, JsonSchema)]
+#[ts(export)]
+pub struct CommandFilter {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl CommandFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.allow.len() + self.deny.len() > MAX_PATTERNS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A filter can have at most {MAX_PATTERNS} patterns"),
+            });
+        }
+        for pattern in self.allow.iter().chain(&self.deny) {
+            if pattern.len() > MAX_PATTERN_LEN {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Patterns can be at most {MAX_PATTERN_LEN} characters"),
+                });
+            }
+            Regex::new(pattern).map_err(|e| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid pattern {pattern} : {e}"),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Why the command is denied, `None` if it is allowed. A pattern that fails to run counts as
+    /// matching a deny pattern and as not matching an allow pattern
+    pub fn denies(&self, command: &str) -> Option<String> {
+        let command = command.trim().trim_start_matches('/');
+        let is_match =
+            |pattern: &str| Regex::new(pattern).and_then(|regex| regex.is_match(command));
+        if let Some(pattern) = self
+            .deny
+            .iter()
+            .find(|pattern| is_match(pattern.as_str()).unwrap_or(true))
+        {
+            return Some(format!("The command matches the denied pattern {pattern}"));
+        }
+        if !self.allow.is_empty()
+            && !self
+                .allow
+                .iter()
+                .any(|pattern| is_match(pattern.as_str()).unwrap_or(false))
+        {
+            return Some("The command matches none of the allowed patterns".to_string());
+        }
+        None
+    }
+}
+
+/// The command filter of each instance, persisted in the stores directory
+pub struct CommandFilters {
+    path_to_store: PathBuf,
+    filters: HashMap<InstanceUuid, CommandFilter>,
+}
+
+impl CommandFilters {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            filters: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.filters = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read command filters file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.filters = serde_json::from_slice(&content).context(format!(
+            "Failed to parse command filters file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.filters)
+                .context("Failed to serialize command filters")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write command filters file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The filter of an instance, an instance without one lets every command through
+    pub fn get(&self, instance_uuid: &InstanceUuid) -> CommandFilter {
+        self.filters.get(instance_uuid).cloned().unwrap_or_default()
+    }
+
+    pub async fn set(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        filter: CommandFilter,
+    ) -> Result<CommandFilter, Error> {
+        filter.validate()?;
+        if filter.is_empty() {
+            self.filters.remove(instance_uuid);
+        } else {
+            self.filters.insert(instance_uuid.clone(), filter.clone());
+        }
+        self.write_to_file().await?;
+        Ok(filter)
+    }
+
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if self.filters.remove(instance_uuid).is_some() {
+            self.write_to_file().await?;
+        }
+        Ok(())
+    }
+
+    /// Check a command the requester is sending to the console of an instance, a denied attempt
+    /// is broadcast as a `CommandDenied` event
+    pub fn check(
+        &self,
+        instance_uuid: &InstanceUuid,
+        instance_name: &str,
+        requester: &User,
+        command: &str,
+        event_broadcaster: &EventBroadcaster,
+    ) -> Result<(), Error> {
+        if requester.is_owner {
+            return Ok(());
+        }
+        let reason = match self
+            .filters
+            .get(instance_uuid)
+            .and_then(|filter| filter.denies(command))
+        {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+        event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: instance_uuid.clone(),
+                instance_name: instance_name.to_string(),
+                instance_event_inner: InstanceEventInner::CommandDenied {
+                    command: command.to_string(),
+                    reason: reason.clone(),
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::User {
+                user_id: requester.uid.clone(),
+                user_name: requester.username.clone(),
+            },
+        });
+        Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("{reason}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandFilter;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> CommandFilter {
+        CommandFilter {
+            allow: allow.iter().map(ToString::to_string).collect(),
+            deny: deny.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn test_denies() {
+        let deny_only = filter(&[], &[r"^(op|deop|stop)\b"]);
+        assert!(deny_only.denies("/op Steve").is_some());
+        assert!(deny_only.denies("  stop").is_some());
+        assert!(deny_only.denies("say stop the raid").is_none());
+        assert!(deny_only.denies("operator").is_none());
+
+        let allow_list = filter(&[r"^(say|kick|tp) "], &[r"^kick Notch$"]);
+        assert!(allow_list.denies("/say hello").is_none());
+        assert!(allow_list.denies("kick Steve").is_none());
+        assert!(allow_list.denies("kick Notch").is_some());
+        assert!(allow_list.denies("gamemode creative Steve").is_some());
+
+        assert!(filter(&[], &[]).denies("op Steve").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(filter(&["^say "], &["^op"]).validate().is_ok());
+        assert!(filter(&[], &["("]).validate().is_err());
+        assert!(filter(&[], &[&"a".repeat(257)]).validate().is_err());
+        let many: Vec<String> = (0..65).map(|i| format!("^cmd{i}")).collect();
+        let many: Vec<&str> = many.iter().map(String::as_str).collect();
+        assert!(filter(&many, &[]).validate().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use ringbuffer::{AllocRingBuffer, RingBufferExt, RingBufferWrite};
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{events::CausedBy, types::InstanceUuid};
+
+/// commands remembered per instance
+const HISTORY_CAPACITY: usize = 256;
+
+/// A command sent to the console of an instance by a user
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct CommandHistoryEntry {
+    pub command: String,
+    pub caused_by: CausedBy,
+    pub timestamp: i64,
+}
+
+/// The commands users sent to each console, newest last, kept apart from the console output so
+/// they can be recalled
+#[derive(Clone, Default)]
+pub struct CommandHistory {
+    histories: Arc<DashMap<InstanceUuid, AllocRingBuffer<CommandHistoryEntry>>>,
+}
+
+impl CommandHistory {
+    /// Remember a command, a repeat of the last one sent by the same user is only kept once
+    pub fn push(&self, uuid: &InstanceUuid, command: &str, caused_by: CausedBy) {
+        let command = command.trim();
+        if command.is_empty() {
+            return;
+        }
+        let mut history = self
+            .histories
+            .entry(uuid.clone())
+            .or_insert_with(|| AllocRingBuffer::with_capacity(HISTORY_CAPACITY));
+        if history
+            .back()
+            .is_some_and(|last| last.command == command && last.caused_by == caused_by)
+        {
+            return;
+        }
+        history.push(CommandHistoryEntry {
+            command: command.to_string(),
+            caused_by,
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+    }
+
+    /// The last `limit` commands of an instance, oldest first
+    pub fn get(&self, uuid: &InstanceUuid, limit: Option<usize>) -> Vec<CommandHistoryEntry> {
+        let entries: Vec<CommandHistoryEntry> = self
+            .histories
+            .get(uuid)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default();
+        let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+        entries.into_iter().skip(skip).collect()
+    }
+
+    pub fn remove(&self, uuid: &InstanceUuid) {
+        self.histories.remove(uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandHistory, HISTORY_CAPACITY};
+    use crate::{events::CausedBy, types::InstanceUuid};
+
+    #[test]
+    fn test_command_history() {
+        let history = CommandHistory::default();
+        let uuid = InstanceUuid::default();
+        let steve = CausedBy::User {
+            user_id: "USER_steve".to_string().into(),
+            user_name: "steve".to_string(),
+        };
+        history.push(&uuid, "list", steve.clone());
+        history.push(&uuid, "list ", steve.clone());
+        history.push(&uuid, "list", CausedBy::System);
+        history.push(&uuid, "  ", steve.clone());
+        let entries = history.get(&uuid, None);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].caused_by, steve);
+
+        for i in 0..HISTORY_CAPACITY {
+            history.push(&uuid, &format!("say {i}"), steve.clone());
+        }
+        let entries = history.get(&uuid, Some(2));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.command.as_str())
+                .collect::<Vec<_>>(),
+            vec!["say 254", "say 255"]
+        );
+        assert_eq!(history.get(&uuid, None).len(), HISTORY_CAPACITY);
+
+        history.remove(&uuid);
+        assert!(history.get(&uuid, None).is_empty());
+    }
+}
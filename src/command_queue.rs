@@ -0,0 +1,323 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_map::InstanceMap,
+    traits::t_server::TServer,
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+/// A console command waiting for the instance to be ready
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct QueuedCommand {
+    pub id: String,
+    pub command: String,
+    pub queued_at: i64,
+    /// seconds after `queued_at` the command is dropped if it hasn't run, kept until it runs if
+    /// not set
+    pub ttl: Option<u64>,
+    /// the command runs on behalf of whoever queued it
+    pub caused_by: CausedBy,
+}
+
+impl QueuedCommand {
+    pub fn is_expired(&self, now: i64) -> bool {
+        match self.ttl {
+            Some(ttl) => now >= self.queued_at.saturating_add(ttl as i64),
+            None => false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct QueuedCommandConfig {
+    pub command: String,
+    pub ttl: Option<u64>,
+}
+
+/// Commands queued for the next time each instance is ready, persisted in the stores directory
+pub struct CommandQueues {
+    path_to_store: PathBuf,
+    queues: HashMap<InstanceUuid, Vec<QueuedCommand>>,
+}
+
+impl CommandQueues {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            queues: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.queues = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read command queues file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.queues = serde_json::from_slice(&content).context(format!(
+            "Failed to parse command queues file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.queues)
+                .context("Failed to serialize command queues")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write command queues file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The commands still to run, in order, leaving out expired ones
+    pub fn queue(&self, instance_uuid: &InstanceUuid, now: i64) -> Vec<QueuedCommand> {
+        self.queues
+            .get(instance_uuid)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .filter(|command| !command.is_expired(now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub async fn push(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        config: QueuedCommandConfig,
+        caused_by: CausedBy,
+    ) -> Result<QueuedCommand, Error> {
+        if config.command.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Command cannot be empty"),
+            });
+        }
+        if config.ttl == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The time to live of a command must be at least 1 second"),
+            });
+        }
+        let queued_command = QueuedCommand {
+            id: rand_alphanumeric(16),
+            command: config.command,
+            queued_at: chrono::Utc::now().timestamp(),
+            ttl: config.ttl,
+            caused_by,
+        };
+        let old_queues = self.queues.clone();
+        self.queues
+            .entry(instance_uuid.clone())
+            .or_default()
+            .push(queued_command.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.queues = old_queues;
+            return Err(e);
+        }
+        Ok(queued_command)
+    }
+
+    pub async fn remove(&mut self, instance_uuid: &InstanceUuid, id: &str) -> Result<(), Error> {
+        let not_found = || Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Queued command not found"),
+        };
+        let old_queues = self.queues.clone();
+        let queue = self.queues.get_mut(instance_uuid).ok_or_else(not_found)?;
+        let index = queue
+            .iter()
+            .position(|command| command.id == id)
+            .ok_or_else(not_found)?;
+        queue.remove(index);
+        if queue.is_empty() {
+            self.queues.remove(instance_uuid);
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.queues = old_queues;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Empty the queue of an instance, returning the commands that are not expired, in order
+    pub async fn take_due(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        now: i64,
+    ) -> Result<Vec<QueuedCommand>, Error> {
+        let queue = match self.queues.remove(instance_uuid) {
+            Some(queue) => queue,
+            None => return Ok(Vec::new()),
+        };
+        if let Err(e) = self.write_to_file().await {
+            self.queues.insert(instance_uuid.clone(), queue);
+            return Err(e);
+        }
+        Ok(queue
+            .into_iter()
+            .filter(|command| !command.is_expired(now))
+            .collect())
+    }
+
+    /// Forget the queue of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.queues.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.queues.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends the queued commands of an instance, in order, as soon as it is ready
+pub async fn command_queue_task(
+    mut event_receiver: Receiver<Event>,
+    command_queues: Arc<Mutex<CommandQueues>>,
+    instances: InstanceMap,
+) {
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Command queue task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let instance_uuid = match event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_event_inner: InstanceEventInner::InstanceReady { .. },
+                ..
+            }) => instance_uuid,
+            _ => continue,
+        };
+        let commands = match command_queues
+            .lock()
+            .await
+            .take_due(&instance_uuid, chrono::Utc::now().timestamp())
+            .await
+        {
+            Ok(commands) => commands,
+            Err(e) => {
+                error!("Failed to take the queued commands of {instance_uuid} : {e}");
+                continue;
+            }
+        };
+        if commands.is_empty() {
+            continue;
+        }
+        let instance = match instances.get(&instance_uuid) {
+            Some(instance) => instance,
+            None => continue,
+        };
+        info!(
+            "Sending {} queued command(s) to {instance_uuid}",
+            commands.len()
+        );
+        for queued_command in commands {
+            if let Err(e) = instance
+                .send_command(&queued_command.command, queued_command.caused_by)
+                .await
+            {
+                error!(
+                    "Failed to send queued command {} to {instance_uuid} : {e}",
+                    queued_command.command
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandQueues, QueuedCommandConfig};
+    use crate::events::CausedBy;
+
+    #[tokio::test]
+    async fn test_command_queues() {
+        let temp_dir = tempdir::TempDir::new("test_command_queues").unwrap();
+        let path = temp_dir.path().join("command_queues.json");
+        let instance_uuid = "INSTANCE_a".to_string().into();
+        let mut command_queues = CommandQueues::new(path.clone());
+        let config = |command: &str, ttl| QueuedCommandConfig {
+            command: command.to_string(),
+            ttl,
+        };
+        assert!(command_queues
+            .push(&instance_uuid, config(" ", None), CausedBy::System)
+            .await
+            .is_err());
+        let op = command_queues
+            .push(&instance_uuid, config("op Steve", None), CausedBy::System)
+            .await
+            .unwrap();
+        let whitelist = command_queues
+            .push(
+                &instance_uuid,
+                config("whitelist add Alex", Some(60)),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        command_queues
+            .push(&instance_uuid, config("say hi", None), CausedBy::System)
+            .await
+            .unwrap();
+        let now = whitelist.queued_at;
+        assert_eq!(command_queues.queue(&instance_uuid, now).len(), 3);
+        // the whitelist change expired before the instance was ready
+        assert_eq!(command_queues.queue(&instance_uuid, now + 60).len(), 2);
+
+        let mut reloaded = CommandQueues::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.queue(&instance_uuid, now).len(), 3);
+
+        command_queues.remove(&instance_uuid, &op.id).await.unwrap();
+        assert!(command_queues.remove(&instance_uuid, &op.id).await.is_err());
+        let due = command_queues
+            .take_due(&instance_uuid, now + 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            due.iter()
+                .map(|command| command.command.as_str())
+                .collect::<Vec<_>>(),
+            vec!["say hi"]
+        );
+        assert!(command_queues.queue(&instance_uuid, now).is_empty());
+        assert!(command_queues
+            .take_due(&instance_uuid, now)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::oneshot, task::AbortHandle};
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    instance_map::InstanceMap,
+    traits::t_server::TServer,
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+/// the most steps a sequence can have
+const MAX_STEPS: usize = 100;
+/// the longest a sequence can wait in total, in seconds
+const MAX_TOTAL_DELAY: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct CommandStep {
+    pub command: String,
+    /// seconds to wait before sending the command
+    #[serde(default)]
+    pub delay: u64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct CommandSequenceConfig {
+    pub steps: Vec<CommandStep>,
+    /// skip the rest of the sequence once a command fails to send, e.g. because the instance
+    /// stopped
+    #[serde(default)]
+    pub stop_on_error: bool,
+}
+
+impl CommandSequenceConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.steps.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A command sequence needs at least one command"),
+            });
+        }
+        if self.steps.len() > MAX_STEPS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A command sequence can have at most {MAX_STEPS} commands"),
+            });
+        }
+        if self
+            .steps
+            .iter()
+            .any(|step| step.command.trim().is_empty() || step.command.contains(['\n', '\r']))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Each step must be a single non empty command"),
+            });
+        }
+        if self.total_delay() > MAX_TOTAL_DELAY {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A command sequence can wait at most {MAX_TOTAL_DELAY} seconds"),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn total_delay(&self) -> u64 {
+        self.steps
+            .iter()
+            .fold(0, |total: u64, step| total.saturating_add(step.delay))
+    }
+}
+
+/// A sequence of commands being sent to an instance
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct CommandSequence {
+    pub id: String,
+    pub instance_uuid: InstanceUuid,
+    pub steps: Vec<CommandStep>,
+    pub stop_on_error: bool,
+    /// the index of the step waiting to be sent
+    pub next_step: usize,
+    pub started_at: i64,
+    /// the commands are sent on behalf of whoever started the sequence
+    pub caused_by: CausedBy,
+}
+
+struct RunningSequence {
+    sequence: CommandSequence,
+    abort_handle: AbortHandle,
+}
+
+/// The command sequences that are running. They are not persisted, a restart of the core cancels
+/// them like it would cancel a client timing the commands itself.
+#[derive(Clone, Default)]
+pub struct CommandSequences {
+    running: Arc<DashMap<String, RunningSequence>>,
+}
+
+impl CommandSequences {
+    /// The sequences running on an instance, oldest first
+    pub fn list(&self, instance_uuid: &InstanceUuid) -> Vec<CommandSequence> {
+        let mut sequences: Vec<CommandSequence> = self
+            .running
+            .iter()
+            .filter(|running| &running.sequence.instance_uuid == instance_uuid)
+            .map(|running| running.sequence.clone())
+            .collect();
+        sequences.sort_by_key(|sequence| sequence.started_at);
+        sequences
+    }
+
+    pub fn start(
+        &self,
+        instances: InstanceMap,
+        instance_uuid: InstanceUuid,
+        config: CommandSequenceConfig,
+        caused_by: CausedBy,
+    ) -> Result<CommandSequence, Error> {
+        config.validate()?;
+        let sequence = CommandSequence {
+            id: rand_alphanumeric(16),
+            instance_uuid,
+            steps: config.steps,
+            stop_on_error: config.stop_on_error,
+            next_step: 0,
+            started_at: chrono::Utc::now().timestamp(),
+            caused_by,
+        };
+        let running = self.running.clone();
+        // the task waits for its entry to be inserted, so it can't finish and remove it before
+        let (inserted_tx, inserted_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn({
+            let sequence = sequence.clone();
+            async move {
+                if inserted_rx.await.is_ok() {
+                    run_sequence(&instances, &running, &sequence).await;
+                }
+                running.remove(&sequence.id);
+            }
+        });
+        self.running.insert(
+            sequence.id.clone(),
+            RunningSequence {
+                sequence: sequence.clone(),
+                abort_handle: task.abort_handle(),
+            },
+        );
+        let _ = inserted_tx.send(());
+        Ok(sequence)
+    }
+
+    /// Stop a sequence, the commands already sent stay sent
+    pub fn cancel(&self, instance_uuid: &InstanceUuid, id: &str) -> Result<(), Error> {
+        match self.running.remove_if(id, |_, running| {
+            &running.sequence.instance_uuid == instance_uuid
+        }) {
+            Some((_, running)) => {
+                running.abort_handle.abort();
+                Ok(())
+            }
+            None => Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Command sequence not found"),
+            }),
+        }
+    }
+
+    /// Cancel the sequences of a deleted instance
+    pub fn remove_instance(&self, instance_uuid: &InstanceUuid) {
+        self.running.retain(|_, running| {
+            if &running.sequence.instance_uuid == instance_uuid {
+                running.abort_handle.abort();
+                return false;
+            }
+            true
+        });
+    }
+}
+
+async fn run_sequence(
+    instances: &InstanceMap,
+    running: &DashMap<String, RunningSequence>,
+    sequence: &CommandSequence,
+) {
+    let instance_uuid = &sequence.instance_uuid;
+    for (index, step) in sequence.steps.iter().enumerate() {
+        if let Some(mut running) = running.get_mut(&sequence.id) {
+            running.sequence.next_step = index;
+        }
+        tokio::time::sleep(Duration::from_secs(step.delay)).await;
+        let result = match instances.get(instance_uuid) {
+            Some(instance) => {
+                instance
+                    .send_command(&step.command, sequence.caused_by.clone())
+                    .await
+            }
+            None => {
+                info!(
+                    "Instance {instance_uuid} was removed, cancelling command sequence {}",
+                    sequence.id
+                );
+                return;
+            }
+        };
+        if let Err(e) = result {
+            error!(
+                "Failed to send command {} of sequence {} to {instance_uuid} : {e}",
+                step.command, sequence.id
+            );
+            if sequence.stop_on_error {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandSequenceConfig, CommandStep, MAX_STEPS, MAX_TOTAL_DELAY};
+
+    #[test]
+    fn test_validate_command_sequence() {
+        let step = |command: &str, delay| CommandStep {
+            command: command.to_string(),
+            delay,
+        };
+        let config = |steps| CommandSequenceConfig {
+            steps,
+            stop_on_error: false,
+        };
+        let shutdown = config(vec![
+            step("say Restarting in 60 seconds", 0),
+            step("say Restarting in 10 seconds", 50),
+            step("kick @a Restarting", 10),
+            step("stop", 1),
+        ]);
+        assert!(shutdown.validate().is_ok());
+        assert_eq!(shutdown.total_delay(), 61);
+
+        assert!(config(vec![]).validate().is_err());
+        assert!(config(vec![step(" ", 0)]).validate().is_err());
+        assert!(config(vec![step("say hi\nstop", 0)]).validate().is_err());
+        assert!(config(vec![step("say hi", 0); MAX_STEPS + 1])
+            .validate()
+            .is_err());
+        assert!(config(vec![
+            step("say hi", MAX_TOTAL_DELAY),
+            step("stop", u64::MAX)
+        ])
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,161 @@
+mod properties;
+mod toml;
+mod yaml;
+
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum ConfigFormat {
+    Properties,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "properties" => Some(ConfigFormat::Properties),
+            "toml" => Some(ConfigFormat::Toml),
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[serde(tag = "type", content = "value")]
+#[ts(export)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Null,
+    List(Vec<ConfigValue>),
+    Map(Vec<ConfigEntry>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: ConfigValue,
+    /// the comment lines directly above the entry, without the comment markers
+    pub comment: Option<String>,
+}
+
+/// A parsed config file, the root is a `Map` except for YAML documents that are a single list or value
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ConfigFile {
+    pub format: ConfigFormat,
+    pub root: ConfigValue,
+}
+
+/// Set the value at `path`, the entry is created if its parent map exists but the key does not.
+///
+/// Only scalar values can be written, except for TOML which supports lists and maps as well.
+#[derive(Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ConfigEdit {
+    /// keys from the root, list items are addressed by their index
+    pub path: Vec<String>,
+    pub value: ConfigValue,
+}
+
+fn comment_from_lines(lines: Vec<String>) -> Option<String> {
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// Guess the type of an untyped value, anything that is not a boolean or a number is a string
+fn infer_scalar(s: &str) -> ConfigValue {
+    match s {
+        "true" | "True" | "TRUE" => return ConfigValue::Boolean(true),
+        "false" | "False" | "FALSE" => return ConfigValue::Boolean(false),
+        _ => {}
+    }
+    if let Ok(i) = s.parse::<i64>() {
+        return ConfigValue::Integer(i);
+    }
+    // f64::from_str also accepts "inf" and "NaN", which are more likely to be strings
+    if s.bytes().any(|b| b.is_ascii_digit())
+        && s.bytes()
+            .all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b))
+    {
+        if let Ok(f) = s.parse::<f64>() {
+            return ConfigValue::Float(f);
+        }
+    }
+    ConfigValue::String(s.to_string())
+}
+
+fn scalar_to_string(value: &ConfigValue) -> Result<String, Error> {
+    match value {
+        ConfigValue::String(s) => Ok(s.clone()),
+        ConfigValue::Integer(i) => Ok(i.to_string()),
+        // debug formatting keeps the decimal point so the value reads back as a float
+        ConfigValue::Float(f) => Ok(format!("{f:?}")),
+        ConfigValue::Boolean(b) => Ok(b.to_string()),
+        ConfigValue::Null => Ok("null".to_string()),
+        ConfigValue::List(_) | ConfigValue::Map(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Only scalar values can be written to this file"),
+        }),
+    }
+}
+
+pub fn parse_config(format: ConfigFormat, content: &str) -> Result<ConfigFile, Error> {
+    let root = match format {
+        ConfigFormat::Properties => properties::parse(content),
+        ConfigFormat::Toml => toml::parse(content)?,
+        ConfigFormat::Yaml => yaml::parse(content)?,
+    };
+    Ok(ConfigFile { format, root })
+}
+
+/// Apply the edits in order while keeping the comments and formatting of the rest of the file
+pub fn edit_config(
+    format: ConfigFormat,
+    content: &str,
+    edits: &[ConfigEdit],
+) -> Result<String, Error> {
+    if edits.iter().any(|edit| edit.path.is_empty()) {
+        return Err(Error::bad_request("Path cannot be empty".to_string()));
+    }
+    match format {
+        ConfigFormat::Properties => properties::edit(content, edits),
+        ConfigFormat::Toml => toml::edit(content, edits),
+        ConfigFormat::Yaml => edits.iter().try_fold(content.to_string(), |content, edit| {
+            yaml::edit(&content, edit)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{infer_scalar, ConfigValue};
+
+    #[test]
+    fn test_infer_scalar() {
+        assert_eq!(infer_scalar("true"), ConfigValue::Boolean(true));
+        assert_eq!(infer_scalar("25565"), ConfigValue::Integer(25565));
+        assert_eq!(infer_scalar("-0.5"), ConfigValue::Float(-0.5));
+        assert_eq!(infer_scalar("inf"), ConfigValue::String("inf".to_string()));
+        assert_eq!(
+            infer_scalar("1.19.2"),
+            ConfigValue::String("1.19.2".to_string())
+        );
+        assert_eq!(infer_scalar(""), ConfigValue::String("".to_string()));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::Error;
+use crate::text_patch::{apply_text_patch, property_key, TextPatchOperation};
+
+use super::{comment_from_lines, infer_scalar, scalar_to_string, ConfigEdit};
+use super::{ConfigEntry, ConfigValue};
+
+pub fn parse(content: &str) -> ConfigValue {
+    let mut entries = Vec::new();
+    let mut comments = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if let Some(comment) = trimmed
+            .strip_prefix('#')
+            .or_else(|| trimmed.strip_prefix('!'))
+        {
+            comments.push(comment.trim().to_string());
+            continue;
+        }
+        let key = match property_key(line) {
+            Some(key) => key,
+            None => continue,
+        };
+        let value = trimmed
+            .split_once(['=', ':'])
+            .map_or("", |(_, value)| value.trim());
+        entries.push(ConfigEntry {
+            key: key.to_string(),
+            value: infer_scalar(value),
+            comment: comment_from_lines(std::mem::take(&mut comments)),
+        });
+    }
+    ConfigValue::Map(entries)
+}
+
+pub fn edit(content: &str, edits: &[ConfigEdit]) -> Result<String, Error> {
+    let operations = edits
+        .iter()
+        .map(|edit| {
+            let key = match edit.path.as_slice() {
+                [key] => key,
+                _ => {
+                    return Err(Error::bad_request(
+                        "Properties files do not have nested keys".to_string(),
+                    ))
+                }
+            };
+            let value = match &edit.value {
+                ConfigValue::Null => String::new(),
+                value => scalar_to_string(value)?,
+            };
+            if value.contains('\n') {
+                return Err(Error::bad_request(
+                    "Property values cannot contain line breaks".to_string(),
+                ));
+            }
+            Ok(TextPatchOperation::SetProperty {
+                key: key.clone(),
+                value,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    apply_text_patch(content, &operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit, parse};
+    use crate::config_editor::{ConfigEdit, ConfigEntry, ConfigValue};
+
+    #[test]
+    fn test_parse_and_edit() {
+        let content = "#Minecraft server properties\nmotd=A Minecraft Server\nserver-port=25565\n";
+        assert_eq!(
+            parse(content),
+            ConfigValue::Map(vec![
+                ConfigEntry {
+                    key: "motd".to_string(),
+                    value: ConfigValue::String("A Minecraft Server".to_string()),
+                    comment: Some("Minecraft server properties".to_string()),
+                },
+                ConfigEntry {
+                    key: "server-port".to_string(),
+                    value: ConfigValue::Integer(25565),
+                    comment: None,
+                },
+            ])
+        );
+        let edited = edit(
+            content,
+            &[ConfigEdit {
+                path: vec!["server-port".to_string()],
+                value: ConfigValue::Integer(25566),
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            edited,
+            "#Minecraft server properties\nmotd=A Minecraft Server\nserver-port=25566\n"
+        );
+        assert!(edit(
+            content,
+            &[ConfigEdit {
+                path: vec!["a".to_string(), "b".to_string()],
+                value: ConfigValue::Integer(1),
+            }],
+        )
+        .is_err());
+    }
+}
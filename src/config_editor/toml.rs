@@ -0,0 +1,201 @@
+use toml_edit::{Decor, Document, Item, Table, TableLike, Value};
+
+use crate::error::Error;
+
+use super::{comment_from_lines, ConfigEdit, ConfigEntry, ConfigValue};
+
+fn decor_comment(decor: Option<&Decor>) -> Option<String> {
+    let prefix = decor?.prefix()?.as_str()?;
+    comment_from_lines(
+        prefix
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix('#'))
+            .map(|line| line.trim().to_string())
+            .collect(),
+    )
+}
+
+fn from_toml_value(value: &Value) -> ConfigValue {
+    match value {
+        Value::String(s) => ConfigValue::String(s.value().clone()),
+        Value::Integer(i) => ConfigValue::Integer(*i.value()),
+        Value::Float(f) => ConfigValue::Float(*f.value()),
+        Value::Boolean(b) => ConfigValue::Boolean(*b.value()),
+        Value::Datetime(d) => ConfigValue::String(d.value().to_string()),
+        Value::Array(array) => ConfigValue::List(array.iter().map(from_toml_value).collect()),
+        Value::InlineTable(table) => ConfigValue::Map(
+            table
+                .iter()
+                .map(|(key, value)| ConfigEntry {
+                    key: key.to_string(),
+                    value: from_toml_value(value),
+                    comment: None,
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn from_toml_table(table: &Table) -> ConfigValue {
+    ConfigValue::Map(
+        table
+            .iter()
+            .map(|(key, item)| {
+                let comment = match item {
+                    Item::Table(sub_table) => decor_comment(Some(sub_table.decor())),
+                    _ => decor_comment(table.key_decor(key)),
+                };
+                ConfigEntry {
+                    key: key.to_string(),
+                    value: from_toml_item(item),
+                    comment,
+                }
+            })
+            .collect(),
+    )
+}
+
+fn from_toml_item(item: &Item) -> ConfigValue {
+    match item {
+        Item::None => ConfigValue::Null,
+        Item::Value(value) => from_toml_value(value),
+        Item::Table(table) => from_toml_table(table),
+        Item::ArrayOfTables(tables) => {
+            ConfigValue::List(tables.iter().map(from_toml_table).collect())
+        }
+    }
+}
+
+fn to_toml_value(value: &ConfigValue) -> Result<Value, Error> {
+    Ok(match value {
+        ConfigValue::String(s) => Value::from(s.as_str()),
+        ConfigValue::Integer(i) => Value::from(*i),
+        ConfigValue::Float(f) => Value::from(*f),
+        ConfigValue::Boolean(b) => Value::from(*b),
+        ConfigValue::Null => {
+            return Err(Error::bad_request(
+                "TOML does not have null values".to_string(),
+            ))
+        }
+        ConfigValue::List(values) => Value::Array(
+            values
+                .iter()
+                .map(to_toml_value)
+                .collect::<Result<_, Error>>()?,
+        ),
+        ConfigValue::Map(entries) => Value::InlineTable(
+            entries
+                .iter()
+                .map(|entry| Ok((entry.key.clone(), to_toml_value(&entry.value)?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+    })
+}
+
+fn parse_document(content: &str) -> Result<Document, Error> {
+    content
+        .parse::<Document>()
+        .map_err(|e| Error::bad_request(format!("Failed to parse TOML: {e}")))
+}
+
+pub fn parse(content: &str) -> Result<ConfigValue, Error> {
+    Ok(from_toml_table(parse_document(content)?.as_table()))
+}
+
+pub fn edit(content: &str, edits: &[ConfigEdit]) -> Result<String, Error> {
+    let mut document = parse_document(content)?;
+    for edit in edits {
+        let (key, parents) = edit
+            .path
+            .split_last()
+            .ok_or_else(|| Error::bad_request("Path cannot be empty".to_string()))?;
+        let mut table: &mut dyn TableLike = document.as_table_mut();
+        for parent in parents {
+            table = table
+                .get_mut(parent)
+                .and_then(|item| item.as_table_like_mut())
+                .ok_or_else(|| {
+                    Error::bad_request(format!("{} is not a table", edit.path.join(".")))
+                })?;
+        }
+        let mut value = to_toml_value(&edit.value)?;
+        match table.get_mut(key) {
+            Some(Item::Value(old_value)) => {
+                // keep the whitespace and trailing comment around the old value
+                *value.decor_mut() = old_value.decor().clone();
+                *old_value = value;
+            }
+            Some(Item::None) | None => {
+                table.insert(key, Item::Value(value));
+            }
+            Some(_) => {
+                return Err(Error::bad_request(format!(
+                    "{} is a table and cannot be replaced",
+                    edit.path.join(".")
+                )))
+            }
+        }
+    }
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit, parse};
+    use crate::config_editor::{ConfigEdit, ConfigEntry, ConfigValue};
+
+    #[test]
+    fn test_parse() {
+        let content = "# the name\nname = \"server\"\n\n[limits]\nplayers = 10 # max\n";
+        assert_eq!(
+            parse(content).unwrap(),
+            ConfigValue::Map(vec![
+                ConfigEntry {
+                    key: "name".to_string(),
+                    value: ConfigValue::String("server".to_string()),
+                    comment: Some("the name".to_string()),
+                },
+                ConfigEntry {
+                    key: "limits".to_string(),
+                    value: ConfigValue::Map(vec![ConfigEntry {
+                        key: "players".to_string(),
+                        value: ConfigValue::Integer(10),
+                        comment: None,
+                    }]),
+                    comment: None,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_edit_keeps_comments() {
+        let content = "# the name\nname = \"server\"\n\n[limits]\nplayers = 10 # max\n";
+        let edited = edit(
+            content,
+            &[
+                ConfigEdit {
+                    path: vec!["limits".to_string(), "players".to_string()],
+                    value: ConfigValue::Integer(20),
+                },
+                ConfigEdit {
+                    path: vec!["limits".to_string(), "view_distance".to_string()],
+                    value: ConfigValue::Integer(8),
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            edited,
+            "# the name\nname = \"server\"\n\n[limits]\nplayers = 20 # max\nview_distance = 8\n"
+        );
+        assert!(edit(
+            content,
+            &[ConfigEdit {
+                path: vec!["limits".to_string()],
+                value: ConfigValue::Integer(1),
+            }],
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,646 @@
+//! A YAML reader for the block style subset used by server and plugin configs.
+//!
+//! Anchors, tags and multi-document files are not supported. Edits rewrite only the line
+//! holding the value so comments and formatting elsewhere are untouched.
+
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::text_patch::{apply_text_patch, TextPatchOperation};
+
+use super::{
+    comment_from_lines, infer_scalar, scalar_to_string, ConfigEdit, ConfigEntry, ConfigValue,
+};
+
+struct Line {
+    /// 0-based line number in the file
+    number: usize,
+    /// last line number belonging to this line, differs from `number` for block scalars
+    end: usize,
+    indent: usize,
+    /// byte offset of `text` in the raw line
+    offset: usize,
+    /// the content of the line without the trailing comment
+    text: String,
+    comments: Vec<String>,
+    block_scalar: Vec<String>,
+}
+
+enum Location {
+    /// byte range of an inline value in its line
+    Inline {
+        line: usize,
+        start: usize,
+        end: usize,
+    },
+    Block {
+        end_line: usize,
+        indent: usize,
+        is_map: bool,
+    },
+}
+
+struct Parser {
+    lines: Vec<Line>,
+    idx: usize,
+    locations: HashMap<Vec<String>, Location>,
+}
+
+/// Split off a trailing comment, a `#` starts a comment outside of quotes when preceded by whitespace
+fn split_comment(s: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev = None;
+    for (i, c) in s.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if q == '"' && c == '\\' {
+                    escaped = true;
+                } else if q == '\'' && c == q && s[i + 1..].starts_with(q) {
+                    // '' is an escaped quote in a single quoted string
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' if prev.is_none_or(|p: char| p.is_whitespace() || "[{,".contains(p)) => {
+                    quote = Some(c)
+                }
+                '#' if prev.is_none_or(char::is_whitespace) => {
+                    return (&s[..i], Some(s[i + 1..].trim()))
+                }
+                _ => {}
+            },
+        }
+        prev = Some(c);
+    }
+    (s, None)
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn is_block_indicator(value: &str) -> bool {
+    matches!(value, "|" | "|-" | "|+" | ">" | ">-" | ">+")
+}
+
+/// Returns the key and the byte offset of the value in `text`
+fn split_key(text: &str) -> Option<(String, usize)> {
+    if let Some(quote) = text.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let close = text[1..].find(quote)? + 1;
+        let rest = &text[close + 1..];
+        if rest.trim_start().starts_with(':') {
+            let colon = close + 1 + rest.find(':')?;
+            return Some((text[1..close].to_string(), colon + 1));
+        }
+        return None;
+    }
+    let colon = match text.find(": ") {
+        Some(colon) => colon,
+        None if text.ends_with(':') => text.len() - 1,
+        None => return None,
+    };
+    Some((text[..colon].trim_end().to_string(), colon + 1))
+}
+
+/// Split a flow collection on the commas that are not inside quotes or nested collections
+fn split_flow(s: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                ret.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push(s[start..].trim());
+    ret.retain(|s| !s.is_empty());
+    ret
+}
+
+fn parse_scalar(s: &str) -> Result<ConfigValue, Error> {
+    if let Some(inner) = s.strip_prefix('"') {
+        let inner = inner
+            .strip_suffix('"')
+            .ok_or_else(|| Error::bad_request(format!("Unterminated string {s}")))?;
+        let mut ret = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                ret.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some(c) => ret.push(c),
+                None => {}
+            }
+        }
+        return Ok(ConfigValue::String(ret));
+    }
+    if let Some(inner) = s.strip_prefix('\'') {
+        let inner = inner
+            .strip_suffix('\'')
+            .ok_or_else(|| Error::bad_request(format!("Unterminated string {s}")))?;
+        return Ok(ConfigValue::String(inner.replace("''", "'")));
+    }
+    if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return Ok(ConfigValue::List(
+            split_flow(inner)
+                .into_iter()
+                .map(parse_scalar)
+                .collect::<Result<_, _>>()?,
+        ));
+    }
+    if let Some(inner) = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+        return Ok(ConfigValue::Map(
+            split_flow(inner)
+                .into_iter()
+                .map(|entry| {
+                    let (key, value) = entry.split_once(':').ok_or_else(|| {
+                        Error::bad_request(format!("Expected a key in flow mapping {s}"))
+                    })?;
+                    Ok(ConfigEntry {
+                        key: key.trim().trim_matches(['"', '\'']).to_string(),
+                        value: parse_scalar(value.trim())?,
+                        comment: None,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+        ));
+    }
+    match s {
+        "null" | "Null" | "NULL" | "~" | "" => Ok(ConfigValue::Null),
+        _ => Ok(infer_scalar(s)),
+    }
+}
+
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.trim() != s
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.contains(['\n', '\t', '\\'])
+        || s.starts_with([
+            '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%',
+            '@', '`',
+        ])
+        || !matches!(parse_scalar(s), Ok(ConfigValue::String(ref parsed)) if parsed == s)
+}
+
+fn format_scalar(value: &ConfigValue) -> Result<String, Error> {
+    match value {
+        ConfigValue::String(s) if needs_quotes(s) => Ok(format!(
+            "\"{}\"",
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\t', "\\t")
+        )),
+        value => scalar_to_string(value),
+    }
+}
+
+fn read_lines(content: &str) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut comments = Vec::new();
+    // indentation of the key owning the block scalar currently being read
+    let mut block_scalar_indent = None;
+    for (number, raw) in content.lines().enumerate() {
+        let text = raw.trim_start();
+        let indent = raw.len() - text.len();
+        if let Some(block_indent) = block_scalar_indent {
+            if text.is_empty() || indent > block_indent {
+                let line = lines.last_mut().unwrap();
+                line.block_scalar.push(raw.to_string());
+                if !text.is_empty() {
+                    line.end = number;
+                }
+                continue;
+            }
+            block_scalar_indent = None;
+        }
+        if text.is_empty() || text == "---" {
+            continue;
+        }
+        if let Some(comment) = text.strip_prefix('#') {
+            comments.push(comment.trim().to_string());
+            continue;
+        }
+        let text = split_comment(text).0.trim_end();
+        let item = text.strip_prefix("- ").unwrap_or(text);
+        let value = split_key(item).map_or(item, |(_, offset)| item[offset..].trim());
+        if is_block_indicator(value) {
+            block_scalar_indent = Some(indent);
+        }
+        lines.push(Line {
+            number,
+            end: number,
+            indent,
+            offset: indent,
+            text: text.to_string(),
+            comments: std::mem::take(&mut comments),
+            block_scalar: Vec::new(),
+        });
+    }
+    lines
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> Error {
+        match self.lines.get(self.idx) {
+            Some(line) => Error::bad_request(format!("{msg} at line {}", line.number + 1)),
+            None => Error::bad_request(format!("{msg} at the end of the file")),
+        }
+    }
+
+    fn last_end(&self) -> usize {
+        self.lines[self.idx - 1].end
+    }
+
+    fn parse_block(&mut self, path: &[String]) -> Result<ConfigValue, Error> {
+        let line = &self.lines[self.idx];
+        if is_sequence_item(&line.text) {
+            self.parse_sequence(line.indent, path)
+        } else {
+            self.parse_map(line.indent, path)
+        }
+    }
+
+    /// Parse the value after a key or a sequence item marker
+    fn parse_value(
+        &mut self,
+        value_offset: usize,
+        indent: usize,
+        path: Vec<String>,
+    ) -> Result<ConfigValue, Error> {
+        let line = &self.lines[self.idx];
+        let number = line.number;
+        let value = line.text[value_offset..].trim();
+        let start = line.offset + line.text.len() - value.len();
+        let end = line.offset + line.text.len();
+        if is_block_indicator(value) {
+            let block_indent = line
+                .block_scalar
+                .iter()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| l.len() - l.trim_start().len())
+                .min()
+                .unwrap_or(0);
+            let lines: Vec<&str> = line
+                .block_scalar
+                .iter()
+                .map(|l| l.get(block_indent..).unwrap_or("").trim_end())
+                .collect();
+            let separator = if value.starts_with('|') { "\n" } else { " " };
+            let ret = ConfigValue::String(lines.join(separator).trim_end().to_string());
+            self.locations.insert(
+                path,
+                Location::Block {
+                    end_line: line.end,
+                    indent,
+                    is_map: false,
+                },
+            );
+            self.idx += 1;
+            return Ok(ret);
+        }
+        let value = if value.is_empty() {
+            None
+        } else {
+            Some(parse_scalar(value))
+        };
+        self.idx += 1;
+        match value {
+            Some(value) => {
+                self.locations.insert(
+                    path,
+                    Location::Inline {
+                        line: number,
+                        start,
+                        end,
+                    },
+                );
+                value
+            }
+            None => match self.lines.get(self.idx) {
+                Some(next)
+                    if next.indent > indent
+                        || (next.indent == indent && is_sequence_item(&next.text)) =>
+                {
+                    self.parse_block(&path)
+                }
+                _ => {
+                    self.locations.insert(
+                        path,
+                        Location::Inline {
+                            line: number,
+                            start: end,
+                            end,
+                        },
+                    );
+                    Ok(ConfigValue::Null)
+                }
+            },
+        }
+    }
+
+    fn parse_map(&mut self, indent: usize, path: &[String]) -> Result<ConfigValue, Error> {
+        let mut entries = Vec::new();
+        while let Some(line) = self.lines.get(self.idx) {
+            if line.indent < indent {
+                break;
+            }
+            if line.indent > indent {
+                return Err(self.error("Unexpected indentation"));
+            }
+            if is_sequence_item(&line.text) {
+                return Err(self.error("Unexpected sequence item"));
+            }
+            let (key, value_offset) =
+                split_key(&line.text).ok_or_else(|| self.error("Expected a key"))?;
+            let comment = comment_from_lines(line.comments.clone());
+            let mut entry_path = path.to_vec();
+            entry_path.push(key.clone());
+            let value = self.parse_value(value_offset, indent, entry_path)?;
+            entries.push(ConfigEntry {
+                key,
+                value,
+                comment,
+            });
+        }
+        self.locations.insert(
+            path.to_vec(),
+            Location::Block {
+                end_line: self.last_end(),
+                indent,
+                is_map: true,
+            },
+        );
+        Ok(ConfigValue::Map(entries))
+    }
+
+    fn parse_sequence(&mut self, indent: usize, path: &[String]) -> Result<ConfigValue, Error> {
+        let mut items = Vec::new();
+        while let Some(line) = self.lines.get(self.idx) {
+            if line.indent < indent || (line.indent == indent && !is_sequence_item(&line.text)) {
+                break;
+            }
+            if line.indent > indent {
+                return Err(self.error("Unexpected indentation"));
+            }
+            let mut item_path = path.to_vec();
+            item_path.push(items.len().to_string());
+            let rest = line.text[1..].trim_start();
+            if !rest.is_empty() && split_key(rest).is_some() && !rest.starts_with(['[', '{']) {
+                // `- key: value` starts a map indented like its first key
+                let shift = line.text.len() - rest.len();
+                let line = &mut self.lines[self.idx];
+                line.indent += shift;
+                line.offset += shift;
+                line.text = line.text[shift..].to_string();
+                let map_indent = line.indent;
+                items.push(self.parse_map(map_indent, &item_path)?);
+            } else {
+                items.push(self.parse_value(1, indent, item_path)?);
+            }
+        }
+        self.locations.insert(
+            path.to_vec(),
+            Location::Block {
+                end_line: self.last_end(),
+                indent,
+                is_map: false,
+            },
+        );
+        Ok(ConfigValue::List(items))
+    }
+}
+
+fn parse_with_locations(
+    content: &str,
+) -> Result<(ConfigValue, HashMap<Vec<String>, Location>), Error> {
+    let mut parser = Parser {
+        lines: read_lines(content),
+        idx: 0,
+        locations: HashMap::new(),
+    };
+    if parser.lines.is_empty() {
+        return Ok((ConfigValue::Map(Vec::new()), parser.locations));
+    }
+    let first = &parser.lines[0].text;
+    let root = if parser.lines.len() == 1 && !is_sequence_item(first) && split_key(first).is_none()
+    {
+        parse_scalar(&parser.lines[0].text)?
+    } else {
+        parser.parse_block(&[])?
+    };
+    if parser.idx < parser.lines.len() {
+        return Err(parser.error("Unexpected indentation"));
+    }
+    Ok((root, parser.locations))
+}
+
+pub fn parse(content: &str) -> Result<ConfigValue, Error> {
+    Ok(parse_with_locations(content)?.0)
+}
+
+pub fn edit(content: &str, edit: &ConfigEdit) -> Result<String, Error> {
+    let value = format_scalar(&edit.value)?;
+    let (_, locations) = parse_with_locations(content)?;
+    let operation = match locations.get(&edit.path) {
+        Some(Location::Inline { line, start, end }) => {
+            let raw = content.lines().nth(*line).unwrap_or_default();
+            let separator = if start == end { " " } else { "" };
+            TextPatchOperation::ReplaceLines {
+                start: line + 1,
+                end: line + 1,
+                lines: vec![format!(
+                    "{}{separator}{value}{}",
+                    &raw[..*start],
+                    &raw[*end..]
+                )],
+            }
+        }
+        Some(Location::Block { .. }) => {
+            return Err(Error::bad_request(format!(
+                "{} is not a single line value and cannot be replaced",
+                edit.path.join(".")
+            )))
+        }
+        None => {
+            let (key, parent) = edit
+                .path
+                .split_last()
+                .ok_or_else(|| Error::bad_request("Path cannot be empty".to_string()))?;
+            let (line, indent) = match locations.get(parent) {
+                Some(Location::Block {
+                    end_line,
+                    indent,
+                    is_map: true,
+                }) => (end_line + 1, *indent),
+                None if parent.is_empty() => (content.lines().count(), 0),
+                _ => {
+                    return Err(Error::bad_request(format!(
+                        "{} is not a map",
+                        if parent.is_empty() {
+                            "The root".to_string()
+                        } else {
+                            parent.join(".")
+                        }
+                    )))
+                }
+            };
+            let key = if needs_quotes(key) {
+                format_scalar(&ConfigValue::String(key.clone()))?
+            } else {
+                key.clone()
+            };
+            TextPatchOperation::InsertLines {
+                line: line + 1,
+                lines: vec![format!("{}{key}: {value}", " ".repeat(indent))],
+            }
+        }
+    };
+    apply_text_patch(content, &[operation])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit, parse, split_comment};
+    use crate::config_editor::{ConfigEdit, ConfigEntry, ConfigValue};
+
+    const CONTENT: &str = "\
+# The Paper config
+_version: 28
+chunk-loading:
+  # Whether to load chunks
+  enabled: true # keep this on
+  rate: 0.5
+  message: 'It''s loading'
+worlds:
+- world
+- name: nether
+  seed: 42
+motd: |
+  line one
+
+  line two
+empty: []
+";
+
+    fn entry(key: &str, value: ConfigValue, comment: Option<&str>) -> ConfigEntry {
+        ConfigEntry {
+            key: key.to_string(),
+            value,
+            comment: comment.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_split_comment() {
+        assert_eq!(split_comment("a: b # c"), ("a: b ", Some("c")));
+        assert_eq!(split_comment("a: 'b # c'"), ("a: 'b # c'", None));
+        assert_eq!(split_comment("a: b#c"), ("a: b#c", None));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(CONTENT).unwrap(),
+            ConfigValue::Map(vec![
+                entry(
+                    "_version",
+                    ConfigValue::Integer(28),
+                    Some("The Paper config")
+                ),
+                entry(
+                    "chunk-loading",
+                    ConfigValue::Map(vec![
+                        entry(
+                            "enabled",
+                            ConfigValue::Boolean(true),
+                            Some("Whether to load chunks")
+                        ),
+                        entry("rate", ConfigValue::Float(0.5), None),
+                        entry(
+                            "message",
+                            ConfigValue::String("It's loading".to_string()),
+                            None
+                        ),
+                    ]),
+                    None
+                ),
+                entry(
+                    "worlds",
+                    ConfigValue::List(vec![
+                        ConfigValue::String("world".to_string()),
+                        ConfigValue::Map(vec![
+                            entry("name", ConfigValue::String("nether".to_string()), None),
+                            entry("seed", ConfigValue::Integer(42), None),
+                        ]),
+                    ]),
+                    None
+                ),
+                entry(
+                    "motd",
+                    ConfigValue::String("line one\n\nline two".to_string()),
+                    None
+                ),
+                entry("empty", ConfigValue::List(vec![]), None),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_edit_keeps_formatting() {
+        let set = |content: &str, path: &[&str], value: ConfigValue| {
+            edit(
+                content,
+                &ConfigEdit {
+                    path: path.iter().map(|s| s.to_string()).collect(),
+                    value,
+                },
+            )
+        };
+        let edited = set(
+            CONTENT,
+            &["chunk-loading", "enabled"],
+            ConfigValue::Boolean(false),
+        )
+        .unwrap();
+        assert!(edited.contains("  enabled: false # keep this on\n"));
+        let edited = set(
+            &edited,
+            &["chunk-loading", "new-key"],
+            ConfigValue::String("yes: no".to_string()),
+        )
+        .unwrap();
+        assert!(edited.contains("  message: 'It''s loading'\n  new-key: \"yes: no\"\nworlds:"));
+        let edited = set(&edited, &["worlds", "1", "seed"], ConfigValue::Integer(7)).unwrap();
+        assert!(edited.contains("  seed: 7\n"));
+        assert_eq!(
+            set(&edited, &["worlds", "0"], ConfigValue::Null).unwrap(),
+            edited.replace("- world\n", "- null\n")
+        );
+        assert!(set(&edited, &["motd"], ConfigValue::Null).is_err());
+        assert!(set(&edited, &["worlds", "2"], ConfigValue::Null).is_err());
+        assert!(set(&edited, &["missing", "key"], ConfigValue::Null).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+//! Parsing of console output into structured events.
+//!
+//! Every line an instance prints runs through a pipeline of regex matchers, each match is
+//! broadcast as a `ConsoleMatch` event carrying the groups the matcher captured. Minecraft
+//! servers come with matchers for warnings, errors and advancements on top of the startup, join
+//! and leave messages of their console profile. Users add their own matchers per instance, those
+//! run over the output of instances of any game.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+};
+
+pub const MAX_MATCHERS_PER_INSTANCE: usize = 32;
+const MAX_MATCHER_NAME_LEN: usize = 32;
+const MAX_PATTERN_LEN: usize = 512;
+
+/// What a line that matched means
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ConsoleMatchKind {
+    StartupComplete,
+    PlayerJoin,
+    PlayerLeave,
+    Advancement,
+    Warning,
+    Error,
+    Custom,
+}
+
+impl ConsoleMatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsoleMatchKind::StartupComplete => "startup_complete",
+            ConsoleMatchKind::PlayerJoin => "player_join",
+            ConsoleMatchKind::PlayerLeave => "player_leave",
+            ConsoleMatchKind::Advancement => "advancement",
+            ConsoleMatchKind::Warning => "warning",
+            ConsoleMatchKind::Error => "error",
+            ConsoleMatchKind::Custom => "custom",
+        }
+    }
+}
+
+/// A line matched by a matcher, `groups` are its capture groups in order, without the whole
+/// match, and `named_groups` the ones with a name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsoleMatch {
+    pub matcher: String,
+    pub kind: ConsoleMatchKind,
+    pub groups: Vec<String>,
+    pub named_groups: BTreeMap<String, String>,
+}
+
+impl ConsoleMatch {
+    /// A match found by the core rather than by a regex, named after its kind
+    pub fn builtin(kind: ConsoleMatchKind, named_groups: &[(&str, &str)]) -> Self {
+        Self {
+            matcher: kind.as_str().to_string(),
+            kind,
+            groups: named_groups
+                .iter()
+                .map(|(_, value)| value.to_string())
+                .collect(),
+            named_groups: named_groups
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn into_event(
+        self,
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        line: &str,
+    ) -> Event {
+        Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_event_inner: InstanceEventInner::ConsoleMatch {
+                    matcher: self.matcher,
+                    kind: self.kind,
+                    line: line.trim_end().to_string(),
+                    groups: self.groups,
+                    named_groups: self.named_groups,
+                },
+                instance_name,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        }
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, Error> {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(Error::bad_request(format!(
+            "The pattern is longer than {MAX_PATTERN_LEN} characters"
+        )));
+    }
+    Regex::new(pattern).map_err(|e| Error::bad_request(format!("The pattern is invalid : {e}")))
+}
+
+fn captures(
+    matcher: &str,
+    kind: ConsoleMatchKind,
+    regex: &Regex,
+    line: &str,
+) -> Option<ConsoleMatch> {
+    let line = line.trim_end();
+    let captures = regex.captures(line).ok()??;
+    let groups = captures
+        .iter()
+        .skip(1)
+        .map(|group| {
+            group
+                .map(|group| group.as_str().to_string())
+                .unwrap_or_default()
+        })
+        .collect();
+    let named_groups = regex
+        .capture_names()
+        .flatten()
+        .filter_map(|name| Some((name.to_string(), captures.name(name)?.as_str().to_string())))
+        .collect();
+    Some(ConsoleMatch {
+        matcher: matcher.to_string(),
+        kind,
+        groups,
+        named_groups,
+    })
+}
+
+/// A matcher ready to run over lines
+pub struct CompiledMatcher {
+    name: String,
+    kind: ConsoleMatchKind,
+    regex: Regex,
+}
+
+impl CompiledMatcher {
+    pub fn new(name: &str, kind: ConsoleMatchKind, pattern: &str) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.to_string(),
+            kind,
+            regex: compile_pattern(pattern)?,
+        })
+    }
+
+    pub fn apply(&self, line: &str) -> Option<ConsoleMatch> {
+        captures(&self.name, self.kind, &self.regex, line)
+    }
+}
+
+/// Matchers run in order over every line, a line can match several of them
+#[derive(Default)]
+pub struct ConsolePipeline {
+    matchers: Vec<CompiledMatcher>,
+}
+
+impl ConsolePipeline {
+    pub fn new(matchers: Vec<CompiledMatcher>) -> Self {
+        Self { matchers }
+    }
+
+    pub fn parse(&self, line: &str) -> Vec<ConsoleMatch> {
+        self.matchers
+            .iter()
+            .filter_map(|matcher| matcher.apply(line))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ConsoleMatcher {
+    pub id: String,
+    pub name: String,
+    pub kind: ConsoleMatchKind,
+    /// matched against whole lines, named groups like `(?P<player>\S+)` are reported by name
+    pub pattern: String,
+    pub enabled: bool,
+    pub creation_time: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleMatcherConfig {
+    pub name: String,
+    #[serde(default = "default_kind")]
+    pub kind: ConsoleMatchKind,
+    pub pattern: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_kind() -> ConsoleMatchKind {
+    ConsoleMatchKind::Custom
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ConsoleMatcherConfig {
+    fn validate(&self) -> Result<(), Error> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err(Error::bad_request(
+                "The name of a matcher cannot be empty".to_string(),
+            ));
+        }
+        if name.len() > MAX_MATCHER_NAME_LEN {
+            return Err(Error::bad_request(format!(
+                "The name of a matcher cannot be longer than {MAX_MATCHER_NAME_LEN} characters"
+            )));
+        }
+        compile_pattern(&self.pattern)?;
+        Ok(())
+    }
+}
+
+/// Console matchers users added to instances, persisted in the stores directory
+pub struct ConsoleMatchers {
+    path_to_store: PathBuf,
+    matchers: HashMap<InstanceUuid, Vec<ConsoleMatcher>>,
+}
+
+impl ConsoleMatchers {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            matchers: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.matchers = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read console matchers file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.matchers = serde_json::from_slice(&content).context(format!(
+            "Failed to parse console matchers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.matchers)
+                .context("Failed to serialize console matchers")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write console matchers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn matchers(&self, instance_uuid: &InstanceUuid) -> &[ConsoleMatcher] {
+        self.matchers
+            .get(instance_uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn not_found() -> Error {
+        Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Console matcher not found"),
+        }
+    }
+
+    fn check_name(
+        &self,
+        instance_uuid: &InstanceUuid,
+        name: &str,
+        except_id: Option<&str>,
+    ) -> Result<(), Error> {
+        if self
+            .matchers(instance_uuid)
+            .iter()
+            .any(|matcher| matcher.name == name && Some(matcher.id.as_str()) != except_id)
+        {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("A matcher named {name} already exists"),
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn add_matcher(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        config: ConsoleMatcherConfig,
+    ) -> Result<ConsoleMatcher, Error> {
+        config.validate()?;
+        let name = config.name.trim().to_string();
+        self.check_name(instance_uuid, &name, None)?;
+        if self.matchers(instance_uuid).len() >= MAX_MATCHERS_PER_INSTANCE {
+            return Err(Error::bad_request(format!(
+                "An instance cannot have more than {MAX_MATCHERS_PER_INSTANCE} matchers"
+            )));
+        }
+        let matcher = ConsoleMatcher {
+            id: rand_alphanumeric(16),
+            name,
+            kind: config.kind,
+            pattern: config.pattern,
+            enabled: config.enabled,
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        let old_matchers = self.matchers.clone();
+        self.matchers
+            .entry(instance_uuid.clone())
+            .or_default()
+            .push(matcher.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.matchers = old_matchers;
+            return Err(e);
+        }
+        Ok(matcher)
+    }
+
+    pub async fn update_matcher(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+        config: ConsoleMatcherConfig,
+    ) -> Result<ConsoleMatcher, Error> {
+        config.validate()?;
+        let name = config.name.trim().to_string();
+        self.check_name(instance_uuid, &name, Some(id))?;
+        let old_matchers = self.matchers.clone();
+        let matcher = self
+            .matchers
+            .get_mut(instance_uuid)
+            .and_then(|matchers| matchers.iter_mut().find(|matcher| matcher.id == id))
+            .ok_or_else(Self::not_found)?;
+        matcher.name = name;
+        matcher.kind = config.kind;
+        matcher.pattern = config.pattern;
+        matcher.enabled = config.enabled;
+        let matcher = matcher.clone();
+        if let Err(e) = self.write_to_file().await {
+            self.matchers = old_matchers;
+            return Err(e);
+        }
+        Ok(matcher)
+    }
+
+    pub async fn remove_matcher(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+    ) -> Result<(), Error> {
+        let old_matchers = self.matchers.clone();
+        let matchers = self
+            .matchers
+            .get_mut(instance_uuid)
+            .ok_or_else(Self::not_found)?;
+        let index = matchers
+            .iter()
+            .position(|matcher| matcher.id == id)
+            .ok_or_else(Self::not_found)?;
+        matchers.remove(index);
+        if matchers.is_empty() {
+            self.matchers.remove(instance_uuid);
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.matchers = old_matchers;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Forget the matchers of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.matchers.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.matchers.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the matchers users added over the output of their instance, broadcasting a
+/// `ConsoleMatch` event for every match
+pub async fn console_matcher_task(
+    mut event_receiver: Receiver<Event>,
+    console_matchers: Arc<Mutex<ConsoleMatchers>>,
+    event_broadcaster: EventBroadcaster,
+) {
+    let mut regexes: HashMap<String, Regex> = HashMap::new();
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Console matcher task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let (instance_event, line) = match &event.event_inner {
+            EventInner::InstanceEvent(
+                instance_event @ InstanceEvent {
+                    instance_event_inner: InstanceEventInner::InstanceOutput { message },
+                    ..
+                },
+            ) => (instance_event, message),
+            _ => continue,
+        };
+        let matchers = console_matchers
+            .lock()
+            .await
+            .matchers(&instance_event.instance_uuid)
+            .to_vec();
+        for matcher in matchers.iter().filter(|matcher| matcher.enabled) {
+            if !regexes.contains_key(&matcher.pattern) {
+                match Regex::new(&matcher.pattern) {
+                    Ok(regex) => {
+                        regexes.insert(matcher.pattern.clone(), regex);
+                    }
+                    Err(_) => continue,
+                }
+            }
+            if let Some(console_match) = captures(
+                &matcher.name,
+                matcher.kind,
+                &regexes[&matcher.pattern],
+                line,
+            ) {
+                event_broadcaster.send(console_match.into_event(
+                    instance_event.instance_uuid.clone(),
+                    instance_event.instance_name.clone(),
+                    line,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        CompiledMatcher, ConsoleMatch, ConsoleMatchKind, ConsoleMatcherConfig, ConsoleMatchers,
+        ConsolePipeline,
+    };
+    use crate::{error::ErrorKind, types::InstanceUuid};
+
+    #[test]
+    fn test_pipeline() {
+        let pipeline = ConsolePipeline::new(vec![
+            CompiledMatcher::new(
+                "vote",
+                ConsoleMatchKind::Custom,
+                r"\[Votifier\] Got a vote from (?P<site>\S+) for (\S+)",
+            )
+            .unwrap(),
+            CompiledMatcher::new("any_vote", ConsoleMatchKind::Custom, r"vote").unwrap(),
+        ]);
+        let matches = pipeline.parse(
+            "[12:00:00] [Server thread/INFO]: [Votifier] Got a vote from planet for Steve\n",
+        );
+        assert_eq!(
+            matches[0],
+            ConsoleMatch {
+                matcher: "vote".to_string(),
+                kind: ConsoleMatchKind::Custom,
+                groups: vec!["planet".to_string(), "Steve".to_string()],
+                named_groups: BTreeMap::from([("site".to_string(), "planet".to_string())]),
+            }
+        );
+        assert_eq!(matches.len(), 2);
+        assert!(pipeline.parse("Steve joined the game").is_empty());
+        assert!(CompiledMatcher::new("broken", ConsoleMatchKind::Custom, "(").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_matchers_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("console_matchers.json");
+        let mut store = ConsoleMatchers::new(path.clone());
+        let uuid = InstanceUuid::default();
+        let config = |name: &str, pattern: &str| ConsoleMatcherConfig {
+            name: name.to_string(),
+            kind: ConsoleMatchKind::Custom,
+            pattern: pattern.to_string(),
+            enabled: true,
+        };
+
+        let matcher = store
+            .add_matcher(&uuid, config("vote", r"Got a vote from (\S+)"))
+            .await
+            .unwrap();
+        let err = store
+            .add_matcher(&uuid, config("vote", "other"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Conflict);
+        let err = store
+            .add_matcher(&uuid, config("broken", "("))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadRequest);
+
+        let updated = store
+            .update_matcher(&uuid, &matcher.id, config("vote", r"Vote from (\S+)"))
+            .await
+            .unwrap();
+        assert_eq!(updated.pattern, r"Vote from (\S+)");
+
+        let mut reloaded = ConsoleMatchers::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.matchers(&uuid), &[updated]);
+
+        store.remove_matcher(&uuid, &matcher.id).await.unwrap();
+        assert!(store.matchers(&uuid).is_empty());
+        let err = store.remove_matcher(&uuid, &matcher.id).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NotFound);
+    }
+}
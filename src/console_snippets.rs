@@ -0,0 +1,397 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+/// Who can see and send a snippet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum SnippetScope {
+    /// only the user who saved it, on any instance they can access the console of
+    User { user_id: UserId },
+    /// everyone who can access the console of the instance
+    Instance { instance_uuid: InstanceUuid },
+}
+
+/// A saved console command. `{{name}}` in the template is a placeholder filled when it is sent,
+/// e.g. `give {{player}} diamond_sword{Enchantments:[{id:sharpness,lvl:5}]} 1`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ConsoleSnippet {
+    pub id: String,
+    pub name: String,
+    pub template: String,
+    /// the names of the placeholders in the template, in order of first appearance
+    pub placeholders: Vec<String>,
+    pub scope: SnippetScope,
+    pub created_by: UserId,
+    pub created_at: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleSnippetConfig {
+    pub name: String,
+    pub template: String,
+}
+
+/// The names of the `{{name}}` placeholders of a template, in order of first appearance
+pub fn parse_placeholders(template: &str) -> Result<Vec<String>, Error> {
+    let mut placeholders: Vec<String> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unclosed placeholder in \"{template}\""),
+        })?;
+        let name = after[..end].trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Invalid placeholder \"{name}\", expected letters, digits, '_' or '-'"
+                ),
+            });
+        }
+        if !placeholders.iter().any(|placeholder| placeholder == name) {
+            placeholders.push(name.to_string());
+        }
+        rest = &after[end + 2..];
+    }
+    Ok(placeholders)
+}
+
+impl ConsoleSnippet {
+    /// The command with its placeholders filled from `values`.
+    ///
+    /// A value can't span several lines, which would send more than one command.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String, Error> {
+        if let Some(unknown) = values.keys().find(|key| !self.placeholders.contains(key)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Snippet {} has no placeholder \"{unknown}\"", self.name),
+            });
+        }
+        let mut command = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("{{") {
+            command.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            // the template was checked when the snippet was saved
+            let end = after.find("}}").unwrap_or(after.len());
+            let name = after[..end].trim();
+            let value = values.get(name).ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Missing a value for placeholder \"{name}\""),
+            })?;
+            if value.contains(['\n', '\r']) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("The value of placeholder \"{name}\" cannot span several lines"),
+                });
+            }
+            command.push_str(value);
+            rest = after.get(end + 2..).unwrap_or_default();
+        }
+        command.push_str(rest);
+        Ok(command)
+    }
+}
+
+/// Saved console commands, persisted in the stores directory
+pub struct ConsoleSnippets {
+    path_to_store: PathBuf,
+    snippets: Vec<ConsoleSnippet>,
+}
+
+impl ConsoleSnippets {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            snippets: Vec::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.snippets = Vec::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read console snippets file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.snippets = serde_json::from_slice(&content).context(format!(
+            "Failed to parse console snippets file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.snippets)
+                .context("Failed to serialize console snippets")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write console snippets file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Result<ConsoleSnippet, Error> {
+        self.snippets
+            .iter()
+            .find(|snippet| snippet.id == id)
+            .cloned()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Console snippet not found"),
+            })
+    }
+
+    pub fn in_scope(&self, scope: &SnippetScope) -> Vec<ConsoleSnippet> {
+        self.snippets
+            .iter()
+            .filter(|snippet| &snippet.scope == scope)
+            .cloned()
+            .collect()
+    }
+
+    fn validate(config: &ConsoleSnippetConfig) -> Result<Vec<String>, Error> {
+        if config.name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Snippet name cannot be empty"),
+            });
+        }
+        if config.template.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Snippet template cannot be empty"),
+            });
+        }
+        if config.template.contains(['\n', '\r']) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A snippet is a single command and cannot span several lines"),
+            });
+        }
+        parse_placeholders(&config.template)
+    }
+
+    pub async fn create(
+        &mut self,
+        scope: SnippetScope,
+        config: ConsoleSnippetConfig,
+        created_by: UserId,
+    ) -> Result<ConsoleSnippet, Error> {
+        let placeholders = Self::validate(&config)?;
+        let snippet = ConsoleSnippet {
+            id: rand_alphanumeric(16),
+            name: config.name,
+            template: config.template,
+            placeholders,
+            scope,
+            created_by,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.snippets.push(snippet.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.snippets.pop();
+            return Err(e);
+        }
+        Ok(snippet)
+    }
+
+    pub async fn update(
+        &mut self,
+        id: &str,
+        config: ConsoleSnippetConfig,
+    ) -> Result<ConsoleSnippet, Error> {
+        let placeholders = Self::validate(&config)?;
+        let old_snippets = self.snippets.clone();
+        let snippet = self
+            .snippets
+            .iter_mut()
+            .find(|snippet| snippet.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Console snippet not found"),
+            })?;
+        snippet.name = config.name;
+        snippet.template = config.template;
+        snippet.placeholders = placeholders;
+        let snippet = snippet.clone();
+        if let Err(e) = self.write_to_file().await {
+            self.snippets = old_snippets;
+            return Err(e);
+        }
+        Ok(snippet)
+    }
+
+    pub async fn remove(&mut self, id: &str) -> Result<(), Error> {
+        let index = self
+            .snippets
+            .iter()
+            .position(|snippet| snippet.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Console snippet not found"),
+            })?;
+        let removed = self.snippets.remove(index);
+        if let Err(e) = self.write_to_file().await {
+            self.snippets.insert(index, removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn remove_scope(&mut self, scope: &SnippetScope) -> Result<(), Error> {
+        if !self.snippets.iter().any(|snippet| &snippet.scope == scope) {
+            return Ok(());
+        }
+        let old_snippets = self.snippets.clone();
+        self.snippets.retain(|snippet| &snippet.scope != scope);
+        if let Err(e) = self.write_to_file().await {
+            self.snippets = old_snippets;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Forget the snippets of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        self.remove_scope(&SnippetScope::Instance {
+            instance_uuid: instance_uuid.clone(),
+        })
+        .await
+    }
+
+    /// Forget the snippets of a deleted user
+    pub async fn remove_user(&mut self, user_id: &UserId) -> Result<(), Error> {
+        self.remove_scope(&SnippetScope::User {
+            user_id: user_id.clone(),
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{parse_placeholders, ConsoleSnippetConfig, ConsoleSnippets, SnippetScope};
+    use crate::auth::user_id::UserId;
+
+    #[test]
+    fn test_parse_placeholders() {
+        assert_eq!(
+            parse_placeholders("give {{player}} {{item}} {{ count }} {{player}}").unwrap(),
+            vec!["player", "item", "count"]
+        );
+        assert!(parse_placeholders("say {hi}").unwrap().is_empty());
+        assert!(parse_placeholders("give {{player} diamond").is_err());
+        assert!(parse_placeholders("give {{}} diamond").is_err());
+        assert!(parse_placeholders("give {{a player}} diamond").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_console_snippets() {
+        let temp_dir = tempdir::TempDir::new("test_console_snippets").unwrap();
+        let path = temp_dir.path().join("console_snippets.json");
+        let user_id = UserId::default();
+        let instance_uuid = "INSTANCE_a".to_string().into();
+        let user_scope = SnippetScope::User {
+            user_id: user_id.clone(),
+        };
+        let instance_scope = SnippetScope::Instance { instance_uuid };
+        let config = |name: &str, template: &str| ConsoleSnippetConfig {
+            name: name.to_string(),
+            template: template.to_string(),
+        };
+        let mut snippets = ConsoleSnippets::new(path.clone());
+        assert!(snippets
+            .create(
+                user_scope.clone(),
+                config("op", "op {{player}}\nstop"),
+                user_id.clone()
+            )
+            .await
+            .is_err());
+        let sword = snippets
+            .create(
+                user_scope.clone(),
+                config(
+                    "sword",
+                    "give {{player}} diamond_sword{Enchantments:[{id:sharpness,lvl:{{level}}}]} 1",
+                ),
+                user_id.clone(),
+            )
+            .await
+            .unwrap();
+        let day = snippets
+            .create(
+                instance_scope.clone(),
+                config("day", "time set day"),
+                user_id.clone(),
+            )
+            .await
+            .unwrap();
+
+        let values = HashMap::from([
+            ("player".to_string(), "Steve".to_string()),
+            ("level".to_string(), "5".to_string()),
+        ]);
+        assert_eq!(
+            sword.render(&values).unwrap(),
+            "give Steve diamond_sword{Enchantments:[{id:sharpness,lvl:5}]} 1"
+        );
+        assert!(sword
+            .render(&HashMap::from([(
+                "player".to_string(),
+                "Steve".to_string()
+            )]))
+            .is_err());
+        assert!(sword
+            .render(&HashMap::from([
+                ("player".to_string(), "Steve\nstop".to_string()),
+                ("level".to_string(), "5".to_string()),
+            ]))
+            .is_err());
+        assert!(day.render(&values).is_err());
+        assert_eq!(day.render(&HashMap::new()).unwrap(), "time set day");
+
+        let mut reloaded = ConsoleSnippets::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.in_scope(&user_scope), vec![sword.clone()]);
+        assert_eq!(reloaded.in_scope(&instance_scope), vec![day.clone()]);
+
+        let updated = snippets
+            .update(&day.id, config("night", "time set {{time}}"))
+            .await
+            .unwrap();
+        assert_eq!(updated.placeholders, vec!["time"]);
+        snippets.remove(&sword.id).await.unwrap();
+        assert!(snippets.get(&sword.id).is_err());
+        snippets.remove_user(&user_id).await.unwrap();
+        assert_eq!(snippets.in_scope(&instance_scope).len(), 1);
+    }
+}
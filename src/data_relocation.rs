@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{Error, ErrorKind};
+
+/// Left in the old data directory so that anything still pointing there finds the new one
+const REDIRECT_FILE_NAME: &str = ".lodestone_relocated.json";
+/// redirects are followed at most this many times, in case they form a loop
+const MAX_REDIRECTS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Redirect {
+    path: PathBuf,
+    /// the old data is deleted on the next start, once nothing has it open anymore
+    remove_old: bool,
+}
+
+fn bad_target(msg: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(msg),
+    }
+}
+
+/// Check that `to` can receive the data directory `from`
+pub fn validate_target(from: &Path, to: &Path) -> Result<(), Error> {
+    if !to.is_absolute() {
+        return Err(bad_target(format!(
+            "{} is not an absolute path",
+            to.display()
+        )));
+    }
+    let from = from
+        .canonicalize()
+        .context(format!("Failed to resolve {}", from.display()))?;
+    // the target may not exist yet, so only its closest existing ancestor can be resolved
+    let existing_ancestor = to
+        .ancestors()
+        .find(|path| path.exists())
+        .ok_or_else(|| bad_target(format!("{} does not exist", to.display())))?;
+    let resolved_to = existing_ancestor
+        .canonicalize()
+        .context(format!("Failed to resolve {}", to.display()))?
+        .join(to.strip_prefix(existing_ancestor).unwrap_or(Path::new("")));
+    if resolved_to.starts_with(&from) || from.starts_with(&resolved_to) {
+        return Err(bad_target(
+            "The new data directory cannot contain or be inside the current one".to_string(),
+        ));
+    }
+    if to.is_file() {
+        return Err(bad_target(format!("{} is a file", to.display())));
+    }
+    if to.is_dir()
+        && std::fs::read_dir(to)
+            .context(format!("Failed to read {}", to.display()))?
+            .next()
+            .is_some()
+    {
+        return Err(bad_target(format!("{} is not empty", to.display())));
+    }
+    Ok(())
+}
+
+/// Replace the `from` prefix of every string in `value` that is a path under `from`,
+/// returns whether anything changed
+fn rebase_paths(value: &mut Value, from: &Path, to: &Path) -> bool {
+    match value {
+        Value::String(s) => match Path::new(s.as_str()).strip_prefix(from) {
+            Ok(rest) => {
+                *s = to.join(rest).to_string_lossy().into_owned();
+                true
+            }
+            Err(_) => false,
+        },
+        Value::Array(values) => values.iter_mut().fold(false, |changed, value| {
+            rebase_paths(value, from, to) || changed
+        }),
+        Value::Object(map) => map.values_mut().fold(false, |changed, value| {
+            rebase_paths(value, from, to) || changed
+        }),
+        _ => false,
+    }
+}
+
+/// Point absolute paths in the configs of the instance in `instance_path`, like a custom java
+/// command or working directory, from under `from` to under `to`
+pub fn rebase_instance_config_paths(
+    instance_path: &Path,
+    from: &Path,
+    to: &Path,
+) -> Result<(), Error> {
+    for name in [
+        ".lodestone_minecraft_config.json",
+        ".lodestone_process_config.json",
+    ] {
+        let path = instance_path.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let content =
+            std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        let mut config: Value = serde_json::from_str(&content)
+            .context(format!("Failed to parse {}", path.display()))?;
+        if rebase_paths(&mut config, from, to) {
+            std::fs::write(
+                &path,
+                serde_json::to_string_pretty(&config)
+                    .context(format!("Failed to serialize {}", path.display()))?,
+            )
+            .context(format!("Failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Point absolute paths in the instance configs to the new data directory
+fn rebase_instance_configs(from: &Path, to: &Path) -> Result<(), Error> {
+    let path_to_instances = to.join("instances");
+    if !path_to_instances.is_dir() {
+        return Ok(());
+    }
+    for instance in std::fs::read_dir(&path_to_instances)
+        .context(format!("Failed to read {}", path_to_instances.display()))?
+    {
+        let instance = instance.context("Failed to read instance directory")?;
+        rebase_instance_config_paths(&instance.path(), from, to)?;
+    }
+    Ok(())
+}
+
+/// Copy the data directory `from` to `to` and leave a redirect behind in `from`
+///
+/// Instances must be stopped. `progress` is called with the bytes copied so far and the total.
+/// If `remove_old` is set the old data is deleted when the core next starts from `from`.
+pub fn relocate(
+    from: &Path,
+    to: &Path,
+    remove_old: bool,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), Error> {
+    validate_target(from, to)?;
+    std::fs::create_dir_all(to).context(format!("Failed to create {}", to.display()))?;
+    let mut options = fs_extra::dir::CopyOptions::new();
+    options.content_only = true;
+    fs_extra::dir::copy_with_progress(from, to, &options, |process| {
+        progress(process.copied_bytes, process.total_bytes);
+        fs_extra::dir::TransitProcessResult::ContinueOrAbort
+    })
+    .context(format!(
+        "Failed to copy {} to {}",
+        from.display(),
+        to.display()
+    ))?;
+    // a redirect copied over from an earlier relocation would point the new directory away
+    let _ = std::fs::remove_file(to.join(REDIRECT_FILE_NAME));
+    rebase_instance_configs(from, to)?;
+    std::fs::write(
+        from.join(REDIRECT_FILE_NAME),
+        serde_json::to_string_pretty(&Redirect {
+            path: to.to_owned(),
+            remove_old,
+        })
+        .context("Failed to serialize redirect")?,
+    )
+    .context(format!("Failed to write redirect in {}", from.display()))?;
+    Ok(())
+}
+
+fn read_redirect(path: &Path) -> Option<Redirect> {
+    let content = std::fs::read_to_string(path.join(REDIRECT_FILE_NAME)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Delete everything in `path` except the redirect
+fn remove_old_data(path: &Path) -> Result<(), Error> {
+    for entry in std::fs::read_dir(path).context(format!("Failed to read {}", path.display()))? {
+        let entry = entry.context(format!("Failed to read {}", path.display()))?;
+        if entry.file_name() == REDIRECT_FILE_NAME {
+            continue;
+        }
+        let result = if entry.path().is_dir() {
+            std::fs::remove_dir_all(entry.path())
+        } else {
+            std::fs::remove_file(entry.path())
+        };
+        result.context(format!("Failed to remove {}", entry.path().display()))?;
+    }
+    Ok(())
+}
+
+/// The data directory to use when started with `path`, following redirects left by relocations
+///
+/// Old data that was relocated with `remove_old` is deleted here, before anything opens it.
+/// Returns the messages to log once logging is set up.
+pub fn resolve_lodestone_path(path: PathBuf) -> (PathBuf, Vec<String>) {
+    let mut path = path;
+    let mut messages = Vec::new();
+    for _ in 0..MAX_REDIRECTS {
+        let redirect = match read_redirect(&path) {
+            Some(redirect) => redirect,
+            None => return (path, messages),
+        };
+        messages.push(format!(
+            "{} was relocated to {}",
+            path.display(),
+            redirect.path.display()
+        ));
+        if redirect.remove_old {
+            match remove_old_data(&path) {
+                Ok(()) => {
+                    let redirect = Redirect {
+                        path: redirect.path.clone(),
+                        remove_old: false,
+                    };
+                    if let Ok(content) = serde_json::to_string_pretty(&redirect) {
+                        let _ = std::fs::write(path.join(REDIRECT_FILE_NAME), content);
+                    }
+                    messages.push(format!("Removed the old data in {}", path.display()));
+                }
+                Err(e) => messages.push(format!("Failed to remove the old data : {e}")),
+            }
+        }
+        path = redirect.path;
+    }
+    messages.push("Too many data directory redirects, using the last one".to_string());
+    (path, messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{rebase_paths, relocate, resolve_lodestone_path, validate_target};
+
+    #[test]
+    fn test_rebase_paths() {
+        let mut config = json!({
+            "name": "survival",
+            "java_cmd": "/old/bin/java/jre17/bin/java",
+            "working_dir": "/older/instances",
+            "args": ["-jar", "/old/instances/a/server.jar"],
+        });
+        assert!(rebase_paths(
+            &mut config,
+            Path::new("/old"),
+            Path::new("/new")
+        ));
+        assert_eq!(
+            config,
+            json!({
+                "name": "survival",
+                "java_cmd": "/new/bin/java/jre17/bin/java",
+                "working_dir": "/older/instances",
+                "args": ["-jar", "/new/instances/a/server.jar"],
+            })
+        );
+        assert!(!rebase_paths(
+            &mut config,
+            Path::new("/old"),
+            Path::new("/new")
+        ));
+    }
+
+    #[test]
+    fn test_relocate() {
+        let temp_dir = tempdir::TempDir::new("test_relocate").unwrap();
+        let from = temp_dir.path().join("old");
+        let to = temp_dir.path().join("new");
+        let instance = from.join("instances").join("a");
+        std::fs::create_dir_all(&instance).unwrap();
+        std::fs::write(
+            instance.join(".lodestone_process_config.json"),
+            json!({ "working_dir": instance }).to_string(),
+        )
+        .unwrap();
+        std::fs::write(from.join("global_settings.json"), "{}").unwrap();
+
+        assert!(validate_target(&from, &from.join("nested")).is_err());
+        assert!(validate_target(&from, Path::new("relative")).is_err());
+
+        let mut last_progress = (0, 0);
+        relocate(&from, &to, true, |copied, total| {
+            last_progress = (copied, total)
+        })
+        .unwrap();
+        assert_eq!(last_progress.0, last_progress.1);
+        assert!(to.join("global_settings.json").is_file());
+        let config: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(
+                to.join("instances")
+                    .join("a")
+                    .join(".lodestone_process_config.json"),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config["working_dir"], json!(to.join("instances").join("a")));
+
+        let (resolved, _) = resolve_lodestone_path(from.clone());
+        assert_eq!(resolved, to);
+        // only the redirect is left behind
+        assert!(!from.join("global_settings.json").exists());
+        assert_eq!(std::fs::read_dir(&from).unwrap().count(), 1);
+        assert_eq!(resolve_lodestone_path(to.clone()).0, to);
+    }
+}
@@ -1,12 +1,19 @@
 use crate::{
-    error::Error, output_types::ClientEvent,
-    prelude::LODESTONE_EPOCH_MIL, events::EventQuery,
+    auth::user_id::UserId,
+    error::Error,
+    events::{EventLevel, EventQuery, EventType},
+    output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
+    types::{InstanceUuid, Snowflake, TimeRange},
 };
 
 use color_eyre::eyre::Context;
 use sqlx::sqlite::SqlitePool;
 use tracing::error;
 
+/// Matches the rows of console output, see `Event::is_event_console_message`
+pub(super) const CONSOLE_EVENT_CONDITION: &str = "IFNULL(json_extract(event_value, '$.event_inner.instance_event_inner.type'), '') IN ('InstanceOutput', 'SystemMessage', 'PlayerMessage')";
+
 // TODO clean up all unwraps
 
 pub async fn search_events(
@@ -69,16 +76,329 @@ FROM ClientEvents"#
     Ok(filtered)
 }
 
+/// Snowflake range covering a time range in milliseconds, inclusive on both ends
+fn time_range_to_snowflake_range(time_range: Option<&TimeRange>) -> (i64, i64) {
+    match time_range {
+        Some(time_range) => (
+            (time_range.start - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22,
+            (time_range.end + 1 - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22,
+        ),
+        None => (i64::MIN, i64::MAX),
+    }
+}
+
+fn parse_client_events(rows: Vec<String>) -> Vec<ClientEvent> {
+    let mut parsed_client_events: Vec<ClientEvent> = Vec::new();
+    // rows are fetched newest first so that the limit applies to the most recent events
+    for row in rows.into_iter().rev() {
+        if let Ok(client_event) = serde_json::from_str(&row) {
+            parsed_client_events.push(client_event);
+        } else {
+            error!("Failed to parse client event: {}", row);
+        }
+    }
+    parsed_client_events
+}
+
+/// Read back the console output of an instance, or of every instance if `instance_id` is `None`
+///
+/// Returns at most `limit` of the most recent events in the time range, oldest first
+pub async fn search_console_events(
+    pool: &SqlitePool,
+    instance_id: Option<&InstanceUuid>,
+    time_range: Option<&TimeRange>,
+    limit: u32,
+) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let (start, end) = time_range_to_snowflake_range(time_range);
+    let rows: Vec<String> = sqlx::query_scalar(&format!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE (?1 IS NULL OR instance_id = ?1) AND snowflake >= ?2 AND snowflake <= ?3 AND {CONSOLE_EVENT_CONDITION}
+ORDER BY snowflake DESC
+LIMIT ?4"#
+    ))
+    .bind(instance_id.map(|uuid| uuid.as_ref().to_owned()))
+    .bind(start)
+    .bind(end)
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch console events")?;
+    Ok(parse_client_events(rows))
+}
+
+/// A page of console output counting back from `anchor`, oldest first
+///
+/// Events newer than `anchor` are ignored so that `offset` keeps pointing at the same lines while
+/// new output is written. Fetches one extra row to tell whether there is anything older than the page.
+pub async fn read_console_page(
+    pool: &SqlitePool,
+    instance_id: Option<&InstanceUuid>,
+    anchor: Option<Snowflake>,
+    offset: u32,
+    limit: u32,
+) -> Result<(Vec<ClientEvent>, bool), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let mut rows: Vec<String> = sqlx::query_scalar(&format!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE (?1 IS NULL OR instance_id = ?1) AND (?2 IS NULL OR snowflake <= ?2) AND {CONSOLE_EVENT_CONDITION}
+ORDER BY snowflake DESC
+LIMIT ?3 OFFSET ?4"#
+    ))
+    .bind(instance_id.map(|uuid| uuid.as_ref().to_owned()))
+    .bind(anchor)
+    .bind(limit + 1)
+    .bind(offset)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch console events")?;
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+    Ok((parse_client_events(rows), has_more))
+}
+
+/// Everything that happened to an instance except its console output, oldest first
+///
+/// Besides the instance's own events this includes any event mentioning the instance,
+/// e.g. backups, setup progress and forced kills. Progression updates are left out.
+pub async fn search_instance_timeline(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    time_range: Option<&TimeRange>,
+    limit: u32,
+) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let (start, end) = time_range_to_snowflake_range(time_range);
+    let rows: Vec<String> = sqlx::query_scalar(&format!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE (instance_id = ?1 OR instr(event_value, ?2) > 0) AND snowflake >= ?3 AND snowflake <= ?4
+AND NOT {CONSOLE_EVENT_CONDITION}
+AND IFNULL(json_extract(event_value, '$.event_inner.progression_event_inner.type'), '') != 'ProgressionUpdate'
+ORDER BY snowflake DESC
+LIMIT ?5"#
+    ))
+    .bind(instance_id.as_ref())
+    // the uuid as a json string, matching any field holding it
+    .bind(format!("\"{}\"", instance_id.as_ref()))
+    .bind(start)
+    .bind(end)
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch instance timeline")?;
+    Ok(parse_client_events(rows))
+}
+
+/// Security events in a time range, oldest first
+pub async fn search_security_events(
+    pool: &SqlitePool,
+    time_range: Option<&TimeRange>,
+    limit: u32,
+) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let (start, end) = time_range_to_snowflake_range(time_range);
+    let rows: Vec<String> = sqlx::query_scalar(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE json_extract(event_value, '$.event_inner.type') = 'SecurityEvent' AND snowflake >= ?1 AND snowflake <= ?2
+ORDER BY snowflake DESC
+LIMIT ?3"#,
+    )
+    .bind(start)
+    .bind(end)
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch security events")?;
+    Ok(parse_client_events(rows))
+}
+
+/// Audit events in a time range, optionally only those of a user or an instance, oldest first
+pub async fn search_audit_events(
+    pool: &SqlitePool,
+    user_id: Option<&UserId>,
+    instance_id: Option<&InstanceUuid>,
+    time_range: Option<&TimeRange>,
+    limit: u32,
+) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let (start, end) = time_range_to_snowflake_range(time_range);
+    let rows: Vec<String> = sqlx::query_scalar(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE json_extract(event_value, '$.event_inner.type') = 'AuditEvent'
+AND (?1 IS NULL OR json_extract(event_value, '$.event_inner.user_id') = ?1)
+AND (?2 IS NULL OR instance_id = ?2) AND snowflake >= ?3 AND snowflake <= ?4
+ORDER BY snowflake DESC
+LIMIT ?5"#,
+    )
+    .bind(user_id.map(|uid| AsRef::<str>::as_ref(uid).to_owned()))
+    .bind(instance_id.map(|uuid| uuid.as_ref().to_owned()))
+    .bind(start)
+    .bind(end)
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch audit events")?;
+    Ok(parse_client_events(rows))
+}
+
+/// Which events a page of history holds, see `read_events_page`
+#[derive(Debug, Clone, Default)]
+pub struct EventPageFilter {
+    pub instance_id: Option<InstanceUuid>,
+    pub event_type: Option<EventType>,
+    /// the least severe level included
+    pub min_level: Option<EventLevel>,
+    pub time_range: Option<TimeRange>,
+    /// only events older than this snowflake
+    pub before: Option<Snowflake>,
+    /// only events newer than this snowflake
+    pub after: Option<Snowflake>,
+}
+
+impl EventPageFilter {
+    /// The levels at least as severe as `min_level` as a json array, `None` for all of them
+    fn levels(&self) -> Option<String> {
+        let min_level = self.min_level?;
+        if min_level == EventLevel::Info {
+            return None;
+        }
+        let levels: Vec<EventLevel> = EventLevel::ALL
+            .into_iter()
+            .filter(|level| *level >= min_level)
+            .collect();
+        serde_json::to_string(&levels).ok()
+    }
+}
+
+/// A page of events matching `filter`, oldest first
+///
+/// With `after` set the page holds the oldest events after it, so a client catching up walks
+/// forward, otherwise it holds the newest events before `before`. Fetches one extra row to tell
+/// whether there are more events in that direction.
+pub async fn read_events_page(
+    pool: &SqlitePool,
+    filter: &EventPageFilter,
+    limit: u32,
+) -> Result<(Vec<ClientEvent>, bool), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let (start, end) = time_range_to_snowflake_range(filter.time_range.as_ref());
+    let forward = filter.after.is_some();
+    let event_type = filter
+        .event_type
+        .as_ref()
+        .and_then(|event_type| serde_json::to_value(event_type).ok())
+        .and_then(|event_type| event_type.as_str().map(str::to_owned));
+    let mut rows: Vec<String> = sqlx::query_scalar(&format!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE (?1 IS NULL OR instance_id = ?1)
+AND (?2 IS NULL OR json_extract(event_value, '$.event_inner.type') = ?2)
+AND (?3 IS NULL OR level IN (SELECT value FROM json_each(?3)))
+AND snowflake >= ?4 AND snowflake <= ?5
+AND (?6 IS NULL OR snowflake < ?6) AND (?7 IS NULL OR snowflake > ?7)
+ORDER BY snowflake {}
+LIMIT ?8"#,
+        if forward { "ASC" } else { "DESC" }
+    ))
+    .bind(
+        filter
+            .instance_id
+            .as_ref()
+            .map(|uuid| uuid.as_ref().to_owned()),
+    )
+    .bind(event_type)
+    .bind(filter.levels())
+    .bind(start)
+    .bind(end)
+    .bind(filter.before)
+    .bind(filter.after)
+    .bind(limit + 1)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch events")?;
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+    if forward {
+        // parse_client_events expects the newest first
+        rows.reverse();
+    }
+    Ok((parse_client_events(rows), has_more))
+}
+
+/// Read back the most recent events that are not console output, oldest first
+pub async fn read_recent_events(pool: &SqlitePool, limit: u32) -> Result<Vec<ClientEvent>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows: Vec<String> = sqlx::query_scalar(&format!(
+        r#"
+SELECT
+event_value
+FROM ClientEvents
+WHERE NOT {CONSOLE_EVENT_CONDITION}
+ORDER BY snowflake DESC
+LIMIT ?1"#
+    ))
+    .bind(limit)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch events")?;
+    Ok(parse_client_events(rows))
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
     use std::{path::PathBuf, str::FromStr};
 
-    use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite};
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+        Pool, Sqlite,
+    };
 
     use crate::{
-        db::write::init_client_events_table,
-        events::{CausedBy, EventInner, EventLevel, FSEvent, FSOperation, FSTarget},
+        db::write::{init_client_events_table, write_client_event},
+        events::{
+            AuditEvent, CausedBy, Event, EventInner, EventLevel, FSEvent, FSOperation, FSTarget,
+            InstanceEvent, InstanceEventInner, ProgressionStartValue,
+        },
+        traits::t_server::State,
         types::Snowflake,
     };
 
@@ -128,6 +448,385 @@ mod tests {
         // let row_1 = row_1_result.unwrap();
     }
 
+    fn dummy_instance_event(instance_uuid: &str, inner: InstanceEventInner) -> ClientEvent {
+        ClientEvent {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: InstanceUuid::from(instance_uuid.to_string()),
+                instance_name: "test".to_string(),
+                instance_event_inner: inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::new(),
+            level: EventLevel::Info,
+            caused_by: CausedBy::System,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_console_events() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        for i in 0..3 {
+            write_client_event(
+                &pool,
+                dummy_instance_event(
+                    "INSTANCE_A",
+                    InstanceEventInner::InstanceOutput {
+                        message: format!("line {i}"),
+                    },
+                ),
+            )
+            .await
+            .unwrap();
+        }
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_B",
+                InstanceEventInner::InstanceOutput {
+                    message: "other".to_string(),
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::StateTransition { to: State::Running },
+            ),
+        )
+        .await
+        .unwrap();
+
+        let instance_a = InstanceUuid::from("INSTANCE_A".to_string());
+        let console = search_console_events(&pool, Some(&instance_a), None, 2)
+            .await
+            .unwrap();
+        // the most recent lines, oldest first
+        assert_eq!(console.len(), 2);
+        assert!(matches!(
+            &console[0].event_inner,
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_event_inner: InstanceEventInner::InstanceOutput { message },
+                ..
+            }) if message == "line 1"
+        ));
+        let all_console = search_console_events(&pool, None, None, 100).await.unwrap();
+        assert_eq!(all_console.len(), 4);
+        let other_events = read_recent_events(&pool, 100).await.unwrap();
+        assert_eq!(other_events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_console_page() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let mut snowflakes = Vec::new();
+        for i in 0..5 {
+            let event = dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::InstanceOutput {
+                    message: format!("line {i}"),
+                },
+            );
+            snowflakes.push(event.snowflake);
+            write_client_event(&pool, event).await.unwrap();
+        }
+        let instance_a = InstanceUuid::from("INSTANCE_A".to_string());
+        let message = |event: &ClientEvent| match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_event_inner: InstanceEventInner::InstanceOutput { message },
+                ..
+            }) => message.clone(),
+            _ => panic!("not console output"),
+        };
+
+        let anchor = snowflakes[3];
+        let (page, has_more) = read_console_page(&pool, Some(&instance_a), Some(anchor), 0, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            page.iter().map(message).collect::<Vec<_>>(),
+            ["line 2", "line 3"]
+        );
+        assert!(has_more);
+
+        // new output does not shift pages counted from the anchor
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::InstanceOutput {
+                    message: "line 5".to_string(),
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        let (page, has_more) = read_console_page(&pool, Some(&instance_a), Some(anchor), 2, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            page.iter().map(message).collect::<Vec<_>>(),
+            ["line 0", "line 1"]
+        );
+        assert!(!has_more);
+
+        let (page, _) = read_console_page(&pool, Some(&instance_a), None, 0, 1)
+            .await
+            .unwrap();
+        assert_eq!(message(&page[0]), "line 5");
+    }
+
+    #[tokio::test]
+    async fn test_read_events_page() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let mut snowflakes = Vec::new();
+        for i in 0..4 {
+            let mut event = dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::InstanceOutput {
+                    message: format!("line {i}"),
+                },
+            );
+            if i == 3 {
+                event.level = EventLevel::Warning;
+            }
+            snowflakes.push(event.snowflake);
+            write_client_event(&pool, event).await.unwrap();
+        }
+        write_client_event(
+            &pool,
+            ClientEvent {
+                event_inner: EventInner::FSEvent(FSEvent {
+                    operation: FSOperation::Read,
+                    target: FSTarget::File(PathBuf::from("/test")),
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::new(),
+                level: EventLevel::Info,
+                caused_by: CausedBy::System,
+            },
+        )
+        .await
+        .unwrap();
+        let instance_a = EventPageFilter {
+            instance_id: Some(InstanceUuid::from("INSTANCE_A".to_string())),
+            ..Default::default()
+        };
+        let snowflakes_of =
+            |page: &[ClientEvent]| page.iter().map(|event| event.snowflake).collect::<Vec<_>>();
+
+        let (page, has_more) = read_events_page(&pool, &instance_a, 2).await.unwrap();
+        assert_eq!(snowflakes_of(&page), snowflakes[2..4]);
+        assert!(has_more);
+        let (page, has_more) = read_events_page(
+            &pool,
+            &EventPageFilter {
+                before: Some(snowflakes[2]),
+                ..instance_a.clone()
+            },
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(snowflakes_of(&page), snowflakes[0..2]);
+        assert!(!has_more);
+
+        // catching up walks forward from the last seen event
+        let (page, has_more) = read_events_page(
+            &pool,
+            &EventPageFilter {
+                after: Some(snowflakes[0]),
+                ..instance_a.clone()
+            },
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(snowflakes_of(&page), snowflakes[1..3]);
+        assert!(has_more);
+
+        let (page, _) = read_events_page(
+            &pool,
+            &EventPageFilter {
+                min_level: Some(EventLevel::Warning),
+                ..Default::default()
+            },
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(snowflakes_of(&page), snowflakes[3..4]);
+        let (page, _) = read_events_page(
+            &pool,
+            &EventPageFilter {
+                event_type: Some(EventType::FSEvent),
+                ..Default::default()
+            },
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(page.len(), 1);
+        let (page, _) = read_events_page(&pool, &EventPageFilter::default(), 10)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_search_instance_timeline() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let instance_a = InstanceUuid::from("INSTANCE_A".to_string());
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::InstanceOutput {
+                    message: "INSTANCE_A".to_string(),
+                },
+            ),
+        )
+        .await
+        .unwrap();
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::StateTransition { to: State::Running },
+            ),
+        )
+        .await
+        .unwrap();
+        let (backup_start, event_id) = Event::new_progression_event_start(
+            "Backing up",
+            None,
+            Some(ProgressionStartValue::InstanceBackup {
+                instance_uuid: instance_a.clone(),
+            }),
+            CausedBy::System,
+        );
+        write_client_event(&pool, backup_start.into())
+            .await
+            .unwrap();
+        write_client_event(
+            &pool,
+            Event::new_progression_event_update(&event_id, "Compressing", 1.0).into(),
+        )
+        .await
+        .unwrap();
+        write_client_event(
+            &pool,
+            dummy_instance_event(
+                "INSTANCE_B",
+                InstanceEventInner::StateTransition { to: State::Running },
+            ),
+        )
+        .await
+        .unwrap();
+
+        let timeline = search_instance_timeline(&pool, &instance_a, None, 100)
+            .await
+            .unwrap();
+        assert_eq!(timeline.len(), 2);
+        assert!(matches!(
+            timeline[0].event_inner,
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_event_inner: InstanceEventInner::StateTransition { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            timeline[1].event_inner,
+            EventInner::ProgressionEvent(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_search_audit_events() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let audit_event = |user_id: &str, instance_uuid: Option<&str>, path: &str| ClientEvent {
+            event_inner: EventInner::AuditEvent(AuditEvent {
+                user_id: Some(UserId::from(user_id.to_string())),
+                method: "DELETE".to_string(),
+                path: path.to_string(),
+                instance_uuid: instance_uuid.map(|uuid| InstanceUuid::from(uuid.to_string())),
+                status: 200,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::new(),
+            level: EventLevel::Info,
+            caused_by: CausedBy::System,
+        };
+        for event in [
+            audit_event("USER_A", Some("INSTANCE_A"), "/api/v1/instance/INSTANCE_A"),
+            audit_event(
+                "USER_B",
+                Some("INSTANCE_A"),
+                "/api/v1/instance/INSTANCE_A/start",
+            ),
+            audit_event("USER_A", None, "/api/v1/user/create"),
+            dummy_instance_event(
+                "INSTANCE_A",
+                InstanceEventInner::StateTransition { to: State::Running },
+            ),
+        ] {
+            write_client_event(&pool, event).await.unwrap();
+        }
+        let path = |event: &ClientEvent| match &event.event_inner {
+            EventInner::AuditEvent(audit_event) => audit_event.path.clone(),
+            _ => panic!("Not an audit event"),
+        };
+
+        let all = search_audit_events(&pool, None, None, None, 100)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(path(&all[0]), "/api/v1/instance/INSTANCE_A");
+
+        let user_a = UserId::from("USER_A".to_string());
+        let instance_a = InstanceUuid::from("INSTANCE_A".to_string());
+        assert_eq!(
+            search_audit_events(&pool, Some(&user_a), None, None, 100)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        let by_user_on_instance =
+            search_audit_events(&pool, Some(&user_a), Some(&instance_a), None, 100)
+                .await
+                .unwrap();
+        assert_eq!(by_user_on_instance.len(), 1);
+        assert_eq!(path(&by_user_on_instance[0]), "/api/v1/instance/INSTANCE_A");
+    }
+
     // TODO should properly implement tests, with dummy values
     // #[tokio::test]
     // async fn test_read() {
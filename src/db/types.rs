@@ -26,17 +26,18 @@ impl From<&ClientEvent> for ClientEventRow {
             None
         };
 
-        let instance_id = if let EventInner::InstanceEvent(i) = &client_event.event_inner {
-            Some(i.instance_uuid.to_owned())
-        } else {
-            None
+        let instance_id = match &client_event.event_inner {
+            EventInner::InstanceEvent(i) => Some(i.instance_uuid.to_owned()),
+            EventInner::AuditEvent(a) => a.instance_uuid.to_owned(),
+            EventInner::JobEvent(j) => j.job.instance_uuid.to_owned(),
+            _ => None,
         };
 
         ClientEventRow {
             event_value: serde_json::to_value(client_event).unwrap(),
             details: client_event.details.clone(),
             snowflake: client_event.snowflake,
-            level: client_event.level.clone(),
+            level: client_event.level,
             caused_by_user_id,
             instance_id,
         }
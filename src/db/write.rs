@@ -1,15 +1,25 @@
+use std::{sync::Arc, time::Duration};
+
 use crate::{
     error::Error,
-    events::{Event, EventInner, ProgressionEventInner},
+    events::{Event, EventInner, JobEvent, JobEventInner, ProgressionEventInner},
+    global_settings::GlobalSettings,
     output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
 };
 
 use color_eyre::eyre::Context;
 use sqlx::sqlite::SqlitePool;
-use tokio::sync::broadcast::{error::RecvError, Receiver};
-use tracing::{error, warn};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, info, warn};
+
+use super::{read::CONSOLE_EVENT_CONDITION, types::ClientEventRow};
 
-use super::types::ClientEventRow;
+/// how often events past their retention are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 // TODO clean up all unwraps
 
@@ -41,6 +51,13 @@ pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_
                 continue;
             }
         }
+        if let EventInner::JobEvent(JobEvent {
+            job_event_inner: JobEventInner::Progress,
+            ..
+        }) = &client_event.event_inner
+        {
+            continue;
+        }
         let insertion_result = write_client_event(&sqlite_pool, client_event).await;
         if let Err(e) = insertion_result.as_ref() {
             error!("Error inserting into database: {}", e);
@@ -49,7 +66,10 @@ pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_
     }
 }
 
-async fn write_client_event(pool: &SqlitePool, client_event: ClientEvent) -> Result<i64, Error> {
+pub(crate) async fn write_client_event(
+    pool: &SqlitePool,
+    client_event: ClientEvent,
+) -> Result<i64, Error> {
     let mut connection = pool
         .acquire()
         .await
@@ -100,9 +120,86 @@ pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
     .await
     .context("Failed to create table")?;
 
+    // console history is queried per instance and time range
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS ClientEventsInstanceSnowflake
+        ON ClientEvents (instance_id, snowflake);
+        "#,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
     Ok(())
 }
 
+/// The first snowflake of events younger than `days`
+fn retention_cutoff(now_millis: i64, days: u32) -> i64 {
+    (now_millis - days as i64 * 24 * 60 * 60 * 1000 - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22
+}
+
+/// Delete the events older than their retention, console output and the other events are kept
+/// for `console_retention_days` and `event_retention_days` respectively, forever if `None`.
+///
+/// Returns the number of events deleted
+pub async fn prune_events(
+    pool: &SqlitePool,
+    event_retention_days: Option<u32>,
+    console_retention_days: Option<u32>,
+) -> Result<u64, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    let now_millis = chrono::Utc::now().timestamp_millis();
+    let mut deleted = 0;
+    for (retention_days, condition) in [
+        (
+            event_retention_days,
+            format!("NOT {CONSOLE_EVENT_CONDITION}"),
+        ),
+        (console_retention_days, CONSOLE_EVENT_CONDITION.to_string()),
+    ] {
+        let retention_days = match retention_days {
+            Some(retention_days) => retention_days,
+            None => continue,
+        };
+        deleted += sqlx::query(&format!(
+            "DELETE FROM ClientEvents WHERE snowflake < ?1 AND {condition}"
+        ))
+        .bind(retention_cutoff(now_millis, retention_days))
+        .execute(&mut connection)
+        .await
+        .context("Failed to delete old events")?
+        .rows_affected();
+    }
+    Ok(deleted)
+}
+
+/// Periodically deletes the events past the retention set in the global settings
+pub async fn prune_events_task(
+    sqlite_pool: SqlitePool,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let buffer_settings = global_settings.lock().await.buffer_settings();
+        match prune_events(
+            &sqlite_pool,
+            buffer_settings.event_retention_days,
+            buffer_settings.console_retention_days,
+        )
+        .await
+        {
+            Ok(0) => {}
+            Ok(deleted) => info!("Deleted {deleted} events past their retention"),
+            Err(e) => error!("Failed to delete events past their retention : {e}"),
+        }
+    }
+}
+
 #[cfg(test)]
 #[allow(unused_imports)]
 
@@ -166,4 +263,47 @@ mod tests {
         assert_eq!(row.caused_by_user_id, None);
         assert_eq!(row.instance_id, None);
     }
+
+    #[tokio::test]
+    async fn test_prune_events() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let fs_event = ClientEvent {
+            event_inner: EventInner::FSEvent(FSEvent {
+                operation: FSOperation::Read,
+                target: FSTarget::File(PathBuf::from("/test")),
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::new(),
+            level: EventLevel::Info,
+            caused_by: CausedBy::System,
+        };
+        let console_event = ClientEvent {
+            event_inner: EventInner::InstanceEvent(crate::events::InstanceEvent {
+                instance_uuid: "INSTANCE_A".to_string().into(),
+                instance_name: "test".to_string(),
+                instance_event_inner: crate::events::InstanceEventInner::InstanceOutput {
+                    message: "Done".to_string(),
+                },
+            }),
+            ..fs_event.clone()
+        };
+        write_client_event(&pool, fs_event).await.unwrap();
+        write_client_event(&pool, console_event).await.unwrap();
+        // both events are 10 days old
+        sqlx::query("UPDATE ClientEvents SET snowflake = ?1")
+            .bind(retention_cutoff(chrono::Utc::now().timestamp_millis(), 10))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(prune_events(&pool, None, None).await.unwrap(), 0);
+        assert_eq!(prune_events(&pool, Some(30), Some(30)).await.unwrap(), 0);
+        assert_eq!(prune_events(&pool, Some(5), None).await.unwrap(), 1);
+        assert_eq!(prune_events(&pool, Some(5), Some(5)).await.unwrap(), 1);
+    }
 }
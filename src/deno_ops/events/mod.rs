@@ -5,10 +5,16 @@ use deno_core::{
     op, OpState,
 };
 
-use crate::{event_broadcaster::EventBroadcaster, events::Event, types::InstanceUuid};
+use crate::{
+    event_broadcaster::EventBroadcaster,
+    events::Event,
+    macro_sandbox::{require_capability, MacroCapability, MacroGrant},
+    types::InstanceUuid,
+};
 
 #[op]
 async fn next_event(state: Rc<RefCell<OpState>>) -> Result<Event, anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::ReadEvents)?;
     let rx = state.borrow().borrow::<EventBroadcaster>().clone();
     let event = rx
         .subscribe()
@@ -19,9 +25,11 @@ async fn next_event(state: Rc<RefCell<OpState>>) -> Result<Event, anyhow::Error>
 }
 
 #[op]
-fn broadcast_event(state: Rc<RefCell<OpState>>, event: Event) {
+fn broadcast_event(state: Rc<RefCell<OpState>>, event: Event) -> Result<(), anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::HostAccess)?;
     let tx = state.borrow().borrow::<EventBroadcaster>().clone();
     tx.send(event);
+    Ok(())
 }
 
 #[op]
@@ -31,6 +39,7 @@ fn emit_console_out(
     instance_name: String,
     instance_uuid: InstanceUuid,
 ) -> Result<(), anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::HostAccess)?;
     let tx = state.borrow().borrow::<EventBroadcaster>().clone();
     tx.send(Event::new_instance_output(
         instance_uuid,
@@ -43,6 +52,7 @@ fn emit_console_out(
 pub fn register_all_event_ops(
     worker_options: &mut deno_runtime::worker::WorkerOptions,
     event_broadcaster: EventBroadcaster,
+    grant: Option<MacroGrant>,
 ) {
     worker_options.extensions.push(
         deno_core::Extension::builder("event_ops")
@@ -53,6 +63,9 @@ pub fn register_all_event_ops(
             ])
             .state(|state| {
                 state.put(event_broadcaster);
+                if let Some(grant) = grant {
+                    state.put(grant);
+                }
             })
             .force_op_registration()
             .build(),
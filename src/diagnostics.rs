@@ -0,0 +1,364 @@
+//! Diagnostic bundles, a zip of what is needed to look into an issue report: the version and
+//! host of the core, its recent logs, and for each instance its config with secrets redacted,
+//! its recent logs, crash reports and monitor history.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Context;
+use serde_json::Value;
+
+use crate::{
+    error::Error,
+    instance_export::{path_to_exports, remove_stale_exports},
+    server_logs,
+    traits::t_server::MonitorReport,
+};
+
+/// shown in place of a secret
+const REDACTED: &str = "<redacted>";
+/// a config key holding any of these holds a secret
+const SECRET_KEY_PARTS: [&str; 7] = [
+    "password",
+    "passphrase",
+    "secret",
+    "token",
+    "api_key",
+    "license_key",
+    "webhook",
+];
+/// lines of each log in the bundle, enough for a crash without shipping days of chat
+const LOG_TAIL_LINES: usize = 5000;
+/// the core rotates its log hourly, the last few hours are kept
+const CORE_LOG_FILES: usize = 3;
+const CRASH_REPORT_FILES: usize = 5;
+/// config files of an instance, relative to it, copied into the bundle when present
+const JSON_CONFIG_FILES: [&str; 1] = [".lodestone_config"];
+const PROPERTIES_CONFIG_FILES: [&str; 1] = ["server.properties"];
+
+pub struct BundledInstance {
+    /// the directory of the instance in the bundle
+    pub dir_name: String,
+    pub path: PathBuf,
+    /// what the instance reports of itself, e.g. its game, version and state
+    pub info: Value,
+    pub monitor_history: Vec<MonitorReport>,
+}
+
+pub struct BundleContents {
+    /// the version and host of the core
+    pub core: Value,
+    pub global_settings: Value,
+    /// the core's own log directory, left out of bundles of a single instance
+    pub core_log_dir: Option<PathBuf>,
+    pub instances: Vec<BundledInstance>,
+}
+
+pub(crate) fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase().replace(['-', '.'], "_");
+    SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+}
+
+/// Replace the values of secret keys anywhere in `value`
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret_key(key) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Replace the values of secret keys in a `key=value` properties file, empty ones are kept as
+/// they show the secret isn't set
+pub fn redact_properties(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| match line.split_once('=') {
+            Some((key, value))
+                if !line.trim_start().starts_with('#')
+                    && is_secret_key(key.trim())
+                    && !value.trim().is_empty() =>
+            {
+                format!("{key}={REDACTED}")
+            }
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The files directly inside `dir` accepted by `filter`, the most recently modified first
+fn recent_files(dir: &Path, filter: impl Fn(&str) -> bool, count: usize) -> Vec<PathBuf> {
+    let mut files: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| filter(&entry.file_name().to_string_lossy()))
+            .filter_map(|entry| {
+                let metadata = entry
+                    .metadata()
+                    .ok()
+                    .filter(|metadata| metadata.is_file())?;
+                Some((metadata.modified().ok(), entry.path()))
+            })
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    files
+        .into_iter()
+        .take(count)
+        .map(|(_, path)| path)
+        .collect()
+}
+
+struct BundleWriter {
+    zip: zip::ZipWriter<File>,
+    options: zip::write::FileOptions,
+}
+
+impl BundleWriter {
+    fn add(&mut self, name: &str, content: &[u8]) -> Result<(), Error> {
+        self.zip
+            .start_file(name, self.options)
+            .context("Failed to write diagnostic bundle")?;
+        self.zip
+            .write_all(content)
+            .context(format!("Failed to add {name} to the diagnostic bundle"))?;
+        Ok(())
+    }
+
+    fn add_json(&mut self, name: &str, value: &impl serde::Serialize) -> Result<(), Error> {
+        let content =
+            serde_json::to_vec_pretty(value).context(format!("Failed to serialize {name}"))?;
+        self.add(name, &content)
+    }
+
+    /// The tail of a log, a log that can't be read is noted in its place rather than failing
+    /// the bundle
+    fn add_log_tail(&mut self, name: &str, path: &Path) -> Result<(), Error> {
+        let content = match server_logs::tail_log_file(path, LOG_TAIL_LINES) {
+            Ok(lines) => lines.join("\n"),
+            Err(e) => format!("Failed to read {} : {e}", path.display()),
+        };
+        self.add(name, content.as_bytes())
+    }
+
+    fn add_instance(&mut self, instance: &BundledInstance) -> Result<(), Error> {
+        let dir = format!("instances/{}", instance.dir_name);
+        self.add_json(&format!("{dir}/info.json"), &instance.info)?;
+        self.add_json(&format!("{dir}/monitor.json"), &instance.monitor_history)?;
+        for config_file in JSON_CONFIG_FILES {
+            let content = match std::fs::read_to_string(instance.path.join(config_file)) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            match serde_json::from_str::<Value>(&content) {
+                Ok(mut config) => {
+                    redact_json(&mut config);
+                    self.add_json(&format!("{dir}/{config_file}"), &config)?;
+                }
+                // not knowing what's in it, it's left out
+                Err(e) => self.add(
+                    &format!("{dir}/{config_file}.error"),
+                    format!("Failed to parse {config_file} : {e}").as_bytes(),
+                )?,
+            }
+        }
+        for config_file in PROPERTIES_CONFIG_FILES {
+            if let Ok(content) = std::fs::read_to_string(instance.path.join(config_file)) {
+                self.add(
+                    &format!("{dir}/{config_file}"),
+                    redact_properties(&content).as_bytes(),
+                )?;
+            }
+        }
+        let logs_dir = instance.path.join(server_logs::LOGS_DIR_NAME);
+        let latest_log = logs_dir.join(server_logs::LATEST_LOG_FILE_NAME);
+        if latest_log.is_file() {
+            self.add_log_tail(
+                &format!("{dir}/logs/{}", server_logs::LATEST_LOG_FILE_NAME),
+                &latest_log,
+            )?;
+        }
+        let crash_reports = recent_files(
+            &instance.path.join("crash-reports"),
+            |name| name.ends_with(".txt"),
+            CRASH_REPORT_FILES,
+        )
+        .into_iter()
+        // the JVM writes its own crash logs next to the server
+        .chain(recent_files(
+            &instance.path,
+            |name| name.starts_with("hs_err_pid") && name.ends_with(".log"),
+            CRASH_REPORT_FILES,
+        ));
+        for path in crash_reports {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            self.add_log_tail(&format!("{dir}/crash-reports/{file_name}"), &path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the bundle into the exports directory, returns its path
+pub fn write_bundle(contents: &BundleContents, name: &str) -> Result<PathBuf, Error> {
+    remove_stale_exports();
+    std::fs::create_dir_all(path_to_exports()).context("Failed to create the export directory")?;
+    let dest = path_to_exports().join(format!(
+        "{}-diagnostics-{}.zip",
+        sanitize_filename::sanitize(name),
+        chrono::Utc::now().format("%Y-%m-%d-%H%M%S"),
+    ));
+    if let Err(e) = write_bundle_to(contents, &dest) {
+        let _ = std::fs::remove_file(&dest);
+        return Err(e);
+    }
+    Ok(dest)
+}
+
+fn write_bundle_to(contents: &BundleContents, dest: &Path) -> Result<(), Error> {
+    let file = File::create(dest).context(format!(
+        "Failed to create diagnostic bundle {}",
+        dest.display()
+    ))?;
+    let mut writer = BundleWriter {
+        zip: zip::ZipWriter::new(file),
+        options: zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated),
+    };
+    writer.add_json("core.json", &contents.core)?;
+    let mut global_settings = contents.global_settings.clone();
+    redact_json(&mut global_settings);
+    writer.add_json("global_settings.json", &global_settings)?;
+    if let Some(core_log_dir) = &contents.core_log_dir {
+        for path in recent_files(
+            core_log_dir,
+            |name| name.starts_with("lodestone_core.log"),
+            CORE_LOG_FILES,
+        ) {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            writer.add_log_tail(&format!("core_logs/{file_name}"), &path)?;
+        }
+    }
+    for instance in &contents.instances {
+        writer.add_instance(instance)?;
+    }
+    writer
+        .zip
+        .finish()
+        .context("Failed to finish diagnostic bundle")?
+        .flush()
+        .context("Failed to finish diagnostic bundle")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use serde_json::json;
+
+    use super::{redact_json, redact_properties, write_bundle_to, BundleContents, BundledInstance};
+
+    #[test]
+    fn test_redact() {
+        let mut config = json!({
+            "name": "survival",
+            "rcon_password": "hunter2",
+            "webhooks": [{ "url": "https://example.com" }],
+            "nested": { "API-Key": "abc", "api_token": null },
+        });
+        redact_json(&mut config);
+        assert_eq!(
+            config,
+            json!({
+                "name": "survival",
+                "rcon_password": "<redacted>",
+                "webhooks": "<redacted>",
+                "nested": { "API-Key": "<redacted>", "api_token": null },
+            })
+        );
+        assert_eq!(
+            redact_properties(
+                "#rcon.password=old\nrcon.password=hunter2\nmanagement-server-secret=\nmotd=hi"
+            ),
+            "#rcon.password=old\nrcon.password=<redacted>\nmanagement-server-secret=\nmotd=hi"
+        );
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let temp_dir = tempdir::TempDir::new("test_diagnostics").unwrap();
+        let instance_path = temp_dir.path().join("instance");
+        std::fs::create_dir_all(instance_path.join("logs")).unwrap();
+        std::fs::create_dir_all(instance_path.join("crash-reports")).unwrap();
+        std::fs::write(
+            instance_path.join("server.properties"),
+            "rcon.password=hunter2\nmotd=hi",
+        )
+        .unwrap();
+        std::fs::write(instance_path.join("logs/latest.log"), "started\n").unwrap();
+        std::fs::write(
+            instance_path.join("crash-reports/crash-2023-01-01_00.00.00-server.txt"),
+            "---- Minecraft Crash Report ----\n",
+        )
+        .unwrap();
+        let dest = temp_dir.path().join("bundle.zip");
+        write_bundle_to(
+            &BundleContents {
+                core: json!({ "version": "0.4.4" }),
+                global_settings: json!({ "core_name": "test", "secret": "s" }),
+                core_log_dir: None,
+                instances: vec![BundledInstance {
+                    dir_name: "survival".to_string(),
+                    path: instance_path,
+                    info: json!({ "name": "survival" }),
+                    monitor_history: Vec::new(),
+                }],
+            },
+            &dest,
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&dest).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "core.json",
+                "global_settings.json",
+                "instances/survival/crash-reports/crash-2023-01-01_00.00.00-server.txt",
+                "instances/survival/info.json",
+                "instances/survival/logs/latest.log",
+                "instances/survival/monitor.json",
+                "instances/survival/server.properties",
+            ]
+        );
+        let mut properties = String::new();
+        archive
+            .by_name("instances/survival/server.properties")
+            .unwrap()
+            .read_to_string(&mut properties)
+            .unwrap();
+        assert_eq!(properties, "rcon.password=<redacted>\nmotd=hi");
+        let mut global_settings = String::new();
+        archive
+            .by_name("global_settings.json")
+            .unwrap()
+            .read_to_string(&mut global_settings)
+            .unwrap();
+        assert!(!global_settings.contains("\"s\""));
+    }
+}
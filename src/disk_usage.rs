@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use dashmap::DashMap;
+use serde::Serialize;
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_map::InstanceMap,
+    traits::t_configurable::{LimitedResource, ResourceLimits, TConfigurable},
+    types::{InstanceUuid, Snowflake},
+};
+
+/// how often quotas are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// how old the size of a directory can get before it is walked again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct MeasuredSize {
+    bytes: u64,
+    /// unix timestamp in seconds of the last walk
+    measured_at: i64,
+    walked: Instant,
+}
+
+/// The disk usage of an instance directory and its quota
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstanceDiskUsage {
+    /// in bytes, `None` until the directory was first measured
+    pub directory_size: Option<u64>,
+    /// unix timestamp in seconds of the last full measurement
+    pub measured_at: Option<i64>,
+    /// in megabytes
+    pub disk_quota: Option<u64>,
+}
+
+/// Whether `size` bytes plus `incoming` more bytes are over a quota of `quota` megabytes
+fn exceeds_quota(size: u64, incoming: u64, quota: Option<u64>) -> bool {
+    quota.is_some_and(|quota| size.saturating_add(incoming) > quota.saturating_mul(MEGABYTE))
+}
+
+/// Size of the files under `path` in bytes, symlinks are not followed
+pub(crate) fn directory_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The sizes of the instance directories.
+///
+/// Walking a directory is slow for big worlds, so each is only walked every `REFRESH_INTERVAL`,
+/// and the files written through the core in between are added to the last measurement so quotas
+/// see them right away.
+#[derive(Clone, Default)]
+pub struct DirectorySizes {
+    sizes: Arc<DashMap<InstanceUuid, MeasuredSize>>,
+}
+
+impl DirectorySizes {
+    pub fn get(&self, uuid: &InstanceUuid) -> Option<u64> {
+        self.sizes.get(uuid).map(|size| size.bytes)
+    }
+
+    pub fn usage(&self, uuid: &InstanceUuid, limits: &ResourceLimits) -> InstanceDiskUsage {
+        let size = self.sizes.get(uuid).map(|size| *size);
+        InstanceDiskUsage {
+            directory_size: size.map(|size| size.bytes),
+            measured_at: size.map(|size| size.measured_at),
+            disk_quota: limits.disk_quota,
+        }
+    }
+
+    /// Account `bytes` written to the directory of an instance since it was last measured
+    pub fn add(&self, uuid: &InstanceUuid, bytes: u64) {
+        if let Some(mut size) = self.sizes.get_mut(uuid) {
+            size.bytes = size.bytes.saturating_add(bytes);
+        }
+    }
+
+    pub fn remove(&self, uuid: &InstanceUuid) {
+        self.sizes.remove(uuid);
+    }
+
+    fn is_stale(&self, uuid: &InstanceUuid) -> bool {
+        self.sizes
+            .get(uuid)
+            .map_or(true, |size| size.walked.elapsed() >= REFRESH_INTERVAL)
+    }
+
+    /// Walk the directory of an instance, on a blocking thread
+    pub async fn refresh(&self, uuid: &InstanceUuid, path: PathBuf) -> Result<u64, Error> {
+        let bytes = tokio::task::spawn_blocking(move || directory_size(&path))
+            .await
+            .context("Failed to measure instance directory")?;
+        self.sizes.insert(
+            uuid.clone(),
+            MeasuredSize {
+                bytes,
+                measured_at: chrono::Utc::now().timestamp(),
+                walked: Instant::now(),
+            },
+        );
+        Ok(bytes)
+    }
+
+    /// Fail if writing `incoming` more bytes would take an instance over its quota.
+    ///
+    /// Instances that were not measured yet are let through, the quota task catches them up.
+    pub fn check_quota(
+        &self,
+        uuid: &InstanceUuid,
+        limits: &ResourceLimits,
+        incoming: u64,
+    ) -> Result<(), Error> {
+        match self.get(uuid) {
+            Some(size) if exceeds_quota(size, incoming, limits.disk_quota) => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Instance is over its disk quota of {} MB",
+                    limits.disk_quota.unwrap_or_default()
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Keeps the directory sizes fresh and emits an event when an instance goes over its disk quota
+pub async fn disk_usage_task(
+    instances: InstanceMap,
+    directory_sizes: DirectorySizes,
+    event_broadcaster: EventBroadcaster,
+) {
+    let mut over_quota: HashSet<InstanceUuid> = HashSet::new();
+    loop {
+        let snapshot = instances.snapshot();
+        let uuids: HashSet<InstanceUuid> = snapshot.iter().map(|(uuid, _)| uuid.clone()).collect();
+        directory_sizes.sizes.retain(|uuid, _| uuids.contains(uuid));
+        over_quota.retain(|uuid| uuids.contains(uuid));
+        for (uuid, instance) in snapshot {
+            if directory_sizes.is_stale(&uuid) {
+                if let Err(e) = directory_sizes.refresh(&uuid, instance.path().await).await {
+                    error!("Failed to measure the directory of instance {uuid} : {e}");
+                    continue;
+                }
+            }
+            let limits = instance.resource_limits().await;
+            let size = directory_sizes.get(&uuid).unwrap_or_default();
+            if !exceeds_quota(size, 0, limits.disk_quota) {
+                over_quota.remove(&uuid);
+                continue;
+            }
+            if !over_quota.insert(uuid.clone()) {
+                continue;
+            }
+            let name = instance.name().await;
+            let usage = (size / MEGABYTE) as f64;
+            let limit = limits.disk_quota.unwrap_or_default() as f64;
+            let details = format!(
+                "Instance {name} is using {usage:.0} MB of disk, over its quota of {limit:.0} MB"
+            );
+            warn!("{details}");
+            event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: uuid,
+                    instance_name: name,
+                    instance_event_inner: InstanceEventInner::ResourceLimitExceeded {
+                        resource: LimitedResource::Disk,
+                        usage,
+                        limit,
+                        killed: false,
+                    },
+                }),
+                details,
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{directory_size, exceeds_quota, DirectorySizes, MEGABYTE};
+    use crate::traits::t_configurable::ResourceLimits;
+
+    #[test]
+    fn test_exceeds_quota() {
+        assert!(!exceeds_quota(10 * MEGABYTE, 0, None));
+        assert!(!exceeds_quota(10 * MEGABYTE, 0, Some(10)));
+        assert!(exceeds_quota(10 * MEGABYTE, 1, Some(10)));
+        assert!(exceeds_quota(0, 11 * MEGABYTE, Some(10)));
+    }
+
+    #[tokio::test]
+    async fn test_directory_sizes() {
+        let temp_dir = tempdir::TempDir::new("test_directory_sizes").unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("world")).unwrap();
+        std::fs::write(root.join("server.properties"), [0; 100]).unwrap();
+        std::fs::write(root.join("world/level.dat"), [0; 400]).unwrap();
+        assert_eq!(directory_size(root), 500);
+
+        let uuid = "INSTANCE_a".to_string().into();
+        let limits = ResourceLimits {
+            disk_quota: Some(1),
+            ..Default::default()
+        };
+        let directory_sizes = DirectorySizes::default();
+        // not measured yet
+        directory_sizes.add(&uuid, MEGABYTE);
+        assert!(directory_sizes.get(&uuid).is_none());
+        assert!(directory_sizes
+            .check_quota(&uuid, &limits, 2 * MEGABYTE)
+            .is_ok());
+
+        assert_eq!(
+            directory_sizes
+                .refresh(&uuid, root.to_path_buf())
+                .await
+                .unwrap(),
+            500
+        );
+        assert!(directory_sizes.check_quota(&uuid, &limits, 1000).is_ok());
+        directory_sizes.add(&uuid, MEGABYTE);
+        assert_eq!(directory_sizes.get(&uuid), Some(MEGABYTE + 500));
+        assert!(directory_sizes.check_quota(&uuid, &limits, 0).is_err());
+        assert!(directory_sizes
+            .check_quota(&uuid, &ResourceLimits::default(), 0)
+            .is_ok());
+    }
+}
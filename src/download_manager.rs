@@ -0,0 +1,595 @@
+//! Every file the core fetches goes through the download manager, which queues the downloads, lets
+//! them be paused and cancelled, reports their progress as events and verifies their checksums.
+//!
+//! A paused download drops its connection, it is resumed with a range request, or from the start
+//! if the server doesn't support them.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use futures::StreamExt;
+use once_cell::sync::OnceCell;
+use reqwest::{header::RANGE, Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::{watch, Semaphore},
+};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, ProgressionEventID},
+    prelude::path_to_tmp,
+    types::Snowflake,
+    util::DownloadProgress,
+};
+
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+/// Finished downloads are kept to be listed, the oldest are forgotten past this
+const MAX_FINISHED_DOWNLOADS: usize = 50;
+
+static DOWNLOAD_MANAGER: OnceCell<DownloadManager> = OnceCell::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(rename_all = "snake_case", tag = "algorithm", content = "hash")]
+pub enum Checksum {
+    Sha1(String),
+    Sha256(String),
+    Sha512(String),
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn new(checksum: &Checksum) -> Self {
+        match checksum {
+            Checksum::Sha1(_) => Hasher::Sha1(Sha1::new()),
+            Checksum::Sha256(_) => Hasher::Sha256(Sha256::new()),
+            Checksum::Sha512(_) => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+        }
+    }
+}
+
+impl Checksum {
+    fn expected(&self) -> String {
+        match self {
+            Checksum::Sha1(hash) | Checksum::Sha256(hash) | Checksum::Sha512(hash) => {
+                hash.to_lowercase()
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            DownloadState::Completed | DownloadState::Failed | DownloadState::Cancelled
+        )
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct DownloadInfo {
+    pub id: Snowflake,
+    pub url: String,
+    /// known once the server responded, unless it was given
+    pub file_name: Option<String>,
+    pub destination: PathBuf,
+    /// in bytes, unknown if the server doesn't say
+    pub total: Option<u64>,
+    pub downloaded: u64,
+    pub state: DownloadState,
+    /// why the download failed
+    pub error: Option<String>,
+    pub queued_at: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadRequest {
+    pub url: String,
+    /// the directory to download into
+    pub path: PathBuf,
+    /// the name of the file, taken from the response otherwise
+    pub name_override: Option<String>,
+    pub overwrite_old: bool,
+    pub checksum: Option<Checksum>,
+}
+
+impl DownloadRequest {
+    pub fn new(url: impl AsRef<str>, path: impl AsRef<Path>) -> Self {
+        Self {
+            url: url.as_ref().to_string(),
+            path: path.as_ref().to_owned(),
+            name_override: None,
+            overwrite_old: true,
+            checksum: None,
+        }
+    }
+
+    pub fn name(mut self, name: impl AsRef<str>) -> Self {
+        self.name_override = Some(name.as_ref().to_string());
+        self
+    }
+
+    pub fn overwrite_old(mut self, overwrite_old: bool) -> Self {
+        self.overwrite_old = overwrite_old;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Option<Checksum>) -> Self {
+        self.checksum = checksum;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+struct DownloadEntry {
+    info: DownloadInfo,
+    control: watch::Sender<Control>,
+}
+
+#[derive(Clone)]
+pub struct DownloadManager {
+    downloads: Arc<Mutex<BTreeMap<Snowflake, DownloadEntry>>>,
+    permits: Arc<Semaphore>,
+    event_broadcaster: Option<EventBroadcaster>,
+}
+
+impl DownloadManager {
+    fn new(event_broadcaster: Option<EventBroadcaster>) -> Self {
+        Self {
+            downloads: Arc::new(Mutex::new(BTreeMap::new())),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_DOWNLOADS)),
+            event_broadcaster,
+        }
+    }
+
+    /// Report the progress of the downloads through `event_broadcaster`, must be called before the
+    /// first download to take effect
+    pub fn init(event_broadcaster: EventBroadcaster) {
+        if DOWNLOAD_MANAGER
+            .set(DownloadManager::new(Some(event_broadcaster)))
+            .is_err()
+        {
+            tracing::warn!("The download manager was used before it was initialized");
+        }
+    }
+
+    pub fn global() -> &'static DownloadManager {
+        DOWNLOAD_MANAGER.get_or_init(|| DownloadManager::new(None))
+    }
+
+    pub fn list(&self) -> Vec<DownloadInfo> {
+        self.downloads
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: &Snowflake) -> Option<DownloadInfo> {
+        self.downloads
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|entry| entry.info.clone())
+    }
+
+    fn control(&self, id: &Snowflake, control: Control) -> Result<DownloadInfo, Error> {
+        let downloads = self.downloads.lock().unwrap();
+        let entry = downloads.get(id).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Download not found"),
+        })?;
+        if entry.info.state.is_finished() {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("The download has already finished"),
+            });
+        }
+        entry.control.send_replace(control);
+        Ok(entry.info.clone())
+    }
+
+    pub fn pause(&self, id: &Snowflake) -> Result<DownloadInfo, Error> {
+        self.control(id, Control::Pause)
+    }
+
+    pub fn resume(&self, id: &Snowflake) -> Result<DownloadInfo, Error> {
+        self.control(id, Control::Run)
+    }
+
+    pub fn cancel(&self, id: &Snowflake) -> Result<DownloadInfo, Error> {
+        self.control(id, Control::Cancel)
+    }
+
+    fn update(&self, id: &Snowflake, f: impl FnOnce(&mut DownloadInfo)) {
+        if let Some(entry) = self.downloads.lock().unwrap().get_mut(id) {
+            f(&mut entry.info);
+        }
+    }
+
+    fn forget_old_downloads(&self) {
+        let mut downloads = self.downloads.lock().unwrap();
+        let finished: Vec<Snowflake> = downloads
+            .iter()
+            .filter(|(_, entry)| entry.info.state.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_DOWNLOADS))
+        {
+            downloads.remove(id);
+        }
+    }
+
+    fn send(&self, event: Event) {
+        if let Some(event_broadcaster) = &self.event_broadcaster {
+            event_broadcaster.send(event);
+        }
+    }
+
+    /// Queue a download and wait for it to finish, returns the path of the downloaded file
+    pub async fn download(
+        &self,
+        request: DownloadRequest,
+        on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+    ) -> Result<PathBuf, Error> {
+        let id = Snowflake::default();
+        let (control_tx, mut control) = watch::channel(Control::Run);
+        self.downloads.lock().unwrap().insert(
+            id,
+            DownloadEntry {
+                info: DownloadInfo {
+                    id,
+                    url: request.url.clone(),
+                    file_name: request.name_override.clone(),
+                    destination: request.path.clone(),
+                    total: None,
+                    downloaded: 0,
+                    state: DownloadState::Queued,
+                    error: None,
+                    queued_at: chrono::Utc::now().timestamp(),
+                },
+                control: control_tx,
+            },
+        );
+        let temp_file_path = path_to_tmp().join(format!("download-{}", id.to_string()));
+        let result = async {
+            let _permit = tokio::select! {
+                permit = self.permits.acquire() => permit.context("The download queue was closed")?,
+                _ = wait_for_cancel(&mut control) => return Err(cancelled()),
+            };
+            self.transfer(&id, &request, &temp_file_path, &mut control, on_download)
+                .await
+        }
+        .await;
+        let _ = tokio::fs::remove_file(&temp_file_path).await;
+        self.update(&id, |info| match &result {
+            Ok(_) => info.state = DownloadState::Completed,
+            Err(e) if *control.borrow() == Control::Cancel => {
+                info.state = DownloadState::Cancelled;
+                info.error = Some(e.to_string());
+            }
+            Err(e) => {
+                info.state = DownloadState::Failed;
+                info.error = Some(e.to_string());
+            }
+        });
+        self.forget_old_downloads();
+        result
+    }
+
+    async fn transfer(
+        &self,
+        id: &Snowflake,
+        request: &DownloadRequest,
+        temp_file_path: &Path,
+        control: &mut watch::Receiver<Control>,
+        on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+    ) -> Result<PathBuf, Error> {
+        tokio::fs::create_dir_all(path_to_tmp())
+            .await
+            .context("Failed to create tmp dir")?;
+        let mut temp_file = tokio::fs::File::create(temp_file_path)
+            .await
+            .context("Failed to create temporary file")?;
+        let client = Client::new();
+        let mut file_name = request.name_override.clone();
+        let mut progression: Option<ProgressionEventID> = None;
+        let mut hasher = request.checksum.as_ref().map(Hasher::new);
+        let mut downloaded: u64 = 0;
+        let mut reported: u64 = 0;
+
+        let result = async {
+            'connection: loop {
+                if *control.borrow() == Control::Pause {
+                    self.update(id, |info| {
+                        info.state = DownloadState::Paused;
+                        info.downloaded = downloaded;
+                    });
+                }
+                wait_while_paused(control).await?;
+                self.update(id, |info| info.state = DownloadState::Downloading);
+                let response = connect(&client, &request.url, downloaded).await?;
+                if downloaded > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+                    // the server ignored the range, start over
+                    temp_file
+                        .set_len(0)
+                        .await
+                        .context("Failed to truncate temporary file")?;
+                    temp_file
+                        .rewind()
+                        .await
+                        .context("Failed to truncate temporary file")?;
+                    hasher = request.checksum.as_ref().map(Hasher::new);
+                    downloaded = 0;
+                    reported = 0;
+                }
+                let total = response.content_length().map(|length| length + downloaded);
+                let name = match &file_name {
+                    Some(name) => name.clone(),
+                    None => {
+                        let name = file_name_of(&response);
+                        file_name = Some(name.clone());
+                        name
+                    }
+                };
+                if !request.overwrite_old && request.path.join(&name).exists() {
+                    return Err(eyre!(
+                        "File {} already exists",
+                        request.path.join(&name).display()
+                    )
+                    .into());
+                }
+                self.update(id, |info| {
+                    info.file_name = Some(name.clone());
+                    info.total = total;
+                });
+                if progression.is_none() {
+                    let (event, event_id) = Event::new_progression_event_start(
+                        format!("Downloading {name}"),
+                        total.map(|total| total as f64),
+                        None,
+                        CausedBy::System,
+                    );
+                    self.send(event);
+                    progression = Some(event_id);
+                }
+
+                let threshold = total.unwrap_or(500000) / 100;
+                let mut stream = response.bytes_stream();
+                loop {
+                    tokio::select! {
+                        item = stream.next() => {
+                            let chunk = match item {
+                                Some(chunk) => chunk.context("Failed to read response")?,
+                                None => break 'connection,
+                            };
+                            temp_file
+                                .write_all(&chunk)
+                                .await
+                                .context(format!("Failed to write to file {}", &name))?;
+                            if let Some(hasher) = hasher.as_mut() {
+                                hasher.update(&chunk);
+                            }
+                            downloaded += chunk.len() as u64;
+                            let step = downloaded - reported;
+                            if step > threshold {
+                                on_download(DownloadProgress {
+                                    total,
+                                    downloaded: reported,
+                                    step,
+                                    download_name: name.clone(),
+                                });
+                                if let Some(event_id) = &progression {
+                                    self.send(Event::new_progression_event_update(
+                                        event_id,
+                                        format!("Downloading {name}"),
+                                        step as f64,
+                                    ));
+                                }
+                                self.update(id, |info| info.downloaded = downloaded);
+                                reported = downloaded;
+                            }
+                        }
+                        _ = control.changed() => {
+                            let current = *control.borrow();
+                            match current {
+                                Control::Cancel => return Err(cancelled()),
+                                // the connection is dropped, a range request resumes it
+                                Control::Pause => continue 'connection,
+                                Control::Run => {}
+                            }
+                        }
+                    }
+                }
+            }
+            temp_file
+                .flush()
+                .await
+                .context("Failed to flush temporary file")?;
+            self.update(id, |info| info.downloaded = downloaded);
+
+            if let (Some(checksum), Some(hasher)) = (&request.checksum, hasher) {
+                let actual = hasher.finalize();
+                if actual != checksum.expected() {
+                    return Err(Error {
+                        kind: ErrorKind::Internal,
+                        source: eyre!(
+                            "Checksum mismatch for {}, expected {} but got {actual}",
+                            request.url,
+                            checksum.expected()
+                        ),
+                    });
+                }
+            }
+
+            let name = file_name.clone().unwrap_or_else(|| "unknown".to_string());
+            tokio::fs::create_dir_all(&request.path)
+                .await
+                .context(format!("Failed to create dir {}", &request.path.display()))?;
+            let destination = request.path.join(&name);
+            // the tmp dir can be on another drive than the destination
+            if tokio::fs::rename(temp_file_path, &destination)
+                .await
+                .is_err()
+            {
+                tokio::fs::copy(temp_file_path, &destination)
+                    .await
+                    .context(format!("Failed to move file {}", &name))?;
+            }
+            Ok(destination)
+        }
+        .await;
+
+        if let Some(event_id) = progression {
+            self.send(Event::new_progression_event_end(
+                event_id,
+                result.is_ok(),
+                result.as_ref().err().map(|e| e.to_string()),
+                None,
+            ));
+        }
+        result
+    }
+}
+
+fn cancelled() -> Error {
+    Error {
+        kind: ErrorKind::Conflict,
+        source: eyre!("The download was cancelled"),
+    }
+}
+
+async fn wait_for_cancel(control: &mut watch::Receiver<Control>) {
+    while *control.borrow() != Control::Cancel {
+        if control.changed().await.is_err() {
+            // the manager forgot the download, it can't be cancelled anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+async fn wait_while_paused(control: &mut watch::Receiver<Control>) -> Result<(), Error> {
+    loop {
+        let current = *control.borrow_and_update();
+        match current {
+            Control::Run => return Ok(()),
+            Control::Cancel => return Err(cancelled()),
+            Control::Pause => {
+                if control.changed().await.is_err() {
+                    return Err(cancelled());
+                }
+            }
+        }
+    }
+}
+
+async fn connect(client: &Client, url: &str, from: u64) -> Result<Response, Error> {
+    let mut request = client.get(url);
+    if from > 0 {
+        request = request.header(RANGE, format!("bytes={from}-"));
+    }
+    let response = request.send().await.context("Failed to send GET request")?;
+    response
+        .error_for_status_ref()
+        .context("Failed to download file")?;
+    Ok(response)
+}
+
+/// The name of the file in the `Content-Disposition` header of the response
+fn file_name_of(response: &Response) -> String {
+    response
+        .headers()
+        .get("Content-Disposition")
+        .and_then(|header| header.to_str().ok())
+        .and_then(parse_content_disposition)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn parse_content_disposition(header: &str) -> Option<String> {
+    header
+        .split(';')
+        .filter_map(|part| part.trim().strip_prefix("filename="))
+        .map(|name| name.replace('\"', ""))
+        .next()
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_content_disposition, Checksum, Hasher};
+
+    #[test]
+    fn test_parse_content_disposition() {
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"server.jar\""),
+            Some("server.jar".to_string())
+        );
+        assert_eq!(
+            parse_content_disposition("attachment;filename=mod.jar"),
+            Some("mod.jar".to_string())
+        );
+        assert_eq!(parse_content_disposition("inline"), None);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let checksum = Checksum::Sha1("A9993E364706816ABA3E25717850C26C9CD0D89D".to_string());
+        let mut hasher = Hasher::new(&checksum);
+        hasher.update(b"ab");
+        hasher.update(b"c");
+        assert_eq!(hasher.finalize(), checksum.expected());
+    }
+}
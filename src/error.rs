@@ -1,15 +1,18 @@
 use std::fmt::{Display, Formatter};
 
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::body::HttpBody;
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use color_eyre::Report;
-use serde::ser::SerializeStruct;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use thiserror::Error;
 use ts_rs::TS;
 
-#[derive(Debug, Clone, Deserialize, Serialize, TS)]
+/// The machine-readable code of an error, the variants are part of the api and must not be renamed
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, TS, JsonSchema)]
 #[ts(export)]
 pub enum ErrorKind {
     NotFound,
@@ -18,6 +21,44 @@ pub enum ErrorKind {
     PermissionDenied,
     Unauthorized,
     Internal,
+    /// the request clashes with the current state, e.g. a name that is taken
+    Conflict,
+    /// the client is rate limited or locked out, the response says for how long
+    TooManyRequests,
+    /// the instance can't do this in its current state, e.g. start while it is installing
+    InvalidStateTransition,
+}
+
+impl ErrorKind {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
+            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorKind::Conflict => StatusCode::CONFLICT,
+            ErrorKind::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::InvalidStateTransition => StatusCode::CONFLICT,
+        }
+    }
+
+    /// The kind of an error response not made by a handler, e.g. a rejected request body
+    fn from_status_code(status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND => ErrorKind::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+                ErrorKind::UnsupportedOperation
+            }
+            StatusCode::FORBIDDEN => ErrorKind::PermissionDenied,
+            StatusCode::UNAUTHORIZED => ErrorKind::Unauthorized,
+            StatusCode::CONFLICT => ErrorKind::Conflict,
+            StatusCode::TOO_MANY_REQUESTS => ErrorKind::TooManyRequests,
+            status if status.is_server_error() => ErrorKind::Internal,
+            _ => ErrorKind::BadRequest,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -36,6 +77,30 @@ impl Display for ErrorKind {
             ErrorKind::PermissionDenied => write!(f, "Permission Denied"),
             ErrorKind::Unauthorized => write!(f, "Unauthorized"),
             ErrorKind::Internal => write!(f, "Internal Error"),
+            ErrorKind::Conflict => write!(f, "Conflict"),
+            ErrorKind::TooManyRequests => write!(f, "Too Many Requests"),
+            ErrorKind::InvalidStateTransition => write!(f, "Invalid State Transition"),
+        }
+    }
+}
+
+/// The body of every error response of the api
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS, JsonSchema)]
+#[ts(export)]
+pub struct ErrorResponse {
+    pub code: ErrorKind,
+    pub message: String,
+    /// the causes of the error, outermost first
+    pub details: Vec<String>,
+}
+
+impl From<&Error> for ErrorResponse {
+    fn from(error: &Error) -> Self {
+        let mut chain = error.source.chain().map(|cause| cause.to_string());
+        ErrorResponse {
+            code: error.kind.clone(),
+            message: chain.next().unwrap_or_else(|| error.kind.to_string()),
+            details: chain.collect(),
         }
     }
 }
@@ -45,35 +110,60 @@ impl Serialize for Error {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Error", 2)?;
-        state.serialize_field("kind", &self.kind)?;
-        let vec: Vec<String> = self.source.chain().map(|cause| cause.to_string()).collect();
-        state.serialize_field("causes", &vec)?;
-        state.end()
+        ErrorResponse::from(self).serialize(serializer)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.kind.status_code(), Json(ErrorResponse::from(&self))).into_response()
     }
 }
 
-#[test]
-fn test_error_serialization() {
-    let error = Error {
-        kind: ErrorKind::NotFound,
-        source: Report::msg("Test"),
+/// Give the error responses made outside of handlers, e.g. when axum rejects a malformed body or
+/// no route matches, the same shape as the ones of handlers
+pub async fn structured_rejections<B>(request: Request<B>, next: Next<B>) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("application/json"));
+    if !status.is_client_error() || is_json {
+        return response;
+    }
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(Ok(chunk)) = body.data().await {
+        bytes.extend_from_slice(&chunk);
+    }
+    let message = String::from_utf8_lossy(&bytes).trim().to_string();
+    let message = if message.is_empty() {
+        status
+            .canonical_reason()
+            .unwrap_or("Request failed")
+            .to_string()
+    } else {
+        message
     };
-    let json = serde_json::to_string(&error).unwrap();
-    assert_eq!(json, r#"{"kind":"NotFound","causes":["Test"]}"#);
+    (
+        status,
+        Json(ErrorResponse {
+            code: ErrorKind::from_status_code(status),
+            message,
+            details: Vec::new(),
+        }),
+    )
+        .into_response()
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        let status = match self.kind {
-            ErrorKind::NotFound => StatusCode::NOT_FOUND,
-            ErrorKind::UnsupportedOperation => StatusCode::NOT_IMPLEMENTED,
-            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
-            ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
-            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
-            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        (status, json!(self).to_string()).into_response()
+impl Error {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::BadRequest,
+            source: Report::msg(message.into()),
+        }
     }
 }
 
@@ -103,3 +193,54 @@ impl From<ErrorKind> for Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use color_eyre::eyre::{eyre, WrapErr};
+
+    use super::{Error, ErrorKind};
+
+    #[test]
+    fn test_error_serialization() {
+        let error = Error {
+            kind: ErrorKind::NotFound,
+            source: color_eyre::Report::msg("Test"),
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"code":"NotFound","message":"Test","details":[]}"#);
+
+        let error: Error = Err::<(), _>(eyre!("Disk full"))
+            .wrap_err("Failed to write config")
+            .unwrap_err()
+            .into();
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "code": "Internal",
+                "message": "Failed to write config",
+                "details": ["Disk full"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_status_codes_round_trip() {
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::UnsupportedOperation,
+            ErrorKind::BadRequest,
+            ErrorKind::PermissionDenied,
+            ErrorKind::Unauthorized,
+            ErrorKind::Internal,
+            ErrorKind::Conflict,
+            ErrorKind::TooManyRequests,
+        ] {
+            assert_eq!(ErrorKind::from_status_code(kind.status_code()), kind);
+        }
+        assert_eq!(
+            ErrorKind::from_status_code(StatusCode::UNPROCESSABLE_ENTITY),
+            ErrorKind::BadRequest
+        );
+    }
+}
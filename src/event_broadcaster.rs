@@ -1,3 +1,8 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
 use tokio::sync::broadcast::{Receiver, Sender};
 use tracing::error;
 
@@ -6,20 +11,42 @@ use crate::events::Event;
 #[derive(Debug, Clone)]
 pub struct EventBroadcaster {
     event_tx: Sender<Event>,
+    sent: Arc<AtomicU64>,
 }
 
 impl EventBroadcaster {
     pub fn new(capacity: usize) -> (Self, Receiver<Event>) {
         let (event_tx, rx) = tokio::sync::broadcast::channel(capacity);
-        (Self { event_tx }, rx)
+        (
+            Self {
+                event_tx,
+                sent: Arc::new(AtomicU64::new(0)),
+            },
+            rx,
+        )
     }
 
     pub fn send(&self, event: Event) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
         if let Err(e) = self.event_tx.send(event) {
             error!("Failed to send event: {e}");
         }
     }
 
+    /// Events sent since the core started
+    pub fn sent_count(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Events not yet received by the slowest subscriber
+    pub fn queued_count(&self) -> usize {
+        self.event_tx.len()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.event_tx.receiver_count()
+    }
+
     pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Event> {
         self.event_tx.subscribe()
     }
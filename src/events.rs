@@ -1,15 +1,27 @@
 #![allow(clippy::enum_variant_names)]
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+};
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
-    auth::{permission::UserPermission, user_id::UserId},
+    auth::{access_grant::AccessGrant, permission::UserPermission, user_id::UserId},
+    backup::BackupEntry,
+    console_matchers::ConsoleMatchKind,
+    geoip::GeoLocation,
+    jobs::{JobInfo, JobState},
     macro_executor::MacroPID,
     output_types::ClientEvent,
-    traits::{t_macro::ExitStatus, t_player::Player, t_server::State, InstanceInfo},
+    suspicious_activity::SuspiciousActivityKind,
+    traits::{
+        t_configurable::LimitedResource, t_macro::ExitStatus, t_player::Player, t_server::State,
+        InstanceInfo,
+    },
     types::{InstanceUuid, Snowflake, TimeRange},
 };
 
@@ -28,6 +40,9 @@ pub struct EventQuery {
     pub event_instance_ids: Option<Vec<InstanceUuid>>,
     pub bearer_token: Option<String>,
     pub time_range: Option<TimeRange>,
+    /// only the events the notification preferences of the requester ask for
+    #[serde(default)]
+    pub notifications_only: bool,
 }
 
 impl EventQuery {
@@ -86,7 +101,28 @@ impl EventQuery {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+/// Lines of console output kept for `InstanceEventInner::InstanceCrashed`
+pub const CRASH_OUTPUT_LINES: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum JobEventInner {
+    Started,
+    /// sent when the progress of the job moves by a whole percent
+    Progress,
+    Finished,
+}
+
+/// A change in the lifecycle of a background job, carrying the job as it is after the change
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+pub struct JobEvent {
+    pub job: JobInfo,
+    pub job_event_inner: JobEventInner,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 #[derive(enum_kinds::EnumKind)]
@@ -114,12 +150,65 @@ pub enum InstanceEventInner {
         player_list: HashSet<Player>,
         players_joined: HashSet<Player>,
         players_left: HashSet<Player>,
+        /// where the players who joined connected from, by name, if GeoIP is on and knows
+        #[serde(default)]
+        joined_from: HashMap<String, GeoLocation>,
     },
 
     PlayerMessage {
         player: String,
         player_message: String,
     },
+    /// The instance ran out of memory, either inside its runtime or because the host killed it
+    InstanceOutOfMemory {
+        killed_by_os: bool,
+        message: String,
+    },
+    /// The server process exited with a failure while the instance was not being stopped,
+    /// `last_output` is the console output leading up to the crash
+    InstanceCrashed {
+        exit_code: Option<i32>,
+        last_output: Vec<String>,
+    },
+    /// The instance crashed and will be restarted after `delay` seconds as per its
+    /// crash restart policy
+    RestartingAfterCrash {
+        retry: u32,
+        max_retries: u32,
+        delay: u32,
+    },
+    /// The server process stayed over a resource limit of the instance, or the instance directory
+    /// went over its disk quota. Usage and limit are in megabytes for memory and disk and in
+    /// percent for CPU
+    ResourceLimitExceeded {
+        resource: LimitedResource,
+        usage: f64,
+        limit: f64,
+        killed: bool,
+    },
+    /// The instance finished starting, `startup_duration` is in milliseconds
+    InstanceReady {
+        startup_duration: u64,
+    },
+    /// A spark profiling session of `duration` seconds finished and its report was uploaded
+    ProfilerReport {
+        report_url: String,
+        duration: u32,
+    },
+    /// A user sent a command the command filter of the instance denies, it wasn't sent
+    CommandDenied {
+        command: String,
+        reason: String,
+    },
+    /// A line of output matched a matcher of the console pipeline, `groups` are the capture
+    /// groups of the matcher in order and `named_groups` the ones with a name
+    ConsoleMatch {
+        matcher: String,
+        kind: ConsoleMatchKind,
+        line: String,
+        groups: Vec<String>,
+        named_groups: BTreeMap<String, String>,
+    },
 }
 
 impl AsRef<InstanceEventInner> for InstanceEventInner {
@@ -128,14 +217,14 @@ impl AsRef<InstanceEventInner> for InstanceEventInner {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct InstanceEvent {
     pub instance_uuid: InstanceUuid,
     pub instance_name: String,
     pub instance_event_inner: InstanceEventInner,
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 #[derive(enum_kinds::EnumKind)]
@@ -151,6 +240,25 @@ pub enum UserEventInner {
     PermissionChanged {
         new_permissions: Box<UserPermission>,
     },
+    AccessGranted {
+        grant: Box<AccessGrant>,
+    },
+    AccessRevoked {
+        grant_id: Snowflake,
+    },
+    SessionRevoked {
+        session_id: Snowflake,
+    },
+    ApiKeyRevoked {
+        key_id: Snowflake,
+    },
+    /// users were imported from another core, `user_id` is the user who imported them
+    UsersImported {
+        imported: u32,
+        overwritten: u32,
+        renamed: u32,
+        skipped: u32,
+    },
 }
 
 impl AsRef<UserEventInner> for UserEventInner {
@@ -159,14 +267,14 @@ impl AsRef<UserEventInner> for UserEventInner {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct UserEvent {
     pub user_id: UserId,
     pub user_event_inner: UserEventInner,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 #[derive(enum_kinds::EnumKind)]
@@ -176,7 +284,7 @@ pub enum MacroEventInner {
     MainModuleExecuted,
     Stopped { exit_status: ExitStatus },
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct MacroEvent {
     pub instance_uuid: Option<InstanceUuid>,
@@ -197,7 +305,7 @@ impl From<MacroEvent> for Event {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum ProgressionEndValue {
@@ -205,14 +313,28 @@ pub enum ProgressionEndValue {
     InstanceDelete {
         instance_uuid: InstanceUuid,
     },
+    /// the instance is out of the instance list until it is unarchived
+    InstanceArchive {
+        instance_uuid: InstanceUuid,
+    },
     FSOperationCompleted {
         instance_uuid: InstanceUuid,
         success: bool,
         message: String,
     },
+    InstanceBackup(BackupEntry),
+    InstanceBackupRestore {
+        instance_uuid: InstanceUuid,
+        backup_id: Snowflake,
+    },
+    InstanceExport {
+        instance_uuid: InstanceUuid,
+        /// download the archive from `/file/:download_key`
+        download_key: String,
+    },
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum ProgressionStartValue {
@@ -226,10 +348,16 @@ pub enum ProgressionStartValue {
     InstanceDelete {
         instance_uuid: InstanceUuid,
     },
+    InstanceBackup {
+        instance_uuid: InstanceUuid,
+    },
+    InstanceExport {
+        instance_uuid: InstanceUuid,
+    },
 }
 
 // the backend will keep exactly 1 copy of ProgressionStart, and 1 copy of ProgressionUpdate OR ProgressionEnd
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum ProgressionEventInner {
@@ -248,7 +376,7 @@ pub enum ProgressionEventInner {
         inner: Option<ProgressionEndValue>,
     },
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub enum FSOperation {
     Read,
@@ -260,14 +388,14 @@ pub enum FSOperation {
     Download,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[serde(tag = "type", content = "path")]
 #[ts(export)]
 pub enum FSTarget {
     File(PathBuf),
     Directory(PathBuf),
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct FSEvent {
     pub operation: FSOperation,
@@ -285,7 +413,7 @@ pub fn new_fs_event(operation: FSOperation, target: FSTarget, caused_by: CausedB
 
 pub struct ProgressionEventID(Snowflake);
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct ProgressionEvent {
     event_id: Snowflake,
@@ -301,7 +429,150 @@ impl ProgressionEvent {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+#[serde(tag = "type")]
+#[derive(enum_kinds::EnumKind)]
+#[enum_kind(SystemEventKind, derive(Serialize, Deserialize, TS))]
+pub enum SystemEventInner {
+    HostMemoryPressure {
+        used_memory: u64,
+        total_memory: u64,
+    },
+    HostDiskPressure {
+        mount_point: PathBuf,
+        available_space: u64,
+        total_space: u64,
+    },
+    /// `grace_period` is in seconds
+    CoreShutdown {
+        grace_period: u64,
+        running_instances: u32,
+    },
+    InstanceKilledOnShutdown {
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+    },
+    /// the owner account was created with the first time setup key
+    SetupCompleted {
+        owner_name: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+pub struct SystemEvent {
+    pub system_event_inner: SystemEventInner,
+}
+
+impl From<SystemEventInner> for Event {
+    fn from(system_event_inner: SystemEventInner) -> Self {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::SystemEvent(SystemEvent { system_event_inner }),
+            caused_by: CausedBy::System,
+        }
+    }
+}
+
+/// Something that matters to the security of the core, only the owner can see these
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+#[serde(tag = "type")]
+#[derive(enum_kinds::EnumKind)]
+#[enum_kind(SecurityEventKind, derive(Serialize, Deserialize, TS))]
+pub enum SecurityEventInner {
+    /// a user logged in from a device none of their sessions were on
+    NewDeviceLogin {
+        user_agent: Option<String>,
+    },
+    LoginFailed {
+        username: String,
+    },
+    /// the password was right but the second factor was not
+    SecondFactorFailed,
+    /// a session, and with it an access token and a refresh token, was created
+    TokenCreated {
+        session_id: Snowflake,
+    },
+    ApiKeyCreated {
+        key_id: Snowflake,
+        name: String,
+    },
+    PermissionChanged {
+        new_permissions: Box<UserPermission>,
+    },
+    /// the console of an instance showed something that looks like an attack, `mitigation` is
+    /// the command that was run in response
+    SuspiciousActivity {
+        instance_uuid: InstanceUuid,
+        kind: SuspiciousActivityKind,
+        player: Option<String>,
+        ip: Option<String>,
+        mitigation: Option<String>,
+    },
+    /// logins for `username`, and from `ip` if known, are refused for `seconds` after repeated
+    /// failures
+    LoginLockout {
+        username: String,
+        ip: Option<String>,
+        seconds: u64,
+    },
+    /// the password of the user was changed and all their sessions were ended
+    PasswordChanged,
+    /// an owner reset the password of the user, who chooses a new one with the single use token
+    /// they were handed before `expires_at`
+    PasswordResetIssued {
+        expires_at: i64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+pub struct SecurityEvent {
+    /// the user concerned, `None` if e.g. a login attempt named a user that doesn't exist
+    pub user_id: Option<UserId>,
+    pub security_event_inner: SecurityEventInner,
+}
+
+impl SecurityEventInner {
+    pub fn is_alert(&self) -> bool {
+        matches!(
+            self,
+            SecurityEventInner::NewDeviceLogin { .. }
+                | SecurityEventInner::LoginFailed { .. }
+                | SecurityEventInner::SecondFactorFailed
+                | SecurityEventInner::SuspiciousActivity { .. }
+                | SecurityEventInner::LoginLockout { .. }
+                | SecurityEventInner::PasswordChanged
+                | SecurityEventInner::PasswordResetIssued { .. }
+        )
+    }
+}
+
+/// A mutating API call, recorded whether it succeeded or not
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
+#[ts(export)]
+pub struct AuditEvent {
+    /// `None` if the request was not authenticated
+    pub user_id: Option<UserId>,
+    pub method: String,
+    /// the path of the endpoint, without the query string
+    pub path: String,
+    /// the instance the endpoint acts on, if any
+    pub instance_uuid: Option<InstanceUuid>,
+    /// the http status code of the response
+    pub status: u16,
+}
+
+impl AuditEvent {
+    pub fn is_success(&self) -> bool {
+        (200..400).contains(&self.status)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 #[derive(enum_kinds::EnumKind)]
@@ -312,6 +583,10 @@ pub enum EventInner {
     MacroEvent(MacroEvent),
     FSEvent(FSEvent),
     ProgressionEvent(ProgressionEvent),
+    SystemEvent(SystemEvent),
+    SecurityEvent(SecurityEvent),
+    AuditEvent(AuditEvent),
+    JobEvent(JobEvent),
 }
 
 impl AsRef<EventInner> for EventInner {
@@ -326,8 +601,10 @@ fn event_type_export() {
     let _ = MacroEventKind::export();
     let _ = UserEventKind::export();
     let _ = InstanceEventKind::export();
+    let _ = SystemEventKind::export();
+    let _ = SecurityEventKind::export();
 }
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum CausedBy {
@@ -351,13 +628,27 @@ pub trait IntoEvent {
     fn into_event(self, caused_by: CausedBy, details: String) -> Event;
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+/// How severe an event is, from least to most
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, TS, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema,
+)]
 #[ts(export)]
 #[derive(sqlx::Type)]
 pub enum EventLevel {
     Info,
     Warning,
     Error,
+    /// needs attention right away, e.g. a server killed or the host running out of room
+    Critical,
+}
+
+impl EventLevel {
+    pub const ALL: [EventLevel; 4] = [
+        EventLevel::Info,
+        EventLevel::Warning,
+        EventLevel::Error,
+        EventLevel::Critical,
+    ];
 }
 
 // impl From<&EventInner> for EventType {
@@ -389,6 +680,82 @@ impl AsRef<Event> for Event {
 }
 
 impl Event {
+    pub fn level(&self) -> EventLevel {
+        match &self.event_inner {
+            EventInner::InstanceEvent(i) => match i.instance_event_inner {
+                InstanceEventInner::InstanceError { .. } => EventLevel::Error,
+                InstanceEventInner::InstanceWarning { .. } => EventLevel::Warning,
+                InstanceEventInner::InstanceOutOfMemory { .. } => EventLevel::Critical,
+                InstanceEventInner::InstanceCrashed { .. } => EventLevel::Error,
+                InstanceEventInner::RestartingAfterCrash { .. } => EventLevel::Warning,
+                InstanceEventInner::ConsoleMatch { kind, .. } => match kind {
+                    ConsoleMatchKind::Error => EventLevel::Error,
+                    ConsoleMatchKind::Warning => EventLevel::Warning,
+                    _ => EventLevel::Info,
+                },
+                InstanceEventInner::ResourceLimitExceeded { killed, .. } => {
+                    if killed {
+                        EventLevel::Critical
+                    } else {
+                        EventLevel::Warning
+                    }
+                }
+                _ => EventLevel::Info,
+            },
+            EventInner::UserEvent(_) => EventLevel::Info,
+            EventInner::MacroEvent(m) => match m.macro_event_inner {
+                MacroEventInner::Started => EventLevel::Info,
+                MacroEventInner::Stopped { ref exit_status } => {
+                    if exit_status.is_success() {
+                        EventLevel::Info
+                    } else {
+                        EventLevel::Error
+                    }
+                }
+                MacroEventInner::MainModuleExecuted => EventLevel::Info,
+            },
+            EventInner::ProgressionEvent(p) => match p.progression_event_inner() {
+                ProgressionEventInner::ProgressionStart { .. } => EventLevel::Info,
+                ProgressionEventInner::ProgressionUpdate { .. } => EventLevel::Info,
+                ProgressionEventInner::ProgressionEnd { success, .. } => {
+                    if *success {
+                        EventLevel::Info
+                    } else {
+                        EventLevel::Error
+                    }
+                }
+            },
+            EventInner::FSEvent(_) => EventLevel::Info,
+            EventInner::SystemEvent(system_event) => match system_event.system_event_inner {
+                SystemEventInner::HostMemoryPressure { .. }
+                | SystemEventInner::HostDiskPressure { .. } => EventLevel::Critical,
+                SystemEventInner::CoreShutdown { .. } => EventLevel::Warning,
+                SystemEventInner::InstanceKilledOnShutdown { .. } => EventLevel::Error,
+                SystemEventInner::SetupCompleted { .. } => EventLevel::Info,
+            },
+            EventInner::SecurityEvent(security_event) => {
+                match security_event.security_event_inner {
+                    SecurityEventInner::SuspiciousActivity { .. }
+                    | SecurityEventInner::LoginLockout { .. } => EventLevel::Critical,
+                    ref inner if inner.is_alert() => EventLevel::Warning,
+                    _ => EventLevel::Info,
+                }
+            }
+            EventInner::AuditEvent(audit_event) => {
+                if audit_event.is_success() {
+                    EventLevel::Info
+                } else {
+                    EventLevel::Warning
+                }
+            }
+            EventInner::JobEvent(job_event) => match job_event.job.state {
+                JobState::Failed => EventLevel::Error,
+                JobState::Cancelled => EventLevel::Warning,
+                JobState::Running | JobState::Succeeded => EventLevel::Info,
+            },
+        }
+    }
+
     pub fn is_event_console_message(&self) -> bool {
         match &self.event_inner {
             EventInner::InstanceEvent(instance_event) => matches!(
@@ -400,6 +767,15 @@ impl Event {
             _ => false,
         }
     }
+    pub fn is_state_transition(&self) -> bool {
+        match &self.event_inner {
+            EventInner::InstanceEvent(instance_event) => matches!(
+                &instance_event.instance_event_inner,
+                InstanceEventInner::StateTransition { .. }
+            ),
+            _ => false,
+        }
+    }
     pub fn try_player_message(&self) -> Option<(String, String)> {
         match &self.event_inner {
             EventInner::InstanceEvent(instance_event) => match &instance_event.instance_event_inner
@@ -416,6 +792,8 @@ impl Event {
     pub fn get_instance_uuid(&self) -> Option<InstanceUuid> {
         match &self.event_inner {
             EventInner::InstanceEvent(instance_event) => Some(instance_event.instance_uuid.clone()),
+            EventInner::AuditEvent(audit_event) => audit_event.instance_uuid.clone(),
+            EventInner::JobEvent(job_event) => job_event.job.instance_uuid.clone(),
             _ => None,
         }
     }
@@ -500,6 +878,25 @@ impl Event {
             caused_by: CausedBy::System,
         }
     }
+
+    pub fn new_instance_ready(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        startup_duration: std::time::Duration,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::InstanceReady {
+                    startup_duration: startup_duration.as_millis() as u64,
+                },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
     #[must_use]
     pub fn new_progression_event_start(
         progression_name: impl AsRef<str>,
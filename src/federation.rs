@@ -0,0 +1,577 @@
+//! Federation of Lodestone cores: a primary core registers other cores as peers, lists their
+//! instances next to its own, forwards the API calls for their instances to them and merges the
+//! events of their instances into its own broadcaster.
+//!
+//! Calls are forwarded with the token the peer was registered with, so only the owner of the
+//! primary can reach the instances of peers. Websockets, e.g. the console stream, aren't forwarded,
+//! the events of peer instances reach clients through the event stream of the primary instead.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{FromRequest, OriginalUri, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{eyre, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+use ts_rs::TS;
+
+use crate::{
+    audit::instance_segment,
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{Event, EventInner},
+    traits::InstanceInfo,
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+    AppState,
+};
+
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+const EVENT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const INSTANCE_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// only the events of instances, the users and the security of a peer are its own business
+const PEER_EVENT_FILTER: &str = r#"{"event_types":["InstanceEvent"]}"#;
+
+/// headers that describe a single connection and aren't forwarded
+const HOP_BY_HOP_HEADERS: &[header::HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct PeerConfig {
+    /// the base url of the peer, e.g. `https://node2.example.com:16662`
+    pub url: String,
+    /// a token of the owner of the peer
+    pub token: String,
+}
+
+impl PeerConfig {
+    fn validate(&self) -> Result<url::Url, Error> {
+        let url = url::Url::parse(self.url.trim_end_matches('/')).map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid peer url : {e}"),
+        })?;
+        if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The peer url must be an http or https url"),
+            });
+        }
+        if self.token.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The token of the peer cannot be empty"),
+            });
+        }
+        Ok(url)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct Peer {
+    id: String,
+    /// the core name of the peer when it was registered
+    name: String,
+    core_uuid: String,
+    url: String,
+    token: String,
+    added_at: i64,
+}
+
+impl Peer {
+    fn api_url(&self, path_and_query: &str) -> String {
+        format!("{}{path_and_query}", self.url.trim_end_matches('/'))
+    }
+
+    fn info(&self) -> PeerInfo {
+        PeerInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            core_uuid: self.core_uuid.clone(),
+            url: self.url.clone(),
+            added_at: self.added_at,
+        }
+    }
+}
+
+/// A registered peer, without its token
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PeerInfo {
+    pub id: String,
+    pub name: String,
+    pub core_uuid: String,
+    pub url: String,
+    pub added_at: i64,
+}
+
+/// The instances of one core, `peer` is `None` for the core answering
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NodeInstances {
+    pub peer: Option<PeerInfo>,
+    pub instances: Vec<InstanceInfo>,
+    /// why the instances of the peer couldn't be listed
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PeerCoreInfo {
+    uuid: String,
+    core_name: String,
+}
+
+async fn peer_get<T: DeserializeOwned>(
+    http: &reqwest::Client,
+    peer: &Peer,
+    path_and_query: &str,
+) -> Result<T, Error> {
+    Ok(http
+        .get(peer.api_url(path_and_query))
+        .bearer_auth(&peer.token)
+        .timeout(PEER_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context(format!("Failed to reach peer {}", peer.name))?
+        .json()
+        .await
+        .context(format!(
+            "Peer {} answered with something unexpected",
+            peer.name
+        ))?)
+}
+
+async fn fetch_instances(http: &reqwest::Client, peer: &Peer) -> Result<Vec<InstanceInfo>, Error> {
+    peer_get(http, peer, "/api/v1/instance/list").await
+}
+
+async fn fetch_events(
+    http: &reqwest::Client,
+    peer: &Peer,
+    since: Option<Snowflake>,
+) -> Result<Vec<Event>, Error> {
+    let mut query = vec![("filter", PEER_EVENT_FILTER.to_string())];
+    if let Some(since) = since {
+        query.push(("since_snowflake", since.to_string()));
+    }
+    Ok(http
+        .get(peer.api_url("/api/v1/events/all/buffer"))
+        .query(&query)
+        .bearer_auth(&peer.token)
+        .timeout(PEER_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context(format!("Failed to reach peer {}", peer.name))?
+        .json()
+        .await
+        .context(format!(
+            "Peer {} answered with something unexpected",
+            peer.name
+        ))?)
+}
+
+/// The peers of this core, persisted in the stores directory, and which of them owns each
+/// instance they reported
+pub struct Peers {
+    path_to_store: PathBuf,
+    peers: Vec<Peer>,
+    owners: HashMap<InstanceUuid, String>,
+    http: reqwest::Client,
+}
+
+impl Peers {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            peers: Vec::new(),
+            owners: HashMap::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.peers = Vec::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read peers file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.peers = serde_json::from_slice(&content).context(format!(
+            "Failed to parse peers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.peers).context("Failed to serialize peers")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write peers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.iter().map(Peer::info).collect()
+    }
+
+    fn peer(&self, id: &str) -> Option<&Peer> {
+        self.peers.iter().find(|peer| peer.id == id)
+    }
+
+    /// The peer owning an instance, as of the last refresh
+    fn owner_of(&self, instance_uuid: &InstanceUuid) -> Option<Peer> {
+        self.peer(self.owners.get(instance_uuid)?).cloned()
+    }
+
+    /// Register a peer after checking its token works, a core can't be its own peer
+    pub async fn add_peer(
+        &mut self,
+        config: PeerConfig,
+        own_uuid: &str,
+    ) -> Result<PeerInfo, Error> {
+        let url = config.validate()?;
+        let mut peer = Peer {
+            id: rand_alphanumeric(16),
+            name: String::new(),
+            core_uuid: String::new(),
+            url: url.as_str().trim_end_matches('/').to_string(),
+            token: config.token,
+            added_at: chrono::Utc::now().timestamp(),
+        };
+        let core_info: PeerCoreInfo = peer_get(&self.http, &peer, "/api/v1/info").await?;
+        if core_info.uuid == own_uuid {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A core cannot be its own peer"),
+            });
+        }
+        if self
+            .peers
+            .iter()
+            .any(|other| other.core_uuid == core_info.uuid)
+        {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("This core is already a peer"),
+            });
+        }
+        peer.name = core_info.core_name;
+        peer.core_uuid = core_info.uuid;
+        // the info endpoint is public, listing instances is what needs the token
+        let instances = fetch_instances(&self.http, &peer).await?;
+        self.peers.push(peer.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.peers.pop();
+            return Err(e);
+        }
+        for instance in instances {
+            self.owners.insert(instance.uuid, peer.id.clone());
+        }
+        Ok(peer.info())
+    }
+
+    pub async fn remove_peer(&mut self, id: &str) -> Result<(), Error> {
+        let index = self
+            .peers
+            .iter()
+            .position(|peer| peer.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Peer not found"),
+            })?;
+        let peer = self.peers.remove(index);
+        if let Err(e) = self.write_to_file().await {
+            self.peers.insert(index, peer);
+            return Err(e);
+        }
+        self.owners.retain(|_, owner| *owner != peer.id);
+        Ok(())
+    }
+
+    fn set_instances(&mut self, peer_id: &str, instances: &[InstanceInfo]) {
+        self.owners.retain(|_, owner| owner != peer_id);
+        for instance in instances {
+            self.owners
+                .insert(instance.uuid.clone(), peer_id.to_string());
+        }
+    }
+}
+
+/// The instances of every peer, the ones that can't be reached are listed with the error
+pub async fn peer_instances(peers: &Mutex<Peers>) -> Vec<NodeInstances> {
+    let (http, snapshot) = {
+        let peers = peers.lock().await;
+        (peers.http.clone(), peers.peers.clone())
+    };
+    let results = futures::future::join_all(
+        snapshot
+            .iter()
+            .map(|peer| async { fetch_instances(&http, peer).await }),
+    )
+    .await;
+    let mut peers = peers.lock().await;
+    snapshot
+        .into_iter()
+        .zip(results)
+        .map(|(peer, result)| match result {
+            Ok(instances) => {
+                // the peer may have been removed meanwhile
+                if peers.peer(&peer.id).is_some() {
+                    peers.set_instances(&peer.id, &instances);
+                }
+                NodeInstances {
+                    peer: Some(peer.info()),
+                    instances,
+                    error: None,
+                }
+            }
+            Err(e) => NodeInstances {
+                peer: Some(peer.info()),
+                instances: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        })
+        .collect()
+}
+
+/// Keeps which peer owns which instance up to date and merges the events of peer instances into
+/// the broadcaster of this core.
+///
+/// An event is only taken from the peer owning its instance, so peers registered with each other
+/// don't send the same events back and forth.
+pub async fn federation_task(peers: Arc<Mutex<Peers>>, event_broadcaster: EventBroadcaster) {
+    let mut poll = tokio::time::interval(EVENT_POLL_INTERVAL);
+    let mut refresh = tokio::time::interval(INSTANCE_REFRESH_INTERVAL);
+    // the newest event seen of each peer, the events from before a peer was polled are skipped
+    let mut last_seen: HashMap<String, Option<Snowflake>> = HashMap::new();
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                for node in peer_instances(&peers).await {
+                    if let (Some(peer), Some(e)) = (node.peer, node.error) {
+                        debug!("Failed to list the instances of peer {} : {e}", peer.name);
+                    }
+                }
+            }
+            _ = poll.tick() => {
+                let (http, snapshot, owners) = {
+                    let peers = peers.lock().await;
+                    (peers.http.clone(), peers.peers.clone(), peers.owners.clone())
+                };
+                last_seen.retain(|id, _| snapshot.iter().any(|peer| &peer.id == id));
+                for peer in snapshot {
+                    let since = last_seen.get(&peer.id).copied().flatten();
+                    let events = match fetch_events(&http, &peer, since).await {
+                        Ok(events) => events,
+                        Err(e) => {
+                            debug!("Failed to poll the events of peer {} : {e}", peer.name);
+                            continue;
+                        }
+                    };
+                    let is_first_poll = !last_seen.contains_key(&peer.id);
+                    let newest = events.iter().map(|event| event.snowflake).max().or(since);
+                    last_seen.insert(peer.id.clone(), newest);
+                    if is_first_poll {
+                        continue;
+                    }
+                    for event in events {
+                        let owned_by_peer = match &event.event_inner {
+                            EventInner::InstanceEvent(instance_event) => owners
+                                .get(&instance_event.instance_uuid)
+                                .is_some_and(|owner| *owner == peer.id),
+                            _ => false,
+                        };
+                        if owned_by_peer {
+                            event_broadcaster.send(event);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn bad_gateway(e: impl std::fmt::Display) -> Response {
+    Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Failed to forward the request to the peer : {e}"),
+    }
+    .into_response()
+}
+
+/// Forward the calls for the instances of peers to them, the owner of this core can use the
+/// instances of every peer as if they were local
+pub async fn proxy_to_peer<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    B: axum::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<axum::BoxError>,
+{
+    let instance_uuid = match instance_segment(request.uri().path()) {
+        Some(segment) => InstanceUuid::from(segment.to_string()),
+        None => return next.run(request).await,
+    };
+    if state.instances.contains_key(&instance_uuid) {
+        return next.run(request).await;
+    }
+    let peer = match state.peers.lock().await.owner_of(&instance_uuid) {
+        Some(peer) => peer,
+        None => return next.run(request).await,
+    };
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string());
+    let requester = match token {
+        Some(token) => state.users_manager.read().await.try_auth(&token),
+        None => None,
+    };
+    match requester {
+        Some(requester) => {
+            if let Err(e) = requester.try_owner("use the instances of peers") {
+                return e.into_response();
+            }
+        }
+        None => {
+            return Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Token error"),
+            }
+            .into_response()
+        }
+    }
+
+    // the peer serves the same api, under the same prefix
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| request.uri().clone());
+    let path_and_query = uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str().to_string())
+        .unwrap_or_default();
+    let method = request.method().clone();
+    let mut headers = forwarded_headers(request.headers());
+    headers.remove(header::AUTHORIZATION);
+    let body = match Bytes::from_request(request, &()).await {
+        Ok(body) => body,
+        Err(e) => return e.into_response(),
+    };
+    let http = state.peers.lock().await.http.clone();
+    let response = match http
+        .request(method, peer.api_url(&path_and_query))
+        .headers(headers)
+        .bearer_auth(&peer.token)
+        .body(body)
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            error!(
+                "Failed to forward {path_and_query} to peer {} : {e}",
+                peer.name
+            );
+            return bad_gateway(e);
+        }
+    };
+    let status = response.status();
+    let headers = forwarded_headers(response.headers());
+    (status, headers, StreamBody::new(response.bytes_stream())).into_response()
+}
+
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Peer, PeerConfig, Peers};
+    use crate::types::InstanceUuid;
+
+    #[test]
+    fn test_peer_config_validate() {
+        let config = |url: &str, token: &str| PeerConfig {
+            url: url.to_string(),
+            token: token.to_string(),
+        };
+        assert_eq!(
+            config("https://node2.example.com:16662/", "token")
+                .validate()
+                .unwrap()
+                .as_str(),
+            "https://node2.example.com:16662/"
+        );
+        assert!(config("ftp://node2.example.com", "token")
+            .validate()
+            .is_err());
+        assert!(config("node2", "token").validate().is_err());
+        assert!(config("http://node2", "").validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peers_owners() {
+        let temp_dir = tempdir::TempDir::new("test_peers").unwrap();
+        let mut peers = Peers::new(temp_dir.path().join("peers.json"));
+        let peer = Peer {
+            id: "node2".to_string(),
+            name: "Node 2".to_string(),
+            core_uuid: "core-2".to_string(),
+            url: "http://node2:16662".to_string(),
+            token: "token".to_string(),
+            added_at: 0,
+        };
+        assert_eq!(
+            peer.api_url("/api/v1/instance/list"),
+            "http://node2:16662/api/v1/instance/list"
+        );
+        peers.peers.push(peer);
+        let uuid = InstanceUuid::from("remote".to_string());
+        peers.owners.insert(uuid.clone(), "node2".to_string());
+        assert_eq!(peers.owner_of(&uuid).unwrap().id, "node2");
+
+        peers.set_instances("node2", &[]);
+        assert!(peers.owner_of(&uuid).is_none());
+
+        peers.owners.insert(uuid.clone(), "node2".to_string());
+        peers.remove_peer("node2").await.unwrap();
+        assert!(peers.owner_of(&uuid).is_none());
+        assert!(peers.peers().is_empty());
+
+        let mut peers = Peers::new(temp_dir.path().join("peers.json"));
+        peers.load_from_file().await.unwrap();
+        assert!(peers.peers().is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use globset::GlobBuilder;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+const DEFAULT_MAX_RESULTS: usize = 200;
+const MAX_RESULTS: usize = 1000;
+/// larger files are matched by name but their content is not searched
+const MAX_CONTENT_SEARCH_SIZE: u64 = 8 * 1024 * 1024;
+/// a file matching on more lines only lists the first ones
+const MAX_LINES_PER_FILE: usize = 20;
+/// longer lines, e.g. of minified json, are cut
+const MAX_LINE_LEN: usize = 200;
+/// a null byte in the start of a file means it's binary, the way git tells
+const BINARY_SNIFF_LEN: usize = 8000;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct FileSearchQuery {
+    /// e.g. `*.properties`, matched against the file name, or against the path from the searched
+    /// directory if it has a `/`, e.g. `config/**/*.toml`
+    pub glob: String,
+    /// only the files containing this text, ignoring case
+    pub content: Option<String>,
+    pub max_results: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct LineMatch {
+    /// starting from 1
+    pub line_number: usize,
+    pub line: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct FileMatch {
+    /// relative to the searched directory, with forward slashes
+    pub path: String,
+    pub size: u64,
+    /// empty if the search is by name only
+    pub lines: Vec<LineMatch>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct FileSearchResult {
+    pub matches: Vec<FileMatch>,
+    /// there were more matches than `max_results`
+    pub truncated: bool,
+    /// files whose name matched but whose content was not searched, as they are binary, too
+    /// large or unreadable
+    pub skipped: u32,
+}
+
+/// The lines of a file containing `needle`, which is lowercase. `None` if the file is binary
+fn grep_file(path: &Path, needle: &str) -> Option<Vec<LineMatch>> {
+    let bytes = std::fs::read(path).ok()?;
+    if bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.to_lowercase().contains(needle))
+            .take(MAX_LINES_PER_FILE)
+            .map(|(index, line)| LineMatch {
+                line_number: index + 1,
+                line: line.trim().chars().take(MAX_LINE_LEN).collect(),
+            })
+            .collect(),
+    )
+}
+
+/// Find the files under `dir` matching the query, sorted by path. Symlinks are not followed and
+/// directories that can't be read are skipped.
+pub fn search_files(dir: &Path, query: &FileSearchQuery) -> Result<FileSearchResult, Error> {
+    let matcher = GlobBuilder::new(&query.glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid glob {} : {e}", query.glob),
+        })?
+        .compile_matcher();
+    let match_path = query.glob.contains('/');
+    let needle = query
+        .content
+        .as_deref()
+        .filter(|content| !content.is_empty())
+        .map(str::to_lowercase);
+    let max_results = query
+        .max_results
+        .unwrap_or(DEFAULT_MAX_RESULTS)
+        .min(MAX_RESULTS);
+
+    let mut result = FileSearchResult::default();
+    for entry in walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative_path = entry
+            .path()
+            .strip_prefix(dir)
+            .context("Failed to get relative path")?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let matched = match match_path {
+            true => matcher.is_match(&relative_path),
+            false => matcher.is_match(entry.file_name()),
+        };
+        if !matched {
+            continue;
+        }
+        let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+        let lines = match &needle {
+            None => Vec::new(),
+            Some(_) if size > MAX_CONTENT_SEARCH_SIZE => {
+                result.skipped += 1;
+                continue;
+            }
+            Some(needle) => match grep_file(entry.path(), needle) {
+                Some(lines) if lines.is_empty() => continue,
+                Some(lines) => lines,
+                None => {
+                    result.skipped += 1;
+                    continue;
+                }
+            },
+        };
+        if result.matches.len() == max_results {
+            result.truncated = true;
+            break;
+        }
+        result.matches.push(FileMatch {
+            path: relative_path,
+            size,
+            lines,
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_files, FileSearchQuery, LineMatch};
+
+    #[test]
+    fn test_search_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("server.properties"),
+            "motd=A Minecraft Server\nview-distance=10\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.path().join("config/mod")).unwrap();
+        std::fs::write(
+            dir.path().join("config/mod/client.toml"),
+            "# View-Distance of the mod\nenabled = true\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("config/other.toml"), "enabled = false\n").unwrap();
+        std::fs::write(dir.path().join("config/blob.properties"), b"view\0distance").unwrap();
+
+        let query = |glob: &str, content: Option<&str>| FileSearchQuery {
+            glob: glob.to_string(),
+            content: content.map(str::to_string),
+            max_results: None,
+        };
+        let paths = |glob: &str| -> Vec<String> {
+            search_files(dir.path(), &query(glob, None))
+                .unwrap()
+                .matches
+                .into_iter()
+                .map(|m| m.path)
+                .collect()
+        };
+        assert_eq!(
+            paths("*.toml"),
+            vec!["config/mod/client.toml", "config/other.toml"]
+        );
+        assert_eq!(paths("config/*.toml"), vec!["config/other.toml"]);
+        assert_eq!(paths("config/**/*.toml").len(), 2);
+
+        let result = search_files(dir.path(), &query("*", Some("view-distance"))).unwrap();
+        assert_eq!(result.matches.len(), 2);
+        assert_eq!(result.matches[0].path, "config/mod/client.toml");
+        assert_eq!(
+            result.matches[1].lines,
+            vec![LineMatch {
+                line_number: 2,
+                line: "view-distance=10".to_string(),
+            }]
+        );
+        assert_eq!(result.skipped, 1);
+
+        let result = search_files(
+            dir.path(),
+            &FileSearchQuery {
+                max_results: Some(1),
+                ..query("*.toml", None)
+            },
+        )
+        .unwrap();
+        assert!(result.truncated);
+        assert!(search_files(dir.path(), &query("[", None)).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Serving files without reading them into memory: the whole file, a byte range of it as asked by
+//! a `Range` header, its last lines, or its last lines followed by whatever is appended to it, for
+//! multi gigabyte logs and dumps.
+
+use std::{io::SeekFrom, path::Path, time::Duration};
+
+use axum::{
+    body::{Bytes, StreamBody},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// how often a followed file is checked for new content
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Deserialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct ReadFileQuery {
+    /// only the last `tail` lines
+    pub tail: Option<u64>,
+    /// keep the response open and send what is appended to the file, starting after the last
+    /// `tail` lines or at the end of the file
+    #[serde(default)]
+    pub follow: bool,
+}
+
+/// The inclusive byte range asked for by a `Range` header, `None` if the header should be ignored
+/// and the whole file sent, e.g. because it asks for several ranges. `Some(Err(()))` if the range
+/// is past the end of the file
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", "") => None,
+        // the last `suffix` bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || len == 0 {
+                return Some(Err(()));
+            }
+            Some(Ok((len.saturating_sub(suffix), len - 1)))
+        }
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end = if end.is_empty() {
+                None
+            } else {
+                Some(end.parse::<u64>().ok()?)
+            };
+            if matches!(end, Some(end) if end < start) {
+                return None;
+            }
+            if start >= len {
+                return Some(Err(()));
+            }
+            Some(Ok((start, end.map_or(len - 1, |end| end.min(len - 1)))))
+        }
+    }
+}
+
+/// The offset the last `lines` lines of the file start at, found by reading it backwards. A
+/// newline ending the file doesn't start another line
+async fn tail_offset(file: &mut tokio::fs::File, len: u64, lines: u64) -> Result<u64, Error> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    let mut seen = 0;
+    let mut end = len;
+    let mut buf = vec![0; CHUNK_SIZE];
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK_SIZE as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))
+            .await
+            .context("Failed to seek in file")?;
+        file.read_exact(chunk)
+            .await
+            .context("Failed to read file")?;
+        for (i, byte) in chunk.iter().enumerate().rev() {
+            let offset = start + i as u64;
+            if *byte == b'\n' && offset != len - 1 {
+                seen += 1;
+                if seen == lines {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+/// Send what is appended to the file from `offset` on, until the client goes away. A file
+/// truncated below what was sent, e.g. a rotated log, is followed from its start
+fn follow(
+    file: tokio::fs::File,
+    offset: u64,
+) -> impl futures::Stream<Item = std::io::Result<Bytes>> {
+    futures::stream::unfold(
+        (file, offset, vec![0; CHUNK_SIZE]),
+        |(mut file, mut offset, mut buf)| async move {
+            loop {
+                let read = match file.read(&mut buf).await {
+                    Ok(read) => read,
+                    Err(e) => return Some((Err(e), (file, offset, buf))),
+                };
+                if read > 0 {
+                    offset += read as u64;
+                    let chunk = Bytes::copy_from_slice(&buf[..read]);
+                    return Some((Ok(chunk), (file, offset, buf)));
+                }
+                tokio::time::sleep(FOLLOW_INTERVAL).await;
+                match file.metadata().await {
+                    Ok(metadata) if metadata.len() < offset => {
+                        if let Err(e) = file.seek(SeekFrom::Start(0)).await {
+                            return Some((Err(e), (file, offset, buf)));
+                        }
+                        offset = 0;
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Some((Err(e), (file, offset, buf))),
+                }
+            }
+        },
+    )
+}
+
+/// The file at `path` as asked by the query and the `Range` header of the request, a range is
+/// ignored for tails
+pub async fn file_response(
+    path: &Path,
+    headers: &HeaderMap,
+    query: &ReadFileQuery,
+) -> Result<Response, Error> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .context(format!("Failed to open file {}", path.display()))?;
+    let len = file
+        .metadata()
+        .await
+        .context(format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    let content_type = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    )];
+
+    if query.follow || query.tail.is_some() {
+        let offset = match query.tail {
+            Some(lines) => tail_offset(&mut file, len, lines).await?,
+            None => len,
+        };
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .context("Failed to seek in file")?;
+        if query.follow {
+            return Ok((content_type, StreamBody::new(follow(file, offset))).into_response());
+        }
+        return Ok((
+            content_type,
+            [(header::CONTENT_LENGTH, (len - offset).to_string())],
+            StreamBody::new(ReaderStream::new(file.take(len - offset))),
+        )
+            .into_response());
+    }
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| parse_range(range, len));
+    match range {
+        None => Ok((
+            content_type,
+            [
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+                (header::CONTENT_LENGTH, len.to_string()),
+            ],
+            // a log still being written to is sent as it was when the request came in
+            StreamBody::new(ReaderStream::new(file.take(len))),
+        )
+            .into_response()),
+        Some(Err(())) => Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+        )
+            .into_response()),
+        Some(Ok((start, end))) => {
+            file.seek(SeekFrom::Start(start))
+                .await
+                .context("Failed to seek in file")?;
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                content_type,
+                [
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                    (header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+                    (header::CONTENT_LENGTH, (end - start + 1).to_string()),
+                ],
+                StreamBody::new(ReaderStream::new(file.take(end - start + 1))),
+            )
+                .into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, tail_offset};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        // clamped to the end of the file
+        assert_eq!(parse_range("bytes=500-5000", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+        // ignored
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("bytes=99-0", 1000), None);
+        assert_eq!(parse_range("lines=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+    }
+
+    #[tokio::test]
+    async fn test_tail_offset() {
+        let temp_dir = tempdir::TempDir::new("test_tail_offset").unwrap();
+        let path = temp_dir.path().join("latest.log");
+        let content = "first\nsecond\nthird\n";
+        std::fs::write(&path, content).unwrap();
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        let len = content.len() as u64;
+
+        assert_eq!(tail_offset(&mut file, len, 0).await.unwrap(), len);
+        assert_eq!(
+            &content[tail_offset(&mut file, len, 1).await.unwrap() as usize..],
+            "third\n"
+        );
+        assert_eq!(
+            &content[tail_offset(&mut file, len, 2).await.unwrap() as usize..],
+            "second\nthird\n"
+        );
+        assert_eq!(tail_offset(&mut file, len, 10).await.unwrap(), 0);
+
+        // spanning several chunks
+        let long_line = "a".repeat(super::CHUNK_SIZE + 10);
+        let content = format!("{long_line}\n{long_line}\nlast");
+        std::fs::write(&path, &content).unwrap();
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        let len = content.len() as u64;
+        assert_eq!(
+            tail_offset(&mut file, len, 2).await.unwrap(),
+            long_line.len() as u64 + 1
+        );
+    }
+}
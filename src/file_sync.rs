@@ -0,0 +1,165 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{error::Error, upload_sessions::sha256_of_file};
+
+/// A file of a synced directory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SyncEntry {
+    /// relative to the synced directory, with forward slashes
+    pub path: String,
+    pub size: u64,
+    /// unix timestamp in seconds
+    pub modified: i64,
+    /// only computed when asked for, as it means reading the whole file
+    pub sha256: Option<String>,
+}
+
+/// What a mirror has to do to match the directory
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SyncDiff {
+    /// new or modified files, to fetch
+    pub changed: Vec<SyncEntry>,
+    /// files of the mirror that are gone from the directory
+    pub removed: Vec<String>,
+}
+
+fn sync_entry(root: &Path, path: &Path, with_hash: bool) -> Result<SyncEntry, Error> {
+    let metadata = std::fs::metadata(path)
+        .context(format!("Failed to read metadata of {}", path.display()))?;
+    let relative_path = path
+        .strip_prefix(root)
+        .context("Failed to get relative path")?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Ok(SyncEntry {
+        path: relative_path,
+        size: metadata.len(),
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default(),
+        sha256: match with_hash {
+            true => Some(sha256_of_file(path)?),
+            false => None,
+        },
+    })
+}
+
+/// Every file under `root`, sorted by path. Symlinks are not followed.
+pub fn manifest(root: &Path, with_hashes: bool) -> Result<Vec<SyncEntry>, Error> {
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(root).min_depth(1) {
+        let entry = entry.context(format!("Failed to read directory {}", root.display()))?;
+        if entry.file_type().is_file() {
+            entries.push(sync_entry(root, entry.path(), with_hashes)?);
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Compare `root` against the files a mirror has.
+///
+/// Files are compared by size first, then by hash if the mirror sent one, and by modification
+/// time otherwise. Only files whose size matches a hashed entry are read.
+pub fn diff(root: &Path, mirror: &[SyncEntry]) -> Result<SyncDiff, Error> {
+    let mirror: HashMap<&str, &SyncEntry> = mirror
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+    let local = manifest(root, false)?;
+    let local_paths: HashSet<&str> = local.iter().map(|entry| entry.path.as_str()).collect();
+    let mut changed = Vec::new();
+    for entry in local.iter() {
+        let is_changed = match mirror.get(entry.path.as_str()) {
+            None => true,
+            Some(mirrored) if mirrored.size != entry.size => true,
+            Some(SyncEntry {
+                sha256: Some(sha256),
+                ..
+            }) => !sha256.eq_ignore_ascii_case(&sha256_of_file(&root.join(&entry.path))?),
+            Some(mirrored) => mirrored.modified != entry.modified,
+        };
+        if is_changed {
+            changed.push(entry.clone());
+        }
+    }
+    let mut removed: Vec<String> = mirror
+        .keys()
+        .filter(|path| !local_paths.contains(*path))
+        .map(|path| path.to_string())
+        .collect();
+    removed.sort();
+    Ok(SyncDiff { changed, removed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, manifest};
+
+    #[test]
+    fn test_diff() {
+        let temp_dir = tempdir::TempDir::new("test_file_sync").unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("world/region")).unwrap();
+        std::fs::write(root.join("server.properties"), "motd=hi\n").unwrap();
+        std::fs::write(root.join("world/region/r.0.0.mca"), "chunks").unwrap();
+        std::fs::write(root.join("world/level.dat"), "level").unwrap();
+
+        let mut mirror = manifest(root, true).unwrap();
+        assert_eq!(
+            mirror
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "server.properties",
+                "world/level.dat",
+                "world/region/r.0.0.mca"
+            ]
+        );
+        let unchanged = diff(root, &mirror).unwrap();
+        assert!(unchanged.changed.is_empty());
+        assert!(unchanged.removed.is_empty());
+
+        // same size, different content
+        std::fs::write(root.join("world/level.dat"), "LEVEL").unwrap();
+        std::fs::write(root.join("ops.json"), "[]").unwrap();
+        std::fs::remove_file(root.join("world/region/r.0.0.mca")).unwrap();
+        let changes = diff(root, &mirror).unwrap();
+        assert_eq!(
+            changes
+                .changed
+                .iter()
+                .map(|entry| entry.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ops.json", "world/level.dat"]
+        );
+        assert_eq!(changes.removed, vec!["world/region/r.0.0.mca".to_string()]);
+
+        // without hashes, files are compared by modification time
+        for entry in mirror.iter_mut() {
+            entry.sha256 = None;
+            entry.modified -= 10;
+        }
+        let changes = diff(root, &mirror).unwrap();
+        assert!(changes
+            .changed
+            .iter()
+            .any(|entry| entry.path == "server.properties"));
+    }
+}
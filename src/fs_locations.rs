@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+};
+
+/// maximum number of recently visited directories kept per user
+const MAX_RECENT_DIRECTORIES: usize = 20;
+/// maximum number of bookmarks per user
+const MAX_BOOKMARKS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct FsBookmark {
+    pub name: String,
+    pub path: PathBuf,
+    pub creation_time: i64,
+}
+
+/// Bookmarks and recently visited directories of a user in the global file manager
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct UserFsLocations {
+    pub bookmarks: Vec<FsBookmark>,
+    /// most recent first
+    pub recent_directories: Vec<PathBuf>,
+}
+
+impl UserFsLocations {
+    fn add_bookmark(&mut self, bookmark: FsBookmark) -> Result<(), Error> {
+        if self.bookmarks.iter().any(|b| b.path == bookmark.path) {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("{} is already bookmarked", bookmark.path.display()),
+            });
+        }
+        if self.bookmarks.len() >= MAX_BOOKMARKS {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Cannot have more than {MAX_BOOKMARKS} bookmarks"),
+            });
+        }
+        self.bookmarks.push(bookmark);
+        Ok(())
+    }
+
+    fn remove_bookmark(&mut self, path: &Path) -> Result<(), Error> {
+        let len = self.bookmarks.len();
+        self.bookmarks.retain(|b| b.path.as_path() != path);
+        if self.bookmarks.len() == len {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("{} is not bookmarked", path.display()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns whether the list changed
+    fn visit_directory(&mut self, path: PathBuf) -> bool {
+        if self.recent_directories.first() == Some(&path) {
+            return false;
+        }
+        self.recent_directories.retain(|p| p != &path);
+        self.recent_directories.insert(0, path);
+        self.recent_directories.truncate(MAX_RECENT_DIRECTORIES);
+        true
+    }
+}
+
+/// Server side store of the file manager bookmarks and recent directories of every user
+pub struct FsLocations {
+    path_to_store: PathBuf,
+    locations: HashMap<UserId, UserFsLocations>,
+}
+
+impl FsLocations {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            locations: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.locations = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read file manager locations file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.locations = serde_json::from_slice(&content).context(format!(
+            "Failed to parse file manager locations file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.locations)
+                .context("Failed to serialize file manager locations")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write file manager locations file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn get(&self, uid: &UserId) -> UserFsLocations {
+        self.locations.get(uid).cloned().unwrap_or_default()
+    }
+
+    pub async fn add_bookmark(
+        &mut self,
+        uid: &UserId,
+        name: Option<String>,
+        path: PathBuf,
+    ) -> Result<(), Error> {
+        let name = name.unwrap_or_else(|| {
+            path.file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string())
+        });
+        self.locations
+            .entry(uid.clone())
+            .or_default()
+            .add_bookmark(FsBookmark {
+                name,
+                path,
+                creation_time: chrono::Utc::now().timestamp(),
+            })?;
+        self.write_to_file().await
+    }
+
+    pub async fn remove_bookmark(&mut self, uid: &UserId, path: &Path) -> Result<(), Error> {
+        self.locations
+            .entry(uid.clone())
+            .or_default()
+            .remove_bookmark(path)?;
+        self.write_to_file().await
+    }
+
+    pub async fn visit_directory(&mut self, uid: &UserId, path: PathBuf) -> Result<(), Error> {
+        if self
+            .locations
+            .entry(uid.clone())
+            .or_default()
+            .visit_directory(path)
+        {
+            self.write_to_file().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn clear_recent_directories(&mut self, uid: &UserId) -> Result<(), Error> {
+        if let Some(locations) = self.locations.get_mut(uid) {
+            locations.recent_directories.clear();
+            self.write_to_file().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{FsBookmark, UserFsLocations, MAX_RECENT_DIRECTORIES};
+
+    #[test]
+    fn test_recent_directories_are_deduplicated_and_capped() {
+        let mut locations = UserFsLocations::default();
+        assert!(locations.visit_directory(PathBuf::from("/a")));
+        assert!(locations.visit_directory(PathBuf::from("/b")));
+        assert!(!locations.visit_directory(PathBuf::from("/b")));
+        assert!(locations.visit_directory(PathBuf::from("/a")));
+        assert_eq!(
+            locations.recent_directories,
+            vec![PathBuf::from("/a"), PathBuf::from("/b")]
+        );
+        for i in 0..MAX_RECENT_DIRECTORIES * 2 {
+            locations.visit_directory(PathBuf::from(format!("/dir{i}")));
+        }
+        assert_eq!(locations.recent_directories.len(), MAX_RECENT_DIRECTORIES);
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let mut locations = UserFsLocations::default();
+        let bookmark = FsBookmark {
+            name: "a".to_string(),
+            path: PathBuf::from("/a"),
+            creation_time: 0,
+        };
+        locations.add_bookmark(bookmark.clone()).unwrap();
+        assert!(locations.add_bookmark(bookmark).is_err());
+        locations.remove_bookmark(Path::new("/a")).unwrap();
+        assert!(locations.remove_bookmark(Path::new("/a")).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Country and region of player ips, looked up in a local GeoIP database so no ip leaves the
+//! host.
+//!
+//! The database is a CSV of ip ranges in the layout of the DB-IP lite databases, either
+//! `start,end,country` or `start,end,continent,country,region,city,...`.
+
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, TS, JsonSchema)]
+#[ts(export)]
+pub struct GeoLocation {
+    /// ISO 3166-1 alpha-2 code
+    pub country: String,
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IpRange {
+    start: u128,
+    end: u128,
+    location: GeoLocation,
+}
+
+/// Ranges of both address families are kept in one list, ipv4 as ipv4-mapped ipv6
+#[derive(Debug, Default)]
+pub struct GeoIpDatabase {
+    ranges: Vec<IpRange>,
+}
+
+fn ip_key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Addresses a database can't know about, e.g. a player on the same network as the host
+fn is_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => {
+            let first_segment = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local fc00::/7 and link local fe80::/10
+                || (first_segment & 0xfe00) == 0xfc00
+                || (first_segment & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+impl GeoIpDatabase {
+    pub fn parse(csv: &str) -> Result<Self, Error> {
+        let mut ranges = Vec::new();
+        for (index, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line
+                .split(',')
+                .map(|field| field.trim().trim_matches('"'))
+                .collect();
+            let (country, region) = match fields.len() {
+                3 => (fields[2], None),
+                n if n >= 5 => (fields[3], Some(fields[4])),
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Line {} of the GeoIP database is malformed", index + 1),
+                    })
+                }
+            };
+            let (start, end) = match (fields[0].parse::<IpAddr>(), fields[1].parse::<IpAddr>()) {
+                (Ok(start), Ok(end)) if start.is_ipv4() == end.is_ipv4() => {
+                    (ip_key(start), ip_key(end))
+                }
+                // a header line
+                _ if index == 0 => continue,
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Line {} of the GeoIP database has no ip range", index + 1),
+                    })
+                }
+            };
+            // "ZZ" marks unassigned ranges
+            if country.is_empty() || country == "ZZ" || start > end {
+                continue;
+            }
+            ranges.push(IpRange {
+                start,
+                end,
+                location: GeoLocation {
+                    country: country.to_uppercase(),
+                    region: region
+                        .filter(|region| !region.is_empty())
+                        .map(str::to_string),
+                },
+            });
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(Self { ranges })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let csv = std::fs::read_to_string(path).context(format!(
+            "Failed to read the GeoIP database at {}",
+            path.display()
+        ))?;
+        Self::parse(&csv)
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoLocation> {
+        // an ipv4 client of a dual stack server shows up as ipv4-mapped ipv6
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+        if is_local(&ip) {
+            return None;
+        }
+        let key = ip_key(ip);
+        let index = self.ranges.partition_point(|range| range.start <= key);
+        let range = self.ranges.get(index.checked_sub(1)?)?;
+        (key <= range.end).then(|| range.location.clone())
+    }
+}
+
+lazy_static! {
+    /// The database players are looked up in, none while GeoIP is off
+    static ref GEOIP_DATABASE: RwLock<Option<Arc<GeoIpDatabase>>> = RwLock::new(None);
+}
+
+/// Load the database at `path` in place of the current one, `None` turns GeoIP off.
+/// Returns the number of ip ranges loaded.
+pub async fn set_database(path: Option<&Path>) -> Result<usize, Error> {
+    let database = match path {
+        Some(path) => {
+            let path = path.to_owned();
+            let database = tokio::task::spawn_blocking(move || GeoIpDatabase::load(&path))
+                .await
+                .context("GeoIP loading task failed")??;
+            Some(Arc::new(database))
+        }
+        None => None,
+    };
+    let len = database
+        .as_ref()
+        .map_or(0, |database| database.ranges.len());
+    *GEOIP_DATABASE.write().unwrap() = database;
+    Ok(len)
+}
+
+/// Where a player connecting from `ip` is, if GeoIP is on and the database knows
+pub fn lookup(ip: IpAddr) -> Option<GeoLocation> {
+    GEOIP_DATABASE.read().unwrap().as_ref()?.lookup(ip)
+}
+
+/// Parse the ip a client connected from as written in the log, `/1.2.3.4:25565` or
+/// `/[2001:db8::1]:25565`
+pub fn parse_client_address(address: &str) -> Option<IpAddr> {
+    let address = address.trim().trim_start_matches('/');
+    if let Some(rest) = address.strip_prefix('[') {
+        return rest
+            .split(']')
+            .next()?
+            .parse::<Ipv6Addr>()
+            .ok()
+            .map(IpAddr::V6);
+    }
+    match address.rsplit_once(':') {
+        Some((ip, _port)) if !ip.contains(':') => ip.parse().ok(),
+        _ => address.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_client_address, GeoIpDatabase, GeoLocation};
+
+    const CSV: &str = "\
+ip_start,ip_end,continent,country,stateprov,city
+1.0.0.0,1.0.0.255,OC,AU,Queensland,South Brisbane
+2.16.0.0,2.16.255.255,EU,FR,Ile-de-France,Paris
+\"8.8.8.0\",\"8.8.8.255\",\"NA\",\"US\",\"California\",\"Mountain View\"
+2001:db8::,2001:db8::ffff,EU,DE,,
+9.0.0.0,9.255.255.255,NA,ZZ,,
+";
+
+    #[test]
+    fn test_lookup() {
+        let database = GeoIpDatabase::parse(CSV).unwrap();
+        assert_eq!(database.ranges.len(), 4);
+        assert_eq!(
+            database.lookup("2.16.4.1".parse().unwrap()),
+            Some(GeoLocation {
+                country: "FR".to_string(),
+                region: Some("Ile-de-France".to_string()),
+            })
+        );
+        assert_eq!(
+            database
+                .lookup("::ffff:8.8.8.8".parse().unwrap())
+                .unwrap()
+                .country,
+            "US"
+        );
+        assert_eq!(
+            database.lookup("2001:db8::42".parse().unwrap()),
+            Some(GeoLocation {
+                country: "DE".to_string(),
+                region: None,
+            })
+        );
+        assert_eq!(database.lookup("1.0.1.0".parse().unwrap()), None);
+        assert_eq!(database.lookup("9.1.1.1".parse().unwrap()), None);
+        assert_eq!(database.lookup("192.168.1.20".parse().unwrap()), None);
+
+        let countries = GeoIpDatabase::parse("1.0.0.0,1.0.0.255,au\n").unwrap();
+        assert_eq!(
+            countries
+                .lookup("1.0.0.1".parse().unwrap())
+                .unwrap()
+                .country,
+            "AU"
+        );
+        assert!(GeoIpDatabase::parse("1.0.0.0,1.0.0.255,AU\n1.0.1.0,AU\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_client_address() {
+        assert_eq!(
+            parse_client_address("/203.0.113.7:53412"),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(
+            parse_client_address("/[2001:db8::1]:53412"),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(parse_client_address("local:E:1a2b3c"), None);
+    }
+}
@@ -1,11 +1,167 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use color_eyre::eyre::Context;
+use chrono_tz::Tz;
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use ts_rs::TS;
 
-use crate::{error::Error, event_broadcaster::EventBroadcaster};
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    schedule::{parse_timezone, DEFAULT_TIMEZONE},
+};
+
+/// bounds of the in-memory buffers, in events
+const MIN_BUFFER_SIZE: usize = 16;
+const MAX_BUFFER_SIZE: usize = 65536;
+/// bounds of the interval instances are monitored at, in seconds
+const MIN_MONITOR_INTERVAL_SECS: u64 = 1;
+const MAX_MONITOR_INTERVAL_SECS: u64 = 30;
+
+/// How much event history is kept, in memory for the dashboard and on disk
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct BufferSettings {
+    /// events other than console output kept in memory, rounded up to a power of two
+    pub event_buffer_size: usize,
+    /// lines of console output kept in memory per instance, rounded up to a power of two
+    pub console_buffer_size: usize,
+    /// days events other than console output are kept on disk, forever if not set
+    pub event_retention_days: Option<u32>,
+    /// days console output is kept on disk, forever if not set
+    pub console_retention_days: Option<u32>,
+    /// monitor reports kept in memory per instance, rounded up to a power of two
+    #[serde(default = "default_monitor_buffer_size")]
+    pub monitor_buffer_size: usize,
+    /// seconds between monitor reports while an instance is running
+    #[serde(default = "default_monitor_interval_secs")]
+    pub monitor_interval_secs: u64,
+    /// events a subscriber can fall behind by before it misses some, only changed on restart
+    #[serde(default = "default_broadcast_capacity")]
+    pub broadcast_capacity: usize,
+}
+
+fn default_monitor_buffer_size() -> usize {
+    64
+}
+
+fn default_monitor_interval_secs() -> u64 {
+    1
+}
+
+fn default_broadcast_capacity() -> usize {
+    512
+}
+
+impl Default for BufferSettings {
+    fn default() -> Self {
+        Self {
+            event_buffer_size: 512,
+            console_buffer_size: 1024,
+            event_retention_days: None,
+            console_retention_days: None,
+            monitor_buffer_size: default_monitor_buffer_size(),
+            monitor_interval_secs: default_monitor_interval_secs(),
+            broadcast_capacity: default_broadcast_capacity(),
+        }
+    }
+}
+
+impl BufferSettings {
+    /// Check the settings and round the buffer sizes up to the power of two ring buffers need
+    pub fn validated(self) -> Result<Self, Error> {
+        for size in [
+            self.event_buffer_size,
+            self.console_buffer_size,
+            self.monitor_buffer_size,
+            self.broadcast_capacity,
+        ] {
+            if !(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE).contains(&size) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Buffer sizes must be between {MIN_BUFFER_SIZE} and {MAX_BUFFER_SIZE}"
+                    ),
+                });
+            }
+        }
+        if !(MIN_MONITOR_INTERVAL_SECS..=MAX_MONITOR_INTERVAL_SECS)
+            .contains(&self.monitor_interval_secs)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Monitor interval must be between {MIN_MONITOR_INTERVAL_SECS} and {MAX_MONITOR_INTERVAL_SECS} seconds"
+                ),
+            });
+        }
+        if self.event_retention_days == Some(0) || self.console_retention_days == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Events must be kept for at least a day"),
+            });
+        }
+        Ok(Self {
+            event_buffer_size: self.event_buffer_size.next_power_of_two(),
+            console_buffer_size: self.console_buffer_size.next_power_of_two(),
+            monitor_buffer_size: self.monitor_buffer_size.next_power_of_two(),
+            ..self
+        })
+    }
+}
+
+/// Limits on how often the api can be called, and on failed logins
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    /// requests a minute from one address, 0 for no limit
+    pub requests_per_minute_per_ip: u32,
+    /// requests a minute by one user, 0 for no limit
+    pub requests_per_minute_per_user: u32,
+    /// requests a minute from one address to the login, token refresh and setup endpoints,
+    /// 0 for no limit
+    pub auth_requests_per_minute: u32,
+    /// failed logins in a row, for a user or from an address, before they are locked out
+    pub lockout_threshold: u32,
+    /// seconds the first lockout lasts, doubled for every failed login after it
+    pub lockout_seconds: u64,
+    /// the longest a lockout lasts, failed logins older than this are forgotten
+    pub max_lockout_seconds: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            requests_per_minute_per_ip: 600,
+            requests_per_minute_per_user: 600,
+            auth_requests_per_minute: 20,
+            lockout_threshold: 5,
+            lockout_seconds: 30,
+            max_lockout_seconds: 3600,
+        }
+    }
+}
+
+impl RateLimitSettings {
+    pub fn validated(self) -> Result<Self, Error> {
+        if self.lockout_threshold == 0 || self.lockout_seconds == 0 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The lockout threshold and duration must be at least 1"),
+            });
+        }
+        if self.max_lockout_seconds < self.lockout_seconds {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The longest lockout can't be shorter than the first one"),
+            });
+        }
+        Ok(self)
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
@@ -13,6 +169,74 @@ pub struct GlobalSettingsData {
     pub core_name: String,
     pub safe_mode: bool,
     pub domain: Option<String>,
+    #[serde(default)]
+    pub buffer_settings: BufferSettings,
+    /// users without a second factor can only set one up
+    #[serde(default)]
+    pub require_two_factor: bool,
+    /// a CSV GeoIP database players are located with, GeoIP is off without one
+    #[serde(default)]
+    pub geoip_database: Option<PathBuf>,
+    #[serde(default)]
+    pub rate_limit_settings: RateLimitSettings,
+    /// opted in to sending anonymous usage reports, never on unless the owner turns it on
+    #[serde(default)]
+    pub telemetry: bool,
+    /// IANA name of the timezone of schedules that don't set their own
+    #[serde(default = "default_timezone")]
+    pub default_timezone: String,
+    /// days deleted files are kept in the trash, forever if `None`
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: Option<u32>,
+    /// instance directories can be mounted over WebDAV under `/api/v1/dav`
+    #[serde(default)]
+    pub webdav: bool,
+}
+
+fn default_timezone() -> String {
+    DEFAULT_TIMEZONE.to_string()
+}
+
+fn default_trash_retention_days() -> Option<u32> {
+    Some(30)
+}
+
+impl GlobalSettingsData {
+    /// The settings saved at `path`, the defaults if there are none yet
+    pub async fn load_from_file(path: &Path) -> Result<Self, Error> {
+        if tokio::fs::OpenOptions::new()
+            .read(true)
+            .create(true)
+            .write(true)
+            .open(path)
+            .await
+            .context(format!(
+                "Failed to open global settings file at {}",
+                path.display()
+            ))?
+            .metadata()
+            .await
+            .context(format!(
+                "Failed to get metadata for global settings file at {}",
+                path.display()
+            ))?
+            .len()
+            == 0
+        {
+            Ok(GlobalSettingsData::default())
+        } else {
+            Ok(
+                serde_json::from_slice(&tokio::fs::read(path).await.context(format!(
+                    "Failed to read global settings file at {}",
+                    path.display()
+                ))?)
+                .context(format!(
+                    "Failed to parse global settings file at {}",
+                    path.display()
+                ))?,
+            )
+        }
+    }
 }
 
 impl Default for GlobalSettingsData {
@@ -21,6 +245,14 @@ impl Default for GlobalSettingsData {
             core_name: format!("{}'s Lodestone Core", whoami::realname()),
             safe_mode: true,
             domain: None,
+            buffer_settings: BufferSettings::default(),
+            require_two_factor: false,
+            geoip_database: None,
+            rate_limit_settings: RateLimitSettings::default(),
+            telemetry: false,
+            default_timezone: default_timezone(),
+            trash_retention_days: default_trash_retention_days(),
+            webdav: false,
         }
     }
 }
@@ -44,43 +276,11 @@ impl GlobalSettings {
         }
     }
     pub async fn load_from_file(&mut self) -> Result<(), Error> {
-        if tokio::fs::OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .open(&self.path_to_global_settings)
-            .await
-            .context(format!(
-                "Failed to open global settings file at {}",
-                self.path_to_global_settings.display()
-            ))?
-            .metadata()
-            .await
-            .context(format!(
-                "Failed to get metadata for global settings file at {}",
-                self.path_to_global_settings.display()
-            ))?
-            .len()
-            == 0
-        {
-            self.global_settings_data = GlobalSettingsData::default();
-        } else {
-            self.global_settings_data = serde_json::from_slice(
-                &tokio::fs::read(&self.path_to_global_settings)
-                    .await
-                    .context(format!(
-                        "Failed to read global settings file at {}",
-                        self.path_to_global_settings.display()
-                    ))?,
-            )
-            .context(format!(
-                "Failed to parse global settings file at {}",
-                self.path_to_global_settings.display()
-            ))?;
-        }
+        self.global_settings_data =
+            GlobalSettingsData::load_from_file(&self.path_to_global_settings).await?;
         Ok(())
     }
-    async fn write_to_file(&self) -> Result<(), Error> {
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
         let mut file = tokio::fs::File::create(&self.path_to_global_settings)
             .await
             .context(format!(
@@ -131,6 +331,22 @@ impl GlobalSettings {
         self.global_settings_data.safe_mode
     }
 
+    pub async fn set_require_two_factor(&mut self, require_two_factor: bool) -> Result<(), Error> {
+        let old_require_two_factor = self.global_settings_data.require_two_factor;
+        self.global_settings_data.require_two_factor = require_two_factor;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.require_two_factor = old_require_two_factor;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn require_two_factor(&self) -> bool {
+        self.global_settings_data.require_two_factor
+    }
+
     pub async fn set_domain(&mut self, domain: Option<String>) -> Result<(), Error> {
         let old_domain = self.global_settings_data.domain.clone();
         self.global_settings_data.domain = domain;
@@ -146,6 +362,139 @@ impl GlobalSettings {
     pub fn domain(&self) -> Option<String> {
         self.global_settings_data.domain.clone()
     }
+
+    pub async fn set_buffer_settings(
+        &mut self,
+        buffer_settings: BufferSettings,
+    ) -> Result<BufferSettings, Error> {
+        let buffer_settings = buffer_settings.validated()?;
+        let old_buffer_settings = self.global_settings_data.buffer_settings;
+        self.global_settings_data.buffer_settings = buffer_settings;
+        match self.write_to_file().await {
+            Ok(_) => Ok(buffer_settings),
+            Err(e) => {
+                self.global_settings_data.buffer_settings = old_buffer_settings;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn buffer_settings(&self) -> BufferSettings {
+        self.global_settings_data.buffer_settings
+    }
+
+    pub async fn set_geoip_database(&mut self, path: Option<PathBuf>) -> Result<(), Error> {
+        let old_path = self.global_settings_data.geoip_database.take();
+        self.global_settings_data.geoip_database = path;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.geoip_database = old_path;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn geoip_database(&self) -> Option<PathBuf> {
+        self.global_settings_data.geoip_database.clone()
+    }
+
+    pub async fn set_rate_limit_settings(
+        &mut self,
+        rate_limit_settings: RateLimitSettings,
+    ) -> Result<RateLimitSettings, Error> {
+        let rate_limit_settings = rate_limit_settings.validated()?;
+        let old_rate_limit_settings = self.global_settings_data.rate_limit_settings;
+        self.global_settings_data.rate_limit_settings = rate_limit_settings;
+        match self.write_to_file().await {
+            Ok(_) => Ok(rate_limit_settings),
+            Err(e) => {
+                self.global_settings_data.rate_limit_settings = old_rate_limit_settings;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn rate_limit_settings(&self) -> RateLimitSettings {
+        self.global_settings_data.rate_limit_settings
+    }
+
+    pub async fn set_telemetry(&mut self, telemetry: bool) -> Result<(), Error> {
+        let old_telemetry = self.global_settings_data.telemetry;
+        self.global_settings_data.telemetry = telemetry;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.telemetry = old_telemetry;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn telemetry(&self) -> bool {
+        self.global_settings_data.telemetry
+    }
+
+    pub async fn set_default_timezone(&mut self, timezone: String) -> Result<(), Error> {
+        parse_timezone(&timezone)?;
+        let old_timezone =
+            std::mem::replace(&mut self.global_settings_data.default_timezone, timezone);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.default_timezone = old_timezone;
+                Err(e)
+            }
+        }
+    }
+
+    /// UTC if the setting was edited into something unknown
+    pub fn default_timezone(&self) -> Tz {
+        parse_timezone(&self.global_settings_data.default_timezone).unwrap_or(Tz::UTC)
+    }
+
+    pub async fn set_trash_retention_days(
+        &mut self,
+        trash_retention_days: Option<u32>,
+    ) -> Result<(), Error> {
+        if trash_retention_days == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Deleted files must be kept for at least a day"),
+            });
+        }
+        let old_trash_retention_days = std::mem::replace(
+            &mut self.global_settings_data.trash_retention_days,
+            trash_retention_days,
+        );
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.trash_retention_days = old_trash_retention_days;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn trash_retention_days(&self) -> Option<u32> {
+        self.global_settings_data.trash_retention_days
+    }
+
+    pub async fn set_webdav(&mut self, webdav: bool) -> Result<(), Error> {
+        let old_webdav = self.global_settings_data.webdav;
+        self.global_settings_data.webdav = webdav;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.webdav = old_webdav;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn webdav(&self) -> bool {
+        self.global_settings_data.webdav
+    }
 }
 
 impl AsRef<GlobalSettingsData> for GlobalSettings {
@@ -181,6 +530,12 @@ mod tests {
         // check that the default values are correct
 
         assert!(global_settings.safe_mode());
+        assert!(!global_settings.telemetry());
+        assert_eq!(global_settings.default_timezone(), chrono_tz::Tz::UTC);
+        assert!(global_settings
+            .set_default_timezone("Not/A_Zone".to_string())
+            .await
+            .is_err());
 
         // set the core name
 
@@ -213,4 +568,47 @@ mod tests {
 
         assert_eq!(global_settings.core_name(), "test_core_name");
     }
+
+    #[test]
+    fn test_buffer_settings() {
+        use super::BufferSettings;
+
+        let settings = BufferSettings {
+            event_buffer_size: 1000,
+            console_buffer_size: 64,
+            event_retention_days: Some(30),
+            console_retention_days: None,
+            monitor_buffer_size: 100,
+            ..BufferSettings::default()
+        }
+        .validated()
+        .unwrap();
+        assert_eq!(settings.event_buffer_size, 1024);
+        assert_eq!(settings.console_buffer_size, 64);
+        assert_eq!(settings.monitor_buffer_size, 128);
+        assert!(BufferSettings {
+            monitor_interval_secs: 0,
+            ..BufferSettings::default()
+        }
+        .validated()
+        .is_err());
+        // settings saved before the monitor and broadcast settings existed
+        let settings: BufferSettings = serde_json::from_str(
+            r#"{"event_buffer_size":512,"console_buffer_size":1024,"event_retention_days":null,"console_retention_days":null}"#,
+        )
+        .unwrap();
+        assert_eq!(settings, BufferSettings::default());
+        assert!(BufferSettings {
+            event_buffer_size: 1,
+            ..BufferSettings::default()
+        }
+        .validated()
+        .is_err());
+        assert!(BufferSettings {
+            console_retention_days: Some(0),
+            ..BufferSettings::default()
+        }
+        .validated()
+        .is_err());
+    }
 }
@@ -0,0 +1,82 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    advisories::{self, AdvisoryDataset, ADVISORY_FEED_URL},
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    handlers::instance_config::get_minecraft_instance,
+    prelude::path_to_stores,
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct RefreshAdvisories {
+    /// where to fetch the dataset from, the official feed if not set
+    pub url: Option<String>,
+}
+
+pub async fn get_advisories(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<AdvisoryDataset>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(advisories::dataset().as_ref().clone()))
+}
+
+/// Fetch the latest advisories, returns the revision in use
+pub async fn refresh_advisories(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(refresh): Json<RefreshAdvisories>,
+) -> Result<Json<u32>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("refresh the advisories")?;
+    let url = refresh.url.as_deref().unwrap_or(ADVISORY_FEED_URL);
+    if !url.starts_with("https://") {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The advisories have to be fetched over https"),
+        });
+    }
+    Ok(Json(
+        advisories::refresh(
+            &reqwest::Client::new(),
+            url,
+            &path_to_stores().join("advisories.json"),
+        )
+        .await?,
+    ))
+}
+
+pub async fn mitigate_advisory(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, advisory_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = get_minecraft_instance(&state, &uuid).await?;
+    advisories::mitigate(&mut instance, &advisory_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_advisories_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/advisories", get(get_advisories))
+        .route("/advisories/refresh", post(refresh_advisories))
+        .route(
+            "/instance/:uuid/advisories/:advisory_id/mitigate",
+            post(mitigate_advisory),
+        )
+        .with_state(state)
+}
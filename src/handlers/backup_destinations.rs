@@ -0,0 +1,254 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    backup::{self, BackupEntry},
+    backup_destinations::{
+        delete_remote_backup, fetch_backup, list_remote_backups, upload_backup, BackupDestination,
+        BackupDestinationConfig,
+    },
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    handlers::instance_backup::restore_backup,
+    jobs::{JobKind, NewJob},
+    prelude::GameInstance,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+pub async fn get_backup_destinations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupDestination>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage backup destinations")?;
+    Ok(Json(state.backup_destinations.lock().await.destinations()))
+}
+
+pub async fn add_backup_destination(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<BackupDestinationConfig>,
+) -> Result<Json<BackupDestination>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage backup destinations")?;
+    Ok(Json(
+        state
+            .backup_destinations
+            .lock()
+            .await
+            .add_destination(config)
+            .await?,
+    ))
+}
+
+pub async fn update_backup_destination(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<BackupDestinationConfig>,
+) -> Result<Json<BackupDestination>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage backup destinations")?;
+    Ok(Json(
+        state
+            .backup_destinations
+            .lock()
+            .await
+            .update_destination(&id, config)
+            .await?,
+    ))
+}
+
+/// Forget a destination, the backups on it are left alone
+pub async fn remove_backup_destination(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage backup destinations")?;
+    state
+        .backup_destinations
+        .lock()
+        .await
+        .remove_destination(&id)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Upload a local backup, the progress is reported through a progression event
+pub async fn upload_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_id, destination_id)): Path<(InstanceUuid, Snowflake, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let backup = backup::get_backup(&uuid, &backup_id).await?;
+    let (http, destination) = {
+        let backup_destinations = state.backup_destinations.lock().await;
+        (
+            backup_destinations.http(),
+            backup_destinations.destination(&destination_id)?,
+        )
+    };
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    tokio::spawn(async move {
+        // the failure is reported by the progression event
+        let _ = upload_backup(
+            &http,
+            &destination,
+            &backup,
+            state.event_broadcaster.clone(),
+            caused_by,
+        )
+        .await;
+    });
+    Ok(Json(()))
+}
+
+pub async fn list_remote_instance_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, destination_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let (http, destination) = {
+        let backup_destinations = state.backup_destinations.lock().await;
+        (
+            backup_destinations.http(),
+            backup_destinations.destination(&destination_id)?,
+        )
+    };
+    Ok(Json(list_remote_backups(&http, &destination, &uuid).await?))
+}
+
+pub async fn delete_remote_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, destination_id, backup_id)): Path<(InstanceUuid, String, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let (http, destination) = {
+        let backup_destinations = state.backup_destinations.lock().await;
+        (
+            backup_destinations.http(),
+            backup_destinations.destination(&destination_id)?,
+        )
+    };
+    delete_remote_backup(&http, &destination, &uuid, &backup_id).await?;
+    Ok(Json(()))
+}
+
+/// Download a backup from a destination if it isn't kept locally anymore, then restore it
+pub async fn restore_remote_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, destination_id, backup_id)): Path<(InstanceUuid, String, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !matches!(instance, GameInstance::MinecraftInstance(_)) {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support restoring backups"),
+        });
+    }
+    let (http, destination) = {
+        let backup_destinations = state.backup_destinations.lock().await;
+        (
+            backup_destinations.http(),
+            backup_destinations.destination(&destination_id)?,
+        )
+    };
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Restore,
+            name: format!("Restoring backup from {}", destination.name),
+            instance_uuid: Some(uuid.clone()),
+            caused_by: caused_by.clone(),
+            cancellable: false,
+        },
+        |_| async move {
+            let event_broadcaster = state.event_broadcaster.clone();
+            let (progression_start_event, event_id) = Event::new_progression_event_start(
+                format!("Downloading backup from {}", destination.name),
+                None,
+                None,
+                caused_by.clone(),
+            );
+            event_broadcaster.send(progression_start_event);
+            match fetch_backup(&http, &destination, &uuid, &backup_id).await {
+                Ok(backup) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Backup downloaded"),
+                        None,
+                    ));
+                    restore_backup(state, instance, backup, caused_by).await
+                }
+                Err(e) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Downloading backup failed: {e}")),
+                        None,
+                    ));
+                    Err(e)
+                }
+            }
+        },
+    );
+    Ok(Json(()))
+}
+
+pub fn get_backup_destination_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/backup_destinations",
+            get(get_backup_destinations).post(add_backup_destination),
+        )
+        .route(
+            "/backup_destinations/:id",
+            put(update_backup_destination).delete(remove_backup_destination),
+        )
+        .route(
+            "/instance/:uuid/backups/:backup_id/upload/:destination_id",
+            post(upload_instance_backup),
+        )
+        .route(
+            "/instance/:uuid/remote_backups/:destination_id",
+            get(list_remote_instance_backups),
+        )
+        .route(
+            "/instance/:uuid/remote_backups/:destination_id/:backup_id",
+            delete(delete_remote_instance_backup),
+        )
+        .route(
+            "/instance/:uuid/remote_backups/:destination_id/:backup_id/restore",
+            post(restore_remote_instance_backup),
+        )
+        .with_state(state)
+}
@@ -1,6 +1,14 @@
+use crate::error::Error;
+use crate::server_probe::{probe, ProbeProtocol, ServerStatus};
 use crate::traits::t_configurable::TConfigurable;
 use crate::{port_manager::PortStatus, AppState};
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
 /// Check the status of a port
 /// Note: this function is not cheap
 pub async fn get_port_status(
@@ -16,7 +24,7 @@ pub async fn is_name_in_use(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(name): Path<String>,
 ) -> Json<bool> {
-    for (_, instance) in state.instances.lock().await.iter() {
+    for instance in state.instances.values() {
         if instance.name().await == name {
             return Json(true);
         }
@@ -24,9 +32,31 @@ pub async fn is_name_in_use(
     Json(false)
 }
 
+#[derive(Deserialize)]
+pub struct ServerStatusQuery {
+    pub host: String,
+    /// defaults to the default port of the protocol
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub protocol: ProbeProtocol,
+}
+
+/// Probe a server on any host the way a game client would, e.g. to check a port forward
+pub async fn get_server_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<ServerStatusQuery>,
+) -> Result<Json<ServerStatus>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    probe(query.protocol, &query.host, query.port)
+        .await
+        .map(Json)
+}
+
 pub fn get_checks_routes(state: AppState) -> Router {
     Router::new()
         .route("/check/port/:port", get(get_port_status))
         .route("/check/name/:name", get(is_name_in_use))
+        .route("/check/server_status", get(get_server_status))
         .with_state(state)
 }
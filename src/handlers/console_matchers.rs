@@ -0,0 +1,108 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    console_matchers::{ConsoleMatcher, ConsoleMatcherConfig},
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+    AppState,
+};
+
+fn check_instance_exists(state: &AppState, uuid: &InstanceUuid) -> Result<(), Error> {
+    if !state.instances.contains_key(uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(())
+}
+
+/// The matchers run over the output of the instance, on top of the built-in ones
+pub async fn get_console_matchers(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ConsoleMatcher>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    check_instance_exists(&state, &uuid)?;
+    Ok(Json(
+        state.console_matchers.lock().await.matchers(&uuid).to_vec(),
+    ))
+}
+
+/// Every line matching the pattern broadcasts a console match event with its capture groups
+pub async fn add_console_matcher(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ConsoleMatcherConfig>,
+) -> Result<Json<ConsoleMatcher>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    check_instance_exists(&state, &uuid)?;
+    Ok(Json(
+        state
+            .console_matchers
+            .lock()
+            .await
+            .add_matcher(&uuid, config)
+            .await?,
+    ))
+}
+
+pub async fn update_console_matcher(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ConsoleMatcherConfig>,
+) -> Result<Json<ConsoleMatcher>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    check_instance_exists(&state, &uuid)?;
+    Ok(Json(
+        state
+            .console_matchers
+            .lock()
+            .await
+            .update_matcher(&uuid, &id, config)
+            .await?,
+    ))
+}
+
+pub async fn remove_console_matcher(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    check_instance_exists(&state, &uuid)?;
+    state
+        .console_matchers
+        .lock()
+        .await
+        .remove_matcher(&uuid, &id)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_console_matcher_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/console_matchers",
+            get(get_console_matchers).post(add_console_matcher),
+        )
+        .route(
+            "/instance/:uuid/console_matchers/:id",
+            put(update_console_matcher).delete(remove_console_matcher),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,113 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction, error::Error,
+    implementations::minecraft::console_profile::ConsoleProfile, prelude::GameInstance,
+    types::InstanceUuid, AppState,
+};
+
+use super::instance_config::get_minecraft_instance;
+
+/// The built-in profiles followed by the ones added by users
+pub async fn get_console_profiles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ConsoleProfile>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.console_profiles.lock().await.list()))
+}
+
+/// Add or replace a profile, instances using it get the new version on their next start
+pub async fn set_console_profile(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(mut profile): Json<ConsoleProfile>,
+) -> Result<Json<ConsoleProfile>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change console profiles")?;
+    profile.name = name;
+    state
+        .console_profiles
+        .lock()
+        .await
+        .set_profile(profile.clone())
+        .await?;
+    for (uuid, instance) in state.instances.snapshot() {
+        if let GameInstance::MinecraftInstance(mut instance) = instance {
+            if instance.console_profile().await.name != profile.name {
+                continue;
+            }
+            if let Err(e) = instance.set_console_profile(profile.clone()).await {
+                error!("Failed to update the console profile of {uuid} : {e}");
+            }
+        }
+    }
+    Ok(Json(profile))
+}
+
+pub async fn delete_console_profile(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change console profiles")?;
+    state
+        .console_profiles
+        .lock()
+        .await
+        .delete_profile(&name)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_console_profile(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ConsoleProfile>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .console_profile()
+            .await,
+    ))
+}
+
+/// Pick the profile of an instance by name, it takes effect on the next start
+pub async fn set_instance_console_profile(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(name): Json<String>,
+) -> Result<Json<ConsoleProfile>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = get_minecraft_instance(&state, &uuid).await?;
+    let profile = state.console_profiles.lock().await.get(&name)?;
+    instance.set_console_profile(profile.clone()).await?;
+    Ok(Json(profile))
+}
+
+pub fn get_console_profile_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/console_profiles", get(get_console_profiles))
+        .route(
+            "/console_profiles/:name",
+            put(set_console_profile).delete(delete_console_profile),
+        )
+        .route(
+            "/instance/:uuid/console_profile",
+            get(get_instance_console_profile).put(set_instance_console_profile),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::Path,
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::{User, UserAction},
+    console_snippets::{ConsoleSnippet, ConsoleSnippetConfig, SnippetScope},
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{t_configurable::TConfigurable, t_server::TServer},
+    types::InstanceUuid,
+    AppState,
+};
+
+/// Fail unless the requester can use `snippet`, other users' snippets are reported as not found
+fn check_snippet_access(requester: &User, snippet: &ConsoleSnippet) -> Result<(), Error> {
+    match &snippet.scope {
+        SnippetScope::User { user_id } if *user_id != requester.uid => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Console snippet not found"),
+        }),
+        SnippetScope::User { .. } => Ok(()),
+        SnippetScope::Instance { instance_uuid } => {
+            requester.try_action(&UserAction::AccessConsole(instance_uuid.clone()))
+        }
+    }
+}
+
+/// The snippets of the requester, usable on every instance they can access the console of
+pub async fn get_user_snippets(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ConsoleSnippet>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.console_snippets.lock().await.in_scope(
+        &SnippetScope::User {
+            user_id: requester.uid,
+        },
+    )))
+}
+
+pub async fn create_user_snippet(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ConsoleSnippetConfig>,
+) -> Result<Json<ConsoleSnippet>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(
+        state
+            .console_snippets
+            .lock()
+            .await
+            .create(
+                SnippetScope::User {
+                    user_id: requester.uid.clone(),
+                },
+                config,
+                requester.uid,
+            )
+            .await?,
+    ))
+}
+
+/// The snippets the requester can send to the instance, the ones shared on the instance first
+pub async fn get_instance_snippets(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ConsoleSnippet>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    let console_snippets = state.console_snippets.lock().await;
+    let mut snippets = console_snippets.in_scope(&SnippetScope::Instance {
+        instance_uuid: uuid,
+    });
+    snippets.extend(console_snippets.in_scope(&SnippetScope::User {
+        user_id: requester.uid,
+    }));
+    Ok(Json(snippets))
+}
+
+/// Share a snippet with everyone who can access the console of the instance
+pub async fn create_instance_snippet(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ConsoleSnippetConfig>,
+) -> Result<Json<ConsoleSnippet>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .console_snippets
+            .lock()
+            .await
+            .create(
+                SnippetScope::Instance {
+                    instance_uuid: uuid,
+                },
+                config,
+                requester.uid,
+            )
+            .await?,
+    ))
+}
+
+pub async fn update_snippet(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(snippet_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ConsoleSnippetConfig>,
+) -> Result<Json<ConsoleSnippet>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut console_snippets = state.console_snippets.lock().await;
+    check_snippet_access(&requester, &console_snippets.get(&snippet_id)?)?;
+    Ok(Json(console_snippets.update(&snippet_id, config).await?))
+}
+
+pub async fn delete_snippet(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(snippet_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut console_snippets = state.console_snippets.lock().await;
+    check_snippet_access(&requester, &console_snippets.get(&snippet_id)?)?;
+    console_snippets.remove(&snippet_id).await.map(|_| Json(()))
+}
+
+/// Fill the placeholders of a snippet with the given values and send it to the console of the
+/// instance, returning the command that was sent
+pub async fn send_snippet(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, snippet_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(values): Json<HashMap<String, String>>,
+) -> Result<Json<String>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let snippet = state.console_snippets.lock().await.get(&snippet_id)?;
+    check_snippet_access(&requester, &snippet)?;
+    if matches!(&snippet.scope, SnippetScope::Instance { instance_uuid } if *instance_uuid != uuid)
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("This snippet belongs to another instance"),
+        });
+    }
+    let command = snippet.render(&values)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    state.command_filters.lock().await.check(
+        &uuid,
+        &instance.name().await,
+        &requester,
+        &command,
+        &state.event_broadcaster,
+    )?;
+    instance.send_command(&command, caused_by.clone()).await?;
+    state.command_history.push(&uuid, &command, caused_by);
+    Ok(Json(command))
+}
+
+pub fn get_console_snippet_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/console/snippets",
+            get(get_user_snippets).post(create_user_snippet),
+        )
+        .route(
+            "/console/snippets/:snippet_id",
+            put(update_snippet).delete(delete_snippet),
+        )
+        .route(
+            "/instance/:uuid/console/snippets",
+            get(get_instance_snippets).post(create_instance_snippet),
+        )
+        .route(
+            "/instance/:uuid/console/snippets/:snippet_id/send",
+            post(send_snippet),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,86 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    implementations::minecraft::{
+        control_channel::ControlChannel, query::QueryStatus,
+        server_properties::ServerPropertiesUpdate,
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::instance_config::get_minecraft_instance;
+
+pub async fn get_control_channel(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ControlChannel>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .control_channel()
+            .await,
+    ))
+}
+
+/// Pick whether commands and player lists go through the console or RCON and Query
+pub async fn set_control_channel(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(control_channel): Json<ControlChannel>,
+) -> Result<Json<ServerPropertiesUpdate>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .set_control_channel(control_channel)
+            .await?,
+    ))
+}
+
+pub async fn get_query_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<QueryStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .query_status()
+            .await?,
+    ))
+}
+
+/// Asked over RCON, `None` if the server doesn't report it
+pub async fn get_tps(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<f64>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid).await?.tps().await?,
+    ))
+}
+
+pub fn get_control_channel_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/control_channel",
+            get(get_control_channel).put(set_control_channel),
+        )
+        .route("/instance/:uuid/query", get(get_query_status))
+        .route("/instance/:uuid/tps", get(get_tps))
+        .with_state(state)
+}
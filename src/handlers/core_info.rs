@@ -1,6 +1,6 @@
 use std::env;
 
-use crate::{prelude::VERSION, AppState};
+use crate::{implementations::minecraft::util::jre_platforms, prelude::VERSION, AppState};
 use axum::{routing::get, Json, Router};
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, DiskExt, System, SystemExt};
@@ -11,6 +11,8 @@ pub struct CoreInfo {
     is_setup: bool,
     os: String,
     arch: String,
+    /// whether JREs can be downloaded for the host, instances use the java of the host otherwise
+    can_download_jre: bool,
     cpu: String,
     cpu_count: u32,
     total_ram: u64,
@@ -30,6 +32,7 @@ pub async fn get_core_info(
         is_setup: state.first_time_setup_key.lock().await.is_none(),
         os: env::consts::OS.to_string(),
         arch: env::consts::ARCH.to_string(),
+        can_download_jre: !jre_platforms().is_empty(),
         cpu: {
             let cpu_str = sys
                 .cpus()
@@ -0,0 +1,151 @@
+use std::env;
+
+use axum::{extract::Path, routing::get, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde_json::json;
+
+use crate::{
+    auth::user::UserAction,
+    diagnostics::{self, BundleContents, BundledInstance},
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    prelude::{lodestone_path, GameInstance, VERSION},
+    traits::{t_configurable::TConfigurable, TInstance},
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+    AppState,
+};
+
+async fn bundled_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    instance: &GameInstance,
+) -> BundledInstance {
+    let name = instance.name().await;
+    BundledInstance {
+        dir_name: format!(
+            "{}-{}",
+            sanitize_filename::sanitize(&name),
+            uuid.no_prefix()
+        ),
+        path: instance.path().await,
+        info: serde_json::to_value(instance.get_instance_info().await).unwrap_or_default(),
+        monitor_history: state
+            .monitor_buffer
+            .lock()
+            .await
+            .get(uuid)
+            .map(|buffer| buffer.iter().cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Write the bundle on a blocking thread and hand out a key to download it from `/file/:key`
+async fn publish_bundle(
+    state: &AppState,
+    contents: BundleContents,
+    name: String,
+    caused_by: CausedBy,
+) -> Result<String, Error> {
+    let path = tokio::task::spawn_blocking(move || diagnostics::write_bundle(&contents, &name))
+        .await
+        .context("Failed to write diagnostic bundle")??;
+    let key = rand_alphanumeric(32);
+    state
+        .download_urls
+        .lock()
+        .await
+        .insert(key.clone(), path.clone());
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Download,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(key)
+}
+
+fn core_summary(state: &AppState) -> serde_json::Value {
+    json!({
+        "version": VERSION.with(|v| v.to_string()),
+        "os": env::consts::OS,
+        "arch": env::consts::ARCH,
+        "uuid": state.uuid,
+        "up_since": state.up_since,
+        "instance_count": state.instances.len(),
+        "generated_at": chrono::Utc::now().timestamp(),
+    })
+}
+
+/// A bundle of the whole core, owner only as it holds every instance
+pub async fn get_core_diagnostic_bundle(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("generate a diagnostic bundle of the core")?;
+    let mut instances = Vec::new();
+    for (uuid, instance) in state.instances.snapshot() {
+        instances.push(bundled_instance(&state, &uuid, &instance).await);
+    }
+    let global_settings =
+        serde_json::to_value(state.global_settings.lock().await.as_ref()).unwrap_or_default();
+    let contents = BundleContents {
+        core: core_summary(&state),
+        global_settings,
+        core_log_dir: Some(lodestone_path().join("log")),
+        instances,
+    };
+    publish_bundle(
+        &state,
+        contents,
+        "lodestone_core".to_string(),
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    )
+    .await
+}
+
+pub async fn get_instance_diagnostic_bundle(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let bundled = bundled_instance(&state, &uuid, &instance).await;
+    let name = bundled.dir_name.clone();
+    let contents = BundleContents {
+        core: core_summary(&state),
+        // the settings of the core aren't the business of every user of an instance
+        global_settings: json!({}),
+        core_log_dir: None,
+        instances: vec![bundled],
+    };
+    publish_bundle(
+        &state,
+        contents,
+        name,
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    )
+    .await
+}
+
+pub fn get_diagnostics_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/diagnostics/bundle", get(get_core_diagnostic_bundle))
+        .route(
+            "/instance/:uuid/diagnostics/bundle",
+            get(get_instance_diagnostic_bundle),
+        )
+        .with_state(state)
+}
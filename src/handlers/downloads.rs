@@ -0,0 +1,81 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::User,
+    download_manager::{DownloadInfo, DownloadManager},
+    error::{Error, ErrorKind},
+    types::Snowflake,
+    AppState,
+};
+
+/// Downloads are made for every user and instance, so only the owner manages them
+async fn owner(state: &AppState, token: &str) -> Result<User, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(token)?;
+    requester.try_owner("manage downloads")?;
+    Ok(requester)
+}
+
+pub async fn get_downloads(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<DownloadInfo>>, Error> {
+    owner(&state, &token).await?;
+    Ok(Json(DownloadManager::global().list()))
+}
+
+pub async fn get_download(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<DownloadInfo>, Error> {
+    owner(&state, &token).await?;
+    DownloadManager::global()
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Download not found"),
+        })
+}
+
+pub async fn pause_download(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<DownloadInfo>, Error> {
+    owner(&state, &token).await?;
+    DownloadManager::global().pause(&id).map(Json)
+}
+
+pub async fn resume_download(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<DownloadInfo>, Error> {
+    owner(&state, &token).await?;
+    DownloadManager::global().resume(&id).map(Json)
+}
+
+pub async fn cancel_download(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<DownloadInfo>, Error> {
+    owner(&state, &token).await?;
+    DownloadManager::global().cancel(&id).map(Json)
+}
+
+pub fn get_download_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/downloads", get(get_downloads))
+        .route("/downloads/:id", get(get_download).delete(cancel_download))
+        .route("/downloads/:id/pause", put(pause_download))
+        .route("/downloads/:id/resume", put(resume_download))
+        .with_state(state)
+}
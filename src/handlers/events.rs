@@ -1,7 +1,10 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
+    extract::{
+        ws::{Message, WebSocket},
+        Path, Query, WebSocketUpgrade,
+    },
     response::Response,
     routing::get,
     Json, Router,
@@ -11,23 +14,32 @@ use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 use futures::{SinkExt, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
+use std::collections::HashSet;
 use tracing::{debug, error};
 
 use crate::output_types::ClientEvent;
-use crate::types::InstanceUuid;
+use crate::prelude::LODESTONE_EPOCH_MIL;
+use crate::types::{InstanceUuid, Snowflake, TimeRange};
 use crate::{
     auth::{user::UsersManager, user_id::UserId},
-    db::read::search_events,
+    db::read::{
+        read_console_page, read_events_page, search_audit_events, search_events,
+        search_instance_timeline, search_security_events, EventPageFilter,
+    },
     error::{Error, ErrorKind},
-    events::EventQuery,
+    events::{EventLevel, EventQuery, EventType},
 };
 
 use crate::{
-    events::{Event, EventInner, UserEventInner},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, UserEventInner},
     AppState,
 };
-use serde::Deserialize;
-use tokio::sync::{broadcast::Receiver, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    mpsc::{self, error::TrySendError},
+    RwLock,
+};
 use ts_rs::TS;
 
 use super::util::parse_bearer_token;
@@ -35,6 +47,8 @@ use super::util::parse_bearer_token;
 #[derive(Deserialize, Clone, Debug, TS)]
 pub struct EventQueryWrapper {
     filter: String,
+    /// only the events after this snowflake, for clients catching up after reconnecting
+    since_snowflake: Option<Snowflake>,
 }
 
 pub async fn get_event_buffer(
@@ -42,6 +56,7 @@ pub async fn get_event_buffer(
     AuthBearer(token): AuthBearer,
     query: Query<EventQueryWrapper>,
 ) -> Result<Json<Vec<Event>>, Error> {
+    let since_snowflake = query.since_snowflake;
     // deserialize query
     let query: EventQuery = serde_json::from_str(&query.filter).map_err(|e| {
         error!("Error deserializing event query: {}", e);
@@ -66,7 +81,11 @@ pub async fn get_event_buffer(
             .await
             .iter()
             .filter(|event| {
-                query.filter(ClientEvent::from(*event)) && requester.can_view_event(*event)
+                since_snowflake.map_or(true, |since| event.snowflake > since)
+                    && query.filter(ClientEvent::from(*event))
+                    && requester.can_view_event(*event)
+                    && (!query.notifications_only
+                        || requester.notification_preferences.wants(event))
             })
             .cloned()
             .collect(),
@@ -133,9 +152,245 @@ pub async fn get_console_buffer(
     ))
 }
 
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct HistoryQuery {
+    /// unix timestamp in milliseconds
+    start: Option<i64>,
+    /// unix timestamp in milliseconds
+    end: Option<i64>,
+    limit: Option<u32>,
+}
+
+impl HistoryQuery {
+    fn time_range(&self) -> Result<TimeRange, Error> {
+        let time_range = TimeRange {
+            start: self.start.unwrap_or(LODESTONE_EPOCH_MIL.with(|p| *p)),
+            end: self
+                .end
+                .unwrap_or_else(|| chrono::Utc::now().timestamp_millis()),
+        };
+        if time_range.start > time_range.end {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Start of the time range must be before its end"),
+            });
+        }
+        Ok(time_range)
+    }
+
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(1024).min(10000)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct EventHistoryQuery {
+    instance_uuid: Option<InstanceUuid>,
+    event_type: Option<EventType>,
+    /// the least severe level included, e.g. `Warning` for warnings and errors
+    level: Option<EventLevel>,
+    /// unix timestamp in milliseconds
+    start: Option<i64>,
+    /// unix timestamp in milliseconds
+    end: Option<i64>,
+    /// `next_cursor` of the previous page, to page back to older events
+    cursor: Option<Snowflake>,
+    /// snowflake of the last event the client has seen, to catch up on the events after it
+    since_snowflake: Option<Snowflake>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct EventPage {
+    /// oldest first
+    events: Vec<Event>,
+    /// pass back as `since_snowflake` when catching up, as `cursor` otherwise, to get the next page.
+    /// `None` if the page is empty
+    next_cursor: Option<Snowflake>,
+    /// whether there are more events in the direction of the next page
+    has_more: bool,
+}
+
+/// Events persisted in the database, filtered and paged
+///
+/// Without `since_snowflake` pages go back from the newest event, or from `cursor`. With it pages
+/// go forward from `since_snowflake`, so a client that reconnects only fetches what it missed.
+pub async fn get_event_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<EventHistoryQuery>,
+) -> Result<Json<EventPage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let time_range = HistoryQuery {
+        start: query.start,
+        end: query.end,
+        limit: query.limit,
+    };
+    let filter = EventPageFilter {
+        instance_id: query.instance_uuid,
+        event_type: query.event_type,
+        min_level: query.level,
+        time_range: Some(time_range.time_range()?),
+        before: query.cursor,
+        after: query.since_snowflake,
+    };
+    let (events, has_more) =
+        read_events_page(&state.sqlite_pool, &filter, time_range.limit()).await?;
+    // taken before filtering out what the requester can't see, so the next page doesn't start
+    // over from an event they already skipped
+    let next_cursor = match filter.after {
+        Some(_) => events.last(),
+        None => events.first(),
+    }
+    .map(|event| event.snowflake);
+    Ok(Json(EventPage {
+        events: events
+            .into_iter()
+            .map(Event::from)
+            .filter(|event| requester.can_view_event(event))
+            .collect(),
+        next_cursor,
+        has_more,
+    }))
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleHistoryQuery {
+    /// snowflake of the newest line to page back from, the latest line if omitted
+    anchor: Option<Snowflake>,
+    /// number of lines to skip back from the anchor
+    offset: Option<u32>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleHistoryPage {
+    /// oldest first
+    events: Vec<Event>,
+    /// pass back with a larger offset to load older lines,
+    /// `None` if there is no console output yet
+    anchor: Option<Snowflake>,
+    /// whether there are lines older than this page
+    has_more: bool,
+}
+
+/// Console output persisted in the database, including output from before the last restart
+///
+/// Pages are counted back from a stable anchor, so new output does not shift the offsets
+pub async fn get_console_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleHistoryQuery>,
+) -> Result<Json<ConsoleHistoryPage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let instance_id = if uuid == "all" { None } else { Some(&uuid) };
+    let offset = query.offset.unwrap_or(0);
+    let (events, has_more) = read_console_page(
+        &state.sqlite_pool,
+        instance_id,
+        query.anchor,
+        offset,
+        query.limit.unwrap_or(1024).min(10000),
+    )
+    .await?;
+    // the first page anchors at its newest line
+    let anchor = match query.anchor {
+        Some(anchor) => Some(anchor),
+        None if offset == 0 => events.last().map(|event| event.snowflake),
+        None => None,
+    };
+    Ok(Json(ConsoleHistoryPage {
+        events: events
+            .into_iter()
+            .map(Event::from)
+            .filter(|event| requester.can_view_event(event))
+            .collect(),
+        anchor,
+        has_more,
+    }))
+}
+
+/// Lifecycle, player, backup and other non console events of an instance, oldest first
+pub async fn get_instance_timeline(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<Event>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let time_range = query.time_range()?;
+    Ok(Json(
+        search_instance_timeline(&state.sqlite_pool, &uuid, Some(&time_range), query.limit())
+            .await?
+            .into_iter()
+            .map(Event::from)
+            .filter(|event| requester.can_view_event(event))
+            .collect(),
+    ))
+}
+
+/// The security feed: logins, failed logins, new sessions and permission changes, oldest first
+pub async fn get_security_events(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<Event>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("view security events")?;
+    let time_range = query.time_range()?;
+    Ok(Json(
+        search_security_events(&state.sqlite_pool, Some(&time_range), query.limit())
+            .await?
+            .into_iter()
+            .map(Event::from)
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct AuditQuery {
+    user_id: Option<UserId>,
+    instance_uuid: Option<InstanceUuid>,
+}
+
+/// Who called which mutating endpoint and how it went, oldest first
+pub async fn get_audit_events(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<HistoryQuery>,
+    Query(audit_query): Query<AuditQuery>,
+) -> Result<Json<Vec<Event>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("view the audit log")?;
+    let time_range = query.time_range()?;
+    Ok(Json(
+        search_audit_events(
+            &state.sqlite_pool,
+            audit_query.user_id.as_ref(),
+            audit_query.instance_uuid.as_ref(),
+            Some(&time_range),
+            query.limit(),
+        )
+        .await?
+        .into_iter()
+        .map(Event::from)
+        .collect(),
+    ))
+}
+
 #[derive(Deserialize)]
 pub struct WebsocketQuery {
     token: String,
+    /// send the buffered console output before streaming new output
+    #[serde(default)]
+    backfill: bool,
 }
 
 pub async fn event_stream(
@@ -191,7 +446,10 @@ async fn event_stream_ws(
                         break;
                     }
                 };
-                if query.filter(ClientEvent::from(event.clone())) && user.can_view_event(&event) {
+                if query.filter(ClientEvent::from(event.clone()))
+                    && user.can_view_event(&event)
+                    && (!query.notifications_only || user.notification_preferences.wants(&event))
+                {
                     if let Err(e) = sender.send(axum::extract::ws::Message::Text(serde_json::to_string(&event).unwrap())).await {
                         error!("Error sending event to websocket: {}", e);
                         break;
@@ -223,43 +481,128 @@ pub async fn console_stream(
             source: eyre!("Token error"),
         })?;
     drop(users_manager);
+    // subscribe before taking the backfill so no event falls in between
     let event_receiver = state.event_broadcaster.subscribe();
+    let backfill = if query.backfill {
+        let console_out_buffer = state.console_out_buffer.lock().await;
+        let mut backfill: Vec<Event> = console_out_buffer
+            .iter()
+            .filter(|(instance_uuid, _)| **instance_uuid == uuid || uuid == "all")
+            .flat_map(|(_, buffer)| buffer.iter())
+            .filter(|event| user.can_view_event(*event))
+            .cloned()
+            .collect();
+        backfill.sort_by_key(|event| event.snowflake);
+        backfill
+    } else {
+        Vec::new()
+    };
 
     Ok(ws.on_upgrade(move |socket| {
-        console_stream_ws(socket, event_receiver, user.uid, uuid, state.users_manager)
+        console_stream_ws(
+            socket,
+            event_receiver,
+            backfill,
+            user.uid,
+            uuid,
+            state.users_manager,
+        )
     }))
 }
 
+/// number of messages queued for a console websocket before console output is dropped
+const CONSOLE_STREAM_QUEUE_SIZE: usize = 256;
+
+fn skipped_console_output_notice(instance_event: &InstanceEvent) -> Event {
+    Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: instance_event.instance_uuid.clone(),
+            instance_name: instance_event.instance_name.clone(),
+            instance_event_inner: InstanceEventInner::SystemMessage {
+                message: "Some console output was skipped because the connection is too slow"
+                    .to_string(),
+            },
+        }),
+        details: "".to_string(),
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    }
+}
+
+/// Streams console output to the client.
+///
+/// The websocket is written to from a separate task through a bounded queue, so a slow client
+/// can't fall behind the broadcast channel. Output that doesn't fit in the queue is dropped and
+/// the client is notified that it missed some output.
 async fn console_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
+    backfill: Vec<Event>,
     uid: UserId,
     uuid: InstanceUuid,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
     let (mut sender, mut receiver) = stream.split();
+    let (queue_tx, mut queue_rx) = mpsc::channel::<Message>(CONSOLE_STREAM_QUEUE_SIZE);
+    let backfilled: HashSet<Snowflake> = backfill.iter().map(|event| event.snowflake).collect();
+    let writer = tokio::spawn(async move {
+        for event in backfill {
+            if let Err(e) = sender
+                .send(Message::Text(serde_json::to_string(&event).unwrap()))
+                .await
+            {
+                error!("Failed to send event: {}", e);
+                return;
+            }
+        }
+        while let Some(msg) = queue_rx.recv().await {
+            if let Err(e) = sender.send(msg).await {
+                debug!("Websocket disconnected: {}", e);
+                return;
+            }
+        }
+    });
+    let mut skipped_output = false;
     loop {
         tokio::select! {
-            Ok(event) = event_receiver.recv() => {
+            result = event_receiver.recv() => {
+                let event = match result {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        skipped_output = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
                 match &event.event_inner {
                     EventInner::InstanceEvent(instance_event) => {
+                        if !event.is_event_console_message()
+                            || !(instance_event.instance_uuid == uuid || uuid == "all")
+                            || backfilled.contains(&event.snowflake)
+                        {
+                            continue;
+                        }
                         let user = match users_manager.read().await.get_user(&uid) {
                             Some(user) => user,
                             None => break,
                         };
-                        if event.is_event_console_message() && (instance_event.instance_uuid == uuid || uuid == "all")
-                            && user.can_view_event(&event)
-                        {
-                            if let Err(e) = sender
-                                .send(axum::extract::ws::Message::Text(
-                                    serde_json::to_string(&event).unwrap(),
-                                ))
-                                .await
+                        if !user.can_view_event(&event) {
+                            continue;
+                        }
+                        if skipped_output {
+                            let notice = skipped_console_output_notice(instance_event);
+                            if queue_tx
+                                .try_send(Message::Text(serde_json::to_string(&notice).unwrap()))
+                                .is_ok()
                             {
-                                error!("Failed to send event: {}", e);
-                                break;
+                                skipped_output = false;
                             }
                         }
+                        match queue_tx.try_send(Message::Text(serde_json::to_string(&event).unwrap())) {
+                            Ok(_) => {}
+                            Err(TrySendError::Full(_)) => skipped_output = true,
+                            Err(TrySendError::Closed(_)) => break,
+                        }
                     }
                     EventInner::UserEvent(user_event) => {
                         match user_event.user_event_inner {
@@ -274,16 +617,24 @@ async fn console_stream_ws(
                     EventInner::MacroEvent(_) => continue,
                     EventInner::ProgressionEvent(_) => continue,
                     EventInner::FSEvent(_) => continue,
+                    EventInner::SystemEvent(_) => continue,
+                    EventInner::SecurityEvent(_) => continue,
+                    EventInner::AuditEvent(_) => continue,
+                    EventInner::JobEvent(_) => continue,
                 }
             }
             Some(Ok(ws_msg)) = receiver.next() => {
-                match sender.send(ws_msg).await {
+                match queue_tx.try_send(ws_msg) {
                     Ok(_) => debug!("Replied to ping"),
-                    Err(_) => break,
+                    // a pong can be skipped, the client will ping again
+                    Err(TrySendError::Full(_)) => {}
+                    Err(TrySendError::Closed(_)) => break,
                 };
             }
+            _ = queue_tx.closed() => break,
         }
     }
+    writer.abort();
 }
 
 pub fn get_events_routes(state: AppState) -> Router {
@@ -291,7 +642,12 @@ pub fn get_events_routes(state: AppState) -> Router {
         .route("/events/:uuid/stream", get(event_stream))
         .route("/events/:uuid/buffer", get(get_event_buffer))
         .route("/events/search", get(get_event_search))
+        .route("/events/history", get(get_event_history))
+        .route("/events/security", get(get_security_events))
+        .route("/audit", get(get_audit_events))
         .route("/instance/:uuid/console/stream", get(console_stream))
         .route("/instance/:uuid/console/buffer", get(get_console_buffer))
+        .route("/instance/:uuid/console/history", get(get_console_history))
+        .route("/instance/:uuid/timeline", get(get_instance_timeline))
         .with_state(state)
 }
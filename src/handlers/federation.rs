@@ -0,0 +1,80 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    error::Error,
+    federation::{peer_instances, NodeInstances, PeerConfig, PeerInfo},
+    traits::{InstanceInfo, TInstance},
+    AppState,
+};
+
+pub async fn get_peers(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PeerInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage peers")?;
+    Ok(Json(state.peers.lock().await.peers()))
+}
+
+/// Register a peer with the url of its core and a token of its owner
+pub async fn add_peer(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<PeerConfig>,
+) -> Result<Json<PeerInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage peers")?;
+    Ok(Json(
+        state
+            .peers
+            .lock()
+            .await
+            .add_peer(config, &state.uuid)
+            .await?,
+    ))
+}
+
+pub async fn remove_peer(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage peers")?;
+    state.peers.lock().await.remove_peer(&id).await?;
+    Ok(Json(()))
+}
+
+/// The instances of this core and of every peer, grouped by core
+pub async fn get_federated_instances(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<NodeInstances>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage peers")?;
+    let mut instances: Vec<InstanceInfo> = Vec::new();
+    for instance in state.instances.values() {
+        instances.push(instance.get_instance_info().await);
+    }
+    instances.sort_by(InstanceInfo::list_order);
+    let mut nodes = vec![NodeInstances {
+        peer: None,
+        instances,
+        error: None,
+    }];
+    nodes.extend(peer_instances(&state.peers).await);
+    Ok(Json(nodes))
+}
+
+pub fn get_federation_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/peers", get(get_peers).post(add_peer))
+        .route("/peers/instances", get(get_federated_instances))
+        .route("/peers/:id", delete(remove_peer))
+        .with_state(state)
+}
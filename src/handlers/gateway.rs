@@ -1,10 +1,21 @@
-use axum::{extract::Path, routing::put, Json, Router};
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
 
 use crate::{
+    auth::user::UserAction,
     error::{Error, ErrorKind},
+    public_address::{instance_address, map_instance_port, unmap_instance_port, InstanceAddress},
+    traits::{
+        t_configurable::{PublicAddress, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
     AppState,
 };
 
@@ -32,8 +43,106 @@ pub async fn open_port(
     Ok(Json(state.port_manager.lock().await.open_port(port).await?))
 }
 
+pub async fn get_public_ip(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<String>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.public_ip.get().await?.to_string()))
+}
+
+/// The address players outside the local network connect to
+pub async fn get_instance_address(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceAddress>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let public_address = instance.public_address().await;
+    let public_ip = match public_address.hostname {
+        Some(_) => None,
+        None => Some(state.public_ip.get().await?),
+    };
+    instance_address(
+        &public_address,
+        public_ip,
+        &instance.game_type().await,
+        instance.port().await,
+    )
+    .map(Json)
+    .ok_or_else(|| Error {
+        kind: ErrorKind::Internal,
+        source: eyre!("Could not tell the public address of the instance"),
+    })
+}
+
+pub async fn get_instance_public_address(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PublicAddress>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .public_address()
+            .await,
+    ))
+}
+
+/// Set the hostname and UPnP mapping of an instance, the mapping of a running instance is
+/// updated right away
+pub async fn set_instance_public_address(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(address): Json<PublicAddress>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let old = instance.public_address().await;
+    if address.upnp != old.upnp {
+        requester.try_owner("change port forwarding")?;
+    }
+    if address.upnp == old.upnp || instance.state().await.is_stopped() {
+        instance.set_public_address(address).await?;
+        return Ok(Json(()));
+    }
+    let upnp = address.upnp;
+    if !upnp {
+        // unmapped while the instance still says it wants the mapping
+        unmap_instance_port(&state.instances, &uuid).await;
+    }
+    instance.set_public_address(address).await?;
+    if upnp {
+        map_instance_port(&state.instances, &uuid).await;
+    }
+    Ok(Json(()))
+}
+
 pub fn get_gateway_routes(state: AppState) -> Router {
     Router::new()
         .route("/gateway/open_port/:port", put(open_port))
+        .route("/gateway/public_ip", get(get_public_ip))
+        .route("/instance/:uuid/address", get(get_instance_address))
+        .route(
+            "/instance/:uuid/public_address",
+            get(get_instance_public_address).put(set_instance_public_address),
+        )
         .with_state(state)
 }
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use axum::{
     body::{Bytes, StreamBody},
-    extract::{Multipart, Path},
+    extract::{Multipart, Path, Query},
     http,
     routing::{delete, get, put},
     Json, Router,
@@ -16,12 +16,16 @@ use serde::{Deserialize, Serialize};
 
 use tokio::io::AsyncWriteExt;
 use tokio_util::io::ReaderStream;
+use tracing::error;
 use ts_rs::TS;
 
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
     events::{new_fs_event, CausedBy, Event, FSOperation, FSTarget},
+    file_search::{search_files, FileSearchQuery, FileSearchResult},
+    fs_locations::UserFsLocations,
+    trash::Trash,
     util::{list_dir, rand_alphanumeric},
     AppState,
 };
@@ -114,10 +118,6 @@ async fn list_files(
     requester.try_action(&UserAction::ReadGlobalFile)?;
 
     let path = PathBuf::from(absolute_path);
-    let caused_by = CausedBy::User {
-        user_id: requester.uid,
-        user_name: requester.username,
-    };
     let ret: Vec<FileEntry> = list_dir(&path, None)
         .await?
         .iter()
@@ -126,6 +126,18 @@ async fn list_files(
             r
         })
         .collect();
+    // failing to record the visit shouldn't fail the listing
+    let _ = state
+        .fs_locations
+        .lock()
+        .await
+        .visit_directory(&requester.uid, path.clone())
+        .await
+        .map_err(|e| error!("Failed to record recent directory: {e}"));
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Read,
         FSTarget::Directory(path),
@@ -170,6 +182,42 @@ async fn read_file(
     Ok(ret)
 }
 
+/// Find files under a directory by name, and optionally by content
+async fn search(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(base64_absolute_path): Path<String>,
+    Query(query): Query<FileSearchQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<FileSearchResult>, Error> {
+    let absolute_path = decode_base64(&base64_absolute_path)?;
+    let requester = state
+        .users_manager
+        .read()
+        .await
+        .try_auth(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+
+    let path = PathBuf::from(absolute_path);
+    let search_path = path.clone();
+    let result = tokio::task::spawn_blocking(move || search_files(&search_path, &query))
+        .await
+        .context("Search task panicked")??;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::Directory(path),
+        caused_by,
+    ));
+    Ok(Json(result))
+}
+
 async fn write_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(base64_absolute_path): Path<String>,
@@ -302,14 +350,18 @@ async fn remove_file(
 
     let path = PathBuf::from(absolute_path);
 
-    tokio::fs::remove_file(&path)
-        .await
-        .context(format!("Failed to remove file {}", path.display()))?;
-
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
     };
+    let trash = Trash::global();
+    if trash.contains(&path) {
+        tokio::fs::remove_file(&path)
+            .await
+            .context(format!("Failed to remove file {}", path.display()))?;
+    } else {
+        trash.move_to_trash(&path, caused_by.clone()).await?;
+    }
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
         FSTarget::File(path),
@@ -337,14 +389,18 @@ async fn remove_dir(
 
     let path = PathBuf::from(absolute_path);
 
-    tokio::fs::remove_dir_all(&path)
-        .await
-        .context(format!("Failed to remove directory {}", path.display()))?;
-
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
     };
+    let trash = Trash::global();
+    if trash.contains(&path) {
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .context(format!("Failed to remove directory {}", path.display()))?;
+    } else {
+        trash.move_to_trash(&path, caused_by.clone()).await?;
+    }
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
         FSTarget::Directory(path),
@@ -606,10 +662,81 @@ async fn download(
     }
 }
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewBookmarkRequest {
+    pub name: Option<String>,
+}
+
+async fn get_fs_locations(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UserFsLocations>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    Ok(Json(state.fs_locations.lock().await.get(&requester.uid)))
+}
+
+async fn clear_recent_directories(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .fs_locations
+        .lock()
+        .await
+        .clear_recent_directories(&requester.uid)
+        .await?;
+    Ok(Json(()))
+}
+
+async fn add_bookmark(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(base64_absolute_path): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(new_bookmark_request): Json<NewBookmarkRequest>,
+) -> Result<Json<()>, Error> {
+    let absolute_path = decode_base64(&base64_absolute_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    let path = PathBuf::from(absolute_path);
+    if !path.is_absolute() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Bookmarked path must be absolute"),
+        });
+    }
+    state
+        .fs_locations
+        .lock()
+        .await
+        .add_bookmark(&requester.uid, new_bookmark_request.name, path)
+        .await?;
+    Ok(Json(()))
+}
+
+async fn remove_bookmark(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(base64_absolute_path): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let absolute_path = decode_base64(&base64_absolute_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .fs_locations
+        .lock()
+        .await
+        .remove_bookmark(&requester.uid, std::path::Path::new(&absolute_path))
+        .await?;
+    Ok(Json(()))
+}
+
 pub fn get_global_fs_routes(state: AppState) -> Router {
     Router::new()
         .route("/fs/:base64_absolute_path/ls", get(list_files))
         .route("/fs/:base64_absolute_path/read", get(read_file))
+        .route("/fs/:base64_absolute_path/search", get(search))
         .route("/fs/:base64_absolute_path/write", put(write_file))
         .route("/fs/:base64_absolute_path/mkdir", put(make_directory))
         .route(
@@ -622,5 +749,11 @@ pub fn get_global_fs_routes(state: AppState) -> Router {
         .route("/fs/:base64_absolute_path/download", get(download_file))
         .route("/fs/:base64_absolute_path/upload", put(upload_file))
         .route("/file/:key", get(download))
+        .route("/fs/locations", get(get_fs_locations))
+        .route("/fs/locations/recent", delete(clear_recent_directories))
+        .route(
+            "/fs/:base64_absolute_path/bookmark",
+            put(add_bookmark).delete(remove_bookmark),
+        )
         .with_state(state)
 }
@@ -4,8 +4,17 @@ use axum::{
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use std::path::PathBuf;
+use tracing::warn;
 
-use crate::{error::ErrorKind, AppState, Error, GlobalSettingsData};
+use crate::{
+    error::ErrorKind,
+    geoip,
+    global_settings::{BufferSettings, RateLimitSettings},
+    resize_event_buffers,
+    telemetry::{self, TelemetryPreview, TELEMETRY_URL},
+    AppState, Error, GlobalSettingsData,
+};
 
 pub async fn get_core_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -80,6 +89,35 @@ pub async fn change_core_safe_mode(
     Ok(())
 }
 
+/// Require every user to set up a second factor, users without one can only set one up. The
+/// owner needs one first so they can't lock themselves out.
+pub async fn change_require_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(require_two_factor): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the two factor policy")?;
+    if require_two_factor && !requester.has_two_factor() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Set up two factor authentication before requiring it"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_require_two_factor(require_two_factor)
+        .await?;
+    state
+        .users_manager
+        .write()
+        .await
+        .set_two_factor_required(require_two_factor);
+    Ok(())
+}
+
 pub async fn change_domain(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -111,11 +149,174 @@ pub async fn change_domain(
     Ok(())
 }
 
+/// Change the sizes of the event buffers and how long events are kept. The buffers are resized
+/// right away, keeping their most recent events, events past the new retention are deleted within
+/// the hour. The monitor interval applies from the next report, the broadcast capacity from the
+/// next start of the core.
+pub async fn change_buffer_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(buffer_settings): Json<BufferSettings>,
+) -> Result<Json<BufferSettings>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the event buffer settings")?;
+    let buffer_settings = state
+        .global_settings
+        .lock()
+        .await
+        .set_buffer_settings(buffer_settings)
+        .await?;
+    resize_event_buffers(&state, buffer_settings).await;
+    Ok(Json(buffer_settings))
+}
+
+/// Locate joining players with the CSV GeoIP database at a path on the host, `null` turns GeoIP
+/// off. The database is loaded before the setting is saved, so a bad file is refused. Returns the
+/// number of ip ranges loaded.
+pub async fn change_geoip_database(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(path): Json<Option<PathBuf>>,
+) -> Result<Json<usize>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the GeoIP database")?;
+    let mut global_settings = state.global_settings.lock().await;
+    let ranges = geoip::set_database(path.as_deref()).await?;
+    if let Err(e) = global_settings.set_geoip_database(path).await {
+        // keep using the database the setting points to
+        if let Err(e) = geoip::set_database(global_settings.geoip_database().as_deref()).await {
+            warn!("Failed to reload the GeoIP database : {e}");
+        }
+        return Err(e);
+    }
+    Ok(Json(ranges))
+}
+
+/// Change the api rate limits and the lockout after failed logins, they apply right away
+pub async fn change_rate_limit_settings(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(rate_limit_settings): Json<RateLimitSettings>,
+) -> Result<Json<RateLimitSettings>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the rate limits")?;
+    let rate_limit_settings = state
+        .global_settings
+        .lock()
+        .await
+        .set_rate_limit_settings(rate_limit_settings)
+        .await?;
+    state
+        .rate_limiter
+        .lock()
+        .await
+        .set_settings(rate_limit_settings);
+    Ok(Json(rate_limit_settings))
+}
+
+/// Opt in or out of the daily anonymous usage report
+pub async fn change_telemetry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(telemetry): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the telemetry setting")?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_telemetry(telemetry)
+        .await
+}
+
+/// The timezone of schedules that don't set their own, an IANA name like `Europe/Berlin`
+pub async fn change_default_timezone(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(timezone): Json<String>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the default timezone")?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_default_timezone(timezone)
+        .await
+}
+
+/// Days deleted files are kept in the trash before being purged, `null` to keep them until
+/// purged by hand
+pub async fn change_trash_retention_days(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(trash_retention_days): Json<Option<u32>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the trash retention")?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_trash_retention_days(trash_retention_days)
+        .await
+}
+
+/// Serve instance directories over WebDAV, off by default
+pub async fn change_webdav(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(webdav): Json<bool>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the WebDAV setting")?;
+    state.global_settings.lock().await.set_webdav(webdav).await
+}
+
+/// The report exactly as it would be sent, whether or not the core is opted in
+pub async fn preview_telemetry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TelemetryPreview>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("preview the telemetry report")?;
+    Ok(Json(TelemetryPreview {
+        enabled: state.global_settings.lock().await.telemetry(),
+        url: TELEMETRY_URL.to_string(),
+        report: telemetry::current_report(&state.instances).await,
+    }))
+}
+
 pub fn get_global_settings_routes(state: AppState) -> Router {
     Router::new()
         .route("/global_settings", get(get_core_settings))
         .route("/global_settings/name", put(change_core_name))
         .route("/global_settings/safe_mode", put(change_core_safe_mode))
         .route("/global_settings/domain", put(change_domain))
+        .route(
+            "/global_settings/require_two_factor",
+            put(change_require_two_factor),
+        )
+        .route("/global_settings/buffers", put(change_buffer_settings))
+        .route(
+            "/global_settings/geoip_database",
+            put(change_geoip_database),
+        )
+        .route(
+            "/global_settings/rate_limits",
+            put(change_rate_limit_settings),
+        )
+        .route("/global_settings/telemetry", put(change_telemetry))
+        .route("/global_settings/webdav", put(change_webdav))
+        .route("/global_settings/telemetry/preview", get(preview_telemetry))
+        .route(
+            "/global_settings/default_timezone",
+            put(change_default_timezone),
+        )
+        .route(
+            "/global_settings/trash_retention_days",
+            put(change_trash_retention_days),
+        )
         .with_state(state)
 }
@@ -0,0 +1,65 @@
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    handlers::overview::InstanceStateCounts, health::SelfCheck, prelude::VERSION,
+    traits::t_server::TServer, AppState,
+};
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct HealthReport {
+    pub ready: bool,
+    pub version: String,
+    pub up_since: i64,
+    pub is_setup: bool,
+    pub event_loop_alive: bool,
+    pub checks: Vec<SelfCheck>,
+    pub instances: InstanceStateCounts,
+}
+
+async fn health_report(state: &AppState) -> HealthReport {
+    let mut instances = InstanceStateCounts::default();
+    for (_, instance) in state.instances.snapshot() {
+        instances.count(instance.state().await);
+    }
+    HealthReport {
+        ready: state.health_checks.is_ready(),
+        version: VERSION.with(|v| v.to_string()),
+        up_since: state.up_since,
+        is_setup: state.first_time_setup_key.lock().await.is_none(),
+        event_loop_alive: state.health_checks.event_loop_alive(),
+        checks: state.health_checks.snapshot(),
+        instances,
+    }
+}
+
+/// Liveness, answers as long as the API is served. No auth is required so container health checks
+/// can call it
+pub async fn get_health(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Json<HealthReport> {
+    Json(health_report(&state).await)
+}
+
+/// Readiness, 503 while a self check fails. A core that is not set up yet is ready, as it needs
+/// traffic to be set up
+pub async fn get_ready(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> (StatusCode, Json<HealthReport>) {
+    let report = health_report(&state).await;
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+pub fn get_health_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(get_health))
+        .route("/ready", get(get_ready))
+        .with_state(state)
+}
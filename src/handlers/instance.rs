@@ -1,26 +1,48 @@
+use std::path::PathBuf;
+
 use axum::routing::{delete, get, post};
 use axum::Router;
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
+    Json,
+};
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::{eyre, Context};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sysinfo::SystemExt;
+use tokio::io::AsyncWriteExt;
 use tracing::error;
+use ts_rs::TS;
 
-use crate::auth::user::UserAction;
+use crate::auth::user::{User, UserAction};
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue};
 
 use crate::implementations::generic;
 use crate::traits::t_configurable::GameType;
 
-
-use crate::implementations::minecraft::MinecraftInstance;
-use crate::prelude::{path_to_instances, GameInstance};
-use crate::traits::t_configurable::manifest::SetupValue;
+use crate::implementations::minecraft::import::{detect_server, DetectedServer};
+use crate::implementations::minecraft::modpack::{resolve_modpack_url, Modpack, ModpackSource};
+use crate::implementations::minecraft::shared_config::{
+    SharedInstanceConfig, SHARED_CONFIG_FORMAT,
+};
+use crate::implementations::minecraft::{
+    first_run::eula_not_accepted, memory, Flavour, FlavourKind, MinecraftInstance, SetupConfig,
+};
+use crate::implementations::process::{self, ProcessSetupConfig};
+use crate::instance_summaries::{InstanceSummaryPage, InstanceSummaryQuery};
+use crate::jobs::{JobKind, NewJob};
+use crate::network_isolation::remove_isolation;
+use crate::port_remap::{remap_ports, PortChange};
+use crate::prelude::{path_to_tmp, GameInstance};
+use crate::reservation::{InstanceReservation, DEFAULT_MEMORY_HEADROOM};
+use crate::traits::t_configurable::manifest::{ConfigurableValue, SetupValue};
 use crate::traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance};
 
 use crate::types::{DotLodestoneConfig, InstanceUuid};
+use crate::usage_history::remove_instance_usage;
+use crate::util::{download_file, unzip_file_async, UnzipOption};
 use crate::{implementations::minecraft, traits::t_server::State, AppState};
 
 use super::instance_setup_configs::HandlerGameType;
@@ -32,18 +54,30 @@ pub async fn get_instance_list(
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     let mut list_of_configs: Vec<InstanceInfo> = Vec::new();
 
-    let instances = state.instances.lock().await;
-    for instance in instances.values() {
+    for instance in state.instances.values() {
         if requester.can_perform_action(&UserAction::ViewInstance(instance.uuid().await)) {
             list_of_configs.push(instance.get_instance_info().await);
         }
     }
 
-    list_of_configs.sort_by(|a, b| a.creation_time.cmp(&b.creation_time));
+    list_of_configs.sort_by(InstanceInfo::list_order);
 
     Ok(Json(list_of_configs))
 }
 
+/// A page of the instance list, read from the summaries kept from events rather than from each
+/// instance
+pub async fn get_instance_summaries(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<InstanceSummaryQuery>,
+) -> Result<Json<InstanceSummaryPage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.instance_summaries.page(&query, |uuid| {
+        requester.can_perform_action(&UserAction::ViewInstance(uuid.clone()))
+    })))
+}
+
 pub async fn get_instance_info(
     Path(uuid): Path<InstanceUuid>,
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -51,9 +85,7 @@ pub async fn get_instance_info(
 ) -> Result<Json<InstanceInfo>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
 
-    let instances = state.instances.lock().await;
-
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -62,35 +94,257 @@ pub async fn get_instance_info(
     Ok(Json(instance.get_instance_info().await))
 }
 
+/// A new uuid whose first 8 characters, used in the instance directory name, are not taken,
+/// owned by `requester`. Fails if it would put them over their quota
+async fn unique_instance_uuid(state: &AppState, requester: &User) -> Result<InstanceUuid, Error> {
+    let mut instance_uuid = InstanceUuid::default();
+
+    for uuid in state.instances.uuids() {
+        if let Some(uuid) = uuid.as_ref().get(0..8) {
+            if uuid == &instance_uuid.no_prefix()[0..8] {
+                instance_uuid = InstanceUuid::default();
+            }
+        }
+    }
+    state
+        .user_quotas
+        .lock()
+        .await
+        .reserve_instance(
+            requester,
+            &instance_uuid,
+            &state.instances,
+            &state.directory_sizes,
+        )
+        .await?;
+    Ok(instance_uuid)
+}
+
+/// Where to create a new instance, the default storage root if not given
+#[derive(Deserialize)]
+pub struct StorageRootQuery {
+    storage_root: Option<String>,
+}
+
+impl StorageRootQuery {
+    pub(crate) async fn instances_dir(&self, state: &AppState) -> Result<PathBuf, Error> {
+        state
+            .storage_roots
+            .lock()
+            .await
+            .instances_dir(self.storage_root.as_deref())
+    }
+}
+
 pub async fn create_minecraft_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
     Path(game_type): Path<HandlerGameType>,
+    Query(query): Query<StorageRootQuery>,
     Json(manifest_value): Json<SetupValue>,
 ) -> Result<Json<InstanceUuid>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::CreateInstance)?;
-    let mut perm = requester.permissions;
+    let instances_dir = query.instances_dir(&state).await?;
+    setup_minecraft_instance(state, requester, game_type, manifest_value, instances_dir)
+        .await
+        .map(Json)
+}
 
-    let mut instance_uuid = InstanceUuid::default();
+/// Validate the setup value and set the instance up in `instances_dir` in the background, the
+/// instance is added once the setup succeeds
+pub(crate) async fn setup_minecraft_instance(
+    state: AppState,
+    requester: User,
+    game_type: HandlerGameType,
+    manifest_value: SetupValue,
+    instances_dir: PathBuf,
+) -> Result<InstanceUuid, Error> {
+    let flavour = game_type.try_into()?;
 
-    for uuid in state.instances.lock().await.keys() {
-        if let Some(uuid) = uuid.as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
-            }
+    let setup_config = MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
+
+    spawn_minecraft_setup(
+        state,
+        requester,
+        game_type,
+        setup_config,
+        None,
+        instances_dir,
+    )
+    .await
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SetupValidationRequest {
+    pub game_type: HandlerGameType,
+    pub setup_value: SetupValue,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum SetupProblemSeverity {
+    /// the setup would fail or the server would not start
+    Error,
+    /// the setup would succeed but likely isn't what was meant
+    Warning,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SetupProblem {
+    /// the setting the problem is with, `None` if it is with the config as a whole
+    pub setting_id: Option<String>,
+    pub severity: SetupProblemSeverity,
+    pub message: String,
+}
+
+impl SetupProblem {
+    fn error(setting_id: Option<&str>, message: String) -> Self {
+        Self {
+            setting_id: setting_id.map(str::to_string),
+            severity: SetupProblemSeverity::Error,
+            message,
         }
     }
 
-    let instance_uuid = instance_uuid;
+    fn warning(setting_id: Option<&str>, message: String) -> Self {
+        Self {
+            setting_id: setting_id.map(str::to_string),
+            severity: SetupProblemSeverity::Warning,
+            message,
+        }
+    }
+}
 
-    let flavour = game_type.try_into()?;
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SetupValidation {
+    /// whether creating the instance would go through, warnings don't prevent it
+    pub valid: bool,
+    pub problems: Vec<SetupProblem>,
+}
 
-    let setup_config = MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
+/// Report what would go wrong creating an instance from `setup_value`, without creating anything
+async fn validate_minecraft_setup(
+    state: &AppState,
+    game_type: HandlerGameType,
+    setup_value: SetupValue,
+) -> Vec<SetupProblem> {
+    let mut problems = Vec::new();
 
-    let setup_path = path_to_instances().join(format!(
+    let name = setup_value.name.trim().to_lowercase();
+    for instance in state.instances.values() {
+        if instance.name().await.trim().to_lowercase() == name {
+            problems.push(SetupProblem::warning(
+                None,
+                format!("An instance named {} already exists", setup_value.name),
+            ));
+            break;
+        }
+    }
+
+    let setup_config = match FlavourKind::try_from(game_type) {
+        Ok(flavour) => MinecraftInstance::construct_setup_config(setup_value, flavour).await,
+        Err(e) => Err(e),
+    };
+    // the checks below need a well formed config, which is the first problem to fix otherwise
+    let setup_config = match setup_config {
+        Ok(setup_config) => setup_config,
+        Err(e) => {
+            problems.push(SetupProblem::error(None, e.source.to_string()));
+            return problems;
+        }
+    };
+
+    let port_status = state
+        .port_manager
+        .lock()
+        .await
+        .port_status(setup_config.port);
+    if port_status.is_allocated {
+        problems.push(SetupProblem::error(
+            Some("port"),
+            format!(
+                "Port {} is already allocated to another instance",
+                setup_config.port
+            ),
+        ));
+    } else if port_status.is_in_use {
+        problems.push(SetupProblem::warning(
+            Some("port"),
+            format!(
+                "Port {} is in use by another program, the server won't start until it is freed",
+                setup_config.port
+            ),
+        ));
+    }
+
+    let mut sys = sysinfo::System::new();
+    sys.refresh_memory();
+    let usable_memory = (sys.total_memory() / 1024 / 1024).saturating_sub(DEFAULT_MEMORY_HEADROOM);
+    let available_memory = sys.available_memory() / 1024 / 1024;
+    let needed_memory =
+        InstanceReservation::jvm_memory(setup_config.max_ram.unwrap_or_default(), None);
+    if needed_memory > usable_memory {
+        problems.push(SetupProblem::error(
+            Some("max_ram"),
+            format!(
+                "The server needs about {needed_memory} MB with this maximum RAM, the host can spare {usable_memory} MB"
+            ),
+        ));
+    } else if needed_memory > available_memory {
+        problems.push(SetupProblem::warning(
+            Some("max_ram"),
+            format!(
+                "The server needs about {needed_memory} MB with this maximum RAM, only {available_memory} MB is free right now"
+            ),
+        ));
+    }
+
+    problems
+}
+
+pub async fn validate_instance_setup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<SetupValidationRequest>,
+) -> Result<Json<SetupValidation>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let problems = validate_minecraft_setup(&state, request.game_type, request.setup_value).await;
+    Ok(Json(SetupValidation {
+        valid: problems
+            .iter()
+            .all(|problem| problem.severity != SetupProblemSeverity::Error),
+        problems,
+    }))
+}
+
+/// What is installed on top of a new server before its first run
+enum SetupContent {
+    Modpack(Modpack),
+    SharedConfig(SharedInstanceConfig),
+}
+
+/// Set a validated setup config up in the background, installing `content` once the server is
+/// in place
+async fn spawn_minecraft_setup(
+    state: AppState,
+    requester: User,
+    game_type: HandlerGameType,
+    setup_config: SetupConfig,
+    content: Option<SetupContent>,
+    instances_dir: PathBuf,
+) -> Result<InstanceUuid, Error> {
+    let instance_uuid = unique_instance_uuid(&state, &requester).await?;
+
+    let mut perm = requester.permissions;
+
+    let setup_path = instances_dir.join(format!(
         "{}-{}",
-        setup_config.name,
+        sanitize_filename::sanitize(&setup_config.name),
         &instance_uuid.no_prefix()[0..8]
     ));
 
@@ -109,6 +363,16 @@ pub async fn create_minecraft_instance(
     .await
     .context("Failed to write .lodestone_config file")?;
 
+    let job = state.jobs.start(NewJob {
+        kind: JobKind::Setup,
+        name: format!("Setting up {}", setup_config.name),
+        instance_uuid: Some(instance_uuid.clone()),
+        caused_by: CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        },
+        cancellable: false,
+    });
     tokio::task::spawn({
         let uuid = instance_uuid.clone();
         let instance_name = setup_config.name.clone();
@@ -133,21 +397,66 @@ pub async fn create_minecraft_instance(
                 caused_by,
             );
             event_broadcaster.send(progression_start_event);
-            let minecraft_instance = match minecraft::MinecraftInstance::new(
-                setup_config.clone(),
-                dot_lodestone_config,
-                setup_path.clone(),
-                &event_id,
-                state.event_broadcaster.clone(),
-                state.macro_executor.clone(),
-            )
-            .await
-            {
+            let result: Result<MinecraftInstance, Error> = async {
+                let mut instance = minecraft::MinecraftInstance::new(
+                    setup_config.clone(),
+                    dot_lodestone_config,
+                    setup_path.clone(),
+                    &event_id,
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await?;
+                match &content {
+                    Some(SetupContent::Modpack(modpack)) => {
+                        modpack
+                            .install(&setup_path, &|path| {
+                                event_broadcaster.send(Event::new_progression_event_update(
+                                    &event_id,
+                                    format!(
+                                        "Installing {} {}, {path}",
+                                        modpack.name, modpack.version
+                                    ),
+                                    0.0,
+                                ));
+                            })
+                            .await?;
+                    }
+                    Some(SetupContent::SharedConfig(config)) => {
+                        let missing = instance
+                            .apply_shared_config(config, &|file_name| {
+                                event_broadcaster.send(Event::new_progression_event_update(
+                                    &event_id,
+                                    format!("Installing {file_name}"),
+                                    0.0,
+                                ));
+                            })
+                            .await?;
+                        if !missing.is_empty() {
+                            event_broadcaster.send(Event::new_progression_event_update(
+                                &event_id,
+                                format!("Not on Modrinth, add by hand: {}", missing.join(", ")),
+                                0.0,
+                            ));
+                        }
+                    }
+                    None => {}
+                }
+                event_broadcaster.send(Event::new_progression_event_update(
+                    &event_id,
+                    "Starting the server once to generate its files",
+                    0.0,
+                ));
+                instance.first_run().await?;
+                Ok(instance)
+            }
+            .await;
+            let minecraft_instance = match result {
                 Ok(v) => {
                     event_broadcaster.send(Event::new_progression_event_end(
                         event_id,
                         true,
-                        Some("Instance created successfully"),
+                        Some("Instance set up and ready to start"),
                         Some(ProgressionEndValue::InstanceCreation(
                             v.get_instance_info().await,
                         )),
@@ -165,6 +474,7 @@ pub async fn create_minecraft_instance(
                         .await
                         .context("Failed to remove directory after instance creation failed")
                         .unwrap();
+                    job.finish::<()>(&Err(e));
                     return;
                 }
             };
@@ -188,12 +498,11 @@ pub async fn create_minecraft_instance(
                 });
             state
                 .instances
-                .lock()
-                .await
                 .insert(uuid.clone(), minecraft_instance.into());
+            job.finish::<()>(&Ok(()));
         }
     });
-    Ok(Json(instance_uuid))
+    Ok(instance_uuid)
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -205,22 +514,15 @@ pub struct GenericSetupConfig {
 pub async fn create_generic_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
     Json(setup_config): Json<GenericSetupConfig>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::CreateInstance)?;
-    let mut instance_uuid = InstanceUuid::default();
-    for uuid in state.instances.lock().await.keys() {
-        if let Some(uuid) = uuid.as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
-            }
-        }
-    }
+    let instances_dir = query.instances_dir(&state).await?;
+    let instance_uuid = unique_instance_uuid(&state, &requester).await?;
 
-    let instance_uuid = instance_uuid;
-
-    let setup_path = path_to_instances().join(format!(
+    let setup_path = instances_dir.join(format!(
         "{}-{}",
         setup_config.setup_value.name,
         &instance_uuid.no_prefix()[0..8]
@@ -253,12 +555,80 @@ pub async fn create_generic_instance(
 
     state
         .instances
-        .lock()
-        .await
         .insert(instance_uuid.clone(), instance.into());
     Ok(Json(()))
 }
 
+pub async fn create_process_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(setup_config): Json<ProcessSetupConfig>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instances_dir = query.instances_dir(&state).await?;
+    let instance_uuid = unique_instance_uuid(&state, &requester).await?;
+    let mut perm = requester.permissions;
+
+    let setup_path = instances_dir.join(format!(
+        "{}-{}",
+        sanitize_filename::sanitize(&setup_config.name),
+        &instance_uuid.no_prefix()[0..8]
+    ));
+
+    tokio::fs::create_dir_all(&setup_path)
+        .await
+        .context("Failed to create instance directory")?;
+
+    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), GameType::Process);
+
+    tokio::fs::write(
+        setup_path.join(".lodestone_config"),
+        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    )
+    .await
+    .context("Failed to write .lodestone_config file")?;
+
+    let port = setup_config.port;
+    let instance = match process::ProcessInstance::new(
+        setup_config,
+        dot_lodestone_config,
+        setup_path.clone(),
+        state.event_broadcaster.clone(),
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            let _ = crate::util::fs::remove_dir_all(setup_path).await;
+            return Err(e);
+        }
+    };
+
+    state.port_manager.lock().await.add_port(port);
+    perm.can_start_instance.insert(instance_uuid.clone());
+    perm.can_stop_instance.insert(instance_uuid.clone());
+    perm.can_view_instance.insert(instance_uuid.clone());
+    perm.can_read_instance_file.insert(instance_uuid.clone());
+    perm.can_write_instance_file.insert(instance_uuid.clone());
+    // ignore errors since we don't care if the permissions update fails
+    let _ = state
+        .users_manager
+        .write()
+        .await
+        .update_permissions(&requester.uid, perm, CausedBy::System)
+        .await
+        .map_err(|e| {
+            error!("Failed to update permissions: {:?}", e);
+            e
+        });
+    state
+        .instances
+        .insert(instance_uuid.clone(), instance.into());
+    Ok(Json(instance_uuid))
+}
+
 pub async fn delete_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -266,14 +636,13 @@ pub async fn delete_instance(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::DeleteInstance)?;
-    let mut instances = state.instances.lock().await;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    if let Some(instance) = instances.remove(&uuid) {
-        if !(instance.state().await == State::Stopped) {
-            instances.insert(uuid.clone(), instance);
+    if let Some(instance) = state.instances.remove(&uuid) {
+        if !instance.state().await.is_idle() {
+            state.instances.insert(uuid.clone(), instance);
             Err(Error {
                 kind: ErrorKind::BadRequest,
                 source: eyre!("Instance must be stopped before deletion"),
@@ -296,7 +665,7 @@ pub async fn delete_instance(
                     Some("Failed to delete .lodestone_config. Instance not deleted"),
                     None,
                 ));
-                instances.insert(uuid.clone(), instance);
+                state.instances.insert(uuid.clone(), instance);
                 return Err::<Json<()>, std::io::Error>(e)
                     .context("Failed to delete .lodestone_config file. Instance not deleted")
                     .map_err(Into::into);
@@ -307,12 +676,128 @@ pub async fn delete_instance(
                 .lock()
                 .await
                 .deallocate(instance.port().await);
+            if let Err(e) = state
+                .macro_triggers
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the macro triggers of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .command_queues
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the command queue of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .command_filters
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the command filter of {uuid} : {e}");
+            }
+            state.command_sequences.remove_instance(&uuid);
+            if let Err(e) = state
+                .console_snippets
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the console snippets of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .console_matchers
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the console matchers of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .instance_webhooks
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the webhooks of {uuid} : {e}");
+            }
+            let mut network_policies = state.network_policies.lock().await;
+            if network_policies.get(&uuid).is_some() {
+                if let Err(e) = remove_isolation(&uuid).await {
+                    error!("Failed to remove the firewall rules of {uuid} : {e}");
+                }
+                if let Err(e) = network_policies.remove_instance(&uuid).await {
+                    error!("Failed to remove the network policy of {uuid} : {e}");
+                }
+            }
+            drop(network_policies);
+            if let Err(e) = state
+                .suspicious_activity_policies
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the suspicious activity policy of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .instance_syncs
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the sync of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .instance_groups
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove {uuid} from its groups : {e}");
+            }
+            if let Err(e) = state
+                .start_dependencies
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the start dependencies of {uuid} : {e}");
+            }
+            if let Err(e) = state.user_quotas.lock().await.remove_instance(&uuid).await {
+                error!("Failed to remove the owner of {uuid} : {e}");
+            }
+            if let Err(e) = state
+                .player_database
+                .lock()
+                .await
+                .remove_instance(&uuid)
+                .await
+            {
+                error!("Failed to remove the players of {uuid} : {e}");
+            }
+            state.command_history.remove(&uuid);
+            if let Err(e) = remove_instance_usage(&state.sqlite_pool, &uuid).await {
+                error!("Failed to remove the usage history of {uuid} : {e}");
+            }
             let instance_path = instance.path().await;
             // if instance is generic
             if let GameInstance::GenericInstance(i) = instance {
                 i.destruct().await;
             };
-            drop(instances);
             let res = crate::util::fs::remove_dir_all(instance_path).await;
             match &res {
                 Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
@@ -344,15 +829,706 @@ pub async fn delete_instance(
     }
 }
 
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct CloneInstanceConfig {
+    pub name: String,
+}
+
+/// Copy the files of a stopped instance into a new instance with its own uuid and port
+/// Move the ports in the config files of a copied server off the port it was set up with
+async fn remap_ports_blocking(
+    path_to_instance: PathBuf,
+    old_port: u32,
+    new_port: u32,
+) -> Result<Vec<PortChange>, Error> {
+    tokio::task::spawn_blocking(move || remap_ports(&path_to_instance, old_port, new_port))
+        .await
+        .context("Port remap task panicked")?
+}
+
+/// The message ending a clone or an import, listing the ports rewritten to avoid a collision
+fn creation_message(message: &str, port_changes: &[PortChange]) -> String {
+    if port_changes.is_empty() {
+        return message.to_string();
+    }
+    format!(
+        "{message}, the port was taken so these were changed: {}",
+        port_changes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+pub async fn clone_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(config): Json<CloneInstanceConfig>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instances_dir = query.instances_dir(&state).await?;
+    let source = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !source.state().await.is_idle() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before cloning"),
+        });
+    }
+    if config.name.trim().is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Name cannot be empty"),
+        });
+    }
+    let source_path = source.path().await;
+    let dot_lodestone_config: DotLodestoneConfig = serde_json::from_str(
+        &crate::util::fs::read_to_string(source_path.join(".lodestone_config")).await?,
+    )
+    .context("Failed to parse .lodestone_config file")?;
+    let (game_type, flavour) = match &source {
+        GameInstance::MinecraftInstance(i) => {
+            ("minecraft".to_string(), i.flavour().await.to_string())
+        }
+        GameInstance::GenericInstance(_) => ("generic".to_string(), "generic".to_string()),
+        GameInstance::ProcessInstance(_) => ("process".to_string(), "process".to_string()),
+        #[cfg(any(test, feature = "test-harness"))]
+        GameInstance::MockInstance(_) => ("mock".to_string(), "mock".to_string()),
+    };
+
+    let instance_uuid = unique_instance_uuid(&state, &requester).await?;
+    let setup_path = instances_dir.join(format!(
+        "{}-{}",
+        sanitize_filename::sanitize(&config.name),
+        &instance_uuid.no_prefix()[0..8]
+    ));
+    let source_port = source.port().await;
+    let port = state.port_manager.lock().await.allocate(source_port);
+    let mut perm = requester.permissions.clone();
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+
+    tokio::task::spawn({
+        let uuid = instance_uuid.clone();
+        let event_broadcaster = state.event_broadcaster.clone();
+        async move {
+            let (progression_start_event, event_id) = Event::new_progression_event_start(
+                format!("Cloning {} to {}", source.name().await, config.name),
+                Some(10.0),
+                Some(ProgressionStartValue::InstanceCreation {
+                    instance_uuid: uuid.clone(),
+                    instance_name: config.name.clone(),
+                    port,
+                    flavour,
+                    game_type,
+                }),
+                caused_by,
+            );
+            event_broadcaster.send(progression_start_event);
+            let result: Result<(GameInstance, Vec<PortChange>), Error> = async {
+                crate::util::fs::create_dir_all(&setup_path).await?;
+                tokio::task::spawn_blocking({
+                    let setup_path = setup_path.clone();
+                    move || {
+                        let mut options = fs_extra::dir::CopyOptions::new();
+                        options.content_only = true;
+                        fs_extra::dir::copy(source_path, setup_path, &options)
+                    }
+                })
+                .await
+                .context("Copy task panicked")?
+                .context("Failed to copy instance files")?;
+                let dot_lodestone_config =
+                    DotLodestoneConfig::new(uuid.clone(), *dot_lodestone_config.game_type());
+                crate::util::fs::write_all(
+                    setup_path.join(".lodestone_config"),
+                    serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+                )
+                .await?;
+                let port_changes =
+                    remap_ports_blocking(setup_path.clone(), source_port, port).await?;
+                let mut instance = crate::restore_instance(
+                    &setup_path,
+                    &dot_lodestone_config,
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await?;
+                instance.set_name(config.name.clone()).await?;
+                match instance.set_port(port).await {
+                    Err(e) if !matches!(e.kind, ErrorKind::UnsupportedOperation) => return Err(e),
+                    _ => {}
+                }
+                Ok((instance, port_changes))
+            }
+            .await;
+            let instance = match result {
+                Ok((instance, port_changes)) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some(&creation_message(
+                            "Instance cloned successfully",
+                            &port_changes,
+                        )),
+                        Some(ProgressionEndValue::InstanceCreation(
+                            instance.get_instance_info().await,
+                        )),
+                    ));
+                    instance
+                }
+                Err(e) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Instance cloning failed: {e}")),
+                        None,
+                    ));
+                    state.port_manager.lock().await.deallocate(port);
+                    if let Err(e) = crate::util::fs::remove_dir_all(&setup_path).await {
+                        error!("Failed to remove directory after instance cloning failed: {e}");
+                    }
+                    return;
+                }
+            };
+            perm.can_start_instance.insert(uuid.clone());
+            perm.can_stop_instance.insert(uuid.clone());
+            perm.can_view_instance.insert(uuid.clone());
+            perm.can_read_instance_file.insert(uuid.clone());
+            perm.can_write_instance_file.insert(uuid.clone());
+            // ignore errors since we don't care if the permissions update fails
+            let _ = state
+                .users_manager
+                .write()
+                .await
+                .update_permissions(&requester.uid, perm, CausedBy::System)
+                .await
+                .map_err(|e| {
+                    error!("Failed to update permissions: {:?}", e);
+                    e
+                });
+            state.instances.insert(uuid, instance);
+        }
+    });
+    Ok(Json(instance_uuid))
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct DetectImportConfig {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct ImportInstanceConfig {
+    /// absolute path to the directory of the server
+    pub path: String,
+    pub name: String,
+    /// move the files into the instance instead of copying them
+    #[serde(default)]
+    pub move_files: bool,
+}
+
+async fn import_source_path(state: &AppState, path: &str) -> Result<PathBuf, Error> {
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Path must be absolute"),
+        });
+    }
+    if state.storage_roots.lock().await.contains(&path) {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("Path is already managed by Lodestone"),
+        });
+    }
+    Ok(path)
+}
+
+async fn detect_server_blocking(path: PathBuf) -> Result<DetectedServer, Error> {
+    tokio::task::spawn_blocking(move || detect_server(&path))
+        .await
+        .context("Server detection task panicked")?
+}
+
+/// What an import of the server at the path would set the instance up as
+pub async fn detect_import(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<DetectImportConfig>,
+) -> Result<Json<DetectedServer>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    detect_server_blocking(import_source_path(&state, &config.path).await?)
+        .await
+        .map(Json)
+}
+
+/// Turn an existing server directory on the host into an instance
+pub async fn import_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(config): Json<ImportInstanceConfig>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    if config.move_files {
+        requester.try_action(&UserAction::WriteGlobalFile)?;
+    }
+    let source = import_source_path(&state, &config.path).await?;
+    let instances_dir = query.instances_dir(&state).await?;
+    spawn_import(
+        state,
+        requester,
+        config.name,
+        source,
+        config.move_files,
+        None,
+        instances_dir,
+    )
+    .await
+    .map(Json)
+}
+
+/// Import the server in an uploaded zip or tar.gz archive
+pub async fn import_instance_archive(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instances_dir = query.instances_dir(&state).await?;
+    crate::util::fs::create_dir_all(path_to_tmp()).await?;
+    let tmp = tempfile::tempdir_in(path_to_tmp())
+        .context("Failed to create temporary directory for the archive")?;
+    let mut field = multipart
+        .next_field()
+        .await
+        .context("Failed to read the uploaded archive")?
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Missing archive"),
+        })?;
+    let archive = tmp.path().join(match field.file_name() {
+        Some(name) if name.ends_with(".tar.gz") || name.ends_with(".tgz") => "server.tgz",
+        _ => "server.zip",
+    });
+    let mut file = crate::util::fs::create(&archive).await?;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .context("Failed to read the uploaded archive")?
+    {
+        file.write_all(&chunk)
+            .await
+            .context("Failed to write the uploaded archive")?;
+    }
+    file.flush()
+        .await
+        .context("Failed to write the uploaded archive")?;
+    drop(file);
+
+    let source = extract_server_archive(&archive, tmp.path()).await?;
+    spawn_import(
+        state,
+        requester,
+        name,
+        source,
+        true,
+        Some(tmp),
+        instances_dir,
+    )
+    .await
+    .map(Json)
+}
+
+/// Extract a server archive into `tmp`, returning the directory holding the server
+async fn extract_server_archive(
+    archive: &std::path::Path,
+    tmp: &std::path::Path,
+) -> Result<PathBuf, Error> {
+    let extracted = tmp.join("server");
+    unzip_file_async(archive, UnzipOption::ToDir(extracted.clone())).await?;
+    // archives usually hold the server in a single top level directory
+    let mut entries = std::fs::read_dir(&extracted)
+        .context("Failed to read the extracted archive")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    Ok(match entries.pop() {
+        Some(dir) if entries.is_empty() && dir.is_dir() => dir,
+        _ => extracted,
+    })
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ModpackSetupConfig {
+    /// link to a Modrinth pack or to a CurseForge server pack
+    pub url: String,
+    /// defaults to the name of the pack
+    pub name: Option<String>,
+    /// defaults to the first free port from 25565
+    pub port: Option<u32>,
+    /// the Minecraft EULA, no server is set up without it
+    #[serde(default)]
+    pub accept_eula: bool,
+}
+
+/// Create an instance from a pack link alone, resolving the version and loader from the pack
+pub async fn create_modpack_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(config): Json<ModpackSetupConfig>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instances_dir = query.instances_dir(&state).await?;
+    if !config.accept_eula {
+        return Err(eula_not_accepted());
+    }
+    match resolve_modpack_url(&config.url).await? {
+        ModpackSource::Modrinth(modpack) => {
+            let (game_type, flavour_kind) = match modpack.flavour {
+                Flavour::Fabric { .. } => (HandlerGameType::MinecraftFabric, FlavourKind::Fabric),
+                Flavour::Forge { .. } => (HandlerGameType::MinecraftForge, FlavourKind::Forge),
+                Flavour::Quilt { .. } => (HandlerGameType::MinecraftQuilt, FlavourKind::Quilt),
+                _ => (HandlerGameType::MinecraftJavaVanilla, FlavourKind::Vanilla),
+            };
+            let port = match config.port {
+                Some(port) => port,
+                None => {
+                    // the setup reserves the port once it succeeds
+                    let mut port_manager = state.port_manager.lock().await;
+                    let port = port_manager.allocate(25565);
+                    port_manager.deallocate(port);
+                    port
+                }
+            };
+            let recommendation =
+                memory::recommend_heap(&flavour_kind, &modpack.mods(), memory::host_memory());
+            let setup_config = SetupConfig {
+                name: config.name.unwrap_or_else(|| modpack.name.clone()),
+                version: modpack.minecraft_version.clone(),
+                flavour: modpack.flavour.clone(),
+                port,
+                cmd_args: vec![],
+                description: Some(format!("{} {}", modpack.name, modpack.version)),
+                min_ram: Some(recommendation.min_ram),
+                max_ram: Some(recommendation.max_ram),
+                auto_start: Some(false),
+                restart_on_crash: Some(false),
+                backup_period: None,
+                accept_eula: config.accept_eula,
+            };
+            spawn_minecraft_setup(
+                state,
+                requester,
+                game_type,
+                setup_config,
+                Some(SetupContent::Modpack(modpack)),
+                instances_dir,
+            )
+            .await
+            .map(Json)
+        }
+        ModpackSource::ServerPack { url, file_name } => {
+            crate::util::fs::create_dir_all(path_to_tmp()).await?;
+            let tmp = tempfile::tempdir_in(path_to_tmp())
+                .context("Failed to create temporary directory for the server pack")?;
+            let archive =
+                download_file(&url, tmp.path(), Some("server.zip"), &|_| {}, true).await?;
+            let source = extract_server_archive(&archive, tmp.path()).await?;
+            let name = config.name.unwrap_or_else(|| {
+                file_name
+                    .strip_suffix(".zip")
+                    .unwrap_or(&file_name)
+                    .to_string()
+            });
+            spawn_import(
+                state,
+                requester,
+                name,
+                source,
+                true,
+                Some(tmp),
+                instances_dir,
+            )
+            .await
+            .map(Json)
+        }
+    }
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceFromSharedConfig {
+    pub config: SharedInstanceConfig,
+    /// defaults to the name in the config
+    pub name: Option<String>,
+    /// defaults to the first free port from 25565
+    pub port: Option<u32>,
+    /// the Minecraft EULA is accepted by whoever creates the instance, not by the config
+    #[serde(default)]
+    pub accept_eula: bool,
+}
+
+/// Create an instance from a shared config, fetching its version and mods fresh
+pub async fn create_instance_from_shared_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(body): Json<InstanceFromSharedConfig>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instances_dir = query.instances_dir(&state).await?;
+    let config = body.config;
+    if config.format > SHARED_CONFIG_FORMAT {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The config was shared by a newer version of Lodestone"),
+        });
+    }
+    let game_type: HandlerGameType = config.flavour.clone().try_into()?;
+    let mut setup_value = config.setup_value.clone();
+    if let Some(name) = body.name {
+        setup_value.name = name;
+    }
+    let port = match body.port {
+        Some(port) => port,
+        None => {
+            // the setup reserves the port once it succeeds
+            let mut port_manager = state.port_manager.lock().await;
+            let port = port_manager.allocate(25565);
+            port_manager.deallocate(port);
+            port
+        }
+    };
+    setup_value.set_unique_setting("port", Some(ConfigurableValue::UnsignedInteger(port)));
+    setup_value.insert_setting(
+        "section_1",
+        "accept_eula",
+        Some(ConfigurableValue::Boolean(body.accept_eula)),
+    );
+    let setup_config =
+        MinecraftInstance::construct_setup_config(setup_value, config.flavour.clone()).await?;
+    spawn_minecraft_setup(
+        state,
+        requester,
+        game_type,
+        setup_config,
+        Some(SetupContent::SharedConfig(config)),
+        instances_dir,
+    )
+    .await
+    .map(Json)
+}
+
+/// Detect the server in `source` and import it in the background, the instance is added once
+/// the import succeeds
+async fn spawn_import(
+    state: AppState,
+    requester: User,
+    name: String,
+    source: PathBuf,
+    move_files: bool,
+    tmp: Option<tempfile::TempDir>,
+    instances_dir: PathBuf,
+) -> Result<InstanceUuid, Error> {
+    if name.trim().is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Name cannot be empty"),
+        });
+    }
+    let detected = detect_server_blocking(source.clone()).await?;
+    // the files of a failed import are only ours to remove if they were copied or uploaded
+    let owns_files = !move_files || tmp.is_some();
+
+    let instance_uuid = unique_instance_uuid(&state, &requester).await?;
+    let setup_path = instances_dir.join(format!(
+        "{}-{}",
+        sanitize_filename::sanitize(&name),
+        &instance_uuid.no_prefix()[0..8]
+    ));
+    let port = state.port_manager.lock().await.allocate(detected.port);
+    let mut perm = requester.permissions.clone();
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+
+    tokio::task::spawn({
+        let uuid = instance_uuid.clone();
+        let event_broadcaster = state.event_broadcaster.clone();
+        async move {
+            let (progression_start_event, event_id) = Event::new_progression_event_start(
+                format!("Importing Minecraft server {name}"),
+                Some(10.0),
+                Some(ProgressionStartValue::InstanceCreation {
+                    instance_uuid: uuid.clone(),
+                    instance_name: name.clone(),
+                    port,
+                    flavour: detected.flavour.to_string(),
+                    game_type: "minecraft".to_string(),
+                }),
+                caused_by,
+            );
+            event_broadcaster.send(progression_start_event);
+            let result: Result<(MinecraftInstance, Vec<PortChange>), Error> = async {
+                event_broadcaster.send(Event::new_progression_event_update(
+                    &event_id,
+                    if move_files {
+                        "1/3: Moving server files"
+                    } else {
+                        "1/3: Copying server files"
+                    },
+                    1.0,
+                ));
+                crate::util::fs::create_dir_all(&setup_path).await?;
+                tokio::task::spawn_blocking({
+                    let setup_path = setup_path.clone();
+                    move || {
+                        let mut options = fs_extra::dir::CopyOptions::new();
+                        options.content_only = true;
+                        if move_files {
+                            fs_extra::dir::move_dir(source, setup_path, &options)
+                        } else {
+                            fs_extra::dir::copy(source, setup_path, &options)
+                        }
+                    }
+                })
+                .await
+                .context("Copy task panicked")?
+                .context("Failed to copy server files")?;
+                let dot_lodestone_config =
+                    DotLodestoneConfig::new(uuid.clone(), GameType::MinecraftJava);
+                crate::util::fs::write_all(
+                    setup_path.join(".lodestone_config"),
+                    serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+                )
+                .await?;
+                let port_changes =
+                    remap_ports_blocking(setup_path.clone(), detected.port, port).await?;
+                let instance = MinecraftInstance::import(
+                    name.clone(),
+                    port,
+                    detected,
+                    dot_lodestone_config,
+                    setup_path.clone(),
+                    &event_id,
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await?;
+                Ok((instance, port_changes))
+            }
+            .await;
+            drop(tmp);
+            let instance = match result {
+                Ok((instance, port_changes)) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some(&creation_message(
+                            "Instance imported successfully",
+                            &port_changes,
+                        )),
+                        Some(ProgressionEndValue::InstanceCreation(
+                            instance.get_instance_info().await,
+                        )),
+                    ));
+                    instance
+                }
+                Err(e) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&if owns_files {
+                            format!("Instance import failed: {e}")
+                        } else {
+                            format!(
+                                "Instance import failed: {e}, the server files were left in {}",
+                                setup_path.display()
+                            )
+                        }),
+                        None,
+                    ));
+                    state.port_manager.lock().await.deallocate(port);
+                    if owns_files {
+                        if let Err(e) = crate::util::fs::remove_dir_all(&setup_path).await {
+                            error!("Failed to remove directory after instance import failed: {e}");
+                        }
+                    }
+                    return;
+                }
+            };
+            perm.can_start_instance.insert(uuid.clone());
+            perm.can_stop_instance.insert(uuid.clone());
+            perm.can_view_instance.insert(uuid.clone());
+            perm.can_read_instance_file.insert(uuid.clone());
+            perm.can_write_instance_file.insert(uuid.clone());
+            // ignore errors since we don't care if the permissions update fails
+            let _ = state
+                .users_manager
+                .write()
+                .await
+                .update_permissions(&requester.uid, perm, CausedBy::System)
+                .await
+                .map_err(|e| {
+                    error!("Failed to update permissions: {:?}", e);
+                    e
+                });
+            state.instances.insert(uuid, instance.into());
+        }
+    });
+    Ok(instance_uuid)
+}
+
 pub fn get_instance_routes(state: AppState) -> Router {
     Router::new()
+        .route(
+            "/instance/import/archive/:name",
+            post(import_instance_archive),
+        )
+        .layer(DefaultBodyLimit::disable())
         .route("/instance/list", get(get_instance_list))
+        .route("/instance/summaries", get(get_instance_summaries))
         .route(
             "/instance/create/:game_type",
             post(create_minecraft_instance),
         )
+        .route("/instance/validate", post(validate_instance_setup))
+        .route("/instance/create_modpack", post(create_modpack_instance))
+        .route(
+            "/instance/create_from_config",
+            post(create_instance_from_shared_config),
+        )
         .route("/instance/create_generic", post(create_generic_instance))
+        .route("/instance/create_process", post(create_process_instance))
         .route("/instance/:uuid", delete(delete_instance))
         .route("/instance/:uuid/info", get(get_instance_info))
+        .route("/instance/:uuid/clone", post(clone_instance))
+        .route("/instance/import", post(import_instance))
+        .route("/instance/import/detect", post(detect_import))
         .with_state(state)
 }
@@ -0,0 +1,272 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionEndValue},
+    instance_archive::ArchivedInstance,
+    migration::versioned,
+    prelude::{lodestone_path, path_to_archived_instances, path_to_instances, GameInstance},
+    traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance},
+    types::{DotLodestoneConfig, InstanceUuid},
+    AppState,
+};
+
+/// The archived instances the requester could view before they were archived
+pub async fn get_archived_instances(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ArchivedInstance>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut archived = state.archived_instances.lock().await.list();
+    archived.retain(|archived| {
+        requester.can_perform_action(&UserAction::ViewInstance(archived.uuid.clone()))
+    });
+    Ok(Json(archived))
+}
+
+/// Stop the instance, release its port and move it out of the instance list, its files and
+/// settings are kept until it is unarchived
+pub async fn archive_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ArchivedInstance>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::DeleteInstance)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let name = instance.name().await;
+    let (progression_event_start, event_id) = Event::new_progression_event_start(
+        format!("Archiving instance {name}"),
+        Some(10.0),
+        None,
+        caused_by.clone(),
+    );
+    let event_broadcaster = state.event_broadcaster.clone();
+    event_broadcaster.send(progression_event_start);
+
+    let result: Result<ArchivedInstance, Error> = async {
+        if !instance.state().await.is_idle() {
+            instance.stop(caused_by.clone(), true).await?;
+        }
+        let instance_path = instance.path().await;
+        let directory_name = instance_path
+            .file_name()
+            .ok_or_else(|| eyre!("Instance directory has no name"))?
+            .to_string_lossy()
+            .to_string();
+        let archive_path = path_to_archived_instances().join(&directory_name);
+        if archive_path.exists() {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("An archived instance already has the directory {directory_name}"),
+            });
+        }
+        let archived_instance = ArchivedInstance {
+            uuid: uuid.clone(),
+            name: name.clone(),
+            game_type: instance.game_type().await,
+            port: instance.port().await,
+            directory_name,
+            archived_at: chrono::Utc::now().timestamp(),
+            archived_by: caused_by.clone(),
+        };
+        if state.instances.remove(&uuid).is_none() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance was deleted while it was being archived"),
+            });
+        }
+        // recorded first so an archived directory is never without its entry
+        let mut archived_instances = state.archived_instances.lock().await;
+        if let Err(e) = archived_instances.insert(archived_instance.clone()).await {
+            state.instances.insert(uuid.clone(), instance.clone());
+            return Err(e);
+        }
+        if let Err(e) = crate::util::fs::rename(&instance_path, &archive_path).await {
+            if let Err(e) = archived_instances.remove(&uuid).await {
+                error!("Failed to remove {uuid} from the archived instances : {e}");
+            }
+            state.instances.insert(uuid.clone(), instance.clone());
+            return Err(e);
+        }
+        drop(archived_instances);
+        state
+            .port_manager
+            .lock()
+            .await
+            .deallocate(archived_instance.port);
+        Ok(archived_instance)
+    }
+    .await;
+
+    match result {
+        Ok(archived_instance) => {
+            if let GameInstance::GenericInstance(i) = instance {
+                i.destruct().await;
+            };
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Instance archived successfully"),
+                Some(ProgressionEndValue::InstanceArchive {
+                    instance_uuid: uuid,
+                }),
+            ));
+            Ok(Json(archived_instance))
+        }
+        Err(e) => {
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to archive instance: {e}")),
+                None,
+            ));
+            Err(e)
+        }
+    }
+}
+
+/// Move an archived instance back and load it, it takes back the port it had
+pub async fn unarchive_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let archived_instance = state.archived_instances.lock().await.get(&uuid)?.clone();
+    if state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("An instance with the same uuid already exists"),
+        });
+    }
+    if state
+        .port_manager
+        .lock()
+        .await
+        .port_status(archived_instance.port)
+        .is_allocated
+    {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!(
+                "Port {} is taken by another instance",
+                archived_instance.port
+            ),
+        });
+    }
+    let archive_path = path_to_archived_instances().join(&archived_instance.directory_name);
+    let instance_path = path_to_instances().join(&archived_instance.directory_name);
+    if instance_path.exists() {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!(
+                "An instance already has the directory {}",
+                archived_instance.directory_name
+            ),
+        });
+    }
+
+    let (progression_event_start, event_id) = Event::new_progression_event_start(
+        format!("Unarchiving instance {}", archived_instance.name),
+        Some(10.0),
+        None,
+        caused_by,
+    );
+    let event_broadcaster = state.event_broadcaster.clone();
+    event_broadcaster.send(progression_event_start);
+
+    if let Err(e) = crate::util::fs::rename(&archive_path, &instance_path).await {
+        event_broadcaster.send(Event::new_progression_event_end(
+            event_id,
+            false,
+            Some(&format!("Failed to unarchive instance: {e}")),
+            None,
+        ));
+        return Err(e);
+    }
+    let result: Result<GameInstance, Error> = async {
+        // it may have been archived by an older version of lodestone
+        versioned::migrate_dot_lodestone_config(
+            lodestone_path(),
+            &instance_path,
+            &versioned::backup_dir(lodestone_path()),
+        )?;
+        let dot_lodestone_config: DotLodestoneConfig = serde_json::from_str(
+            &crate::util::fs::read_to_string(instance_path.join(".lodestone_config")).await?,
+        )
+        .context("Failed to parse .lodestone_config")?;
+        crate::restore_instance(
+            &instance_path,
+            &dot_lodestone_config,
+            state.event_broadcaster.clone(),
+            state.macro_executor.clone(),
+        )
+        .await
+    }
+    .await;
+
+    let instance = match result {
+        Ok(instance) => instance,
+        Err(e) => {
+            if let Err(e) = crate::util::fs::rename(&instance_path, &archive_path).await {
+                error!("Failed to move {uuid} back to the archive after unarchiving failed : {e}");
+            }
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Failed to unarchive instance: {e}")),
+                None,
+            ));
+            return Err(e);
+        }
+    };
+    state
+        .port_manager
+        .lock()
+        .await
+        .add_port(archived_instance.port);
+    let instance_info = instance.get_instance_info().await;
+    state.instances.insert(uuid.clone(), instance);
+    if let Err(e) = state.archived_instances.lock().await.remove(&uuid).await {
+        error!("Failed to remove {uuid} from the archived instances : {e}");
+    }
+    event_broadcaster.send(Event::new_progression_event_end(
+        event_id,
+        true,
+        Some("Instance unarchived successfully"),
+        Some(ProgressionEndValue::InstanceCreation(instance_info.clone())),
+    ));
+    Ok(Json(instance_info))
+}
+
+pub fn get_instance_archive_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/archived", get(get_archived_instances))
+        .route("/instance/:uuid/archive", post(archive_instance))
+        .route(
+            "/instance/archived/:uuid/unarchive",
+            post(unarchive_instance),
+        )
+        .with_state(state)
+}
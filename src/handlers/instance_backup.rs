@@ -0,0 +1,348 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    backup::{self, BackupEntry, BackupRetentionPolicy},
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, ProgressionEndValue},
+    implementations::minecraft::MinecraftInstance,
+    jobs::{JobKind, NewJob},
+    migration::versioned,
+    prelude::{lodestone_path, GameInstance},
+    schedule::WallClockSchedule,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::{DotLodestoneConfig, InstanceUuid, Snowflake},
+    AppState,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewBackupRequest {
+    pub name: Option<String>,
+    /// Back up a running server without stopping it, saving is paused while the files are copied
+    #[serde(default)]
+    pub hot: bool,
+}
+
+pub async fn list_instance_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(backup::list_backups(&uuid).await?))
+}
+
+pub async fn create_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_backup_request): Json<NewBackupRequest>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let hot_instance = match &instance {
+        _ if !new_backup_request.hot => None,
+        GameInstance::MinecraftInstance(instance) => Some(instance.clone()),
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("This instance does not support hot backups"),
+            })
+        }
+    };
+    let instance_name = instance.name().await;
+    let instance_path = instance.path().await;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Backup,
+            name: format!("Backing up {instance_name}"),
+            instance_uuid: Some(uuid.clone()),
+            caused_by: caused_by.clone(),
+            cancellable: false,
+        },
+        |_| async move {
+            match hot_instance {
+                Some(instance) => {
+                    instance
+                        .hot_backup(new_backup_request.name, caused_by)
+                        .await
+                }
+                None => {
+                    backup::create_backup(
+                        uuid,
+                        instance_name,
+                        instance_path,
+                        new_backup_request.name,
+                        state.event_broadcaster.clone(),
+                        caused_by,
+                    )
+                    .await
+                }
+            }
+            .map(|_| ())
+        },
+    );
+    Ok(Json(()))
+}
+
+pub async fn delete_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    backup::delete_backup(&uuid, &backup_id).await?;
+    Ok(Json(()))
+}
+
+pub async fn restore_instance_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !matches!(instance, GameInstance::MinecraftInstance(_)) {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support restoring backups"),
+        });
+    }
+    let backup = backup::get_backup(&uuid, &backup_id).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Restore,
+            name: format!("Restoring backup {}", backup.name),
+            instance_uuid: Some(uuid),
+            caused_by: caused_by.clone(),
+            cancellable: false,
+        },
+        |_| restore_backup(state, instance, backup, caused_by),
+    );
+    Ok(Json(()))
+}
+
+/// Stop the instance, swap its files for the ones of a local backup, reload it from disk and
+/// start it again if it was running, under a progression event
+pub(crate) async fn restore_backup(
+    state: AppState,
+    mut instance: GameInstance,
+    backup: BackupEntry,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    let uuid = backup.instance_uuid.clone();
+    let backup_id = backup.id;
+    let event_broadcaster = state.event_broadcaster.clone();
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Restoring backup {}", backup.name),
+        None,
+        None,
+        caused_by.clone(),
+    );
+    event_broadcaster.send(progression_start_event);
+    let was_running = !instance.state().await.is_stopped();
+    let result: Result<(), Error> = async {
+        if was_running {
+            instance.stop(caused_by.clone(), true).await?;
+        }
+        let instance_path = instance.path().await;
+        backup::restore_backup_files(&uuid, &backup_id, &instance_path).await?;
+        // a backup taken by an older version of lodestone may hold an older format
+        versioned::migrate_dot_lodestone_config(
+            lodestone_path(),
+            &instance_path,
+            &versioned::backup_dir(lodestone_path()),
+        )?;
+        // the restored files may hold a different config, so reload the instance from disk
+        let dot_lodestone_config: DotLodestoneConfig = serde_json::from_str(
+            &crate::util::fs::read_to_string(instance_path.join(".lodestone_config")).await?,
+        )
+        .context("Failed to parse .lodestone_config of the restored backup")?;
+        let mut restored_instance: GameInstance = MinecraftInstance::restore(
+            instance_path,
+            dot_lodestone_config,
+            state.event_broadcaster.clone(),
+            state.macro_executor.clone(),
+        )
+        .await?
+        .into();
+        state
+            .instances
+            .insert(uuid.clone(), restored_instance.clone());
+        if was_running {
+            restored_instance.start(caused_by.clone(), false).await?;
+        }
+        Ok(())
+    }
+    .await;
+    // the files are put back when the swap fails, so the instance can run as it did
+    if result.is_err() && was_running {
+        if let Some(mut instance) = state.instances.get(&uuid) {
+            if instance.state().await.is_stopped() {
+                if let Err(e) = instance.start(caused_by, false).await {
+                    error!("Failed to start {uuid} again after a failed restore : {e}");
+                }
+            }
+        }
+    }
+    match &result {
+        Ok(_) => event_broadcaster.send(Event::new_progression_event_end(
+            event_id,
+            true,
+            Some("Backup restored"),
+            Some(ProgressionEndValue::InstanceBackupRestore {
+                instance_uuid: uuid,
+                backup_id,
+            }),
+        )),
+        Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+            event_id,
+            false,
+            Some(&format!("Restoring backup failed: {e}")),
+            None,
+        )),
+    }
+    result
+}
+
+pub async fn get_backup_retention_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BackupRetentionPolicy>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    Ok(Json(backup::read_retention_policy(&uuid).await?))
+}
+
+pub async fn set_backup_retention_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(policy): Json<BackupRetentionPolicy>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    backup::write_retention_policy(&uuid, &policy).await?;
+    backup::apply_retention_policy(&uuid).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_backup_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<WallClockSchedule>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    Ok(Json(backup::read_backup_schedule(&uuid).await?))
+}
+
+/// Back up at a time of day, in the timezone of the schedule or the default one of the core
+pub async fn set_backup_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(schedule): Json<Option<WallClockSchedule>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    backup::write_backup_schedule(&uuid, schedule.as_ref()).await?;
+    Ok(Json(()))
+}
+
+pub async fn set_backup_period(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(backup_period): Json<Option<u32>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .set_backup_period(backup_period)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_backup_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/backups",
+            get(list_instance_backups).post(create_instance_backup),
+        )
+        .route(
+            "/instance/:uuid/backups/retention",
+            get(get_backup_retention_policy).put(set_backup_retention_policy),
+        )
+        .route("/instance/:uuid/backups/period", put(set_backup_period))
+        .route(
+            "/instance/:uuid/backups/schedule",
+            get(get_backup_schedule).put(set_backup_schedule),
+        )
+        .route(
+            "/instance/:uuid/backups/:backup_id",
+            delete(delete_instance_backup),
+        )
+        .route(
+            "/instance/:uuid/backups/:backup_id/restore",
+            post(restore_instance_backup),
+        )
+        .with_state(state)
+}
@@ -6,12 +6,21 @@ use axum::{
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 
+use indexmap::IndexMap;
+
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
+    implementations::minecraft::{
+        scheduling::ProcessScheduling,
+        server_properties::ServerPropertiesUpdate,
+        version_switch::{VersionChange, VersionChangeReport},
+        MinecraftInstance,
+    },
+    prelude::GameInstance,
     traits::t_configurable::{
-        manifest::{ConfigurableManifest, ConfigurableValue},
-        TConfigurable,
+        manifest::{ConfigurableManifest, ConfigurableValue, SettingManifest},
+        CrashRestartPolicy, DisplayMetadata, LaunchOverrides, ResourceLimits, TConfigurable,
     },
     types::InstanceUuid,
     AppState,
@@ -24,8 +33,7 @@ pub async fn get_instance_configurable_manifest(
 ) -> Result<Json<ConfigurableManifest>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
-    let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -39,8 +47,7 @@ pub async fn get_instance_settings(
 ) -> Result<Json<ConfigurableManifest>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
-    let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -55,8 +62,7 @@ pub async fn set_instance_setting(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
-    let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or(Error {
+    let mut instance = state.instances.get(&uuid).ok_or(Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -64,6 +70,8 @@ pub async fn set_instance_setting(
     instance
         .update_configurable(&section_id, &setting_id, value)
         .await?;
+    // the setting may be the port
+    state.instance_summaries.refresh(&instance).await;
 
     Ok(Json(()))
 }
@@ -73,62 +81,330 @@ pub async fn set_instance_name(
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
     Json(new_name): Json<String>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_name(new_name).await?;
+    state.instance_summaries.refresh(&instance).await;
+    Ok(Json(()))
+}
+
+pub async fn set_instance_description(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(new_description): Json<String>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .set_name(new_name)
+        .set_description(new_description)
         .await?;
     Ok(Json(()))
 }
 
-pub async fn set_instance_description(
+pub async fn change_version(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, new_version)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .change_version(new_version)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn switch_version(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
-    Json(new_description): Json<String>,
+    Json(change): Json<VersionChange>,
+) -> Result<Json<VersionChangeReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .switch_version(change)
+            .await?,
+    ))
+}
+
+pub async fn get_instance_resource_limits(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ResourceLimits>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .resource_limits()
+            .await,
+    ))
+}
+
+pub async fn set_instance_resource_limits(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(limits): Json<ResourceLimits>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .set_description(new_description)
+        .set_resource_limits(limits)
         .await?;
     Ok(Json(()))
 }
 
-pub async fn change_version(
+pub async fn get_instance_display_metadata(
     axum::extract::State(state): axum::extract::State<AppState>,
-    Path((uuid, new_version)): Path<(InstanceUuid, String)>,
+    Path(uuid): Path<InstanceUuid>,
     AuthBearer(token): AuthBearer,
+) -> Result<Json<DisplayMetadata>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .display_metadata()
+            .await,
+    ))
+}
+
+pub async fn set_instance_display_metadata(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(display): Json<DisplayMetadata>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_display_metadata(display).await?;
+    state.instance_summaries.refresh(&instance).await;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_crash_restart_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<CrashRestartPolicy>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .crash_restart_policy()
+            .await,
+    ))
+}
+
+pub async fn set_instance_crash_restart_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(policy): Json<CrashRestartPolicy>,
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
         })?
-        .change_version(new_version)
+        .set_crash_restart_policy(policy)
+        .await?;
+    Ok(Json(()))
+}
+
+/// The minecraft instance `uuid`, for what only minecraft instances have
+pub(crate) async fn get_minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<MinecraftInstance, Error> {
+    match state.instances.get(uuid) {
+        Some(GameInstance::MinecraftInstance(instance)) => Ok(instance),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This is only supported for minecraft instances"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        }),
+    }
+}
+
+pub async fn get_server_properties(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<IndexMap<String, SettingManifest>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .server_properties()
+            .await?,
+    ))
+}
+
+pub async fn update_server_properties(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(values): Json<IndexMap<String, ConfigurableValue>>,
+) -> Result<Json<ServerPropertiesUpdate>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = get_minecraft_instance(&state, &uuid).await?;
+    let old_port = instance.port().await;
+    let new_port = match values.get("server-port") {
+        Some(value) => Some(value.to_string().parse::<u32>().map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Invalid value: {} for \"server-port\", expected u16",
+                value.to_string()
+            ),
+        })?),
+        None => None,
+    };
+    // the port manager only knows the ports of instances, so a port in use by something else
+    // is left for the server to report when it starts
+    let mut port_manager = state.port_manager.lock().await;
+    if let Some(new_port) = new_port {
+        if new_port != old_port && port_manager.port_status(new_port).is_allocated {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Port {new_port} is already allocated to another instance"),
+            });
+        }
+    }
+    let update = instance.update_server_properties(values).await?;
+    if let Some(new_port) = new_port.filter(|port| *port != old_port) {
+        port_manager.deallocate(old_port);
+        port_manager.add_port(new_port);
+    }
+    Ok(Json(update))
+}
+
+/// Sensitive environment variables are masked
+pub async fn get_instance_launch_overrides(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LaunchOverrides>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .launch_overrides()
+            .await
+            .masked(),
+    ))
+}
+
+/// Masked sensitive values sent back keep their current value
+pub async fn set_instance_launch_overrides(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(overrides): Json<LaunchOverrides>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let current = instance.launch_overrides().await;
+    instance
+        .set_launch_overrides(overrides.unmask(&current))
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_instance_scheduling(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ProcessScheduling>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .scheduling()
+            .await,
+    ))
+}
+
+pub async fn set_instance_scheduling(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(scheduling): Json<ProcessScheduling>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    get_minecraft_instance(&state, &uuid)
+        .await?
+        .set_scheduling(scheduling)
         .await?;
     Ok(Json(()))
 }
@@ -139,13 +415,38 @@ pub fn get_instance_config_routes(state: AppState) -> Router {
             "/instance/:uuid/configurable_manifest",
             get(get_instance_configurable_manifest),
         )
+        .route("/instance/:uuid/version", put(switch_version))
         .route("/instance/:uuid/version/:new_version", put(change_version))
         .route("/instance/:uuid/settings", get(get_instance_settings))
         .route(
             "/instance/:uuid/settings/:section_id/:setting_id",
             put(set_instance_setting),
         )
+        .route(
+            "/instance/:uuid/server_properties",
+            get(get_server_properties).patch(update_server_properties),
+        )
         .route("/instance/:uuid/name", put(set_instance_name))
         .route("/instance/:uuid/description", put(set_instance_description))
+        .route(
+            "/instance/:uuid/resource_limits",
+            get(get_instance_resource_limits).put(set_instance_resource_limits),
+        )
+        .route(
+            "/instance/:uuid/display",
+            get(get_instance_display_metadata).put(set_instance_display_metadata),
+        )
+        .route(
+            "/instance/:uuid/crash_restart_policy",
+            get(get_instance_crash_restart_policy).put(set_instance_crash_restart_policy),
+        )
+        .route(
+            "/instance/:uuid/launch_overrides",
+            get(get_instance_launch_overrides).put(set_instance_launch_overrides),
+        )
+        .route(
+            "/instance/:uuid/scheduling",
+            get(get_instance_scheduling).put(set_instance_scheduling),
+        )
         .with_state(state)
 }
@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Path, Query},
+    routing::get,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    implementations::minecraft::shared_config::SharedInstanceConfig,
+    instance_export::{export_instance, ExportOptions},
+    jobs::{JobKind, NewJob},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+    AppState,
+};
+
+use super::instance_config::get_minecraft_instance;
+
+/// Package the instance into an archive in the background.
+///
+/// Returns the download key right away, the archive can be downloaded from `/file/:key` once the
+/// progression event of the export ends. The export runs as a cancellable job.
+pub async fn export_instance_archive(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Query(options): Query<ExportOptions>,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let instance_name = instance.name().await;
+    let instance_path = instance.path().await;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let key = rand_alphanumeric(32);
+    state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Export,
+            name: format!("Exporting {instance_name}"),
+            instance_uuid: Some(uuid.clone()),
+            caused_by: caused_by.clone(),
+            cancellable: true,
+        },
+        |job| {
+            let key = key.clone();
+            async move {
+                export_instance(
+                    uuid,
+                    instance_name,
+                    instance_path,
+                    options,
+                    key,
+                    state.download_urls.clone(),
+                    state.event_broadcaster.clone(),
+                    caused_by,
+                    job,
+                )
+                .await
+                .map(|_| ())
+                .map_err(|e| {
+                    error!("Failed to export instance : {e}");
+                    e
+                })
+            }
+        },
+    );
+    Ok(key)
+}
+
+/// The setup, server properties and mods of the instance as a JSON document to share, without
+/// any of its files
+pub async fn export_instance_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<SharedInstanceConfig>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .shared_config()
+            .await?,
+    ))
+}
+
+pub fn get_instance_export_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/export", get(export_instance_archive))
+        .route("/instance/:uuid/export/config", get(export_instance_config))
+        .with_state(state)
+}
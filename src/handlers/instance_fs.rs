@@ -2,8 +2,9 @@ use std::path::PathBuf;
 
 use axum::{
     body::Bytes,
-    extract::{DefaultBodyLimit, Multipart, Path},
-    routing::{delete, get, put},
+    extract::{DefaultBodyLimit, Multipart, Path, Query},
+    response::Response,
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
@@ -19,10 +20,17 @@ use walkdir::WalkDir;
 
 use crate::{
     auth::user::UserAction,
+    config_editor::{edit_config, parse_config, ConfigEdit, ConfigFile, ConfigFormat},
     error::{Error, ErrorKind},
     events::{new_fs_event, CausedBy, Event, FSOperation, FSTarget, ProgressionEndValue},
+    file_search::{search_files, FileSearchQuery, FileSearchResult},
+    file_stream::{file_response, ReadFileQuery},
+    file_sync::{diff, manifest, SyncDiff, SyncEntry},
+    instance_export::export_selected_files,
     prelude::path_to_tmp,
+    text_patch::{apply_text_patch, TextPatchOperation},
     traits::t_configurable::TConfigurable,
+    trash::{Trash, TRASH_DIR_NAME},
     types::InstanceUuid,
     util::{
         format_byte, format_byte_download, list_dir, rand_alphanumeric, resolve_path_conflict,
@@ -45,10 +53,17 @@ static PROTECTED_EXTENSIONS: [&str; 10] = [
     "inf",
 ];
 
-static PROTECTED_DIR_NAME: [&str; 1] = ["mods"];
+pub(super) static PROTECTED_DIR_NAME: [&str; 1] = ["mods"];
 
-fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
+pub(super) fn is_path_protected(path: impl AsRef<std::path::Path>) -> bool {
     let path = path.as_ref();
+    // trash entries say where they are restored to, they are only changed through the trash
+    if path
+        .components()
+        .any(|component| component.as_os_str() == TRASH_DIR_NAME)
+    {
+        return true;
+    }
     if path.is_dir() {
         path.file_name()
             .and_then(|s| s.to_str().map(|s| PROTECTED_DIR_NAME.contains(&s)))
@@ -73,13 +88,11 @@ async fn list_instance_files(
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
 
     requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(&root, relative_path)?;
 
     let ret: Vec<FileEntry> = list_dir(&path, None)
@@ -104,26 +117,26 @@ async fn list_instance_files(
     Ok(Json(ret))
 }
 
+/// Stream the file, or the byte range of it asked by a `Range` header, or its last `tail` lines,
+/// optionally followed by what is appended to it
 async fn read_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    Query(query): Query<ReadFileQuery>,
+    headers: HeaderMap,
     AuthBearer(token): AuthBearer,
-) -> Result<String, Error> {
+) -> Result<Response, Error> {
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(root, relative_path)?;
 
-    let ret = tokio::fs::read_to_string(&path)
-        .await
-        .context("Failed to read file")?;
+    let ret = file_response(&path, &headers, &query).await?;
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
@@ -145,13 +158,11 @@ async fn write_instance_file(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(root, relative_path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
@@ -179,6 +190,128 @@ async fn write_instance_file(
     Ok(Json(()))
 }
 
+async fn patch_instance_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(operations): Json<Vec<TextPatchOperation>>,
+) -> Result<Json<()>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(root, relative_path)?;
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You don't have permission to write to this file"),
+        });
+    }
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .context("Failed to read file, only text files can be patched")?;
+    let patched = apply_text_patch(&content, &operations)?;
+    tokio::fs::write(&path, patched)
+        .await
+        .context("Failed to write to file")?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Write,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(Json(()))
+}
+
+async fn read_instance_config_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ConfigFile>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(root, relative_path)?;
+    let format = ConfigFormat::from_path(&path).ok_or_else(|| Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("Only .properties, .toml and .yml files can be parsed"),
+    })?;
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .context("Failed to read file")?;
+    let ret = parse_config(format, &content)?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(Json(ret))
+}
+
+async fn edit_instance_config_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(edits): Json<Vec<ConfigEdit>>,
+) -> Result<Json<ConfigFile>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(root, relative_path)?;
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You don't have permission to write to this file"),
+        });
+    }
+    let format = ConfigFormat::from_path(&path).ok_or_else(|| Error {
+        kind: ErrorKind::UnsupportedOperation,
+        source: eyre!("Only .properties, .toml and .yml files can be edited"),
+    })?;
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .context("Failed to read file")?;
+    let edited = edit_config(format, &content, &edits)?;
+    let ret = parse_config(format, &edited)?;
+    tokio::fs::write(&path, edited)
+        .await
+        .context("Failed to write to file")?;
+
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Write,
+        FSTarget::File(path),
+        caused_by,
+    ));
+    Ok(Json(ret))
+}
+
 async fn make_instance_directory(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
@@ -187,13 +320,11 @@ async fn make_instance_directory(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(root, relative_path)?;
     // create the file if it doesn't exist
     crate::util::fs::create_dir_all(&path).await?;
@@ -228,13 +359,11 @@ async fn copy_instance_files(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     // join each path to the root
     let paths_source = relative_paths_source
         .iter()
@@ -372,13 +501,11 @@ async fn move_instance_file(
     let relative_path_dest = decode_base64(&base64_relative_path_dest)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path_source = scoped_join_win_safe(&root, relative_path_source)?;
     let path_dest = scoped_join_win_safe(&root, relative_path_dest)?;
 
@@ -440,14 +567,12 @@ async fn remove_instance_file(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
-    let path = scoped_join_win_safe(root, relative_path)?;
+    let path = scoped_join_win_safe(&root, relative_path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
         return Err(Error {
@@ -456,12 +581,23 @@ async fn remove_instance_file(
         });
     }
 
-    crate::util::fs::remove_file(&path).await?;
-
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
     };
+    if !path.exists() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("File not found"),
+        });
+    }
+    let trash = Trash::for_instance(&root);
+    if trash.contains(&path) {
+        crate::util::fs::remove_file(&path).await?;
+    } else if path.is_file() {
+        trash.move_to_trash(&path, caused_by.clone()).await?;
+    }
+
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
         FSTarget::File(path),
@@ -478,13 +614,11 @@ async fn remove_instance_dir(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(&root, relative_path)?;
     if path == root {
         return Err(Error {
@@ -500,11 +634,7 @@ async fn remove_instance_dir(
         });
     }
 
-    if requester.can_perform_action(&UserAction::WriteGlobalFile) {
-        tokio::fs::remove_dir_all(&path)
-            .await
-            .context("Failed to remove directory")?;
-    } else {
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) {
         // recursively access all files in the directory and check if they are protected
         for entry in WalkDir::new(path.clone()) {
             let entry =
@@ -516,15 +646,20 @@ async fn remove_instance_dir(
                 });
             }
         }
-        tokio::fs::remove_dir_all(&path)
-            .await
-            .context("Failed to remove directory")?;
     }
 
     let caused_by = CausedBy::User {
         user_id: requester.uid,
         user_name: requester.username,
     };
+    let trash = Trash::for_instance(&root);
+    if trash.contains(&path) {
+        tokio::fs::remove_dir_all(&path)
+            .await
+            .context("Failed to remove directory")?;
+    } else {
+        trash.move_to_trash(&path, caused_by.clone()).await?;
+    }
     state.event_broadcaster.send(new_fs_event(
         FSOperation::Delete,
         FSTarget::Directory(path),
@@ -541,13 +676,11 @@ async fn new_instance_file(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(root, relative_path)?;
     // if target has a protected extension, or no extension, deny
     if !requester.can_perform_action(&UserAction::WriteGlobalFile) && is_path_protected(&path) {
@@ -579,13 +712,11 @@ async fn get_instance_file_url(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path = scoped_join_win_safe(&root, relative_path)?;
 
     let key = rand_alphanumeric(32);
@@ -609,6 +740,151 @@ async fn get_instance_file_url(
     Ok(key)
 }
 
+#[derive(Deserialize)]
+pub struct SyncManifestQuery {
+    /// hash every file, which reads the whole directory
+    #[serde(default)]
+    hashes: bool,
+}
+
+/// The files under a directory of the instance with their size and modification time, for
+/// mirrors to tell what they are missing
+async fn get_instance_sync_manifest(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    Query(query): Query<SyncManifestQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<SyncEntry>>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    let entries = tokio::task::spawn_blocking(move || manifest(&path, query.hashes))
+        .await
+        .context("Manifest task panicked")??;
+    Ok(Json(entries))
+}
+
+/// Find files under a directory of the instance by name, and optionally by content
+async fn search_instance_files(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    Query(query): Query<FileSearchQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<FileSearchResult>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    let search_path = path.clone();
+    let result = tokio::task::spawn_blocking(move || search_files(&search_path, &query))
+        .await
+        .context("Search task panicked")??;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Read,
+        FSTarget::Directory(path),
+        caused_by,
+    ));
+    Ok(Json(result))
+}
+
+/// Compare a directory of the instance against the files of a mirror, see `file_sync::diff`
+async fn diff_instance_files(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(mirror): Json<Vec<SyncEntry>>,
+) -> Result<Json<SyncDiff>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    let sync_diff = tokio::task::spawn_blocking(move || diff(&path, &mirror))
+        .await
+        .context("Diff task panicked")??;
+    Ok(Json(sync_diff))
+}
+
+/// Package the given files of a directory of the instance into a single archive, returns the key
+/// to download it with. Paths are relative to the directory, as in the manifest.
+async fn bundle_instance_files(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(paths): Json<Vec<String>>,
+) -> Result<String, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let name = instance.name().await;
+    let dir = scoped_join_win_safe(&root, relative_path)?;
+    if paths.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("No files to bundle"),
+        });
+    }
+    let mut relative_paths = Vec::new();
+    for path in paths {
+        let full_path = scoped_join_win_safe(&dir, &path)?;
+        if !full_path.is_file() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{path} is not a file"),
+            });
+        }
+        relative_paths.push(
+            full_path
+                .strip_prefix(&dir)
+                .context("Failed to get relative path")?
+                .to_owned(),
+        );
+    }
+    let bundle = tokio::task::spawn_blocking({
+        let dir = dir.clone();
+        move || export_selected_files(&dir, &relative_paths, &format!("{name}-sync"))
+    })
+    .await
+    .context("Bundle task panicked")??;
+
+    let key = rand_alphanumeric(32);
+    state.download_urls.lock().await.insert(key.clone(), bundle);
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Download,
+        FSTarget::Directory(dir),
+        caused_by,
+    ));
+    Ok(key)
+}
+
 async fn upload_instance_file(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
@@ -623,13 +899,11 @@ async fn upload_instance_file(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path_to_dir = scoped_join_win_safe(&root, relative_path)?;
     crate::util::fs::create_dir_all(&path_to_dir).await?;
 
@@ -637,6 +911,11 @@ async fn upload_instance_file(
         .get(CONTENT_LENGTH)
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.parse::<f64>().ok());
+    state.directory_sizes.check_quota(
+        &uuid,
+        &instance.resource_limits().await,
+        total.unwrap_or_default() as u64,
+    )?;
     let (progression_start_event, event_id) =
         Event::new_progression_event_start("Uploading files", total, None, caused_by.clone());
     state.event_broadcaster.send(progression_start_event);
@@ -726,6 +1005,7 @@ async fn upload_instance_file(
             };
         }
 
+        state.directory_sizes.add(&uuid, elapsed_bytes);
         state.event_broadcaster.send(new_fs_event(
             FSOperation::Upload,
             FSTarget::File(path),
@@ -756,13 +1036,11 @@ pub async fn unzip_instance_file(
     let relative_path = decode_base64(&base64_relative_path)?;
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let path_to_zip_file = scoped_join_win_safe(root, &relative_path)?;
 
     if let UnzipOption::ToDir(ref dir) = unzip_option {
@@ -830,13 +1108,11 @@ async fn zip_instance_files(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
     let root = instance.path().await;
-    drop(instances);
     let ZipRequest {
         mut target_relative_paths,
         mut destination_relative_path,
@@ -927,6 +1203,14 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             "/instance/:uuid/fs/:base64_relative_path/write",
             put(write_instance_file),
         )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/patch",
+            patch(patch_instance_file),
+        )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/config",
+            get(read_instance_config_file).put(edit_instance_config_file),
+        )
         .route(
             "/instance/:uuid/fs/:base64_relative_path/mkdir",
             put(make_instance_directory),
@@ -952,6 +1236,22 @@ pub fn get_instance_fs_routes(state: AppState) -> Router {
             "/instance/:uuid/fs/:base64_relative_path/url",
             get(get_instance_file_url),
         )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/search",
+            get(search_instance_files),
+        )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/sync/manifest",
+            get(get_instance_sync_manifest),
+        )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/sync/diff",
+            post(diff_instance_files),
+        )
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/sync/bundle",
+            post(bundle_instance_files),
+        )
         .route(
             "/instance/:uuid/fs/:base64_relative_path/upload",
             put(upload_instance_file),
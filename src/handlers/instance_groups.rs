@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use futures::future::join_all;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    instance_groups::BulkOperationResult,
+    start_dependencies::start_in_order,
+    traits::{t_configurable::TConfigurable, t_server::TServer},
+    types::InstanceUuid,
+    AppState,
+};
+
+/// Every group with the instances of it the requester can view, groups with none are left out
+pub async fn get_instance_groups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeMap<String, Vec<InstanceUuid>>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut groups = state.instance_groups.lock().await.groups();
+    for members in groups.values_mut() {
+        members
+            .retain(|uuid| requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())));
+    }
+    groups.retain(|_, members| !members.is_empty());
+    Ok(Json(groups))
+}
+
+pub async fn get_groups_of_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.instance_groups.lock().await.groups_of(&uuid)))
+}
+
+/// Replace the groups an instance is in
+pub async fn set_groups_of_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(groups): Json<BTreeSet<String>>,
+) -> Result<Json<BTreeSet<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .instance_groups
+            .lock()
+            .await
+            .set_groups(&uuid, groups)
+            .await?,
+    ))
+}
+
+#[derive(Clone, Copy)]
+enum BulkOperation {
+    Start,
+    Stop,
+    Restart,
+}
+
+/// Run an operation on one instance of a group, with the checks of its own endpoint
+async fn run_on_instance(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    operation: BulkOperation,
+    caused_by: CausedBy,
+) -> Result<(), Error> {
+    match operation {
+        BulkOperation::Start => requester.try_action(&UserAction::StartInstance(uuid.clone()))?,
+        BulkOperation::Stop => requester.try_action(&UserAction::StopInstance(uuid.clone()))?,
+        BulkOperation::Restart => {
+            requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+            requester.try_action(&UserAction::StartInstance(uuid.clone()))?;
+        }
+    }
+    let mut instance = state.instances.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    match operation {
+        BulkOperation::Start => {
+            let port = instance.port().await;
+            if state.port_manager.lock().await.port_status(port).is_in_use {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!("Port {} is in use", port),
+                });
+            }
+            state
+                .user_quotas
+                .lock()
+                .await
+                .check_start(uuid, &state.instances, &state.directory_sizes)
+                .await?;
+            instance.start(caused_by, false).await
+        }
+        BulkOperation::Stop => instance.stop(caused_by, false).await,
+        BulkOperation::Restart => instance.restart(caused_by, false).await,
+    }
+}
+
+/// Run an operation on every instance of a group at once, starts wait for the start
+/// dependencies of each instance
+async fn run_on_group(
+    state: AppState,
+    token: String,
+    group: String,
+    operation: BulkOperation,
+) -> Result<Json<Vec<BulkOperationResult>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let members = group_members(&state, &requester, &group).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    if let BulkOperation::Start = operation {
+        let dependencies = state.start_dependencies.lock().await.all();
+        let results = start_in_order(
+            &state.instances,
+            &state.event_broadcaster,
+            &dependencies,
+            members,
+            |uuid| {
+                let state = &state;
+                let requester = &requester;
+                let caused_by = caused_by.clone();
+                async move { run_on_instance(state, requester, &uuid, operation, caused_by).await }
+            },
+            |_, _| {},
+        )
+        .await?;
+        return Ok(Json(
+            results
+                .into_iter()
+                .map(|(uuid, result)| BulkOperationResult::new(uuid, result))
+                .collect(),
+        ));
+    }
+    Ok(Json(
+        join_all(members.into_iter().map(|uuid| {
+            let state = &state;
+            let requester = &requester;
+            let caused_by = caused_by.clone();
+            async move {
+                let result = run_on_instance(state, requester, &uuid, operation, caused_by).await;
+                BulkOperationResult::new(uuid, result)
+            }
+        }))
+        .await,
+    ))
+}
+
+/// The instances of a group the requester can view, the others aren't reported on
+async fn group_members(
+    state: &AppState,
+    requester: &User,
+    group: &str,
+) -> Result<Vec<InstanceUuid>, Error> {
+    let members: Vec<InstanceUuid> = state
+        .instance_groups
+        .lock()
+        .await
+        .members(group)
+        .into_iter()
+        .filter(|uuid| requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())))
+        .collect();
+    if members.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Group not found"),
+        });
+    }
+    Ok(members)
+}
+
+pub async fn start_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BulkOperationResult>>, Error> {
+    run_on_group(state, token, group, BulkOperation::Start).await
+}
+
+pub async fn stop_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BulkOperationResult>>, Error> {
+    run_on_group(state, token, group, BulkOperation::Stop).await
+}
+
+pub async fn restart_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BulkOperationResult>>, Error> {
+    run_on_group(state, token, group, BulkOperation::Restart).await
+}
+
+/// Send a command to the console of every instance of a group
+pub async fn send_command_to_group(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(group): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(command): Json<String>,
+) -> Result<Json<Vec<BulkOperationResult>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let members = group_members(&state, &requester, &group).await?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        join_all(members.into_iter().map(|uuid| {
+            let state = &state;
+            let requester = &requester;
+            let command = &command;
+            let caused_by = caused_by.clone();
+            async move {
+                let result = async {
+                    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+                    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+                        kind: ErrorKind::NotFound,
+                        source: eyre!("Instance not found"),
+                    })?;
+                    state.command_filters.lock().await.check(
+                        &uuid,
+                        &instance.name().await,
+                        &requester,
+                        command,
+                        &state.event_broadcaster,
+                    )?;
+                    instance.send_command(command, caused_by.clone()).await?;
+                    state.command_history.push(&uuid, command, caused_by);
+                    Ok::<(), Error>(())
+                }
+                .await;
+                BulkOperationResult::new(uuid, result)
+            }
+        }))
+        .await,
+    ))
+}
+
+pub fn get_instance_group_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/groups", get(get_instance_groups))
+        .route(
+            "/instance/:uuid/groups",
+            get(get_groups_of_instance).put(set_groups_of_instance),
+        )
+        .route("/instance/groups/:group/start", post(start_group))
+        .route("/instance/groups/:group/stop", post(stop_group))
+        .route("/instance/groups/:group/restart", post(restart_group))
+        .route(
+            "/instance/groups/:group/command",
+            post(send_command_to_group),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Path, Query, WebSocketUpgrade,
+    },
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    handlers::util::parse_bearer_token,
+    log_housekeeping::{self, LogHousekeepingReport, LogRetentionRule},
+    server_logs::{
+        self, LogFileEntry, LogFollower, DEFAULT_TAIL_LINES, LATEST_LOG_FILE_NAME, MAX_TAIL_LINES,
+    },
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+    AppState,
+};
+
+/// how often a streamed log is checked for new lines
+const LOG_STREAM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+async fn instance_path(state: &AppState, uuid: &InstanceUuid) -> Result<std::path::PathBuf, Error> {
+    Ok(state
+        .instances
+        .get(uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .path()
+        .await)
+}
+
+pub async fn get_log_retention_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LogRetentionRule>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let path = instance_path(&state, &uuid).await?;
+    Ok(Json(
+        log_housekeeping::read_log_retention_rule(&path).await?,
+    ))
+}
+
+pub async fn set_log_retention_rule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(rule): Json<LogRetentionRule>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let path = instance_path(&state, &uuid).await?;
+    log_housekeeping::write_log_retention_rule(&path, &rule).await?;
+    Ok(Json(()))
+}
+
+/// Apply the retention rule now instead of waiting for the scheduler
+pub async fn housekeep_instance_logs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<LogHousekeepingReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let path = instance_path(&state, &uuid).await?;
+    Ok(Json(log_housekeeping::housekeep_instance_logs(path).await?))
+}
+
+pub async fn list_instance_log_files(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<LogFileEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let path = instance_path(&state, &uuid).await?;
+    Ok(Json(
+        tokio::task::spawn_blocking(move || server_logs::list_log_files(&path))
+            .await
+            .context("Failed to list log files")??,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct TailQuery {
+    lines: Option<usize>,
+}
+
+/// The last lines of a log file, 200 unless asked for more
+pub async fn tail_instance_log_file(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    Query(query): Query<TailQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<String>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let lines = query
+        .lines
+        .unwrap_or(DEFAULT_TAIL_LINES)
+        .clamp(1, MAX_TAIL_LINES);
+    let path = server_logs::path_to_log_file(&instance_path(&state, &uuid).await?, &name)?;
+    Ok(Json(
+        tokio::task::spawn_blocking(move || server_logs::tail_log_file(&path, lines))
+            .await
+            .context("Failed to read log file")??,
+    ))
+}
+
+/// A key to download a log file with, as is, from `/file/:key`
+pub async fn get_instance_log_file_url(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<String, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let path = server_logs::path_to_log_file(&instance_path(&state, &uuid).await?, &name)?;
+    let key = rand_alphanumeric(32);
+    state
+        .download_urls
+        .lock()
+        .await
+        .insert(key.clone(), path.clone());
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Download,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    ));
+    Ok(key)
+}
+
+#[derive(Deserialize)]
+pub struct LogStreamQuery {
+    token: String,
+    /// lines of the log sent before the new ones
+    #[serde(default)]
+    lines: usize,
+}
+
+/// Stream the lines the server writes to its latest log, following it when it is rotated
+pub async fn stream_instance_log(
+    ws: WebSocketUpgrade,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<LogStreamQuery>,
+) -> Result<Response, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = parse_bearer_token(&query.token)
+        .and_then(|token| users_manager.try_auth(&token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    drop(users_manager);
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let path = instance_path(&state, &uuid)
+        .await?
+        .join(server_logs::LOGS_DIR_NAME)
+        .join(LATEST_LOG_FILE_NAME);
+    let lines = query.lines.min(MAX_TAIL_LINES);
+    let backfill = if lines > 0 && path.is_file() {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || server_logs::tail_log_file(&path, lines))
+            .await
+            .context("Failed to read log file")??
+    } else {
+        Vec::new()
+    };
+    let follower = LogFollower::new(path).await;
+    Ok(ws.on_upgrade(move |socket| log_stream_ws(socket, backfill, follower)))
+}
+
+async fn log_stream_ws(socket: WebSocket, backfill: Vec<String>, mut follower: LogFollower) {
+    let (mut sender, mut receiver) = socket.split();
+    for line in backfill {
+        if sender.send(Message::Text(line)).await.is_err() {
+            return;
+        }
+    }
+    let mut interval = tokio::time::interval(LOG_STREAM_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            message = receiver.next() => {
+                if matches!(message, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    return;
+                }
+            }
+            _ = interval.tick() => {
+                let lines = match follower.poll().await {
+                    Ok(lines) => lines,
+                    Err(e) => {
+                        error!("Failed to follow log file : {e}");
+                        let _ = sender.close().await;
+                        return;
+                    }
+                };
+                for line in lines {
+                    if sender.send(Message::Text(line)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn get_instance_logs_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/logs/retention",
+            get(get_log_retention_rule).put(set_log_retention_rule),
+        )
+        .route(
+            "/instance/:uuid/logs/housekeep",
+            post(housekeep_instance_logs),
+        )
+        .route("/instance/:uuid/logs/files", get(list_instance_log_files))
+        .route(
+            "/instance/:uuid/logs/files/:name/tail",
+            get(tail_instance_log_file),
+        )
+        .route(
+            "/instance/:uuid/logs/files/:name/url",
+            get(get_instance_log_file_url),
+        )
+        .route("/instance/:uuid/logs/stream", get(stream_instance_log))
+        .with_state(state)
+}
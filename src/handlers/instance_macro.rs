@@ -12,6 +12,8 @@ use crate::{
     error::{Error, ErrorKind},
     events::CausedBy,
     macro_executor::MacroPID,
+    macro_sandbox::{MacroCapability, MacroGrant},
+    macro_triggers::{MacroTrigger, MacroTriggerConfig},
     traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
     types::InstanceUuid,
     AppState,
@@ -24,8 +26,7 @@ pub async fn get_instance_task_list(
 ) -> Result<Json<Vec<TaskEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -40,8 +41,7 @@ pub async fn get_instance_macro_list(
 ) -> Result<Json<Vec<MacroEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -56,8 +56,7 @@ pub async fn get_instance_history_list(
 ) -> Result<Json<Vec<HistoryEntry>>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
-    let instances = state.instances.lock().await;
-    let instance = instances.get(&uuid).ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -73,8 +72,7 @@ pub async fn run_macro(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
-    let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -98,8 +96,7 @@ pub async fn kill_macro(
 ) -> Result<Json<()>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
     requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
-    let mut instances = state.instances.lock().await;
-    let instance = instances.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -107,11 +104,154 @@ pub async fn kill_macro(
     Ok(Json(()))
 }
 
+pub async fn get_macro_triggers(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<MacroTrigger>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state.macro_triggers.lock().await.triggers(&uuid).to_vec(),
+    ))
+}
+
+/// Fail unless the instance has a macro called `macro_name`
+async fn check_macro_exists(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    macro_name: &str,
+) -> Result<(), Error> {
+    let instance = state.instances.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !instance
+        .get_macro_list()
+        .await?
+        .iter()
+        .any(|entry| entry.name == macro_name)
+    {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Macro {macro_name} not found"),
+        });
+    }
+    Ok(())
+}
+
+pub async fn add_macro_trigger(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<MacroTriggerConfig>,
+) -> Result<Json<MacroTrigger>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    check_macro_exists(&state, &uuid, &config.macro_name).await?;
+    Ok(Json(
+        state
+            .macro_triggers
+            .lock()
+            .await
+            .add_trigger(&uuid, config)
+            .await?,
+    ))
+}
+
+pub async fn update_macro_trigger(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, trigger_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<MacroTriggerConfig>,
+) -> Result<Json<MacroTrigger>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    check_macro_exists(&state, &uuid, &config.macro_name).await?;
+    Ok(Json(
+        state
+            .macro_triggers
+            .lock()
+            .await
+            .update_trigger(&uuid, &trigger_id, config)
+            .await?,
+    ))
+}
+
+pub async fn remove_macro_trigger(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, trigger_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    state
+        .macro_triggers
+        .lock()
+        .await
+        .remove_trigger(&uuid, &trigger_id)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_macro_grant(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, macro_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<MacroGrant>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    check_macro_exists(&state, &uuid, &macro_name).await?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.macro_grant(&macro_name).await?))
+}
+
+pub async fn set_macro_grant(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, macro_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(grant): Json<MacroGrant>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessMacro(Some(uuid.clone())))?;
+    if grant.allows(MacroCapability::HostAccess) {
+        requester.try_owner("grant a macro host access")?;
+    }
+    check_macro_exists(&state, &uuid, &macro_name).await?;
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_macro_grant(&macro_name, grant).await?;
+    Ok(Json(()))
+}
+
 pub fn get_instance_macro_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/macro/run/:macro_name", put(run_macro))
         .route("/instance/:uuid/macro/kill/:pid", put(kill_macro))
         .route("/instance/:uuid/macro/list", get(get_instance_macro_list))
+        .route(
+            "/instance/:uuid/macro/triggers",
+            get(get_macro_triggers).post(add_macro_trigger),
+        )
+        .route(
+            "/instance/:uuid/macro/triggers/:trigger_id",
+            put(update_macro_trigger).delete(remove_macro_trigger),
+        )
+        .route(
+            "/instance/:uuid/macro/grant/:macro_name",
+            get(get_macro_grant).put(set_macro_grant),
+        )
         .route("/instance/:uuid/task/list", get(get_instance_task_list))
         .route(
             "/instance/:uuid/history/list",
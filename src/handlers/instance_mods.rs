@@ -0,0 +1,273 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    implementations::minecraft::{
+        memory::MemoryRecommendation,
+        mods::ModInfo,
+        spark::{MAX_PROFILE_SECONDS, MIN_PROFILE_SECONDS},
+        MinecraftInstance,
+    },
+    prelude::GameInstance,
+    traits::t_configurable::TConfigurable,
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+async fn minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<MinecraftInstance, Error> {
+    match state.instances.get(uuid) {
+        Some(GameInstance::MinecraftInstance(instance)) => Ok(instance),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support mods"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        }),
+    }
+}
+
+pub async fn list_instance_mods(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.list_mods().await?))
+}
+
+/// The heap recommended for the installed mods, with warnings about the configured heap
+pub async fn get_instance_memory_recommendation(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<MemoryRecommendation>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.memory_recommendation().await?))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct InstallModFromUrl {
+    /// a Modrinth version page, or a direct Modrinth or CurseForge download link
+    url: String,
+}
+
+pub async fn install_instance_mod_from_url(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<InstallModFromUrl>,
+) -> Result<Json<ModInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.install_mod_from_url(&body.url).await?))
+}
+
+pub async fn upload_instance_mods(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<ModInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    let mut installed = Vec::new();
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .context("Failed to read multipart field")?
+    {
+        let name = field
+            .file_name()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Missing file name"),
+            })?
+            .to_string();
+        let content = field
+            .bytes()
+            .await
+            .context(format!("Failed to read {name}"))?;
+        installed.push(instance.install_mod_from_file(&name, &content).await?);
+    }
+    Ok(Json(installed))
+}
+
+pub async fn enable_instance_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ModInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.set_mod_enabled(&file_name, true).await?))
+}
+
+pub async fn disable_instance_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ModInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.set_mod_enabled(&file_name, false).await?))
+}
+
+pub async fn delete_instance_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance.delete_mod(&file_name).await?;
+    Ok(Json(()))
+}
+
+/// Install the spark profiler, it's loaded on the next start
+pub async fn install_instance_spark(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ModInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteResource(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.install_spark().await?))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ProfileInstance {
+    /// how long to profile for, in seconds
+    duration: u32,
+}
+
+/// Profile the server with spark. The session is reported through a progression event, and the
+/// link to the report is sent in an instance event once it's uploaded
+pub async fn profile_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<ProfileInstance>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    if !(MIN_PROFILE_SECONDS..=MAX_PROFILE_SECONDS).contains(&body.duration) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Profiling takes between {MIN_PROFILE_SECONDS} and {MAX_PROFILE_SECONDS} seconds"
+            ),
+        });
+    }
+    if !instance.spark_installed().await? {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("spark is not installed, install it and restart the server first"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    tokio::spawn(async move {
+        let event_broadcaster = state.event_broadcaster.clone();
+        let instance_name = instance.name().await;
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Profiling {instance_name} for {} seconds", body.duration),
+            None,
+            None,
+            caused_by.clone(),
+        );
+        event_broadcaster.send(progression_start_event);
+        match instance
+            .profile_with_spark(body.duration, &event_broadcaster, caused_by.clone())
+            .await
+        {
+            Ok(report_url) => {
+                event_broadcaster.send(Event::new_progression_event_end(
+                    event_id,
+                    true,
+                    Some(&format!("Profiler report: {report_url}")),
+                    None,
+                ));
+                event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_uuid: uuid,
+                        instance_name,
+                        instance_event_inner: InstanceEventInner::ProfilerReport {
+                            report_url,
+                            duration: body.duration,
+                        },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+            }
+            Err(e) => event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Profiling failed: {e}")),
+                None,
+            )),
+        }
+    });
+    Ok(Json(()))
+}
+
+pub fn get_instance_mods_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/mods", get(list_instance_mods))
+        .route(
+            "/instance/:uuid/mods/memory",
+            get(get_instance_memory_recommendation),
+        )
+        .route(
+            "/instance/:uuid/mods/url",
+            post(install_instance_mod_from_url),
+        )
+        .route("/instance/:uuid/mods/upload", post(upload_instance_mods))
+        .layer(DefaultBodyLimit::disable())
+        .route(
+            "/instance/:uuid/mods/:file_name/enable",
+            put(enable_instance_mod),
+        )
+        .route(
+            "/instance/:uuid/mods/:file_name/disable",
+            put(disable_instance_mod),
+        )
+        .route(
+            "/instance/:uuid/mods/:file_name",
+            delete(delete_instance_mod),
+        )
+        .route("/instance/:uuid/spark", post(install_instance_spark))
+        .route("/instance/:uuid/spark/profile", post(profile_instance))
+        .with_state(state)
+}
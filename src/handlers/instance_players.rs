@@ -1,23 +1,53 @@
 use std::collections::HashSet;
 
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
 
 use crate::{
+    auth::user::UserAction,
     error::{Error, ErrorKind},
+    events::CausedBy,
+    implementations::minecraft::{
+        player_admin::{BannedPlayer, OpEntry, WhitelistEntry},
+        MinecraftInstance,
+    },
+    player_database::PlayerRecord,
+    prelude::GameInstance,
     traits::t_player::{Player, TPlayerManagement},
     types::InstanceUuid,
     AppState,
 };
 
+async fn minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+) -> Result<MinecraftInstance, Error> {
+    match state.instances.get(uuid) {
+        Some(GameInstance::MinecraftInstance(instance)) => Ok(instance),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support player administration"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        }),
+    }
+}
+
 pub async fn get_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
 ) -> Result<Json<u32>, Error> {
     state
         .instances
-        .lock()
-        .await
         .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
@@ -34,8 +64,6 @@ pub async fn get_max_player_count(
 ) -> Result<Json<u32>, Error> {
     state
         .instances
-        .lock()
-        .await
         .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
@@ -53,9 +81,7 @@ pub async fn set_max_player_count(
 ) -> Result<Json<()>, Error> {
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
@@ -71,9 +97,7 @@ pub async fn get_player_list(
 ) -> Result<Json<HashSet<Player>>, Error> {
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
@@ -83,6 +107,213 @@ pub async fn get_player_list(
         .map(Json)
 }
 
+/// Every player who joined the instance and where they joined from. Locations are as sensitive
+/// as the addresses in the console, so they need console access.
+pub async fn get_player_records(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PlayerRecord>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.player_database.lock().await.players(&uuid)))
+}
+
+pub async fn get_whitelist(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<WhitelistEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.whitelist().await?))
+}
+
+pub async fn whitelist_add(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .whitelist_add(
+            &name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn whitelist_remove(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .whitelist_remove(
+            &name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_ops(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<OpEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.ops().await?))
+}
+
+pub async fn op_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .op_player(
+            &name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn deop_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .deop_player(
+            &name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn get_banned_players(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BannedPlayer>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    Ok(Json(instance.banned_players().await?))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct PlayerBan {
+    pub reason: Option<String>,
+}
+
+pub async fn ban_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<PlayerBan>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .ban_player(
+            &name,
+            body.reason,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn pardon_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .pardon_player(
+            &name,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct PlayerKick {
+    pub message: Option<String>,
+}
+
+pub async fn kick_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<PlayerKick>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = minecraft_instance(&state, &uuid).await?;
+    instance
+        .kick_player(
+            &name,
+            body.message,
+            CausedBy::User {
+                user_id: requester.uid,
+                user_name: requester.username,
+            },
+        )
+        .await?;
+    Ok(Json(()))
+}
+
 pub fn get_instance_players_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/players/count", get(get_player_count))
@@ -91,5 +322,22 @@ pub fn get_instance_players_routes(state: AppState) -> Router {
             get(get_max_player_count).put(set_max_player_count),
         )
         .route("/instance/:uuid/players", get(get_player_list))
+        .route("/instance/:uuid/players/history", get(get_player_records))
+        .route("/instance/:uuid/players/whitelist", get(get_whitelist))
+        .route(
+            "/instance/:uuid/players/whitelist/:name",
+            post(whitelist_add).delete(whitelist_remove),
+        )
+        .route("/instance/:uuid/players/ops", get(get_ops))
+        .route(
+            "/instance/:uuid/players/ops/:name",
+            post(op_player).delete(deop_player),
+        )
+        .route("/instance/:uuid/players/banned", get(get_banned_players))
+        .route(
+            "/instance/:uuid/players/banned/:name",
+            post(ban_player).delete(pardon_player),
+        )
+        .route("/instance/:uuid/players/kick/:name", post(kick_player))
         .with_state(state)
 }
@@ -1,6 +1,8 @@
+use std::time::Duration;
+
 use axum::{
-    extract::Path,
-    routing::{get, post, put},
+    extract::{Path, Query},
+    routing::{delete, get, post, put},
     Router,
 };
 
@@ -8,17 +10,26 @@ use axum::Json;
 use axum_auth::AuthBearer;
 
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 use crate::{
     auth::user::UserAction,
+    command_filter::CommandFilter,
+    command_history::CommandHistoryEntry,
+    command_queue::{QueuedCommand, QueuedCommandConfig},
+    command_sequence::{CommandSequence, CommandSequenceConfig},
     error::{Error, ErrorKind},
     events::CausedBy,
+    readiness::wait_until_ready,
     types::InstanceUuid,
 };
 
 use crate::{
-    traits::{t_configurable::TConfigurable, t_server::TServer},
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{CommandInfo, Readiness, TServer},
+    },
     AppState,
 };
 
@@ -33,8 +44,7 @@ pub async fn start_instance(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -42,10 +52,16 @@ pub async fn start_instance(
 
     if state.port_manager.lock().await.port_status(port).is_in_use {
         return Err(Error {
-            kind: ErrorKind::Internal,
+            kind: ErrorKind::Conflict,
             source: eyre!("Port {} is in use", port),
         });
     }
+    state
+        .user_quotas
+        .lock()
+        .await
+        .check_start(&uuid, &state.instances, &state.directory_sizes)
+        .await?;
 
     instance.start(caused_by, false).await?;
     Ok(Json(()))
@@ -64,9 +80,7 @@ pub async fn stop_instance(
     };
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
@@ -89,8 +103,7 @@ pub async fn restart_instance(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    let mut instance_list = state.instances.lock().await;
-    let instance = instance_list.get_mut(&uuid).ok_or_else(|| Error {
+    let mut instance = state.instances.get(&uuid).ok_or_else(|| Error {
         kind: ErrorKind::NotFound,
         source: eyre!("Instance not found"),
     })?;
@@ -112,9 +125,7 @@ pub async fn kill_instance(
     };
     state
         .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
+        .get(&uuid)
         .ok_or_else(|| Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
@@ -136,18 +147,107 @@ pub async fn send_command(
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
     };
-    state
-        .instances
-        .lock()
-        .await
-        .get_mut(&uuid)
-        .ok_or_else(|| Error {
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    state.command_filters.lock().await.check(
+        &uuid,
+        &instance.name().await,
+        &requester,
+        &command,
+        &state.event_broadcaster,
+    )?;
+    instance.send_command(&command, caused_by.clone()).await?;
+    state.command_history.push(&uuid, &command, caused_by);
+    Ok(Json(()))
+}
+
+/// The regexes deciding which commands users other than the owner can send to the console
+pub async fn get_command_filter(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<CommandFilter>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
             kind: ErrorKind::NotFound,
             source: eyre!("Instance not found"),
-        })?
-        .send_command(&command, caused_by)
-        .await
-        .map(|_| Json(()))
+        });
+    }
+    Ok(Json(state.command_filters.lock().await.get(&uuid)))
+}
+
+/// Replace the command filter of the instance, an empty filter lets every command through
+pub async fn set_command_filter(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(filter): Json<CommandFilter>,
+) -> Result<Json<CommandFilter>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManagePermission)?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .command_filters
+            .lock()
+            .await
+            .set(&uuid, filter)
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct CommandHistoryQuery {
+    /// only the newest commands
+    pub limit: Option<usize>,
+}
+
+/// Commands users sent to the console of the instance, oldest first
+pub async fn get_command_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<CommandHistoryQuery>,
+) -> Result<Json<Vec<CommandHistoryEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.command_history.get(&uuid, query.limit)))
+}
+
+/// Commands the instance knows of, for autocomplete
+pub async fn get_known_commands(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<CommandInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instances
+            .get(&uuid)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?
+            .known_commands()
+            .await,
+    ))
 }
 
 pub async fn get_instance_state(
@@ -165,8 +265,6 @@ pub async fn get_instance_state(
     Ok(Json(json!(
         state
             .instances
-            .lock()
-            .await
             .get(&uuid)
             .ok_or_else(|| Error {
                 kind: ErrorKind::NotFound,
@@ -177,6 +275,187 @@ pub async fn get_instance_state(
     )))
 }
 
+/// The longest a request can wait for an instance to be ready
+static MAX_READINESS_WAIT: u64 = 600;
+
+#[derive(Deserialize)]
+pub struct ReadinessQuery {
+    /// seconds to wait for the instance to be ready, returns right away if not set
+    pub wait: Option<u64>,
+}
+
+/// Whether the instance is done starting. With `wait`, answers once it is ready, stopped or the
+/// wait is over, so automation doesn't have to poll.
+pub async fn get_instance_readiness(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ReadinessQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Readiness>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let wait = match query.wait {
+        Some(wait) => wait.min(MAX_READINESS_WAIT),
+        None => return Ok(Json(instance.readiness().await)),
+    };
+    match wait_until_ready(
+        &state.event_broadcaster,
+        &uuid,
+        async { (instance.state().await, instance.readiness().await) },
+        Duration::from_secs(wait),
+    )
+    .await
+    {
+        Some(readiness) => Ok(Json(readiness)),
+        None => Ok(Json(instance.readiness().await)),
+    }
+}
+
+/// Commands waiting for the instance to be ready, in the order they will be sent
+pub async fn get_command_queue(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<QueuedCommand>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .command_queues
+            .lock()
+            .await
+            .queue(&uuid, chrono::Utc::now().timestamp()),
+    ))
+}
+
+/// Queue a command to send the next time the instance is ready, e.g. while it is stopped
+pub async fn queue_command(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<QueuedCommandConfig>,
+) -> Result<Json<QueuedCommand>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    state.command_filters.lock().await.check(
+        &uuid,
+        &instance.name().await,
+        &requester,
+        &config.command,
+        &state.event_broadcaster,
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        state
+            .command_queues
+            .lock()
+            .await
+            .push(&uuid, config, caused_by)
+            .await?,
+    ))
+}
+
+pub async fn remove_queued_command(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, command_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    state
+        .command_queues
+        .lock()
+        .await
+        .remove(&uuid, &command_id)
+        .await
+        .map(|_| Json(()))
+}
+
+/// Command sequences still sending commands to the instance
+pub async fn get_command_sequences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<CommandSequence>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.command_sequences.list(&uuid)))
+}
+
+/// Send commands one after the other with a delay before each, e.g. warning players, kicking them
+/// and stopping the server, without the client having to time the requests
+pub async fn start_command_sequence(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<CommandSequenceConfig>,
+) -> Result<Json<CommandSequence>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let instance_name = instance.name().await;
+    let command_filters = state.command_filters.lock().await;
+    for step in &config.steps {
+        command_filters.check(
+            &uuid,
+            &instance_name,
+            &requester,
+            &step.command,
+            &state.event_broadcaster,
+        )?;
+    }
+    drop(command_filters);
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(state.command_sequences.start(
+        state.instances.clone(),
+        uuid,
+        config,
+        caused_by,
+    )?))
+}
+
+pub async fn cancel_command_sequence(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, sequence_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    state
+        .command_sequences
+        .cancel(&uuid, &sequence_id)
+        .map(|_| Json(()))
+}
+
 pub fn get_instance_server_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/start", put(start_instance))
@@ -184,6 +463,29 @@ pub fn get_instance_server_routes(state: AppState) -> Router {
         .route("/instance/:uuid/restart", put(restart_instance))
         .route("/instance/:uuid/kill", put(kill_instance))
         .route("/instance/:uuid/console", post(send_command))
+        .route("/instance/:uuid/console/history", get(get_command_history))
+        .route(
+            "/instance/:uuid/console/filter",
+            get(get_command_filter).put(set_command_filter),
+        )
+        .route("/instance/:uuid/console/commands", get(get_known_commands))
         .route("/instance/:uuid/state", get(get_instance_state))
+        .route("/instance/:uuid/readiness", get(get_instance_readiness))
+        .route(
+            "/instance/:uuid/console/queue",
+            get(get_command_queue).post(queue_command),
+        )
+        .route(
+            "/instance/:uuid/console/queue/:command_id",
+            delete(remove_queued_command),
+        )
+        .route(
+            "/instance/:uuid/console/sequence",
+            get(get_command_sequences).post(start_command_sequence),
+        )
+        .route(
+            "/instance/:uuid/console/sequence/:sequence_id",
+            delete(cancel_command_sequence),
+        )
         .with_state(state)
 }
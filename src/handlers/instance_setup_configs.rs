@@ -1,29 +1,37 @@
+use crate::auth::user::UserAction;
 use crate::error::Error;
 use crate::error::ErrorKind;
 use crate::implementations::generic;
 use crate::implementations::minecraft;
+use crate::implementations::minecraft::memory::{
+    check_heap, host_memory, recommend_heap, MemoryRecommendation,
+};
+use crate::implementations::minecraft::version_cache::{self, VersionListStatus};
 use crate::minecraft::FlavourKind;
 use crate::traits::t_configurable::manifest::SetupManifest;
 use crate::traits::t_configurable::GameType;
 use crate::AppState;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::routing::get;
+use axum::routing::post;
 use axum::routing::put;
 use axum::Json;
 use axum::Router;
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 use serde::Deserialize;
 use serde::Serialize;
 use ts_rs::TS;
 
 #[allow(clippy::enum_variant_names)]
-#[derive(Serialize, Deserialize, TS, Clone, Copy)]
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug)]
 #[ts(export)]
 pub enum HandlerGameType {
     MinecraftJavaVanilla,
     MinecraftFabric,
     MinecraftForge,
     MinecraftPaper,
+    MinecraftQuilt,
     MinecraftBedrock,
 }
 
@@ -34,6 +42,7 @@ impl From<HandlerGameType> for GameType {
             HandlerGameType::MinecraftFabric => Self::MinecraftJava,
             HandlerGameType::MinecraftForge => Self::MinecraftJava,
             HandlerGameType::MinecraftPaper => Self::MinecraftJava,
+            HandlerGameType::MinecraftQuilt => Self::MinecraftJava,
             HandlerGameType::MinecraftBedrock => Self::MinecraftBedrock,
         }
     }
@@ -48,6 +57,7 @@ impl TryFrom<HandlerGameType> for FlavourKind {
             HandlerGameType::MinecraftFabric => Self::Fabric,
             HandlerGameType::MinecraftForge => Self::Forge,
             HandlerGameType::MinecraftPaper => Self::Paper,
+            HandlerGameType::MinecraftQuilt => Self::Quilt,
             HandlerGameType::MinecraftBedrock => {
                 return Err(Error {
                     kind: ErrorKind::BadRequest,
@@ -58,12 +68,33 @@ impl TryFrom<HandlerGameType> for FlavourKind {
     }
 }
 
+impl TryFrom<FlavourKind> for HandlerGameType {
+    type Error = Error;
+
+    fn try_from(value: FlavourKind) -> Result<Self, Error> {
+        Ok(match value {
+            FlavourKind::Vanilla => Self::MinecraftJavaVanilla,
+            FlavourKind::Fabric => Self::MinecraftFabric,
+            FlavourKind::Forge => Self::MinecraftForge,
+            FlavourKind::Paper => Self::MinecraftPaper,
+            FlavourKind::Quilt => Self::MinecraftQuilt,
+            FlavourKind::Spigot => {
+                return Err(Error {
+                    kind: ErrorKind::UnsupportedOperation,
+                    source: eyre!("Spigot instances cannot be set up by Lodestone"),
+                })
+            }
+        })
+    }
+}
+
 pub async fn get_available_games() -> Json<Vec<HandlerGameType>> {
     Json(vec![
         HandlerGameType::MinecraftJavaVanilla,
         HandlerGameType::MinecraftFabric,
         HandlerGameType::MinecraftForge,
         HandlerGameType::MinecraftPaper,
+        HandlerGameType::MinecraftQuilt,
     ])
 }
 
@@ -75,6 +106,47 @@ pub async fn get_setup_manifest(
         .map(Json)
 }
 
+#[derive(Deserialize)]
+pub struct SetupMemoryQuery {
+    /// the heap the user picked, in megabytes
+    pub min_ram: Option<u32>,
+    pub max_ram: Option<u32>,
+}
+
+/// The heap recommended for a new instance, with warnings about the heap the user picked
+pub async fn get_setup_memory_recommendation(
+    Path(game_type): Path<HandlerGameType>,
+    Query(query): Query<SetupMemoryQuery>,
+) -> Result<Json<MemoryRecommendation>, Error> {
+    let flavour: FlavourKind = game_type.try_into()?;
+    let mut recommendation = recommend_heap(&flavour, &[], host_memory());
+    if let (Some(min_ram), Some(max_ram)) = (query.min_ram, query.max_ram) {
+        recommendation.warnings.extend(check_heap(
+            min_ram,
+            max_ram,
+            recommendation.max_ram,
+            recommendation.host_memory,
+        ));
+    }
+    Ok(Json(recommendation))
+}
+
+#[derive(Deserialize)]
+pub struct LoaderVersionsQuery {
+    /// the minecraft version
+    pub version: String,
+}
+
+/// The versions of the mod loader a new instance can be pinned to
+pub async fn get_setup_loader_versions(
+    Path(game_type): Path<HandlerGameType>,
+    Query(query): Query<LoaderVersionsQuery>,
+) -> Result<Json<Vec<String>>, Error> {
+    minecraft::MinecraftInstance::loader_versions(&game_type.try_into()?, &query.version)
+        .await
+        .map(Json)
+}
+
 #[derive(Deserialize)]
 pub struct GenericSetupManifestBody {
     pub url: String,
@@ -89,10 +161,40 @@ pub async fn get_generic_setup_manifest(
         .map(Json)
 }
 
+/// The cached version lists offered by the setup manifests
+pub async fn get_version_cache(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<VersionListStatus>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    Ok(Json(version_cache::status()))
+}
+
+/// Fetch every cached version list again, e.g. right after a release
+pub async fn refresh_version_cache(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<VersionListStatus>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    Ok(Json(version_cache::refresh_all().await))
+}
+
 pub fn get_instance_setup_config_routes(appstate: AppState) -> Router {
     Router::new()
         .route("/games", get(get_available_games))
         .route("/setup_manifest/:game_type", get(get_setup_manifest))
+        .route(
+            "/setup_manifest/:game_type/memory",
+            get(get_setup_memory_recommendation),
+        )
+        .route(
+            "/setup_manifest/:game_type/loader_versions",
+            get(get_setup_loader_versions),
+        )
         .route("/generic_setup_manifest", put(get_generic_setup_manifest))
+        .route("/version_cache", get(get_version_cache))
+        .route("/version_cache/refresh", post(refresh_version_cache))
         .with_state(appstate)
 }
@@ -0,0 +1,158 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    instance_sync::{InstanceSync, SyncConfig, SyncRecord},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_instance_sync(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<InstanceSync>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.instance_syncs.lock().await.get(&uuid)))
+}
+
+pub async fn set_instance_sync(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<SyncConfig>,
+) -> Result<Json<InstanceSync>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .instance_syncs
+            .lock()
+            .await
+            .set_config(&uuid, config)
+            .await?,
+    ))
+}
+
+pub async fn remove_instance_sync(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instance_syncs
+        .lock()
+        .await
+        .remove_instance(&uuid)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Overwrite the synced files of the instance with the ones on the remote
+pub async fn pull_instance_sync(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<SyncRecord>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let path_to_instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .path()
+        .await;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        state
+            .instance_syncs
+            .lock()
+            .await
+            .pull(&uuid, &path_to_instance, caused_by)
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SyncPushConfig {
+    /// the commit message, one is made up if left out
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Commit the synced files of the instance and push them to the remote
+pub async fn push_instance_sync(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    config: Option<Json<SyncPushConfig>>,
+) -> Result<Json<SyncRecord>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    let path_to_instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .path()
+        .await;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let message = config.and_then(|Json(config)| config.message);
+    Ok(Json(
+        state
+            .instance_syncs
+            .lock()
+            .await
+            .push(&uuid, &path_to_instance, message, caused_by)
+            .await?,
+    ))
+}
+
+pub fn get_instance_sync_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/sync",
+            get(get_instance_sync)
+                .put(set_instance_sync)
+                .delete(remove_instance_sync),
+        )
+        .route("/instance/:uuid/sync/pull", post(pull_instance_sync))
+        .route("/instance/:uuid/sync/push", post(push_instance_sync))
+        .with_state(state)
+}
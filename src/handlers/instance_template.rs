@@ -0,0 +1,168 @@
+use axum::{
+    extract::{Path, Query},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    instance_template::{self, InstanceTemplate},
+    prelude::GameInstance,
+    traits::t_configurable::manifest::{ConfigurableValue, SetupValue},
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::{
+    instance::{setup_minecraft_instance, StorageRootQuery},
+    instance_setup_configs::HandlerGameType,
+};
+
+pub async fn list_instance_templates(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstanceTemplate>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    Ok(Json(instance_template::list_templates().await?))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewInstanceTemplate {
+    pub name: String,
+    pub game_type: HandlerGameType,
+    pub setup_value: SetupValue,
+}
+
+pub async fn create_instance_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(template): Json<NewInstanceTemplate>,
+) -> Result<Json<InstanceTemplate>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    Ok(Json(
+        instance_template::save_template(template.name, template.game_type, template.setup_value)
+            .await?,
+    ))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceTemplateName {
+    pub name: String,
+}
+
+/// Save the setup config of an existing instance as a template
+pub async fn save_instance_as_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<InstanceTemplateName>,
+) -> Result<Json<InstanceTemplate>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let (flavour, setup_value) = match instance {
+        GameInstance::MinecraftInstance(instance) => instance.setup_value().await,
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Only minecraft instances can be saved as templates"),
+            })
+        }
+    };
+    Ok(Json(
+        instance_template::save_template(body.name, flavour.try_into()?, setup_value).await?,
+    ))
+}
+
+pub async fn delete_instance_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    instance_template::delete_template(&id).await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceFromTemplate {
+    pub name: String,
+    /// defaults to the first free port from the port of the template
+    pub port: Option<u32>,
+    /// the Minecraft EULA is accepted by whoever creates the instance, not by the template
+    #[serde(default)]
+    pub accept_eula: bool,
+}
+
+pub async fn create_instance_from_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<StorageRootQuery>,
+    Json(body): Json<InstanceFromTemplate>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instances_dir = query.instances_dir(&state).await?;
+    let template = instance_template::get_template(&id).await?;
+    let mut setup_value = template.setup_value;
+    setup_value.name = body.name;
+    let port = match body.port {
+        Some(port) => port,
+        None => {
+            let template_port = setup_value
+                .get_unique_setting("port")
+                .and_then(|setting| setting.get_value())
+                .and_then(|value| value.try_as_unsigned_integer().ok())
+                .unwrap_or(25565);
+            // the setup reserves the port once it succeeds
+            let mut port_manager = state.port_manager.lock().await;
+            let port = port_manager.allocate(template_port);
+            port_manager.deallocate(port);
+            port
+        }
+    };
+    setup_value.set_unique_setting("port", Some(ConfigurableValue::UnsignedInteger(port)));
+    setup_value.insert_setting(
+        "section_1",
+        "accept_eula",
+        Some(ConfigurableValue::Boolean(body.accept_eula)),
+    );
+    setup_minecraft_instance(
+        state,
+        requester,
+        template.game_type,
+        setup_value,
+        instances_dir,
+    )
+    .await
+    .map(Json)
+}
+
+pub fn get_instance_template_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance_template/list", get(list_instance_templates))
+        .route("/instance_template", post(create_instance_template))
+        .route("/instance_template/:id", delete(delete_instance_template))
+        .route(
+            "/instance_template/:id/create",
+            post(create_instance_from_template),
+        )
+        .route("/instance/:uuid/template", post(save_instance_as_template))
+        .with_state(state)
+}
@@ -0,0 +1,109 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    instance_webhooks::{InstanceWebhook, InstanceWebhookConfig},
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_instance_webhooks(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstanceWebhook>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .instance_webhooks
+            .lock()
+            .await
+            .webhooks(&uuid)
+            .to_vec(),
+    ))
+}
+
+pub async fn add_instance_webhook(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<InstanceWebhookConfig>,
+) -> Result<Json<InstanceWebhook>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .instance_webhooks
+            .lock()
+            .await
+            .add_webhook(&uuid, config)
+            .await?,
+    ))
+}
+
+pub async fn update_instance_webhook(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, webhook_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<InstanceWebhookConfig>,
+) -> Result<Json<InstanceWebhook>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    Ok(Json(
+        state
+            .instance_webhooks
+            .lock()
+            .await
+            .update_webhook(&uuid, &webhook_id, config)
+            .await?,
+    ))
+}
+
+pub async fn remove_instance_webhook(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, webhook_id)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    state
+        .instance_webhooks
+        .lock()
+        .await
+        .remove_webhook(&uuid, &webhook_id)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_webhook_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/webhooks",
+            get(get_instance_webhooks).post(add_instance_webhook),
+        )
+        .route(
+            "/instance/:uuid/webhooks/:webhook_id",
+            put(update_instance_webhook).delete(remove_instance_webhook),
+        )
+        .with_state(state)
+}
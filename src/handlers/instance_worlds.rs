@@ -0,0 +1,107 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    events::CausedBy,
+    implementations::minecraft::worlds::{WorldInfo, WorldReset, WorldResetReport},
+    trash::TrashEntry,
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::instance_config::get_minecraft_instance;
+
+pub async fn get_worlds(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<WorldInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .worlds()
+            .await?,
+    ))
+}
+
+/// Returns whether the server was restarted into the world
+pub async fn switch_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(name): Json<String>,
+) -> Result<Json<bool>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .switch_world(name, caused_by)
+            .await?,
+    ))
+}
+
+pub async fn reset_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(reset): Json<WorldReset>,
+) -> Result<Json<WorldResetReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    requester.try_action(&UserAction::StopInstance(uuid.clone()))?;
+    if reset.seed.is_some() {
+        requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .reset_world(name, reset, caused_by)
+            .await?,
+    ))
+}
+
+pub async fn delete_world(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid,
+        user_name: requester.username,
+    };
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .delete_world(name, caused_by)
+            .await?,
+    ))
+}
+
+pub fn get_instance_worlds_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/worlds", get(get_worlds))
+        .route("/instance/:uuid/worlds/active", put(switch_world))
+        .route("/instance/:uuid/worlds/:name", delete(delete_world))
+        .route("/instance/:uuid/worlds/:name/reset", post(reset_world))
+        .with_state(state)
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    implementations::minecraft::java::{
+        detect_runtimes, install_runtime, InstanceJavaRuntime, JavaRuntime,
+    },
+    prelude::path_to_binaries,
+    types::InstanceUuid,
+    AppState,
+};
+
+use super::instance_config::get_minecraft_instance;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct JavaRuntimePin {
+    /// the java to run the server on, the downloaded JRE its version needs if `None`
+    pub java: Option<PathBuf>,
+}
+
+pub async fn get_java_runtimes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<JavaRuntime>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(detect_runtimes(path_to_binaries()).await))
+}
+
+pub async fn install_java_runtime(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(major_version): Path<u64>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<JavaRuntime>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("install Java runtimes")?;
+    install_runtime(path_to_binaries(), major_version)
+        .await
+        .map(Json)
+}
+
+pub async fn get_instance_java_runtime(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceJavaRuntime>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    Ok(Json(
+        get_minecraft_instance(&state, &uuid)
+            .await?
+            .java_runtime()
+            .await,
+    ))
+}
+
+pub async fn pin_instance_java_runtime(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(pin): Json<JavaRuntimePin>,
+) -> Result<Json<InstanceJavaRuntime>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    get_minecraft_instance(&state, &uuid)
+        .await?
+        .pin_java_runtime(pin.java)
+        .await
+        .map(Json)
+}
+
+pub fn get_java_runtime_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/java/runtimes", get(get_java_runtimes))
+        .route("/java/runtimes/:major_version", post(install_java_runtime))
+        .route(
+            "/instance/:uuid/java",
+            get(get_instance_java_runtime).put(pin_instance_java_runtime),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,73 @@
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    jobs::JobInfo,
+    types::Snowflake,
+    AppState,
+};
+
+/// The running jobs and the recently finished ones the requester can see
+pub async fn get_jobs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<JobInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let mut jobs = state.jobs.list();
+    jobs.retain(|job| job.visible_to(&requester));
+    Ok(Json(jobs))
+}
+
+pub async fn get_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<JobInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let job = state.jobs.get(&id)?;
+    if !job.visible_to(&requester) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Job not found"),
+        });
+    }
+    Ok(Json(job))
+}
+
+/// Ask a running job to stop, only the users who can write the files of its instance can, or the
+/// owner for jobs not on an instance
+pub async fn cancel_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<JobInfo>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let job = state.jobs.get(&id)?;
+    if !job.visible_to(&requester) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Job not found"),
+        });
+    }
+    if !job.can_be_cancelled_by(&requester) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You don't have permission to cancel this job"),
+        });
+    }
+    Ok(Json(state.jobs.cancel(&id)?))
+}
+
+pub fn get_jobs_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/jobs", get(get_jobs))
+        .route("/jobs/:id", get(get_job))
+        .route("/jobs/:id/cancel", post(cancel_job))
+        .with_state(state)
+}
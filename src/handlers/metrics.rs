@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_auth::AuthBearer;
+use ringbuffer::RingBufferExt;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    metrics::{render_metrics, CoreMetrics, InstanceMetrics},
+    traits::{t_configurable::TConfigurable, t_player::TPlayerManagement, t_server::TServer},
+    AppState,
+};
+
+/// Prometheus metrics of the instances the user can view and of the core itself
+pub async fn get_metrics(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let instances = state.instances.snapshot();
+    let mut latest_reports: HashMap<_, _> = state
+        .monitor_buffer
+        .lock()
+        .await
+        .iter()
+        .filter_map(|(uuid, buffer)| Some((uuid.clone(), buffer.back()?.clone())))
+        .collect();
+    let mut instance_metrics = Vec::new();
+    for (uuid, instance) in instances {
+        if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+            continue;
+        }
+        instance_metrics.push(InstanceMetrics {
+            name: instance.name().await,
+            state: instance.state().await,
+            report: latest_reports.remove(&uuid),
+            player_count: instance.get_player_count().await.ok(),
+            startup_duration: instance.readiness().await.startup_duration,
+            uuid,
+        });
+    }
+    let metrics = CoreMetrics {
+        instances: instance_metrics,
+        api_requests: state.api_requests.snapshot(),
+        events_sent: state.event_broadcaster.sent_count(),
+        events_queued: state.event_broadcaster.queued_count(),
+        event_subscribers: state.event_broadcaster.subscriber_count(),
+    };
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&metrics),
+    )
+        .into_response())
+}
+
+pub fn get_metrics_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(state)
+}
@@ -1,21 +1,59 @@
 // pub mod jar;
 // pub mod instance;
 // pub mod users;
+pub mod advisories;
+pub mod backup_destinations;
 pub mod checks;
+pub mod console_matchers;
+pub mod console_profiles;
+pub mod console_snippets;
+pub mod control_channel;
 pub mod core_info;
+pub mod diagnostics;
+pub mod downloads;
 pub mod events;
+pub mod federation;
 pub mod gateway;
 pub mod global_fs;
 pub mod global_settings;
+pub mod health;
 pub mod instance;
+pub mod instance_archive;
+pub mod instance_backup;
 pub mod instance_config;
+pub mod instance_export;
 pub mod instance_fs;
+pub mod instance_groups;
+pub mod instance_logs;
 pub mod instance_macro;
+pub mod instance_mods;
 pub mod instance_players;
 pub mod instance_server;
 pub mod instance_setup_configs;
+pub mod instance_sync;
+pub mod instance_template;
+pub mod instance_webhooks;
+pub mod instance_worlds;
+pub mod java_runtimes;
+pub mod jobs;
+pub mod metrics;
 pub mod monitor;
+pub mod network_isolation;
+pub mod notifications;
+pub mod openapi;
+pub mod overview;
+pub mod read_only;
+pub mod reservation;
 pub mod setup;
+pub mod start_dependencies;
+pub mod status_page;
+pub mod storage_roots;
+pub mod suspicious_activity;
 pub mod system;
+pub mod trash;
+pub mod uploads;
+pub mod usage_accounting;
+pub mod user_quotas;
 pub mod users;
 mod util;
+pub mod webdav;
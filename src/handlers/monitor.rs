@@ -1,11 +1,12 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    extract::{ws::WebSocket, Path, WebSocketUpgrade},
+    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
     response::Response,
     routing::get,
-    Router,
+    Json, Router,
 };
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 use futures::{SinkExt, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
@@ -13,10 +14,15 @@ use tokio::sync::Mutex;
 use tracing::error;
 
 use crate::{
+    auth::user::UserAction,
+    disk_usage::{DirectorySizes, InstanceDiskUsage},
     error::Error,
     prelude::GameInstance,
-    traits::{t_server::MonitorReport, t_server::TServer},
+    traits::{
+        t_configurable::TConfigurable, t_server::MonitorReport, t_server::State, t_server::TServer,
+    },
     types::InstanceUuid,
+    usage_history::{UsageHistoryPage, UsageHistoryQuery},
     AppState,
 };
 
@@ -25,20 +31,15 @@ pub async fn monitor(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
 ) -> Result<Response, Error> {
-    let instance = state
-        .instances
-        .lock()
-        .await
-        .get(&uuid)
-        .ok_or_else(|| Error {
-            kind: crate::error::ErrorKind::NotFound,
-            source: eyre!("Instance not found"),
-        })?
-        .to_owned();
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: crate::error::ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
     Ok(ws.on_upgrade(move |stream| {
         monitor_ws(
             stream,
             state.monitor_buffer.clone(),
+            state.directory_sizes.clone(),
             instance.to_owned(),
             uuid,
         )
@@ -48,6 +49,7 @@ pub async fn monitor(
 async fn monitor_ws(
     stream: WebSocket,
     monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
+    directory_sizes: DirectorySizes,
     instance: GameInstance,
     uuid: InstanceUuid,
 ) {
@@ -69,7 +71,12 @@ async fn monitor_ws(
     loop {
         tokio::select! {
             _ = interval.tick() => {
-                let monitor = instance.monitor().await;
+                // a stopped instance has nothing to report
+                if instance.state().await.is_stopped() {
+                    continue;
+                }
+                let mut monitor = instance.monitor().await;
+                monitor.directory_size = directory_sizes.get(&uuid);
                 if let Err(e) = tx
                     .send(axum::extract::ws::Message::Text(
                         serde_json::to_string(&monitor).unwrap(),
@@ -89,8 +96,56 @@ async fn monitor_ws(
     }
 }
 
+pub async fn get_disk_usage(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceDiskUsage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: crate::error::ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(
+        state
+            .directory_sizes
+            .usage(&uuid, &instance.resource_limits().await),
+    ))
+}
+
+/// The resource usage of the instance over a time range, as minute, hour or day rollups
+pub async fn get_usage_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<UsageHistoryQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UsageHistoryPage>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: crate::error::ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state
+            .usage_history
+            .query(
+                &state.sqlite_pool,
+                &uuid,
+                &query,
+                chrono::Utc::now().timestamp(),
+            )
+            .await?,
+    ))
+}
+
 pub fn get_monitor_routes(state: AppState) -> Router {
     Router::new()
         .route("/monitor/:uuid", get(monitor))
+        .route("/monitor/:uuid/disk", get(get_disk_usage))
+        .route("/monitor/:uuid/history", get(get_usage_history))
         .with_state(state)
 }
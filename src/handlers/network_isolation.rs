@@ -0,0 +1,91 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    network_isolation::{apply_policy, NetworkPolicy},
+    traits::{t_configurable::TConfigurable, t_server::TServer},
+    types::InstanceUuid,
+    AppState,
+};
+
+/// Isolation protects the other tenants of the host, so only the owner can change it
+/// The network policy of an instance, `None` if its traffic isn't restricted
+pub async fn get_network_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<NetworkPolicy>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(state.network_policies.lock().await.get(&uuid)))
+}
+
+/// Set the network policy of an instance. The firewall rules are replaced right away, the
+/// process of a running instance is restricted too.
+pub async fn set_network_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(policy): Json<NetworkPolicy>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the network isolation of instances")?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    policy.validate()?;
+    apply_policy(
+        &uuid,
+        Some(&policy),
+        instance.port().await,
+        instance.pid().await,
+    )
+    .await?;
+    state
+        .network_policies
+        .lock()
+        .await
+        .set(&uuid, policy)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn remove_network_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("change the network isolation of instances")?;
+    let mut network_policies = state.network_policies.lock().await;
+    if network_policies.get(&uuid).is_none() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance has no network policy"),
+        });
+    }
+    apply_policy(&uuid, None, 0, None).await?;
+    network_policies.remove_instance(&uuid).await?;
+    Ok(Json(()))
+}
+
+pub fn get_network_isolation_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/network_policy",
+            get(get_network_policy)
+                .put(set_network_policy)
+                .delete(remove_network_policy),
+        )
+        .with_state(state)
+}
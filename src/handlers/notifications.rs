@@ -0,0 +1,89 @@
+use axum::{
+    extract::Path,
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+use crate::{
+    error::Error,
+    notifications::{send_test_notification, WebhookTarget, WebhookTargetConfig},
+    AppState,
+};
+
+pub async fn list_webhook_targets(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<WebhookTarget>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage notifications")?;
+    Ok(Json(state.notifications.lock().await.targets().to_vec()))
+}
+
+pub async fn add_webhook_target(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<WebhookTargetConfig>,
+) -> Result<Json<WebhookTarget>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage notifications")?;
+    Ok(Json(
+        state.notifications.lock().await.add_target(config).await?,
+    ))
+}
+
+pub async fn update_webhook_target(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<WebhookTargetConfig>,
+) -> Result<Json<WebhookTarget>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage notifications")?;
+    Ok(Json(
+        state
+            .notifications
+            .lock()
+            .await
+            .update_target(&id, config)
+            .await?,
+    ))
+}
+
+pub async fn remove_webhook_target(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage notifications")?;
+    state.notifications.lock().await.remove_target(&id).await?;
+    Ok(Json(()))
+}
+
+pub async fn test_webhook_target(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage notifications")?;
+    // cloned so the store is not locked while waiting on the webhook
+    let target = state.notifications.lock().await.target(&id)?.clone();
+    send_test_notification(&target).await?;
+    Ok(Json(()))
+}
+
+pub fn get_notifications_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/notifications",
+            get(list_webhook_targets).post(add_webhook_target),
+        )
+        .route(
+            "/notifications/:id",
+            put(update_webhook_target).delete(remove_webhook_target),
+        )
+        .route("/notifications/:id/test", post(test_webhook_target))
+        .with_state(state)
+}
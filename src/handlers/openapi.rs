@@ -0,0 +1,678 @@
+//! The OpenAPI description of the API, so clients can be written without reading the dashboard.
+//!
+//! Routes are listed here by hand as axum doesn't expose its router, a test checks the list
+//! against the routers of the handler modules. Bodies of types deriving `JsonSchema` are
+//! described in full, the others are named after their typescript binding.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+
+use axum::{routing::get, Json, Router};
+use lazy_static::lazy_static;
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::Schema,
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    auth::access_grant::AccessGrant,
+    auth::permission::UserPermission,
+    backup::BackupEntry,
+    command_filter::CommandFilter,
+    error::ErrorResponse,
+    instance_archive::ArchivedInstance,
+    instance_summaries::InstanceSummaryPage,
+    jobs::JobInfo,
+    output_types::ClientEvent,
+    prelude::VERSION,
+    server_probe::ServerStatus,
+    traits::{
+        t_configurable::DisplayMetadata, t_player::Player, t_server::Readiness, InstanceInfo,
+    },
+    types::{InstanceUuid, Snowflake},
+    usage_history::UsageHistoryPage,
+    AppState,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ApiAuth {
+    /// an access token or api key in the `Authorization` header
+    Bearer,
+    /// username and password in the `Authorization` header
+    Basic,
+    /// websockets can't set headers, the access token goes in the query string instead
+    WebsocketToken,
+    Public,
+}
+
+enum Body {
+    /// json described by the schema of the type
+    Typed(fn(&mut SchemaGenerator) -> Schema),
+    /// json of the type of this name in the typescript bindings
+    Named(&'static str),
+    Text,
+    Binary,
+    Multipart,
+    WebSocket,
+}
+
+struct ApiRoute {
+    tag: &'static str,
+    method: &'static str,
+    path: &'static str,
+    handler: &'static str,
+    auth: ApiAuth,
+    summary: &'static str,
+    /// `None` if the request has no body
+    request: Option<Body>,
+    /// `None` if the response is empty, or `null` for json endpoints
+    response: Option<Body>,
+}
+
+impl ApiRoute {
+    fn new(
+        tag: &'static str,
+        method: &'static str,
+        path: &'static str,
+        handler: &'static str,
+        auth: ApiAuth,
+        summary: &'static str,
+    ) -> Self {
+        Self {
+            tag,
+            method,
+            path,
+            handler,
+            auth,
+            summary,
+            request: None,
+            response: None,
+        }
+    }
+
+    fn request(mut self, body: Body) -> Self {
+        self.request = Some(body);
+        self
+    }
+
+    fn response(mut self, body: Body) -> Self {
+        self.response = Some(body);
+        self
+    }
+
+    /// The path in OpenAPI syntax, `{uuid}` instead of `:uuid`, and its parameters
+    fn openapi_path(&self) -> (String, Vec<&'static str>) {
+        let mut parameters = Vec::new();
+        let path = self
+            .path
+            .split('/')
+            .map(|segment| match segment.strip_prefix([':', '*']) {
+                Some(parameter) => {
+                    parameters.push(parameter);
+                    format!("{{{parameter}}}")
+                }
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        (path, parameters)
+    }
+}
+
+fn schema_of<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    gen.subschema_for::<T>()
+}
+
+fn json<T: JsonSchema>() -> Body {
+    Body::Typed(schema_of::<T>)
+}
+
+fn content(body: &Body, gen: &mut SchemaGenerator) -> Value {
+    match body {
+        Body::Typed(schema) => json!({ "application/json": { "schema": schema(gen) } }),
+        Body::Named(name) => json!({
+            "application/json": {
+                "schema": { "description": format!("`{name}`, see the typescript bindings") }
+            }
+        }),
+        Body::Text => json!({ "text/plain": { "schema": { "type": "string" } } }),
+        Body::Binary | Body::WebSocket => json!({
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } }
+        }),
+        Body::Multipart => json!({
+            "multipart/form-data": { "schema": { "type": "object" } }
+        }),
+    }
+}
+
+fn operation(route: &ApiRoute, parameters: &[&str], gen: &mut SchemaGenerator) -> Value {
+    let mut operation = json!({
+        "tags": [route.tag],
+        "summary": route.summary,
+        "operationId": route.handler,
+        "parameters": parameters
+            .iter()
+            .map(|name| json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            }))
+            .collect::<Vec<_>>(),
+        "security": match route.auth {
+            ApiAuth::Bearer => json!([{ "bearer": [] }]),
+            ApiAuth::Basic => json!([{ "basic": [] }]),
+            ApiAuth::WebsocketToken => json!([{ "websocket_token": [] }]),
+            ApiAuth::Public => json!([]),
+        },
+    });
+    if let Some(request) = &route.request {
+        operation["requestBody"] = json!({ "required": true, "content": content(request, gen) });
+    }
+    let success = match &route.response {
+        Some(Body::WebSocket) => json!({ "101": { "description": "Switching to a websocket" } }),
+        Some(response) => json!({
+            "200": { "description": "Success", "content": content(response, gen) }
+        }),
+        None => json!({ "200": { "description": "Success" } }),
+    };
+    let mut responses = success.as_object().cloned().unwrap_or_default();
+    responses.insert(
+        "default".to_string(),
+        json!({
+            "description": "Error",
+            "content": { "application/json": { "schema": schema_of::<ErrorResponse>(gen) } },
+        }),
+    );
+    operation["responses"] = Value::Object(responses);
+    operation
+}
+
+fn openapi_document(routes: &[ApiRoute]) -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+    for route in routes {
+        let (path, parameters) = route.openapi_path();
+        let operation = operation(route, &parameters, &mut gen);
+        paths
+            .entry(path)
+            .or_default()
+            .insert(route.method.to_string(), operation);
+    }
+    let tags: BTreeSet<&str> = routes.iter().map(|route| route.tag).collect();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Lodestone Core",
+            "version": VERSION.with(|v| v.to_string()),
+        },
+        "servers": [{ "url": "/api/v1" }],
+        "tags": tags.iter().map(|tag| json!({ "name": tag })).collect::<Vec<_>>(),
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "basic": { "type": "http", "scheme": "basic" },
+                "websocket_token": { "type": "apiKey", "in": "query", "name": "token" },
+            },
+        },
+    })
+}
+
+lazy_static! {
+    static ref OPENAPI_DOCUMENT: Value = openapi_document(&routes());
+}
+
+/// The OpenAPI description of every route, no auth is required
+pub async fn get_openapi() -> Json<Value> {
+    Json(OPENAPI_DOCUMENT.clone())
+}
+
+pub fn get_openapi_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/openapi.json", get(get_openapi))
+        .with_state(state)
+}
+
+/// Every route of the API, in the order of the handler modules
+fn routes() -> Vec<ApiRoute> {
+    use ApiAuth::{Basic, Bearer, Public, WebsocketToken};
+    vec![
+        ApiRoute::new("advisories", "get", "/advisories", "get_advisories", Bearer, "Get advisories").response(Body::Named("AdvisoryDataset")),
+        ApiRoute::new("advisories", "post", "/advisories/refresh", "refresh_advisories", Bearer, "Fetch the latest advisories, returns the revision in use").request(Body::Named("RefreshAdvisories")).response(json::<u32>()),
+        ApiRoute::new("advisories", "post", "/instance/:uuid/advisories/:advisory_id/mitigate", "mitigate_advisory", Bearer, "Mitigate advisory"),
+        ApiRoute::new("backup_destinations", "get", "/backup_destinations", "get_backup_destinations", Bearer, "Get backup destinations").response(Body::Named("BackupDestination[]")),
+        ApiRoute::new("backup_destinations", "post", "/backup_destinations", "add_backup_destination", Bearer, "Add backup destination").request(Body::Named("BackupDestinationConfig")).response(Body::Named("BackupDestination")),
+        ApiRoute::new("backup_destinations", "put", "/backup_destinations/:id", "update_backup_destination", Bearer, "Update backup destination").request(Body::Named("BackupDestinationConfig")).response(Body::Named("BackupDestination")),
+        ApiRoute::new("backup_destinations", "delete", "/backup_destinations/:id", "remove_backup_destination", Bearer, "Forget a destination, the backups on it are left alone"),
+        ApiRoute::new("backup_destinations", "post", "/instance/:uuid/backups/:backup_id/upload/:destination_id", "upload_instance_backup", Bearer, "Upload a local backup, the progress is reported through a progression event"),
+        ApiRoute::new("backup_destinations", "get", "/instance/:uuid/remote_backups/:destination_id", "list_remote_instance_backups", Bearer, "List remote instance backups").response(json::<Vec<BackupEntry>>()),
+        ApiRoute::new("backup_destinations", "delete", "/instance/:uuid/remote_backups/:destination_id/:backup_id", "delete_remote_instance_backup", Bearer, "Delete remote instance backup"),
+        ApiRoute::new("backup_destinations", "post", "/instance/:uuid/remote_backups/:destination_id/:backup_id/restore", "restore_remote_instance_backup", Bearer, "Download a backup from a destination if it isn't kept locally anymore, then restore it"),
+        ApiRoute::new("checks", "get", "/check/port/:port", "get_port_status", Public, "Check the status of a port").response(Body::Named("PortStatus")),
+        ApiRoute::new("checks", "get", "/check/name/:name", "is_name_in_use", Public, "Check whether a name is in use").response(json::<bool>()),
+        ApiRoute::new("checks", "get", "/check/server_status", "get_server_status", Bearer, "Probe a server on any host the way a game client would, e.g. to check a port forward").response(json::<ServerStatus>()),
+        ApiRoute::new("console_matchers", "get", "/instance/:uuid/console_matchers", "get_console_matchers", Bearer, "The matchers run over the output of the instance, on top of the built-in ones").response(Body::Named("ConsoleMatcher[]")),
+        ApiRoute::new("console_matchers", "post", "/instance/:uuid/console_matchers", "add_console_matcher", Bearer, "Every line matching the pattern broadcasts a console match event with its capture groups").request(Body::Named("ConsoleMatcherConfig")).response(Body::Named("ConsoleMatcher")),
+        ApiRoute::new("console_matchers", "put", "/instance/:uuid/console_matchers/:id", "update_console_matcher", Bearer, "Update console matcher").request(Body::Named("ConsoleMatcherConfig")).response(Body::Named("ConsoleMatcher")),
+        ApiRoute::new("console_matchers", "delete", "/instance/:uuid/console_matchers/:id", "remove_console_matcher", Bearer, "Remove console matcher"),
+        ApiRoute::new("console_profiles", "get", "/console_profiles", "get_console_profiles", Bearer, "The built-in profiles followed by the ones added by users").response(Body::Named("ConsoleProfile[]")),
+        ApiRoute::new("console_profiles", "put", "/console_profiles/:name", "set_console_profile", Bearer, "Add or replace a profile, instances using it get the new version on their next start").request(Body::Named("ConsoleProfile")).response(Body::Named("ConsoleProfile")),
+        ApiRoute::new("console_profiles", "delete", "/console_profiles/:name", "delete_console_profile", Bearer, "Delete console profile"),
+        ApiRoute::new("console_profiles", "get", "/instance/:uuid/console_profile", "get_instance_console_profile", Bearer, "Get instance console profile").response(Body::Named("ConsoleProfile")),
+        ApiRoute::new("console_profiles", "put", "/instance/:uuid/console_profile", "set_instance_console_profile", Bearer, "Pick the profile of an instance by name, it takes effect on the next start").request(json::<String>()).response(Body::Named("ConsoleProfile")),
+        ApiRoute::new("console_snippets", "get", "/console/snippets", "get_user_snippets", Bearer, "The snippets of the requester, usable on every instance they can access the console of").response(Body::Named("ConsoleSnippet[]")),
+        ApiRoute::new("console_snippets", "post", "/console/snippets", "create_user_snippet", Bearer, "Create user snippet").request(Body::Named("ConsoleSnippetConfig")).response(Body::Named("ConsoleSnippet")),
+        ApiRoute::new("console_snippets", "put", "/console/snippets/:snippet_id", "update_snippet", Bearer, "Update snippet").request(Body::Named("ConsoleSnippetConfig")).response(Body::Named("ConsoleSnippet")),
+        ApiRoute::new("console_snippets", "delete", "/console/snippets/:snippet_id", "delete_snippet", Bearer, "Delete snippet"),
+        ApiRoute::new("console_snippets", "get", "/instance/:uuid/console/snippets", "get_instance_snippets", Bearer, "The snippets the requester can send to the instance, the ones shared on the instance first").response(Body::Named("ConsoleSnippet[]")),
+        ApiRoute::new("console_snippets", "post", "/instance/:uuid/console/snippets", "create_instance_snippet", Bearer, "Share a snippet with everyone who can access the console of the instance").request(Body::Named("ConsoleSnippetConfig")).response(Body::Named("ConsoleSnippet")),
+        ApiRoute::new("console_snippets", "post", "/instance/:uuid/console/snippets/:snippet_id/send", "send_snippet", Bearer, "Fill the placeholders of a snippet with the given values and send it to the console of the instance, returning the command that was sent").request(json::<HashMap<String, String>>()).response(json::<String>()),
+        ApiRoute::new("control_channel", "get", "/instance/:uuid/control_channel", "get_control_channel", Bearer, "Get control channel").response(Body::Named("ControlChannel")),
+        ApiRoute::new("control_channel", "put", "/instance/:uuid/control_channel", "set_control_channel", Bearer, "Pick whether commands and player lists go through the console or RCON and Query").request(Body::Named("ControlChannel")).response(Body::Named("ServerPropertiesUpdate")),
+        ApiRoute::new("control_channel", "get", "/instance/:uuid/query", "get_query_status", Bearer, "Get query status").response(Body::Named("QueryStatus")),
+        ApiRoute::new("control_channel", "get", "/instance/:uuid/tps", "get_tps", Bearer, "Asked over RCON, `None` if the server doesn't report it").response(json::<Option<f64>>()),
+        ApiRoute::new("core_info", "get", "/info", "get_core_info", Public, "Get core info").response(Body::Named("CoreInfo")),
+        ApiRoute::new("diagnostics", "get", "/diagnostics/bundle", "get_core_diagnostic_bundle", Bearer, "A bundle of the whole core, owner only as it holds every instance").response(Body::Text),
+        ApiRoute::new("diagnostics", "get", "/instance/:uuid/diagnostics/bundle", "get_instance_diagnostic_bundle", Bearer, "Get instance diagnostic bundle").response(Body::Text),
+        ApiRoute::new("downloads", "get", "/downloads", "get_downloads", Bearer, "Get downloads").response(Body::Named("DownloadInfo[]")),
+        ApiRoute::new("downloads", "get", "/downloads/:id", "get_download", Bearer, "Get download").response(Body::Named("DownloadInfo")),
+        ApiRoute::new("downloads", "delete", "/downloads/:id", "cancel_download", Bearer, "Cancel download").response(Body::Named("DownloadInfo")),
+        ApiRoute::new("downloads", "put", "/downloads/:id/pause", "pause_download", Bearer, "Pause download").response(Body::Named("DownloadInfo")),
+        ApiRoute::new("downloads", "put", "/downloads/:id/resume", "resume_download", Bearer, "Resume download").response(Body::Named("DownloadInfo")),
+        ApiRoute::new("events", "get", "/events/:uuid/stream", "event_stream", WebsocketToken, "Stream the events matching the `filter` query parameter over a websocket, the token is its `bearer_token`").response(Body::WebSocket),
+        ApiRoute::new("events", "get", "/events/:uuid/buffer", "get_event_buffer", Bearer, "Get event buffer").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("events", "get", "/events/search", "get_event_search", Bearer, "Get event search").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("events", "get", "/events/history", "get_event_history", Bearer, "Events persisted in the database, filtered and paged").response(Body::Named("EventPage")),
+        ApiRoute::new("events", "get", "/events/security", "get_security_events", Bearer, "The security feed: logins, failed logins, new sessions and permission changes, oldest first").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("events", "get", "/audit", "get_audit_events", Bearer, "Who called which mutating endpoint and how it went, oldest first").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("events", "get", "/instance/:uuid/console/stream", "console_stream", WebsocketToken, "Stream the console output of an instance over a websocket").response(Body::WebSocket),
+        ApiRoute::new("events", "get", "/instance/:uuid/console/buffer", "get_console_buffer", Bearer, "Get console buffer").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("events", "get", "/instance/:uuid/console/history", "get_console_history", Bearer, "Console output persisted in the database, including output from before the last restart").response(Body::Named("ConsoleHistoryPage")),
+        ApiRoute::new("events", "get", "/instance/:uuid/timeline", "get_instance_timeline", Bearer, "Lifecycle, player, backup and other non console events of an instance, oldest first").response(json::<Vec<ClientEvent>>()),
+        ApiRoute::new("federation", "get", "/peers", "get_peers", Bearer, "Get peers").response(Body::Named("PeerInfo[]")),
+        ApiRoute::new("federation", "post", "/peers", "add_peer", Bearer, "Register a peer with the url of its core and a token of its owner").request(Body::Named("PeerConfig")).response(Body::Named("PeerInfo")),
+        ApiRoute::new("federation", "get", "/peers/instances", "get_federated_instances", Bearer, "The instances of this core and of every peer, grouped by core").response(Body::Named("NodeInstances[]")),
+        ApiRoute::new("federation", "delete", "/peers/:id", "remove_peer", Bearer, "Remove peer"),
+        ApiRoute::new("gateway", "put", "/gateway/open_port/:port", "open_port", Bearer, "Open port"),
+        ApiRoute::new("gateway", "get", "/gateway/public_ip", "get_public_ip", Bearer, "Get public ip").response(json::<String>()),
+        ApiRoute::new("gateway", "get", "/instance/:uuid/address", "get_instance_address", Bearer, "The address players outside the local network connect to").response(Body::Named("InstanceAddress")),
+        ApiRoute::new("gateway", "get", "/instance/:uuid/public_address", "get_instance_public_address", Bearer, "Get instance public address").response(Body::Named("PublicAddress")),
+        ApiRoute::new("gateway", "put", "/instance/:uuid/public_address", "set_instance_public_address", Bearer, "Set the hostname and UPnP mapping of an instance, the mapping of a running instance is updated right away").request(Body::Named("PublicAddress")),
+        ApiRoute::new("global_fs", "get", "/fs/:base64_absolute_path/ls", "list_files", Bearer, "List files").response(Body::Named("FileEntry[]")),
+        ApiRoute::new("global_fs", "get", "/fs/:base64_absolute_path/read", "read_file", Bearer, "Read file").response(Body::Text),
+        ApiRoute::new("global_fs", "get", "/fs/:base64_absolute_path/search", "search", Bearer, "Find files under a directory by name, and optionally by content").response(Body::Named("FileSearchResult")),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/write", "write_file", Bearer, "Write file").request(Body::Binary),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/mkdir", "make_directory", Bearer, "Make directory"),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/move/:base64_relative_path_dest", "move_file", Bearer, "Move file"),
+        ApiRoute::new("global_fs", "delete", "/fs/:base64_absolute_path/rm", "remove_file", Bearer, "Remove file"),
+        ApiRoute::new("global_fs", "delete", "/fs/:base64_absolute_path/rmdir", "remove_dir", Bearer, "Remove dir"),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/new", "new_file", Bearer, "New file"),
+        ApiRoute::new("global_fs", "get", "/fs/:base64_absolute_path/download", "download_file", Bearer, "Download file").response(Body::Text),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/upload", "upload_file", Bearer, "Upload file").request(Body::Multipart),
+        ApiRoute::new("global_fs", "get", "/file/:key", "download", Public, "Download").response(Body::Binary),
+        ApiRoute::new("global_fs", "get", "/fs/locations", "get_fs_locations", Bearer, "Get fs locations").response(Body::Named("UserFsLocations")),
+        ApiRoute::new("global_fs", "delete", "/fs/locations/recent", "clear_recent_directories", Bearer, "Clear recent directories"),
+        ApiRoute::new("global_fs", "put", "/fs/:base64_absolute_path/bookmark", "add_bookmark", Bearer, "Add bookmark").request(Body::Named("NewBookmarkRequest")),
+        ApiRoute::new("global_fs", "delete", "/fs/:base64_absolute_path/bookmark", "remove_bookmark", Bearer, "Remove bookmark"),
+        ApiRoute::new("global_settings", "get", "/global_settings", "get_core_settings", Bearer, "Get core settings").response(Body::Named("GlobalSettingsData")),
+        ApiRoute::new("global_settings", "put", "/global_settings/name", "change_core_name", Bearer, "Change core name").request(json::<String>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/safe_mode", "change_core_safe_mode", Bearer, "Change core safe mode").request(json::<bool>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/domain", "change_domain", Bearer, "Change domain").request(json::<String>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/require_two_factor", "change_require_two_factor", Bearer, "Require every user to set up a second factor, users without one can only set one up").request(json::<bool>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/buffers", "change_buffer_settings", Bearer, "Change the sizes of the event buffers and how long events are kept").request(Body::Named("BufferSettings")).response(Body::Named("BufferSettings")),
+        ApiRoute::new("global_settings", "put", "/global_settings/geoip_database", "change_geoip_database", Bearer, "Locate joining players with the CSV GeoIP database at a path on the host, `null` turns GeoIP off").request(json::<Option<PathBuf>>()).response(json::<usize>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/rate_limits", "change_rate_limit_settings", Bearer, "Change the api rate limits and the lockout after failed logins, they apply right away").request(Body::Named("RateLimitSettings")).response(Body::Named("RateLimitSettings")),
+        ApiRoute::new("global_settings", "put", "/global_settings/telemetry", "change_telemetry", Bearer, "Opt in or out of the daily anonymous usage report").request(json::<bool>()),
+        ApiRoute::new("global_settings", "get", "/global_settings/telemetry/preview", "preview_telemetry", Bearer, "The report exactly as it would be sent, whether or not the core is opted in").response(Body::Named("TelemetryPreview")),
+        ApiRoute::new("global_settings", "put", "/global_settings/default_timezone", "change_default_timezone", Bearer, "The timezone of schedules that don't set their own, an IANA name like `Europe/Berlin`").request(json::<String>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/webdav", "change_webdav", Bearer, "Serve instance directories over WebDAV, off by default").request(json::<bool>()),
+        ApiRoute::new("global_settings", "put", "/global_settings/trash_retention_days", "change_trash_retention_days", Bearer, "Days deleted files are kept in the trash before being purged, `null` to keep them until purged by hand").request(json::<Option<u32>>()),
+        ApiRoute::new("health", "get", "/health", "get_health", Public, "Liveness, answers as long as the API is served").response(Body::Named("HealthReport")),
+        ApiRoute::new("health", "get", "/ready", "get_ready", Public, "Readiness, 503 while a self check fails").response(Body::Named("HealthReport")),
+        ApiRoute::new("instance", "post", "/instance/import/archive/:name", "import_instance_archive", Bearer, "Import the server in an uploaded zip or tar.gz archive").request(Body::Multipart).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "get", "/instance/list", "get_instance_list", Bearer, "Get instance list").response(json::<Vec<InstanceInfo>>()),
+        ApiRoute::new("instance", "get", "/instance/summaries", "get_instance_summaries", Bearer, "A page of the instance list, read from the summaries kept from events rather than from each instance").response(json::<InstanceSummaryPage>()),
+        ApiRoute::new("instance", "post", "/instance/create/:game_type", "create_minecraft_instance", Bearer, "Create minecraft instance").request(Body::Named("SetupValue")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "post", "/instance/validate", "validate_instance_setup", Bearer, "Validate instance setup").request(Body::Named("SetupValidationRequest")).response(Body::Named("SetupValidation")),
+        ApiRoute::new("instance", "post", "/instance/create_modpack", "create_modpack_instance", Bearer, "Create an instance from a pack link alone, resolving the version and loader from the pack").request(Body::Named("ModpackSetupConfig")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "post", "/instance/create_from_config", "create_instance_from_shared_config", Bearer, "Create an instance from a shared config, fetching its version and mods fresh").request(Body::Named("InstanceFromSharedConfig")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "post", "/instance/create_generic", "create_generic_instance", Bearer, "Create generic instance").request(Body::Named("GenericSetupConfig")),
+        ApiRoute::new("instance", "post", "/instance/create_process", "create_process_instance", Bearer, "Create process instance").request(Body::Named("ProcessSetupConfig")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "delete", "/instance/:uuid", "delete_instance", Bearer, "Delete instance"),
+        ApiRoute::new("instance", "get", "/instance/:uuid/info", "get_instance_info", Bearer, "Get instance info").response(json::<InstanceInfo>()),
+        ApiRoute::new("instance", "post", "/instance/:uuid/clone", "clone_instance", Bearer, "Clone instance").request(Body::Named("CloneInstanceConfig")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "post", "/instance/import", "import_instance", Bearer, "Turn an existing server directory on the host into an instance").request(Body::Named("ImportInstanceConfig")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance", "post", "/instance/import/detect", "detect_import", Bearer, "What an import of the server at the path would set the instance up as").request(Body::Named("DetectImportConfig")).response(Body::Named("DetectedServer")),
+        ApiRoute::new("instance_archive", "get", "/instance/archived", "get_archived_instances", Bearer, "The archived instances the requester could view before they were archived").response(json::<Vec<ArchivedInstance>>()),
+        ApiRoute::new("instance_archive", "post", "/instance/:uuid/archive", "archive_instance", Bearer, "Stop the instance, release its port and move it out of the instance list, its files and settings are kept until it is unarchived").response(json::<ArchivedInstance>()),
+        ApiRoute::new("instance_archive", "post", "/instance/archived/:uuid/unarchive", "unarchive_instance", Bearer, "Move an archived instance back and load it, it takes back the port it had").response(json::<InstanceInfo>()),
+        ApiRoute::new("instance_backup", "get", "/instance/:uuid/backups", "list_instance_backups", Bearer, "List instance backups").response(json::<Vec<BackupEntry>>()),
+        ApiRoute::new("instance_backup", "post", "/instance/:uuid/backups", "create_instance_backup", Bearer, "Back up the instance in a job, a hot backup of a running Minecraft server pauses saving instead of needing it stopped").request(Body::Named("NewBackupRequest")),
+        ApiRoute::new("instance_backup", "get", "/instance/:uuid/backups/retention", "get_backup_retention_policy", Bearer, "Get backup retention policy").response(Body::Named("BackupRetentionPolicy")),
+        ApiRoute::new("instance_backup", "put", "/instance/:uuid/backups/retention", "set_backup_retention_policy", Bearer, "Set backup retention policy").request(Body::Named("BackupRetentionPolicy")),
+        ApiRoute::new("instance_backup", "put", "/instance/:uuid/backups/period", "set_backup_period", Bearer, "Set backup period").request(json::<Option<u32>>()),
+        ApiRoute::new("instance_backup", "get", "/instance/:uuid/backups/schedule", "get_backup_schedule", Bearer, "Get backup schedule").response(Body::Named("WallClockSchedule | null")),
+        ApiRoute::new("instance_backup", "put", "/instance/:uuid/backups/schedule", "set_backup_schedule", Bearer, "Back up at a time of day, in the timezone of the schedule or the default one of the core").request(Body::Named("WallClockSchedule | null")),
+        ApiRoute::new("instance_backup", "delete", "/instance/:uuid/backups/:backup_id", "delete_instance_backup", Bearer, "Delete instance backup"),
+        ApiRoute::new("instance_backup", "post", "/instance/:uuid/backups/:backup_id/restore", "restore_instance_backup", Bearer, "Restore instance backup"),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/configurable_manifest", "get_instance_configurable_manifest", Bearer, "Get instance configurable manifest").response(Body::Named("ConfigurableManifest")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/version", "switch_version", Bearer, "Switch version").request(Body::Named("VersionChange")).response(Body::Named("VersionChangeReport")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/version/:new_version", "change_version", Bearer, "Change version"),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/settings", "get_instance_settings", Bearer, "Get instance settings").response(Body::Named("ConfigurableManifest")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/settings/:section_id/:setting_id", "set_instance_setting", Bearer, "Set instance setting").request(Body::Named("ConfigurableValue")),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/server_properties", "get_server_properties", Bearer, "Get server properties").response(Body::Named("Record<string, SettingManifest>")),
+        ApiRoute::new("instance_config", "patch", "/instance/:uuid/server_properties", "update_server_properties", Bearer, "Update server properties").request(Body::Named("Record<string, ConfigurableValue>")).response(Body::Named("ServerPropertiesUpdate")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/name", "set_instance_name", Bearer, "Set instance name").request(json::<String>()),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/description", "set_instance_description", Bearer, "Set instance description").request(json::<String>()),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/resource_limits", "get_instance_resource_limits", Bearer, "Get instance resource limits").response(Body::Named("ResourceLimits")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/resource_limits", "set_instance_resource_limits", Bearer, "Set instance resource limits").request(Body::Named("ResourceLimits")),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/display", "get_instance_display_metadata", Bearer, "Get instance display metadata").response(json::<DisplayMetadata>()),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/display", "set_instance_display_metadata", Bearer, "Set instance display metadata").request(json::<DisplayMetadata>()),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/crash_restart_policy", "get_instance_crash_restart_policy", Bearer, "Get instance crash restart policy").response(Body::Named("CrashRestartPolicy")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/crash_restart_policy", "set_instance_crash_restart_policy", Bearer, "Set instance crash restart policy").request(Body::Named("CrashRestartPolicy")),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/launch_overrides", "get_instance_launch_overrides", Bearer, "Sensitive environment variables are masked").response(Body::Named("LaunchOverrides")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/launch_overrides", "set_instance_launch_overrides", Bearer, "Masked sensitive values sent back keep their current value").request(Body::Named("LaunchOverrides")),
+        ApiRoute::new("instance_config", "get", "/instance/:uuid/scheduling", "get_instance_scheduling", Bearer, "Get instance scheduling").response(Body::Named("ProcessScheduling")),
+        ApiRoute::new("instance_config", "put", "/instance/:uuid/scheduling", "set_instance_scheduling", Bearer, "Set instance scheduling").request(Body::Named("ProcessScheduling")),
+        ApiRoute::new("instance_export", "get", "/instance/:uuid/export", "export_instance_archive", Bearer, "Package the instance into an archive in the background").response(Body::Text),
+        ApiRoute::new("instance_export", "get", "/instance/:uuid/export/config", "export_instance_config", Bearer, "The setup, server properties and mods of the instance as a JSON document to share, without any of its files").response(Body::Named("SharedInstanceConfig")),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/ls", "list_instance_files", Bearer, "List instance files").response(Body::Named("FileEntry[]")),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/read", "read_instance_file", Bearer, "Stream the file, or the byte range of it asked by a `Range` header, or its last `tail` lines, optionally followed by what is appended to it").response(Body::Text),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/write", "write_instance_file", Bearer, "Write instance file").request(Body::Binary),
+        ApiRoute::new("instance_fs", "patch", "/instance/:uuid/fs/:base64_relative_path/patch", "patch_instance_file", Bearer, "Patch instance file").request(Body::Named("TextPatchOperation[]")),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/config", "read_instance_config_file", Bearer, "Read instance config file").response(Body::Named("ConfigFile")),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/config", "edit_instance_config_file", Bearer, "Edit instance config file").request(Body::Named("ConfigEdit[]")).response(Body::Named("ConfigFile")),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/mkdir", "make_instance_directory", Bearer, "Make instance directory"),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/cpr", "copy_instance_files", Bearer, "Copy instance files").request(Body::Named("CopyInstanceFileRequest")),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/move/:base64_relative_path_dest", "move_instance_file", Bearer, "Move instance file"),
+        ApiRoute::new("instance_fs", "delete", "/instance/:uuid/fs/:base64_relative_path/rm", "remove_instance_file", Bearer, "Remove instance file"),
+        ApiRoute::new("instance_fs", "delete", "/instance/:uuid/fs/:base64_relative_path/rmdir", "remove_instance_dir", Bearer, "Remove instance dir"),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/new", "new_instance_file", Bearer, "New instance file"),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/url", "get_instance_file_url", Bearer, "Get instance file url").response(Body::Text),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/search", "search_instance_files", Bearer, "Find files under a directory of the instance by name, and optionally by content").response(Body::Named("FileSearchResult")),
+        ApiRoute::new("instance_fs", "get", "/instance/:uuid/fs/:base64_relative_path/sync/manifest", "get_instance_sync_manifest", Bearer, "The files under a directory of the instance with their size and modification time, for mirrors to tell what they are missing").response(Body::Named("SyncEntry[]")),
+        ApiRoute::new("instance_fs", "post", "/instance/:uuid/fs/:base64_relative_path/sync/diff", "diff_instance_files", Bearer, "Compare a directory of the instance against the files of a mirror, see `file_sync::diff`").request(Body::Named("SyncEntry[]")).response(Body::Named("SyncDiff")),
+        ApiRoute::new("instance_fs", "post", "/instance/:uuid/fs/:base64_relative_path/sync/bundle", "bundle_instance_files", Bearer, "Package the given files of a directory of the instance into a single archive, returns the key to download it with").request(json::<Vec<String>>()).response(Body::Text),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/upload", "upload_instance_file", Bearer, "Upload instance file").request(Body::Multipart),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/:base64_relative_path/unzip", "unzip_instance_file", Bearer, "Unzip instance file").request(Body::Named("UnzipOption")),
+        ApiRoute::new("instance_fs", "put", "/instance/:uuid/fs/zip", "zip_instance_files", Bearer, "Zip instance files").request(Body::Named("ZipRequest")),
+        ApiRoute::new("instance_groups", "get", "/instance/groups", "get_instance_groups", Bearer, "Every group with the instances of it the requester can view, groups with none are left out").response(json::<BTreeMap<String, Vec<InstanceUuid>>>()),
+        ApiRoute::new("instance_groups", "get", "/instance/:uuid/groups", "get_groups_of_instance", Bearer, "Get groups of instance").response(json::<BTreeSet<String>>()),
+        ApiRoute::new("instance_groups", "put", "/instance/:uuid/groups", "set_groups_of_instance", Bearer, "Replace the groups an instance is in").request(json::<BTreeSet<String>>()).response(json::<BTreeSet<String>>()),
+        ApiRoute::new("instance_groups", "post", "/instance/groups/:group/start", "start_group", Bearer, "Start group").response(Body::Named("BulkOperationResult[]")),
+        ApiRoute::new("instance_groups", "post", "/instance/groups/:group/stop", "stop_group", Bearer, "Stop group").response(Body::Named("BulkOperationResult[]")),
+        ApiRoute::new("instance_groups", "post", "/instance/groups/:group/restart", "restart_group", Bearer, "Restart group").response(Body::Named("BulkOperationResult[]")),
+        ApiRoute::new("instance_groups", "post", "/instance/groups/:group/command", "send_command_to_group", Bearer, "Send a command to the console of every instance of a group").request(json::<String>()).response(Body::Named("BulkOperationResult[]")),
+        ApiRoute::new("instance_logs", "get", "/instance/:uuid/logs/retention", "get_log_retention_rule", Bearer, "Get log retention rule").response(Body::Named("LogRetentionRule")),
+        ApiRoute::new("instance_logs", "put", "/instance/:uuid/logs/retention", "set_log_retention_rule", Bearer, "Set log retention rule").request(Body::Named("LogRetentionRule")),
+        ApiRoute::new("instance_logs", "post", "/instance/:uuid/logs/housekeep", "housekeep_instance_logs", Bearer, "Apply the retention rule now instead of waiting for the scheduler").response(Body::Named("LogHousekeepingReport")),
+        ApiRoute::new("instance_logs", "get", "/instance/:uuid/logs/files", "list_instance_log_files", Bearer, "List instance log files").response(Body::Named("LogFileEntry[]")),
+        ApiRoute::new("instance_logs", "get", "/instance/:uuid/logs/files/:name/tail", "tail_instance_log_file", Bearer, "The last lines of a log file, 200 unless asked for more").response(json::<Vec<String>>()),
+        ApiRoute::new("instance_logs", "get", "/instance/:uuid/logs/files/:name/url", "get_instance_log_file_url", Bearer, "A key to download a log file with, as is, from `/file/:key`").response(Body::Text),
+        ApiRoute::new("instance_logs", "get", "/instance/:uuid/logs/stream", "stream_instance_log", WebsocketToken, "Stream a log file of an instance over a websocket as it is written").response(Body::WebSocket),
+        ApiRoute::new("instance_macro", "put", "/instance/:uuid/macro/run/:macro_name", "run_macro", Bearer, "Run macro").request(json::<Vec<String>>()),
+        ApiRoute::new("instance_macro", "put", "/instance/:uuid/macro/kill/:pid", "kill_macro", Bearer, "Kill macro"),
+        ApiRoute::new("instance_macro", "get", "/instance/:uuid/macro/list", "get_instance_macro_list", Bearer, "Get instance macro list").response(Body::Named("MacroEntry[]")),
+        ApiRoute::new("instance_macro", "get", "/instance/:uuid/macro/triggers", "get_macro_triggers", Bearer, "Get macro triggers").response(Body::Named("MacroTrigger[]")),
+        ApiRoute::new("instance_macro", "post", "/instance/:uuid/macro/triggers", "add_macro_trigger", Bearer, "Add macro trigger").request(Body::Named("MacroTriggerConfig")).response(Body::Named("MacroTrigger")),
+        ApiRoute::new("instance_macro", "put", "/instance/:uuid/macro/triggers/:trigger_id", "update_macro_trigger", Bearer, "Update macro trigger").request(Body::Named("MacroTriggerConfig")).response(Body::Named("MacroTrigger")),
+        ApiRoute::new("instance_macro", "delete", "/instance/:uuid/macro/triggers/:trigger_id", "remove_macro_trigger", Bearer, "Remove macro trigger"),
+        ApiRoute::new("instance_macro", "get", "/instance/:uuid/macro/grant/:macro_name", "get_macro_grant", Bearer, "Get macro grant").response(Body::Named("MacroGrant")),
+        ApiRoute::new("instance_macro", "put", "/instance/:uuid/macro/grant/:macro_name", "set_macro_grant", Bearer, "Set macro grant").request(Body::Named("MacroGrant")),
+        ApiRoute::new("instance_macro", "get", "/instance/:uuid/task/list", "get_instance_task_list", Bearer, "Get instance task list").response(Body::Named("TaskEntry[]")),
+        ApiRoute::new("instance_macro", "get", "/instance/:uuid/history/list", "get_instance_history_list", Bearer, "Get instance history list").response(Body::Named("HistoryEntry[]")),
+        ApiRoute::new("instance_mods", "get", "/instance/:uuid/mods", "list_instance_mods", Bearer, "List instance mods").response(Body::Named("ModInfo[]")),
+        ApiRoute::new("instance_mods", "get", "/instance/:uuid/mods/memory", "get_instance_memory_recommendation", Bearer, "The heap recommended for the installed mods, with warnings about the configured heap").response(Body::Named("MemoryRecommendation")),
+        ApiRoute::new("instance_mods", "post", "/instance/:uuid/mods/url", "install_instance_mod_from_url", Bearer, "Install instance mod from url").request(Body::Named("InstallModFromUrl")).response(Body::Named("ModInfo")),
+        ApiRoute::new("instance_mods", "post", "/instance/:uuid/mods/upload", "upload_instance_mods", Bearer, "Upload instance mods").request(Body::Multipart).response(Body::Named("ModInfo[]")),
+        ApiRoute::new("instance_mods", "put", "/instance/:uuid/mods/:file_name/enable", "enable_instance_mod", Bearer, "Enable instance mod").response(Body::Named("ModInfo")),
+        ApiRoute::new("instance_mods", "put", "/instance/:uuid/mods/:file_name/disable", "disable_instance_mod", Bearer, "Disable instance mod").response(Body::Named("ModInfo")),
+        ApiRoute::new("instance_mods", "delete", "/instance/:uuid/mods/:file_name", "delete_instance_mod", Bearer, "Delete instance mod"),
+        ApiRoute::new("instance_mods", "post", "/instance/:uuid/spark", "install_instance_spark", Bearer, "Install the spark profiler, it's loaded on the next start").response(Body::Named("ModInfo")),
+        ApiRoute::new("instance_mods", "post", "/instance/:uuid/spark/profile", "profile_instance", Bearer, "Profile the server with spark").request(Body::Named("ProfileInstance")),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/count", "get_player_count", Public, "Get player count").response(json::<u32>()),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/max", "get_max_player_count", Public, "Get max player count").response(json::<u32>()),
+        ApiRoute::new("instance_players", "put", "/instance/:uuid/players/max", "set_max_player_count", Public, "Set max player count").request(json::<u32>()),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players", "get_player_list", Public, "Get player list").response(json::<HashSet<Player>>()),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/history", "get_player_records", Bearer, "Every player who joined the instance and where they joined from").response(Body::Named("PlayerRecord[]")),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/whitelist", "get_whitelist", Bearer, "Get whitelist").response(Body::Named("WhitelistEntry[]")),
+        ApiRoute::new("instance_players", "post", "/instance/:uuid/players/whitelist/:name", "whitelist_add", Bearer, "Whitelist add"),
+        ApiRoute::new("instance_players", "delete", "/instance/:uuid/players/whitelist/:name", "whitelist_remove", Bearer, "Whitelist remove"),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/ops", "get_ops", Bearer, "Get ops").response(Body::Named("OpEntry[]")),
+        ApiRoute::new("instance_players", "post", "/instance/:uuid/players/ops/:name", "op_player", Bearer, "Op player"),
+        ApiRoute::new("instance_players", "delete", "/instance/:uuid/players/ops/:name", "deop_player", Bearer, "Deop player"),
+        ApiRoute::new("instance_players", "get", "/instance/:uuid/players/banned", "get_banned_players", Bearer, "Get banned players").response(Body::Named("BannedPlayer[]")),
+        ApiRoute::new("instance_players", "post", "/instance/:uuid/players/banned/:name", "ban_player", Bearer, "Ban player").request(Body::Named("PlayerBan")),
+        ApiRoute::new("instance_players", "delete", "/instance/:uuid/players/banned/:name", "pardon_player", Bearer, "Pardon player"),
+        ApiRoute::new("instance_players", "post", "/instance/:uuid/players/kick/:name", "kick_player", Bearer, "Kick player").request(Body::Named("PlayerKick")),
+        ApiRoute::new("instance_server", "put", "/instance/:uuid/start", "start_instance", Bearer, "Start instance"),
+        ApiRoute::new("instance_server", "put", "/instance/:uuid/stop", "stop_instance", Bearer, "Stop instance"),
+        ApiRoute::new("instance_server", "put", "/instance/:uuid/restart", "restart_instance", Bearer, "Restart instance"),
+        ApiRoute::new("instance_server", "put", "/instance/:uuid/kill", "kill_instance", Bearer, "Kill instance").response(json::<serde_json::Value>()),
+        ApiRoute::new("instance_server", "post", "/instance/:uuid/console", "send_command", Bearer, "Send command").request(json::<String>()),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/console/history", "get_command_history", Bearer, "Commands users sent to the console of the instance, oldest first").response(Body::Named("CommandHistoryEntry[]")),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/console/filter", "get_command_filter", Bearer, "The regexes deciding which commands users other than the owner can send to the console").response(json::<CommandFilter>()),
+        ApiRoute::new("instance_server", "put", "/instance/:uuid/console/filter", "set_command_filter", Bearer, "Replace the command filter of the instance, an empty filter lets every command through").request(json::<CommandFilter>()).response(json::<CommandFilter>()),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/console/commands", "get_known_commands", Bearer, "Commands the instance knows of, for autocomplete").response(Body::Named("CommandInfo[]")),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/state", "get_instance_state", Bearer, "Get instance state").response(json::<serde_json::Value>()),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/readiness", "get_instance_readiness", Bearer, "Whether the instance is done starting").response(json::<Readiness>()),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/console/queue", "get_command_queue", Bearer, "Commands waiting for the instance to be ready, in the order they will be sent").response(Body::Named("QueuedCommand[]")),
+        ApiRoute::new("instance_server", "post", "/instance/:uuid/console/queue", "queue_command", Bearer, "Queue a command to send the next time the instance is ready, e.g. while it is stopped").request(Body::Named("QueuedCommandConfig")).response(Body::Named("QueuedCommand")),
+        ApiRoute::new("instance_server", "delete", "/instance/:uuid/console/queue/:command_id", "remove_queued_command", Bearer, "Remove queued command"),
+        ApiRoute::new("instance_server", "get", "/instance/:uuid/console/sequence", "get_command_sequences", Bearer, "Command sequences still sending commands to the instance").response(Body::Named("CommandSequence[]")),
+        ApiRoute::new("instance_server", "post", "/instance/:uuid/console/sequence", "start_command_sequence", Bearer, "Send commands one after the other with a delay before each, e.g. warning players, kicking them and stopping the server, without the client having to time the requests").request(Body::Named("CommandSequenceConfig")).response(Body::Named("CommandSequence")),
+        ApiRoute::new("instance_server", "delete", "/instance/:uuid/console/sequence/:sequence_id", "cancel_command_sequence", Bearer, "Cancel command sequence"),
+        ApiRoute::new("instance_setup_configs", "get", "/games", "get_available_games", Public, "Get available games").response(Body::Named("HandlerGameType[]")),
+        ApiRoute::new("instance_setup_configs", "get", "/setup_manifest/:game_type", "get_setup_manifest", Public, "Get setup manifest").response(Body::Named("SetupManifest")),
+        ApiRoute::new("instance_setup_configs", "get", "/setup_manifest/:game_type/memory", "get_setup_memory_recommendation", Public, "The heap recommended for a new instance, with warnings about the heap the user picked").response(Body::Named("MemoryRecommendation")),
+        ApiRoute::new("instance_setup_configs", "get", "/setup_manifest/:game_type/loader_versions", "get_setup_loader_versions", Public, "The versions of the mod loader a new instance can be pinned to").response(json::<Vec<String>>()),
+        ApiRoute::new("instance_setup_configs", "put", "/generic_setup_manifest", "get_generic_setup_manifest", Public, "Get generic setup manifest").request(Body::Named("GenericSetupManifestBody")).response(Body::Named("SetupManifest")),
+        ApiRoute::new("instance_setup_configs", "get", "/version_cache", "get_version_cache", Bearer, "The cached version lists offered by the setup manifests").response(Body::Named("VersionListStatus[]")),
+        ApiRoute::new("instance_setup_configs", "post", "/version_cache/refresh", "refresh_version_cache", Bearer, "Fetch every cached version list again, e.g. right after a release").response(Body::Named("VersionListStatus[]")),
+        ApiRoute::new("instance_sync", "get", "/instance/:uuid/sync", "get_instance_sync", Bearer, "Get instance sync").response(Body::Named("InstanceSync | null")),
+        ApiRoute::new("instance_sync", "put", "/instance/:uuid/sync", "set_instance_sync", Bearer, "Set instance sync").request(Body::Named("SyncConfig")).response(Body::Named("InstanceSync")),
+        ApiRoute::new("instance_sync", "delete", "/instance/:uuid/sync", "remove_instance_sync", Bearer, "Remove instance sync"),
+        ApiRoute::new("instance_sync", "post", "/instance/:uuid/sync/pull", "pull_instance_sync", Bearer, "Overwrite the synced files of the instance with the ones on the remote").response(Body::Named("SyncRecord")),
+        ApiRoute::new("instance_sync", "post", "/instance/:uuid/sync/push", "push_instance_sync", Bearer, "Commit the synced files of the instance and push them to the remote").response(Body::Named("SyncRecord")),
+        ApiRoute::new("instance_template", "get", "/instance_template/list", "list_instance_templates", Bearer, "List instance templates").response(Body::Named("InstanceTemplate[]")),
+        ApiRoute::new("instance_template", "post", "/instance_template", "create_instance_template", Bearer, "Create instance template").request(Body::Named("NewInstanceTemplate")).response(Body::Named("InstanceTemplate")),
+        ApiRoute::new("instance_template", "delete", "/instance_template/:id", "delete_instance_template", Bearer, "Delete instance template"),
+        ApiRoute::new("instance_template", "post", "/instance_template/:id/create", "create_instance_from_template", Bearer, "Create instance from template").request(Body::Named("InstanceFromTemplate")).response(json::<InstanceUuid>()),
+        ApiRoute::new("instance_template", "post", "/instance/:uuid/template", "save_instance_as_template", Bearer, "Save the setup config of an existing instance as a template").request(Body::Named("InstanceTemplateName")).response(Body::Named("InstanceTemplate")),
+        ApiRoute::new("instance_webhooks", "get", "/instance/:uuid/webhooks", "get_instance_webhooks", Bearer, "Get instance webhooks").response(Body::Named("InstanceWebhook[]")),
+        ApiRoute::new("instance_webhooks", "post", "/instance/:uuid/webhooks", "add_instance_webhook", Bearer, "Add instance webhook").request(Body::Named("InstanceWebhookConfig")).response(Body::Named("InstanceWebhook")),
+        ApiRoute::new("instance_webhooks", "put", "/instance/:uuid/webhooks/:webhook_id", "update_instance_webhook", Bearer, "Update instance webhook").request(Body::Named("InstanceWebhookConfig")).response(Body::Named("InstanceWebhook")),
+        ApiRoute::new("instance_webhooks", "delete", "/instance/:uuid/webhooks/:webhook_id", "remove_instance_webhook", Bearer, "Remove instance webhook"),
+        ApiRoute::new("instance_worlds", "get", "/instance/:uuid/worlds", "get_worlds", Bearer, "Get worlds").response(Body::Named("WorldInfo[]")),
+        ApiRoute::new("instance_worlds", "put", "/instance/:uuid/worlds/active", "switch_world", Bearer, "Returns whether the server was restarted into the world").request(json::<String>()).response(json::<bool>()),
+        ApiRoute::new("instance_worlds", "delete", "/instance/:uuid/worlds/:name", "delete_world", Bearer, "Delete world").response(Body::Named("TrashEntry[]")),
+        ApiRoute::new("instance_worlds", "post", "/instance/:uuid/worlds/:name/reset", "reset_world", Bearer, "Reset world").request(Body::Named("WorldReset")).response(Body::Named("WorldResetReport")),
+        ApiRoute::new("java_runtimes", "get", "/java/runtimes", "get_java_runtimes", Bearer, "Get java runtimes").response(Body::Named("JavaRuntime[]")),
+        ApiRoute::new("java_runtimes", "post", "/java/runtimes/:major_version", "install_java_runtime", Bearer, "Install java runtime").response(Body::Named("JavaRuntime")),
+        ApiRoute::new("java_runtimes", "get", "/instance/:uuid/java", "get_instance_java_runtime", Bearer, "Get instance java runtime").response(Body::Named("InstanceJavaRuntime")),
+        ApiRoute::new("java_runtimes", "put", "/instance/:uuid/java", "pin_instance_java_runtime", Bearer, "Pin instance java runtime").request(Body::Named("JavaRuntimePin")).response(Body::Named("InstanceJavaRuntime")),
+        ApiRoute::new("jobs", "get", "/jobs", "get_jobs", Bearer, "The running jobs and the recently finished ones the requester can see").response(json::<Vec<JobInfo>>()),
+        ApiRoute::new("jobs", "get", "/jobs/:id", "get_job", Bearer, "Get job").response(json::<JobInfo>()),
+        ApiRoute::new("jobs", "post", "/jobs/:id/cancel", "cancel_job", Bearer, "Ask a running job to stop, only the users who can write the files of its instance can, or the owner for jobs not on an instance").response(json::<JobInfo>()),
+        ApiRoute::new("metrics", "get", "/metrics", "get_metrics", Bearer, "Prometheus metrics of the instances the user can view and of the core itself").response(Body::Binary),
+        ApiRoute::new("monitor", "get", "/monitor/:uuid", "monitor", Public, "Stream the resource usage of an instance over a websocket").response(Body::WebSocket),
+        ApiRoute::new("monitor", "get", "/monitor/:uuid/disk", "get_disk_usage", Bearer, "Get disk usage").response(Body::Named("InstanceDiskUsage")),
+        ApiRoute::new("monitor", "get", "/monitor/:uuid/history", "get_usage_history", Bearer, "The resource usage of the instance over a time range, as minute, hour or day rollups").response(json::<UsageHistoryPage>()),
+        ApiRoute::new("network_isolation", "get", "/instance/:uuid/network_policy", "get_network_policy", Bearer, "The network policy of an instance, `None` if its traffic isn't restricted").response(Body::Named("NetworkPolicy | null")),
+        ApiRoute::new("network_isolation", "put", "/instance/:uuid/network_policy", "set_network_policy", Bearer, "Set the network policy of an instance").request(Body::Named("NetworkPolicy")),
+        ApiRoute::new("network_isolation", "delete", "/instance/:uuid/network_policy", "remove_network_policy", Bearer, "Remove network policy"),
+        ApiRoute::new("notifications", "get", "/notifications", "list_webhook_targets", Bearer, "List webhook targets").response(Body::Named("WebhookTarget[]")),
+        ApiRoute::new("notifications", "post", "/notifications", "add_webhook_target", Bearer, "Add webhook target").request(Body::Named("WebhookTargetConfig")).response(Body::Named("WebhookTarget")),
+        ApiRoute::new("notifications", "put", "/notifications/:id", "update_webhook_target", Bearer, "Update webhook target").request(Body::Named("WebhookTargetConfig")).response(Body::Named("WebhookTarget")),
+        ApiRoute::new("notifications", "delete", "/notifications/:id", "remove_webhook_target", Bearer, "Remove webhook target"),
+        ApiRoute::new("notifications", "post", "/notifications/:id/test", "test_webhook_target", Bearer, "Test webhook target"),
+        ApiRoute::new("overview", "get", "/overview", "get_overview", Bearer, "Get overview").response(Body::Named("Overview")),
+        ApiRoute::new("reservation", "get", "/instance/reservations", "get_reservation_plan", Bearer, "Whether the configured heaps and limits of the instances the user can view fit on the host, and which sets of auto start instances can run together").response(Body::Named("ReservationPlan")),
+        ApiRoute::new("setup", "get", "/setup", "get_setup_status", Public, "Get setup status").response(Body::Named("SetupStatus")),
+        ApiRoute::new("setup", "post", "/setup/:key", "setup_owner", Public, "Setup owner").request(Body::Named("OwnerSetup")).response(Body::Named("LoginReply")),
+        ApiRoute::new("setup", "delete", "/setup/:key", "invalidate_setup_key", Public, "Expire the setup key right away, e.g. when it leaked. It can't be rotated afterwards, a new one is generated when the core restarts"),
+        ApiRoute::new("setup", "put", "/setup/:key/rotate", "rotate_setup_key", Public, "Replace the setup key, which can be expired but not invalidated, by a new one").response(Body::Named("SetupKeyInfo")),
+        ApiRoute::new("start_dependencies", "get", "/instance/:uuid/start_dependencies", "get_start_dependencies", Bearer, "Get start dependencies").response(Body::Named("StartDependency[]")),
+        ApiRoute::new("start_dependencies", "put", "/instance/:uuid/start_dependencies", "set_start_dependencies", Bearer, "Replace the instances that have to be ready before this one starts").request(Body::Named("StartDependency[]")).response(Body::Named("StartDependency[]")),
+        ApiRoute::new("status_page", "get", "/status_page", "get_status_page_config", Bearer, "Get status page config").response(Body::Named("StatusPageConfig | null")),
+        ApiRoute::new("status_page", "put", "/status_page", "set_status_page_config", Bearer, "Set status page config").request(Body::Named("StatusPageConfig | null")),
+        ApiRoute::new("status_page", "get", "/status_page/preview", "preview_status_page", Bearer, "What would be published right now").response(Body::Named("ServerStatus")),
+        ApiRoute::new("status_page", "post", "/status_page/publish", "publish_status_page", Bearer, "Publish now instead of waiting for the next change, even if publishing is disabled").response(Body::Named("ServerStatus")),
+        ApiRoute::new("storage_roots", "get", "/storage_roots", "get_storage_roots", Bearer, "Every storage root with the space left on its disk, to pick one when creating an instance").response(Body::Named("StorageRootInfo[]")),
+        ApiRoute::new("storage_roots", "post", "/storage_roots", "add_storage_root", Bearer, "Add storage root").request(Body::Named("StorageRootConfig")).response(Body::Named("StorageRoot")),
+        ApiRoute::new("storage_roots", "delete", "/storage_roots/:id", "remove_storage_root", Bearer, "Forget a root that holds no instance, its directory is left alone"),
+        ApiRoute::new("storage_roots", "put", "/storage_roots/:id/default", "set_default_storage_root", Bearer, "Place new instances in this root when no root is asked for"),
+        ApiRoute::new("storage_roots", "post", "/instance/:uuid/move", "move_instance", Bearer, "Move a stopped instance to another storage root in a job, the instance is unavailable until the job finishes").request(Body::Named("MoveInstanceRequest")).response(json::<Snowflake>()),
+        ApiRoute::new("suspicious_activity", "get", "/instance/:uuid/suspicious_activity", "get_suspicious_activity_policy", Bearer, "Get suspicious activity policy").response(Body::Named("SuspiciousActivityPolicy")),
+        ApiRoute::new("suspicious_activity", "put", "/instance/:uuid/suspicious_activity", "set_suspicious_activity_policy", Bearer, "Mitigations are console commands run without anyone at the console, so setting them takes access to the console too").request(Body::Named("SuspiciousActivityPolicy")),
+        ApiRoute::new("system", "get", "/system/ram", "get_ram", Public, "Get ram").response(Body::Named("MemInfo")),
+        ApiRoute::new("system", "get", "/system/disk", "get_disk", Public, "Get disk").response(Body::Named("DiskInfo")),
+        ApiRoute::new("system", "get", "/system/cpu", "get_cpu_info", Public, "Get cpu info").response(Body::Named("CPUInfo")),
+        ApiRoute::new("system", "post", "/system/relocate", "relocate_data_directory", Bearer, "Move the data directory to a new path").request(Body::Named("RelocateDataConfig")),
+        ApiRoute::new("system", "get", "/system/update", "get_update_status", Bearer, "The running version and the newest release for this platform, the feed is checked daily").response(Body::Named("UpdateStatus")),
+        ApiRoute::new("system", "post", "/system/update", "install_update", Bearer, "Download and integrity check the update found by the last check in a job, then restart the core on it once every instance is stopped gracefully").request(Body::Named("InstallUpdateRequest")).response(json::<Snowflake>()),
+        ApiRoute::new("system", "post", "/system/maintenance", "host_maintenance", Bearer, "Prepare the host for maintenance: warn the players, stop every instance, flush the stores and then reboot or shut down the host if asked to").request(Body::Named("HostMaintenanceConfig")),
+        ApiRoute::new("trash", "get", "/instance/:uuid/trash", "get_instance_trash", Bearer, "Get instance trash").response(Body::Named("TrashEntry[]")),
+        ApiRoute::new("trash", "delete", "/instance/:uuid/trash", "purge_instance_trash", Bearer, "Empty the trash of the instance, returns what was purged").response(Body::Named("TrashEntry[]")),
+        ApiRoute::new("trash", "delete", "/instance/:uuid/trash/:id", "purge_instance_trash_entry", Bearer, "Purge instance trash entry"),
+        ApiRoute::new("trash", "put", "/instance/:uuid/trash/:id/restore", "restore_instance_trash_entry", Bearer, "Put an entry back in the instance, the same files are protected as when deleting").response(Body::Named("TrashEntry")),
+        ApiRoute::new("trash", "get", "/fs/trash", "get_global_trash", Bearer, "Get global trash").response(Body::Named("TrashEntry[]")),
+        ApiRoute::new("trash", "delete", "/fs/trash", "purge_global_trash", Bearer, "Purge global trash").response(Body::Named("TrashEntry[]")),
+        ApiRoute::new("trash", "delete", "/fs/trash/:id", "purge_global_trash_entry", Bearer, "Purge global trash entry"),
+        ApiRoute::new("trash", "put", "/fs/trash/:id/restore", "restore_global_trash_entry", Bearer, "Restore global trash entry").response(Body::Named("TrashEntry")),
+        ApiRoute::new("uploads", "put", "/upload/:upload_id/part/:index", "upload_part", Bearer, "Upload part").request(Body::Binary).response(Body::Named("UploadStatus")),
+        ApiRoute::new("uploads", "post", "/instance/:uuid/fs/:base64_relative_path/upload/chunked", "start_instance_upload", Bearer, "Start instance upload").request(Body::Named("NewUpload")).response(Body::Named("UploadStatus")),
+        ApiRoute::new("uploads", "post", "/fs/:base64_absolute_path/upload/chunked", "start_global_upload", Bearer, "Start global upload").request(Body::Named("NewUpload")).response(Body::Named("UploadStatus")),
+        ApiRoute::new("uploads", "get", "/upload/:upload_id", "get_upload", Bearer, "Get upload").response(Body::Named("UploadStatus")),
+        ApiRoute::new("uploads", "delete", "/upload/:upload_id", "abort_upload", Bearer, "Abort upload"),
+        ApiRoute::new("uploads", "post", "/upload/:upload_id/complete", "complete_upload", Bearer, "Complete upload"),
+        ApiRoute::new("usage_accounting", "get", "/usage/months", "get_usage_months", Bearer, "Months with recorded usage, oldest first").response(json::<Vec<String>>()),
+        ApiRoute::new("usage_accounting", "get", "/usage/:month/instances", "get_instance_usage", Bearer, "Get instance usage").response(Body::Binary),
+        ApiRoute::new("usage_accounting", "get", "/usage/:month/users", "get_user_usage", Bearer, "The usage of a month added up per instance owner").response(Body::Binary),
+        ApiRoute::new("user_quotas", "get", "/user/:uid/quota", "get_user_quota", Bearer, "The quota of a user with what they use of it").response(Body::Named("UserQuotaUsage")),
+        ApiRoute::new("user_quotas", "put", "/user/:uid/quota", "set_user_quota", Bearer, "Replace the quota of a user, the instances they already have are kept if it is lowered").request(Body::Named("UserQuota")).response(Body::Named("UserQuota")),
+        ApiRoute::new("users", "get", "/user/list", "get_all_users", Bearer, "Get all users").response(Body::Named("PublicUser[]")),
+        ApiRoute::new("users", "get", "/user/export", "export_users", Bearer, "The users store, password hashes included, for importing into another core").response(Body::Named("Record<string, User>")),
+        ApiRoute::new("users", "post", "/user/import", "import_users", Bearer, "Import users").request(Body::Named("UserImportConfig")).response(Body::Named("UserImportReport")),
+        ApiRoute::new("users", "post", "/user", "new_user", Bearer, "New user").request(Body::Named("NewUser")).response(Body::Named("LoginReply")),
+        ApiRoute::new("users", "get", "/user/:uid", "get_user_info", Bearer, "Get user info").response(Body::Named("PublicUser")),
+        ApiRoute::new("users", "delete", "/user/:uid", "delete_user", Bearer, "Delete user").response(json::<serde_json::Value>()),
+        ApiRoute::new("users", "put", "/user/:uid/update_perm", "update_permissions", Bearer, "Update permissions").request(json::<UserPermission>()),
+        ApiRoute::new("users", "get", "/user/info", "get_self_info", Bearer, "Get self info").response(Body::Named("PublicUser")),
+        ApiRoute::new("users", "get", "/user/notification_preferences", "get_notification_preferences", Bearer, "Get notification preferences").response(Body::Named("NotificationPreferences")),
+        ApiRoute::new("users", "put", "/user/notification_preferences", "set_notification_preferences", Bearer, "Set notification preferences").request(Body::Named("NotificationPreferences")),
+        ApiRoute::new("users", "get", "/user/keys", "get_api_keys", Bearer, "Get api keys").response(Body::Named("PublicApiKey[]")),
+        ApiRoute::new("users", "post", "/user/keys", "create_api_key", Bearer, "Create a key for the requester, it can do what the scope allows of what the requester can do").request(Body::Named("NewApiKey")).response(Body::Named("NewApiKeyReply")),
+        ApiRoute::new("users", "delete", "/user/keys/:key_id", "revoke_api_key", Bearer, "Revoke api key"),
+        ApiRoute::new("users", "put", "/user/:uid/rename", "rename_user", Bearer, "Rename user").request(json::<String>()),
+        ApiRoute::new("users", "put", "/user/:uid/password", "change_password", Bearer, "Change password").request(Body::Named("ChangePasswordConfig")),
+        ApiRoute::new("users", "post", "/user/:uid/password/reset", "reset_password", Bearer, "Reset the password of a user who forgot it").response(Body::Named("PasswordResetReply")),
+        ApiRoute::new("users", "post", "/user/password/reset", "complete_password_reset", Public, "Choose a new password with the token of a reset and log in, users with a second factor also pass a code of it in the `x-two-factor-code` header").request(Body::Named("CompletePasswordResetConfig")).response(Body::Named("LoginReply")),
+        ApiRoute::new("users", "post", "/user/:uid/grants", "grant_access", Bearer, "Give a user temporary access to an instance, e.g. to let someone debug a server for a day").request(Body::Named("NewAccessGrant")).response(json::<AccessGrant>()),
+        ApiRoute::new("users", "delete", "/user/:uid/grants/:grant_id", "revoke_access", Bearer, "Revoke access"),
+        ApiRoute::new("users", "get", "/user/:uid/sessions", "get_sessions", Bearer, "Get sessions").response(Body::Named("PublicSession[]")),
+        ApiRoute::new("users", "delete", "/user/:uid/sessions/:session_id", "revoke_session", Bearer, "Revoke session"),
+        ApiRoute::new("users", "post", "/user/login", "login", Basic, "Log in with basic auth, plus a code of the authenticator or a recovery code in the `x-two-factor-code` header for users with a second factor").response(Body::Named("LoginReply")),
+        ApiRoute::new("users", "post", "/user/refresh", "refresh", Public, "Refresh").request(Body::Named("RefreshConfig")).response(Body::Named("LoginReply")),
+        ApiRoute::new("users", "post", "/user/logout/:uid", "logout", Bearer, "Logout"),
+        ApiRoute::new("users", "post", "/user/two_factor/enroll", "enroll_two_factor", Bearer, "Start setting up a second factor for the requester, replacing any pending one").response(Body::Named("TwoFactorEnrollment")),
+        ApiRoute::new("users", "post", "/user/two_factor/confirm", "confirm_two_factor", Bearer, "Enable the pending second factor, returns the recovery codes which are only shown once").request(Body::Named("TwoFactorCode")).response(json::<Vec<String>>()),
+        ApiRoute::new("users", "post", "/user/two_factor/recovery_codes", "regenerate_recovery_codes", Bearer, "Regenerate recovery codes").request(Body::Named("TwoFactorCode")).response(json::<Vec<String>>()),
+        ApiRoute::new("users", "put", "/user/two_factor/disable", "disable_two_factor", Bearer, "Disable two factor").request(Body::Named("TwoFactorCode")),
+        ApiRoute::new("users", "delete", "/user/:uid/two_factor", "reset_two_factor", Bearer, "Remove the second factor of a user who lost their authenticator and recovery codes, so they can log in with their password and set up a new one"),
+        ApiRoute::new("openapi", "get", "/openapi.json", "get_openapi", Public, "The OpenAPI description of every route, no auth is required"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{openapi_document, routes};
+
+    /// The method and path of every route registered by the routers of the handler modules
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        // split so the search doesn't find itself in this file
+        let needle = concat!(".", "route(");
+        let mut registered = BTreeSet::new();
+        let handlers = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/handlers");
+        for entry in std::fs::read_dir(handlers).unwrap() {
+            let path = entry.unwrap().path();
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            // the read only listener serves a subset of the routes of the other modules
+            if ["mod.rs", "read_only.rs"].contains(&file_name.as_str()) {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let mut rest = source.as_str();
+            while let Some(start) = rest.find(needle) {
+                rest = &rest[start + needle.len()..];
+                let route_path = rest.split('"').nth(1).unwrap();
+                let mut depth = 1;
+                let end = rest
+                    .char_indices()
+                    .find(|(_, c)| {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .unwrap()
+                    .0;
+                let method_router = &rest[..end];
+                for method in ["get", "post", "put", "patch", "delete"] {
+                    for (i, _) in method_router.match_indices(&format!("{method}(")) {
+                        if !method_router[..i].ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                        {
+                            registered.insert((method.to_string(), route_path.to_string()));
+                        }
+                    }
+                }
+            }
+        }
+        registered
+    }
+
+    #[test]
+    fn test_every_route_is_described() {
+        let described: BTreeSet<(String, String)> = routes()
+            .iter()
+            .map(|route| (route.method.to_string(), route.path.to_string()))
+            .collect();
+        let registered = registered_routes();
+        assert_eq!(
+            registered.difference(&described).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "routes missing from the OpenAPI description"
+        );
+        assert_eq!(
+            described.difference(&registered).collect::<Vec<_>>(),
+            Vec::<&(String, String)>::new(),
+            "routes described but not registered"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let document = openapi_document(&routes());
+        let info = &document["paths"]["/instance/{uuid}/info"]["get"];
+        assert_eq!(info["parameters"][0]["name"], "uuid");
+        assert_eq!(
+            info["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/InstanceInfo"
+        );
+        let schemas = &document["components"]["schemas"];
+        assert!(schemas["ClientEvent"].is_object());
+        assert!(schemas["ErrorResponse"].is_object());
+        assert_eq!(
+            document["paths"]["/openapi.json"]["get"]["security"],
+            serde_json::json!([])
+        );
+    }
+}
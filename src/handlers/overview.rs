@@ -0,0 +1,135 @@
+use axum::{routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use ringbuffer::RingBufferExt;
+use serde::Serialize;
+use sysinfo::{CpuExt, DiskExt, SystemExt};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    events::{Event, EventLevel},
+    host_pressure::HostPressureWatcher,
+    traits::{
+        t_player::TPlayerManagement,
+        t_server::{State, TServer},
+    },
+    AppState,
+};
+
+/// number of critical events returned in the overview
+const RECENT_CRITICAL_EVENTS: usize = 10;
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct HostOverview {
+    pub cpu_load: f32,
+    pub total_memory: u64,
+    pub available_memory: u64,
+    pub total_disk: u64,
+    pub available_disk: u64,
+    pub up_since: i64,
+}
+
+#[derive(Serialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct InstanceStateCounts {
+    pub total: u32,
+    pub starting: u32,
+    pub running: u32,
+    pub stopping: u32,
+    pub stopped: u32,
+    pub error: u32,
+    pub crashed: u32,
+    pub installing: u32,
+}
+
+impl InstanceStateCounts {
+    pub fn count(&mut self, state: State) {
+        self.total += 1;
+        match state {
+            State::Starting => self.starting += 1,
+            State::Running => self.running += 1,
+            State::Stopping => self.stopping += 1,
+            State::Stopped => self.stopped += 1,
+            State::Error => self.error += 1,
+            State::Crashed => self.crashed += 1,
+            State::Installing => self.installing += 1,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct Overview {
+    pub host: HostOverview,
+    /// only the instances the user can view are counted
+    pub instances: InstanceStateCounts,
+    pub players_online: u32,
+    /// most recent first
+    pub recent_critical_events: Vec<Event>,
+    /// host resources currently under pressure
+    pub pending_alerts: Vec<Event>,
+}
+
+pub async fn get_overview(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Overview>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+
+    let (host, pending_alerts) = {
+        let mut sys = state.system.lock().await;
+        sys.refresh_cpu();
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        sys.refresh_cpu();
+        // a fresh watcher reports every resource that is currently under pressure
+        let pending_alerts = HostPressureWatcher::new().check(&mut sys, true);
+        let host = HostOverview {
+            cpu_load: sys.cpus().iter().fold(0.0, |acc, v| acc + v.cpu_usage())
+                / sys.cpus().len() as f32,
+            total_memory: sys.total_memory(),
+            available_memory: sys.available_memory(),
+            total_disk: sys.disks().iter().map(|disk| disk.total_space()).sum(),
+            available_disk: sys.disks().iter().map(|disk| disk.available_space()).sum(),
+            up_since: state.up_since,
+        };
+        (host, pending_alerts)
+    };
+
+    let mut instances = InstanceStateCounts::default();
+    let mut players_online = 0;
+    for (uuid, instance) in state.instances.snapshot() {
+        if !requester.can_perform_action(&UserAction::ViewInstance(uuid)) {
+            continue;
+        }
+        instances.count(instance.state().await);
+        players_online += instance.get_player_count().await.unwrap_or(0);
+    }
+
+    let recent_critical_events = state
+        .events_buffer
+        .lock()
+        .await
+        .iter()
+        .rev()
+        .filter(|event| event.level() >= EventLevel::Error)
+        .filter(|event| requester.can_view_event(event))
+        .take(RECENT_CRITICAL_EVENTS)
+        .cloned()
+        .collect();
+
+    Ok(Json(Overview {
+        host,
+        instances,
+        players_online,
+        recent_critical_events,
+        pending_alerts,
+    }))
+}
+
+pub fn get_overview_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/overview", get(get_overview))
+        .with_state(state)
+}
@@ -0,0 +1,39 @@
+use axum::{routing::get, Router};
+
+use crate::AppState;
+
+use super::{
+    core_info::get_core_info,
+    events::{event_stream, get_event_buffer},
+    instance::{get_instance_info, get_instance_list},
+    instance_players::{get_player_count, get_player_list},
+    instance_server::get_instance_state,
+    metrics::get_metrics,
+    monitor::monitor,
+    overview::get_overview,
+    reservation::get_reservation_plan,
+    system::{get_cpu_info, get_disk, get_ram},
+};
+
+/// The routes served on the read only listener, nothing here can change the state of the core.
+///
+/// Handlers still authenticate as usual, this only limits what is reachable.
+pub fn get_read_only_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/info", get(get_core_info))
+        .route("/overview", get(get_overview))
+        .route("/system/ram", get(get_ram))
+        .route("/system/disk", get(get_disk))
+        .route("/system/cpu", get(get_cpu_info))
+        .route("/instance/list", get(get_instance_list))
+        .route("/instance/reservations", get(get_reservation_plan))
+        .route("/instance/:uuid/info", get(get_instance_info))
+        .route("/instance/:uuid/state", get(get_instance_state))
+        .route("/instance/:uuid/players/count", get(get_player_count))
+        .route("/instance/:uuid/players", get(get_player_list))
+        .route("/monitor/:uuid", get(monitor))
+        .route("/events/:uuid/stream", get(event_stream))
+        .route("/events/:uuid/buffer", get(get_event_buffer))
+        .route("/metrics", get(get_metrics))
+        .with_state(state)
+}
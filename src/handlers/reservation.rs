@@ -0,0 +1,76 @@
+use axum::{extract::Query, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use sysinfo::SystemExt;
+
+use crate::{
+    auth::user::UserAction,
+    error::Error,
+    prelude::GameInstance,
+    reservation::{
+        plan_reservations, HostSpecs, InstanceReservation, ReservationPlan, DEFAULT_MEMORY_HEADROOM,
+    },
+    traits::t_configurable::TConfigurable,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct ReservationQuery {
+    /// plan for a host with this much memory instead of this one, in megabytes
+    pub total_memory: Option<u64>,
+    /// in megabytes
+    pub memory_headroom: Option<u64>,
+}
+
+/// Whether the configured heaps and limits of the instances the user can view fit on the host,
+/// and which sets of auto start instances can run together
+pub async fn get_reservation_plan(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<ReservationQuery>,
+) -> Result<Json<ReservationPlan>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let total_memory = match query.total_memory {
+        Some(total_memory) => total_memory,
+        None => {
+            let mut sys = state.system.lock().await;
+            sys.refresh_memory();
+            sys.total_memory() / 1024 / 1024
+        }
+    };
+    let mut reservations = Vec::new();
+    for (uuid, instance) in state.instances.snapshot() {
+        if !requester.can_perform_action(&UserAction::ViewInstance(uuid.clone())) {
+            continue;
+        }
+        let limits = instance.resource_limits().await;
+        let memory = match &instance {
+            GameInstance::MinecraftInstance(minecraft) => Some(InstanceReservation::jvm_memory(
+                minecraft.heap_sizes().await.1,
+                limits.memory_limit,
+            )),
+            _ => limits.memory_limit,
+        };
+        reservations.push(InstanceReservation {
+            uuid,
+            name: instance.name().await,
+            auto_start: instance.auto_start().await,
+            memory,
+            cpu: limits.cpu_limit,
+        });
+    }
+    reservations.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(plan_reservations(
+        HostSpecs {
+            total_memory,
+            memory_headroom: query.memory_headroom.unwrap_or(DEFAULT_MEMORY_HEADROOM),
+        },
+        reservations,
+    )))
+}
+
+pub fn get_reservation_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/reservations", get(get_reservation_plan))
+        .with_state(state)
+}
@@ -1,10 +1,15 @@
-use axum::{extract::Path, Json, Router};
+use axum::{
+    extract::Path,
+    routing::{get, post, put},
+    Json, Router,
+};
 use color_eyre::eyre::eyre;
 
 use crate::{
     auth::{permission::UserPermission, user::User},
     error::{Error, ErrorKind},
     events::CausedBy,
+    setup_key::{setup_completed_event, SetupKeyInfo, SetupStatus},
     AppState,
 };
 
@@ -16,46 +21,98 @@ pub struct OwnerSetup {
     password: String,
 }
 
+fn already_set_up() -> Error {
+    Error {
+        kind: ErrorKind::PermissionDenied,
+        source: eyre!("Setup key already used."),
+    }
+}
+
+pub async fn get_setup_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Json<SetupStatus> {
+    let setup_key = state.first_time_setup_key.lock().await;
+    Json(SetupStatus {
+        is_setup: setup_key.is_none(),
+        key_expires_at: setup_key.as_ref().map(|setup_key| setup_key.expires_at()),
+    })
+}
+
 pub async fn setup_owner(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(key): Path<String>,
     Json(owner_setup): Json<OwnerSetup>,
 ) -> Result<Json<LoginReply>, Error> {
     let mut setup_key_lock = state.first_time_setup_key.lock().await;
-    match setup_key_lock.clone() {
-        Some(k) if k == key => {
-            setup_key_lock.take();
-            let owner = User::new(
-                owner_setup.username,
-                &owner_setup.password,
-                true,
-                false,
-                UserPermission::default(),
-            );
-            state
-                .users_manager
-                .write()
-                .await
-                .add_user(owner.clone(), CausedBy::System)
-                .await?;
-            Ok(Json(LoginReply {
-                token: owner.create_jwt()?,
-                user: owner.into(),
-            }))
-        }
-        None => Err(Error {
-            kind: ErrorKind::PermissionDenied,
-            source: eyre!("Setup key already used."),
-        }),
-        Some(_) => Err(Error {
+    let setup_key = setup_key_lock.as_ref().ok_or_else(already_set_up)?;
+    setup_key.verify(&key)?;
+    let owner = User::new(
+        owner_setup.username,
+        &owner_setup.password,
+        true,
+        false,
+        UserPermission::default(),
+    );
+    let mut users_manager = state.users_manager.write().await;
+    users_manager
+        .add_user(owner.clone(), CausedBy::System)
+        .await?;
+    // only used up once the owner exists, so a failed attempt can be retried
+    if let Some(setup_key) = setup_key_lock.take() {
+        setup_key.remove_file().await;
+    }
+    state
+        .event_broadcaster
+        .send(setup_completed_event(&owner.username));
+    let (token, refresh_token) = users_manager
+        .create_session(&owner.uid, None, CausedBy::System)
+        .await?;
+    Ok(Json(LoginReply {
+        token,
+        refresh_token,
+        two_factor_setup_required: users_manager.two_factor_required(),
+        user: owner.into(),
+    }))
+}
+
+/// Replace the setup key, which can be expired but not invalidated, by a new one. Only a holder
+/// of the key can do so
+pub async fn rotate_setup_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Json<SetupKeyInfo>, Error> {
+    let mut setup_key_lock = state.first_time_setup_key.lock().await;
+    let setup_key = setup_key_lock.as_mut().ok_or_else(already_set_up)?;
+    setup_key.verify_rotation(&key)?;
+    setup_key.rotate().await;
+    Ok(Json(setup_key.info()))
+}
+
+/// Expire the setup key right away, e.g. when it leaked. It can't be rotated afterwards, a new
+/// one is generated when the core restarts
+pub async fn invalidate_setup_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Json<()>, Error> {
+    let mut setup_key_lock = state.first_time_setup_key.lock().await;
+    let setup_key = setup_key_lock.as_mut().ok_or_else(already_set_up)?;
+    if !setup_key.is(&key) {
+        return Err(Error {
             kind: ErrorKind::PermissionDenied,
             source: eyre!("Invalid setup key."),
-        }),
+        });
     }
+    setup_key.invalidate().await;
+    Ok(Json(()))
 }
 
 pub fn get_setup_route(state: AppState) -> Router {
     Router::new()
-        .route("/setup/:key", axum::routing::post(setup_owner))
+        .route("/setup", get(get_setup_status))
+        .route(
+            "/setup/:key",
+            post(setup_owner).delete(invalidate_setup_key),
+        )
+        .route("/setup/:key/rotate", put(rotate_setup_key))
         .with_state(state)
 }
@@ -0,0 +1,75 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    start_dependencies::StartDependency,
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_start_dependencies(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<StartDependency>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ViewInstance(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state.start_dependencies.lock().await.dependencies_of(&uuid),
+    ))
+}
+
+/// Replace the instances that have to be ready before this one starts
+pub async fn set_start_dependencies(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(dependencies): Json<Vec<StartDependency>>,
+) -> Result<Json<Vec<StartDependency>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    for dependency in &dependencies {
+        // an instance the requester can't see is reported as missing
+        if !state.instances.contains_key(&dependency.instance_uuid)
+            || !requester
+                .can_perform_action(&UserAction::ViewInstance(dependency.instance_uuid.clone()))
+        {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Dependency {} not found", dependency.instance_uuid),
+            });
+        }
+    }
+    Ok(Json(
+        state
+            .start_dependencies
+            .lock()
+            .await
+            .set_dependencies(&uuid, dependencies)
+            .await?,
+    ))
+}
+
+pub fn get_start_dependency_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/start_dependencies",
+            get(get_start_dependencies).put(set_start_dependencies),
+        )
+        .with_state(state)
+}
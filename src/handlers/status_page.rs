@@ -0,0 +1,88 @@
+use axum::{
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    error::{Error, ErrorKind},
+    status_page::{publish, server_status, ServerStatus, StatusPageConfig},
+    AppState,
+};
+
+fn not_configured() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("The status page is not configured"),
+    }
+}
+
+pub async fn get_status_page_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<StatusPageConfig>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage the status page")?;
+    Ok(Json(state.status_page.lock().await.config().cloned()))
+}
+
+pub async fn set_status_page_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<Option<StatusPageConfig>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage the status page")?;
+    state.status_page.lock().await.set_config(config).await?;
+    Ok(Json(()))
+}
+
+/// What would be published right now
+pub async fn preview_status_page(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ServerStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage the status page")?;
+    let config = state
+        .status_page
+        .lock()
+        .await
+        .config()
+        .cloned()
+        .ok_or_else(not_configured)?;
+    Ok(Json(
+        server_status(&config, &state.instances, &state.global_settings).await,
+    ))
+}
+
+/// Publish now instead of waiting for the next change, even if publishing is disabled
+pub async fn publish_status_page(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ServerStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage the status page")?;
+    let config = state
+        .status_page
+        .lock()
+        .await
+        .config()
+        .cloned()
+        .ok_or_else(not_configured)?;
+    let status = server_status(&config, &state.instances, &state.global_settings).await;
+    publish(&reqwest::Client::new(), &config.destination, &status).await?;
+    Ok(Json(status))
+}
+
+pub fn get_status_page_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/status_page",
+            get(get_status_page_config).put(set_status_page_config),
+        )
+        .route("/status_page/preview", get(preview_status_page))
+        .route("/status_page/publish", post(publish_status_page))
+        .with_state(state)
+}
@@ -0,0 +1,316 @@
+use std::path::PathBuf;
+
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use sysinfo::SystemExt;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    data_relocation::rebase_instance_config_paths,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    jobs::{JobHandle, JobKind, NewJob},
+    prelude::GameInstance,
+    storage_roots::{disk_space, StorageRoot, StorageRootConfig, StorageRootInfo},
+    traits::t_configurable::TConfigurable,
+    traits::t_server::TServer,
+    types::{DotLodestoneConfig, InstanceUuid, Snowflake},
+    util::format_byte_download,
+    AppState,
+};
+
+async fn instance_paths(state: &AppState) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for instance in state.instances.values() {
+        paths.push(instance.path().await);
+    }
+    paths
+}
+
+/// Every storage root with the space left on its disk, to pick one when creating an instance
+pub async fn get_storage_roots(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<StorageRootInfo>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::CreateInstance)?;
+    let instance_paths = instance_paths(&state).await;
+    let storage_roots = state.storage_roots.lock().await;
+    let mut sys = state.system.lock().await;
+    sys.refresh_disks_list();
+    sys.refresh_disks();
+    Ok(Json(
+        storage_roots
+            .roots()
+            .into_iter()
+            .map(|root| {
+                let space = disk_space(&sys, &root.path);
+                StorageRootInfo {
+                    is_default: root.id == storage_roots.default_root_id(),
+                    instance_count: instance_paths
+                        .iter()
+                        .filter(|path| storage_roots.root_of(path).is_some_and(|r| r.id == root.id))
+                        .count(),
+                    available_space: space.map(|(available, _)| available),
+                    total_space: space.map(|(_, total)| total),
+                    root,
+                }
+            })
+            .collect(),
+    ))
+}
+
+pub async fn add_storage_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<StorageRootConfig>,
+) -> Result<Json<StorageRoot>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage storage roots")?;
+    Ok(Json(state.storage_roots.lock().await.add(config).await?))
+}
+
+/// Forget a root that holds no instance, its directory is left alone
+pub async fn remove_storage_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage storage roots")?;
+    let instance_paths = instance_paths(&state).await;
+    state
+        .storage_roots
+        .lock()
+        .await
+        .remove(&id, &instance_paths)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Place new instances in this root when no root is asked for
+pub async fn set_default_storage_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("manage storage roots")?;
+    state.storage_roots.lock().await.set_default(&id).await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct MoveInstanceRequest {
+    pub storage_root: String,
+}
+
+/// Move a stopped instance to another storage root in a job, the instance is unavailable until
+/// the job finishes
+pub async fn move_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<MoveInstanceRequest>,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::DeleteInstance)?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !instance.state().await.is_idle() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before moving"),
+        });
+    }
+    let from = instance.path().await;
+    let (target, current) = {
+        let storage_roots = state.storage_roots.lock().await;
+        (
+            storage_roots.root(&request.storage_root)?,
+            storage_roots.root_of(&from),
+        )
+    };
+    if current.is_some_and(|root| root.id == target.id) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The instance is already in {}", target.name),
+        });
+    }
+    let to = target.path.join(
+        from.file_name()
+            .ok_or_else(|| eyre!("Instance directory {} has no name", from.display()))?,
+    );
+    if to.exists() {
+        return Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("{} already exists", to.display()),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let name = format!("Moving {} to {}", instance.name().await, target.name);
+    Ok(Json(state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Move,
+            name,
+            instance_uuid: Some(uuid.clone()),
+            caused_by,
+            cancellable: true,
+        },
+        |job| relocate_instance(state, uuid, from, to, job),
+    )))
+}
+
+/// Rename `from` to `to`, or copy it over if they are on different disks. Returns whether it was
+/// renamed, a copy leaves `from` in place
+async fn move_files(from: PathBuf, to: PathBuf, job: JobHandle) -> Result<bool, Error> {
+    if tokio::fs::rename(&from, &to).await.is_ok() {
+        return Ok(true);
+    }
+    crate::util::fs::create_dir_all(&to).await?;
+    let result = tokio::task::spawn_blocking({
+        let to = to.clone();
+        let job = job.clone();
+        move || {
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.content_only = true;
+            fs_extra::dir::copy_with_progress(from, to, &options, |process| {
+                if job.is_cancelled() {
+                    return fs_extra::dir::TransitProcessResult::Abort;
+                }
+                job.set_progress(
+                    process.copied_bytes as f64 / process.total_bytes.max(1) as f64 * 100.0,
+                    Some(format!(
+                        "Copying, {}",
+                        format_byte_download(process.copied_bytes, process.total_bytes)
+                    )),
+                );
+                fs_extra::dir::TransitProcessResult::ContinueOrAbort
+            })
+        }
+    })
+    .await
+    .context("Copy task panicked")?;
+    if job.is_cancelled() || result.is_err() {
+        if let Err(e) = crate::util::fs::remove_dir_all(&to).await {
+            error!(
+                "Failed to remove {} after the move failed : {e}",
+                to.display()
+            );
+        }
+    }
+    if job.is_cancelled() {
+        return Err(eyre!("Move cancelled").into());
+    }
+    result.context("Failed to copy instance files")?;
+    Ok(false)
+}
+
+/// Load the instance from its new directory
+async fn restore_moved_instance(
+    state: &AppState,
+    from: &std::path::Path,
+    to: &std::path::Path,
+) -> Result<GameInstance, Error> {
+    rebase_instance_config_paths(to, from, to)?;
+    let dot_lodestone_config: DotLodestoneConfig =
+        serde_json::from_str(&crate::util::fs::read_to_string(to.join(".lodestone_config")).await?)
+            .context("Failed to parse .lodestone_config file")?;
+    crate::restore_instance(
+        to,
+        &dot_lodestone_config,
+        state.event_broadcaster.clone(),
+        state.macro_executor.clone(),
+    )
+    .await
+}
+
+async fn relocate_instance(
+    state: AppState,
+    uuid: InstanceUuid,
+    from: PathBuf,
+    to: PathBuf,
+    job: JobHandle,
+) -> Result<(), Error> {
+    // out of the map while the files move, so that it can't be started or changed
+    let instance = state.instances.remove(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    if !instance.state().await.is_idle() {
+        state.instances.insert(uuid, instance);
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before moving"),
+        });
+    }
+    let renamed = match move_files(from.clone(), to.clone(), job.clone()).await {
+        Ok(renamed) => renamed,
+        Err(e) => {
+            state.instances.insert(uuid, instance);
+            return Err(e);
+        }
+    };
+    let moved = match restore_moved_instance(&state, &from, &to).await {
+        Ok(moved) => moved,
+        Err(e) => {
+            // put the files back the way they were
+            let rollback = if renamed {
+                match crate::util::fs::rename(&to, &from).await {
+                    Ok(()) => rebase_instance_config_paths(&from, &to, &from),
+                    Err(e) => Err(e),
+                }
+            } else {
+                crate::util::fs::remove_dir_all(&to).await
+            };
+            if let Err(e) = rollback {
+                error!(
+                    "Failed to undo the move of {uuid} to {} : {e}",
+                    to.display()
+                );
+            }
+            state.instances.insert(uuid, instance);
+            return Err(e);
+        }
+    };
+    if let GameInstance::GenericInstance(i) = instance {
+        i.destruct().await;
+    }
+    state.instances.insert(uuid.clone(), moved);
+    if !renamed {
+        job.set_progress(100.0, Some("Removing the old files".to_string()));
+        if let Err(e) = crate::util::fs::remove_dir_all(&from).await {
+            error!(
+                "Moved {uuid} but failed to remove its old directory {} : {e}",
+                from.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn get_storage_roots_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/storage_roots",
+            get(get_storage_roots).post(add_storage_root),
+        )
+        .route("/storage_roots/:id", delete(remove_storage_root))
+        .route("/storage_roots/:id/default", put(set_default_storage_root))
+        .route("/instance/:uuid/move", post(move_instance))
+        .with_state(state)
+}
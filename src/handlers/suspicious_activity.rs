@@ -0,0 +1,66 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    suspicious_activity::SuspiciousActivityPolicy,
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_suspicious_activity_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<SuspiciousActivityPolicy>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    Ok(Json(
+        state.suspicious_activity_policies.lock().await.get(&uuid),
+    ))
+}
+
+/// Mitigations are console commands run without anyone at the console, so setting them takes
+/// access to the console too
+pub async fn set_suspicious_activity_policy(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(policy): Json<SuspiciousActivityPolicy>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()))?;
+    if !policy.mitigations.is_empty() {
+        requester.try_action(&UserAction::AccessConsole(uuid.clone()))?;
+    }
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    state
+        .suspicious_activity_policies
+        .lock()
+        .await
+        .set(&uuid, policy)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_suspicious_activity_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/suspicious_activity",
+            get(get_suspicious_activity_policy).put(set_suspicious_activity_policy),
+        )
+        .with_state(state)
+}
@@ -1,10 +1,31 @@
-use axum::{routing::get, Json, Router};
+use std::{path::PathBuf, time::Duration};
+
+use axum::{
+    extract::Query,
+    routing::{get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 use sysinfo::{CpuExt, CpuRefreshKind, DiskExt, SystemExt};
 
 use tokio::time::sleep;
+use tracing::{error, info};
+use ts_rs::TS;
 
-use crate::AppState;
+use crate::{
+    data_relocation,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event},
+    jobs::{JobKind, NewJob},
+    prelude::lodestone_path,
+    self_update::UpdateStatus,
+    server_config::ServerConfig,
+    shutdown::{self, HostPowerAction},
+    types::Snowflake,
+    AppState,
+};
 
 // Since MemInfo is not serializable, we need to create a new struct that is serializable.
 #[derive(Serialize, Deserialize)]
@@ -63,10 +84,290 @@ pub async fn get_cpu_info(
     })
 }
 
+#[derive(Deserialize)]
+pub struct RelocateDataConfig {
+    /// absolute path of the new data directory, must be empty or not exist
+    pub path: PathBuf,
+    /// delete the old data once the core is running from the new directory
+    #[serde(default)]
+    pub remove_old: bool,
+}
+
+/// Move the data directory to a new path
+///
+/// Every instance is stopped, the data is copied with progress events and the core restarts
+/// from the new directory. The old directory keeps a redirect, so the core can still be
+/// started with the old path.
+pub async fn relocate_data_directory(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<RelocateDataConfig>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("relocate the data directory")?;
+    let from = lodestone_path().clone();
+    data_relocation::validate_target(&from, &config.path)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            "Relocating the data directory",
+            Some(100.0),
+            None,
+            caused_by,
+        );
+        state.event_broadcaster.send(progression_start_event);
+        let grace_period = ServerConfig::load(&from)
+            .await
+            .map(|config| config.shutdown_grace_period)
+            .unwrap_or(30);
+        shutdown::stop_all_instances(&state, Duration::from_secs(grace_period)).await;
+        shutdown::flush_stores(&state).await;
+        let event_broadcaster = state.event_broadcaster.clone();
+        let relocation = tokio::task::spawn_blocking(move || {
+            let mut last_percent = 0;
+            let result = data_relocation::relocate(
+                &from,
+                &config.path,
+                config.remove_old,
+                |copied, total| {
+                    let percent = (copied * 100).checked_div(total).unwrap_or(100);
+                    if percent > last_percent {
+                        event_broadcaster.send(Event::new_progression_event_update(
+                            &event_id,
+                            format!("Copied {percent}%"),
+                            (percent - last_percent) as f64,
+                        ));
+                        last_percent = percent;
+                    }
+                },
+            );
+            (event_id, result)
+        })
+        .await;
+        match relocation {
+            Ok((event_id, Ok(()))) => {
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Data directory relocated, restarting"),
+                        None,
+                    ));
+                shutdown::request_restart();
+            }
+            Ok((event_id, Err(e))) => {
+                error!("Failed to relocate the data directory : {e}");
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Failed to relocate the data directory : {e}")),
+                        None,
+                    ));
+            }
+            Err(e) => error!("Relocation task panicked : {e}"),
+        }
+    });
+    Ok(Json(()))
+}
+
+/// the longest players can be warned ahead of a host maintenance, in seconds
+const MAX_MAINTENANCE_WARNING: u64 = 3600;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct HostMaintenanceConfig {
+    /// what to do with the host once everything is stopped, the core keeps running if not set
+    pub power_action: Option<HostPowerAction>,
+    /// seconds between warning the players and stopping the instances
+    #[serde(default = "default_maintenance_warning")]
+    pub warning: u64,
+    /// shown to the players of minecraft instances
+    pub message: Option<String>,
+}
+
+fn default_maintenance_warning() -> u64 {
+    60
+}
+
+/// Prepare the host for maintenance: warn the players, stop every instance, flush the stores and
+/// then reboot or shut down the host if asked to
+pub async fn host_maintenance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<HostMaintenanceConfig>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("put the host into maintenance")?;
+    if config.warning > MAX_MAINTENANCE_WARNING {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Players can be warned at most {MAX_MAINTENANCE_WARNING} seconds ahead"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    tokio::spawn(async move {
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            "Preparing the host for maintenance",
+            None,
+            None,
+            caused_by,
+        );
+        state.event_broadcaster.send(progression_start_event);
+        let message = config
+            .message
+            .unwrap_or_else(|| "The server is going down for maintenance".to_string());
+        if config.warning > 0 {
+            shutdown::warn_running_instances(
+                &state,
+                &format!("{message} in {} seconds", config.warning),
+            )
+            .await;
+            sleep(Duration::from_secs(config.warning)).await;
+        }
+        shutdown::warn_running_instances(&state, &message).await;
+        let grace_period = ServerConfig::load(lodestone_path())
+            .await
+            .map(|config| config.shutdown_grace_period)
+            .unwrap_or(30);
+        shutdown::stop_all_instances(&state, Duration::from_secs(grace_period)).await;
+        shutdown::flush_stores(&state).await;
+        let power_action = match config.power_action {
+            Some(power_action) => power_action,
+            None => {
+                state
+                    .event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Every instance is stopped"),
+                        None,
+                    ));
+                return;
+            }
+        };
+        state
+            .event_broadcaster
+            .send(Event::new_progression_event_update(
+                &event_id,
+                format!("Every instance is stopped, running the host {power_action:?}"),
+                0.0,
+            ));
+        let (success, message) = match shutdown::run_host_power_action(power_action).await {
+            Ok(()) => {
+                info!("Host {power_action:?} started");
+                (true, format!("Host {power_action:?} started"))
+            }
+            Err(e) => {
+                error!("Failed to {power_action:?} the host : {e}");
+                (false, format!("Failed to {power_action:?} the host : {e}"))
+            }
+        };
+        state
+            .event_broadcaster
+            .send(Event::new_progression_event_end(
+                event_id,
+                success,
+                Some(&message),
+                None,
+            ));
+    });
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct UpdateStatusQuery {
+    /// check the release feed now rather than return the result of the last check, owner only
+    #[serde(default)]
+    pub refresh: bool,
+    /// consider pre-releases when checking
+    #[serde(default)]
+    pub prerelease: bool,
+}
+
+/// The running version and the newest release for this platform, the feed is checked daily
+pub async fn get_update_status(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<UpdateStatusQuery>,
+) -> Result<Json<UpdateStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if query.refresh {
+        requester.try_owner("check for updates")?;
+    }
+    let status = state.updater.status().await;
+    // others get the empty status until the daily check ran
+    if query.refresh || (requester.is_owner && status.checked_at.is_none()) {
+        return Ok(Json(state.updater.check(query.prerelease).await?));
+    }
+    Ok(Json(status))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct InstallUpdateRequest {
+    /// the version found by the last check
+    pub version: String,
+}
+
+/// Download and integrity check the update found by the last check in a job, then restart the core on it
+/// once every instance is stopped gracefully
+pub async fn install_update(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<InstallUpdateRequest>,
+) -> Result<Json<Snowflake>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_owner("update the core")?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let updater = state.updater.clone();
+    Ok(Json(state.jobs.clone().spawn(
+        NewJob {
+            kind: JobKind::Update,
+            name: format!("Updating Lodestone Core to {}", request.version),
+            instance_uuid: None,
+            caused_by,
+            cancellable: true,
+        },
+        |job| async move {
+            updater.install(&request.version, &job).await?;
+            shutdown::warn_running_instances(
+                &state,
+                &format!(
+                    "The server is restarting to update Lodestone Core to {}",
+                    request.version
+                ),
+            )
+            .await;
+            // the instances are stopped and the stores flushed on the way out
+            shutdown::request_restart();
+            Ok(())
+        },
+    )))
+}
+
 pub fn get_system_routes(state: AppState) -> Router {
     Router::new()
         .route("/system/ram", get(get_ram))
         .route("/system/disk", get(get_disk))
         .route("/system/cpu", get(get_cpu_info))
+        .route("/system/relocate", post(relocate_data_directory))
+        .route("/system/maintenance", post(host_maintenance))
+        .route(
+            "/system/update",
+            get(get_update_status).post(install_update),
+        )
         .with_state(state)
 }
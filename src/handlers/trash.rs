@@ -0,0 +1,234 @@
+use std::path::PathBuf;
+
+use axum::{
+    extract::Path,
+    routing::{delete, get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use walkdir::WalkDir;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    traits::t_configurable::TConfigurable,
+    trash::{Trash, TrashEntry},
+    types::{InstanceUuid, Snowflake},
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::instance_fs::{is_path_protected, PROTECTED_DIR_NAME};
+
+async fn instance_path(state: &AppState, uuid: &InstanceUuid) -> Result<PathBuf, Error> {
+    let instance = state.instances.get(uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(instance.path().await)
+}
+
+async fn instance_trash(state: &AppState, uuid: &InstanceUuid) -> Result<Trash, Error> {
+    Ok(Trash::for_instance(&instance_path(state, uuid).await?))
+}
+
+fn fs_target(entry: &TrashEntry, path: PathBuf) -> FSTarget {
+    if entry.is_dir {
+        FSTarget::Directory(path)
+    } else {
+        FSTarget::File(path)
+    }
+}
+
+fn caused_by(requester: &User) -> CausedBy {
+    CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    }
+}
+
+pub async fn get_instance_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()))?;
+    Ok(Json(instance_trash(&state, &uuid).await?.list().await?))
+}
+
+/// Put an entry back in the instance, the same files are protected as when deleting
+pub async fn restore_instance_trash_entry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TrashEntry>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let root = instance_path(&state, &uuid).await?;
+    let trash = Trash::for_instance(&root);
+    let entry = trash.get(&id).await?;
+    let original_path = scoped_join_win_safe(&root, &entry.original_path)?;
+    if !requester.can_perform_action(&UserAction::WriteGlobalFile) {
+        let content = trash.path_to_content(&id);
+        let protected = if entry.is_dir {
+            entry
+                .original_path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(true, |name| PROTECTED_DIR_NAME.contains(&name))
+                || WalkDir::new(&content)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .any(|file| {
+                        // checked where it is restored to, the trash itself is protected
+                        file.file_type().is_file()
+                            && file.path().strip_prefix(&content).map_or(true, |relative| {
+                                is_path_protected(original_path.join(relative))
+                            })
+                    })
+        } else {
+            // the content is kept under the id of the entry, which has no extension
+            is_path_protected(&original_path)
+        };
+        if protected {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Entry is or contains protected files"),
+            });
+        }
+    }
+    let path = trash.restore(&id).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Create,
+        fs_target(&entry, path),
+        caused_by(&requester),
+    ));
+    Ok(Json(entry))
+}
+
+pub async fn purge_instance_trash_entry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let trash = instance_trash(&state, &uuid).await?;
+    let content = trash.path_to_content(&id);
+    let entry = trash.purge(&id).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Delete,
+        fs_target(&entry, content),
+        caused_by(&requester),
+    ));
+    Ok(Json(()))
+}
+
+/// Empty the trash of the instance, returns what was purged
+pub async fn purge_instance_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteInstanceFile(uuid.clone()))?;
+    let trash = instance_trash(&state, &uuid).await?;
+    let purged = trash.purge_all().await?;
+    for entry in &purged {
+        state.event_broadcaster.send(new_fs_event(
+            FSOperation::Delete,
+            fs_target(entry, trash.path_to_content(&entry.id)),
+            caused_by(&requester),
+        ));
+    }
+    Ok(Json(purged))
+}
+
+pub async fn get_global_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ReadGlobalFile)?;
+    Ok(Json(Trash::global().list().await?))
+}
+
+pub async fn restore_global_trash_entry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TrashEntry>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteGlobalFile)?;
+    let trash = Trash::global();
+    let entry = trash.get(&id).await?;
+    let path = trash.restore(&id).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Create,
+        fs_target(&entry, path),
+        caused_by(&requester),
+    ));
+    Ok(Json(entry))
+}
+
+pub async fn purge_global_trash_entry(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteGlobalFile)?;
+    let trash = Trash::global();
+    let content = trash.path_to_content(&id);
+    let entry = trash.purge(&id).await?;
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Delete,
+        fs_target(&entry, content),
+        caused_by(&requester),
+    ));
+    Ok(Json(()))
+}
+
+pub async fn purge_global_trash(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<TrashEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::WriteGlobalFile)?;
+    let trash = Trash::global();
+    let purged = trash.purge_all().await?;
+    for entry in &purged {
+        state.event_broadcaster.send(new_fs_event(
+            FSOperation::Delete,
+            fs_target(entry, trash.path_to_content(&entry.id)),
+            caused_by(&requester),
+        ));
+    }
+    Ok(Json(purged))
+}
+
+pub fn get_trash_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/trash",
+            get(get_instance_trash).delete(purge_instance_trash),
+        )
+        .route(
+            "/instance/:uuid/trash/:id",
+            delete(purge_instance_trash_entry),
+        )
+        .route(
+            "/instance/:uuid/trash/:id/restore",
+            put(restore_instance_trash_entry),
+        )
+        .route(
+            "/fs/trash",
+            get(get_global_trash).delete(purge_global_trash),
+        )
+        .route("/fs/trash/:id", delete(purge_global_trash_entry))
+        .route("/fs/trash/:id/restore", put(restore_global_trash_entry))
+        .with_state(state)
+}
@@ -0,0 +1,272 @@
+use std::path::PathBuf;
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query},
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    upload_sessions::{
+        finish_upload, write_part, NewUpload, UploadSession, UploadTarget, MAX_CHUNK_SIZE,
+    },
+    util::scoped_join_win_safe,
+    AppState,
+};
+
+use super::{instance_fs::is_path_protected, util::decode_base64};
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct UploadStatus {
+    pub session: UploadSession,
+    pub part_count: u64,
+    /// the parts to send to finish the upload
+    pub missing_parts: Vec<u64>,
+}
+
+impl From<UploadSession> for UploadStatus {
+    fn from(session: UploadSession) -> Self {
+        Self {
+            part_count: session.part_count(),
+            missing_parts: session.missing_parts(),
+            session,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PartQuery {
+    /// hex encoded SHA-256 of the part
+    pub sha256: Option<String>,
+}
+
+/// The directory an upload goes to, checking the requester can still write there and that the
+/// `size` bytes of the upload fit in the disk quota of the instance
+async fn resolve_target_dir(
+    state: &AppState,
+    requester: &User,
+    target: &UploadTarget,
+    file_name: &str,
+    size: u64,
+) -> Result<PathBuf, Error> {
+    match target {
+        UploadTarget::Instance {
+            instance_uuid,
+            relative_path,
+        } => {
+            requester.try_action(&UserAction::WriteInstanceFile(instance_uuid.clone()))?;
+            let instance = state.instances.get(instance_uuid).ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?;
+            let root = instance.path().await;
+            let dir = scoped_join_win_safe(&root, relative_path)?;
+            // if the file has a protected extension, or no extension, deny
+            if !requester.can_perform_action(&UserAction::WriteGlobalFile)
+                && is_path_protected(scoped_join_win_safe(&dir, file_name)?)
+            {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("File extension is protected"),
+                });
+            }
+            state.directory_sizes.check_quota(
+                instance_uuid,
+                &instance.resource_limits().await,
+                size,
+            )?;
+            Ok(dir)
+        }
+        UploadTarget::Global { path } => {
+            requester.try_action(&UserAction::WriteGlobalFile)?;
+            Ok(path.clone())
+        }
+    }
+}
+
+async fn start_upload(
+    state: &AppState,
+    requester: User,
+    target: UploadTarget,
+    new_upload: NewUpload,
+) -> Result<Json<UploadStatus>, Error> {
+    let file_name = sanitize_filename::sanitize(&new_upload.file_name);
+    resolve_target_dir(state, &requester, &target, &file_name, new_upload.size).await?;
+    Ok(Json(
+        state
+            .upload_sessions
+            .lock()
+            .await
+            .create(requester.uid, target, new_upload)
+            .await?
+            .into(),
+    ))
+}
+
+async fn start_instance_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, base64_relative_path)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(new_upload): Json<NewUpload>,
+) -> Result<Json<UploadStatus>, Error> {
+    let relative_path = decode_base64(&base64_relative_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    start_upload(
+        &state,
+        requester,
+        UploadTarget::Instance {
+            instance_uuid: uuid,
+            relative_path,
+        },
+        new_upload,
+    )
+    .await
+}
+
+async fn start_global_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(base64_absolute_path): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(new_upload): Json<NewUpload>,
+) -> Result<Json<UploadStatus>, Error> {
+    let absolute_path = decode_base64(&base64_absolute_path)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    start_upload(
+        &state,
+        requester,
+        UploadTarget::Global {
+            path: PathBuf::from(absolute_path),
+        },
+        new_upload,
+    )
+    .await
+}
+
+async fn get_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(upload_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UploadStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(
+        state
+            .upload_sessions
+            .lock()
+            .await
+            .session(&upload_id, &requester.uid)?
+            .clone()
+            .into(),
+    ))
+}
+
+async fn upload_part(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((upload_id, index)): Path<(String, u64)>,
+    Query(query): Query<PartQuery>,
+    AuthBearer(token): AuthBearer,
+    body: Bytes,
+) -> Result<Json<UploadStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let offset = state.upload_sessions.lock().await.check_part(
+        &upload_id,
+        &requester.uid,
+        index,
+        &body,
+        query.sha256.as_deref(),
+    )?;
+    // parts are written without holding the lock so they can be sent in parallel
+    write_part(&upload_id, offset, &body).await?;
+    Ok(Json(
+        state
+            .upload_sessions
+            .lock()
+            .await
+            .mark_received(&upload_id, index)
+            .await?
+            .into(),
+    ))
+}
+
+async fn complete_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(upload_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let session = state
+        .upload_sessions
+        .lock()
+        .await
+        .session(&upload_id, &requester.uid)?
+        .clone();
+    let dir = resolve_target_dir(
+        &state,
+        &requester,
+        &session.target,
+        &session.file_name,
+        session.size,
+    )
+    .await?;
+    let session = state
+        .upload_sessions
+        .lock()
+        .await
+        .take_complete(&upload_id, &requester.uid)
+        .await?;
+    let path = finish_upload(&session, &dir).await?;
+    if let UploadTarget::Instance { instance_uuid, .. } = &session.target {
+        state.directory_sizes.add(instance_uuid, session.size);
+    }
+    state.event_broadcaster.send(new_fs_event(
+        FSOperation::Upload,
+        FSTarget::File(path),
+        CausedBy::User {
+            user_id: requester.uid,
+            user_name: requester.username,
+        },
+    ));
+    Ok(Json(()))
+}
+
+async fn abort_upload(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(upload_id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    state
+        .upload_sessions
+        .lock()
+        .await
+        .abort(&upload_id, &requester.uid)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_upload_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/upload/:upload_id/part/:index", put(upload_part))
+        .layer(DefaultBodyLimit::max(MAX_CHUNK_SIZE as usize))
+        .route(
+            "/instance/:uuid/fs/:base64_relative_path/upload/chunked",
+            post(start_instance_upload),
+        )
+        .route(
+            "/fs/:base64_absolute_path/upload/chunked",
+            post(start_global_upload),
+        )
+        .route("/upload/:upload_id", get(get_upload).delete(abort_upload))
+        .route("/upload/:upload_id/complete", post(complete_upload))
+        .with_state(state)
+}
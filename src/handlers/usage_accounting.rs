@@ -0,0 +1,103 @@
+use axum::{
+    extract::{Path, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::Error,
+    usage_accounting::{render_instances_csv, render_users_csv, usage_per_user, InstanceUsage},
+    AppState,
+};
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub format: UsageFormat,
+}
+
+/// The usage of a month, users who can't manage users only see the instances they own
+async fn visible_usage(
+    state: &AppState,
+    requester: &User,
+    month: &str,
+) -> Result<Vec<InstanceUsage>, Error> {
+    let mut instances = state.usage_ledger.lock().await.instances(month)?;
+    if !requester.can_perform_action(&UserAction::ManageUser) {
+        instances.retain(|usage| usage.owner.as_ref() == Some(&requester.uid));
+    }
+    Ok(instances)
+}
+
+fn csv_response(month: &str, kind: &str, body: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"usage-{month}-{kind}.csv\""),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Months with recorded usage, oldest first
+pub async fn get_usage_months(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<String>>, Error> {
+    state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(state.usage_ledger.lock().await.months()))
+}
+
+pub async fn get_instance_usage(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(month): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let instances = visible_usage(&state, &requester, &month).await?;
+    Ok(match query.format {
+        UsageFormat::Json => Json(instances).into_response(),
+        UsageFormat::Csv => csv_response(&month, "instances", render_instances_csv(&instances)),
+    })
+}
+
+/// The usage of a month added up per instance owner
+pub async fn get_user_usage(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(month): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let users = usage_per_user(&visible_usage(&state, &requester, &month).await?);
+    Ok(match query.format {
+        UsageFormat::Json => Json(users).into_response(),
+        UsageFormat::Csv => csv_response(&month, "users", render_users_csv(&users)),
+    })
+}
+
+pub fn get_usage_accounting_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/usage/months", get(get_usage_months))
+        .route("/usage/:month/instances", get(get_instance_usage))
+        .route("/usage/:month/users", get(get_user_usage))
+        .with_state(state)
+}
@@ -0,0 +1,76 @@
+use axum::{extract::Path, routing::get, Json, Router};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::{user::UserAction, user_id::UserId},
+    error::{Error, ErrorKind},
+    user_quotas::{UserQuota, UserQuotaUsage},
+    AppState,
+};
+
+fn user_not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("User not found"),
+    }
+}
+
+/// The quota of a user with what they use of it
+pub async fn get_user_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<UserQuotaUsage>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to get the quota of other users"),
+        });
+    }
+    users_manager.get_user(&uid).ok_or_else(user_not_found)?;
+    drop(users_manager);
+    Ok(Json(
+        state
+            .user_quotas
+            .lock()
+            .await
+            .usage(&uid, &state.instances, &state.directory_sizes)
+            .await,
+    ))
+}
+
+/// Replace the quota of a user, the instances they already have are kept if it is lowered
+pub async fn set_user_quota(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(quota): Json<UserQuota>,
+) -> Result<Json<UserQuota>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(&UserAction::ManageUser)?;
+    let user = users_manager.get_user(&uid).ok_or_else(user_not_found)?;
+    drop(users_manager);
+    if user.is_owner {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The owner has no quota"),
+        });
+    }
+    state
+        .user_quotas
+        .lock()
+        .await
+        .set_quota(&uid, quota)
+        .await?;
+    Ok(Json(quota))
+}
+
+pub fn get_user_quota_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/user/:uid/quota", get(get_user_quota).put(set_user_quota))
+        .with_state(state)
+}
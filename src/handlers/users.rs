@@ -1,25 +1,40 @@
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, time::Instant};
+
 use crate::{
     auth::{
+        access_grant::{AccessGrant, GrantableAction},
+        api_key::{ApiKeyScope, ApiKeyToken, PublicApiKey},
         jwt_token::JwtToken,
+        password_reset::PasswordResetToken,
         permission::UserPermission,
-        user::{PublicUser, User, UserAction},
+        session::{PublicSession, RefreshToken},
+        two_factor::TwoFactorEnrollment,
+        user::{
+            PublicUser, User, UserAction, UserImportConflict, UserImportReport, UsersManager,
+            TWO_FACTOR_CODE_REQUIRED,
+        },
         user_id::UserId,
     },
     error::{Error, ErrorKind},
     events::CausedBy,
+    notifications::NotificationPreferences,
+    rate_limit::send_lockout_event,
+    types::{InstanceUuid, Snowflake},
     AppState,
 };
 
 use axum::{
-    extract::Path,
+    extract::{ConnectInfo, Path},
+    http::{header::USER_AGENT, HeaderMap},
     routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_auth::{AuthBasic, AuthBearer};
 
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use tracing::error;
 use ts_rs::TS;
 
 #[derive(Deserialize, Serialize)]
@@ -50,8 +65,13 @@ pub async fn new_user(
     users_manager
         .add_user(user.clone(), caused_by.clone())
         .await?;
+    let (token, refresh_token) = users_manager
+        .create_session(&user.uid, None, caused_by)
+        .await?;
     Ok(Json(LoginReply {
-        token: user.create_jwt()?,
+        token,
+        refresh_token,
+        two_factor_setup_required: users_manager.two_factor_required(),
         user: user.into(),
     }))
 }
@@ -79,6 +99,13 @@ pub async fn delete_user(
     users_manager
         .delete_user(uid.clone(), caused_by.clone())
         .await?;
+    drop(users_manager);
+    if let Err(e) = state.console_snippets.lock().await.remove_user(&uid).await {
+        error!("Failed to remove the console snippets of {uid} : {e}");
+    }
+    if let Err(e) = state.user_quotas.lock().await.remove_user(&uid).await {
+        error!("Failed to remove the quota of {uid} : {e}");
+    }
     Ok(Json(json!("ok")))
 }
 
@@ -90,6 +117,7 @@ pub async fn logout(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
 
     if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -175,6 +203,7 @@ pub async fn rename_user(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
 
     if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -206,8 +235,9 @@ pub async fn change_password(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
 
-    if requester.uid != config.uid || !requester.can_perform_action(&UserAction::ManageUser) {
+    if requester.uid != config.uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
             kind: ErrorKind::PermissionDenied,
             source: eyre!("You are not authorized to change other users password"),
@@ -237,29 +267,168 @@ pub async fn change_password(
     Ok(Json(()))
 }
 
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct PasswordResetReply {
+    /// hand this to the user, it is only ever returned here
+    pub token: PasswordResetToken,
+    /// unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+/// Reset the password of a user who forgot it. Their password and sessions stop working and the
+/// returned token lets them choose a new password once at `/user/password/reset`
+pub async fn reset_password(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PasswordResetReply>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    requester.try_owner("reset passwords")?;
+    if requester.uid == uid {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Change your own password instead"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username,
+    };
+    let (token, expires_at) = users_manager.reset_password(&uid, caused_by).await?;
+    Ok(Json(PasswordResetReply { token, expires_at }))
+}
+
+#[derive(Deserialize)]
+pub struct CompletePasswordResetConfig {
+    token: PasswordResetToken,
+    new_password: String,
+}
+
+/// Choose a new password with the token of a reset and log in, users with a second factor also
+/// pass a code of it in the `x-two-factor-code` header
+pub async fn complete_password_reset(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Json(config): Json<CompletePasswordResetConfig>,
+) -> Result<Json<LoginReply>, Error> {
+    let two_factor_code = headers
+        .get(TWO_FACTOR_CODE_HEADER)
+        .and_then(|code| code.to_str().ok());
+    let mut users_manager = state.users_manager.write().await;
+    let (user, token, refresh_token) = users_manager
+        .complete_password_reset(
+            &config.token,
+            config.new_password,
+            two_factor_code,
+            user_agent(&headers),
+        )
+        .await?;
+    Ok(Json(LoginReply {
+        token,
+        refresh_token,
+        two_factor_setup_required: users_manager.two_factor_required() && !user.has_two_factor(),
+        user: user.into(),
+    }))
+}
+
 #[derive(Serialize, TS)]
 #[ts(export)]
 pub struct LoginReply {
+    /// short lived, exchange `refresh_token` at `/user/refresh` for a new one
     pub token: JwtToken,
+    pub refresh_token: RefreshToken,
     pub user: PublicUser,
+    /// the core requires a second factor and the user has none, the token can only be used to set
+    /// one up at `/user/two_factor/enroll`
+    pub two_factor_setup_required: bool,
 }
 
+const TWO_FACTOR_CODE_HEADER: &str = "x-two-factor-code";
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(ToOwned::to_owned)
+}
+
+/// Log in with basic auth, plus a code of the authenticator or a recovery code in the
+/// `x-two-factor-code` header for users with a second factor.
+///
+/// Repeated failures lock the user and the address out for a time that grows with every failure.
 pub async fn login(
     axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     AuthBasic((username, password)): AuthBasic,
 ) -> Result<Json<LoginReply>, Error> {
     if let Some(password) = password {
-        let users_manager = state.users_manager.read().await;
+        let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+        if let Err(retry_after) =
+            state
+                .rate_limiter
+                .lock()
+                .await
+                .check_lockout(ip, &username, Instant::now())
+        {
+            return Err(Error {
+                kind: ErrorKind::TooManyRequests,
+                source: eyre!(
+                    "Too many failed logins, try again in {} seconds",
+                    retry_after.as_secs().max(1)
+                ),
+            });
+        }
+        let two_factor_code = headers
+            .get(TWO_FACTOR_CODE_HEADER)
+            .and_then(|code| code.to_str().ok());
+        let mut users_manager = state.users_manager.write().await;
+        let (token, refresh_token) = match users_manager
+            .login(&username, &password, two_factor_code, user_agent(&headers))
+            .await
+        {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                // a missing second factor is the client asking for it, not a wrong guess
+                if matches!(e.kind, ErrorKind::Unauthorized)
+                    && e.source.to_string() != TWO_FACTOR_CODE_REQUIRED
+                {
+                    let lockout = state.rate_limiter.lock().await.record_login_failure(
+                        ip,
+                        &username,
+                        Instant::now(),
+                    );
+                    if let Some(duration) = lockout {
+                        let user_id = users_manager
+                            .get_user_by_username(&username)
+                            .map(|user| user.uid);
+                        send_lockout_event(&state, ip, &username, user_id, duration);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        state
+            .rate_limiter
+            .lock()
+            .await
+            .record_login_success(ip, &username);
+        let user = users_manager
+            .get_user_by_username(&username)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User not found"),
+            })?;
 
         Ok(Json(LoginReply {
-            token: users_manager.login(&username, &password)?,
-            user: users_manager
-                .get_user_by_username(&username)
-                .ok_or_else(|| Error {
-                    kind: ErrorKind::NotFound,
-                    source: eyre!("User not found"),
-                })?
-                .into(),
+            token,
+            refresh_token,
+            two_factor_setup_required: users_manager.two_factor_required()
+                && !user.has_two_factor(),
+            user: user.into(),
         }))
     } else {
         Err(Error {
@@ -288,18 +457,421 @@ pub async fn get_all_users(
     ))
 }
 
+/// The users store, password hashes included, for importing into another core
+pub async fn export_users(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<HashMap<UserId, User>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_owner("export or import users")?;
+    Ok(Json(users_manager.as_ref().clone()))
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum UserImportSource {
+    /// the output of `/user/export` of the other core
+    Bundle { users: HashMap<UserId, User> },
+    /// the other core's `users.json`, or its lodestone path
+    Store { path: PathBuf },
+}
+
+#[derive(Deserialize)]
+pub struct UserImportConfig {
+    pub source: UserImportSource,
+    #[serde(default)]
+    pub on_conflict: UserImportConflict,
+}
+
+pub async fn import_users(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<UserImportConfig>,
+) -> Result<Json<UserImportReport>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_owner("export or import users")?;
+    let users = match config.source {
+        UserImportSource::Bundle { users } => users,
+        UserImportSource::Store { path } => {
+            let path = if path.is_dir() {
+                path.join("stores").join("users.json")
+            } else {
+                path
+            };
+            let content = crate::util::fs::read_to_string(&path).await?;
+            serde_json::from_str(&content)
+                .context(format!("Failed to parse users store {}", path.display()))?
+        }
+    };
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        users_manager
+            .import_users(users.into_values(), config.on_conflict, caused_by)
+            .await?,
+    ))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewAccessGrant {
+    pub instance_uuid: InstanceUuid,
+    pub actions: Vec<GrantableAction>,
+    /// how long the grant lasts, in seconds
+    pub duration: i64,
+}
+
+/// Give a user temporary access to an instance, e.g. to let someone debug a server for a day
+pub async fn grant_access(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<NewAccessGrant>,
+) -> Result<Json<AccessGrant>, Error> {
+    if !state.instances.contains_key(&config.instance_uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_owner("grant or revoke temporary access")?;
+    let grant = AccessGrant::new(
+        config.instance_uuid,
+        config.actions,
+        requester.uid.clone(),
+        config.duration,
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .add_grant(uid, grant.clone(), caused_by)
+        .await?;
+    Ok(Json(grant))
+}
+
+pub async fn revoke_access(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uid, grant_id)): Path<(UserId, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_owner("grant or revoke temporary access")?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .revoke_grant(uid, &grant_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshConfig {
+    pub refresh_token: RefreshToken,
+}
+
+pub async fn refresh(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(config): Json<RefreshConfig>,
+) -> Result<Json<LoginReply>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let (user, token, refresh_token) = users_manager.refresh_session(&config.refresh_token).await?;
+    Ok(Json(LoginReply {
+        token,
+        refresh_token,
+        two_factor_setup_required: users_manager.two_factor_required() && !user.has_two_factor(),
+        user: user.into(),
+    }))
+}
+
+fn require_self_or_manage_user(requester: &User, uid: &UserId) -> Result<(), Error> {
+    require_session(requester)?;
+    if &requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to manage the sessions of other users"),
+        });
+    }
+    Ok(())
+}
+
+pub async fn get_sessions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PublicSession>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_self_or_manage_user(&requester, &uid)?;
+    let user = users_manager.get_user(&uid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("User not found"),
+    })?;
+    let now = chrono::Utc::now().timestamp();
+    Ok(Json(
+        user.sessions
+            .iter()
+            .filter(|session| !session.is_expired(now))
+            .map(PublicSession::from)
+            .collect(),
+    ))
+}
+
+pub async fn revoke_session(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uid, session_id)): Path<(UserId, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_self_or_manage_user(&requester, &uid)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .revoke_session(uid, &session_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Auth for setting up a second factor, which users without one can do even when the core
+/// requires it
+fn try_auth_for_two_factor_setup(users_manager: &UsersManager, token: &str) -> Result<User, Error> {
+    let requester = users_manager
+        .try_auth_for_two_factor_setup(token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Unauthorized"),
+        })?;
+    require_session(&requester)?;
+    Ok(requester)
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+/// Start setting up a second factor for the requester, replacing any pending one
+pub async fn enroll_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TwoFactorEnrollment>, Error> {
+    let issuer = state.global_settings.lock().await.core_name();
+    let mut users_manager = state.users_manager.write().await;
+    let requester = try_auth_for_two_factor_setup(&users_manager, &token)?;
+    Ok(Json(
+        users_manager
+            .begin_two_factor_enrollment(&requester.uid, &issuer)
+            .await?,
+    ))
+}
+
+/// Enable the pending second factor, returns the recovery codes which are only shown once
+pub async fn confirm_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<Vec<String>>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = try_auth_for_two_factor_setup(&users_manager, &token)?;
+    Ok(Json(
+        users_manager
+            .confirm_two_factor(&requester.uid, &code)
+            .await?,
+    ))
+}
+
+pub async fn regenerate_recovery_codes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<Vec<String>>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    Ok(Json(
+        users_manager
+            .regenerate_recovery_codes(&requester.uid, &code)
+            .await?,
+    ))
+}
+
+pub async fn disable_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    if users_manager.two_factor_required() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("This core requires two factor authentication"),
+        });
+    }
+    users_manager
+        .disable_two_factor(&requester.uid, Some(&code))
+        .await?;
+    Ok(Json(()))
+}
+
+/// Remove the second factor of a user who lost their authenticator and recovery codes, so they
+/// can log in with their password and set up a new one
+pub async fn reset_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_owner("reset two factor authentication")?;
+    users_manager.disable_two_factor(&uid, None).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_notification_preferences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<NotificationPreferences>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    Ok(Json(requester.notification_preferences))
+}
+
+pub async fn set_notification_preferences(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(notification_preferences): Json<NotificationPreferences>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    users_manager
+        .set_notification_preferences(&requester.uid, notification_preferences)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Accounts are only managed from a login, a leaked key can't lock its user out
+fn require_session(requester: &User) -> Result<(), Error> {
+    if requester.api_key.is_some() {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("This can't be done with an API key, log in instead"),
+        });
+    }
+    Ok(())
+}
+
+pub async fn get_api_keys(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PublicApiKey>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    Ok(Json(
+        requester.api_keys.iter().map(PublicApiKey::from).collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    #[serde(default)]
+    pub scope: ApiKeyScope,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct NewApiKeyReply {
+    pub key: PublicApiKey,
+    /// only shown once, send it as a bearer token
+    pub token: ApiKeyToken,
+}
+
+/// Create a key for the requester, it can do what the scope allows of what the requester can do
+pub async fn create_api_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(NewApiKey { name, scope }): Json<NewApiKey>,
+) -> Result<Json<NewApiKeyReply>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    let (key, token) = users_manager
+        .create_api_key(&requester.uid, name, scope, caused_by)
+        .await?;
+    Ok(Json(NewApiKeyReply { key, token }))
+}
+
+pub async fn revoke_api_key(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(key_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    require_session(&requester)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .revoke_api_key(&requester.uid, &key_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
 // return the thing created by Router::new() so we can nest it in main
 pub fn get_user_routes(state: AppState) -> Router {
     Router::new()
         .route("/user/list", get(get_all_users))
+        .route("/user/export", get(export_users))
+        .route("/user/import", post(import_users))
         .route("/user", post(new_user))
         .route("/user/:uid", get(get_user_info))
         .route("/user/:uid", delete(delete_user))
         .route("/user/:uid/update_perm", put(update_permissions))
         .route("/user/info", get(get_self_info))
+        .route(
+            "/user/notification_preferences",
+            get(get_notification_preferences).put(set_notification_preferences),
+        )
+        .route("/user/keys", get(get_api_keys).post(create_api_key))
+        .route("/user/keys/:key_id", delete(revoke_api_key))
         .route("/user/:uid/rename", put(rename_user))
         .route("/user/:uid/password", put(change_password))
+        .route("/user/:uid/password/reset", post(reset_password))
+        .route("/user/password/reset", post(complete_password_reset))
+        .route("/user/:uid/grants", post(grant_access))
+        .route("/user/:uid/grants/:grant_id", delete(revoke_access))
+        .route("/user/:uid/sessions", get(get_sessions))
+        .route("/user/:uid/sessions/:session_id", delete(revoke_session))
         .route("/user/login", post(login))
+        .route("/user/refresh", post(refresh))
         .route("/user/logout/:uid", post(logout))
+        .route("/user/two_factor/enroll", post(enroll_two_factor))
+        .route("/user/two_factor/confirm", post(confirm_two_factor))
+        .route(
+            "/user/two_factor/recovery_codes",
+            post(regenerate_recovery_codes),
+        )
+        .route("/user/two_factor/disable", put(disable_two_factor))
+        .route("/user/:uid/two_factor", delete(reset_two_factor))
         .with_state(state)
 }
@@ -0,0 +1,525 @@
+use std::path::Path as FsPath;
+
+use axum::{
+    body::BodyStream,
+    extract::Path,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use color_eyre::eyre::{eyre, Context};
+use futures::StreamExt;
+use headers::{authorization::Basic, Authorization, HeaderMapExt};
+use tokio::io::AsyncWriteExt;
+use walkdir::WalkDir;
+
+use crate::{
+    auth::user::{User, UserAction},
+    disk_usage::directory_size,
+    error::{Error, ErrorKind},
+    events::{new_fs_event, CausedBy, FSOperation, FSTarget},
+    file_stream::{file_response, ReadFileQuery},
+    traits::t_configurable::TConfigurable,
+    trash::Trash,
+    types::InstanceUuid,
+    util::scoped_join_win_safe,
+    webdav::{destination_path, encode_path, multistatus, split_instance_path, DavEntry, Depth},
+    AppState,
+};
+
+use super::instance_fs::is_path_protected;
+
+/// where the bridge is served, `Destination` headers are relative to it
+const DAV_PREFIX: &str = "/api/v1/dav";
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MKCOL, MOVE, COPY";
+
+/// The user of the basic credentials, the password being one of their api keys or the token of
+/// one of their sessions
+async fn authenticate(state: &AppState, headers: &HeaderMap) -> Result<User, Error> {
+    let unauthorized = || Error {
+        kind: ErrorKind::Unauthorized,
+        source: eyre!("Unauthorized"),
+    };
+    let credentials = headers
+        .typed_get::<Authorization<Basic>>()
+        .ok_or_else(unauthorized)?;
+    let user = state
+        .users_manager
+        .read()
+        .await
+        .try_auth_or_err(credentials.password())?;
+    if user.username != credentials.username() {
+        return Err(unauthorized());
+    }
+    Ok(user)
+}
+
+/// Fail unless `requester` may change `path`, protected files can only be changed by those who
+/// can write anywhere
+fn check_writable(requester: &User, path: &FsPath) -> Result<(), Error> {
+    if requester.can_perform_action(&UserAction::WriteGlobalFile) {
+        return Ok(());
+    }
+    if is_path_protected(path) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("File extension is protected"),
+        });
+    }
+    if path.is_dir() {
+        for entry in WalkDir::new(path) {
+            let entry =
+                entry.context("Failed to walk directory while scanning for protected files")?;
+            if entry.file_type().is_file() && is_path_protected(entry.path()) {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("Directory contains protected files"),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fs_target(path: &FsPath) -> FSTarget {
+    if path.is_dir() {
+        FSTarget::Directory(path.to_owned())
+    } else {
+        FSTarget::File(path.to_owned())
+    }
+}
+
+fn created_or_replaced(existed: bool) -> Response {
+    if existed {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::CREATED.into_response()
+    }
+}
+
+fn multistatus_response(entries: &[DavEntry]) -> Response {
+    (
+        StatusCode::MULTI_STATUS,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        )],
+        multistatus(entries),
+    )
+        .into_response()
+}
+
+/// The instances the requester can read the files of, as directories named after their uuid
+async fn list_instances(
+    state: &AppState,
+    requester: &User,
+    method: &Method,
+    depth: Depth,
+) -> Result<Response, Error> {
+    if method.as_str() != "PROPFIND" {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+    let mut entries = vec![DavEntry {
+        href: format!("{DAV_PREFIX}/"),
+        display_name: "Lodestone".to_string(),
+        is_dir: true,
+        len: 0,
+        modified: None,
+    }];
+    if depth == Depth::One {
+        for instance in state.instances.values() {
+            let uuid = instance.uuid().await;
+            if !requester.can_perform_action(&UserAction::ReadInstanceFile(uuid.clone())) {
+                continue;
+            }
+            if let Ok(entry) = DavEntry::from_path(
+                encode_path(&format!("{DAV_PREFIX}/{uuid}/")),
+                instance.name().await,
+                &instance.path().await,
+            )
+            .await
+            {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(multistatus_response(&entries))
+}
+
+async fn propfind(
+    uuid: &InstanceUuid,
+    root: &FsPath,
+    path: &FsPath,
+    depth: Depth,
+) -> Result<Response, Error> {
+    let href = |path: &FsPath, is_dir: bool| {
+        let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy();
+        let relative = relative.replace('\\', "/");
+        let mut href = format!("{DAV_PREFIX}/{uuid}/{relative}");
+        if is_dir && !href.ends_with('/') {
+            href.push('/');
+        }
+        encode_path(&href)
+    };
+    let display_name = |path: &FsPath| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let entry = match DavEntry::from_path(String::new(), display_name(path), path).await {
+        Ok(entry) => entry,
+        Err(_) => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    let mut entries = vec![DavEntry {
+        href: href(path, entry.is_dir),
+        ..entry
+    }];
+    if entries[0].is_dir && depth == Depth::One {
+        let mut dir = tokio::fs::read_dir(path)
+            .await
+            .context(format!("Failed to read directory {}", path.display()))?;
+        while let Some(child) = dir
+            .next_entry()
+            .await
+            .context(format!("Failed to read directory {}", path.display()))?
+        {
+            let child = child.path();
+            if let Ok(entry) =
+                DavEntry::from_path(String::new(), display_name(&child), &child).await
+            {
+                entries.push(DavEntry {
+                    href: href(&child, entry.is_dir),
+                    ..entry
+                });
+            }
+        }
+    }
+    Ok(multistatus_response(&entries))
+}
+
+/// Write the body to `path`, returning how many bytes were written
+async fn put(path: &FsPath, mut body: BodyStream) -> Result<u64, Error> {
+    let mut file = crate::util::fs::create(path).await?;
+    let mut written = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.context("Failed to read the request body")?;
+        file.write_all(&chunk)
+            .await
+            .context(format!("Failed to write to file at {}", path.display()))?;
+        written += chunk.len() as u64;
+    }
+    file.flush()
+        .await
+        .context(format!("Failed to write to file at {}", path.display()))?;
+    Ok(written)
+}
+
+async fn remove(root: &FsPath, path: &FsPath, caused_by: CausedBy) -> Result<(), Error> {
+    let trash = Trash::for_instance(root);
+    if !trash.contains(path) {
+        return trash.move_to_trash(path, caused_by).await.map(|_| ());
+    }
+    if path.is_dir() {
+        crate::util::fs::remove_dir_all(path).await
+    } else {
+        crate::util::fs::remove_file(path).await
+    }
+}
+
+async fn copy(from: &FsPath, to: &FsPath) -> Result<(), Error> {
+    if from.is_dir() {
+        crate::util::fs::create_dir_all(to).await?;
+        let (from, to) = (from.to_owned(), to.to_owned());
+        tokio::task::spawn_blocking(move || {
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.content_only = true;
+            fs_extra::dir::copy(from, to, &options)
+        })
+        .await
+        .context("Copy task panicked")?
+        .context("Failed to copy directory")?;
+    } else {
+        tokio::fs::copy(from, to)
+            .await
+            .context(format!("Failed to copy {}", from.display()))?;
+    }
+    Ok(())
+}
+
+async fn handle(
+    state: AppState,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Result<Response, Error> {
+    if !state.global_settings.lock().await.webdav() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("WebDAV is disabled"),
+        });
+    }
+    if method == Method::OPTIONS {
+        return Ok((
+            StatusCode::OK,
+            [
+                (header::HeaderName::from_static("dav"), "1"),
+                (header::ALLOW, ALLOWED_METHODS),
+            ],
+        )
+            .into_response());
+    }
+    let requester = authenticate(&state, &headers).await?;
+    let depth = Depth::from_header(headers.get("depth").and_then(|v| v.to_str().ok()));
+    let Some((uuid, relative_path)) = split_instance_path(&path) else {
+        return list_instances(&state, &requester, &method, depth).await;
+    };
+    let uuid = InstanceUuid::from(uuid);
+    let is_read = matches!(method.as_str(), "PROPFIND" | "GET" | "HEAD");
+    requester.try_action(&if is_read {
+        UserAction::ReadInstanceFile(uuid.clone())
+    } else {
+        UserAction::WriteInstanceFile(uuid.clone())
+    })?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let root = instance.path().await;
+    let path = scoped_join_win_safe(&root, relative_path)?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+
+    match method.as_str() {
+        "PROPFIND" => propfind(&uuid, &root, &path, depth).await,
+        "GET" | "HEAD" => {
+            if path.is_dir() {
+                return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+            }
+            let response = file_response(&path, &headers, &ReadFileQuery::default()).await?;
+            state.event_broadcaster.send(new_fs_event(
+                FSOperation::Read,
+                FSTarget::File(path),
+                caused_by,
+            ));
+            Ok(response)
+        }
+        "PUT" => {
+            if path.is_dir() {
+                return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+            }
+            if !path.parent().is_some_and(|parent| parent.is_dir()) {
+                return Ok(StatusCode::CONFLICT.into_response());
+            }
+            check_writable(&requester, &path)?;
+            let content_length = headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            state.directory_sizes.check_quota(
+                &uuid,
+                &instance.resource_limits().await,
+                content_length.unwrap_or_default(),
+            )?;
+            let existed = path.exists();
+            let written = match put(&path, body).await {
+                Ok(written) => written,
+                Err(e) => {
+                    // a half written new file is worse than none
+                    if !existed {
+                        let _ = crate::util::fs::remove_file(&path).await;
+                    }
+                    return Err(e);
+                }
+            };
+            state.directory_sizes.add(&uuid, written);
+            state.event_broadcaster.send(new_fs_event(
+                if existed {
+                    FSOperation::Write
+                } else {
+                    FSOperation::Create
+                },
+                FSTarget::File(path),
+                caused_by,
+            ));
+            Ok(created_or_replaced(existed))
+        }
+        "DELETE" => {
+            if path == root {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("Cannot delete instance root"),
+                });
+            }
+            if !path.exists() {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            }
+            check_writable(&requester, &path)?;
+            let target = fs_target(&path);
+            remove(&root, &path, caused_by.clone()).await?;
+            state
+                .event_broadcaster
+                .send(new_fs_event(FSOperation::Delete, target, caused_by));
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        "MKCOL" => {
+            if path.exists() {
+                return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+            }
+            if !path.parent().is_some_and(|parent| parent.is_dir()) {
+                return Ok(StatusCode::CONFLICT.into_response());
+            }
+            check_writable(&requester, &path)?;
+            tokio::fs::create_dir(&path)
+                .await
+                .context(format!("Failed to create directory at {}", path.display()))?;
+            state.event_broadcaster.send(new_fs_event(
+                FSOperation::Create,
+                FSTarget::Directory(path),
+                caused_by,
+            ));
+            Ok(StatusCode::CREATED.into_response())
+        }
+        "MOVE" | "COPY" => {
+            let destination = headers
+                .get("destination")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|destination| destination_path(destination, DAV_PREFIX))
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Missing or invalid Destination header"),
+                })?;
+            let (destination_uuid, destination_relative_path) = split_instance_path(&destination)
+                .ok_or_else(|| Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Files can only be moved within an instance"),
+            })?;
+            if destination_uuid != uuid.to_string() {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("Files can only be moved within an instance"),
+                });
+            }
+            let destination = scoped_join_win_safe(&root, destination_relative_path)?;
+            if path == root || destination == root || destination.starts_with(&path) {
+                return Err(Error {
+                    kind: ErrorKind::PermissionDenied,
+                    source: eyre!("Cannot move or copy the instance root or into itself"),
+                });
+            }
+            if !path.exists() {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            }
+            if !destination.parent().is_some_and(|parent| parent.is_dir()) {
+                return Ok(StatusCode::CONFLICT.into_response());
+            }
+            let overwrite = !matches!(
+                headers.get("overwrite").and_then(|v| v.to_str().ok()),
+                Some(v) if v.trim().eq_ignore_ascii_case("F")
+            );
+            let existed = destination.exists();
+            if existed && !overwrite {
+                return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+            }
+            if method.as_str() == "MOVE" {
+                check_writable(&requester, &path)?;
+            }
+            check_writable(&requester, &destination)?;
+            let copied_size = if method.as_str() == "COPY" {
+                let source = path.clone();
+                let size = tokio::task::spawn_blocking(move || directory_size(&source))
+                    .await
+                    .context("Failed to measure the source of the copy")?;
+                state.directory_sizes.check_quota(
+                    &uuid,
+                    &instance.resource_limits().await,
+                    size,
+                )?;
+                size
+            } else {
+                0
+            };
+            if existed {
+                remove(&root, &destination, caused_by.clone()).await?;
+            }
+            if method.as_str() == "MOVE" {
+                crate::util::fs::rename(&path, &destination).await?;
+                state.event_broadcaster.send(new_fs_event(
+                    FSOperation::Move { source: path },
+                    fs_target(&destination),
+                    caused_by,
+                ));
+            } else {
+                copy(&path, &destination).await?;
+                state.directory_sizes.add(&uuid, copied_size);
+                state.event_broadcaster.send(new_fs_event(
+                    FSOperation::Create,
+                    fs_target(&destination),
+                    caused_by,
+                ));
+            }
+            Ok(created_or_replaced(existed))
+        }
+        _ => Ok((
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(header::ALLOW, ALLOWED_METHODS)],
+        )
+            .into_response()),
+    }
+}
+
+async fn respond(
+    state: AppState,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Response {
+    match handle(state, method, path, headers, body).await {
+        Ok(response) => response,
+        Err(e) => {
+            let ask_for_credentials = e.kind == ErrorKind::Unauthorized;
+            let mut response = e.into_response();
+            // makes file managers prompt for the username and an api key
+            if ask_for_credentials {
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static("Basic realm=\"Lodestone\", charset=\"UTF-8\""),
+                );
+            }
+            response
+        }
+    }
+}
+
+/// The instances the requester can read the files of
+pub async fn dav_root(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    method: Method,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Response {
+    respond(state, method, String::new(), headers, body).await
+}
+
+/// A file or directory of an instance, at `<uuid>/<path in the instance directory>`
+pub async fn dav_path(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(path): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Response {
+    respond(state, method, path, headers, body).await
+}
+
+pub fn get_webdav_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/dav", any(dav_root))
+        .route("/dav/", any(dav_root))
+        .route("/dav/*path", any(dav_path))
+        .with_state(state)
+}
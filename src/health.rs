@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{event_broadcaster::EventBroadcaster, prelude::path_to_stores};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// The event loop is considered stalled once the checks are this many intervals late
+const MISSED_CHECKS_BEFORE_STALLED: i64 = 3;
+const PROBE_FILE_NAME: &str = ".health_probe";
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SelfCheck {
+    pub name: String,
+    pub ok: bool,
+    /// why the check failed
+    pub message: Option<String>,
+    pub checked_at: i64,
+}
+
+/// The results of the self checks the core runs periodically, which decide whether it reports
+/// itself as ready
+#[derive(Clone, Default)]
+pub struct HealthChecks {
+    checks: Arc<Mutex<BTreeMap<String, SelfCheck>>>,
+    last_heartbeat: Arc<AtomicI64>,
+}
+
+impl HealthChecks {
+    pub fn record(&self, name: &str, result: Result<(), String>) {
+        let check = SelfCheck {
+            name: name.to_string(),
+            ok: result.is_ok(),
+            message: result.err(),
+            checked_at: chrono::Utc::now().timestamp(),
+        };
+        let mut checks = self.checks.lock().unwrap();
+        match checks.get(name) {
+            Some(previous) if previous.ok && !check.ok => {
+                error!(
+                    "Self check {name} failed : {}",
+                    check.message.as_deref().unwrap_or_default()
+                )
+            }
+            Some(previous) if !previous.ok && check.ok => info!("Self check {name} recovered"),
+            _ => {}
+        }
+        checks.insert(name.to_string(), check);
+    }
+
+    pub fn snapshot(&self) -> Vec<SelfCheck> {
+        self.checks.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn heartbeat(&self) {
+        self.last_heartbeat
+            .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    /// Whether the checks ran recently, they stop when the runtime is starved or the task is stuck
+    pub fn event_loop_alive(&self) -> bool {
+        let last_heartbeat = self.last_heartbeat.load(Ordering::Relaxed);
+        last_heartbeat != 0
+            && chrono::Utc::now().timestamp() - last_heartbeat
+                <= CHECK_INTERVAL.as_secs() as i64 * MISSED_CHECKS_BEFORE_STALLED
+    }
+
+    /// Ready once the checks ran and none of them failed
+    pub fn is_ready(&self) -> bool {
+        self.event_loop_alive() && self.snapshot().iter().all(|check| check.ok)
+    }
+}
+
+fn check_broadcaster(event_broadcaster: &EventBroadcaster) -> Result<(), String> {
+    // the event buffer and the database writer hold a receiver for as long as they run
+    if event_broadcaster.subscriber_count() == 0 {
+        return Err("Nothing is receiving events".to_string());
+    }
+    Ok(())
+}
+
+async fn check_stores_writable() -> Result<(), String> {
+    let probe = path_to_stores().join(PROBE_FILE_NAME);
+    tokio::fs::write(&probe, chrono::Utc::now().timestamp().to_string())
+        .await
+        .map_err(|e| format!("Failed to write to {} : {e}", path_to_stores().display()))?;
+    tokio::fs::remove_file(&probe)
+        .await
+        .map_err(|e| format!("Failed to remove {} : {e}", probe.display()))
+}
+
+async fn check_database(sqlite_pool: &sqlx::SqlitePool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(sqlite_pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Failed to query the database : {e}"))
+}
+
+pub async fn health_check_task(
+    health_checks: HealthChecks,
+    event_broadcaster: EventBroadcaster,
+    sqlite_pool: sqlx::SqlitePool,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        health_checks.record("broadcaster", check_broadcaster(&event_broadcaster));
+        health_checks.record("stores", check_stores_writable().await);
+        health_checks.record("database", check_database(&sqlite_pool).await);
+        health_checks.heartbeat();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HealthChecks;
+
+    #[test]
+    fn test_readiness() {
+        let health_checks = HealthChecks::default();
+        // nothing ran yet
+        assert!(!health_checks.is_ready());
+
+        health_checks.record("stores", Ok(()));
+        health_checks.heartbeat();
+        assert!(health_checks.is_ready());
+
+        health_checks.record("database", Err("locked".to_string()));
+        assert!(!health_checks.is_ready());
+        assert_eq!(health_checks.snapshot().len(), 2);
+
+        health_checks.record("database", Ok(()));
+        assert!(health_checks.is_ready());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use sysinfo::{DiskExt, SystemExt};
+
+use crate::events::{Event, SystemEventInner};
+
+/// percentage of memory in use at which the host is considered under pressure
+const MEMORY_PRESSURE_THRESHOLD: f64 = 0.95;
+/// percentage of memory in use below which the host is considered recovered
+const MEMORY_RECOVERY_THRESHOLD: f64 = 0.90;
+/// percentage of free space on a disk below which it is considered under pressure
+const DISK_PRESSURE_THRESHOLD: f64 = 0.05;
+/// percentage of free space on a disk above which it is considered recovered
+const DISK_RECOVERY_THRESHOLD: f64 = 0.10;
+
+/// Keeps track of host memory and disk pressure.
+///
+/// An event is only emitted when a resource enters the pressure state, the resource has to recover
+/// past a lower threshold before another event can be emitted for it.
+#[derive(Default)]
+pub struct HostPressureWatcher {
+    memory_under_pressure: bool,
+    disks_under_pressure: HashSet<PathBuf>,
+}
+
+impl HostPressureWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check_memory(&mut self, used_memory: u64, total_memory: u64) -> Option<Event> {
+        if total_memory == 0 {
+            return None;
+        }
+        let ratio = used_memory as f64 / total_memory as f64;
+        if !self.memory_under_pressure && ratio >= MEMORY_PRESSURE_THRESHOLD {
+            self.memory_under_pressure = true;
+            let mut event: Event = SystemEventInner::HostMemoryPressure {
+                used_memory,
+                total_memory,
+            }
+            .into();
+            event.details = "The host is running out of memory. Instances may be killed by the operating system, consider stopping some instances or lowering their maximum RAM".to_string();
+            Some(event)
+        } else {
+            if self.memory_under_pressure && ratio < MEMORY_RECOVERY_THRESHOLD {
+                self.memory_under_pressure = false;
+            }
+            None
+        }
+    }
+
+    pub fn check_disk(
+        &mut self,
+        mount_point: &Path,
+        available_space: u64,
+        total_space: u64,
+    ) -> Option<Event> {
+        if total_space == 0 {
+            return None;
+        }
+        let ratio = available_space as f64 / total_space as f64;
+        let under_pressure = self.disks_under_pressure.contains(mount_point);
+        if !under_pressure && ratio <= DISK_PRESSURE_THRESHOLD {
+            self.disks_under_pressure.insert(mount_point.to_owned());
+            let mut event: Event = SystemEventInner::HostDiskPressure {
+                mount_point: mount_point.to_owned(),
+                available_space,
+                total_space,
+            }
+            .into();
+            event.details = format!(
+                "Disk {} is almost full. Instances writing to it may crash or corrupt their data, consider freeing up space or removing old backups",
+                mount_point.display()
+            );
+            Some(event)
+        } else {
+            if under_pressure && ratio > DISK_RECOVERY_THRESHOLD {
+                self.disks_under_pressure.remove(mount_point);
+            }
+            None
+        }
+    }
+
+    /// Check the memory, and the disks as well if `refresh_disks` is set
+    pub fn check(&mut self, sys: &mut sysinfo::System, refresh_disks: bool) -> Vec<Event> {
+        let mut ret = Vec::new();
+        sys.refresh_memory();
+        ret.extend(self.check_memory(sys.used_memory(), sys.total_memory()));
+        if refresh_disks {
+            sys.refresh_disks_list();
+            sys.refresh_disks();
+            for disk in sys.disks() {
+                ret.extend(self.check_disk(
+                    disk.mount_point(),
+                    disk.available_space(),
+                    disk.total_space(),
+                ));
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::HostPressureWatcher;
+
+    #[test]
+    fn test_memory_pressure_hysteresis() {
+        let mut watcher = HostPressureWatcher::new();
+        assert!(watcher.check_memory(50, 100).is_none());
+        assert!(watcher.check_memory(96, 100).is_some());
+        // still under pressure, no new event
+        assert!(watcher.check_memory(97, 100).is_none());
+        assert!(watcher.check_memory(92, 100).is_none());
+        assert!(watcher.check_memory(96, 100).is_none());
+        // recovered
+        assert!(watcher.check_memory(80, 100).is_none());
+        assert!(watcher.check_memory(99, 100).is_some());
+    }
+
+    #[test]
+    fn test_disk_pressure_per_mount_point() {
+        let mut watcher = HostPressureWatcher::new();
+        let root = Path::new("/");
+        let data = Path::new("/data");
+        assert!(watcher.check_disk(root, 4, 100).is_some());
+        assert!(watcher.check_disk(data, 4, 100).is_some());
+        assert!(watcher.check_disk(root, 3, 100).is_none());
+        assert!(watcher.check_disk(root, 50, 100).is_none());
+        assert!(watcher.check_disk(root, 2, 100).is_some());
+        assert!(watcher.check_disk(data, 1, 100).is_none());
+    }
+}
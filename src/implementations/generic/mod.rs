@@ -275,9 +275,12 @@ impl TInstance for GenericInstance {
             auto_start: self.auto_start().await,
             restart_on_crash: self.restart_on_crash().await,
             state: self.state().await,
+            readiness: self.readiness().await,
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            display: self.display_metadata().await,
+            advisories: self.advisories().await,
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
@@ -11,7 +12,7 @@ use crate::{
 
 use super::{bridge::procedure_call::ProcedureCallInner, GenericInstance};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, TS, Clone, Hash)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, TS, Clone, Hash, JsonSchema)]
 #[ts(export)]
 pub struct GenericPlayer {
     pub id: String,
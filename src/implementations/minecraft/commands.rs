@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+
+use crate::traits::t_server::CommandInfo;
+
+/// commands learned from the output, so a chatty plugin can't grow the list forever
+const MAX_LEARNED_COMMANDS: usize = 1024;
+const MAX_COMMAND_NAME_LEN: usize = 64;
+const MAX_USAGE_LEN: usize = 256;
+/// how long after `help` is sent its output is expected
+const HELP_OUTPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Commands of a vanilla dedicated server
+const VANILLA_COMMANDS: &[&str] = &[
+    "advancement",
+    "attribute",
+    "ban",
+    "ban-ip",
+    "banlist",
+    "bossbar",
+    "clear",
+    "clone",
+    "damage",
+    "data",
+    "datapack",
+    "debug",
+    "defaultgamemode",
+    "deop",
+    "difficulty",
+    "effect",
+    "enchant",
+    "execute",
+    "experience",
+    "fill",
+    "fillbiome",
+    "forceload",
+    "function",
+    "gamemode",
+    "gamerule",
+    "give",
+    "help",
+    "item",
+    "jfr",
+    "kick",
+    "kill",
+    "list",
+    "locate",
+    "loot",
+    "me",
+    "msg",
+    "op",
+    "pardon",
+    "pardon-ip",
+    "particle",
+    "perf",
+    "place",
+    "playsound",
+    "recipe",
+    "reload",
+    "return",
+    "ride",
+    "save-all",
+    "save-off",
+    "save-on",
+    "say",
+    "schedule",
+    "scoreboard",
+    "seed",
+    "setblock",
+    "setidletimeout",
+    "setworldspawn",
+    "spawnpoint",
+    "spectate",
+    "spreadplayers",
+    "stop",
+    "stopsound",
+    "summon",
+    "tag",
+    "team",
+    "teammsg",
+    "teleport",
+    "tell",
+    "tellraw",
+    "tick",
+    "time",
+    "title",
+    "tm",
+    "tp",
+    "transfer",
+    "trigger",
+    "w",
+    "weather",
+    "whitelist",
+    "worldborder",
+    "xp",
+];
+
+/// Parse a line of the output of `help`, which is `/name <arguments>` on vanilla and
+/// `/name: description` on Bukkit based servers
+pub fn parse_help_line(system_msg: &str) -> Option<(String, Option<String>)> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^/([A-Za-z0-9_.\-]+(?::[A-Za-z0-9_.\-]+)*)(?::\s*|\s+|$)(.*)$").unwrap();
+    }
+    let caps = RE.captures(system_msg.trim()).ok()??;
+    let name = caps.get(1)?.as_str().to_lowercase();
+    if name.len() > MAX_COMMAND_NAME_LEN {
+        return None;
+    }
+    let usage = caps
+        .get(2)
+        .map(|usage| usage.as_str().trim())
+        .filter(|usage| !usage.is_empty())
+        .map(|usage| usage.chars().take(MAX_USAGE_LEN).collect());
+    Some((name, usage))
+}
+
+/// Whether a command sent to the console lists commands
+pub fn is_help_command(command: &str) -> bool {
+    matches!(
+        command
+            .trim()
+            .trim_start_matches('/')
+            .split_whitespace()
+            .next(),
+        Some("help" | "?")
+    )
+}
+
+/// The commands of a minecraft instance, the vanilla ones and the ones learned from `help`.
+///
+/// Other output can look like a help line, e.g. a path, so lines are only learned from shortly
+/// after `help` was sent.
+#[derive(Debug, Default)]
+pub struct KnownCommands {
+    learned: BTreeMap<String, Option<String>>,
+    help_sent_at: Option<Instant>,
+}
+
+impl KnownCommands {
+    pub fn help_sent(&mut self) {
+        self.help_sent_at = Some(Instant::now());
+    }
+
+    pub fn is_expecting_help(&self) -> bool {
+        self.help_sent_at
+            .is_some_and(|sent_at| sent_at.elapsed() < HELP_OUTPUT_WINDOW)
+    }
+
+    pub fn learn(&mut self, name: String, usage: Option<String>) {
+        if self.learned.len() >= MAX_LEARNED_COMMANDS && !self.learned.contains_key(&name) {
+            return;
+        }
+        // a later line without usage shouldn't erase what was learned
+        let entry = self.learned.entry(name).or_default();
+        if usage.is_some() {
+            *entry = usage;
+        }
+    }
+
+    pub fn commands(&self) -> Vec<CommandInfo> {
+        let mut commands: BTreeMap<&str, CommandInfo> = VANILLA_COMMANDS
+            .iter()
+            .map(|name| {
+                (
+                    *name,
+                    CommandInfo {
+                        name: name.to_string(),
+                        usage: None,
+                        learned: false,
+                    },
+                )
+            })
+            .collect();
+        for (name, usage) in &self.learned {
+            commands
+                .entry(name.as_str())
+                .and_modify(|command| command.usage = usage.clone())
+                .or_insert_with(|| CommandInfo {
+                    name: name.clone(),
+                    usage: usage.clone(),
+                    learned: true,
+                });
+        }
+        commands.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_help_command, parse_help_line, KnownCommands, VANILLA_COMMANDS};
+
+    #[test]
+    fn test_parse_help_line() {
+        assert_eq!(
+            parse_help_line("/ban <targets> [<reason>]"),
+            Some(("ban".to_string(), Some("<targets> [<reason>]".to_string())))
+        );
+        assert_eq!(
+            parse_help_line("/Essentials:Home: Teleport to your home."),
+            Some((
+                "essentials:home".to_string(),
+                Some("Teleport to your home.".to_string())
+            ))
+        );
+        assert_eq!(parse_help_line("/list"), Some(("list".to_string(), None)));
+        assert_eq!(parse_help_line("Steve joined the game"), None);
+        assert_eq!(parse_help_line("/"), None);
+        assert_eq!(parse_help_line("/home/steve/server.jar"), None);
+    }
+
+    #[test]
+    fn test_is_help_command() {
+        assert!(is_help_command("help"));
+        assert!(is_help_command("/help 2"));
+        assert!(is_help_command("?"));
+        assert!(!is_help_command("helpop hi"));
+        assert!(!is_help_command("say help"));
+    }
+
+    #[test]
+    fn test_known_commands() {
+        let mut known_commands = KnownCommands::default();
+        known_commands.learn("ban".to_string(), Some("<targets>".to_string()));
+        known_commands.learn("ban".to_string(), None);
+        known_commands.learn("home".to_string(), None);
+        let commands = known_commands.commands();
+        assert_eq!(commands.len(), VANILLA_COMMANDS.len() + 1);
+        let ban = commands.iter().find(|c| c.name == "ban").unwrap();
+        assert_eq!(ban.usage.as_deref(), Some("<targets>"));
+        assert!(!ban.learned);
+        assert!(commands.iter().find(|c| c.name == "home").unwrap().learned);
+        assert!(commands.windows(2).all(|w| w[0].name < w[1].name));
+    }
+}
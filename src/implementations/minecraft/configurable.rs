@@ -5,17 +5,17 @@ use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 
 use crate::error::{Error, ErrorKind};
-use crate::prelude::path_to_tmp;
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SettingManifest,
 };
-use crate::traits::t_configurable::{Game, TConfigurable};
-use crate::traits::t_server::State;
+use crate::traits::t_configurable::{
+    CrashRestartPolicy, DisplayMetadata, Game, LaunchOverrides, PublicAddress, ResourceLimits,
+    TConfigurable,
+};
 
 use crate::types::InstanceUuid;
-use crate::util::download_file;
 
-use super::util::{get_fabric_jar_url, get_paper_jar_url, get_vanilla_jar_url};
+use super::version_switch::VersionChange;
 use super::MinecraftInstance;
 
 #[async_trait]
@@ -60,6 +60,30 @@ impl TConfigurable for MinecraftInstance {
         self.config.lock().await.restart_on_crash
     }
 
+    async fn backup_period(&self) -> Option<u32> {
+        self.config.lock().await.backup_period
+    }
+
+    async fn resource_limits(&self) -> ResourceLimits {
+        self.config.lock().await.resource_limits
+    }
+
+    async fn crash_restart_policy(&self) -> CrashRestartPolicy {
+        self.config.lock().await.crash_restart_policy
+    }
+
+    async fn display_metadata(&self) -> DisplayMetadata {
+        self.config.lock().await.display.clone()
+    }
+
+    async fn public_address(&self) -> PublicAddress {
+        self.config.lock().await.public_address.clone()
+    }
+
+    async fn launch_overrides(&self) -> LaunchOverrides {
+        self.config.lock().await.launch_overrides.clone()
+    }
+
     async fn set_name(&mut self, name: String) -> Result<(), Error> {
         if name.is_empty() {
             return Err(Error {
@@ -104,72 +128,63 @@ impl TConfigurable for MinecraftInstance {
 
     async fn set_restart_on_crash(&mut self, restart_on_crash: bool) -> Result<(), Error> {
         self.config.lock().await.restart_on_crash = restart_on_crash;
-        self.auto_start
+        self.restart_on_crash
             .store(restart_on_crash, atomic::Ordering::Relaxed);
         self.write_config_to_file().await
     }
 
-    async fn change_version(&mut self, version: String) -> Result<(), Error> {
-        if *self.state.lock().await != State::Stopped {
+    async fn set_backup_period(&mut self, backup_period: Option<u32>) -> Result<(), Error> {
+        if backup_period == Some(0) {
             return Err(Error {
                 kind: ErrorKind::BadRequest,
-                source: eyre!("Cannot change version while server is running"),
+                source: eyre!("Backup period cannot be 0"),
             });
         }
+        self.config.lock().await.backup_period = backup_period;
+        self.write_config_to_file().await
+    }
+
+    async fn set_resource_limits(&mut self, limits: ResourceLimits) -> Result<(), Error> {
+        limits.validate()?;
+        self.config.lock().await.resource_limits = limits;
+        self.write_config_to_file().await
+    }
+
+    async fn set_crash_restart_policy(&mut self, policy: CrashRestartPolicy) -> Result<(), Error> {
+        policy.validate()?;
+        self.config.lock().await.crash_restart_policy = policy;
+        self.write_config_to_file().await
+    }
+
+    async fn set_display_metadata(&mut self, display: DisplayMetadata) -> Result<(), Error> {
+        display.validate()?;
+        self.config.lock().await.display = display;
+        self.write_config_to_file().await
+    }
+
+    async fn set_public_address(&mut self, address: PublicAddress) -> Result<(), Error> {
+        address.validate()?;
+        self.config.lock().await.public_address = address;
+        self.write_config_to_file().await
+    }
+
+    async fn set_launch_overrides(&mut self, overrides: LaunchOverrides) -> Result<(), Error> {
+        overrides.validate()?;
+        self.config.lock().await.launch_overrides = overrides;
+        self.write_config_to_file().await
+    }
+
+    async fn change_version(&mut self, version: String) -> Result<(), Error> {
         if version == self.config.lock().await.version {
             return Ok(());
         }
-        let (url, _) = match self.config.lock().await.flavour {
-            super::Flavour::Vanilla => get_vanilla_jar_url(&version).await.ok_or_else(|| {
-                let error_msg =
-                    format!("Cannot get the vanilla jar version for version {}", version);
-                Error {
-                    kind: ErrorKind::BadRequest,
-                    source: eyre!(error_msg),
-                }
-            })?,
-            super::Flavour::Fabric { .. } => get_fabric_jar_url(&version, &None, &None)
-                .await
-                .ok_or_else(|| {
-                    let error_msg =
-                        format!("Cannot get the fabric jar version for version {}", version);
-                    Error {
-                        kind: ErrorKind::BadRequest,
-                        source: eyre!(error_msg),
-                    }
-                })?,
-            super::Flavour::Paper { .. } => {
-                get_paper_jar_url(&version, &None).await.ok_or_else(|| {
-                    let error_msg =
-                        format!("Cannot get the paper jar version for version {}", version);
-                    Error {
-                        kind: ErrorKind::BadRequest,
-                        source: eyre!(error_msg),
-                    }
-                })?
-            }
-            super::Flavour::Spigot => todo!(),
-            super::Flavour::Forge { .. } => {
-                return Err(Error {
-                    kind: ErrorKind::UnsupportedOperation,
-                    source: eyre!("Changing versions is unsupported for forge servers"),
-                })
-            }
-        };
-        let lodestone_tmp = path_to_tmp().clone();
-        let temp_dir = tempfile::tempdir_in(lodestone_tmp).context("Failed to create temp dir")?;
-        download_file(
-            &url,
-            temp_dir.path(),
-            Some("server.jar"),
-            &Box::new(|_| {}),
-            true,
-        )
+        self.switch_version(VersionChange {
+            version,
+            flavour: None,
+            allow_downgrade: true,
+        })
         .await?;
-        let jar_path = temp_dir.path().join("server.jar");
-        crate::util::fs::rename(jar_path, self.path().await.join("server.jar")).await?;
-        self.config.lock().await.version = version;
-        self.write_config_to_file().await
+        Ok(())
     }
 
     async fn configurable_manifest(&mut self) -> ConfigurableManifest {
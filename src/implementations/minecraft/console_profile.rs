@@ -0,0 +1,355 @@
+//! Profiles of the messages a server prints when it's ready and when players join or leave.
+//!
+//! Servers translated with plugins or mods print these in their own language, so the patterns
+//! are part of the config of an instance rather than hardcoded. A profile is copied into the
+//! instance when it is picked, and updated there when the profile is changed.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+use super::MinecraftInstance;
+
+const MAX_PROFILE_NAME_LEN: usize = 32;
+const MAX_PATTERN_LEN: usize = 512;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ConsoleProfile {
+    pub name: String,
+    /// matched against whole lines, e.g. `Done \(.+\)!`
+    pub server_started: String,
+    /// matched against system messages, the first group is the name of the player
+    pub player_joined: String,
+    pub player_left: String,
+}
+
+impl Default for ConsoleProfile {
+    fn default() -> Self {
+        builtin_profiles().remove(0)
+    }
+}
+
+fn profile(
+    name: &str,
+    server_started: &str,
+    player_joined: &str,
+    player_left: &str,
+) -> ConsoleProfile {
+    ConsoleProfile {
+        name: name.to_string(),
+        server_started: server_started.to_string(),
+        player_joined: player_joined.to_string(),
+        player_left: player_left.to_string(),
+    }
+}
+
+/// The profiles that come with the core, the first is the default
+pub fn builtin_profiles() -> Vec<ConsoleProfile> {
+    vec![
+        profile(
+            "english",
+            r"Done \(.+\)!",
+            r"(.+) joined the game",
+            r"(.+) left the game",
+        ),
+        profile(
+            "german",
+            r"(?:Done|Fertig) \(.+\)!",
+            r"(.+) hat das Spiel betreten",
+            r"(.+) hat das Spiel verlassen",
+        ),
+        profile(
+            "french",
+            r"(?:Done|Terminé) \(.+\) ?!",
+            r"(.+) a rejoint la partie",
+            r"(.+) a quitté la partie",
+        ),
+        profile(
+            "spanish",
+            r"(?:Done|Hecho) \(.+\)!",
+            r"(.+) se ha unido a la partida",
+            r"(.+) ha abandonado la partida",
+        ),
+    ]
+}
+
+fn is_builtin(name: &str) -> bool {
+    builtin_profiles()
+        .iter()
+        .any(|profile| profile.name == name)
+}
+
+fn compile_pattern(field: &str, pattern: &str, needs_group: bool) -> Result<Regex, Error> {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The {field} pattern is longer than {MAX_PATTERN_LEN} characters"),
+        });
+    }
+    let regex = Regex::new(pattern).map_err(|e| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("The {field} pattern is invalid : {e}"),
+    })?;
+    // the whole match is group 0
+    if needs_group && regex.captures_len() < 2 {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The {field} pattern needs a group for the name of the player"),
+        });
+    }
+    Ok(regex)
+}
+
+/// A profile ready to parse the output of a server
+pub struct ConsoleParser {
+    server_started: Regex,
+    player_joined: Regex,
+    player_left: Regex,
+}
+
+impl ConsoleParser {
+    pub fn server_started(&self, line: &str) -> bool {
+        self.server_started.is_match(line).unwrap_or(false)
+    }
+
+    pub fn player_joined(&self, system_msg: &str) -> Option<String> {
+        Some(
+            self.player_joined
+                .captures(system_msg)
+                .ok()??
+                .get(1)?
+                .as_str()
+                .to_string(),
+        )
+    }
+
+    pub fn player_left(&self, system_msg: &str) -> Option<String> {
+        Some(
+            self.player_left
+                .captures(system_msg)
+                .ok()??
+                .get(1)?
+                .as_str()
+                .to_string(),
+        )
+    }
+}
+
+impl ConsoleProfile {
+    pub fn validate(&self) -> Result<(), Error> {
+        self.compile().map(|_| ())
+    }
+
+    pub fn compile(&self) -> Result<ConsoleParser, Error> {
+        if self.name.is_empty()
+            || self.name.len() > MAX_PROFILE_NAME_LEN
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Profile names must be 1 to {MAX_PROFILE_NAME_LEN} lowercase letters, digits, - or _"
+                ),
+            });
+        }
+        Ok(ConsoleParser {
+            server_started: compile_pattern("server started", &self.server_started, false)?,
+            player_joined: compile_pattern("player joined", &self.player_joined, true)?,
+            player_left: compile_pattern("player left", &self.player_left, true)?,
+        })
+    }
+}
+
+/// The profiles added by users, persisted in the stores directory
+pub struct ConsoleProfiles {
+    path_to_store: PathBuf,
+    profiles: BTreeMap<String, ConsoleProfile>,
+}
+
+impl ConsoleProfiles {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.profiles = BTreeMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read console profiles file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.profiles = serde_json::from_slice(&content).context(format!(
+            "Failed to parse console profiles file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.profiles)
+                .context("Failed to serialize console profiles")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write console profiles file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The built-in profiles followed by the ones added by users
+    pub fn list(&self) -> Vec<ConsoleProfile> {
+        let mut profiles = builtin_profiles();
+        profiles.extend(self.profiles.values().cloned());
+        profiles
+    }
+
+    pub fn get(&self, name: &str) -> Result<ConsoleProfile, Error> {
+        self.list()
+            .into_iter()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Console profile {name} not found"),
+            })
+    }
+
+    /// Add or replace a profile, the built-in ones can't be replaced
+    pub async fn set_profile(&mut self, profile: ConsoleProfile) -> Result<(), Error> {
+        profile.validate()?;
+        if is_builtin(&profile.name) {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("{} is a built-in profile", profile.name),
+            });
+        }
+        let old = self.profiles.insert(profile.name.clone(), profile.clone());
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.profiles.insert(profile.name, old),
+                None => self.profiles.remove(&profile.name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Instances using the profile keep their copy of it
+    pub async fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        let old = self.profiles.remove(name).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Console profile {name} not found"),
+        })?;
+        if let Err(e) = self.write_to_file().await {
+            self.profiles.insert(name.to_string(), old);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl MinecraftInstance {
+    pub async fn console_profile(&self) -> ConsoleProfile {
+        self.config.lock().await.console_profile.clone()
+    }
+
+    /// Takes effect on the next start
+    pub async fn set_console_profile(&mut self, profile: ConsoleProfile) -> Result<(), Error> {
+        profile.validate()?;
+        self.config.lock().await.console_profile = profile;
+        self.write_config_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin_profiles, profile, ConsoleProfiles};
+
+    #[test]
+    fn test_builtin_profiles() {
+        for profile in builtin_profiles() {
+            assert!(profile.validate().is_ok(), "{}", profile.name);
+        }
+        let german = builtin_profiles()
+            .into_iter()
+            .find(|profile| profile.name == "german")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            german.player_joined("Steve hat das Spiel betreten"),
+            Some("Steve".to_string())
+        );
+        assert_eq!(
+            german.player_left("Steve hat das Spiel verlassen"),
+            Some("Steve".to_string())
+        );
+        assert_eq!(german.player_joined("Steve joined the game"), None);
+        assert!(german.server_started("[12:00:00] [Server thread/INFO]: Fertig (3.2s)!"));
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        assert!(
+            profile("custom", "Ready", r"(\S+) arrived", r"(\S+) departed")
+                .validate()
+                .is_ok()
+        );
+        // no group for the player
+        assert!(
+            profile("custom", "Ready", r"\S+ arrived", r"(\S+) departed")
+                .validate()
+                .is_err()
+        );
+        assert!(
+            profile("custom", "Ready(", r"(\S+) arrived", r"(\S+) departed")
+                .validate()
+                .is_err()
+        );
+        assert!(
+            profile("Custom", "Ready", r"(\S+) arrived", r"(\S+) departed")
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_console_profiles() {
+        let temp_dir = tempdir::TempDir::new("test_console_profiles").unwrap();
+        let path = temp_dir.path().join("console_profiles.json");
+        let mut profiles = ConsoleProfiles::new(path.clone());
+        let custom = profile("custom", "Ready", r"(\S+) arrived", r"(\S+) departed");
+
+        profiles.set_profile(custom.clone()).await.unwrap();
+        assert!(profiles
+            .set_profile(profile("english", "Ready", r"(\S+) a", r"(\S+) b"))
+            .await
+            .is_err());
+
+        let mut reloaded = ConsoleProfiles::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.get("custom").unwrap(), custom);
+        assert!(reloaded.get("english").is_ok());
+        assert_eq!(reloaded.list().len(), builtin_profiles().len() + 1);
+
+        reloaded.delete_profile("custom").await.unwrap();
+        assert!(reloaded.get("custom").is_err());
+        assert!(reloaded.delete_profile("english").await.is_err());
+    }
+}
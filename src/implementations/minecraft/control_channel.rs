@@ -0,0 +1,195 @@
+//! The channel commands and player lists go through. The console is always there, but heavily
+//! modded servers can print join and leave messages its parser doesn't recognise, and commands
+//! written to it don't get an answer. RCON and Query answer the same way on every server.
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use indexmap::IndexMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::manifest::ConfigurableValue;
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::util::rand_alphanumeric;
+
+use super::configurable::ServerPropertySetting;
+use super::query::{query, QueryStatus};
+use super::server_properties::ServerPropertiesUpdate;
+use super::{Flavour, MinecraftInstance};
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const RCON_PASSWORD_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlChannel {
+    /// commands are written to the console and players are tracked from its output
+    #[default]
+    Console,
+    /// commands are sent over RCON and players are listed with the Query protocol, commands
+    /// go to the console while RCON is not connected
+    Network,
+}
+
+/// The command that prints the ticks per second on `flavour`, `tick query` needs 1.20.3
+pub(super) fn tps_command(flavour: &Flavour) -> &'static str {
+    match flavour {
+        Flavour::Paper { .. } | Flavour::Spigot => "tps",
+        Flavour::Forge { .. } => "forge tps",
+        Flavour::Vanilla | Flavour::Fabric { .. } | Flavour::Quilt { .. } => "tick query",
+    }
+}
+
+/// The ticks per second over the last minute in the output of the command of `tps_command`
+pub fn parse_tps(output: &str) -> Option<f64> {
+    lazy_static! {
+        static ref COLOR_CODE: Regex = Regex::new(r"§.").unwrap();
+        // paper prefixes the values above 20 with a star
+        static ref PAPER: Regex = Regex::new(r"TPS from last 1m, 5m, 15m: \*?([\d.]+)").unwrap();
+        static ref FORGE: Regex = Regex::new(r"Overall\s*:.*Mean TPS: ([\d.]+)").unwrap();
+        static ref TARGET_RATE: Regex = Regex::new(r"Target tick rate: ([\d.]+)").unwrap();
+        static ref TICK_TIME: Regex = Regex::new(r"Average time per tick: ([\d.]+)ms").unwrap();
+    }
+    let output = COLOR_CODE.replace_all(output, "");
+    let capture = |regex: &Regex| -> Option<f64> {
+        regex.captures(&output).ok()??.get(1)?.as_str().parse().ok()
+    };
+    if let Some(tps) = capture(&PAPER).or_else(|| capture(&FORGE)) {
+        return Some(tps);
+    }
+    // a server keeping up runs at its target rate, a lagging one at one tick per tick time
+    let target_rate = capture(&TARGET_RATE)?;
+    match capture(&TICK_TIME) {
+        Some(tick_time) if tick_time > 0.0 => Some(target_rate.min(1000.0 / tick_time)),
+        _ => Some(target_rate),
+    }
+}
+
+impl MinecraftInstance {
+    pub async fn control_channel(&self) -> ControlChannel {
+        self.config.lock().await.control_channel
+    }
+
+    /// Picking the network channel turns on RCON and Query in server.properties, with a random
+    /// RCON password if there is none, which the server reads on its next start
+    pub async fn set_control_channel(
+        &mut self,
+        control_channel: ControlChannel,
+    ) -> Result<ServerPropertiesUpdate, Error> {
+        let update = match control_channel {
+            ControlChannel::Console => ServerPropertiesUpdate {
+                changed: Vec::new(),
+                restart_required: false,
+            },
+            ControlChannel::Network => {
+                let password_set = self
+                    .server_properties()
+                    .await?
+                    .get(&ServerPropertySetting::RconPassword(String::new()).get_identifier())
+                    .and_then(|setting| setting.get_value())
+                    .map_or(false, |password| !password.to_string().is_empty());
+                let mut values = IndexMap::new();
+                values.insert(
+                    ServerPropertySetting::EnableRcon(true).get_identifier(),
+                    ConfigurableValue::Boolean(true),
+                );
+                values.insert(
+                    ServerPropertySetting::EnableQuery(true).get_identifier(),
+                    ConfigurableValue::Boolean(true),
+                );
+                if !password_set {
+                    values.insert(
+                        ServerPropertySetting::RconPassword(String::new()).get_identifier(),
+                        ConfigurableValue::String(rand_alphanumeric(RCON_PASSWORD_LEN)),
+                    );
+                }
+                self.update_server_properties(values).await?
+            }
+        };
+        self.config.lock().await.control_channel = control_channel;
+        self.write_config_to_file().await?;
+        Ok(update)
+    }
+
+    /// `query.port` defaults to the port of the server
+    async fn query_port(&self) -> Result<u16, Error> {
+        let (enabled, query_port) = {
+            let lock = self.configurable_manifest.lock().await;
+            let enabled = lock
+                .get_unique_setting_key(&ServerPropertySetting::EnableQuery(false).get_identifier())
+                .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+                .flatten();
+            let query_port = lock
+                .get_unique_setting_key(&ServerPropertySetting::QueryPort(0).get_identifier())
+                .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+                .flatten();
+            (enabled, query_port)
+        };
+        if enabled != Some(true) {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Query is not enabled, set enable-query in server.properties"),
+            });
+        }
+        let port = match query_port {
+            Some(port) => port,
+            None => self.port().await,
+        };
+        u16::try_from(port).map_err(|_| eyre!("Invalid query port {port}").into())
+    }
+
+    /// The MOTD, version and players online, as answered by the Query protocol
+    pub async fn query_status(&self) -> Result<QueryStatus, Error> {
+        if self.state().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server is not running"),
+            });
+        }
+        query(self.query_port().await?, QUERY_TIMEOUT).await
+    }
+
+    /// The ticks per second over the last minute, `None` if the server doesn't report it
+    pub async fn tps(&self) -> Result<Option<f64>, Error> {
+        let flavour = self.config.lock().await.flavour.clone();
+        Ok(parse_tps(&self.send_rcon(tps_command(&flavour)).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_tps;
+
+    #[test]
+    fn test_parse_tps() {
+        assert_eq!(
+            parse_tps("§6TPS from last 1m, 5m, 15m: §a*20.0, §a19.98, §a19.5"),
+            Some(20.0)
+        );
+        assert_eq!(
+            parse_tps("TPS from last 1m, 5m, 15m: 17.2, 19.98, 19.5"),
+            Some(17.2)
+        );
+        assert_eq!(
+            parse_tps(
+                "Dim minecraft:overworld: Mean tick time: 1.2 ms. Mean TPS: 20.000\n\
+                 Overall: Mean tick time: 62.5 ms. Mean TPS: 16.000"
+            ),
+            Some(16.0)
+        );
+        assert_eq!(
+            parse_tps(
+                "The game is running normally\nTarget tick rate: 20.0 per second.\n\
+                 Average time per tick: 100.0ms (Target: 50.0ms)"
+            ),
+            Some(10.0)
+        );
+        assert_eq!(parse_tps("Unknown command"), None);
+    }
+}
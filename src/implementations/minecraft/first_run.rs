@@ -0,0 +1,79 @@
+//! The EULA and the first run of a new server.
+//!
+//! A server refuses to start until its EULA is accepted, and writes its default files, e.g. the
+//! full `server.properties` and the configs of its mods, the first time it starts. Both are done
+//! during the setup so a new instance is ready to start, and a server that can't start fails its
+//! setup rather than its first launch.
+
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::readiness::wait_until_ready;
+use crate::traits::t_server::{State, TServer};
+
+use super::MinecraftInstance;
+
+pub const EULA_URL: &str = "https://aka.ms/MinecraftEULA";
+/// how long a first run has to get ready, world generation and mod loading take a while
+const FIRST_RUN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+pub fn eula_not_accepted() -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("The Minecraft EULA ({EULA_URL}) must be accepted to set up a server"),
+    }
+}
+
+/// Write `eula.txt` the way the server would once it's accepted
+pub async fn write_eula(path_to_instance: &Path) -> Result<(), Error> {
+    let path_to_eula = path_to_instance.join("eula.txt");
+    tokio::fs::write(
+        &path_to_eula,
+        format!(
+            "#By changing the setting below to TRUE you are indicating your agreement to our EULA ({EULA_URL}).\n\
+             #Accepted through Lodestone on {}\n\
+             eula=true\n",
+            chrono::Utc::now().to_rfc2822()
+        ),
+    )
+    .await
+    .context(format!("Failed to write {}", path_to_eula.display()))?;
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// Start the server once and stop it as soon as it's ready
+    pub async fn first_run(&mut self) -> Result<(), Error> {
+        self.start(CausedBy::System, false).await?;
+        let readiness = wait_until_ready(
+            &self.event_broadcaster,
+            &self.uuid,
+            async { (self.state().await, self.readiness().await) },
+            FIRST_RUN_TIMEOUT,
+        )
+        .await;
+        match readiness {
+            Some(readiness) if readiness.ready => self.stop(CausedBy::System, true).await,
+            Some(_) => Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("The server stopped during its first run, check its console"),
+            }),
+            None => {
+                if !self.state().await.is_stopped() {
+                    let _ = self.kill(CausedBy::System).await;
+                }
+                Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!(
+                        "The server was not ready after {} minutes on its first run",
+                        FIRST_RUN_TIMEOUT.as_secs() / 60
+                    ),
+                })
+            }
+        }
+    }
+}
@@ -21,6 +21,27 @@ pub async fn get_forge_minecraft_versions() -> Result<Vec<String>, Error> {
     Ok(response.into_iter().map(|(k, _)| k).rev().collect())
 }
 
+/// The forge builds for `version`, newest first, formatted as `<minecraft version>-<forge version>`
+pub async fn get_forge_builds(version: &str) -> Result<Vec<String>, Error> {
+    let http = reqwest::Client::new();
+    let response: IndexMap<String, Vec<String>> = serde_json::from_str(
+        http.get("https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json")
+            .send()
+            .await
+            .context("Failed to get forge builds, http request failed")?
+            .text()
+            .await
+            .context("Failed to get forge builds, text conversion failed")?
+            .as_str(),
+    )
+    .context("Failed to get forge builds, json is not a map")?;
+
+    Ok(response
+        .get(version)
+        .map(|builds| builds.iter().rev().cloned().collect())
+        .unwrap_or_default())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -34,4 +55,11 @@ mod test {
         assert!(versions.contains(&"1.16.2".to_string()));
         assert!(versions.contains(&"1.16.1".to_string()));
     }
+
+    #[tokio::test]
+    async fn test_get_forge_builds() {
+        let builds = get_forge_builds("1.16.5").await.unwrap();
+        assert!(builds.contains(&"1.16.5-36.2.34".to_string()));
+        assert!(get_forge_builds("not a version").await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,181 @@
+//! Metrics the server reports about itself, asked over RCON. A high tick time shows a lag spike
+//! that the CPU usage of the host doesn't, e.g. a single overloaded thread.
+
+use std::time::{Duration, Instant};
+
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+
+use crate::traits::t_server::GameMetrics;
+
+use super::control_channel::{parse_tps, tps_command};
+use super::{Flavour, MinecraftInstance};
+
+/// every sample is a few commands, so it's taken less often than the host usage
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The last sample of a run of the server
+#[derive(Default)]
+pub struct GameMetricsCache {
+    metrics: Option<GameMetrics>,
+    sampled_at: Option<Instant>,
+    sampling: bool,
+}
+
+fn strip_color_codes(output: &str) -> String {
+    lazy_static! {
+        static ref COLOR_CODE: Regex = Regex::new(r"§.").unwrap();
+    }
+    COLOR_CODE.replace_all(output, "").into_owned()
+}
+
+fn capture<T: std::str::FromStr>(regex: &Regex, output: &str) -> Option<T> {
+    regex.captures(output).ok()??.get(1)?.as_str().parse().ok()
+}
+
+/// The average tick time in milliseconds, in the output of `mspt` on paper or of the command
+/// of `tps_command` on forge and vanilla. Paper's is over the last 5 seconds
+pub fn parse_tick_time(output: &str) -> Option<f64> {
+    lazy_static! {
+        static ref PAPER: Regex =
+            Regex::new(r"avg/min/max\) from last 5s, 10s, 1m:\D*([\d.]+)").unwrap();
+        static ref FORGE: Regex = Regex::new(r"Overall\s*:\s*Mean tick time: ([\d.]+) ms").unwrap();
+        static ref VANILLA: Regex = Regex::new(r"Average time per tick: ([\d.]+)ms").unwrap();
+    }
+    let output = strip_color_codes(output);
+    capture(&PAPER, &output)
+        .or_else(|| capture(&FORGE, &output))
+        .or_else(|| capture(&VANILLA, &output))
+}
+
+/// The chunks loaded in every world, in the output of `paper chunkinfo *`
+pub fn parse_loaded_chunks(output: &str) -> Option<u32> {
+    lazy_static! {
+        static ref TOTAL: Regex = Regex::new(r"Total: (\d+)").unwrap();
+    }
+    let output = strip_color_codes(output);
+    // with several worlds, the total of all of them comes last
+    let total = TOTAL.captures_iter(&output).filter_map(Result::ok).last()?;
+    total.get(1)?.as_str().parse().ok()
+}
+
+/// The number of entities in the output of `execute if entity @e`
+pub fn parse_entity_count(output: &str) -> Option<u32> {
+    lazy_static! {
+        static ref COUNT: Regex = Regex::new(r"Test passed, count: (\d+)").unwrap();
+    }
+    capture(&COUNT, &strip_color_codes(output))
+}
+
+impl MinecraftInstance {
+    async fn sample_game_metrics(&self) -> Option<GameMetrics> {
+        let flavour = self.config.lock().await.flavour.clone();
+        // a server that doesn't answer the first command isn't reachable over RCON
+        let tps_output = self.send_rcon(tps_command(&flavour)).await.ok()?;
+        let tick_time_ms = match flavour {
+            Flavour::Paper { .. } => self
+                .send_rcon("mspt")
+                .await
+                .ok()
+                .and_then(|output| parse_tick_time(&output)),
+            Flavour::Spigot => None,
+            _ => parse_tick_time(&tps_output),
+        };
+        let loaded_chunks = match flavour {
+            Flavour::Paper { .. } => self
+                .send_rcon("paper chunkinfo *")
+                .await
+                .ok()
+                .and_then(|output| parse_loaded_chunks(&output)),
+            _ => None,
+        };
+        let entity_count = self
+            .send_rcon("execute if entity @e")
+            .await
+            .ok()
+            .and_then(|output| parse_entity_count(&output));
+        Some(GameMetrics {
+            tps: parse_tps(&tps_output),
+            tick_time_ms,
+            loaded_chunks,
+            entity_count,
+        })
+    }
+
+    /// The game metrics of the last sample, a new one is taken in the background once it is
+    /// older than `SAMPLE_INTERVAL`. `None` while RCON is not connected
+    pub(super) async fn game_metrics(&self) -> Option<GameMetrics> {
+        let mut cache = self.game_metrics.lock().await;
+        let due = cache
+            .sampled_at
+            .map_or(true, |sampled_at| sampled_at.elapsed() >= SAMPLE_INTERVAL);
+        if due && !cache.sampling {
+            cache.sampling = true;
+            let instance = self.clone();
+            tokio::spawn(async move {
+                let metrics = instance.sample_game_metrics().await;
+                let mut cache = instance.game_metrics.lock().await;
+                cache.metrics = metrics;
+                cache.sampled_at = Some(Instant::now());
+                cache.sampling = false;
+            });
+        }
+        cache.metrics.clone()
+    }
+
+    /// Forget the metrics of the previous run
+    pub(super) async fn reset_game_metrics(&self) {
+        let mut cache = self.game_metrics.lock().await;
+        cache.metrics = None;
+        cache.sampled_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_entity_count, parse_loaded_chunks, parse_tick_time};
+
+    #[test]
+    fn test_parse_tick_time() {
+        assert_eq!(
+            parse_tick_time(
+                "§6Server tick times §e(§7avg§e/§7min§e/§7max§e)§6 from last 5s§7,§6 10s§7,§6 1m§e:\n\
+                 §6◴ §a12.5§7/§a3.1§7/§c61.0§e, §a10.2§7/§a3.1§7/§c61.0§e, §a9.8§7/§a2.0§7/§c75.3"
+            ),
+            Some(12.5)
+        );
+        assert_eq!(
+            parse_tick_time(
+                "Dim minecraft:overworld: Mean tick time: 1.2 ms. Mean TPS: 20.000\n\
+                 Overall: Mean tick time: 62.5 ms. Mean TPS: 16.000"
+            ),
+            Some(62.5)
+        );
+        assert_eq!(
+            parse_tick_time(
+                "Target tick rate: 20.0 per second.\nAverage time per tick: 100.0ms (Target: 50.0ms)"
+            ),
+            Some(100.0)
+        );
+        assert_eq!(parse_tick_time("Unknown command"), None);
+    }
+
+    #[test]
+    fn test_parse_loaded_chunks() {
+        assert_eq!(
+            parse_loaded_chunks(
+                "Chunks in world:\nTotal: 625 Inactive: 0 Full: 441 Block Ticking: 289 Entity Ticking: 289\n\
+                 Chunks in world_nether:\nTotal: 12 Inactive: 0 Full: 0 Block Ticking: 0 Entity Ticking: 0\n\
+                 Chunks in all listed worlds:\nTotal: 637 Inactive: 0 Full: 441 Block Ticking: 289 Entity Ticking: 289"
+            ),
+            Some(637)
+        );
+        assert_eq!(parse_loaded_chunks("Unknown command"), None);
+    }
+
+    #[test]
+    fn test_parse_entity_count() {
+        assert_eq!(parse_entity_count("Test passed, count: 214"), Some(214));
+        assert_eq!(parse_entity_count("Test failed"), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Backups of a running server. Saving is turned off while the instance directory is archived,
+//! after the world is flushed to disk, so the archive doesn't catch region files half written.
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::error;
+
+use crate::backup::{self, BackupEntry};
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_server::{State, TServer};
+
+use super::MinecraftInstance;
+
+/// how long a `save-all flush` of a large world may take
+const SAVE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether `line` is the server confirming a `save-all`, "Saved the world" before 1.13.
+/// A chat message saying the same doesn't count
+pub fn is_save_complete(line: &str) -> bool {
+    lazy_static! {
+        static ref SAVED: Regex = Regex::new(r"(?:^|\]:?\s)Saved the (?:game|world)\s*$").unwrap();
+    }
+    SAVED.is_match(line.trim()).unwrap_or(false)
+}
+
+impl MinecraftInstance {
+    /// Flush the world to disk, returning once the server confirms it
+    async fn save_all(&self, caused_by: CausedBy) -> Result<(), Error> {
+        // subscribed before the command is sent so the confirmation can't be missed
+        let mut rx = self.event_broadcaster.subscribe();
+        self.send_command("save-all flush", caused_by).await?;
+        let wait_for_save = async {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if let EventInner::InstanceEvent(InstanceEvent {
+                            instance_uuid,
+                            instance_event_inner,
+                            ..
+                        }) = event.event_inner
+                        {
+                            if instance_uuid != self.uuid {
+                                continue;
+                            }
+                            match instance_event_inner {
+                                InstanceEventInner::InstanceOutput { message }
+                                    if is_save_complete(&message) =>
+                                {
+                                    return Ok(())
+                                }
+                                InstanceEventInner::StateTransition { to } if to.is_stopped() => {
+                                    return Err(Error::from(eyre!(
+                                        "The server stopped before the world was saved"
+                                    )))
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        return Err(Error::from(eyre!("Stopped listening for the save")))
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(SAVE_TIMEOUT, wait_for_save)
+            .await
+            .map_err(|_| Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("The server did not confirm the world was saved, check the console"),
+            })?
+    }
+
+    /// Back up the instance without stopping it. The world is flushed and saving is turned off
+    /// for the duration of the backup, then turned back on even if the backup failed. A server
+    /// that isn't running is backed up as is
+    pub async fn hot_backup(
+        &self,
+        name: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<BackupEntry, Error> {
+        let instance_name = self.config.lock().await.name.clone();
+        // another backup turning saving back on would leave this one copying a live world
+        let _hot_backup_guard = self.hot_backup_lock.lock().await;
+        let live = self.state().await == State::Running;
+        if live {
+            self.send_command("save-off", caused_by.clone()).await?;
+        }
+        let result = async {
+            if live {
+                self.save_all(caused_by.clone()).await?;
+            }
+            backup::create_backup(
+                self.uuid.clone(),
+                instance_name.clone(),
+                self.path_to_instance.clone(),
+                name,
+                self.event_broadcaster.clone(),
+                caused_by.clone(),
+            )
+            .await
+        }
+        .await;
+        if live {
+            if let Err(e) = self.send_command("save-on", caused_by).await {
+                error!("[{instance_name}] Failed to turn saving back on after a backup : {e}");
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_save_complete;
+
+    #[test]
+    fn test_is_save_complete() {
+        assert!(is_save_complete(
+            "[12:00:00] [Server thread/INFO]: Saved the game"
+        ));
+        assert!(is_save_complete(
+            "[12:00:00] [Server thread/INFO] [minecraft/MinecraftServer]: Saved the game\r"
+        ));
+        assert!(is_save_complete("[12:00:00 INFO]: Saved the world"));
+        // the answer over RCON
+        assert!(is_save_complete("Saved the game"));
+        assert!(!is_save_complete(
+            "[12:00:00] [Server thread/INFO]: <Steve> Saved the game"
+        ));
+        assert!(!is_save_complete(
+            "[12:00:00] [Server thread/INFO]: Saving the game (this may take a moment!)"
+        ));
+    }
+}
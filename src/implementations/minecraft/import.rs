@@ -0,0 +1,576 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::{Event, ProgressionEventID},
+    macro_executor::MacroExecutor,
+    prelude::path_to_binaries,
+    traits::t_configurable::{
+        CrashRestartPolicy, DisplayMetadata, LaunchOverrides, PublicAddress, ResourceLimits,
+        TConfigurable,
+    },
+    types::DotLodestoneConfig,
+    util::format_byte_download,
+};
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    console_profile::ConsoleProfile,
+    control_channel::ControlChannel,
+    download_jre_if_missing,
+    memory::{host_memory, recommend_heap},
+    mods::list_mods,
+    scheduling::ProcessScheduling,
+    FabricInstallerVersion, FabricLoaderVersion, Flavour, FlavourKind, ForgeBuildVersion, Jre,
+    MinecraftInstance, PaperBuildVersion, RestoreConfig,
+};
+
+/// files that commonly hold the JVM arguments of a hand-run server
+const LAUNCH_SCRIPTS: [&str; 6] = [
+    "user_jvm_args.txt",
+    "start.sh",
+    "run.sh",
+    "start.command",
+    "start.bat",
+    "run.bat",
+];
+
+/// What was found in an existing server directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DetectedServer {
+    pub flavour: Flavour,
+    pub version: String,
+    /// the jar the server is launched with, `None` for Forge 1.17+ which launches from its libraries
+    pub jar: Option<String>,
+    /// from server.properties, 25565 if not set
+    pub port: u32,
+    /// in megabytes, from the -Xms and -Xmx of a launch script if there is one
+    pub min_ram: Option<u32>,
+    pub max_ram: Option<u32>,
+}
+
+/// Read the version id from the version.json vanilla and paperclip jars ship with
+fn jar_version(path: &Path) -> Option<String> {
+    #[derive(Deserialize)]
+    struct VersionJson {
+        id: String,
+    }
+    let mut archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+    let mut content = String::new();
+    archive
+        .by_name("version.json")
+        .ok()?
+        .read_to_string(&mut content)
+        .ok()?;
+    Some(serde_json::from_str::<VersionJson>(&content).ok()?.id)
+}
+
+/// `-Xmx4G` style heap sizes in megabytes
+fn parse_heap_size(size: &str) -> Option<u32> {
+    let (number, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit())?);
+    let number: u64 = number.parse().ok()?;
+    let megabytes = match unit {
+        "g" | "G" => number * 1024,
+        "m" | "M" => number,
+        "k" | "K" => number / 1024,
+        _ => return None,
+    };
+    u32::try_from(megabytes).ok()
+}
+
+/// The heap sizes set in the first launch script that sets any
+fn detect_heap_sizes(dir: &Path) -> (Option<u32>, Option<u32>) {
+    for script in LAUNCH_SCRIPTS {
+        let content = match std::fs::read_to_string(dir.join(script)) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let mut min_ram = None;
+        let mut max_ram = None;
+        for token in content
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(str::split_whitespace)
+        {
+            if let Some(size) = token.strip_prefix("-Xms") {
+                min_ram = parse_heap_size(size);
+            } else if let Some(size) = token.strip_prefix("-Xmx") {
+                max_ram = parse_heap_size(size);
+            }
+        }
+        if min_ram.is_some() || max_ram.is_some() {
+            return (min_ram, max_ram);
+        }
+    }
+    (None, None)
+}
+
+fn detect_port(dir: &Path) -> u32 {
+    std::fs::read_to_string(dir.join("server.properties"))
+        .ok()
+        .and_then(|content| {
+            content.lines().find_map(|line| {
+                line.trim()
+                    .strip_prefix("server-port=")
+                    .and_then(|port| port.trim().parse().ok())
+            })
+        })
+        .unwrap_or(25565)
+}
+
+/// `prefix<version>suffix` file names, like `spigot-1.20.1.jar`
+fn version_between<'a>(name: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    name.strip_prefix(prefix)?
+        .strip_suffix(suffix)
+        .filter(|version| !version.is_empty())
+}
+
+/// Figure out the flavour, version and launch jar of the server in `dir`
+pub fn detect_server(dir: &Path) -> Result<DetectedServer, Error> {
+    if !dir.is_dir() {
+        return Err(Error::bad_request(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
+    }
+    let mut jars: Vec<String> = std::fs::read_dir(dir)
+        .context(format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| name.ends_with(".jar") && !name.ends_with("-installer.jar"))
+        .collect();
+    jars.sort();
+    let find_jar = |prefix: &str| jars.iter().find(|name| name.starts_with(prefix)).cloned();
+    let (min_ram, max_ram) = detect_heap_sizes(dir);
+    let detected = |flavour, version: String, jar| DetectedServer {
+        flavour,
+        version,
+        jar,
+        port: detect_port(dir),
+        min_ram,
+        max_ram,
+    };
+    let not_detected = |what: &str| {
+        Error::bad_request(format!(
+            "Could not tell the Minecraft version of the {what} server in {}",
+            dir.display()
+        ))
+    };
+
+    // Forge 1.17+ launches from the args file in its libraries
+    let forge_libraries = dir
+        .join("libraries")
+        .join("net")
+        .join("minecraftforge")
+        .join("forge");
+    if let Ok(entries) = std::fs::read_dir(&forge_libraries) {
+        let mut builds: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("unix_args.txt").is_file())
+            .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+            .collect();
+        builds.sort();
+        if let Some(build) = builds.pop() {
+            let version = build
+                .split('-')
+                .next()
+                .ok_or_else(|| not_detected("Forge"))?
+                .to_string();
+            return Ok(detected(
+                Flavour::Forge {
+                    build_version: Some(ForgeBuildVersion(build)),
+                },
+                version,
+                None,
+            ));
+        }
+    }
+    if let Some(jar) = find_jar("forge-") {
+        let build = version_between(&jar, "forge-", ".jar")
+            .map(|build| build.trim_end_matches("-universal").to_string())
+            .ok_or_else(|| not_detected("Forge"))?;
+        let version = build.split('-').next().unwrap_or_default().to_string();
+        return Ok(detected(
+            Flavour::Forge {
+                build_version: Some(ForgeBuildVersion(build)),
+            },
+            version,
+            Some(jar),
+        ));
+    }
+    // fabric-server-mc.1.20.1-loader.0.14.21-launcher.0.11.2.jar
+    if let Some(jar) = find_jar("fabric-server-mc.") {
+        let versions = version_between(&jar, "fabric-server-mc.", ".jar")
+            .ok_or_else(|| not_detected("Fabric"))?;
+        let (version, rest) = versions
+            .split_once("-loader.")
+            .ok_or_else(|| not_detected("Fabric"))?;
+        let (loader, installer) = rest
+            .split_once("-launcher.")
+            .ok_or_else(|| not_detected("Fabric"))?;
+        return Ok(detected(
+            Flavour::Fabric {
+                loader_version: Some(FabricLoaderVersion(loader.to_string())),
+                installer_version: Some(FabricInstallerVersion(installer.to_string())),
+            },
+            version.to_string(),
+            Some(jar),
+        ));
+    }
+    // the older fabric launcher runs the vanilla server.jar next to it
+    if let Some(jar) = find_jar("fabric-server-launch") {
+        let version = jar_version(&dir.join("server.jar")).ok_or_else(|| not_detected("Fabric"))?;
+        return Ok(detected(
+            Flavour::Fabric {
+                loader_version: None,
+                installer_version: None,
+            },
+            version,
+            Some(jar),
+        ));
+    }
+    // the quilt launcher runs the vanilla server.jar next to it
+    if let Some(jar) = find_jar("quilt-server-launch") {
+        let version = jar_version(&dir.join("server.jar")).ok_or_else(|| not_detected("Quilt"))?;
+        return Ok(detected(
+            Flavour::Quilt {
+                loader_version: None,
+            },
+            version,
+            Some(jar),
+        ));
+    }
+    // paper-1.20.1-100.jar
+    if let Some(jar) = find_jar("paper") {
+        let from_name = version_between(&jar, "paper-", ".jar").and_then(|versions| {
+            let (version, build) = versions.rsplit_once('-')?;
+            Some((version.to_string(), build.parse().ok()?))
+        });
+        let (version, build) = match from_name {
+            Some((version, build)) => (version, Some(build)),
+            None => (
+                jar_version(&dir.join(&jar)).ok_or_else(|| not_detected("Paper"))?,
+                None,
+            ),
+        };
+        return Ok(detected(
+            Flavour::Paper {
+                build_version: build.map(PaperBuildVersion),
+            },
+            version,
+            Some(jar),
+        ));
+    }
+    if let Some(jar) = find_jar("spigot") {
+        let version = version_between(&jar, "spigot-", ".jar")
+            .map(str::to_string)
+            .or_else(|| jar_version(&dir.join(&jar)))
+            .ok_or_else(|| not_detected("Spigot"))?;
+        return Ok(detected(Flavour::Spigot, version, Some(jar)));
+    }
+    let vanilla_jar = jars
+        .iter()
+        .find(|name| *name == "server.jar")
+        .or_else(|| {
+            jars.iter()
+                .find(|name| name.starts_with("minecraft_server"))
+        })
+        .or_else(|| (jars.len() == 1).then(|| &jars[0]))
+        .cloned();
+    if let Some(jar) = vanilla_jar {
+        let version = jar_version(&dir.join(&jar))
+            .or_else(|| version_between(&jar, "minecraft_server.", ".jar").map(str::to_string))
+            .ok_or_else(|| not_detected("vanilla"))?;
+        return Ok(detected(Flavour::Vanilla, version, Some(jar)));
+    }
+    Err(Error::bad_request(format!(
+        "Could not find a Minecraft server jar in {}",
+        dir.display()
+    )))
+}
+
+/// Rename the launch jar to the server.jar Lodestone starts, keeping the older fabric launcher
+/// pointed at the vanilla jar it runs
+fn prepare_launch_jar(dir: &Path, detected: &DetectedServer) -> Result<(), Error> {
+    let jar = match &detected.jar {
+        // forge finds its own jar
+        Some(_) if matches!(detected.flavour, Flavour::Forge { .. }) => return Ok(()),
+        // so does quilt, from its launch jar
+        Some(_) if matches!(detected.flavour, Flavour::Quilt { .. }) => return Ok(()),
+        Some(jar) if jar != "server.jar" => jar,
+        _ => return Ok(()),
+    };
+    let server_jar = dir.join("server.jar");
+    if server_jar.exists() {
+        let moved_to = if jar.starts_with("fabric-server-launch") {
+            let vanilla_jar = "vanilla-server.jar";
+            std::fs::write(
+                dir.join("fabric-server-launcher.properties"),
+                format!("serverJarPath={vanilla_jar}\n"),
+            )
+            .context("Failed to write fabric-server-launcher.properties")?;
+            dir.join(vanilla_jar)
+        } else {
+            dir.join("server.jar.old")
+        };
+        std::fs::rename(&server_jar, &moved_to).context(format!(
+            "Failed to move server.jar to {}",
+            moved_to.display()
+        ))?;
+    }
+    std::fs::rename(dir.join(jar), &server_jar).context(format!("Failed to rename {jar}"))?;
+    Ok(())
+}
+
+impl MinecraftInstance {
+    /// Register the server already in `path_to_instance` as an instance, nothing but the JRE is
+    /// downloaded
+    #[allow(clippy::too_many_arguments)]
+    pub async fn import(
+        name: String,
+        port: u32,
+        detected: DetectedServer,
+        dot_lodestone_config: DotLodestoneConfig,
+        path_to_instance: PathBuf,
+        progression_event_id: &ProgressionEventID,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<MinecraftInstance, Error> {
+        let path_to_runtimes = path_to_binaries().to_owned();
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "2/3: Preparing the server",
+            1.0,
+        ));
+        tokio::task::spawn_blocking({
+            let path_to_instance = path_to_instance.clone();
+            let detected = detected.clone();
+            move || prepare_launch_jar(&path_to_instance, &detected)
+        })
+        .await
+        .context("Failed to prepare the server jar")??;
+        let path_to_resources = path_to_instance.join("resources");
+        tokio::fs::create_dir_all(path_to_instance.join("macros"))
+            .await
+            .and(tokio::fs::create_dir_all(path_to_resources.join("mods")).await)
+            .and(tokio::fs::create_dir_all(path_to_resources.join("worlds")).await)
+            .and(tokio::fs::create_dir_all(path_to_resources.join("defaults")).await)
+            .context("Could not create some directories for instance")?;
+        let path_to_eula = path_to_instance.join("eula.txt");
+        if !path_to_eula.exists() {
+            tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true")
+                .await
+                .context("Could not write eula.txt")?;
+        }
+
+        let Jre {
+            major_version: jre_major_version,
+            java: jre,
+            ..
+        } = download_jre_if_missing(&detected.version, &path_to_runtimes, {
+            let event_broadcaster = event_broadcaster.clone();
+            &move |dl| {
+                if let Some(total) = dl.total {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "3/3: Downloading JRE {}",
+                            format_byte_download(dl.downloaded, total)
+                        ),
+                        (dl.step as f64 / total as f64) * 4.0,
+                    ));
+                }
+            }
+        })
+        .await?;
+
+        // heaps missing from the launch scripts are estimated from the mods
+        let recommendation = {
+            let mods_dir = path_to_instance.join("mods");
+            let mods = tokio::task::spawn_blocking(move || list_mods(&mods_dir))
+                .await
+                .context("Failed to list mods")??;
+            recommend_heap(&FlavourKind::from(&detected.flavour), &mods, host_memory())
+        };
+        let restore_config = RestoreConfig {
+            name,
+            version: detected.version,
+            flavour: detected.flavour,
+            description: String::new(),
+            cmd_args: Vec::new(),
+            java_cmd: Some(jre.to_string_lossy().to_string()),
+            port,
+            min_ram: detected.min_ram.unwrap_or(recommendation.min_ram),
+            max_ram: detected.max_ram.unwrap_or(recommendation.max_ram),
+            auto_start: false,
+            restart_on_crash: false,
+            backup_period: None,
+            jre_major_version,
+            // the world was already generated by the previous installation
+            has_started: true,
+            resource_limits: ResourceLimits::default(),
+            crash_restart_policy: CrashRestartPolicy::default(),
+            display: DisplayMetadata::default(),
+            public_address: PublicAddress::default(),
+            launch_overrides: LaunchOverrides::default(),
+            launch_target: None,
+            macro_grants: HashMap::new(),
+            console_profile: ConsoleProfile::default(),
+            control_channel: ControlChannel::default(),
+            scheduling: ProcessScheduling::default(),
+        };
+        let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
+        tokio::fs::write(
+            &path_to_config,
+            serde_json::to_string_pretty(&restore_config).context(
+                "Failed to serialize config to string. This is a bug, please report it.",
+            )?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config file at {}",
+            &path_to_config.display()
+        ))?;
+        let mut instance = MinecraftInstance::restore(
+            path_to_instance,
+            dot_lodestone_config,
+            event_broadcaster,
+            macro_executor,
+        )
+        .await?;
+        // the port may have been moved off the one in server.properties
+        if port != detected.port {
+            instance.set_port(port).await?;
+        }
+        Ok(instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::Path};
+
+    use super::{detect_server, parse_heap_size, prepare_launch_jar};
+    use crate::implementations::minecraft::{FabricLoaderVersion, Flavour, ForgeBuildVersion};
+
+    fn write_jar(path: &Path, version: Option<&str>) {
+        let mut jar = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        if let Some(version) = version {
+            jar.start_file("version.json", zip::write::FileOptions::default())
+                .unwrap();
+            jar.write_all(format!(r#"{{"id": "{version}", "name": "{version}"}}"#).as_bytes())
+                .unwrap();
+        }
+        jar.finish().unwrap();
+    }
+
+    #[test]
+    fn test_parse_heap_size() {
+        assert_eq!(parse_heap_size("4G"), Some(4096));
+        assert_eq!(parse_heap_size("512m"), Some(512));
+        assert_eq!(parse_heap_size("2097152K"), Some(2048));
+        assert_eq!(parse_heap_size("4"), None);
+        assert_eq!(parse_heap_size("G"), None);
+    }
+
+    #[test]
+    fn test_detect_vanilla() {
+        let temp_dir = tempdir::TempDir::new("test_detect_vanilla").unwrap();
+        let dir = temp_dir.path();
+        assert!(detect_server(dir).is_err());
+
+        write_jar(&dir.join("minecraft_server.1.20.1.jar"), Some("1.20.1"));
+        std::fs::write(
+            dir.join("server.properties"),
+            "motd=hi\nserver-port=25570\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("start.sh"),
+            "#!/bin/sh\n# -Xmx1G\njava -Xms1G -Xmx6G -jar minecraft_server.1.20.1.jar nogui\n",
+        )
+        .unwrap();
+        let detected = detect_server(dir).unwrap();
+        assert_eq!(detected.flavour, Flavour::Vanilla);
+        assert_eq!(detected.version, "1.20.1");
+        assert_eq!(detected.port, 25570);
+        assert_eq!(
+            (detected.min_ram, detected.max_ram),
+            (Some(1024), Some(6144))
+        );
+
+        prepare_launch_jar(dir, &detected).unwrap();
+        assert!(dir.join("server.jar").is_file());
+        assert!(!dir.join("minecraft_server.1.20.1.jar").exists());
+    }
+
+    #[test]
+    fn test_detect_modded() {
+        let temp_dir = tempdir::TempDir::new("test_detect_modded").unwrap();
+        let forge = temp_dir.path().join("forge");
+        let libraries = forge.join("libraries/net/minecraftforge/forge/1.19.2-43.2.0");
+        std::fs::create_dir_all(&libraries).unwrap();
+        std::fs::write(libraries.join("unix_args.txt"), "").unwrap();
+        let detected = detect_server(&forge).unwrap();
+        assert_eq!(detected.version, "1.19.2");
+        assert_eq!(
+            detected.flavour,
+            Flavour::Forge {
+                build_version: Some(ForgeBuildVersion("1.19.2-43.2.0".to_string()))
+            }
+        );
+        assert_eq!(detected.jar, None);
+
+        let fabric = temp_dir.path().join("fabric");
+        std::fs::create_dir_all(&fabric).unwrap();
+        write_jar(&fabric.join("server.jar"), Some("1.18.2"));
+        write_jar(&fabric.join("fabric-server-launch.jar"), None);
+        let detected = detect_server(&fabric).unwrap();
+        assert_eq!(detected.version, "1.18.2");
+        prepare_launch_jar(&fabric, &detected).unwrap();
+        assert!(fabric.join("vanilla-server.jar").is_file());
+        assert!(!fabric.join("fabric-server-launch.jar").exists());
+        assert_eq!(
+            std::fs::read_to_string(fabric.join("fabric-server-launcher.properties")).unwrap(),
+            "serverJarPath=vanilla-server.jar\n"
+        );
+
+        let launcher = temp_dir.path().join("launcher");
+        std::fs::create_dir_all(&launcher).unwrap();
+        write_jar(
+            &launcher.join("fabric-server-mc.1.20.1-loader.0.14.21-launcher.0.11.2.jar"),
+            None,
+        );
+        let detected = detect_server(&launcher).unwrap();
+        assert_eq!(detected.version, "1.20.1");
+        assert!(matches!(
+            detected.flavour,
+            Flavour::Fabric { loader_version: Some(FabricLoaderVersion(ref loader)), .. } if loader == "0.14.21"
+        ));
+
+        let quilt = temp_dir.path().join("quilt");
+        std::fs::create_dir_all(&quilt).unwrap();
+        write_jar(&quilt.join("server.jar"), Some("1.20.1"));
+        write_jar(&quilt.join("quilt-server-launch.jar"), None);
+        let detected = detect_server(&quilt).unwrap();
+        assert_eq!(detected.version, "1.20.1");
+        assert!(matches!(detected.flavour, Flavour::Quilt { .. }));
+        prepare_launch_jar(&quilt, &detected).unwrap();
+        assert!(quilt.join("quilt-server-launch.jar").is_file());
+
+        let paper = temp_dir.path().join("paper");
+        std::fs::create_dir_all(&paper).unwrap();
+        write_jar(&paper.join("paper-1.20.1-100.jar"), None);
+        let detected = detect_server(&paper).unwrap();
+        assert_eq!(detected.version, "1.20.1");
+        assert_eq!(detected.port, 25565);
+    }
+}
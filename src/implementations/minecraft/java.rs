@@ -0,0 +1,278 @@
+//! The Java runtimes servers run on: the Temurin JREs downloaded to the runtimes directory and the
+//! JVMs installed on the host.
+//!
+//! An instance runs on the java its `java_cmd` points to, the downloaded JRE its minecraft version
+//! needs unless it is pinned to another runtime. The runtime is checked before the server starts,
+//! one too old otherwise fails with a class file version error in the console.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    traits::t_configurable::manifest::ConfigurableValue,
+};
+
+use super::{
+    configurable::CmdArgSetting, download_jre, download_jre_if_missing, path_to_bundled_java,
+    util::java_major_version, MinecraftInstance,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum JavaRuntimeSource {
+    /// downloaded by lodestone to the runtimes directory
+    Bundled,
+    /// installed on the host
+    System,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct JavaRuntime {
+    pub java: PathBuf,
+    pub major_version: u64,
+    pub source: JavaRuntimeSource,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct InstanceJavaRuntime {
+    /// the major version the minecraft version of the instance needs
+    pub required_major_version: u64,
+    pub java: PathBuf,
+    /// `None` if the java doesn't run
+    pub major_version: Option<u64>,
+    /// whether the instance runs on another runtime than the downloaded one for its version
+    pub pinned: bool,
+}
+
+fn java_binary_name() -> String {
+    format!("java{}", std::env::consts::EXE_SUFFIX)
+}
+
+/// The JREs downloaded to the runtimes directory, named `jre<major version>`
+pub async fn bundled_runtimes(path_to_runtimes: &Path) -> Vec<JavaRuntime> {
+    let mut runtimes = Vec::new();
+    let mut entries = match tokio::fs::read_dir(path_to_runtimes.join("java")).await {
+        Ok(entries) => entries,
+        Err(_) => return runtimes,
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let major_version = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("jre"))
+            .and_then(|major_version| major_version.parse().ok())
+        {
+            Some(major_version) => major_version,
+            None => continue,
+        };
+        let java = path_to_bundled_java(path_to_runtimes, major_version);
+        if java.with_file_name(java_binary_name()).exists() {
+            runtimes.push(JavaRuntime {
+                java,
+                major_version,
+                source: JavaRuntimeSource::Bundled,
+            });
+        }
+    }
+    runtimes.sort_by_key(|runtime| runtime.major_version);
+    runtimes
+}
+
+/// Where JVMs are commonly installed on this platform, each holding one JVM per directory
+fn jvm_directories() -> Vec<(PathBuf, &'static str)> {
+    match std::env::consts::OS {
+        "linux" => vec![(PathBuf::from("/usr/lib/jvm"), "bin")],
+        "macos" => vec![(
+            PathBuf::from("/Library/Java/JavaVirtualMachines"),
+            "Contents/Home/bin",
+        )],
+        "windows" => vec![
+            (PathBuf::from(r"C:\Program Files\Java"), "bin"),
+            (PathBuf::from(r"C:\Program Files\Eclipse Adoptium"), "bin"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// The JVMs of the host: the one of `JAVA_HOME`, the one on the PATH and the ones in the usual
+/// install directories
+pub async fn system_runtimes() -> Vec<JavaRuntime> {
+    let mut candidates = Vec::new();
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        candidates.push(PathBuf::from(java_home).join("bin").join("java"));
+    }
+    candidates.push(PathBuf::from("java"));
+    for (directory, bin) in jvm_directories() {
+        if let Ok(mut entries) = tokio::fs::read_dir(&directory).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let java = entry.path().join(bin).join("java");
+                if java.with_file_name(java_binary_name()).exists() {
+                    candidates.push(java);
+                }
+            }
+        }
+    }
+    let mut runtimes: Vec<JavaRuntime> = Vec::new();
+    let mut seen = Vec::new();
+    for java in candidates {
+        // the same JVM is often both JAVA_HOME and a symlink in the install directory
+        let resolved = tokio::fs::canonicalize(&java)
+            .await
+            .unwrap_or_else(|_| java.clone());
+        if seen.contains(&resolved) {
+            continue;
+        }
+        seen.push(resolved);
+        if let Some(major_version) = java_major_version(&java).await {
+            runtimes.push(JavaRuntime {
+                java,
+                major_version,
+                source: JavaRuntimeSource::System,
+            });
+        }
+    }
+    runtimes
+}
+
+pub async fn detect_runtimes(path_to_runtimes: &Path) -> Vec<JavaRuntime> {
+    let mut runtimes = bundled_runtimes(path_to_runtimes).await;
+    runtimes.extend(system_runtimes().await);
+    runtimes
+}
+
+/// Download the Temurin JRE `major_version` unless it already is
+pub async fn install_runtime(
+    path_to_runtimes: &Path,
+    major_version: u64,
+) -> Result<JavaRuntime, Error> {
+    let java = path_to_bundled_java(path_to_runtimes, major_version);
+    if !java.with_file_name(java_binary_name()).exists()
+        && !download_jre(major_version, path_to_runtimes, &|_| {}).await?
+    {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!(
+                "There is no JRE {} to download for {}/{}",
+                major_version,
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            ),
+        });
+    }
+    Ok(JavaRuntime {
+        java,
+        major_version,
+        source: JavaRuntimeSource::Bundled,
+    })
+}
+
+/// Check that `java` runs and can run a server needing java `required_major_version`, returns
+/// its major version
+pub async fn check_compatibility(java: &Path, required_major_version: u64) -> Result<u64, Error> {
+    let major_version = java_major_version(java).await.ok_or_else(|| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(
+            "Could not run {}, pin the instance to another Java runtime",
+            java.display()
+        ),
+    })?;
+    if major_version < required_major_version {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "This version of minecraft needs Java {} or newer, {} is Java {}. Pin the instance to a newer Java runtime",
+                required_major_version,
+                java.display(),
+                major_version
+            ),
+        });
+    }
+    Ok(major_version)
+}
+
+impl MinecraftInstance {
+    pub async fn java_runtime(&self) -> InstanceJavaRuntime {
+        let (java_cmd, required_major_version) = {
+            let config = self.config.lock().await;
+            (config.java_cmd.clone(), config.jre_major_version)
+        };
+        let bundled = path_to_bundled_java(&self.path_to_runtimes, required_major_version);
+        let java = java_cmd.map_or_else(|| bundled.clone(), PathBuf::from);
+        InstanceJavaRuntime {
+            required_major_version,
+            major_version: java_major_version(&java).await,
+            pinned: java != bundled,
+            java,
+        }
+    }
+
+    /// Run the server on `java`, or on the downloaded JRE its version needs if `None`, which is
+    /// downloaded if missing. Takes effect on the next start
+    pub async fn pin_java_runtime(
+        &mut self,
+        java: Option<PathBuf>,
+    ) -> Result<InstanceJavaRuntime, Error> {
+        let (version, required_major_version) = {
+            let config = self.config.lock().await;
+            (config.version.clone(), config.jre_major_version)
+        };
+        let java = match java {
+            Some(java) => {
+                check_compatibility(&java, required_major_version).await?;
+                java
+            }
+            None => {
+                download_jre_if_missing(&version, &self.path_to_runtimes, &|_| {})
+                    .await?
+                    .java
+            }
+        };
+        let java_cmd = java.to_string_lossy().to_string();
+        self.config.lock().await.java_cmd = Some(java_cmd.clone());
+        self.configurable_manifest
+            .lock()
+            .await
+            .update_setting_value(
+                CmdArgSetting::get_section_id(),
+                CmdArgSetting::JavaCmd(Default::default()).get_identifier(),
+                ConfigurableValue::String(java_cmd),
+            )?;
+        self.write_config_to_file().await?;
+        Ok(self.java_runtime().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bundled_runtimes, JavaRuntimeSource};
+    use crate::implementations::minecraft::path_to_bundled_java;
+
+    #[tokio::test]
+    async fn test_bundled_runtimes() {
+        let path_to_runtimes = tempfile::tempdir().unwrap();
+        for major_version in [17, 8] {
+            let java = path_to_bundled_java(path_to_runtimes.path(), major_version);
+            std::fs::create_dir_all(java.parent().unwrap()).unwrap();
+            std::fs::write(java.with_file_name(super::java_binary_name()), "").unwrap();
+        }
+        // an unpacked archive that wasn't renamed yet
+        std::fs::create_dir_all(path_to_runtimes.path().join("java/jdk-17.0.7+7-jre")).unwrap();
+        let runtimes = bundled_runtimes(path_to_runtimes.path()).await;
+        assert_eq!(
+            runtimes
+                .iter()
+                .map(|runtime| runtime.major_version)
+                .collect::<Vec<_>>(),
+            vec![8, 17]
+        );
+        assert!(runtimes
+            .iter()
+            .all(|runtime| runtime.source == JavaRuntimeSource::Bundled));
+    }
+}
@@ -1,6 +1,8 @@
 use fancy_regex::Regex;
 use lazy_static::lazy_static;
 
+use crate::console_matchers::{CompiledMatcher, ConsoleMatchKind, ConsolePipeline};
+
 pub struct PlayerMessage {
     pub player: String,
     pub message: String,
@@ -37,39 +39,111 @@ pub fn parse_player_msg(msg: &str) -> Option<PlayerMessage> {
     }
 }
 
-pub fn parse_player_joined(system_msg: &str) -> Option<String> {
+/// The name and address of a player logging in, e.g.
+/// `Steve[/203.0.113.7:53412] logged in with entity id 42 at (0.5, 64.0, 0.5)`
+pub fn parse_player_login(system_msg: &str) -> Option<(String, String)> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) joined the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
-            None
-        }
-    } else {
-        None
+        static ref RE: Regex = Regex::new(r"^(\S+)\[(.+)\] logged in with entity id").unwrap();
     }
+    let cap = RE.captures(system_msg).ok()??;
+    Some((
+        cap.get(1)?.as_str().to_string(),
+        cap.get(2)?.as_str().to_string(),
+    ))
 }
 
-pub fn parse_player_left(system_msg: &str) -> Option<String> {
+/// The address and reason of a connection the server dropped, e.g.
+/// `Disconnecting Steve (/203.0.113.7:53412): You are not white-listed on this server!` or
+/// `com.mojang.authlib.GameProfile@1b2c[id=<null>,name=Steve] (/203.0.113.7:53412) lost connection: Failed to verify username!`
+pub fn parse_disconnection(system_msg: &str) -> Option<(String, String)> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) left the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
-            None
-        }
-    } else {
-        None
+        static ref RE: Regex = Regex::new(r"\((/[^)\s]+)\)(?: lost connection)?: (.+)$").unwrap();
     }
+    let cap = RE.captures(system_msg).ok()??;
+    Some((
+        cap.get(1)?.as_str().to_string(),
+        cap.get(2)?.as_str().trim().to_string(),
+    ))
 }
 
-pub fn parse_server_started(system_msg: &str) -> bool {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r#"Done \(.+\)!"#).unwrap();
+pub fn parse_out_of_memory_error(line: &str) -> bool {
+    line.contains("java.lang.OutOfMemoryError")
+}
+
+/// The matchers every Minecraft server runs its output through, on top of the startup, join and
+/// leave messages of its console profile. Both the vanilla `[12:00:00] [Server thread/WARN]: `
+/// and the Paper `[12:00:00 WARN]: ` prefixes are understood
+pub fn builtin_console_pipeline() -> ConsolePipeline {
+    let matcher = |kind: ConsoleMatchKind, pattern: &str| {
+        CompiledMatcher::new(kind.as_str(), kind, pattern).expect("builtin matchers are valid")
+    };
+    ConsolePipeline::new(vec![
+        matcher(
+            ConsoleMatchKind::Advancement,
+            r"\]: (?P<player>[^\s<]+) has (?:made the advancement|reached the goal|completed the challenge) \[(?P<advancement>.+)\]$",
+        ),
+        matcher(
+            ConsoleMatchKind::Warning,
+            r"^\[(?:[^\]]+\] \[[^\]]+/|[^\]]+ )WARN\]: (?P<message>.+)$",
+        ),
+        matcher(
+            ConsoleMatchKind::Error,
+            r"^\[(?:[^\]]+\] \[[^\]]+/|[^\]]+ )(?:ERROR|FATAL)\]: (?P<message>.+)$",
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::builtin_console_pipeline;
+    use crate::console_matchers::ConsoleMatchKind;
+
+    #[test]
+    fn test_builtin_console_pipeline() {
+        let pipeline = builtin_console_pipeline();
+        let kinds = |line: &str| {
+            pipeline
+                .parse(line)
+                .into_iter()
+                .map(|m| m.kind)
+                .collect::<Vec<_>>()
+        };
+
+        let advancement = pipeline
+            .parse("[12:00:00] [Server thread/INFO]: Steve has made the advancement [Stone Age]\n");
+        assert_eq!(advancement.len(), 1);
+        assert_eq!(advancement[0].kind, ConsoleMatchKind::Advancement);
+        assert_eq!(advancement[0].named_groups["player"], "Steve");
+        assert_eq!(advancement[0].named_groups["advancement"], "Stone Age");
+        assert_eq!(
+            kinds(
+                "[12:00:00] [Server thread/INFO]: Steve has completed the challenge [Arbalistic]"
+            ),
+            vec![ConsoleMatchKind::Advancement]
+        );
+
+        let warning = pipeline.parse("[12:00:00] [Server thread/WARN]: Can't keep up!");
+        assert_eq!(warning[0].kind, ConsoleMatchKind::Warning);
+        assert_eq!(warning[0].named_groups["message"], "Can't keep up!");
+        assert_eq!(
+            kinds("[12:00:00 WARN]: Can't keep up!"),
+            vec![ConsoleMatchKind::Warning]
+        );
+        assert_eq!(
+            kinds("[12:00:00] [main/ERROR]: Failed to load properties"),
+            vec![ConsoleMatchKind::Error]
+        );
+        assert_eq!(
+            kinds("[12:00:00 FATAL]: Encountered an unexpected exception"),
+            vec![ConsoleMatchKind::Error]
+        );
+
+        // a player can't fake an advancement or a warning through the chat
+        assert!(kinds(
+            "[12:00:00] [Server thread/INFO]: <Steve> Alex has made the advancement [Stone Age]"
+        )
+        .is_empty());
+        assert!(kinds("[12:00:00] [Server thread/INFO]: <Steve> [main/ERROR]: oops").is_empty());
+        assert!(kinds("[12:00:00] [Server thread/INFO]: Steve joined the game").is_empty());
     }
-    RE.is_match(system_msg).unwrap()
 }
@@ -12,6 +12,7 @@ use crate::{
     error::Error,
     events::{CausedBy, EventInner},
     macro_executor::{self, MacroPID, SpawnResult, WorkerOptionGenerator},
+    macro_sandbox::{require_capability, MacroCapability, MacroGrant},
     traits::{
         t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
         t_server::TServer,
@@ -22,6 +23,7 @@ use super::MinecraftInstance;
 
 #[op]
 async fn send_stdin(state: Rc<RefCell<OpState>>, cmd: String) -> Result<(), anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::SendCommand)?;
     let instance = state.borrow().borrow::<MinecraftInstance>().clone();
     instance.send_command(&cmd, CausedBy::Unknown).await?;
     Ok(())
@@ -29,6 +31,7 @@ async fn send_stdin(state: Rc<RefCell<OpState>>, cmd: String) -> Result<(), anyh
 
 #[op]
 async fn send_rcon(state: Rc<RefCell<OpState>>, cmd: String) -> Result<String, anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::SendCommand)?;
     let instance = state.borrow().borrow::<MinecraftInstance>().clone();
     let ret = instance.send_rcon(&cmd).await?;
     Ok(ret)
@@ -39,6 +42,7 @@ async fn on_event(
     state: Rc<RefCell<OpState>>,
     event: String,
 ) -> Result<Option<String>, anyhow::Error> {
+    require_capability(&state.borrow(), MacroCapability::ReadEvents)?;
     let instance = state.borrow().borrow::<MinecraftInstance>().clone();
     let mut event_rx = instance.event_broadcaster.subscribe();
     if event == "playerMessage" {
@@ -256,6 +260,7 @@ impl TMacro for MinecraftInstance {
         let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
             .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
 
+        let grant = self.macro_grant(name).await?;
         let main_worker_generator = MinecraftMainWorkerGenerator::new(self.clone());
         let SpawnResult { macro_pid: pid, .. } = self
             .macro_executor
@@ -264,7 +269,7 @@ impl TMacro for MinecraftInstance {
                 args,
                 caused_by,
                 Box::new(main_worker_generator),
-                None,
+                Some(grant),
                 Some(self.uuid.clone()),
                 None,
             )
@@ -290,4 +295,25 @@ impl TMacro for MinecraftInstance {
         self.macro_executor.abort_macro(pid)?;
         Ok(())
     }
+
+    async fn macro_grant(&self, name: &str) -> Result<MacroGrant, Error> {
+        Ok(self
+            .config
+            .lock()
+            .await
+            .macro_grants
+            .get(name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_macro_grant(&mut self, name: &str, grant: MacroGrant) -> Result<(), Error> {
+        grant.validate()?;
+        self.config
+            .lock()
+            .await
+            .macro_grants
+            .insert(name.to_string(), grant);
+        self.write_config_to_file().await
+    }
 }
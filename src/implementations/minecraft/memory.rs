@@ -0,0 +1,212 @@
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use sysinfo::SystemExt;
+use ts_rs::TS;
+
+use crate::{error::Error, reservation::DEFAULT_MEMORY_HEADROOM};
+
+use super::{
+    mods::{list_mods, mods_dir_name, ModInfo},
+    FlavourKind, MinecraftInstance,
+};
+
+/// heap of a server without mods, in megabytes
+const BASE_HEAP: u32 = 2048;
+/// heap of a modded server before its mods are counted
+const BASE_MODDED_HEAP: u32 = 3072;
+/// heap per mod, in megabytes
+const HEAP_PER_MOD: u32 = 48;
+/// heap per library or API mod, they add little content of their own
+const HEAP_PER_LIBRARY_MOD: u32 = 16;
+/// the largest heap recommended, past it garbage collection pauses outweigh the extra room
+const MAX_RECOMMENDED_HEAP: u32 = 16384;
+/// heaps under this share of the recommendation are likely to run out of memory
+const UNDERSIZED_HEAP_RATIO: f64 = 0.75;
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MemoryRecommendation {
+    /// in megabytes
+    pub min_ram: u32,
+    /// in megabytes
+    pub max_ram: u32,
+    /// total memory of the host, in megabytes
+    pub host_memory: u64,
+    /// problems with the recommendation or with the heap it was checked against
+    pub warnings: Vec<String>,
+}
+
+/// Total memory of the host, in megabytes
+pub fn host_memory() -> u64 {
+    let mut sys = sysinfo::System::new();
+    sys.refresh_memory();
+    sys.total_memory() / 1024 / 1024
+}
+
+fn is_library(info: &ModInfo) -> bool {
+    let id = info
+        .metadata
+        .id
+        .as_deref()
+        .unwrap_or_default()
+        .to_lowercase();
+    id.ends_with("lib") || id.ends_with("api") || id.contains("library")
+}
+
+fn round_up_to_512(megabytes: u32) -> u32 {
+    (megabytes + 511) / 512 * 512
+}
+
+/// Estimate the heap a server needs from its enabled mods, capped to what the host can spare
+pub fn recommend_heap(
+    flavour: &FlavourKind,
+    mods: &[ModInfo],
+    host_memory: u64,
+) -> MemoryRecommendation {
+    let modded = matches!(
+        flavour,
+        FlavourKind::Fabric | FlavourKind::Forge | FlavourKind::Quilt
+    );
+    let wanted = if modded {
+        let mods_heap: u32 = mods
+            .iter()
+            .filter(|info| info.enabled)
+            .map(|info| {
+                if is_library(info) {
+                    HEAP_PER_LIBRARY_MOD
+                } else {
+                    HEAP_PER_MOD
+                }
+            })
+            .sum();
+        round_up_to_512(BASE_MODDED_HEAP + mods_heap).min(MAX_RECOMMENDED_HEAP)
+    } else {
+        BASE_HEAP
+    };
+    let usable = host_memory
+        .saturating_sub(DEFAULT_MEMORY_HEADROOM)
+        .min(u32::MAX as u64) as u32;
+    let mut warnings = Vec::new();
+    let max_ram = if wanted > usable {
+        warnings.push(format!(
+            "The server needs about {wanted} MB of heap but the host can only spare {usable} MB, expect it to run out of memory"
+        ));
+        // leave the heap at least something to work with
+        usable.max(1024)
+    } else {
+        wanted
+    };
+    MemoryRecommendation {
+        // modded servers allocate most of their heap while loading, so start with all of it
+        min_ram: if modded { max_ram } else { max_ram.min(1024) },
+        max_ram,
+        host_memory,
+        warnings,
+    }
+}
+
+/// Problems with running a server with the given heap, `recommended` being what
+/// `recommend_heap` suggests for it
+pub fn check_heap(min_ram: u32, max_ram: u32, recommended: u32, host_memory: u64) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if min_ram > max_ram {
+        warnings.push(format!(
+            "The minimum RAM ({min_ram} MB) is greater than the maximum RAM ({max_ram} MB), the server will not start"
+        ));
+    }
+    if max_ram as u64 >= host_memory {
+        warnings.push(format!(
+            "The maximum RAM ({max_ram} MB) is more than the host has ({host_memory} MB), the server will be killed once it uses it"
+        ));
+    } else if max_ram as u64 > host_memory.saturating_sub(DEFAULT_MEMORY_HEADROOM) {
+        warnings.push(format!(
+            "The maximum RAM ({max_ram} MB) leaves less than {DEFAULT_MEMORY_HEADROOM} MB to the rest of the host"
+        ));
+    }
+    if (max_ram as f64) < recommended as f64 * UNDERSIZED_HEAP_RATIO {
+        warnings.push(format!(
+            "The maximum RAM ({max_ram} MB) is well below the recommended {recommended} MB, the server is likely to run out of memory"
+        ));
+    }
+    warnings
+}
+
+impl MinecraftInstance {
+    /// The heap recommended for the installed mods, with warnings about the configured heap
+    pub async fn memory_recommendation(&self) -> Result<MemoryRecommendation, Error> {
+        let flavour = self.flavour().await;
+        let mods = match mods_dir_name(&flavour) {
+            Some(dir) => {
+                let mods_dir = self.path_to_instance.join(dir);
+                tokio::task::spawn_blocking(move || list_mods(&mods_dir))
+                    .await
+                    .context("Failed to list mods")??
+            }
+            None => Vec::new(),
+        };
+        let (min_ram, max_ram) = {
+            let config = self.config.lock().await;
+            (config.min_ram, config.max_ram)
+        };
+        let mut recommendation = recommend_heap(&FlavourKind::from(&flavour), &mods, host_memory());
+        recommendation.warnings.extend(check_heap(
+            min_ram,
+            max_ram,
+            recommendation.max_ram,
+            recommendation.host_memory,
+        ));
+        Ok(recommendation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_heap, recommend_heap};
+    use crate::implementations::minecraft::{
+        mods::{ModInfo, ModMetadata},
+        FlavourKind,
+    };
+
+    fn mod_info(id: &str, enabled: bool) -> ModInfo {
+        ModInfo {
+            file_name: format!("{id}.jar"),
+            enabled,
+            size: 0,
+            metadata: ModMetadata {
+                id: Some(id.to_string()),
+                ..Default::default()
+            },
+            compatible: None,
+        }
+    }
+
+    #[test]
+    fn test_recommend_heap() {
+        let vanilla = recommend_heap(&FlavourKind::Vanilla, &[], 16384);
+        assert_eq!((vanilla.min_ram, vanilla.max_ram), (1024, 2048));
+        assert!(vanilla.warnings.is_empty());
+
+        let mut mods: Vec<ModInfo> = (0..60)
+            .map(|i| mod_info(&format!("mod{i}"), true))
+            .collect();
+        mods.push(mod_info("cloth-api", true));
+        mods.push(mod_info("disabled", false));
+        // 3072 + 60 * 48 + 16 = 5968, rounded up
+        let modded = recommend_heap(&FlavourKind::Fabric, &mods, 16384);
+        assert_eq!((modded.min_ram, modded.max_ram), (6144, 6144));
+        assert!(modded.warnings.is_empty());
+
+        let small_host = recommend_heap(&FlavourKind::Forge, &mods, 4096);
+        assert_eq!(small_host.max_ram, 3072);
+        assert_eq!(small_host.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_check_heap() {
+        assert!(check_heap(2048, 6144, 6144, 16384).is_empty());
+        assert_eq!(check_heap(4096, 2048, 2048, 16384).len(), 1);
+        assert_eq!(check_heap(1024, 16384, 6144, 16384).len(), 1);
+        assert_eq!(check_heap(1024, 15872, 6144, 16384).len(), 1);
+        assert_eq!(check_heap(1024, 2048, 6144, 16384).len(), 1);
+    }
+}
@@ -1,24 +1,48 @@
+mod commands;
 pub mod configurable;
+pub mod console_profile;
+pub mod control_channel;
 pub mod fabric;
+pub mod first_run;
 mod forge;
-mod line_parser;
+pub mod game_metrics;
+pub mod hot_backup;
+pub mod import;
+pub mod java;
+pub mod line_parser;
 pub mod r#macro;
+pub mod memory;
+pub mod modpack;
+pub mod mods;
 mod paper;
 pub mod player;
+pub mod player_admin;
 mod players_manager;
+pub mod query;
+mod quilt;
 pub mod resource;
+pub mod scheduling;
 pub mod server;
+pub mod server_properties;
+pub mod shared_config;
+pub mod spark;
+pub mod status_ping;
 pub mod util;
 mod vanilla;
+pub mod version_cache;
+pub mod version_switch;
 pub mod versions;
+pub mod worlds;
 
+use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use enum_kinds::EnumKind;
 use indexmap::IndexMap;
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Stdio;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU32};
 use std::sync::Arc;
 use sysinfo::SystemExt;
 use tokio::io::AsyncWriteExt;
@@ -29,21 +53,28 @@ use tokio::sync::Mutex;
 use ::serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 
-use tracing::error;
+use tracing::{error, info, warn};
 
 use tokio;
 use ts_rs::TS;
 
-use crate::error::Error;
+use crate::advisories::{self, InstanceAdvisory};
+use crate::error::{Error, ErrorKind};
 use crate::event_broadcaster::EventBroadcaster;
 use crate::events::{Event, ProgressionEventID};
 use crate::macro_executor::{MacroExecutor, MacroPID};
+use crate::macro_sandbox::MacroGrant;
+use crate::network_usage::NetworkUsageTracker;
 use crate::prelude::path_to_binaries;
-use crate::traits::t_configurable::PathBuf;
+use crate::readiness::ReadinessTracker;
+use crate::traits::t_configurable::{
+    CrashRestartPolicy, DisplayMetadata, LaunchOverrides, PathBuf, PublicAddress, ResourceLimits,
+    TConfigurable,
+};
 
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SectionManifest,
-    SettingManifest, SetupManifest, SetupValue,
+    SectionManifestValue, SettingManifest, SettingManifestValue, SetupManifest, SetupValue,
 };
 
 use crate::traits::t_macro::TaskEntry;
@@ -52,16 +83,22 @@ use crate::traits::TInstance;
 use crate::types::{DotLodestoneConfig, InstanceUuid};
 use crate::util::{
     dont_spawn_terminal, download_file, format_byte, format_byte_download, unzip_file_async,
-    UnzipOption,
+    DownloadProgress, UnzipOption,
 };
 
+use self::commands::KnownCommands;
 use self::configurable::{CmdArgSetting, ServerPropertySetting};
-use self::fabric::get_fabric_minecraft_versions;
-use self::forge::get_forge_minecraft_versions;
-use self::paper::get_paper_minecraft_versions;
+use self::console_profile::ConsoleProfile;
+use self::control_channel::ControlChannel;
+use self::first_run::{eula_not_accepted, write_eula, EULA_URL};
+use self::game_metrics::GameMetricsCache;
 use self::players_manager::PlayersManager;
-use self::util::{get_jre_url, get_server_jar_url, read_properties_from_path};
-use self::vanilla::get_vanilla_minecraft_versions;
+use self::scheduling::ProcessScheduling;
+use self::util::{
+    detect_launch_target, find_system_java, get_jre_major_version, get_jre_url, get_server_jar_url,
+    jre_platforms, read_properties_from_path,
+};
+use self::version_cache::VersionList;
 
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
@@ -75,6 +112,9 @@ pub struct PaperBuildVersion(i64);
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
 #[ts(export)]
 pub struct ForgeBuildVersion(String);
+#[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
+#[ts(export)]
+pub struct QuiltLoaderVersion(String);
 
 /// A parameter for constructor of `MinecraftInstance`
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, EnumKind)]
@@ -93,6 +133,9 @@ pub enum Flavour {
     Forge {
         build_version: Option<ForgeBuildVersion>,
     },
+    Quilt {
+        loader_version: Option<QuiltLoaderVersion>,
+    },
 }
 
 impl From<FlavourKind> for Flavour {
@@ -110,6 +153,28 @@ impl From<FlavourKind> for Flavour {
             FlavourKind::Forge => Flavour::Forge {
                 build_version: None,
             },
+            FlavourKind::Quilt => Flavour::Quilt {
+                loader_version: None,
+            },
+        }
+    }
+}
+
+impl Flavour {
+    /// The pinned version of the mod loader, `None` for flavours without one
+    pub fn loader_version(&self) -> Option<String> {
+        match self {
+            Flavour::Fabric {
+                loader_version: Some(FabricLoaderVersion(version)),
+                ..
+            }
+            | Flavour::Forge {
+                build_version: Some(ForgeBuildVersion(version)),
+            }
+            | Flavour::Quilt {
+                loader_version: Some(QuiltLoaderVersion(version)),
+            } => Some(version.clone()),
+            _ => None,
         }
     }
 }
@@ -122,6 +187,7 @@ impl ToString for Flavour {
             Flavour::Paper { .. } => "paper".to_string(),
             Flavour::Spigot => "spigot".to_string(),
             Flavour::Forge { .. } => "forge".to_string(),
+            Flavour::Quilt { .. } => "quilt".to_string(),
         }
     }
 }
@@ -134,6 +200,7 @@ impl ToString for FlavourKind {
             FlavourKind::Paper => "paper".to_string(),
             FlavourKind::Spigot => "spigot".to_string(),
             FlavourKind::Forge => "forge".to_string(),
+            FlavourKind::Quilt => "quilt".to_string(),
         }
     }
 }
@@ -151,6 +218,8 @@ pub struct SetupConfig {
     pub auto_start: Option<bool>,
     pub restart_on_crash: Option<bool>,
     pub backup_period: Option<u32>,
+    /// the Minecraft EULA, no server is set up without it
+    pub accept_eula: bool,
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RestoreConfig {
@@ -168,6 +237,265 @@ pub struct RestoreConfig {
     pub backup_period: Option<u32>,
     pub jre_major_version: u64,
     pub has_started: bool,
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    #[serde(default)]
+    pub crash_restart_policy: CrashRestartPolicy,
+    #[serde(default)]
+    pub display: DisplayMetadata,
+    #[serde(default)]
+    pub public_address: PublicAddress,
+    #[serde(default)]
+    pub launch_overrides: LaunchOverrides,
+    /// detected when the instance is set up, instances set up before it was recorded detect it
+    /// on every start
+    #[serde(default)]
+    pub launch_target: Option<LaunchTarget>,
+    /// by macro name, macros without one run with the default grant
+    #[serde(default)]
+    pub macro_grants: HashMap<String, MacroGrant>,
+    #[serde(default)]
+    pub console_profile: ConsoleProfile,
+    #[serde(default)]
+    pub control_channel: ControlChannel,
+    #[serde(default)]
+    pub scheduling: ProcessScheduling,
+}
+
+/// What the JVM runs to start the server, paths are relative to the instance directory
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LaunchTarget {
+    /// `-jar <jar>`
+    Jar { jar: PathBuf },
+    /// `@<args file>`, the arguments written by the forge installer since 1.17
+    ArgsFile { args_file: PathBuf },
+}
+
+/// Whether `flavour` runs a mod loader whose version can be picked
+pub fn has_mod_loader(flavour: &FlavourKind) -> bool {
+    matches!(
+        flavour,
+        FlavourKind::Fabric | FlavourKind::Forge | FlavourKind::Quilt
+    )
+}
+
+/// The java binary of a JRE downloaded to the runtimes directory
+pub(crate) fn path_to_bundled_java(path_to_runtimes: &Path, jre_major_version: u64) -> PathBuf {
+    path_to_runtimes
+        .join("java")
+        .join(format!("jre{}", jre_major_version))
+        .join(if std::env::consts::OS == "macos" {
+            "Contents/Home/bin"
+        } else {
+            "bin"
+        })
+        .join("java")
+}
+
+/// The JRE an instance runs on
+pub(crate) struct Jre {
+    /// the major version the minecraft version needs
+    pub major_version: u64,
+    pub java: PathBuf,
+    pub downloaded: bool,
+}
+
+/// Download the JRE `version` needs unless it already is.
+///
+/// The JRE is looked for under each of the platforms of the host, and if Adoptium has none of
+/// them, like on hosts it doesn't build for, the java of the host is used instead.
+pub(crate) async fn download_jre_if_missing(
+    version: &str,
+    path_to_runtimes: &Path,
+    on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+) -> Result<Jre, Error> {
+    let jre_major_version = get_jre_major_version(version)
+        .await
+        .context("Could not get the Java version needed")?;
+    let path_to_jre = path_to_runtimes
+        .join("java")
+        .join(format!("jre{}", jre_major_version));
+    if path_to_jre.exists() {
+        return Ok(Jre {
+            major_version: jre_major_version,
+            java: path_to_bundled_java(path_to_runtimes, jre_major_version),
+            downloaded: false,
+        });
+    }
+    if download_jre(jre_major_version, path_to_runtimes, on_download).await? {
+        return Ok(Jre {
+            major_version: jre_major_version,
+            java: path_to_bundled_java(path_to_runtimes, jre_major_version),
+            downloaded: true,
+        });
+    }
+    match find_system_java(jre_major_version).await {
+        Some((java, system_major_version)) => {
+            warn!(
+                "No JRE {} to download for {}/{}, using Java {} at {}",
+                jre_major_version,
+                std::env::consts::OS,
+                std::env::consts::ARCH,
+                system_major_version,
+                java.display()
+            );
+            Ok(Jre {
+                major_version: jre_major_version,
+                java,
+                downloaded: false,
+            })
+        }
+        None => Err(eyre!(
+            "There is no JRE {} to download for {}/{}, install Java {} or newer and put it on the PATH or point JAVA_HOME to it",
+            jre_major_version,
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            jre_major_version
+        )
+        .into()),
+    }
+}
+
+/// Download the Temurin JRE `jre_major_version` to the runtimes directory, false if Adoptium
+/// builds it for none of the platforms of the host
+pub(crate) async fn download_jre(
+    jre_major_version: u64,
+    path_to_runtimes: &Path,
+    on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+) -> Result<bool, Error> {
+    let path_to_jre = path_to_runtimes
+        .join("java")
+        .join(format!("jre{}", jre_major_version));
+    let client = reqwest::Client::new();
+    for platform in jre_platforms() {
+        let url = get_jre_url(jre_major_version, platform);
+        if let Ok(response) = client.head(&url).send().await {
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                info!(
+                    "No JRE {} for {}/{}, trying the next platform",
+                    jre_major_version, platform.0, platform.1
+                );
+                continue;
+            }
+        }
+        let downloaded = download_file(
+            &url,
+            &path_to_runtimes.join("java"),
+            None,
+            on_download,
+            true,
+        )
+        .await?;
+
+        let unzipped_content = unzip_file_async(
+            &downloaded,
+            UnzipOption::ToDir(path_to_runtimes.join("java")),
+        )
+        .await?;
+        if unzipped_content.len() != 1 {
+            return Err(eyre!(
+                "Expected only one file in the JRE archive, got {}",
+                unzipped_content.len()
+            )
+            .into());
+        }
+
+        tokio::fs::remove_file(&downloaded).await.context(format!(
+            "Could not remove downloaded JRE file {}",
+            downloaded.display()
+        ))?;
+
+        tokio::fs::rename(unzipped_content.iter().last().unwrap(), &path_to_jre)
+            .await
+            .context(format!(
+                "Could not rename JRE directory {}",
+                unzipped_content.iter().last().unwrap().display()
+            ))?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// The name the jar of `flavour` is downloaded as, which is an installer for some flavours
+pub(crate) fn server_jar_name(flavour: &Flavour) -> &'static str {
+    match flavour {
+        Flavour::Forge { .. } => "forge-installer.jar",
+        Flavour::Quilt { .. } => "quilt-installer.jar",
+        _ => "server.jar",
+    }
+}
+
+/// Run the installer `flavour` is set up with, if it has one, and remove it
+pub(crate) async fn run_server_installer(
+    path_to_instance: &Path,
+    jre: &Path,
+    flavour: &Flavour,
+    version: &str,
+) -> Result<(), Error> {
+    if let Flavour::Forge { .. } = flavour {
+        if !dont_spawn_terminal(
+            Command::new(jre)
+                .arg("-jar")
+                .arg(path_to_instance.join("forge-installer.jar"))
+                .arg("--installServer")
+                .arg(path_to_instance)
+                .current_dir(path_to_instance),
+        )
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .stdin(Stdio::null())
+        .spawn()
+        .context("Failed to start forge-installer.jar")?
+        .wait()
+        .await
+        .context("forge-installer.jar failed")?
+        .success()
+        {
+            return Err(eyre!("Failed to install forge server").into());
+        }
+
+        tokio::fs::write(
+            path_to_instance.join("user_jvm_args.txt"),
+            "# Generated by Lodestone\n# This file is ignored by Lodestone\n# Please set arguments using Lodestone",
+        )
+        .await
+        .context("Could not create user_jvm_args.txt")?;
+    }
+    if let Flavour::Quilt {
+        loader_version: Some(QuiltLoaderVersion(loader_version)),
+    } = flavour
+    {
+        // the installer also downloads the vanilla server.jar the launcher runs
+        if !dont_spawn_terminal(
+            Command::new(jre)
+                .arg("-jar")
+                .arg(path_to_instance.join("quilt-installer.jar"))
+                .arg("install")
+                .arg("server")
+                .arg(version)
+                .arg(loader_version)
+                .arg("--download-server")
+                .arg(format!("--install-dir={}", path_to_instance.display()))
+                .current_dir(path_to_instance),
+        )
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .stdin(Stdio::null())
+        .spawn()
+        .context("Failed to start quilt-installer.jar")?
+        .wait()
+        .await
+        .context("quilt-installer.jar failed")?
+        .success()
+        {
+            return Err(eyre!("Failed to install quilt server").into());
+        }
+    }
+    for installer in ["forge-installer.jar", "quilt-installer.jar"] {
+        let _ = tokio::fs::remove_file(path_to_instance.join(installer)).await;
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -190,6 +518,8 @@ pub struct MinecraftInstance {
     // variables which can be changed at runtime
     auto_start: Arc<AtomicBool>,
     restart_on_crash: Arc<AtomicBool>,
+    /// restarts after crashes in a row, reset by a stable run or a start that is not a restart
+    crash_restarts: Arc<AtomicU32>,
     backup_period: Option<u32>,
     process: Arc<Mutex<Option<Child>>>,
     stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
@@ -200,6 +530,11 @@ pub struct MinecraftInstance {
     rcon_conn: Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>>,
     macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
     pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
+    network_usage_tracker: Arc<Mutex<NetworkUsageTracker>>,
+    game_metrics: Arc<Mutex<GameMetricsCache>>,
+    readiness: ReadinessTracker,
+    known_commands: Arc<Mutex<KnownCommands>>,
+    hot_backup_lock: Arc<Mutex<()>>,
 }
 
 #[tokio::test]
@@ -214,13 +549,15 @@ async fn test_setup_manifest() {
 impl MinecraftInstance {
     pub async fn setup_manifest(flavour: &FlavourKind) -> Result<SetupManifest, Error> {
         let versions = match flavour {
-            FlavourKind::Vanilla => get_vanilla_minecraft_versions().await,
-            FlavourKind::Fabric => get_fabric_minecraft_versions().await,
-            FlavourKind::Paper => get_paper_minecraft_versions().await,
+            FlavourKind::Vanilla => version_cache::versions(VersionList::VanillaVersions).await,
+            FlavourKind::Fabric => version_cache::versions(VersionList::FabricVersions).await,
+            FlavourKind::Paper => version_cache::versions(VersionList::PaperVersions).await,
             FlavourKind::Spigot => todo!(),
-            FlavourKind::Forge => get_forge_minecraft_versions().await,
+            FlavourKind::Forge => version_cache::versions(VersionList::ForgeVersions).await,
+            FlavourKind::Quilt => version_cache::versions(VersionList::QuiltVersions).await,
         }
         .context("Failed to get minecraft versions")?;
+        let recommendation = memory::recommend_heap(flavour, &[], memory::host_memory());
 
         let version_setting = SettingManifest::new_value_with_type(
             "version".to_string(),
@@ -251,8 +588,8 @@ impl MinecraftInstance {
             "min_ram".to_string(),
             "Minimum RAM".to_string(),
             "The minimum amount of RAM to allocate to the server".to_string(),
-            ConfigurableValue::UnsignedInteger(1024),
-            Some(ConfigurableValue::UnsignedInteger(1024)),
+            ConfigurableValue::UnsignedInteger(recommendation.min_ram),
+            Some(ConfigurableValue::UnsignedInteger(recommendation.min_ram)),
             false,
             true,
         );
@@ -261,8 +598,8 @@ impl MinecraftInstance {
             "max_ram".to_string(),
             "Maximum RAM".to_string(),
             "The maximum amount of RAM to allocate to the server".to_string(),
-            ConfigurableValue::UnsignedInteger(2048),
-            Some(ConfigurableValue::UnsignedInteger(2048)),
+            ConfigurableValue::UnsignedInteger(recommendation.max_ram),
+            Some(ConfigurableValue::UnsignedInteger(recommendation.max_ram)),
             false,
             true,
         );
@@ -278,10 +615,35 @@ impl MinecraftInstance {
             true,
         );
 
+        let accept_eula_setting = SettingManifest::new_required_value(
+            "accept_eula".to_string(),
+            "Accept the Minecraft EULA".to_string(),
+            format!("Whether you agree to the Minecraft EULA ({EULA_URL}), the server can't be set up without it"),
+            ConfigurableValue::Boolean(false),
+            Some(ConfigurableValue::Boolean(false)),
+            false,
+            true,
+        );
+
         let mut section_1_map = IndexMap::new();
 
         section_1_map.insert("version".to_string(), version_setting);
+        if has_mod_loader(flavour) {
+            // the versions depend on the minecraft version, see `loader_versions`
+            let loader_version_setting = SettingManifest::new_optional_value(
+                "loader_version".to_string(),
+                "Loader Version".to_string(),
+                "The version of the mod loader, the latest stable one if empty".to_string(),
+                None,
+                ConfigurableValueType::String { regex: None },
+                None,
+                false,
+                true,
+            );
+            section_1_map.insert("loader_version".to_string(), loader_version_setting);
+        }
         section_1_map.insert("port".to_string(), port_setting);
+        section_1_map.insert("accept_eula".to_string(), accept_eula_setting);
 
         let mut section_2_map = IndexMap::new();
 
@@ -315,6 +677,33 @@ impl MinecraftInstance {
         })
     }
 
+    /// The versions of the mod loader of `flavour` supporting the minecraft `version`, newest
+    /// first
+    pub async fn loader_versions(
+        flavour: &FlavourKind,
+        version: &str,
+    ) -> Result<Vec<String>, Error> {
+        match flavour {
+            FlavourKind::Fabric => version_cache::versions(VersionList::FabricLoaderVersions).await,
+            FlavourKind::Forge => {
+                version_cache::versions(VersionList::ForgeBuilds {
+                    version: version.to_string(),
+                })
+                .await
+            }
+            FlavourKind::Quilt => {
+                version_cache::versions(VersionList::QuiltLoaderVersions {
+                    version: version.to_string(),
+                })
+                .await
+            }
+            FlavourKind::Vanilla | FlavourKind::Paper | FlavourKind::Spigot => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} servers have no mod loader", flavour.to_string()),
+            }),
+        }
+    }
+
     pub async fn construct_setup_config(
         setup_value: SetupValue,
         flavour: FlavourKind,
@@ -323,6 +712,16 @@ impl MinecraftInstance {
             .await?
             .validate_setup_value(&setup_value)?;
 
+        // a missing value is a client that didn't ask
+        let accept_eula = setup_value
+            .get_unique_setting("accept_eula")
+            .and_then(|setting| setting.get_value())
+            .and_then(|value| value.try_as_boolean().ok())
+            .unwrap_or(false);
+        if !accept_eula {
+            return Err(eula_not_accepted());
+        }
+
         // ALL of the following unwraps are safe because we just validated the manifest value
         let description = setup_value.description.clone();
 
@@ -359,6 +758,12 @@ impl MinecraftInstance {
             .unwrap()
             .try_as_unsigned_integer()
             .unwrap();
+        if min_ram > max_ram {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Minimum RAM cannot be greater than maximum RAM"),
+            });
+        }
 
         let cmd_args: Vec<String> = setup_value
             .get_unique_setting("cmd_args")
@@ -370,6 +775,52 @@ impl MinecraftInstance {
             .map(|s| s.to_string())
             .collect();
 
+        let loader_version = setup_value
+            .get_unique_setting("loader_version")
+            .and_then(|setting| setting.get_value())
+            .map(|v| v.try_as_string().unwrap().trim().to_string())
+            .filter(|v| !v.is_empty());
+        let flavour = match loader_version {
+            Some(loader_version) => {
+                // forge builds are prefixed with the minecraft version, which can be left out
+                let loader_version = match flavour {
+                    FlavourKind::Forge if !loader_version.starts_with(&format!("{version}-")) => {
+                        format!("{version}-{loader_version}")
+                    }
+                    _ => loader_version,
+                };
+                if !Self::loader_versions(&flavour, version)
+                    .await?
+                    .contains(&loader_version)
+                {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "{} {} does not support minecraft {}",
+                            flavour.to_string(),
+                            loader_version,
+                            version
+                        ),
+                    });
+                }
+                match flavour {
+                    FlavourKind::Fabric => Flavour::Fabric {
+                        loader_version: Some(FabricLoaderVersion(loader_version)),
+                        installer_version: None,
+                    },
+                    FlavourKind::Forge => Flavour::Forge {
+                        build_version: Some(ForgeBuildVersion(loader_version)),
+                    },
+                    FlavourKind::Quilt => Flavour::Quilt {
+                        loader_version: Some(QuiltLoaderVersion(loader_version)),
+                    },
+                    // the setting is only in the manifest of flavours with a mod loader
+                    _ => flavour.into(),
+                }
+            }
+            None => flavour.into(),
+        };
+
         Ok(SetupConfig {
             name,
             description,
@@ -378,13 +829,89 @@ impl MinecraftInstance {
             min_ram: Some(min_ram),
             max_ram: Some(max_ram),
             cmd_args,
-            flavour: flavour.into(),
+            flavour,
             auto_start: Some(setup_value.auto_start),
             restart_on_crash: Some(setup_value.restart_on_crash),
             backup_period: None,
+            accept_eula,
         })
     }
 
+    pub async fn flavour(&self) -> Flavour {
+        self.config.lock().await.flavour.clone()
+    }
+
+    /// The -Xms and -Xmx the server is started with, in megabytes
+    pub async fn heap_sizes(&self) -> (u32, u32) {
+        let config = self.config.lock().await;
+        util::heap_sizes(
+            config.min_ram,
+            config.max_ram,
+            config.resource_limits.memory_limit,
+        )
+    }
+
+    /// The flavour and setup value that would set up an instance with the same configuration,
+    /// the inverse of `construct_setup_config`
+    pub async fn setup_value(&self) -> (FlavourKind, SetupValue) {
+        let config = self.config.lock().await.clone();
+        let mut section_1 = IndexMap::new();
+        section_1.insert(
+            "version".to_string(),
+            SettingManifestValue::new(Some(ConfigurableValue::Enum(config.version))),
+        );
+        if has_mod_loader(&FlavourKind::from(&config.flavour)) {
+            section_1.insert(
+                "loader_version".to_string(),
+                SettingManifestValue::new(
+                    config
+                        .flavour
+                        .loader_version()
+                        .map(ConfigurableValue::String),
+                ),
+            );
+        }
+        section_1.insert(
+            "port".to_string(),
+            SettingManifestValue::new(Some(ConfigurableValue::UnsignedInteger(config.port))),
+        );
+        let mut section_2 = IndexMap::new();
+        section_2.insert(
+            "min_ram".to_string(),
+            SettingManifestValue::new(Some(ConfigurableValue::UnsignedInteger(config.min_ram))),
+        );
+        section_2.insert(
+            "max_ram".to_string(),
+            SettingManifestValue::new(Some(ConfigurableValue::UnsignedInteger(config.max_ram))),
+        );
+        let cmd_args = config.cmd_args.join(" ");
+        section_2.insert(
+            "cmd_args".to_string(),
+            SettingManifestValue::new(
+                (!cmd_args.trim().is_empty()).then_some(ConfigurableValue::String(cmd_args)),
+            ),
+        );
+        let mut setting_sections = IndexMap::new();
+        setting_sections.insert(
+            "section_1".to_string(),
+            SectionManifestValue::new(section_1),
+        );
+        setting_sections.insert(
+            "section_2".to_string(),
+            SectionManifestValue::new(section_2),
+        );
+        (
+            FlavourKind::from(&config.flavour),
+            SetupValue {
+                name: config.name,
+                description: Some(config.description),
+                auto_start: config.auto_start,
+                restart_on_crash: config.restart_on_crash,
+                setting_sections,
+            },
+        )
+    }
+
     fn init_configurable_manifest(
         restore_config: &RestoreConfig,
         java_cmd: String,
@@ -436,8 +963,10 @@ impl MinecraftInstance {
         event_broadcaster: EventBroadcaster,
         macro_executor: MacroExecutor,
     ) -> Result<MinecraftInstance, Error> {
+        if !config.accept_eula {
+            return Err(eula_not_accepted());
+        }
         let path_to_config = path_to_instance.join(".lodestone_minecraft_config.json");
-        let path_to_eula = path_to_instance.join("eula.txt");
         let path_to_macros = path_to_instance.join("macros");
         let path_to_resources = path_to_instance.join("resources");
         let path_to_properties = path_to_instance.join("server.properties");
@@ -448,7 +977,7 @@ impl MinecraftInstance {
         // Step 1: Create Directories
         event_broadcaster.send(Event::new_progression_event_update(
             progression_event_id,
-            "1/4: Creating directories",
+            "1/4: Creating directories and accepting the EULA",
             1.0,
         ));
         tokio::fs::create_dir_all(&path_to_instance)
@@ -457,7 +986,6 @@ impl MinecraftInstance {
             .and(tokio::fs::create_dir_all(&path_to_resources.join("mods")).await)
             .and(tokio::fs::create_dir_all(&path_to_resources.join("worlds")).await)
             .and(tokio::fs::create_dir_all(&path_to_resources.join("defaults")).await)
-            .and(tokio::fs::write(&path_to_eula, "#generated by Lodestone\neula=true").await)
             .and(
                 tokio::fs::write(&path_to_properties, format!("server-port={}", config.port)).await,
             )
@@ -466,69 +994,30 @@ impl MinecraftInstance {
                 error!("{e}");
                 e
             })?;
+        write_eula(&path_to_instance).await?;
 
         // Step 2: Download JRE
-        let (url, jre_major_version) = get_jre_url(config.version.as_str())
-            .await
-            .context("Could not get JRE URL")?;
-        if !path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .exists()
-        {
-            let downloaded = download_file(
-                &url,
-                &path_to_runtimes.join("java"),
-                None,
-                {
-                    let event_broadcaster = event_broadcaster.clone();
-                    &move |dl| {
-                        if let Some(total) = dl.total {
-                            event_broadcaster.send(Event::new_progression_event_update(
-                                progression_event_id,
-                                format!(
-                                    "2/4: Downloading JRE {}",
-                                    format_byte_download(dl.downloaded, total)
-                                ),
-                                (dl.step as f64 / total as f64) * 4.0,
-                            ));
-                        }
-                    }
-                },
-                true,
-            )
-            .await?;
-
-            let unzipped_content = unzip_file_async(
-                &downloaded,
-                UnzipOption::ToDir(path_to_runtimes.join("java")),
-            )
-            .await?;
-            if unzipped_content.len() != 1 {
-                return Err(eyre!(
-                    "Expected only one file in the JRE archive, got {}",
-                    unzipped_content.len()
-                )
-                .into());
+        let Jre {
+            major_version: jre_major_version,
+            java: jre,
+            downloaded,
+        } = download_jre_if_missing(config.version.as_str(), &path_to_runtimes, {
+            let event_broadcaster = event_broadcaster.clone();
+            &move |dl| {
+                if let Some(total) = dl.total {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "2/4: Downloading JRE {}",
+                            format_byte_download(dl.downloaded, total)
+                        ),
+                        (dl.step as f64 / total as f64) * 4.0,
+                    ));
+                }
             }
-
-            tokio::fs::remove_file(&downloaded).await.context(format!(
-                "Could not remove downloaded JRE file {}",
-                downloaded.display()
-            ))?;
-
-            tokio::fs::rename(
-                unzipped_content.iter().last().unwrap(),
-                path_to_runtimes
-                    .join("java")
-                    .join(format!("jre{}", jre_major_version)),
-            )
-            .await
-            .context(format!(
-                "Could not rename JRE directory {}",
-                unzipped_content.iter().last().unwrap().display()
-            ))?;
-        } else {
+        })
+        .await?;
+        if !downloaded {
             event_broadcaster.send(Event::new_progression_event_update(
                 progression_event_id,
                 "2/4: JRE already downloaded",
@@ -549,10 +1038,7 @@ impl MinecraftInstance {
                     )
                 }
             })?;
-        let jar_name = match flavour {
-            Flavour::Forge { .. } => "forge-installer.jar",
-            _ => "server.jar",
-        };
+        let jar_name = server_jar_name(&flavour);
 
         download_file(
             jar_url.as_str(),
@@ -589,51 +1075,22 @@ impl MinecraftInstance {
             true,
         )
         .await?;
-        let jre = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
-        // Step 3 (part 2): Forge Setup
-        if let Flavour::Forge { .. } = flavour.clone() {
+        // Step 3 (part 2): Forge or Quilt Setup
+        let installing = match flavour {
+            Flavour::Forge { .. } => Some("3/4: Installing Forge Server"),
+            Flavour::Quilt { .. } => Some("3/4: Installing Quilt Server"),
+            _ => None,
+        };
+        if let Some(installing) = installing {
             event_broadcaster.send(Event::new_progression_event_update(
                 progression_event_id,
-                "3/4: Installing Forge Server",
+                installing,
                 1.0,
             ));
-
-            if !dont_spawn_terminal(
-                Command::new(&jre)
-                    .arg("-jar")
-                    .arg(&path_to_instance.join("forge-installer.jar"))
-                    .arg("--installServer")
-                    .arg(&path_to_instance)
-                    .current_dir(&path_to_instance),
-            )
-            .stderr(Stdio::null())
-            .stdout(Stdio::null())
-            .stdin(Stdio::null())
-            .spawn()
-            .context("Failed to start forge-installer.jar")?
-            .wait()
-            .await
-            .context("forge-installer.jar failed")?
-            .success()
-            {
-                return Err(eyre!("Failed to install forge server").into());
-            }
-
-            tokio::fs::write(
-                &path_to_instance.join("user_jvm_args.txt"),
-                "# Generated by Lodestone\n# This file is ignored by Lodestone\n# Please set arguments using Lodestone",
-            )
-            .await
-            .context("Could not create user_jvm_args.txt")?;
         }
+        run_server_installer(&path_to_instance, &jre, &flavour, &config.version).await?;
+        let launch_target =
+            detect_launch_target(&path_to_instance, &flavour, &config.version).await?;
 
         // Step 4: Finishing Up
         event_broadcaster.send(Event::new_progression_event_update(
@@ -657,6 +1114,16 @@ impl MinecraftInstance {
             jre_major_version,
             has_started: false,
             java_cmd: Some(jre.to_string_lossy().to_string()),
+            resource_limits: ResourceLimits::default(),
+            crash_restart_policy: CrashRestartPolicy::default(),
+            display: DisplayMetadata::default(),
+            public_address: PublicAddress::default(),
+            launch_overrides: LaunchOverrides::default(),
+            launch_target: Some(launch_target),
+            macro_grants: HashMap::new(),
+            console_profile: ConsoleProfile::default(),
+            control_channel: ControlChannel::default(),
+            scheduling: ProcessScheduling::default(),
         };
         // create config file
         tokio::fs::write(
@@ -707,15 +1174,11 @@ impl MinecraftInstance {
             .await
             .expect("failed to write to server.properties");
         };
-        let java_path = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", restore_config.jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
+        // the java of the host if there was no JRE to download for it
+        let java_path = match &restore_config.java_cmd {
+            Some(java_cmd) => PathBuf::from(java_cmd),
+            None => path_to_bundled_java(&path_to_runtimes, restore_config.jre_major_version),
+        };
 
         let configurable_manifest = Arc::new(Mutex::new(Self::init_configurable_manifest(
             &restore_config,
@@ -728,6 +1191,7 @@ impl MinecraftInstance {
             creation_time: dot_lodestone_config.creation_time(),
             auto_start: Arc::new(AtomicBool::new(restore_config.auto_start)),
             restart_on_crash: Arc::new(AtomicBool::new(restore_config.restart_on_crash)),
+            crash_restarts: Arc::new(AtomicU32::new(0)),
             backup_period: restore_config.backup_period,
             players_manager: Arc::new(Mutex::new(PlayersManager::new(
                 event_broadcaster.clone(),
@@ -749,6 +1213,11 @@ impl MinecraftInstance {
             configurable_manifest,
             macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
             pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
+            network_usage_tracker: Arc::new(Mutex::new(NetworkUsageTracker::new())),
+            game_metrics: Arc::new(Mutex::new(GameMetricsCache::default())),
+            readiness: ReadinessTracker::default(),
+            known_commands: Arc::new(Mutex::new(KnownCommands::default())),
+            hot_backup_lock: Arc::new(Mutex::new(())),
         };
         instance
             .read_properties()
@@ -907,6 +1376,32 @@ impl MinecraftInstance {
             .context("Failed to send rcon command")?;
         Ok(a)
     }
+
+    /// Add a flag to the java command line, takes effect on the next start
+    pub async fn add_jvm_flag(&mut self, flag: &str) -> Result<(), Error> {
+        let mut cmd_args = self.config.lock().await.cmd_args.clone();
+        if cmd_args.iter().any(|arg| arg == flag) {
+            return Ok(());
+        }
+        cmd_args.retain(|arg| !arg.is_empty());
+        cmd_args.push(flag.to_string());
+        self.update_configurable(
+            CmdArgSetting::get_section_id(),
+            CmdArgSetting::Args(Default::default()).get_identifier(),
+            ConfigurableValue::String(cmd_args.join(" ")),
+        )
+        .await
+    }
 }
 
-impl TInstance for MinecraftInstance {}
+#[async_trait]
+impl TInstance for MinecraftInstance {
+    async fn advisories(&self) -> Vec<InstanceAdvisory> {
+        let config = self.config.lock().await;
+        advisories::for_instance(
+            &config.flavour.clone().into(),
+            &config.version,
+            &config.cmd_args,
+        )
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+
+use crate::{
+    download_manager::{Checksum, DownloadManager, DownloadRequest},
+    error::Error,
+    util::{download_file, scoped_join_win_safe},
+};
+
+use super::{
+    mods::{ModInfo, ModMetadata},
+    FabricLoaderVersion, Flavour, ForgeBuildVersion, QuiltLoaderVersion,
+};
+
+/// loaders of the Modrinth versions that can be set up
+static SUPPORTED_LOADERS: [&str; 2] = ["fabric", "forge"];
+
+#[derive(Deserialize)]
+struct ModrinthFile {
+    url: String,
+    filename: String,
+    primary: bool,
+}
+
+#[derive(Deserialize)]
+struct ModrinthVersion {
+    loaders: Vec<String>,
+    files: Vec<ModrinthFile>,
+}
+
+impl ModrinthVersion {
+    fn mrpack(&self) -> Option<&ModrinthFile> {
+        self.files
+            .iter()
+            .filter(|file| file.filename.ends_with(".mrpack"))
+            .max_by_key(|file| file.primary)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct PackFileEnv {
+    server: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct PackFileHashes {
+    sha512: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PackFile {
+    path: String,
+    hashes: PackFileHashes,
+    env: Option<PackFileEnv>,
+    downloads: Vec<String>,
+    file_size: u64,
+}
+
+/// `modrinth.index.json`, at the root of a `.mrpack`
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PackIndex {
+    format_version: u32,
+    name: String,
+    version_id: String,
+    files: Vec<PackFile>,
+    dependencies: HashMap<String, String>,
+}
+
+/// What a pack link points to
+pub enum ModpackSource {
+    /// a Modrinth pack, set up from scratch with the files it lists
+    Modrinth(Modpack),
+    /// a ready to run server, imported like an uploaded archive
+    ServerPack { url: String, file_name: String },
+}
+
+/// A resolved Modrinth pack, downloaded to a temporary directory
+pub struct Modpack {
+    pub name: String,
+    pub version: String,
+    pub minecraft_version: String,
+    pub flavour: Flavour,
+    files: Vec<PackFile>,
+    path_to_mrpack: PathBuf,
+    _tmp: tempfile::TempDir,
+}
+
+/// The Minecraft version and the flavour a pack depends on
+fn flavour_from_dependencies(
+    dependencies: &HashMap<String, String>,
+) -> Result<(String, Flavour), Error> {
+    let minecraft_version = dependencies
+        .get("minecraft")
+        .ok_or_else(|| Error::bad_request("The pack does not depend on Minecraft".to_string()))?
+        .clone();
+    let flavour = if let Some(loader) = dependencies.get("fabric-loader") {
+        Flavour::Fabric {
+            loader_version: Some(FabricLoaderVersion(loader.clone())),
+            installer_version: None,
+        }
+    } else if let Some(forge) = dependencies.get("forge") {
+        Flavour::Forge {
+            build_version: Some(ForgeBuildVersion(format!("{minecraft_version}-{forge}"))),
+        }
+    } else if let Some(loader) = dependencies.get("quilt-loader") {
+        Flavour::Quilt {
+            loader_version: Some(QuiltLoaderVersion(loader.clone())),
+        }
+    } else if let Some(loader) = dependencies.keys().find(|key| key.as_str() != "minecraft") {
+        return Err(Error::bad_request(format!(
+            "Packs using {loader} are not supported"
+        )));
+    } else {
+        Flavour::Vanilla
+    };
+    Ok((minecraft_version, flavour))
+}
+
+async fn get_modrinth_versions(project: &str) -> Result<Vec<ModrinthVersion>, Error> {
+    Ok(reqwest::get(format!(
+        "https://api.modrinth.com/v2/project/{project}/version"
+    ))
+    .await
+    .context("Failed to reach Modrinth")?
+    .error_for_status()
+    .context("Failed to find the pack on Modrinth")?
+    .json()
+    .await
+    .context("Failed to parse the Modrinth versions")?)
+}
+
+async fn get_modrinth_version(project: &str, version: &str) -> Result<ModrinthVersion, Error> {
+    Ok(reqwest::get(format!(
+        "https://api.modrinth.com/v2/project/{project}/version/{version}"
+    ))
+    .await
+    .context("Failed to reach Modrinth")?
+    .error_for_status()
+    .context("Failed to find the version on Modrinth")?
+    .json()
+    .await
+    .context("Failed to parse the Modrinth version")?)
+}
+
+/// Resolve a Modrinth or CurseForge pack link, a link to a pack without a version picks its
+/// latest version with a supported loader
+pub async fn resolve_modpack_url(url: &str) -> Result<ModpackSource, Error> {
+    let parsed =
+        url::Url::parse(url).map_err(|_| Error::bad_request(format!("Invalid url {url}")))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let segments: Vec<&str> = parsed
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let file_name = segments
+        .last()
+        .map(|name| name.replace("%20", " ").replace("%2B", "+"))
+        .unwrap_or_default();
+    let mrpack_url = match (host, segments.as_slice()) {
+        ("cdn.modrinth.com", _) if file_name.ends_with(".mrpack") => url.to_string(),
+        // https://modrinth.com/modpack/<project>/version/<version id or number>
+        ("modrinth.com" | "www.modrinth.com", ["modpack", project, "version", version]) => {
+            get_modrinth_version(project, version)
+                .await?
+                .mrpack()
+                .ok_or_else(|| {
+                    Error::bad_request("The Modrinth version has no .mrpack".to_string())
+                })?
+                .url
+                .clone()
+        }
+        // https://modrinth.com/modpack/<project>
+        ("modrinth.com" | "www.modrinth.com", ["modpack", project]) => {
+            // versions are listed newest first
+            get_modrinth_versions(project)
+                .await?
+                .iter()
+                .filter(|version| {
+                    version
+                        .loaders
+                        .iter()
+                        .any(|loader| SUPPORTED_LOADERS.contains(&loader.as_str()))
+                })
+                .find_map(|version| version.mrpack())
+                .ok_or_else(|| {
+                    Error::bad_request(format!(
+                        "No version of the pack uses a supported loader ({})",
+                        SUPPORTED_LOADERS.join(", ")
+                    ))
+                })?
+                .url
+                .clone()
+        }
+        ("modrinth.com" | "www.modrinth.com", _) => {
+            return Err(Error::bad_request("Link to a Modrinth modpack".to_string()))
+        }
+        ("edge.forgecdn.net" | "mediafilez.forgecdn.net", _) if file_name.ends_with(".zip") => {
+            return Ok(ModpackSource::ServerPack {
+                url: url.to_string(),
+                file_name,
+            })
+        }
+        // the CurseForge API requires an API key, so only the CDN links can be used
+        ("www.curseforge.com" | "curseforge.com" | "legacy.curseforge.com", _) => {
+            return Err(Error::bad_request(
+                "Use the direct download link of the server pack of the CurseForge file"
+                    .to_string(),
+            ))
+        }
+        _ => {
+            return Err(Error::bad_request(
+                "Only Modrinth and CurseForge links are supported".to_string(),
+            ))
+        }
+    };
+    Ok(ModpackSource::Modrinth(
+        Modpack::download(&mrpack_url).await?,
+    ))
+}
+
+fn read_index(path_to_mrpack: &Path) -> Result<PackIndex, Error> {
+    let mut archive = zip::ZipArchive::new(
+        File::open(path_to_mrpack).context("Failed to open the downloaded pack")?,
+    )
+    .context("The pack is not a valid .mrpack")?;
+    let mut content = String::new();
+    archive
+        .by_name("modrinth.index.json")
+        .context("The pack has no modrinth.index.json")?
+        .read_to_string(&mut content)
+        .context("Failed to read modrinth.index.json")?;
+    let index: PackIndex =
+        serde_json::from_str(&content).context("Failed to parse modrinth.index.json")?;
+    if index.format_version != 1 {
+        return Err(Error::bad_request(format!(
+            "Unsupported pack format version {}",
+            index.format_version
+        )));
+    }
+    Ok(index)
+}
+
+/// Copy `overrides` then `server-overrides` of the pack into the instance
+fn extract_overrides(path_to_mrpack: &Path, path_to_instance: &Path) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(
+        File::open(path_to_mrpack).context("Failed to open the downloaded pack")?,
+    )
+    .context("The pack is not a valid .mrpack")?;
+    for prefix in ["overrides", "server-overrides"] {
+        for i in 0..archive.len() {
+            let mut entry = archive
+                .by_index(i)
+                .context("Failed to read an entry of the pack")?;
+            let relative_path = match entry
+                .enclosed_name()
+                .and_then(|path| path.strip_prefix(prefix).ok())
+            {
+                Some(path) if !entry.is_dir() && !path.as_os_str().is_empty() => path.to_path_buf(),
+                _ => continue,
+            };
+            let dest = scoped_join_win_safe(path_to_instance, &relative_path)?;
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)
+                    .context(format!("Failed to create directory {}", parent.display()))?;
+            }
+            let mut file =
+                File::create(&dest).context(format!("Failed to create file {}", dest.display()))?;
+            std::io::copy(&mut entry, &mut file)
+                .context(format!("Failed to extract {}", dest.display()))?;
+        }
+    }
+    Ok(())
+}
+
+impl PackFile {
+    fn is_needed_by_server(&self) -> bool {
+        self.env
+            .as_ref()
+            .map_or(true, |env| env.server != "unsupported")
+    }
+}
+
+impl Modpack {
+    async fn download(url: &str) -> Result<Self, Error> {
+        crate::util::fs::create_dir_all(crate::prelude::path_to_tmp()).await?;
+        let tmp = tempfile::tempdir_in(crate::prelude::path_to_tmp())
+            .context("Failed to create temporary directory for the pack")?;
+        let path_to_mrpack =
+            download_file(url, tmp.path(), Some("pack.mrpack"), &|_| {}, true).await?;
+        let index = {
+            let path_to_mrpack = path_to_mrpack.clone();
+            tokio::task::spawn_blocking(move || read_index(&path_to_mrpack))
+                .await
+                .context("Failed to read the pack")??
+        };
+        let (minecraft_version, flavour) = flavour_from_dependencies(&index.dependencies)?;
+        Ok(Self {
+            name: index.name,
+            version: index.version_id,
+            minecraft_version,
+            flavour,
+            files: index
+                .files
+                .into_iter()
+                .filter(PackFile::is_needed_by_server)
+                .collect(),
+            path_to_mrpack,
+            _tmp: tmp,
+        })
+    }
+
+    /// The mods of the pack, enough to recommend a heap before they are downloaded
+    pub fn mods(&self) -> Vec<ModInfo> {
+        self.files
+            .iter()
+            .filter(|file| file.path.ends_with(".jar"))
+            .map(|file| ModInfo {
+                file_name: file.path.rsplit('/').next().unwrap_or_default().to_string(),
+                enabled: true,
+                size: file.file_size,
+                metadata: ModMetadata::default(),
+                compatible: None,
+            })
+            .collect()
+    }
+
+    /// Download the files of the pack into the instance and apply its overrides
+    pub async fn install(
+        &self,
+        path_to_instance: &Path,
+        on_file: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<(), Error> {
+        for file in &self.files {
+            on_file(&file.path);
+            let dest = scoped_join_win_safe(path_to_instance, &file.path)?;
+            let (dir, file_name) = match (dest.parent(), dest.file_name()) {
+                (Some(dir), Some(file_name)) => (dir, file_name.to_string_lossy().to_string()),
+                _ => {
+                    return Err(Error::bad_request(format!(
+                        "Invalid file path {}",
+                        file.path
+                    )))
+                }
+            };
+            let url = file
+                .downloads
+                .first()
+                .ok_or_else(|| Error::bad_request(format!("{} has no download", file.path)))?;
+            DownloadManager::global()
+                .download(
+                    DownloadRequest::new(url, dir)
+                        .name(&file_name)
+                        .checksum(Some(Checksum::Sha512(file.hashes.sha512.clone()))),
+                    &|_| {},
+                )
+                .await?;
+        }
+        let path_to_mrpack = self.path_to_mrpack.clone();
+        let path_to_instance = path_to_instance.to_path_buf();
+        tokio::task::spawn_blocking(move || extract_overrides(&path_to_mrpack, &path_to_instance))
+            .await
+            .context("Failed to extract the overrides of the pack")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{flavour_from_dependencies, PackIndex};
+    use crate::implementations::minecraft::{
+        FabricLoaderVersion, Flavour, ForgeBuildVersion, QuiltLoaderVersion,
+    };
+
+    fn dependencies(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_flavour_from_dependencies() {
+        assert_eq!(
+            flavour_from_dependencies(&dependencies(&[
+                ("minecraft", "1.20.1"),
+                ("fabric-loader", "0.14.21")
+            ]))
+            .unwrap(),
+            (
+                "1.20.1".to_string(),
+                Flavour::Fabric {
+                    loader_version: Some(FabricLoaderVersion("0.14.21".to_string())),
+                    installer_version: None,
+                }
+            )
+        );
+        assert_eq!(
+            flavour_from_dependencies(&dependencies(&[
+                ("minecraft", "1.19.2"),
+                ("forge", "43.2.0")
+            ]))
+            .unwrap(),
+            (
+                "1.19.2".to_string(),
+                Flavour::Forge {
+                    build_version: Some(ForgeBuildVersion("1.19.2-43.2.0".to_string())),
+                }
+            )
+        );
+        assert_eq!(
+            flavour_from_dependencies(&dependencies(&[
+                ("minecraft", "1.20.1"),
+                ("quilt-loader", "0.19.1")
+            ]))
+            .unwrap(),
+            (
+                "1.20.1".to_string(),
+                Flavour::Quilt {
+                    loader_version: Some(QuiltLoaderVersion("0.19.1".to_string())),
+                }
+            )
+        );
+        assert!(flavour_from_dependencies(&dependencies(&[
+            ("minecraft", "1.20.1"),
+            ("neoforge", "20.4.80")
+        ]))
+        .is_err());
+        assert!(flavour_from_dependencies(&dependencies(&[("forge", "43.2.0")])).is_err());
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let index: PackIndex = serde_json::from_str(
+            r#"{
+                "formatVersion": 1,
+                "game": "minecraft",
+                "versionId": "1.0.0",
+                "name": "Test Pack",
+                "files": [
+                    {
+                        "path": "mods/lithium.jar",
+                        "hashes": { "sha1": "a", "sha512": "b" },
+                        "env": { "client": "required", "server": "required" },
+                        "downloads": ["https://cdn.modrinth.com/lithium.jar"],
+                        "fileSize": 10
+                    },
+                    {
+                        "path": "mods/sodium.jar",
+                        "hashes": { "sha1": "c", "sha512": "d" },
+                        "env": { "client": "required", "server": "unsupported" },
+                        "downloads": ["https://cdn.modrinth.com/sodium.jar"],
+                        "fileSize": 20
+                    }
+                ],
+                "dependencies": { "minecraft": "1.20.1", "fabric-loader": "0.14.21" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(index.name, "Test Pack");
+        let server_files: Vec<_> = index
+            .files
+            .iter()
+            .filter(|file| file.is_needed_by_server())
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(server_files, vec!["mods/lithium.jar"]);
+    }
+}
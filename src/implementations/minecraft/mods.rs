@@ -0,0 +1,608 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    config_editor::{parse_config, ConfigFormat, ConfigValue},
+    download_manager::{Checksum, DownloadManager, DownloadRequest},
+    error::{Error, ErrorKind},
+};
+
+use super::{Flavour, MinecraftInstance};
+
+/// suffix appended to the jar of a disabled mod, the convention used by most launchers
+static DISABLED_SUFFIX: &str = ".disabled";
+
+/// where the metadata of a mod was read from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum ModLoader {
+    Fabric,
+    Forge,
+    Quilt,
+    Bukkit,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+pub struct ModMetadata {
+    pub loader: Option<ModLoader>,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ModInfo {
+    /// name of the jar, without the suffix of disabled mods
+    pub file_name: String,
+    pub enabled: bool,
+    pub size: u64,
+    pub metadata: ModMetadata,
+    /// whether the loader of the instance can load the mod, `None` if its loader is unknown
+    #[serde(default)]
+    pub compatible: Option<bool>,
+}
+
+impl ModInfo {
+    fn for_flavour(mut self, flavour: &Flavour) -> Self {
+        self.compatible = self
+            .metadata
+            .loader
+            .map(|loader| supported_loaders(flavour).contains(&loader));
+        self
+    }
+}
+
+/// The loaders whose mods or plugins `flavour` can load
+pub fn supported_loaders(flavour: &Flavour) -> &'static [ModLoader] {
+    match flavour {
+        Flavour::Vanilla => &[],
+        Flavour::Fabric { .. } => &[ModLoader::Fabric],
+        // quilt keeps compatibility with fabric mods
+        Flavour::Quilt { .. } => &[ModLoader::Quilt, ModLoader::Fabric],
+        Flavour::Forge { .. } => &[ModLoader::Forge],
+        Flavour::Paper { .. } | Flavour::Spigot => &[ModLoader::Bukkit],
+    }
+}
+
+/// The folder mods or plugins are loaded from, `None` for flavours without mod support
+pub fn mods_dir_name(flavour: &Flavour) -> Option<&'static str> {
+    match flavour {
+        Flavour::Vanilla => None,
+        Flavour::Fabric { .. } | Flavour::Forge { .. } | Flavour::Quilt { .. } => Some("mods"),
+        Flavour::Paper { .. } | Flavour::Spigot => Some("plugins"),
+    }
+}
+
+fn config_get<'a>(value: &'a ConfigValue, key: &str) -> Option<&'a ConfigValue> {
+    match value {
+        ConfigValue::Map(entries) => entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.value),
+        _ => None,
+    }
+}
+
+fn config_string(value: Option<&ConfigValue>) -> Option<String> {
+    match value? {
+        ConfigValue::String(s) => Some(s.clone()),
+        ConfigValue::Integer(i) => Some(i.to_string()),
+        // debug formatting keeps the decimal point of versions like 1.0
+        ConfigValue::Float(f) => Some(format!("{f:?}")),
+        _ => None,
+    }
+}
+
+pub fn parse_fabric_metadata(content: &str) -> Option<ModMetadata> {
+    let json: serde_json::Value = serde_json::from_str(content).ok()?;
+    let string = |key: &str| json.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    let authors = json
+        .get("authors")
+        .and_then(|authors| authors.as_array())
+        .map(|authors| {
+            authors
+                .iter()
+                .filter_map(|author| {
+                    // an author is either a name or a person object
+                    author
+                        .as_str()
+                        .or_else(|| author.get("name").and_then(|name| name.as_str()))
+                        .map(str::to_string)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ModMetadata {
+        loader: Some(ModLoader::Fabric),
+        id: string("id"),
+        name: string("name"),
+        version: string("version"),
+        description: string("description"),
+        authors,
+    })
+}
+
+pub fn parse_quilt_metadata(content: &str) -> Option<ModMetadata> {
+    let json: serde_json::Value = serde_json::from_str(content).ok()?;
+    let loader = json.get("quilt_loader")?;
+    let string =
+        |value: Option<&serde_json::Value>| value.and_then(|v| v.as_str()).map(str::to_string);
+    let metadata = loader.get("metadata");
+    // contributors map names to their role
+    let authors = metadata
+        .and_then(|metadata| metadata.get("contributors"))
+        .and_then(|contributors| contributors.as_object())
+        .map(|contributors| contributors.keys().cloned().collect())
+        .unwrap_or_default();
+    Some(ModMetadata {
+        loader: Some(ModLoader::Quilt),
+        id: string(loader.get("id")),
+        name: string(metadata.and_then(|metadata| metadata.get("name"))),
+        version: string(loader.get("version")),
+        description: string(metadata.and_then(|metadata| metadata.get("description"))),
+        authors,
+    })
+}
+
+/// `manifest` is the content of `META-INF/MANIFEST.MF`, used when the version is taken from the jar
+pub fn parse_forge_metadata(content: &str, manifest: Option<&str>) -> Option<ModMetadata> {
+    let root = parse_config(ConfigFormat::Toml, content).ok()?.root;
+    let first_mod = match config_get(&root, "mods")? {
+        ConfigValue::List(mods) => mods.first()?,
+        _ => return None,
+    };
+    let field = |key: &str| config_string(config_get(first_mod, key));
+    let version = field("version").and_then(|version| {
+        if version == "${file.jarVersion}" {
+            manifest?.lines().find_map(|line| {
+                line.strip_prefix("Implementation-Version:")
+                    .map(|v| v.trim().to_string())
+            })
+        } else {
+            Some(version)
+        }
+    });
+    Some(ModMetadata {
+        loader: Some(ModLoader::Forge),
+        id: field("modId"),
+        name: field("displayName"),
+        version,
+        description: field("description").map(|d| d.trim().to_string()),
+        authors: field("authors")
+            .map(|authors| vec![authors])
+            .unwrap_or_default(),
+    })
+}
+
+pub fn parse_plugin_metadata(content: &str) -> Option<ModMetadata> {
+    let root = parse_config(ConfigFormat::Yaml, content).ok()?.root;
+    let field = |key: &str| config_string(config_get(&root, key));
+    let mut authors: Vec<String> = field("author").into_iter().collect();
+    if let Some(ConfigValue::List(list)) = config_get(&root, "authors") {
+        authors.extend(list.iter().filter_map(|author| config_string(Some(author))));
+    }
+    Some(ModMetadata {
+        loader: Some(ModLoader::Bukkit),
+        id: field("name"),
+        name: field("name"),
+        version: field("version"),
+        description: field("description"),
+        authors,
+    })
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Option<String> {
+    let mut file = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+/// Read the metadata of a mod or plugin jar, an unrecognised jar yields empty metadata
+pub fn read_mod_metadata(path: &Path) -> ModMetadata {
+    let mut archive = match File::open(path)
+        .ok()
+        .and_then(|file| zip::ZipArchive::new(file).ok())
+    {
+        Some(archive) => archive,
+        None => return ModMetadata::default(),
+    };
+    if let Some(content) = read_zip_entry(&mut archive, "quilt.mod.json") {
+        return parse_quilt_metadata(&content).unwrap_or_default();
+    }
+    if let Some(content) = read_zip_entry(&mut archive, "fabric.mod.json") {
+        return parse_fabric_metadata(&content).unwrap_or_default();
+    }
+    if let Some(content) = read_zip_entry(&mut archive, "META-INF/mods.toml") {
+        let manifest = read_zip_entry(&mut archive, "META-INF/MANIFEST.MF");
+        return parse_forge_metadata(&content, manifest.as_deref()).unwrap_or_default();
+    }
+    for plugin_file in ["paper-plugin.yml", "plugin.yml"] {
+        if let Some(content) = read_zip_entry(&mut archive, plugin_file) {
+            return parse_plugin_metadata(&content).unwrap_or_default();
+        }
+    }
+    ModMetadata::default()
+}
+
+/// Split a file name into the name of the jar and whether it is enabled, `None` if it is not a jar
+fn jar_name(file_name: &str) -> Option<(&str, bool)> {
+    match file_name.strip_suffix(DISABLED_SUFFIX) {
+        Some(name) if name.ends_with(".jar") => Some((name, false)),
+        Some(_) => None,
+        None if file_name.ends_with(".jar") => Some((file_name, true)),
+        None => None,
+    }
+}
+
+fn mod_info(path: &Path) -> Result<ModInfo, Error> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (name, enabled) = jar_name(&file_name)
+        .ok_or_else(|| Error::bad_request(format!("{file_name} is not a jar file")))?;
+    Ok(ModInfo {
+        file_name: name.to_string(),
+        enabled,
+        size: path
+            .metadata()
+            .context(format!("Failed to read metadata of {}", path.display()))?
+            .len(),
+        metadata: read_mod_metadata(path),
+        compatible: None,
+    })
+}
+
+pub fn list_mods(mods_dir: &Path) -> Result<Vec<ModInfo>, Error> {
+    if !mods_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(mods_dir)
+        .context(format!("Failed to read directory {}", mods_dir.display()))?
+    {
+        let path = entry
+            .context(format!("Failed to read directory {}", mods_dir.display()))?
+            .path();
+        if path.is_file()
+            && jar_name(&path.file_name().unwrap_or_default().to_string_lossy()).is_some()
+        {
+            ret.push(mod_info(&path)?);
+        }
+    }
+    ret.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(ret)
+}
+
+/// The current path of the mod named `file_name`, whether it is enabled or not
+pub(super) fn find_mod(mods_dir: &Path, file_name: &str) -> Result<PathBuf, Error> {
+    if !file_name.ends_with(".jar") || sanitize_filename::sanitize(file_name) != file_name {
+        return Err(Error::bad_request(format!(
+            "{file_name} is not a valid mod file name"
+        )));
+    }
+    let enabled = mods_dir.join(file_name);
+    let disabled = mods_dir.join(format!("{file_name}{DISABLED_SUFFIX}"));
+    if enabled.is_file() {
+        Ok(enabled)
+    } else if disabled.is_file() {
+        Ok(disabled)
+    } else {
+        Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Mod {file_name} not found"),
+        })
+    }
+}
+
+pub fn set_mod_enabled(mods_dir: &Path, file_name: &str, enabled: bool) -> Result<ModInfo, Error> {
+    let current = find_mod(mods_dir, file_name)?;
+    let target = if enabled {
+        mods_dir.join(file_name)
+    } else {
+        mods_dir.join(format!("{file_name}{DISABLED_SUFFIX}"))
+    };
+    if current != target {
+        std::fs::rename(&current, &target).context(format!(
+            "Failed to rename {} to {}",
+            current.display(),
+            target.display()
+        ))?;
+    }
+    mod_info(&target)
+}
+
+pub fn delete_mod(mods_dir: &Path, file_name: &str) -> Result<(), Error> {
+    let path = find_mod(mods_dir, file_name)?;
+    std::fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+    Ok(())
+}
+
+/// Hosts serving mod jars directly
+static DIRECT_DOWNLOAD_HOSTS: &[&str] = &[
+    "cdn.modrinth.com",
+    "edge.forgecdn.net",
+    "mediafilez.forgecdn.net",
+];
+
+#[derive(Deserialize)]
+pub(super) struct ModrinthFile {
+    pub url: String,
+    pub filename: String,
+    pub primary: bool,
+    /// by algorithm, e.g. `sha1`
+    #[serde(default)]
+    pub hashes: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ModrinthVersion {
+    pub files: Vec<ModrinthFile>,
+}
+
+impl ModrinthFile {
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.hashes
+            .get("sha512")
+            .map(|hash| Checksum::Sha512(hash.clone()))
+            .or_else(|| {
+                self.hashes
+                    .get("sha1")
+                    .map(|hash| Checksum::Sha1(hash.clone()))
+            })
+    }
+}
+
+impl ModrinthVersion {
+    pub fn primary_file(&self) -> Option<&ModrinthFile> {
+        self.files
+            .iter()
+            .find(|file| file.primary)
+            .or_else(|| self.files.first())
+    }
+}
+
+/// Resolve a Modrinth or CurseForge link to the download url and the file name of the jar, with
+/// its checksum when the host publishes it
+pub async fn resolve_mod_url(url: &str) -> Result<(String, String, Option<Checksum>), Error> {
+    let parsed =
+        url::Url::parse(url).map_err(|_| Error::bad_request(format!("Invalid url {url}")))?;
+    let host = parsed.host_str().unwrap_or_default();
+    let segments: Vec<&str> = parsed
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    if DIRECT_DOWNLOAD_HOSTS.contains(&host) {
+        let file_name = segments
+            .last()
+            .map(|name| name.replace("%20", " ").replace("%2B", "+"))
+            .ok_or_else(|| Error::bad_request(format!("{url} does not point to a file")))?;
+        return Ok((url.to_string(), file_name, None));
+    }
+    match (host, segments.as_slice()) {
+        // https://modrinth.com/<project type>/<project>/version/<version id or number>
+        ("modrinth.com" | "www.modrinth.com", [_, project, "version", version]) => {
+            let version: ModrinthVersion = reqwest::get(format!(
+                "https://api.modrinth.com/v2/project/{project}/version/{version}"
+            ))
+            .await
+            .context("Failed to reach Modrinth")?
+            .error_for_status()
+            .context("Failed to find the version on Modrinth")?
+            .json()
+            .await
+            .context("Failed to parse the Modrinth version")?;
+            let file = version.primary_file().ok_or_else(|| {
+                Error::bad_request("The Modrinth version has no files".to_string())
+            })?;
+            Ok((file.url.clone(), file.filename.clone(), file.checksum()))
+        }
+        ("modrinth.com" | "www.modrinth.com", _) => Err(Error::bad_request(
+            "Link to a specific version of the Modrinth project".to_string(),
+        )),
+        // the CurseForge API requires an API key, so only the CDN links can be used
+        ("www.curseforge.com" | "curseforge.com" | "legacy.curseforge.com", _) => Err(
+            Error::bad_request("Use the direct download link of the CurseForge file".to_string()),
+        ),
+        _ => Err(Error::bad_request(
+            "Only Modrinth and CurseForge links are supported".to_string(),
+        )),
+    }
+}
+
+impl MinecraftInstance {
+    pub async fn path_to_mods(&self) -> Result<PathBuf, Error> {
+        let flavour = self.flavour().await;
+        match mods_dir_name(&flavour) {
+            Some(dir) => Ok(self.path_to_instance.join(dir)),
+            None => Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("{} instances do not support mods", flavour.to_string()),
+            }),
+        }
+    }
+
+    pub async fn list_mods(&self) -> Result<Vec<ModInfo>, Error> {
+        let mods_dir = self.path_to_mods().await?;
+        let flavour = self.flavour().await;
+        Ok(tokio::task::spawn_blocking(move || list_mods(&mods_dir))
+            .await
+            .context("Failed to list mods")??
+            .into_iter()
+            .map(|info| info.for_flavour(&flavour))
+            .collect())
+    }
+
+    pub async fn install_mod_from_url(&self, url: &str) -> Result<ModInfo, Error> {
+        let mods_dir = self.path_to_mods().await?;
+        let (download_url, file_name, checksum) = resolve_mod_url(url).await?;
+        let file_name = sanitize_filename::sanitize(file_name);
+        if !file_name.ends_with(".jar") {
+            return Err(Error::bad_request(format!("{file_name} is not a jar file")));
+        }
+        let path = DownloadManager::global()
+            .download(
+                DownloadRequest::new(&download_url, &mods_dir)
+                    .name(&file_name)
+                    .overwrite_old(false)
+                    .checksum(checksum),
+                &|_| {},
+            )
+            .await?;
+        Ok(tokio::task::spawn_blocking(move || mod_info(&path))
+            .await
+            .context("Failed to read the installed mod")??
+            .for_flavour(&self.flavour().await))
+    }
+
+    pub async fn install_mod_from_file(
+        &self,
+        file_name: &str,
+        content: &[u8],
+    ) -> Result<ModInfo, Error> {
+        let mods_dir = self.path_to_mods().await?;
+        let file_name = sanitize_filename::sanitize(file_name);
+        if !file_name.ends_with(".jar") {
+            return Err(Error::bad_request(format!("{file_name} is not a jar file")));
+        }
+        if find_mod(&mods_dir, &file_name).is_ok() {
+            return Err(Error::bad_request(format!(
+                "Mod {file_name} is already installed"
+            )));
+        }
+        crate::util::fs::create_dir_all(&mods_dir).await?;
+        let path = mods_dir.join(&file_name);
+        crate::util::fs::write_all(&path, content).await?;
+        Ok(tokio::task::spawn_blocking(move || mod_info(&path))
+            .await
+            .context("Failed to read the installed mod")??
+            .for_flavour(&self.flavour().await))
+    }
+
+    pub async fn set_mod_enabled(&self, file_name: &str, enabled: bool) -> Result<ModInfo, Error> {
+        let mods_dir = self.path_to_mods().await?;
+        let file_name = file_name.to_string();
+        Ok(
+            tokio::task::spawn_blocking(move || set_mod_enabled(&mods_dir, &file_name, enabled))
+                .await
+                .context("Failed to update the mod")??
+                .for_flavour(&self.flavour().await),
+        )
+    }
+
+    pub async fn delete_mod(&self, file_name: &str) -> Result<(), Error> {
+        let mods_dir = self.path_to_mods().await?;
+        let file_name = file_name.to_string();
+        tokio::task::spawn_blocking(move || delete_mod(&mods_dir, &file_name))
+            .await
+            .context("Failed to delete the mod")?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        jar_name, list_mods, parse_fabric_metadata, parse_forge_metadata, parse_plugin_metadata,
+        parse_quilt_metadata, set_mod_enabled, ModLoader,
+    };
+    use crate::implementations::minecraft::Flavour;
+
+    #[test]
+    fn test_parse_metadata() {
+        let fabric = parse_fabric_metadata(
+            r#"{"id": "sodium", "name": "Sodium", "version": "0.4.10",
+                "authors": ["JellySquid", {"name": "IMS"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(fabric.loader, Some(ModLoader::Fabric));
+        assert_eq!(fabric.id.as_deref(), Some("sodium"));
+        assert_eq!(fabric.authors, vec!["JellySquid", "IMS"]);
+
+        let quilt = parse_quilt_metadata(
+            r#"{"schema_version": 1, "quilt_loader": {"id": "qsl", "version": "6.0.1",
+                "metadata": {"name": "QSL", "contributors": {"Ennui": "Owner"}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(quilt.loader, Some(ModLoader::Quilt));
+        assert_eq!(quilt.id.as_deref(), Some("qsl"));
+        assert_eq!(quilt.version.as_deref(), Some("6.0.1"));
+        assert_eq!(quilt.authors, vec!["Ennui"]);
+
+        let quilt_flavour = Flavour::Quilt {
+            loader_version: None,
+        };
+        let fabric_info = super::ModInfo {
+            file_name: "sodium.jar".to_string(),
+            enabled: true,
+            size: 0,
+            metadata: fabric.clone(),
+            compatible: None,
+        };
+        assert_eq!(
+            fabric_info.clone().for_flavour(&quilt_flavour).compatible,
+            Some(true)
+        );
+        assert_eq!(
+            fabric_info
+                .for_flavour(&Flavour::Forge {
+                    build_version: None
+                })
+                .compatible,
+            Some(false)
+        );
+
+        let forge = parse_forge_metadata(
+            "modLoader=\"javafml\"\n[[mods]]\nmodId=\"jei\"\nversion=\"${file.jarVersion}\"\ndisplayName=\"Just Enough Items\"\nauthors=\"mezz\"\n",
+            Some("Manifest-Version: 1.0\nImplementation-Version: 11.6.0\n"),
+        )
+        .unwrap();
+        assert_eq!(forge.id.as_deref(), Some("jei"));
+        assert_eq!(forge.name.as_deref(), Some("Just Enough Items"));
+        assert_eq!(forge.version.as_deref(), Some("11.6.0"));
+        assert_eq!(forge.authors, vec!["mezz"]);
+
+        let plugin = parse_plugin_metadata(
+            "name: EssentialsX\nversion: 2.19.7\nmain: com.earth2me.essentials.Essentials\nauthors: [Zenexer, md_5]\n",
+        )
+        .unwrap();
+        assert_eq!(plugin.loader, Some(ModLoader::Bukkit));
+        assert_eq!(plugin.name.as_deref(), Some("EssentialsX"));
+        assert_eq!(plugin.version.as_deref(), Some("2.19.7"));
+        assert_eq!(plugin.authors, vec!["Zenexer", "md_5"]);
+    }
+
+    #[test]
+    fn test_enable_disable() {
+        assert_eq!(jar_name("a.jar"), Some(("a.jar", true)));
+        assert_eq!(jar_name("a.jar.disabled"), Some(("a.jar", false)));
+        assert_eq!(jar_name("a.txt.disabled"), None);
+
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("a.jar"), "not a zip").unwrap();
+        std::fs::write(temp.path().join("notes.txt"), "").unwrap();
+
+        let info = set_mod_enabled(temp.path(), "a.jar", false).unwrap();
+        assert!(!info.enabled);
+        assert!(temp.path().join("a.jar.disabled").is_file());
+        let mods = list_mods(temp.path()).unwrap();
+        assert_eq!(mods.len(), 1);
+        assert_eq!(mods[0].file_name, "a.jar");
+        assert!(!mods[0].enabled);
+
+        assert!(set_mod_enabled(temp.path(), "a.jar", true).unwrap().enabled);
+        assert!(set_mod_enabled(temp.path(), "../a.jar", true).is_err());
+    }
+}
@@ -1,16 +1,19 @@
 use async_trait::async_trait;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::traits::t_player::Player;
 use crate::traits::t_player::{TPlayer, TPlayerManagement};
+use crate::traits::t_server::{State, TServer};
 use crate::Error;
 
 use super::configurable::ServerPropertySetting;
+use super::control_channel::ControlChannel;
 use super::MinecraftInstance;
 
-#[derive(Eq, Debug, Clone, Serialize, Deserialize, TS)]
+#[derive(Eq, Debug, Clone, Serialize, Deserialize, TS, JsonSchema)]
 #[ts(export)]
 pub struct MinecraftPlayer {
     pub name: String,
@@ -54,6 +57,11 @@ impl TPlayer for MinecraftPlayer {
 #[async_trait]
 impl TPlayerManagement for MinecraftInstance {
     async fn get_player_count(&self) -> Result<u32, Error> {
+        if self.control_channel().await == ControlChannel::Network
+            && self.state().await == State::Running
+        {
+            return Ok(self.query_status().await?.players_online);
+        }
         Ok(self.players_manager.lock().await.count())
     }
 
@@ -67,6 +75,17 @@ impl TPlayerManagement for MinecraftInstance {
     }
 
     async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
+        if self.control_channel().await == ControlChannel::Network
+            && self.state().await == State::Running
+        {
+            return Ok(self
+                .query_status()
+                .await?
+                .players
+                .into_iter()
+                .map(|name| Player::MinecraftPlayer(MinecraftPlayer::new(name, None)))
+                .collect());
+        }
         Ok(self.players_manager.lock().await.clone().into())
     }
 }
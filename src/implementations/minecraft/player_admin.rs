@@ -0,0 +1,422 @@
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::t_server::{State, TServer},
+};
+
+use super::{configurable::ServerPropertySetting, util::name_to_uuid, MinecraftInstance};
+
+/// what the vanilla server puts in the reason of a ban without one
+static DEFAULT_BAN_REASON: &str = "Banned by an operator.";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct WhitelistEntry {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[serde(rename_all = "camelCase")]
+#[ts(export)]
+pub struct OpEntry {
+    pub uuid: String,
+    pub name: String,
+    pub level: u32,
+    pub bypasses_player_limit: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub struct BannedPlayer {
+    pub uuid: String,
+    pub name: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String,
+}
+
+/// An entry of one of the player lists the server keeps next to server.properties
+trait PlayerListEntry: Serialize + DeserializeOwned {
+    const FILE_NAME: &'static str;
+    fn name(&self) -> &str;
+}
+
+impl PlayerListEntry for WhitelistEntry {
+    const FILE_NAME: &'static str = "whitelist.json";
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PlayerListEntry for OpEntry {
+    const FILE_NAME: &'static str = "ops.json";
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PlayerListEntry for BannedPlayer {
+    const FILE_NAME: &'static str = "banned-players.json";
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Player names are sent to the console as part of a command, so only allow what Minecraft does
+pub fn validate_player_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.len() > 16
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(Error::bad_request(format!(
+            "{name} is not a valid player name"
+        )));
+    }
+    Ok(())
+}
+
+/// Keep a free form argument, like a ban reason, from ending the command early
+fn single_line(text: &str) -> String {
+    text.split(['\r', '\n'])
+        .filter(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The Mojang API returns uuids without dashes, the player lists use the dashed form
+fn format_uuid(id: &str) -> String {
+    if id.len() != 32 || id.contains('-') {
+        return id.to_string();
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &id[0..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..32]
+    )
+}
+
+fn read_list<T: PlayerListEntry>(dir: &Path) -> Result<Vec<T>, Error> {
+    let path = dir.join(T::FILE_NAME);
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let content =
+        std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&content).context(format!("Failed to parse {}", path.display()))?)
+}
+
+fn write_list<T: PlayerListEntry>(dir: &Path, list: &[T]) -> Result<(), Error> {
+    let path = dir.join(T::FILE_NAME);
+    std::fs::write(
+        &path,
+        serde_json::to_string_pretty(list)
+            .context(format!("Failed to serialize {}", T::FILE_NAME))?,
+    )
+    .context(format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Add `entry` to its list unless a player with the same name is already on it,
+/// returns whether the list changed
+fn add_to_list<T: PlayerListEntry>(dir: &Path, entry: T) -> Result<bool, Error> {
+    let mut list: Vec<T> = read_list(dir)?;
+    if list
+        .iter()
+        .any(|e| e.name().eq_ignore_ascii_case(entry.name()))
+    {
+        return Ok(false);
+    }
+    list.push(entry);
+    write_list(dir, &list)?;
+    Ok(true)
+}
+
+/// Remove the player named `name` from a list, returns whether the list changed
+fn remove_from_list<T: PlayerListEntry>(dir: &Path, name: &str) -> Result<bool, Error> {
+    let mut list: Vec<T> = read_list(dir)?;
+    let len = list.len();
+    list.retain(|e| !e.name().eq_ignore_ascii_case(name));
+    if list.len() == len {
+        return Ok(false);
+    }
+    write_list(dir, &list)?;
+    Ok(true)
+}
+
+/// Look up a player in the usercache.json the server keeps of everyone who joined
+fn cached_uuid(dir: &Path, name: &str) -> Option<(String, String)> {
+    #[derive(Deserialize)]
+    struct CachedUser {
+        name: String,
+        uuid: String,
+    }
+    let content = std::fs::read_to_string(dir.join("usercache.json")).ok()?;
+    let users: Vec<CachedUser> = serde_json::from_str(&content).ok()?;
+    users
+        .into_iter()
+        .find(|user| user.name.eq_ignore_ascii_case(name))
+        .map(|user| (user.uuid, user.name))
+}
+
+impl MinecraftInstance {
+    /// Whether changes go through the console, the server overwrites the list files while running
+    async fn player_admin_via_console(&self) -> Result<bool, Error> {
+        match self.state().await {
+            State::Running => Ok(true),
+            state if state.is_idle() => Ok(false),
+            _ => Err(Error::bad_request(
+                "Wait for the instance to finish starting, stopping or installing".to_string(),
+            )),
+        }
+    }
+
+    /// The uuid and properly cased name of a player, the server needs both in its list files
+    async fn resolve_player(&self, name: &str) -> Result<(String, String), Error> {
+        if let Some(player) = cached_uuid(&self.path_to_instance, name) {
+            return Ok(player);
+        }
+        match name_to_uuid(name).await {
+            Some(uuid) => Ok((format_uuid(&uuid), name.to_string())),
+            None => Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Could not find a Minecraft account named {name}"),
+            }),
+        }
+    }
+
+    pub async fn whitelist(&self) -> Result<Vec<WhitelistEntry>, Error> {
+        read_list(&self.path_to_instance)
+    }
+
+    pub async fn ops(&self) -> Result<Vec<OpEntry>, Error> {
+        read_list(&self.path_to_instance)
+    }
+
+    pub async fn banned_players(&self) -> Result<Vec<BannedPlayer>, Error> {
+        read_list(&self.path_to_instance)
+    }
+
+    pub async fn whitelist_add(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.player_admin_via_console().await? {
+            return self
+                .send_command(&format!("whitelist add {name}"), caused_by)
+                .await;
+        }
+        let (uuid, name) = self.resolve_player(name).await?;
+        add_to_list(&self.path_to_instance, WhitelistEntry { uuid, name })?;
+        Ok(())
+    }
+
+    pub async fn whitelist_remove(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.player_admin_via_console().await? {
+            return self
+                .send_command(&format!("whitelist remove {name}"), caused_by)
+                .await;
+        }
+        remove_from_list::<WhitelistEntry>(&self.path_to_instance, name)?;
+        Ok(())
+    }
+
+    pub async fn op_player(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.player_admin_via_console().await? {
+            return self.send_command(&format!("op {name}"), caused_by).await;
+        }
+        let (uuid, name) = self.resolve_player(name).await?;
+        // the op command gives the level set in server.properties
+        let level = self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_unique_setting_key(&ServerPropertySetting::OpPermissionLevel(0).get_identifier())
+            .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer()))
+            .unwrap_or(Ok(4))?;
+        add_to_list(
+            &self.path_to_instance,
+            OpEntry {
+                uuid,
+                name,
+                level,
+                bypasses_player_limit: false,
+            },
+        )?;
+        Ok(())
+    }
+
+    pub async fn deop_player(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.player_admin_via_console().await? {
+            return self.send_command(&format!("deop {name}"), caused_by).await;
+        }
+        remove_from_list::<OpEntry>(&self.path_to_instance, name)?;
+        Ok(())
+    }
+
+    pub async fn ban_player(
+        &self,
+        name: &str,
+        reason: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        validate_player_name(name)?;
+        let reason = reason
+            .map(|reason| single_line(&reason))
+            .filter(|reason| !reason.is_empty());
+        if self.player_admin_via_console().await? {
+            let command = match reason {
+                Some(reason) => format!("ban {name} {reason}"),
+                None => format!("ban {name}"),
+            };
+            return self.send_command(&command, caused_by).await;
+        }
+        let (uuid, name) = self.resolve_player(name).await?;
+        add_to_list(
+            &self.path_to_instance,
+            BannedPlayer {
+                uuid,
+                name,
+                created: chrono::Local::now()
+                    .format("%Y-%m-%d %H:%M:%S %z")
+                    .to_string(),
+                source: "Server".to_string(),
+                expires: "forever".to_string(),
+                reason: reason.unwrap_or_else(|| DEFAULT_BAN_REASON.to_string()),
+            },
+        )?;
+        Ok(())
+    }
+
+    pub async fn pardon_player(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.player_admin_via_console().await? {
+            return self
+                .send_command(&format!("pardon {name}"), caused_by)
+                .await;
+        }
+        remove_from_list::<BannedPlayer>(&self.path_to_instance, name)?;
+        Ok(())
+    }
+
+    pub async fn kick_player(
+        &self,
+        name: &str,
+        message: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        validate_player_name(name)?;
+        if self.state().await != State::Running {
+            return Err(Error::bad_request(
+                "Only players on a running server can be kicked".to_string(),
+            ));
+        }
+        let command = match message
+            .map(|message| single_line(&message))
+            .filter(|message| !message.is_empty())
+        {
+            Some(message) => format!("kick {name} {message}"),
+            None => format!("kick {name}"),
+        };
+        self.send_command(&command, caused_by).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_player_name() {
+        assert!(validate_player_name("Notch").is_ok());
+        assert!(validate_player_name("a_b_1").is_ok());
+        assert!(validate_player_name("").is_err());
+        assert!(validate_player_name("seventeen_chars__").is_err());
+        assert!(validate_player_name("a\nstop").is_err());
+        assert!(validate_player_name("a b").is_err());
+    }
+
+    #[test]
+    fn test_single_line() {
+        assert_eq!(
+            single_line("griefing\nstop\r\n spawn"),
+            "griefing stop spawn"
+        );
+        assert_eq!(single_line("\n"), "");
+    }
+
+    #[test]
+    fn test_format_uuid() {
+        assert_eq!(
+            format_uuid("069a79f444e94726a5befca90e38aaf5"),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+        assert_eq!(
+            format_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf5"),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+    }
+
+    #[test]
+    fn test_player_lists() {
+        let temp_dir = tempdir::TempDir::new("test_player_lists").unwrap();
+        let dir = temp_dir.path();
+        assert!(read_list::<OpEntry>(dir).unwrap().is_empty());
+
+        std::fs::write(
+            dir.join("ops.json"),
+            r#"[{"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "Notch", "level": 4, "bypassesPlayerLimit": false}]"#,
+        )
+        .unwrap();
+        let entry = OpEntry {
+            uuid: "853c80ef-3c37-49fd-aa49-938b674adae6".to_string(),
+            name: "jeb_".to_string(),
+            level: 3,
+            bypasses_player_limit: false,
+        };
+        assert!(add_to_list(dir, entry.clone()).unwrap());
+        assert!(!add_to_list(dir, entry).unwrap());
+        let ops = read_list::<OpEntry>(dir).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[1].level, 3);
+
+        assert!(remove_from_list::<OpEntry>(dir, "notch").unwrap());
+        assert!(!remove_from_list::<OpEntry>(dir, "notch").unwrap());
+        let content = std::fs::read_to_string(dir.join("ops.json")).unwrap();
+        assert!(content.contains("bypassesPlayerLimit"));
+        assert_eq!(read_list::<OpEntry>(dir).unwrap()[0].name, "jeb_");
+    }
+
+    #[test]
+    fn test_cached_uuid() {
+        let temp_dir = tempdir::TempDir::new("test_cached_uuid").unwrap();
+        std::fs::write(
+            temp_dir.path().join("usercache.json"),
+            r#"[{"name": "Notch", "uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "expiresOn": "2023-07-01 12:00:00 +0000"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cached_uuid(temp_dir.path(), "notch"),
+            Some((
+                "069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string(),
+                "Notch".to_string()
+            ))
+        );
+        assert_eq!(cached_uuid(temp_dir.path(), "jeb_"), None);
+    }
+}
@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    geoip::GeoLocation,
     traits::t_player::Player,
     types::{InstanceUuid, Snowflake},
 };
@@ -25,8 +26,16 @@ impl PlayersManager {
         }
     }
 
-    pub fn add_player(&mut self, player: MinecraftPlayer, instance_name: String) {
+    pub fn add_player(
+        &mut self,
+        player: MinecraftPlayer,
+        location: Option<GeoLocation>,
+        instance_name: String,
+    ) {
         self.players.insert(player.clone());
+        let joined_from = location
+            .map(|location| HashMap::from([(player.name.clone(), location)]))
+            .unwrap_or_default();
         self.event_broadcaster.send(Event {
             event_inner: EventInner::InstanceEvent(InstanceEvent {
                 instance_uuid: self.instance_uuid.clone(),
@@ -35,6 +44,7 @@ impl PlayersManager {
                     player_list: self.players.iter().map(|p| p.clone().into()).collect(),
                     players_joined: HashSet::from([player.into()]),
                     players_left: HashSet::new(),
+                    joined_from,
                 },
             }),
             details: "".to_string(),
@@ -55,6 +65,7 @@ impl PlayersManager {
                         player_list: self.players.iter().map(|p| p.clone().into()).collect(),
                         players_joined: HashSet::new(),
                         players_left: HashSet::from([player.into()]),
+                        joined_from: HashMap::new(),
                     },
                 }),
                 details: "".to_string(),
@@ -90,6 +101,7 @@ impl PlayersManager {
                     player_list: HashSet::new(),
                     players_joined: HashSet::new(),
                     players_left: self.players.iter().map(|p| p.clone().into()).collect(),
+                    joined_from: HashMap::new(),
                 },
             }),
             details: "".to_string(),
@@ -128,7 +140,7 @@ mod tests {
     async fn test_players_manager() {
         use crate::types::InstanceUuid;
         use crate::{events::InstanceEventInner, traits::t_player::Player};
-        use std::collections::HashSet;
+        use std::collections::{HashMap, HashSet};
 
         let mock_instance = (InstanceUuid::default(), "mock_instance".to_string());
 
@@ -140,6 +152,7 @@ mod tests {
                 name: "player1".to_string(),
                 uuid: Some("uuid1".to_string()),
             },
+            None,
             mock_instance.1.clone(),
         );
 
@@ -148,6 +161,7 @@ mod tests {
                 name: "player2".to_string(),
                 uuid: Some("uuid2".to_string()),
             },
+            None,
             mock_instance.1.clone(),
         );
 
@@ -156,6 +170,7 @@ mod tests {
                 name: "player3".to_string(),
                 uuid: Some("uuid3".to_string()),
             },
+            None,
             mock_instance.1.clone(),
         );
 
@@ -176,6 +191,7 @@ mod tests {
                     uuid: Some("uuid1".to_string()),
                 })]),
                 players_left: HashSet::new(),
+                joined_from: HashMap::new(),
             },
             InstanceEventInner::PlayerChange {
                 player_list: HashSet::from([
@@ -193,6 +209,7 @@ mod tests {
                     uuid: Some("uuid2".to_string()),
                 })]),
                 players_left: HashSet::new(),
+                joined_from: HashMap::new(),
             },
             InstanceEventInner::PlayerChange {
                 player_list: HashSet::from([
@@ -214,6 +231,7 @@ mod tests {
                     uuid: Some("uuid3".to_string()),
                 })]),
                 players_left: HashSet::new(),
+                joined_from: HashMap::new(),
             },
             InstanceEventInner::PlayerChange {
                 player_list: HashSet::from([
@@ -231,6 +249,7 @@ mod tests {
                     name: "player2".to_string(),
                     uuid: Some("uuid2".to_string()),
                 })]),
+                joined_from: HashMap::new(),
             },
             InstanceEventInner::PlayerChange {
                 player_list: HashSet::from([Player::MinecraftPlayer(super::MinecraftPlayer {
@@ -242,6 +261,7 @@ mod tests {
                     name: "player3".to_string(),
                     uuid: Some("uuid3".to_string()),
                 })]),
+                joined_from: HashMap::new(),
             },
             InstanceEventInner::PlayerChange {
                 player_list: HashSet::new(),
@@ -250,6 +270,7 @@ mod tests {
                     name: "player1".to_string(),
                     uuid: Some("uuid1".to_string()),
                 })]),
+                joined_from: HashMap::new(),
             },
         ];
 
@@ -0,0 +1,211 @@
+//! The Query protocol, answered over UDP on `query.port` when `enable-query` is set. Unlike the
+//! console, it lists the players online no matter which mods rename or translate the join and
+//! leave messages.
+
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use tokio::net::UdpSocket;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+static MAGIC: [u8; 2] = [0xfe, 0xfd];
+static HANDSHAKE: u8 = 0x09;
+static STAT: u8 = 0x00;
+/// only the low 4 bits of each byte are read by the server
+static SESSION_ID: i32 = 0x0102_0304;
+/// `splitnum\0\x80\0` before the key values of a full stat
+static KEY_VALUES_PADDING: usize = 11;
+/// `\x01player_\0\0` before the player names of a full stat
+static PLAYERS_PADDING: usize = 10;
+/// a full stat fits in a datagram, and servers list a few hundred players at most
+static MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct QueryStatus {
+    pub motd: String,
+    pub version: String,
+    /// e.g. `Paper on 1.20.1: LuckPerms 5.4.102; WorldEdit 7.2.15`, empty for vanilla servers
+    pub plugins: String,
+    pub map: String,
+    pub players_online: u32,
+    pub max_players: u32,
+    pub players: Vec<String>,
+}
+
+fn request(packet_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut request = MAGIC.to_vec();
+    request.push(packet_type);
+    request.extend_from_slice(&SESSION_ID.to_be_bytes());
+    request.extend_from_slice(payload);
+    request
+}
+
+/// The payload of a response, after its type and session id
+fn payload(response: &[u8], packet_type: u8) -> Result<&[u8], Error> {
+    if response.len() < 5 || response[0] != packet_type {
+        return Err(eyre!("Unexpected query response").into());
+    }
+    if response[1..5] != SESSION_ID.to_be_bytes() {
+        return Err(eyre!("The query response is for another session").into());
+    }
+    Ok(&response[5..])
+}
+
+/// Split off a null terminated string
+fn read_string(data: &[u8]) -> Option<(String, &[u8])> {
+    let end = data.iter().position(|b| *b == 0)?;
+    Some((
+        String::from_utf8_lossy(&data[..end]).into_owned(),
+        &data[end + 1..],
+    ))
+}
+
+fn parse_challenge_token(response: &[u8]) -> Result<i32, Error> {
+    let (token, _) = read_string(payload(response, HANDSHAKE)?)
+        .ok_or_else(|| eyre!("The challenge token is not terminated"))?;
+    Ok(token
+        .parse()
+        .context(format!("Invalid challenge token {token}"))?)
+}
+
+fn parse_full_stat(response: &[u8]) -> Result<QueryStatus, Error> {
+    let mut data = payload(response, STAT)?
+        .get(KEY_VALUES_PADDING..)
+        .ok_or_else(|| eyre!("The full stat response is truncated"))?;
+    let mut status = QueryStatus::default();
+    loop {
+        let (key, rest) =
+            read_string(data).ok_or_else(|| eyre!("The full stat response is truncated"))?;
+        if key.is_empty() {
+            data = rest;
+            break;
+        }
+        let (value, rest) =
+            read_string(rest).ok_or_else(|| eyre!("The full stat response is truncated"))?;
+        data = rest;
+        match key.as_str() {
+            "hostname" => status.motd = value,
+            "version" => status.version = value,
+            "plugins" => status.plugins = value,
+            "map" => status.map = value,
+            "numplayers" => status.players_online = value.parse().unwrap_or_default(),
+            "maxplayers" => status.max_players = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    let mut data = data.get(PLAYERS_PADDING..).unwrap_or_default();
+    while let Some((player, rest)) = read_string(data) {
+        if player.is_empty() {
+            break;
+        }
+        status.players.push(player);
+        data = rest;
+    }
+    Ok(status)
+}
+
+async fn exchange(socket: &UdpSocket, request: &[u8]) -> Result<Vec<u8>, Error> {
+    socket
+        .send(request)
+        .await
+        .context("Failed to send the query request")?;
+    let mut response = vec![0; MAX_RESPONSE_SIZE];
+    let len = socket
+        .recv(&mut response)
+        .await
+        .context("Failed to read the query response")?;
+    response.truncate(len);
+    Ok(response)
+}
+
+async fn full_stat(port: u16) -> Result<QueryStatus, Error> {
+    let socket = UdpSocket::bind(("127.0.0.1", 0))
+        .await
+        .context("Failed to bind a socket for the query")?;
+    socket
+        .connect(("127.0.0.1", port))
+        .await
+        .context("Failed to connect to the query port")?;
+    let challenge_token =
+        parse_challenge_token(&exchange(&socket, &request(HANDSHAKE, &[])).await?)?;
+    let mut payload = challenge_token.to_be_bytes().to_vec();
+    // the padding asks for the full stat rather than the basic one
+    payload.extend_from_slice(&[0; 4]);
+    parse_full_stat(&exchange(&socket, &request(STAT, &payload)).await?)
+}
+
+/// Ask the minecraft server with the query port `port` on the host for its full stat
+pub async fn query(port: u16, timeout: Duration) -> Result<QueryStatus, Error> {
+    tokio::time::timeout(timeout, full_stat(port))
+        .await
+        .map_err(|_| eyre!("The server did not answer the query in time"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use super::{query, QueryStatus, SESSION_ID};
+
+    fn full_stat_response() -> Vec<u8> {
+        let mut response = vec![0x00];
+        response.extend_from_slice(&SESSION_ID.to_be_bytes());
+        response.extend_from_slice(b"splitnum\0\x80\0");
+        for (key, value) in [
+            ("hostname", "A Minecraft Server"),
+            ("gametype", "SMP"),
+            ("version", "1.20.1"),
+            ("plugins", ""),
+            ("map", "world"),
+            ("numplayers", "2"),
+            ("maxplayers", "20"),
+        ] {
+            response.extend_from_slice(key.as_bytes());
+            response.push(0);
+            response.extend_from_slice(value.as_bytes());
+            response.push(0);
+        }
+        response.push(0);
+        response.extend_from_slice(b"\x01player_\0\0Steve\0Alex\0\0");
+        response
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            let (len, client) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..len], [0xfe, 0xfd, 0x09, 1, 2, 3, 4]);
+            let mut handshake = vec![0x09];
+            handshake.extend_from_slice(&SESSION_ID.to_be_bytes());
+            handshake.extend_from_slice(b"9513307\0");
+            server.send_to(&handshake, client).await.unwrap();
+
+            let (len, client) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..7], [0xfe, 0xfd, 0x00, 1, 2, 3, 4]);
+            assert_eq!(buf[7..11], 9513307_i32.to_be_bytes());
+            assert_eq!(len, 15);
+            server.send_to(&full_stat_response(), client).await.unwrap();
+        });
+        assert_eq!(
+            query(port, Duration::from_secs(5)).await.unwrap(),
+            QueryStatus {
+                motd: "A Minecraft Server".to_string(),
+                version: "1.20.1".to_string(),
+                plugins: String::new(),
+                map: "world".to_string(),
+                players_online: 2,
+                max_players: 20,
+                players: vec!["Steve".to_string(), "Alex".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use color_eyre::eyre::{eyre, Context};
+use serde_json::Value;
+
+use crate::error::Error;
+
+async fn get_quilt_meta(path: &str, what: &str) -> Result<Value, Error> {
+    let http = reqwest::Client::new();
+    Ok(serde_json::from_str(
+        http.get(format!("https://meta.quiltmc.org/v3/versions/{path}"))
+            .send()
+            .await
+            .context(format!("Failed to get quilt {what}"))?
+            .text()
+            .await
+            .context(format!("Failed to get quilt {what}"))?
+            .as_str(),
+    )
+    .context(format!(
+        "Failed to get quilt {what}, response is not valid json"
+    ))?)
+}
+
+/// The string at `pointer` of every item of `response`
+fn versions_at(response: &Value, pointer: &str, what: &str) -> Result<Vec<String>, Error> {
+    response
+        .as_array()
+        .ok_or_else(|| eyre!("Failed to get quilt {what}. Response is not an array"))?
+        .iter()
+        .map(|item| {
+            item.pointer(pointer)
+                .and_then(Value::as_str)
+                .map(|version| version.to_string())
+                .ok_or_else(|| {
+                    eyre!("Failed to get quilt {what}. Version string is not a string").into()
+                })
+        })
+        .collect()
+}
+
+pub async fn get_quilt_minecraft_versions() -> Result<Vec<String>, Error> {
+    versions_at(
+        &get_quilt_meta("game", "versions").await?,
+        "/version",
+        "versions",
+    )
+}
+
+/// The loader versions supporting `version`, newest first
+pub async fn get_quilt_loader_versions(version: &str) -> Result<Vec<String>, Error> {
+    versions_at(
+        &get_quilt_meta(&format!("loader/{version}"), "loader versions").await?,
+        "/loader/version",
+        "loader versions",
+    )
+}
+
+/// The url of the latest installer
+pub async fn get_quilt_installer_url() -> Result<String, Error> {
+    versions_at(
+        &get_quilt_meta("installer", "installer versions").await?,
+        "/url",
+        "installer versions",
+    )?
+    .into_iter()
+    .next()
+    .ok_or_else(|| eyre!("Failed to get quilt installer versions. No installer found").into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_quilt_versions() {
+        let versions = get_quilt_minecraft_versions().await.unwrap();
+        assert!(versions.contains(&"1.19.2".to_string()));
+        let loaders = get_quilt_loader_versions("1.19.2").await.unwrap();
+        assert!(loaders.contains(&"0.17.6".to_string()));
+        assert!(get_quilt_installer_url().await.unwrap().ends_with(".jar"));
+    }
+}
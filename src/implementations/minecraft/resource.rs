@@ -4,21 +4,27 @@ use crate::{error::Error, traits::t_resource::TResourceManagement};
 
 use super::MinecraftInstance;
 
+/// Mods and plugins are the resources of a minecraft instance, see `mods.rs`
 #[async_trait]
 impl TResourceManagement for MinecraftInstance {
     async fn list(&self) -> Vec<serde_json::Value> {
-        todo!()
+        self.list_mods()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|info| serde_json::to_value(info).ok())
+            .collect()
     }
 
-    async fn load(&mut self, _resource: &str) -> Result<(), Error> {
-        todo!()
+    async fn load(&mut self, resource: &str) -> Result<(), Error> {
+        self.set_mod_enabled(resource, true).await.map(|_| ())
     }
 
-    async fn unload(&mut self, _resource: &str) -> Result<(), Error> {
-        todo!()
+    async fn unload(&mut self, resource: &str) -> Result<(), Error> {
+        self.set_mod_enabled(resource, false).await.map(|_| ())
     }
 
-    async fn delete(&mut self, _resource: &str) -> Result<(), Error> {
-        todo!()
+    async fn delete(&mut self, resource: &str) -> Result<(), Error> {
+        self.delete_mod(resource).await
     }
 }
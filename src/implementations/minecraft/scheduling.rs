@@ -0,0 +1,239 @@
+//! How the OS schedules the server process: the CPUs it may run on and its priority, so a lag
+//! sensitive server isn't starved by a backup or another instance.
+//!
+//! On unix the JVM is started through `nice` and `taskset`, which exec it, so the spawned child is
+//! still the server and every thread of the JVM inherits the scheduling. On Windows it is set on
+//! the process right after it is spawned.
+
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::ffi::OsString;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use tokio::process::{Child, Command};
+#[cfg(windows)]
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+#[cfg(windows)]
+use crate::util::dont_spawn_terminal;
+
+use super::MinecraftInstance;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub enum ProcessPriority {
+    /// nice 19 on unix, the idle priority class on Windows
+    Idle,
+    /// nice 10
+    BelowNormal,
+    #[default]
+    Normal,
+    /// nice -5, needs the core to run with the privilege to raise priorities on unix
+    AboveNormal,
+    /// nice -10, same as above
+    High,
+}
+
+impl ProcessPriority {
+    pub fn nice(&self) -> i32 {
+        match self {
+            ProcessPriority::Idle => 19,
+            ProcessPriority::BelowNormal => 10,
+            ProcessPriority::Normal => 0,
+            ProcessPriority::AboveNormal => -5,
+            ProcessPriority::High => -10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ProcessScheduling {
+    /// the CPUs the server may run on by index, any of them if empty. Not supported on macOS
+    #[serde(default)]
+    pub cpu_affinity: Vec<usize>,
+    #[serde(default)]
+    pub priority: ProcessPriority,
+}
+
+fn cpu_count() -> usize {
+    std::thread::available_parallelism().map_or(1, |count| count.get())
+}
+
+impl ProcessScheduling {
+    pub fn validate(&self) -> Result<(), Error> {
+        let cpu_count = cpu_count();
+        if let Some(cpu) = self.cpu_affinity.iter().find(|cpu| **cpu >= cpu_count) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("CPU {cpu} does not exist, the host has {cpu_count} CPUs"),
+            });
+        }
+        #[cfg(all(unix, not(target_os = "linux")))]
+        if !self.cpu_affinity.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("CPU affinity is not supported on this platform"),
+            });
+        }
+        #[cfg(windows)]
+        if self
+            .cpu_affinity
+            .iter()
+            .any(|cpu| *cpu >= usize::BITS as usize)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Only the first {} CPUs can be pinned to", usize::BITS),
+            });
+        }
+        Ok(())
+    }
+
+    /// The command starting `program` with the scheduling applied
+    #[cfg(unix)]
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let nice = self.priority.nice();
+        let mut wrappers: Vec<OsString> = Vec::new();
+        if nice != 0 {
+            // without the privilege to raise the priority `nice` warns on stderr and starts the
+            // server at the normal priority, the warning shows in the console
+            wrappers.extend(["nice".into(), "-n".into(), nice.to_string().into()]);
+        }
+        #[cfg(target_os = "linux")]
+        if !self.cpu_affinity.is_empty() {
+            let cpus = self
+                .cpu_affinity
+                .iter()
+                .map(|cpu| cpu.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            wrappers.extend(["taskset".into(), "-c".into(), cpus.into()]);
+        }
+        match wrappers.split_first() {
+            Some((wrapper, args)) => {
+                let mut command = Command::new(wrapper);
+                command.args(args).arg(program);
+                command
+            }
+            None => Command::new(program),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        Command::new(program)
+    }
+
+    /// Set the priority class and the affinity of the process, in the background
+    #[cfg(windows)]
+    pub fn after_spawn(&self, child: &Child, name: &str) {
+        if *self == ProcessScheduling::default() {
+            return;
+        }
+        let Some(pid) = child.id() else {
+            return;
+        };
+        let mut script = format!("$process = Get-Process -Id {pid}");
+        let priority_class = match self.priority {
+            ProcessPriority::Idle => "Idle",
+            ProcessPriority::BelowNormal => "BelowNormal",
+            ProcessPriority::Normal => "Normal",
+            ProcessPriority::AboveNormal => "AboveNormal",
+            ProcessPriority::High => "High",
+        };
+        script.push_str(&format!("; $process.PriorityClass = '{priority_class}'"));
+        if !self.cpu_affinity.is_empty() {
+            let mask = self
+                .cpu_affinity
+                .iter()
+                .fold(0usize, |mask, cpu| mask | (1 << cpu));
+            script.push_str(&format!("; $process.ProcessorAffinity = {mask}"));
+        }
+        let name = name.to_owned();
+        tokio::spawn(async move {
+            let output = dont_spawn_terminal(
+                Command::new("powershell")
+                    .args(["-NoProfile", "-NonInteractive", "-Command"])
+                    .arg(script),
+            )
+            .output()
+            .await;
+            match output {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => warn!(
+                    "[{name}] Failed to set the scheduling of the server : {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => warn!("[{name}] Failed to set the scheduling of the server : {e}"),
+            }
+        });
+    }
+
+    #[cfg(not(windows))]
+    pub fn after_spawn(&self, _child: &Child, _name: &str) {}
+}
+
+impl MinecraftInstance {
+    pub async fn scheduling(&self) -> ProcessScheduling {
+        self.config.lock().await.scheduling.clone()
+    }
+
+    /// Takes effect on the next start
+    pub async fn set_scheduling(&mut self, scheduling: ProcessScheduling) -> Result<(), Error> {
+        scheduling.validate()?;
+        self.config.lock().await.scheduling = scheduling;
+        self.write_config_to_file().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cpu_count, ProcessPriority, ProcessScheduling};
+
+    #[test]
+    fn test_validate() {
+        assert!(ProcessScheduling::default().validate().is_ok());
+        let scheduling = ProcessScheduling {
+            cpu_affinity: vec![0],
+            priority: ProcessPriority::High,
+        };
+        assert!(scheduling.validate().is_ok());
+        let scheduling = ProcessScheduling {
+            cpu_affinity: vec![cpu_count()],
+            priority: ProcessPriority::Normal,
+        };
+        assert!(scheduling.validate().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_command() {
+        let command = ProcessScheduling::default().command("java");
+        assert_eq!(command.as_std().get_program(), "java");
+        let command = ProcessScheduling {
+            cpu_affinity: vec![0, 2],
+            priority: ProcessPriority::High,
+        }
+        .command("java");
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "nice");
+        assert_eq!(
+            command
+                .get_args()
+                .map(|arg| arg.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["-n", "-10", "taskset", "-c", "0,2", "java"]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_defaults() {
+        let scheduling: ProcessScheduling = serde_json::from_str("{}").unwrap();
+        assert_eq!(scheduling, ProcessScheduling::default());
+        assert_eq!(scheduling.priority.nice(), 0);
+    }
+}
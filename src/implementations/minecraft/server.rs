@@ -1,36 +1,79 @@
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::process::Stdio;
-use std::time::Duration;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{eyre, Context};
 use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::Command;
 
+use crate::console_matchers::{ConsoleMatch, ConsoleMatchKind};
 use crate::error::{Error, ErrorKind};
-use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, CRASH_OUTPUT_LINES,
+};
+use crate::geoip::{self, parse_client_address, GeoLocation};
+use crate::implementations::minecraft::commands::{is_help_command, parse_help_line};
+use crate::implementations::minecraft::console_profile::ConsoleProfile;
+use crate::implementations::minecraft::control_channel::ControlChannel;
+use crate::implementations::minecraft::java::check_compatibility;
 use crate::implementations::minecraft::line_parser::{
-    parse_player_joined, parse_player_left, parse_player_msg, parse_server_started,
+    builtin_console_pipeline, parse_out_of_memory_error, parse_player_login, parse_player_msg,
     parse_system_msg, PlayerMessage,
 };
+use crate::implementations::minecraft::path_to_bundled_java;
 use crate::implementations::minecraft::player::MinecraftPlayer;
-use crate::implementations::minecraft::util::name_to_uuid;
+use crate::implementations::minecraft::util::{detect_launch_target, heap_sizes, name_to_uuid};
 use crate::macro_executor::SpawnResult;
 use crate::traits::t_configurable::TConfigurable;
 use crate::traits::t_macro::TaskEntry;
-use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
+use crate::traits::t_server::{CommandInfo, MonitorReport, Readiness, State, StateAction, TServer};
 
 use crate::types::Snowflake;
-use crate::util::{dont_spawn_terminal, list_dir};
+use crate::util::dont_spawn_terminal;
 
 use super::r#macro::{resolve_macro_invocation, MinecraftMainWorkerGenerator};
-use super::{Flavour, ForgeBuildVersion, MinecraftInstance};
+use super::status_ping::status_ping;
+use super::{LaunchTarget, MinecraftInstance};
 use tracing::{error, info, warn};
 
+#[cfg(unix)]
+fn is_killed_by_sigkill(status: &std::process::ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal() == Some(9)
+}
+
+#[cfg(not(unix))]
+fn is_killed_by_sigkill(_status: &std::process::ExitStatus) -> bool {
+    false
+}
+
+/// How often a starting server is pinged, and how long it has to answer
+static READINESS_PING_INTERVAL: Duration = Duration::from_secs(5);
+
 #[async_trait::async_trait]
 impl TServer for MinecraftInstance {
     async fn start(&mut self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
+        // the port and the java are checked before the transition, an instance left starting
+        // couldn't be started again
+        self.state
+            .lock()
+            .await
+            .try_new_state(StateAction::UserStart, None)?;
+        if !port_scanner::local_port_available(config.port as u16) {
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Port {} is already in use", config.port),
+            });
+        }
+        let jre = if let Some(jre) = &config.java_cmd {
+            PathBuf::from(jre)
+        } else {
+            path_to_bundled_java(&self.path_to_runtimes, config.jre_major_version)
+        };
+        check_compatibility(&jre, config.jre_major_version).await?;
         self.state.lock().await.try_transition(
             StateAction::UserStart,
             Some(&|state| {
@@ -46,12 +89,10 @@ impl TServer for MinecraftInstance {
                 });
             }),
         )?;
-
-        if !port_scanner::local_port_available(config.port as u16) {
-            return Err(Error {
-                kind: ErrorKind::Internal,
-                source: eyre!("Port {} is already in use", config.port),
-            });
+        self.readiness.starting();
+        // restarts after a crash are started by the system, anything else starts over
+        if !matches!(cause_by, CausedBy::System) {
+            self.crash_restarts.store(0, Ordering::SeqCst);
         }
 
         let prelaunch = resolve_macro_invocation(&self.path_to_instance, "prelaunch");
@@ -125,109 +166,52 @@ impl TServer for MinecraftInstance {
             );
         }
 
-        let jre = if let Some(jre) = &config.java_cmd {
-            PathBuf::from(jre)
-        } else {
-            self.path_to_runtimes
-                .join("java")
-                .join(format!("jre{}", config.jre_major_version))
-                .join(if std::env::consts::OS == "macos" {
-                    "Contents/Home/bin"
-                } else {
-                    "bin"
-                })
-                .join("java")
-        };
-
-        let mut server_start_command = Command::new(&jre);
+        let (min_heap, max_heap) = heap_sizes(
+            config.min_ram,
+            config.max_ram,
+            config.resource_limits.memory_limit,
+        );
+        let mut server_start_command = config.scheduling.command(&jre);
         let server_start_command = server_start_command
-            .arg(format!("-Xmx{}M", config.max_ram))
-            .arg(format!("-Xms{}M", config.min_ram))
+            .arg(format!("-Xmx{max_heap}M"))
+            .arg(format!("-Xms{min_heap}M"))
             .args(
                 &config
                     .cmd_args
                     .iter()
                     .filter(|s| !s.is_empty())
                     .collect::<Vec<&String>>(),
+            )
+            .args(&config.launch_overrides.jvm_args)
+            .envs(
+                config
+                    .launch_overrides
+                    .env
+                    .iter()
+                    .map(|(name, var)| (name, &var.value)),
             );
 
-        let server_start_command = match &config.flavour {
-            Flavour::Forge { build_version } => {
-                let ForgeBuildVersion(build_version) = build_version
-                    .as_ref()
-                    .ok_or_else(|| eyre!("Forge version not found"))?;
-                let version_parts: Vec<&str> = config.version.split('.').collect();
-                let major_version: i32 = version_parts[1]
-                    .parse()
-                    .context("Unable to parse major Minecraft version for Forge")?;
-
-                if 17 <= major_version {
-                    let forge_args = match std::env::consts::OS {
-                        "windows" => "win_args.txt",
-                        _ => "unix_args.txt",
-                    };
-
-                    let mut full_forge_args = std::ffi::OsString::from("@");
-                    full_forge_args.push(
-                        self.path_to_instance
-                            .join("libraries")
-                            .join("net")
-                            .join("minecraftforge")
-                            .join("forge")
-                            .join(build_version.as_str())
-                            .join(forge_args)
-                            .into_os_string()
-                            .as_os_str(),
-                    );
-
-                    server_start_command.arg(full_forge_args)
-                } else if (7..=16).contains(&major_version) {
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find forge.jar")?;
-                    let forge_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with(format!("forge-{}-", config.version,).as_str())
-                        })
-                        .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(forge_jar_name))
-                } else {
-                    // 1.5 doesn't work due to JRE issues
-                    // 1.4 doesn't work since forge doesn't provide an installer
-                    let files = list_dir(&self.path_to_instance, Some(false))
-                        .await
-                        .context("Failed to find minecraftforge.jar")?;
-                    let server_jar_name = files
-                        .iter()
-                        .find(|p| {
-                            p.extension().unwrap_or_default() == "jar"
-                                && p.file_name()
-                                    .unwrap_or_default()
-                                    .to_str()
-                                    .unwrap_or_default()
-                                    .starts_with("minecraftforge")
-                        })
-                        .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
-                    server_start_command
-                        .arg("-jar")
-                        .arg(&self.path_to_instance.join(server_jar_name))
-                }
+        let launch_target = match &config.launch_target {
+            Some(launch_target) => launch_target.clone(),
+            None => {
+                detect_launch_target(&self.path_to_instance, &config.flavour, &config.version)
+                    .await?
             }
-            _ => server_start_command
+        };
+        let server_start_command = match launch_target {
+            LaunchTarget::Jar { jar } => server_start_command
                 .arg("-jar")
-                .arg(&self.path_to_instance.join("server.jar")),
+                .arg(&self.path_to_instance.join(jar)),
+            LaunchTarget::ArgsFile { args_file } => {
+                let mut full_args_file = std::ffi::OsString::from("@");
+                full_args_file.push(self.path_to_instance.join(args_file).as_os_str());
+                server_start_command.arg(full_args_file)
+            }
         };
 
         let server_start_command = server_start_command
             .arg("nogui")
+            .args(&config.launch_overrides.program_args)
             .current_dir(&self.path_to_instance);
 
         match dont_spawn_terminal(server_start_command)
@@ -237,6 +221,7 @@ impl TServer for MinecraftInstance {
             .spawn()
         {
             Ok(mut proc) => {
+                config.scheduling.after_spawn(&proc, &config.name);
                 let stdin = proc.stdin.take().ok_or_else(|| {
                     error!(
                         "[{}] Failed to take stdin during startup",
@@ -260,14 +245,31 @@ impl TServer for MinecraftInstance {
                     eyre!("Failed to take stderr during startup")
                 })?;
                 *self.process.lock().await = Some(proc);
+                self.network_usage_tracker.lock().await.reset();
                 tokio::task::spawn({
                     let event_broadcaster = self.event_broadcaster.clone();
                     let uuid = self.uuid.clone();
                     let name = config.name.clone();
                     let players_manager = self.players_manager.clone();
                     let mut __self = self.clone();
+                    let started_at = Instant::now();
+                    // profiles are checked when they are set, a config edited by hand falls back
+                    let console_parser = config.console_profile.compile().unwrap_or_else(|e| {
+                        warn!(
+                            "[{}] Invalid console profile, using the default : {e}",
+                            name
+                        );
+                        ConsoleProfile::default()
+                            .compile()
+                            .expect("the default console profile is valid")
+                    });
+                    let console_pipeline = builtin_console_pipeline();
                     async move {
                         let mut did_start = false;
+                        let mut jvm_out_of_memory = false;
+                        let mut last_output = VecDeque::with_capacity(CRASH_OUTPUT_LINES);
+                        // the login line with the address comes right before the join line
+                        let mut login_locations: HashMap<String, GeoLocation> = HashMap::new();
 
                         let mut stdout_reader = BufReader::new(stdout);
                         let mut stderr_reader = BufReader::new(stderr);
@@ -309,6 +311,13 @@ impl TServer for MinecraftInstance {
                                         // info!("[{}] {}", name, line);
                                         warn!("[{}] {}", name, line);
                                     }
+                                    if parse_out_of_memory_error(&line) {
+                                        jvm_out_of_memory = true;
+                                    }
+                                    if last_output.len() == CRASH_OUTPUT_LINES {
+                                        last_output.pop_front();
+                                    }
+                                    last_output.push_back(line.trim_end().to_string());
                                     event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
                                             instance_uuid: uuid.clone(),
@@ -322,101 +331,32 @@ impl TServer for MinecraftInstance {
                                         snowflake: Snowflake::default(),
                                         caused_by: CausedBy::System,
                                     });
+                                    let mut console_matches = console_pipeline.parse(&line);
 
-                                    if parse_server_started(&line) && !did_start {
+                                    if console_parser.server_started(&line) && !did_start {
                                         did_start = true;
-                                        self.state
-                                            .lock()
-                                            .await
-                                            .try_transition(
-                                                StateAction::InstanceStart,
-                                                Some(&|state| {
-                                                    self.event_broadcaster.send(Event {
-                                                event_inner: EventInner::InstanceEvent(
-                                                    InstanceEvent {
-                                                        instance_name: config.name.clone(),
-                                                        instance_uuid: self.uuid.clone(),
-                                                        instance_event_inner:
-                                                            InstanceEventInner::StateTransition {
-                                                                to: state,
-                                                            },
-                                                    },
-                                                ),
-                                                snowflake: Snowflake::default(),
-                                                details: "Starting server".to_string(),
-                                                caused_by: cause_by.clone(),
-                                            });
-                                                }),
-                                            )
-                                            .unwrap();
-
-                                        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
-                                            let lock = self.configurable_manifest.lock().await;
-
-                                            let a = lock
-                                                .get_unique_setting_key("enable-rcon")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_boolean().ok())
-                                                })
-                                                .flatten();
-
-                                            let b = lock
-                                                .get_unique_setting_key("rcon.password")
-                                                .and_then(|v| {
-                                                    v.get_value().map(|v| v.try_as_string().ok())
-                                                })
-                                                .flatten()
-                                                .cloned();
-
-                                            let c = lock
-                                                .get_unique_setting_key("rcon.port")
-                                                .and_then(|v| {
-                                                    v.get_value()
-                                                        .map(|v| v.try_as_unsigned_integer().ok())
-                                                })
-                                                .flatten();
-                                            (a, b, c)
-                                        } {
-                                            let max_retry = 3;
-                                            for i in 0..max_retry {
-                                                let rcon =
-                                                <rcon::Connection<tokio::net::TcpStream>>::builder(
-                                                )
-                                                .enable_minecraft_quirks(true)
-                                                .connect(
-                                                    &format!("localhost:{}", rcon_port),
-                                                    &rcon_psw,
-                                                )
-                                                .await
-                                                .map_err(|e| {
-                                                    warn!(
-                                                    "Failed to connect to RCON: {}, retry {}/{}",
-                                                    e, i, max_retry
-                                                );
-                                                    e
-                                                });
-                                                if let Ok(rcon) = rcon {
-                                                    info!("Connected to RCON");
-                                                    self.rcon_conn.lock().await.replace(rcon);
-                                                    break;
-                                                }
-                                                tokio::time::sleep(Duration::from_secs(
-                                                    2_u64.pow(i),
-                                                ))
-                                                .await;
-                                            }
-                                        } else {
-                                            warn!("RCON is not enabled or misconfigured, skipping");
-                                            self.rcon_conn.lock().await.take();
-                                        }
+                                        console_matches.push(ConsoleMatch::builtin(
+                                            ConsoleMatchKind::StartupComplete,
+                                            &[],
+                                        ));
+                                        self.on_ready(&cause_by).await;
                                     }
                                     if let Some(system_msg) = parse_system_msg(&line) {
+                                        let mut known_commands = self.known_commands.lock().await;
+                                        if known_commands.is_expecting_help() {
+                                            if let Some((command, usage)) =
+                                                parse_help_line(&system_msg)
+                                            {
+                                                known_commands.learn(command, usage);
+                                            }
+                                        }
+                                        drop(known_commands);
                                         let _ = event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
                                                 instance_event_inner:
                                                     InstanceEventInner::SystemMessage {
-                                                        message: line,
+                                                        message: line.clone(),
                                                     },
                                                 instance_name: name.clone(),
                                             }),
@@ -424,18 +364,37 @@ impl TServer for MinecraftInstance {
                                             snowflake: Snowflake::default(),
                                             caused_by: CausedBy::System,
                                         });
-                                        if let Some(player_name) = parse_player_joined(&system_msg)
+                                        if let Some((player_name, address)) =
+                                            parse_player_login(&system_msg)
+                                        {
+                                            if let Some(location) = parse_client_address(&address)
+                                                .and_then(geoip::lookup)
+                                            {
+                                                login_locations.insert(player_name, location);
+                                            }
+                                        } else if let Some(player_name) =
+                                            console_parser.player_joined(&system_msg)
                                         {
+                                            let location = login_locations.remove(&player_name);
+                                            console_matches.push(ConsoleMatch::builtin(
+                                                ConsoleMatchKind::PlayerJoin,
+                                                &[("player", player_name.as_str())],
+                                            ));
                                             players_manager.lock().await.add_player(
                                                 MinecraftPlayer {
                                                     name: player_name.clone(),
                                                     uuid: name_to_uuid(&player_name).await,
                                                 },
+                                                location,
                                                 self.name().await,
                                             );
                                         } else if let Some(player_name) =
-                                            parse_player_left(&system_msg)
+                                            console_parser.player_left(&system_msg)
                                         {
+                                            console_matches.push(ConsoleMatch::builtin(
+                                                ConsoleMatchKind::PlayerLeave,
+                                                &[("player", player_name.as_str())],
+                                            ));
                                             players_manager
                                                 .lock()
                                                 .await
@@ -459,17 +418,82 @@ impl TServer for MinecraftInstance {
                                             caused_by: CausedBy::System,
                                         });
                                     }
+                                    for console_match in console_matches {
+                                        event_broadcaster.send(console_match.into_event(
+                                            uuid.clone(),
+                                            name.clone(),
+                                            &line,
+                                        ));
+                                    }
                                 } else {
                                     break;
                                 }
                             }
                         }
                         info!("Instance {} process shutdown", name);
+                        // the process is taken out by `kill`, so a process still present here
+                        // exited on its own or was killed by someone else
+                        let exit_status = match self.process.lock().await.as_mut() {
+                            Some(proc) => proc.wait().await.ok(),
+                            None => None,
+                        };
+                        let is_stopping = *self.state.lock().await == State::Stopping;
+                        let killed_by_os = !is_stopping
+                            && exit_status.map_or(false, |status| is_killed_by_sigkill(&status));
+                        if killed_by_os || jvm_out_of_memory {
+                            let message = if killed_by_os {
+                                "The server process was killed by the operating system, most likely because the host ran out of memory. Consider lowering the maximum RAM of this or other instances, or adding more memory or swap to the host".to_string()
+                            } else {
+                                format!("The server ran out of Java heap memory. Consider increasing the maximum RAM of this instance (currently {} MB)", config.max_ram)
+                            };
+                            error!("[{}] {}", name, message);
+                            event_broadcaster.send(Event {
+                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                    instance_uuid: uuid.clone(),
+                                    instance_event_inner: InstanceEventInner::InstanceOutOfMemory {
+                                        killed_by_os,
+                                        message,
+                                    },
+                                    instance_name: name.clone(),
+                                }),
+                                details: "".to_string(),
+                                snowflake: Snowflake::default(),
+                                caused_by: CausedBy::System,
+                            });
+                        }
+                        let crashed = !is_stopping
+                            && (killed_by_os
+                                || jvm_out_of_memory
+                                || exit_status.is_some_and(|status| !status.success()));
+                        if crashed {
+                            let exit_code = exit_status.and_then(|status| status.code());
+                            error!(
+                                "[{}] Server process crashed with exit code {:?}",
+                                name, exit_code
+                            );
+                            event_broadcaster.send(Event {
+                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                    instance_uuid: uuid.clone(),
+                                    instance_event_inner: InstanceEventInner::InstanceCrashed {
+                                        exit_code,
+                                        last_output: last_output.into_iter().collect(),
+                                    },
+                                    instance_name: name.clone(),
+                                }),
+                                details: "".to_string(),
+                                snowflake: Snowflake::default(),
+                                caused_by: CausedBy::System,
+                            });
+                        }
                         self.state
                             .lock()
                             .await
                             .try_transition(
-                                StateAction::InstanceStop,
+                                if crashed {
+                                    StateAction::InstanceCrash
+                                } else {
+                                    StateAction::InstanceStop
+                                },
                                 Some(&|state| {
                                     self.event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
@@ -486,7 +510,28 @@ impl TServer for MinecraftInstance {
                                 }),
                             )
                             .unwrap();
+                        self.readiness.stopped();
                         self.players_manager.lock().await.clear(name);
+                        if crashed && self.restart_on_crash.load(Ordering::Relaxed) {
+                            self.schedule_crash_restart(started_at.elapsed()).await;
+                        }
+                    }
+                });
+                // servers printing something else than the usual "Done" line are noticed by
+                // pinging them
+                tokio::task::spawn({
+                    let __self = self.clone();
+                    let cause_by = cause_by.clone();
+                    let port = config.port as u16;
+                    async move {
+                        while self.state().await == State::Starting {
+                            tokio::time::sleep(READINESS_PING_INTERVAL).await;
+                            if self.state().await == State::Starting
+                                && status_ping(port, READINESS_PING_INTERVAL).await.is_ok()
+                            {
+                                self.on_ready(&cause_by).await;
+                            }
+                        }
                     }
                 });
                 self.config.lock().await.has_started = true;
@@ -505,7 +550,7 @@ impl TServer for MinecraftInstance {
                             if instance_uuid == event_instance_uuid {
                                 if to == State::Running {
                                     return Ok(()); // Instance started successfully
-                                } else if to == State::Stopped {
+                                } else if to.is_stopped() {
                                     return Err(eyre!(
                                         "Instance exited unexpectedly before starting"
                                     )
@@ -594,7 +639,7 @@ impl TServer for MinecraftInstance {
                     ..
                 }) = event.event_inner
                 {
-                    if instance_uuid == event_instance_uuid && to == State::Stopped {
+                    if instance_uuid == event_instance_uuid && to.is_stopped() {
                         return Ok(());
                     }
                 }
@@ -627,13 +672,12 @@ impl TServer for MinecraftInstance {
     async fn kill(&mut self, _cause_by: CausedBy) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
 
-        if self.state().await == State::Stopped {
+        if self.state().await.is_stopped() {
             warn!("[{}] Instance is already stopped", config.name.clone());
             return Err(eyre!("Instance is already stopped").into());
         }
-        self.process
-            .lock()
-            .await
+        let mut process = self.process.lock().await;
+        process
             .as_mut()
             .ok_or_else(|| {
                 error!(
@@ -649,6 +693,8 @@ impl TServer for MinecraftInstance {
                 error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
                 e
             })?;
+        // so the exit of the process isn't mistaken for an OOM kill or a crash
+        process.take();
         Ok(())
     }
 
@@ -656,11 +702,37 @@ impl TServer for MinecraftInstance {
         *self.state.lock().await
     }
 
+    async fn readiness(&self) -> Readiness {
+        self.readiness.readiness()
+    }
+
     async fn send_command(&self, command: &str, cause_by: CausedBy) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
-        if self.state().await == State::Stopped {
+        if self.state().await.is_stopped() {
             Err(eyre!("Instance is stopped").into())
         } else {
+            // stopping changes the state, and the answer to help is parsed from the console
+            if config.control_channel == ControlChannel::Network
+                && command != "stop"
+                && !is_help_command(command)
+            {
+                match self.send_rcon(command).await {
+                    Ok(response) => {
+                        for line in response.lines().filter(|line| !line.is_empty()) {
+                            self.event_broadcaster.send(Event::new_instance_output(
+                                self.uuid.clone(),
+                                config.name.clone(),
+                                line.to_string(),
+                            ));
+                        }
+                        return Ok(());
+                    }
+                    Err(e) => warn!(
+                        "[{}] Failed to send command over RCON, sending it to the console : {}",
+                        config.name, e
+                    ),
+                }
+            }
             match self.stdin.lock().await.as_mut() {
                 Some(stdin) => match {
                     if command == "stop" {
@@ -682,6 +754,9 @@ impl TServer for MinecraftInstance {
                             }),
                         )?;
                     }
+                    if is_help_command(command) {
+                        self.known_commands.lock().await.help_sent();
+                    }
                     stdin.write_all(format!("{}\n", command).as_bytes()).await
                 } {
                     Ok(_) => Ok(()),
@@ -704,6 +779,14 @@ impl TServer for MinecraftInstance {
             }
         }
     }
+    async fn pid(&self) -> Option<u32> {
+        self.process.lock().await.as_ref().and_then(|p| p.id())
+    }
+
+    async fn known_commands(&self) -> Vec<CommandInfo> {
+        self.known_commands.lock().await.commands()
+    }
+
     async fn monitor(&self) -> MonitorReport {
         let mut sys = self.system.lock().await;
         sys.refresh_memory();
@@ -717,11 +800,17 @@ impl TServer for MinecraftInstance {
                 let memory_usage = proc.memory();
                 let disk_usage = proc.disk_usage();
                 let start_time = proc.start_time();
+                drop(sys);
+                let port = self.config.lock().await.port;
+                let network_usage = self.network_usage_tracker.lock().await.sample(port).await;
                 MonitorReport {
                     memory_usage: Some(memory_usage),
                     disk_usage: Some(disk_usage.into()),
                     cpu_usage: Some(cpu_usage),
                     start_time: Some(start_time),
+                    network_usage,
+                    directory_size: None,
+                    game_metrics: self.game_metrics().await,
                 }
             } else {
                 MonitorReport::default()
@@ -731,3 +820,148 @@ impl TServer for MinecraftInstance {
         }
     }
 }
+
+impl MinecraftInstance {
+    /// Called once the server is done starting, whichever of its console or a status ping
+    /// tells first
+    async fn on_ready(&self, cause_by: &CausedBy) {
+        let startup_duration = match self.readiness.mark_ready() {
+            Some(startup_duration) => startup_duration,
+            None => return,
+        };
+        self.reset_game_metrics().await;
+        let name = self.name().await;
+        self.state
+            .lock()
+            .await
+            .try_transition(
+                StateAction::InstanceStart,
+                Some(&|state| {
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_name: name.clone(),
+                            instance_uuid: self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                        }),
+                        snowflake: Snowflake::default(),
+                        details: "Starting server".to_string(),
+                        caused_by: cause_by.clone(),
+                    });
+                }),
+            )
+            .unwrap();
+        self.event_broadcaster.send(Event::new_instance_ready(
+            self.uuid.clone(),
+            name,
+            startup_duration,
+        ));
+
+        if let (Some(true), Some(rcon_psw), Some(rcon_port)) = {
+            let lock = self.configurable_manifest.lock().await;
+
+            let a = lock
+                .get_unique_setting_key("enable-rcon")
+                .and_then(|v| v.get_value().map(|v| v.try_as_boolean().ok()))
+                .flatten();
+
+            let b = lock
+                .get_unique_setting_key("rcon.password")
+                .and_then(|v| v.get_value().map(|v| v.try_as_string().ok()))
+                .flatten()
+                .cloned();
+
+            let c = lock
+                .get_unique_setting_key("rcon.port")
+                .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer().ok()))
+                .flatten();
+            (a, b, c)
+        } {
+            let max_retry = 3;
+            for i in 0..max_retry {
+                let rcon = <rcon::Connection<tokio::net::TcpStream>>::builder()
+                    .enable_minecraft_quirks(true)
+                    .connect(&format!("localhost:{}", rcon_port), &rcon_psw)
+                    .await
+                    .map_err(|e| {
+                        warn!(
+                            "Failed to connect to RCON: {}, retry {}/{}",
+                            e, i, max_retry
+                        );
+                        e
+                    });
+                if let Ok(rcon) = rcon {
+                    info!("Connected to RCON");
+                    self.rcon_conn.lock().await.replace(rcon);
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(2_u64.pow(i))).await;
+            }
+        } else {
+            warn!("RCON is not enabled or misconfigured, skipping");
+            self.rcon_conn.lock().await.take();
+        }
+    }
+
+    /// Start the instance again after a crash, as per its crash restart policy
+    async fn schedule_crash_restart(&self, uptime: Duration) {
+        let config = self.config.lock().await.clone();
+        let policy = config.crash_restart_policy;
+        if uptime >= Duration::from_secs(policy.stable_after as u64) {
+            self.crash_restarts.store(0, Ordering::SeqCst);
+        }
+        let retries = self.crash_restarts.load(Ordering::SeqCst);
+        let delay = match policy.restart_delay(retries) {
+            Some(delay) => delay,
+            None => {
+                let message = format!("Not restarting the server after {retries} crashes in a row");
+                warn!("[{}] {}", config.name, message);
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::InstanceError { message },
+                        instance_name: config.name.clone(),
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by: CausedBy::System,
+                });
+                return;
+            }
+        };
+        self.crash_restarts.store(retries + 1, Ordering::SeqCst);
+        info!(
+            "[{}] Restarting after a crash in {}s, retry {}/{}",
+            config.name,
+            delay,
+            retries + 1,
+            policy.max_retries
+        );
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::RestartingAfterCrash {
+                    retry: retries + 1,
+                    max_retries: policy.max_retries,
+                    delay,
+                },
+                instance_name: config.name.clone(),
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        });
+        let mut instance = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(delay as u64)).await;
+            // started by hand or restarts turned off in the meantime
+            if instance.state().await != State::Crashed
+                || !instance.restart_on_crash.load(Ordering::Relaxed)
+            {
+                return;
+            }
+            if let Err(e) = instance.start(CausedBy::System, false).await {
+                error!("[{}] Failed to restart after a crash : {}", config.name, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,127 @@
+use indexmap::IndexMap;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    traits::{
+        t_configurable::manifest::{ConfigurableValue, SettingManifest},
+        t_server::{State, TServer},
+    },
+};
+
+use super::{configurable::ServerPropertySetting, MinecraftInstance};
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct ServerPropertiesUpdate {
+    /// keys whose value changed
+    pub changed: Vec<String>,
+    /// the server only reads its properties when it starts, so a running server needs a restart
+    pub restart_required: bool,
+}
+
+/// Parse `value` as the type of the property `key`, unknown keys are kept as strings
+fn typed_property(key: &str, value: &ConfigurableValue) -> Result<SettingManifest, Error> {
+    ServerPropertySetting::from_key_val(key, &value.to_string())
+        .map(SettingManifest::from)
+        .map_err(|e| Error {
+            kind: ErrorKind::BadRequest,
+            source: e.source,
+        })
+}
+
+impl MinecraftInstance {
+    /// The properties in server.properties, with their type and description
+    pub async fn server_properties(&mut self) -> Result<IndexMap<String, SettingManifest>, Error> {
+        self.configurable_manifest
+            .lock()
+            .await
+            .clear_section(ServerPropertySetting::get_section_id());
+        self.read_properties().await?;
+        Ok(self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_section(ServerPropertySetting::get_section_id())
+            .map(|section| section.all_settings().clone())
+            .unwrap_or_default())
+    }
+
+    /// Set properties in server.properties, nothing is written if one of the values is invalid
+    pub async fn update_server_properties(
+        &mut self,
+        values: IndexMap<String, ConfigurableValue>,
+    ) -> Result<ServerPropertiesUpdate, Error> {
+        let current = self.server_properties().await?;
+        let mut settings = Vec::new();
+        for (key, value) in values.iter() {
+            let setting = typed_property(key, value)?;
+            if current.get(key).and_then(|setting| setting.get_value()) != setting.get_value() {
+                settings.push(setting);
+            }
+        }
+        if settings.is_empty() {
+            return Ok(ServerPropertiesUpdate {
+                changed: Vec::new(),
+                restart_required: false,
+            });
+        }
+        let changed = settings
+            .iter()
+            .map(|setting| setting.get_identifier().clone())
+            .collect();
+        {
+            let mut manifest = self.configurable_manifest.lock().await;
+            for setting in settings {
+                manifest.set_setting(ServerPropertySetting::get_section_id(), setting)?;
+            }
+        }
+        self.sync_configurable_to_restore_config().await;
+        self.write_config_to_file().await?;
+        self.write_properties_to_file().await?;
+        Ok(ServerPropertiesUpdate {
+            changed,
+            restart_required: !self.state().await.is_stopped(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::typed_property;
+    use crate::traits::t_configurable::manifest::ConfigurableValue;
+
+    #[test]
+    fn test_typed_property() {
+        let difficulty =
+            typed_property("difficulty", &ConfigurableValue::String("hard".to_string())).unwrap();
+        assert_eq!(
+            difficulty.get_value(),
+            Some(&ConfigurableValue::Enum("hard".to_string()))
+        );
+        assert!(typed_property(
+            "difficulty",
+            &ConfigurableValue::String("impossible".to_string())
+        )
+        .is_err());
+
+        let port =
+            typed_property("server-port", &ConfigurableValue::UnsignedInteger(25570)).unwrap();
+        assert_eq!(
+            port.get_value(),
+            Some(&ConfigurableValue::UnsignedInteger(25570))
+        );
+        assert!(typed_property("server-port", &ConfigurableValue::UnsignedInteger(70000)).is_err());
+
+        let unknown = typed_property(
+            "custom-key",
+            &ConfigurableValue::String("value".to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            unknown.get_value(),
+            Some(&ConfigurableValue::String("value".to_string()))
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! The configuration of an instance as a JSON document to share, without its worlds or any
+//! file: the setup, the server properties and the mods, which are referenced by their Modrinth
+//! download and fetched again by whoever creates an instance from it.
+
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    diagnostics::is_secret_key,
+    error::{Error, ErrorKind},
+    traits::t_configurable::manifest::{ConfigurableValue, SetupValue},
+};
+
+use super::{
+    mods::{find_mod, ModrinthFile, ModrinthVersion},
+    FlavourKind, MinecraftInstance,
+};
+
+/// bumped when the document changes in a way older cores can't read
+pub const SHARED_CONFIG_FORMAT: u32 = 1;
+/// properties tied to the host the server runs on, left out of the document
+const HOST_PROPERTIES: [&str; 5] = [
+    "server-port",
+    "server-ip",
+    "query.port",
+    "rcon.port",
+    "rcon.password",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct SharedMod {
+    pub file_name: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub enabled: bool,
+    pub sha1: String,
+    /// the Modrinth download of the jar, `None` for mods that aren't on Modrinth, which have to
+    /// be added by hand
+    pub download_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SharedInstanceConfig {
+    pub format: u32,
+    pub flavour: FlavourKind,
+    pub setup_value: SetupValue,
+    pub server_properties: IndexMap<String, ConfigurableValue>,
+    pub mods: Vec<SharedMod>,
+}
+
+fn is_shareable_property(key: &str) -> bool {
+    !HOST_PROPERTIES.contains(&key) && !is_secret_key(key)
+}
+
+fn sha1_of_file(path: &Path) -> Result<String, Error> {
+    let content = std::fs::read(path).context(format!("Failed to read {}", path.display()))?;
+    Ok(format!("{:x}", Sha1::digest(&content)))
+}
+
+/// The Modrinth files with the given sha1 hashes, by hash. Files Modrinth doesn't know are left
+/// out
+async fn modrinth_files_by_sha1(hashes: &[String]) -> Result<HashMap<String, ModrinthFile>, Error> {
+    if hashes.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let versions: HashMap<String, ModrinthVersion> = reqwest::Client::new()
+        .post("https://api.modrinth.com/v2/version_files")
+        .json(&json!({ "hashes": hashes, "algorithm": "sha1" }))
+        .send()
+        .await
+        .context("Failed to reach Modrinth")?
+        .error_for_status()
+        .context("Failed to look the mods up on Modrinth")?
+        .json()
+        .await
+        .context("Failed to parse the versions of the mods")?;
+    Ok(versions
+        .into_iter()
+        .filter_map(|(hash, version)| {
+            let file = version
+                .files
+                .into_iter()
+                .find(|file| file.hashes.get("sha1") == Some(&hash))?;
+            Some((hash, file))
+        })
+        .collect())
+}
+
+impl MinecraftInstance {
+    pub async fn shared_config(&mut self) -> Result<SharedInstanceConfig, Error> {
+        let (flavour, setup_value) = self.setup_value().await;
+        let server_properties = self
+            .server_properties()
+            .await?
+            .into_iter()
+            .filter(|(key, _)| is_shareable_property(key))
+            .filter_map(|(key, setting)| setting.get_value().cloned().map(|value| (key, value)))
+            .collect();
+        // servers without mods share none
+        let (mods_dir, mods) = match self.path_to_mods().await {
+            Ok(mods_dir) => (mods_dir, self.list_mods().await?),
+            Err(_) => {
+                return Ok(SharedInstanceConfig {
+                    format: SHARED_CONFIG_FORMAT,
+                    flavour,
+                    setup_value,
+                    server_properties,
+                    mods: Vec::new(),
+                })
+            }
+        };
+        let hashes = {
+            let file_names: Vec<String> = mods.iter().map(|info| info.file_name.clone()).collect();
+            tokio::task::spawn_blocking(move || {
+                file_names
+                    .iter()
+                    .map(|file_name| sha1_of_file(&find_mod(&mods_dir, file_name)?))
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .await
+            .context("Failed to hash the mods")??
+        };
+        // the document is still worth sharing without the downloads
+        let mut files = modrinth_files_by_sha1(&hashes).await.unwrap_or_else(|e| {
+            warn!("Failed to find the mods on Modrinth : {e}");
+            HashMap::new()
+        });
+        let mods = mods
+            .into_iter()
+            .zip(hashes)
+            .map(|(info, sha1)| SharedMod {
+                download_url: files.remove(&sha1).map(|file| file.url),
+                file_name: info.file_name,
+                name: info.metadata.name,
+                version: info.metadata.version,
+                enabled: info.enabled,
+                sha1,
+            })
+            .collect();
+        Ok(SharedInstanceConfig {
+            format: SHARED_CONFIG_FORMAT,
+            flavour,
+            setup_value,
+            server_properties,
+            mods,
+        })
+    }
+
+    /// Set the properties and install the mods of a shared config, returns the mods that have to
+    /// be added by hand
+    pub async fn apply_shared_config(
+        &mut self,
+        config: &SharedInstanceConfig,
+        on_mod: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<Vec<String>, Error> {
+        let properties: IndexMap<String, ConfigurableValue> = config
+            .server_properties
+            .iter()
+            .filter(|(key, _)| is_shareable_property(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        if !properties.is_empty() {
+            self.update_server_properties(properties).await?;
+        }
+        let mut missing = Vec::new();
+        for shared_mod in &config.mods {
+            let download_url = match &shared_mod.download_url {
+                Some(download_url) => download_url,
+                None => {
+                    missing.push(shared_mod.file_name.clone());
+                    continue;
+                }
+            };
+            on_mod(&shared_mod.file_name);
+            let info = self.install_mod_from_url(download_url).await?;
+            let path = self.path_to_mods().await?.join(&info.file_name);
+            let sha1 = tokio::task::spawn_blocking(move || sha1_of_file(&path))
+                .await
+                .context("Failed to hash the mod")??;
+            if !sha1.eq_ignore_ascii_case(&shared_mod.sha1) {
+                self.delete_mod(&info.file_name).await?;
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!("{} doesn't match the shared config", info.file_name),
+                });
+            }
+            if !shared_mod.enabled {
+                self.set_mod_enabled(&info.file_name, false).await?;
+            }
+        }
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_shareable_property;
+
+    #[test]
+    fn test_is_shareable_property() {
+        assert!(is_shareable_property("difficulty"));
+        assert!(is_shareable_property("level-seed"));
+        assert!(!is_shareable_property("server-port"));
+        assert!(!is_shareable_property("rcon.password"));
+        assert!(!is_shareable_property("management-server-secret"));
+    }
+}
@@ -0,0 +1,189 @@
+//! The spark profiler, installed from Modrinth as a mod or a plugin. A profiling session is
+//! started from the console, and spark uploads the report to its viewer once it's done.
+
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{CausedBy, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_server::{State, TServer};
+
+use super::mods::{ModInfo, ModrinthVersion};
+use super::{Flavour, MinecraftInstance};
+
+/// spark refuses shorter sessions, and longer ones are better run by hand
+pub const MIN_PROFILE_SECONDS: u32 = 10;
+pub const MAX_PROFILE_SECONDS: u32 = 600;
+/// how long after the session ends the report can take to be uploaded
+const UPLOAD_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// The Modrinth loader of the spark build for `flavour`, `None` if spark doesn't run on it
+fn spark_loader(flavour: &Flavour) -> Option<&'static str> {
+    match flavour {
+        Flavour::Vanilla => None,
+        // quilt loads the fabric build
+        Flavour::Fabric { .. } | Flavour::Quilt { .. } => Some("fabric"),
+        Flavour::Forge { .. } => Some("forge"),
+        Flavour::Paper { .. } | Flavour::Spigot => Some("bukkit"),
+    }
+}
+
+/// The link to the report spark prints once it's uploaded
+pub fn parse_report_url(line: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"https://spark\.lucko\.me/[A-Za-z0-9]+").unwrap();
+    }
+    RE.find(line)
+        .ok()
+        .flatten()
+        .map(|report_url| report_url.as_str().to_string())
+}
+
+fn is_spark(info: &ModInfo) -> bool {
+    [&info.metadata.id, &info.metadata.name]
+        .into_iter()
+        .flatten()
+        .any(|name| name.eq_ignore_ascii_case("spark"))
+}
+
+impl MinecraftInstance {
+    pub async fn spark_installed(&self) -> Result<bool, Error> {
+        Ok(self
+            .list_mods()
+            .await?
+            .iter()
+            .any(|info| info.enabled && is_spark(info)))
+    }
+
+    /// Install the latest spark build for the flavour and version of the server, it's loaded on
+    /// the next start
+    pub async fn install_spark(&self) -> Result<ModInfo, Error> {
+        let flavour = self.flavour().await;
+        let loader = spark_loader(&flavour).ok_or_else(|| Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("spark does not run on {} servers", flavour.to_string()),
+        })?;
+        if let Some(installed) = self.list_mods().await?.into_iter().find(is_spark) {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("spark is already installed as {}", installed.file_name),
+            });
+        }
+        let version = self.config.lock().await.version.clone();
+        let versions: Vec<ModrinthVersion> = reqwest::Client::new()
+            .get("https://api.modrinth.com/v2/project/spark/version")
+            .query(&[
+                ("loaders", format!("[\"{loader}\"]")),
+                ("game_versions", format!("[\"{version}\"]")),
+            ])
+            .send()
+            .await
+            .context("Failed to reach Modrinth")?
+            .error_for_status()
+            .context("Failed to list the versions of spark")?
+            .json()
+            .await
+            .context("Failed to parse the versions of spark")?;
+        // Modrinth lists the newest version first
+        let file = versions
+            .iter()
+            .find_map(ModrinthVersion::primary_file)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("There is no spark build for {loader} {version}"),
+            })?;
+        self.install_mod_from_url(&file.url).await
+    }
+
+    /// Profile the server for `seconds`, returns the link to the report
+    pub async fn profile_with_spark(
+        &self,
+        seconds: u32,
+        event_broadcaster: &EventBroadcaster,
+        caused_by: CausedBy,
+    ) -> Result<String, Error> {
+        if !(MIN_PROFILE_SECONDS..=MAX_PROFILE_SECONDS).contains(&seconds) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Profiling takes between {MIN_PROFILE_SECONDS} and {MAX_PROFILE_SECONDS} seconds"
+                ),
+            });
+        }
+        if *self.state.lock().await != State::Running {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server must be running to be profiled"),
+            });
+        }
+        if !self.spark_installed().await? {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("spark is not installed, install it and restart the server first"),
+            });
+        }
+        // subscribed before the command is sent so the report can't be missed
+        let mut rx = event_broadcaster.subscribe();
+        self.send_command(
+            &format!("spark profiler start --timeout {seconds}"),
+            caused_by,
+        )
+        .await?;
+        let wait_for_report = async {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if let EventInner::InstanceEvent(InstanceEvent {
+                            instance_uuid,
+                            instance_event_inner: InstanceEventInner::InstanceOutput { message },
+                            ..
+                        }) = event.event_inner
+                        {
+                            if instance_uuid != self.uuid {
+                                continue;
+                            }
+                            if let Some(report_url) = parse_report_url(&message) {
+                                return Ok(report_url);
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        return Err(Error::from(eyre!("Stopped listening for the report")))
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(
+            Duration::from_secs(seconds as u64) + UPLOAD_GRACE_PERIOD,
+            wait_for_report,
+        )
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::Internal,
+            source: eyre!("spark did not report back, check the console"),
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_report_url;
+
+    #[test]
+    fn test_parse_report_url() {
+        assert_eq!(
+            parse_report_url("[12:00:00 INFO]: [⚡] https://spark.lucko.me/a1B2c3D4e5"),
+            Some("https://spark.lucko.me/a1B2c3D4e5".to_string())
+        );
+        assert_eq!(
+            parse_report_url("[12:00:00 INFO]: [⚡] Profiler stopped & upload complete!"),
+            None
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Context};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::error::Error;
+
+/// -1, servers answer the status of any protocol version
+static ANY_PROTOCOL_VERSION: i32 = -1;
+/// status responses are JSON documents of a few kilobytes, favicon included
+static MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+/// some servers close the connection after the status rather than answering pings
+static PONG_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+async fn read_var_int(stream: &mut TcpStream) -> Result<i32, Error> {
+    let mut value = 0_u32;
+    for i in 0..5 {
+        let byte = stream
+            .read_u8()
+            .await
+            .context("Failed to read the status response")?;
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(eyre!("Invalid varint in the status response").into())
+}
+
+/// A packet prefixed with its length
+fn packet(id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_var_int(&mut body, id);
+    body.extend_from_slice(data);
+    let mut packet = Vec::new();
+    write_var_int(&mut packet, body.len() as i32);
+    packet.extend(body);
+    packet
+}
+
+/// The status of the server and the round trip time of a ping after it, or of the status request
+/// if the server doesn't answer pings
+async fn ping(host: &str, port: u16) -> Result<(serde_json::Value, Duration), Error> {
+    let mut stream = TcpStream::connect((host, port))
+        .await
+        .context("Failed to connect to the server")?;
+    let mut handshake = Vec::new();
+    write_var_int(&mut handshake, ANY_PROTOCOL_VERSION);
+    let address = host.as_bytes();
+    write_var_int(&mut handshake, address.len() as i32);
+    handshake.extend_from_slice(address);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    // next state: status
+    write_var_int(&mut handshake, 1);
+    let mut request = packet(0x00, &handshake);
+    request.extend(packet(0x00, &[]));
+    let sent_at = Instant::now();
+    stream
+        .write_all(&request)
+        .await
+        .context("Failed to send the status request")?;
+
+    let length = read_var_int(&mut stream).await?;
+    if length <= 0 || length as usize > MAX_RESPONSE_SIZE {
+        return Err(eyre!("Invalid status response length {length}").into());
+    }
+    let id = read_var_int(&mut stream).await?;
+    if id != 0x00 {
+        return Err(eyre!("Unexpected packet {id} in place of the status response").into());
+    }
+    let json_length = read_var_int(&mut stream).await?;
+    if json_length < 0 || json_length as usize > MAX_RESPONSE_SIZE {
+        return Err(eyre!("Invalid status response length {json_length}").into());
+    }
+    let mut json = vec![0; json_length as usize];
+    stream
+        .read_exact(&mut json)
+        .await
+        .context("Failed to read the status response")?;
+    let status_round_trip = sent_at.elapsed();
+    let status = serde_json::from_slice(&json).context("The status response is not valid json")?;
+    let latency = match tokio::time::timeout(PONG_TIMEOUT, pong(&mut stream)).await {
+        Ok(Ok(latency)) => latency,
+        _ => status_round_trip,
+    };
+    Ok((status, latency))
+}
+
+/// The round trip time of a ping packet, the server answers with the same payload
+async fn pong(stream: &mut TcpStream) -> Result<Duration, Error> {
+    let payload = chrono::Utc::now().timestamp_millis().to_be_bytes();
+    let sent_at = Instant::now();
+    stream
+        .write_all(&packet(0x01, &payload))
+        .await
+        .context("Failed to send the ping")?;
+    let length = read_var_int(stream).await?;
+    let id = read_var_int(stream).await?;
+    if length != 9 || id != 0x01 {
+        return Err(eyre!("Unexpected packet {id} in place of the pong").into());
+    }
+    let mut echoed = [0; 8];
+    stream
+        .read_exact(&mut echoed)
+        .await
+        .context("Failed to read the pong")?;
+    Ok(sent_at.elapsed())
+}
+
+/// Ask the minecraft server on `port` of the host for its status, as shown in the server list.
+///
+/// The server only answers once it is done starting, unlike a bare connection that is accepted
+/// while the world loads.
+pub async fn status_ping(port: u16, timeout: Duration) -> Result<serde_json::Value, Error> {
+    status_ping_host("127.0.0.1", port, timeout)
+        .await
+        .map(|(status, _)| status)
+}
+
+/// Ask the minecraft server at `host:port` for its status, along with the latency to it
+pub async fn status_ping_host(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<(serde_json::Value, Duration), Error> {
+    tokio::time::timeout(timeout, ping(host, port))
+        .await
+        .map_err(|_| eyre!("The server did not answer the status request in time"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{packet, status_ping, write_var_int};
+
+    #[test]
+    fn test_write_var_int() {
+        let encode = |value| {
+            let mut buf = Vec::new();
+            write_var_int(&mut buf, value);
+            buf
+        };
+        assert_eq!(encode(0), vec![0x00]);
+        assert_eq!(encode(127), vec![0x7f]);
+        assert_eq!(encode(128), vec![0x80, 0x01]);
+        assert_eq!(encode(25565), vec![0xdd, 0xc7, 0x01]);
+        assert_eq!(encode(-1), vec![0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[tokio::test]
+    async fn test_status_ping() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 64];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let json =
+                br#"{"version":{"name":"1.20.1","protocol":763},"players":{"max":20,"online":0}}"#;
+            let mut data = Vec::new();
+            write_var_int(&mut data, json.len() as i32);
+            data.extend_from_slice(json);
+            stream.write_all(&packet(0x00, &data)).await.unwrap();
+        });
+        let status = status_ping(port, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status["version"]["name"], "1.20.1");
+        assert_eq!(status["players"]["max"], 20);
+    }
+}
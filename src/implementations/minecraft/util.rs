@@ -1,13 +1,20 @@
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use indexmap::IndexMap;
 use serde_json::{self, Value};
-use std::{collections::BTreeMap, path::Path, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::io::AsyncBufReadExt;
 
 use super::{
-    FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
+    quilt::{get_quilt_installer_url, get_quilt_loader_versions},
+    FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, LaunchTarget,
+    PaperBuildVersion, QuiltLoaderVersion,
 };
 use crate::error::Error;
+use crate::util::{dont_spawn_terminal, list_dir};
 
 pub async fn read_properties_from_path(
     path_to_properties: &Path,
@@ -61,6 +68,7 @@ pub async fn get_server_jar_url(version: &str, flavour: &Flavour) -> Option<(Str
         Flavour::Paper { build_version } => get_paper_jar_url(version, build_version).await,
         Flavour::Spigot => todo!(),
         Flavour::Forge { build_version } => get_forge_jar_url(version, build_version).await.ok(),
+        Flavour::Quilt { loader_version } => get_quilt_jar_url(version, loader_version).await.ok(),
     }
 }
 
@@ -109,8 +117,15 @@ pub async fn get_fabric_jar_url(
     fabric_loader_version: &Option<FabricLoaderVersion>,
     fabric_installer_version: &Option<FabricInstallerVersion>,
 ) -> Option<(String, Flavour)> {
-    let mut loader_version = String::new();
-    let mut installer_version = String::new();
+    // a pinned version is kept, whatever is not pinned is resolved to the latest stable one
+    let mut loader_version = fabric_loader_version
+        .as_ref()
+        .map(|FabricLoaderVersion(l)| l.to_string())
+        .unwrap_or_default();
+    let mut installer_version = fabric_installer_version
+        .as_ref()
+        .map(|FabricInstallerVersion(i)| i.to_string())
+        .unwrap_or_default();
     let client = reqwest::Client::new();
 
     if let (Some(FabricLoaderVersion(l)), Some(FabricInstallerVersion(i))) =
@@ -355,19 +370,185 @@ pub async fn get_forge_jar_url(
     ))
 }
 
-pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
-    let client = reqwest::Client::new();
-    let os = if std::env::consts::OS == "macos" {
-        "mac"
-    } else {
-        std::env::consts::OS
+/// The url of the quilt installer, which sets up the server for `version`
+pub async fn get_quilt_jar_url(
+    version: &str,
+    quilt_loader_version: &Option<QuiltLoaderVersion>,
+) -> Result<(String, Flavour), Error> {
+    let loader_version = match quilt_loader_version {
+        Some(QuiltLoaderVersion(l)) => l.clone(),
+        // betas are suffixed with -beta.N
+        None => get_quilt_loader_versions(version)
+            .await?
+            .into_iter()
+            .find(|l| !l.contains('-'))
+            .context("Failed to get quilt loader versions, no stable loader found")?,
     };
-    let arch = if std::env::consts::ARCH == "x86_64" {
-        "x64"
-    } else {
-        std::env::consts::ARCH
+    Ok((
+        get_quilt_installer_url().await?,
+        Flavour::Quilt {
+            loader_version: Some(QuiltLoaderVersion(loader_version)),
+        },
+    ))
+}
+
+/// The first jar in `path_to_instance` whose name starts with `prefix`
+async fn find_jar(path_to_instance: &Path, prefix: &str) -> Result<Option<PathBuf>, Error> {
+    Ok(list_dir(path_to_instance, Some(false))
+        .await?
+        .into_iter()
+        .filter_map(|path| path.file_name().map(PathBuf::from))
+        .find(|name| {
+            name.extension().unwrap_or_default() == "jar"
+                && name.to_string_lossy().starts_with(prefix)
+        }))
+}
+
+/// What starts the server an installer left in `path_to_instance`
+pub async fn detect_launch_target(
+    path_to_instance: &Path,
+    flavour: &Flavour,
+    version: &str,
+) -> Result<LaunchTarget, Error> {
+    match flavour {
+        Flavour::Forge { build_version } => {
+            let ForgeBuildVersion(build_version) = build_version
+                .as_ref()
+                .ok_or_else(|| eyre!("Forge version not found"))?;
+            let major_version: i32 = version
+                .split('.')
+                .nth(1)
+                .unwrap_or_default()
+                .parse()
+                .context("Unable to parse major Minecraft version for Forge")?;
+
+            if 17 <= major_version {
+                let forge_args = match std::env::consts::OS {
+                    "windows" => "win_args.txt",
+                    _ => "unix_args.txt",
+                };
+                Ok(LaunchTarget::ArgsFile {
+                    args_file: PathBuf::from("libraries")
+                        .join("net")
+                        .join("minecraftforge")
+                        .join("forge")
+                        .join(build_version.as_str())
+                        .join(forge_args),
+                })
+            } else if (7..=16).contains(&major_version) {
+                let jar = find_jar(path_to_instance, &format!("forge-{}-", version))
+                    .await?
+                    .ok_or_else(|| eyre!("Failed to find forge.jar"))?;
+                Ok(LaunchTarget::Jar { jar })
+            } else {
+                // 1.5 doesn't work due to JRE issues
+                // 1.4 doesn't work since forge doesn't provide an installer
+                let jar = find_jar(path_to_instance, "minecraftforge")
+                    .await?
+                    .ok_or_else(|| eyre!("Failed to find minecraftforge.jar"))?;
+                Ok(LaunchTarget::Jar { jar })
+            }
+        }
+        // the launcher runs the vanilla server.jar next to it
+        Flavour::Quilt { .. } if path_to_instance.join("quilt-server-launch.jar").is_file() => {
+            Ok(LaunchTarget::Jar {
+                jar: PathBuf::from("quilt-server-launch.jar"),
+            })
+        }
+        _ => Ok(LaunchTarget::Jar {
+            jar: PathBuf::from("server.jar"),
+        }),
+    }
+}
+
+/// The os and architecture Adoptium publishes JREs for, in order of preference, for a host.
+///
+/// ARM macs and Windows machines can emulate x64, so they fall back to it for the versions without
+/// an ARM build, e.g. Java 8 on macs. Empty if Adoptium has no JREs for the host at all.
+fn jre_platforms_for(os: &str, arch: &str, musl: bool) -> Vec<(&'static str, &'static str)> {
+    let adoptium_os = match os {
+        "linux" if musl => "alpine-linux",
+        "linux" => "linux",
+        "macos" => "mac",
+        "windows" => "windows",
+        _ => return Vec::new(),
+    };
+    let adoptium_arch = match arch {
+        "x86_64" => "x64",
+        "x86" => "x32",
+        "aarch64" => "aarch64",
+        "arm" => "arm",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64le",
+        "powerpc64" => "ppc64",
+        "s390x" => "s390x",
+        "riscv64" => "riscv64",
+        _ => return Vec::new(),
     };
+    let mut platforms = vec![(adoptium_os, adoptium_arch)];
+    if adoptium_arch == "aarch64" && matches!(adoptium_os, "mac" | "windows") {
+        platforms.push((adoptium_os, "x64"));
+    }
+    platforms
+}
+
+/// The platforms to look for a JRE for on this host, see [`jre_platforms_for`]
+pub fn jre_platforms() -> Vec<(&'static str, &'static str)> {
+    jre_platforms_for(
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        cfg!(target_env = "musl"),
+    )
+}
+
+pub fn get_jre_url(major_java_version: u64, (os, arch): (&str, &str)) -> String {
+    format!(
+        "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
+        major_java_version, os, arch
+    )
+}
+
+/// The major version of `java -version` output, `1.8.0_292` being 8
+pub fn parse_java_major_version(output: &str) -> Option<u64> {
+    let version = output.split('"').nth(1)?;
+    let mut parts = version.split(['.', '_', '-', '+']);
+    match parts.next()? {
+        "1" => parts.next()?.parse().ok(),
+        major => major.parse().ok(),
+    }
+}
+
+/// The major version of the java at `java`, `None` if it doesn't run
+pub async fn java_major_version(java: &Path) -> Option<u64> {
+    let output = dont_spawn_terminal(tokio::process::Command::new(java).arg("-version"))
+        .output()
+        .await
+        .ok()?;
+    // java -version prints to stderr
+    parse_java_major_version(&String::from_utf8_lossy(&output.stderr))
+}
 
+/// The java of the host, from `JAVA_HOME` or the PATH, with its major version, if it is at least
+/// `min_major_version`
+pub async fn find_system_java(min_major_version: u64) -> Option<(PathBuf, u64)> {
+    let mut candidates = Vec::new();
+    if let Some(java_home) = std::env::var_os("JAVA_HOME") {
+        candidates.push(PathBuf::from(java_home).join("bin").join("java"));
+    }
+    candidates.push(PathBuf::from("java"));
+    for java in candidates {
+        match java_major_version(&java).await {
+            Some(major_version) if major_version >= min_major_version => {
+                return Some((java, major_version))
+            }
+            _ => continue,
+        }
+    }
+    None
+}
+
+/// The major version of the JRE `version` needs
+pub async fn get_jre_major_version(version: &str) -> Option<u64> {
+    let client = reqwest::Client::new();
     let major_java_version = {
         let val = match serde_json::Value::from_str(
             client
@@ -414,13 +595,7 @@ pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
         }
     };
 
-    Some((
-        format!(
-            "https://api.adoptium.net/v3/binary/latest/{}/ga/{}/{}/jre/hotspot/normal/eclipse",
-            major_java_version, os, arch
-        ),
-        major_java_version,
-    ))
+    Some(major_java_version)
 }
 
 pub async fn name_to_uuid(name: impl AsRef<str>) -> Option<String> {
@@ -440,14 +615,34 @@ pub async fn name_to_uuid(name: impl AsRef<str>) -> Option<String> {
     Some(res["id"].as_str()?.to_owned())
 }
 
+/// The -Xms and -Xmx values in megabytes.
+///
+/// With a memory limit the heap is capped to 3/4 of it, leaving room for the memory the JVM uses
+/// outside of the heap so the process as a whole stays under the limit.
+pub fn heap_sizes(min_ram: u32, max_ram: u32, memory_limit: Option<u64>) -> (u32, u32) {
+    let max_ram = match memory_limit {
+        Some(limit) => max_ram.min((limit * 3 / 4).min(u32::MAX as u64) as u32),
+        None => max_ram,
+    };
+    (min_ram.min(max_ram), max_ram)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::minecraft::{
-        util::{get_forge_jar_url, get_server_jar_url},
+        util::{get_forge_jar_url, get_server_jar_url, heap_sizes},
         FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
     };
     use tokio;
 
+    #[test]
+    fn test_heap_sizes() {
+        assert_eq!(heap_sizes(1024, 4096, None), (1024, 4096));
+        assert_eq!(heap_sizes(1024, 4096, Some(8192)), (1024, 4096));
+        assert_eq!(heap_sizes(1024, 4096, Some(2048)), (1024, 1536));
+        assert_eq!(heap_sizes(1024, 4096, Some(512)), (384, 384));
+    }
+
     #[tokio::test]
     async fn test_get_vanilla_jar_url() {
         assert_eq!(super::get_vanilla_jar_url("1.18.2").await, Some(("https://piston-data.mojang.com/v1/objects/c8f83c5655308435b3dcf03c06d9fe8740a77469/server.jar".to_string(), Flavour::Vanilla)));
@@ -457,17 +652,60 @@ mod tests {
         assert_eq!(super::get_vanilla_jar_url("1.8.4asdasd").await, None);
     }
     #[tokio::test]
-    async fn test_get_jre_url() {
-        let os_str = if std::env::consts::OS == "macos" {
-            "mac"
-        } else {
-            std::env::consts::OS
-        };
-        assert_eq!(super::get_jre_url("1.18.2").await, Some((format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17)));
-        assert_eq!(super::get_jre_url("21w44a").await, Some((format!("https://api.adoptium.net/v3/binary/latest/17/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 17)));
-        assert_eq!(super::get_jre_url("1.8.4").await, Some((format!("https://api.adoptium.net/v3/binary/latest/8/ga/{os_str}/x64/jre/hotspot/normal/eclipse"), 8)));
+    async fn test_get_jre_major_version() {
+        assert_eq!(super::get_jre_major_version("1.18.2").await, Some(17));
+        assert_eq!(super::get_jre_major_version("21w44a").await, Some(17));
+        assert_eq!(super::get_jre_major_version("1.8.4").await, Some(8));
+
+        assert_eq!(super::get_jre_major_version("1.8.4asdasd").await, None);
+    }
+
+    #[test]
+    fn test_jre_platforms() {
+        assert_eq!(
+            super::jre_platforms_for("linux", "x86_64", false),
+            vec![("linux", "x64")]
+        );
+        assert_eq!(
+            super::jre_platforms_for("linux", "aarch64", true),
+            vec![("alpine-linux", "aarch64")]
+        );
+        assert_eq!(
+            super::jre_platforms_for("linux", "arm", false),
+            vec![("linux", "arm")]
+        );
+        assert_eq!(
+            super::jre_platforms_for("macos", "aarch64", false),
+            vec![("mac", "aarch64"), ("mac", "x64")]
+        );
+        assert!(super::jre_platforms_for("freebsd", "x86_64", false).is_empty());
+        assert!(super::jre_platforms_for("linux", "mips", false).is_empty());
+        assert_eq!(
+            super::get_jre_url(17, ("linux", "aarch64")),
+            "https://api.adoptium.net/v3/binary/latest/17/ga/linux/aarch64/jre/hotspot/normal/eclipse"
+        );
+    }
 
-        assert_eq!(super::get_jre_url("1.8.4asdasd").await, None);
+    #[test]
+    fn test_parse_java_major_version() {
+        assert_eq!(
+            super::parse_java_major_version(
+                "openjdk version \"17.0.8\" 2023-07-18\nOpenJDK Runtime Environment"
+            ),
+            Some(17)
+        );
+        assert_eq!(
+            super::parse_java_major_version("java version \"1.8.0_381\""),
+            Some(8)
+        );
+        assert_eq!(
+            super::parse_java_major_version("openjdk version \"21\" 2023-09-19"),
+            Some(21)
+        );
+        assert_eq!(
+            super::parse_java_major_version("java: command not found"),
+            None
+        );
     }
 
     /// Test subject to fail if fabric updates their installer or loader
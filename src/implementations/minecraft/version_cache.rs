@@ -0,0 +1,241 @@
+//! The version lists offered when setting up an instance, cached so the setup form doesn't wait
+//! on Mojang and the loader APIs on every request and still works while they are unreachable.
+//!
+//! A list is fetched again once it is older than `FRESH_FOR`. Until then the cached one is
+//! served, and a stale one is served while it is fetched again in the background.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use color_eyre::eyre::Context;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+use super::{
+    fabric::{get_fabric_loader_versions, get_fabric_minecraft_versions},
+    forge::{get_forge_builds, get_forge_minecraft_versions},
+    paper::get_paper_minecraft_versions,
+    quilt::{get_quilt_loader_versions, get_quilt_minecraft_versions},
+    vanilla::get_vanilla_minecraft_versions,
+};
+
+/// seconds a fetched list is served without fetching it again
+pub const FRESH_FOR: i64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum VersionList {
+    VanillaVersions,
+    FabricVersions,
+    PaperVersions,
+    ForgeVersions,
+    QuiltVersions,
+    FabricLoaderVersions,
+    /// the forge builds for a minecraft version
+    ForgeBuilds {
+        version: String,
+    },
+    QuiltLoaderVersions {
+        version: String,
+    },
+}
+
+impl VersionList {
+    async fn fetch(&self) -> Result<Vec<String>, Error> {
+        match self {
+            VersionList::VanillaVersions => get_vanilla_minecraft_versions().await,
+            VersionList::FabricVersions => get_fabric_minecraft_versions().await,
+            VersionList::PaperVersions => get_paper_minecraft_versions().await,
+            VersionList::ForgeVersions => get_forge_minecraft_versions().await,
+            VersionList::QuiltVersions => get_quilt_minecraft_versions().await,
+            VersionList::FabricLoaderVersions => get_fabric_loader_versions().await,
+            VersionList::ForgeBuilds { version } => get_forge_builds(version).await,
+            VersionList::QuiltLoaderVersions { version } => {
+                get_quilt_loader_versions(version).await
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct CachedVersionList {
+    list: VersionList,
+    versions: Vec<String>,
+    fetched_at: i64,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct VersionListStatus {
+    pub list: VersionList,
+    /// unix timestamp in seconds of the last successful fetch, `None` if it never was
+    pub fetched_at: Option<i64>,
+    pub stale: bool,
+    /// why the list couldn't be fetched, when refreshing
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct VersionCache {
+    /// where the lists are kept across restarts, only in memory if `None`
+    path: Option<PathBuf>,
+    lists: HashMap<VersionList, CachedVersionList>,
+    /// the lists being fetched in the background
+    refreshing: HashSet<VersionList>,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<VersionCache> = Mutex::new(VersionCache::default());
+    /// keeps writes of the cache file in order
+    static ref WRITE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+}
+
+fn is_fresh(cached: &CachedVersionList, now: i64) -> bool {
+    now - cached.fetched_at < FRESH_FOR
+}
+
+/// Keep the lists at `path`, and use the ones fetched before the last restart
+pub async fn load(path: &Path) -> Result<(), Error> {
+    let cached: Vec<CachedVersionList> = if path.is_file() {
+        serde_json::from_str(&crate::util::fs::read_to_string(path).await?)
+            .context("Failed to parse the version cache")?
+    } else {
+        Vec::new()
+    };
+    let mut cache = CACHE.lock().unwrap();
+    cache.path = Some(path.to_owned());
+    for cached in cached {
+        cache.lists.entry(cached.list.clone()).or_insert(cached);
+    }
+    Ok(())
+}
+
+async fn write_to_file() -> Result<(), Error> {
+    let _write_lock = WRITE_LOCK.lock().await;
+    let (path, lists) = {
+        let cache = CACHE.lock().unwrap();
+        match &cache.path {
+            Some(path) => (
+                path.clone(),
+                cache.lists.values().cloned().collect::<Vec<_>>(),
+            ),
+            None => return Ok(()),
+        }
+    };
+    crate::util::fs::write_all(
+        &path,
+        serde_json::to_string(&lists).context("Failed to serialize the version cache")?,
+    )
+    .await
+}
+
+/// Fetch `list` now and cache it
+pub async fn refresh(list: &VersionList) -> Result<Vec<String>, Error> {
+    let versions = list.fetch().await?;
+    CACHE.lock().unwrap().lists.insert(
+        list.clone(),
+        CachedVersionList {
+            list: list.clone(),
+            versions: versions.clone(),
+            fetched_at: chrono::Utc::now().timestamp(),
+        },
+    );
+    if let Err(e) = write_to_file().await {
+        warn!("Failed to write the version cache : {e}");
+    }
+    Ok(versions)
+}
+
+/// The versions of `list`, fetched only if it was never fetched before
+pub async fn versions(list: VersionList) -> Result<Vec<String>, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let cached = CACHE.lock().unwrap().lists.get(&list).cloned();
+    match cached {
+        Some(cached) if is_fresh(&cached, now) => Ok(cached.versions),
+        Some(cached) => {
+            if CACHE.lock().unwrap().refreshing.insert(list.clone()) {
+                tokio::spawn(async move {
+                    if let Err(e) = refresh(&list).await {
+                        warn!("Failed to refresh {list:?}, serving the cached versions : {e}");
+                    }
+                    CACHE.lock().unwrap().refreshing.remove(&list);
+                });
+            }
+            Ok(cached.versions)
+        }
+        None => refresh(&list).await,
+    }
+}
+
+pub fn status() -> Vec<VersionListStatus> {
+    let now = chrono::Utc::now().timestamp();
+    CACHE
+        .lock()
+        .unwrap()
+        .lists
+        .values()
+        .map(|cached| VersionListStatus {
+            list: cached.list.clone(),
+            fetched_at: Some(cached.fetched_at),
+            stale: !is_fresh(cached, now),
+            error: None,
+        })
+        .collect()
+}
+
+/// Fetch every cached list again now, the ones that fail keep their cached versions
+pub async fn refresh_all() -> Vec<VersionListStatus> {
+    let lists: Vec<VersionList> = CACHE.lock().unwrap().lists.keys().cloned().collect();
+    let results = futures::future::join_all(lists.iter().map(refresh)).await;
+    let now = chrono::Utc::now().timestamp();
+    let cache = CACHE.lock().unwrap();
+    lists
+        .into_iter()
+        .zip(results)
+        .map(|(list, result)| {
+            let cached = cache.lists.get(&list);
+            VersionListStatus {
+                fetched_at: cached.map(|cached| cached.fetched_at),
+                stale: cached.map_or(true, |cached| !is_fresh(cached, now)),
+                error: result.err().map(|e| e.to_string()),
+                list,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, versions, CachedVersionList, VersionList, CACHE};
+
+    #[tokio::test]
+    async fn test_load_and_serve_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("version_cache.json");
+        let list = VersionList::ForgeBuilds {
+            version: "test".to_string(),
+        };
+        std::fs::write(
+            &path,
+            serde_json::to_string(&vec![CachedVersionList {
+                list: list.clone(),
+                versions: vec!["1.0".to_string()],
+                fetched_at: chrono::Utc::now().timestamp(),
+            }])
+            .unwrap(),
+        )
+        .unwrap();
+        load(&path).await.unwrap();
+        // fresh, so served without fetching the made up version
+        assert_eq!(versions(list.clone()).await.unwrap(), vec!["1.0"]);
+        assert!(CACHE.lock().unwrap().refreshing.is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::prelude::path_to_tmp;
+use crate::traits::t_configurable::manifest::ConfigurableValue;
+use crate::traits::t_server::StateAction;
+use crate::types::Snowflake;
+use crate::util::download_file;
+
+use super::configurable::CmdArgSetting;
+use super::util::{detect_launch_target, get_server_jar_url};
+use super::version_cache::{self, VersionList};
+use super::{
+    download_jre_if_missing, path_to_bundled_java, run_server_installer, server_jar_name, Flavour,
+    FlavourKind, MinecraftInstance,
+};
+
+/// where the jars replaced by a version change are kept, relative to the instance directory
+pub const JAR_BACKUPS_DIR: &str = ".lodestone_jar_backups";
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct VersionChange {
+    pub version: String,
+    /// the flavour to switch to, the current one if not set. The mod loader is set to the latest
+    /// version supporting `version` either way
+    pub flavour: Option<FlavourKind>,
+    /// worlds saved by a newer version may not load in an older one, so a downgrade has to be
+    /// asked for explicitly
+    #[serde(default)]
+    pub allow_downgrade: bool,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct VersionChangeReport {
+    pub previous_version: String,
+    pub previous_flavour: FlavourKind,
+    pub version: String,
+    pub flavour: FlavourKind,
+    pub downgrade: bool,
+    /// the directory the replaced jars were moved to, relative to the instance directory
+    pub backup: PathBuf,
+}
+
+/// Whether going from `from` to `to` is a downgrade, by their order in `versions`, newest first.
+///
+/// `None` if `to` is not a known version, and `false` if `from` is not, as there is nothing to
+/// compare against.
+fn is_downgrade(versions: &[String], from: &str, to: &str) -> Option<bool> {
+    let to_index = versions.iter().position(|version| version == to)?;
+    Some(
+        versions
+            .iter()
+            .position(|version| version == from)
+            .is_some_and(|from_index| to_index > from_index),
+    )
+}
+
+/// Move the jars at the top of the instance directory to `path_to_backup`, returning their names
+async fn back_up_jars(
+    path_to_instance: &Path,
+    path_to_backup: &Path,
+) -> Result<Vec<String>, Error> {
+    crate::util::fs::create_dir_all(path_to_backup).await?;
+    let mut read_dir = tokio::fs::read_dir(path_to_instance)
+        .await
+        .context("Failed to read instance directory")?;
+    let mut backed_up = Vec::new();
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .context("Failed to read instance directory")?
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_file() && name.ends_with(".jar") {
+            crate::util::fs::rename(entry.path(), path_to_backup.join(&name)).await?;
+            backed_up.push(name);
+        }
+    }
+    Ok(backed_up)
+}
+
+/// Put the jars of a failed version change back
+async fn restore_jars(path_to_instance: &Path, path_to_backup: &Path, jars: &[String]) {
+    for jar in jars {
+        if let Err(e) =
+            crate::util::fs::rename(path_to_backup.join(jar), path_to_instance.join(jar)).await
+        {
+            error!("Failed to restore {jar} after a failed version change : {e}");
+        }
+    }
+}
+
+impl MinecraftInstance {
+    async fn transition_install(&self, action: StateAction, name: &str) -> Result<(), Error> {
+        self.state.lock().await.try_transition(
+            action,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.to_string(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: "Changing version".to_string(),
+                    caused_by: CausedBy::System,
+                });
+            }),
+        )
+    }
+
+    /// Switch the server to another version or flavour. The instance is installing meanwhile, so
+    /// it can't be started.
+    ///
+    /// The new jar is downloaded before anything is touched, then the jars of the current version
+    /// are moved to [`JAR_BACKUPS_DIR`] and put back if the new one fails to install.
+    pub async fn switch_version(
+        &mut self,
+        change: VersionChange,
+    ) -> Result<VersionChangeReport, Error> {
+        let name = self.config.lock().await.name.clone();
+        self.transition_install(StateAction::InstallStart, &name)
+            .await?;
+        let report = self.install_version(change).await;
+        self.transition_install(StateAction::InstallFinish, &name)
+            .await?;
+        report
+    }
+
+    async fn install_version(
+        &mut self,
+        change: VersionChange,
+    ) -> Result<VersionChangeReport, Error> {
+        let (previous_version, previous_flavour, previous_jre_major_version) = {
+            let config = self.config.lock().await;
+            (
+                config.version.clone(),
+                config.flavour.clone(),
+                config.jre_major_version,
+            )
+        };
+        let flavour_kind = change
+            .flavour
+            .unwrap_or_else(|| FlavourKind::from(&previous_flavour));
+        if flavour_kind == FlavourKind::Spigot {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Changing versions is unsupported for spigot servers"),
+            });
+        }
+        if change.version == previous_version
+            && flavour_kind == FlavourKind::from(&previous_flavour)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The server already runs {flavour_kind:?} {previous_version}"),
+            });
+        }
+        let downgrade = is_downgrade(
+            &version_cache::versions(VersionList::VanillaVersions).await?,
+            &previous_version,
+            &change.version,
+        )
+        .ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Unknown minecraft version {}", change.version),
+        })?;
+        if downgrade {
+            if !change.allow_downgrade {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "{} is older than {previous_version}, worlds saved by a newer version may be corrupted by an older one. Back up the worlds and allow the downgrade to go ahead",
+                        change.version
+                    ),
+                });
+            }
+            warn!(
+                "[{}] Downgrading from {previous_version} to {}",
+                self.uuid, change.version
+            );
+        }
+
+        let (url, flavour) = get_server_jar_url(&change.version, &Flavour::from(flavour_kind))
+            .await
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Could not find a {flavour_kind:?} server jar for version {}",
+                    change.version
+                ),
+            })?;
+        let jre = download_jre_if_missing(&change.version, &self.path_to_runtimes, &|_| {}).await?;
+        let jar_name = server_jar_name(&flavour);
+        let temp_dir = tempfile::tempdir_in(path_to_tmp()).context("Failed to create temp dir")?;
+        download_file(&url, temp_dir.path(), Some(jar_name), &|_| {}, true).await?;
+
+        let backup = PathBuf::from(JAR_BACKUPS_DIR).join(format!(
+            "{previous_version}-{}",
+            chrono::Utc::now().timestamp()
+        ));
+        let path_to_backup = self.path_to_instance.join(&backup);
+        let backed_up = back_up_jars(&self.path_to_instance, &path_to_backup).await?;
+        let installed = async {
+            crate::util::fs::rename(
+                temp_dir.path().join(jar_name),
+                self.path_to_instance.join(jar_name),
+            )
+            .await?;
+            run_server_installer(&self.path_to_instance, &jre.java, &flavour, &change.version)
+                .await?;
+            detect_launch_target(&self.path_to_instance, &flavour, &change.version).await
+        }
+        .await;
+        let launch_target = match installed {
+            Ok(launch_target) => launch_target,
+            Err(e) => {
+                restore_jars(&self.path_to_instance, &path_to_backup, &backed_up).await;
+                return Err(e);
+            }
+        };
+
+        let java_cmd = {
+            let mut config = self.config.lock().await;
+            config.version = change.version.clone();
+            config.flavour = flavour.clone();
+            config.jre_major_version = jre.major_version;
+            config.launch_target = Some(launch_target);
+            // a java picked by the user is kept, the one picked by lodestone follows the version
+            let path_to_previous_jre =
+                path_to_bundled_java(&self.path_to_runtimes, previous_jre_major_version);
+            if config
+                .java_cmd
+                .as_ref()
+                .map_or(true, |java_cmd| Path::new(java_cmd) == path_to_previous_jre)
+            {
+                config.java_cmd = Some(jre.java.to_string_lossy().to_string());
+            }
+            config.java_cmd.clone().unwrap_or_default()
+        };
+        self.configurable_manifest
+            .lock()
+            .await
+            .update_setting_value(
+                CmdArgSetting::get_section_id(),
+                CmdArgSetting::JavaCmd(Default::default()).get_identifier(),
+                ConfigurableValue::String(java_cmd),
+            )?;
+        self.write_config_to_file().await?;
+        Ok(VersionChangeReport {
+            previous_version,
+            previous_flavour: FlavourKind::from(&previous_flavour),
+            version: change.version,
+            flavour: FlavourKind::from(&flavour),
+            downgrade,
+            backup,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_downgrade;
+
+    #[test]
+    fn test_is_downgrade() {
+        let versions: Vec<String> = ["1.20.1", "23w18a", "1.19.4", "1.8.9"]
+            .iter()
+            .map(|version| version.to_string())
+            .collect();
+        assert_eq!(is_downgrade(&versions, "1.19.4", "1.20.1"), Some(false));
+        assert_eq!(is_downgrade(&versions, "1.19.4", "23w18a"), Some(false));
+        assert_eq!(is_downgrade(&versions, "23w18a", "1.8.9"), Some(true));
+        assert_eq!(is_downgrade(&versions, "1.0-custom", "1.8.9"), Some(false));
+        assert_eq!(is_downgrade(&versions, "1.19.4", "1.21"), None);
+    }
+}
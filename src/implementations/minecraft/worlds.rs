@@ -0,0 +1,355 @@
+//! The worlds of a server are the folders of the instance with a `level.dat` in them. The server
+//! plays the one named by `level-name` in server.properties, and generates it if it is missing.
+//!
+//! Vanilla keeps the nether and the end in `<world>/DIM-1` and `<world>/DIM1`, bukkit and its
+//! forks in `<world>_nether/DIM-1` and `<world>_the_end/DIM1`, both layouts are handled.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    backup::{self, BackupEntry},
+    disk_usage::directory_size,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::t_configurable::manifest::ConfigurableValue,
+    traits::t_server::TServer,
+    trash::{Trash, TrashEntry},
+};
+
+use super::{util::read_properties_from_path, MinecraftInstance};
+
+const DEFAULT_LEVEL_NAME: &str = "world";
+const NETHER_SUFFIX: &str = "_nether";
+const END_SUFFIX: &str = "_the_end";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum Dimension {
+    Overworld,
+    Nether,
+    End,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WorldInfo {
+    pub name: String,
+    /// the world the server plays
+    pub active: bool,
+    /// the dimensions generated so far
+    pub dimensions: Vec<Dimension>,
+    /// in bytes, with the folders of its dimensions
+    pub size: u64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct WorldReset {
+    /// the dimensions to regenerate, the whole world if not set
+    pub dimensions: Option<Vec<Dimension>>,
+    /// the seed to regenerate the whole world with, the one in server.properties if not set
+    pub seed: Option<String>,
+    /// back the instance up first
+    #[serde(default = "default_true")]
+    pub backup: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct WorldResetReport {
+    pub backup: Option<BackupEntry>,
+    /// the removed folders, which can be restored from the trash
+    pub trashed: Vec<TrashEntry>,
+    /// the server was running, so it was stopped and started again
+    pub restarted: bool,
+}
+
+/// A world name is a single folder of the instance
+fn validate_world_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name == "." || name == ".." || sanitize_filename::sanitize(name) != name {
+        return Err(Error::bad_request(format!("Invalid world name {name}")));
+    }
+    Ok(())
+}
+
+/// The folders holding a dimension of `world` that exist, the overworld shares its folder with
+/// the rest of the world in both layouts so only its own data is listed for it
+pub fn dimension_paths(instance: &Path, world: &str, dimension: Dimension) -> Vec<PathBuf> {
+    let root = instance.join(world);
+    let candidates = match dimension {
+        Dimension::Overworld => vec![root.join("region"), root.join("entities"), root.join("poi")],
+        Dimension::Nether => vec![
+            root.join("DIM-1"),
+            instance
+                .join(format!("{world}{NETHER_SUFFIX}"))
+                .join("DIM-1"),
+        ],
+        Dimension::End => vec![
+            root.join("DIM1"),
+            instance.join(format!("{world}{END_SUFFIX}")).join("DIM1"),
+        ],
+    };
+    candidates
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// The folders of `world` with the ones bukkit keeps its other dimensions in
+fn world_folders(instance: &Path, world: &str) -> Vec<PathBuf> {
+    [
+        world.to_string(),
+        format!("{world}{NETHER_SUFFIX}"),
+        format!("{world}{END_SUFFIX}"),
+    ]
+    .iter()
+    .map(|folder| instance.join(folder))
+    .filter(|path| path.is_dir())
+    .collect()
+}
+
+/// The worlds in `instance`, sorted by name. The dimension folders of bukkit are listed as part of
+/// the world they belong to
+pub fn list_worlds(instance: &Path, active: &str) -> Result<Vec<WorldInfo>, Error> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(instance)
+        .context(format!("Failed to read directory {}", instance.display()))?
+        .filter_map(Result::ok)
+    {
+        if entry.path().join("level.dat").is_file() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    let is_dimension_of_other = |name: &str| {
+        [NETHER_SUFFIX, END_SUFFIX].iter().any(|suffix| {
+            name.strip_suffix(suffix)
+                .map_or(false, |world| names.iter().any(|name| name == world))
+        })
+    };
+    let mut worlds: Vec<WorldInfo> = names
+        .iter()
+        .filter(|name| !is_dimension_of_other(name))
+        .map(|name| WorldInfo {
+            name: name.clone(),
+            active: name == active,
+            dimensions: [Dimension::Overworld, Dimension::Nether, Dimension::End]
+                .into_iter()
+                .filter(|dimension| !dimension_paths(instance, name, *dimension).is_empty())
+                .collect(),
+            size: world_folders(instance, name)
+                .iter()
+                .map(|folder| directory_size(folder))
+                .sum(),
+        })
+        .collect();
+    worlds.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(worlds)
+}
+
+impl MinecraftInstance {
+    /// The `level-name` of server.properties
+    pub async fn active_world(&self) -> String {
+        read_properties_from_path(&self.path_to_properties)
+            .await
+            .ok()
+            .and_then(|properties| properties.get("level-name").cloned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| DEFAULT_LEVEL_NAME.to_string())
+    }
+
+    pub async fn worlds(&self) -> Result<Vec<WorldInfo>, Error> {
+        let active = self.active_world().await;
+        let path_to_instance = self.path_to_instance.clone();
+        tokio::task::spawn_blocking(move || list_worlds(&path_to_instance, &active))
+            .await
+            .context("World listing task panicked")?
+    }
+
+    async fn set_level_property(&mut self, key: &str, value: String) -> Result<(), Error> {
+        self.update_server_properties(IndexMap::from([(
+            key.to_string(),
+            ConfigurableValue::String(value),
+        )]))
+        .await?;
+        Ok(())
+    }
+
+    /// Play another world, which is generated if it doesn't exist. A running server is restarted
+    /// into it, returns whether it was
+    pub async fn switch_world(&mut self, name: String, caused_by: CausedBy) -> Result<bool, Error> {
+        validate_world_name(&name)?;
+        if name == self.active_world().await {
+            return Ok(false);
+        }
+        self.set_level_property("level-name", name).await?;
+        if self.state().await.is_stopped() {
+            return Ok(false);
+        }
+        self.stop(caused_by.clone(), true).await?;
+        self.start(caused_by, false).await?;
+        Ok(true)
+    }
+
+    /// Move dimensions of a world, or all of it, to the trash for the server to generate them
+    /// anew. The server is stopped meanwhile if it plays the world
+    pub async fn reset_world(
+        &mut self,
+        name: String,
+        reset: WorldReset,
+        caused_by: CausedBy,
+    ) -> Result<WorldResetReport, Error> {
+        validate_world_name(&name)?;
+        if world_folders(&self.path_to_instance, &name).is_empty() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("World {name} not found"),
+            });
+        }
+        // the seed is kept in level.dat, which only goes with the whole world
+        if reset.dimensions.is_some() && reset.seed.is_some() {
+            return Err(Error::bad_request(
+                "A seed can only be set when resetting the whole world".to_string(),
+            ));
+        }
+        let restart = name == self.active_world().await && !self.state().await.is_stopped();
+        if restart {
+            self.stop(caused_by.clone(), true).await?;
+        }
+        let result = async {
+            let backup = if reset.backup {
+                let config = self.config.lock().await.clone();
+                Some(
+                    backup::create_backup(
+                        self.uuid.clone(),
+                        config.name.clone(),
+                        self.path_to_instance.clone(),
+                        Some(format!("{} before resetting {name}", config.name)),
+                        self.event_broadcaster.clone(),
+                        caused_by.clone(),
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            let paths = match &reset.dimensions {
+                Some(dimensions) => dimensions
+                    .iter()
+                    .flat_map(|dimension| {
+                        dimension_paths(&self.path_to_instance, &name, *dimension)
+                    })
+                    .collect(),
+                None => world_folders(&self.path_to_instance, &name),
+            };
+            let trash = Trash::for_instance(&self.path_to_instance);
+            let mut trashed = Vec::new();
+            for path in paths {
+                trashed.push(trash.move_to_trash(&path, caused_by.clone()).await?);
+            }
+            if let Some(seed) = reset.seed {
+                self.set_level_property("level-seed", seed).await?;
+            }
+            Ok::<_, Error>((backup, trashed))
+        }
+        .await;
+        // the server goes back up even if the reset failed half way
+        if restart {
+            self.start(caused_by, false).await?;
+        }
+        let (backup, trashed) = result?;
+        Ok(WorldResetReport {
+            backup,
+            trashed,
+            restarted: restart,
+        })
+    }
+
+    /// Move a world other than the active one to the trash
+    pub async fn delete_world(
+        &self,
+        name: String,
+        caused_by: CausedBy,
+    ) -> Result<Vec<TrashEntry>, Error> {
+        validate_world_name(&name)?;
+        if name == self.active_world().await {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!("Switch to another world before deleting {name}"),
+            });
+        }
+        let folders = world_folders(&self.path_to_instance, &name);
+        if folders.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("World {name} not found"),
+            });
+        }
+        let trash = Trash::for_instance(&self.path_to_instance);
+        let mut trashed = Vec::new();
+        for folder in folders {
+            trashed.push(trash.move_to_trash(&folder, caused_by.clone()).await?);
+        }
+        Ok(trashed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dimension_paths, list_worlds, validate_world_name, Dimension};
+
+    #[test]
+    fn test_list_worlds() {
+        let instance = tempfile::tempdir().unwrap();
+        let create = |path: &str| std::fs::create_dir_all(instance.path().join(path)).unwrap();
+        let level = |world: &str| {
+            std::fs::write(instance.path().join(world).join("level.dat"), "").unwrap()
+        };
+        // vanilla layout
+        create("world/region");
+        create("world/DIM1");
+        level("world");
+        // bukkit layout
+        create("survival/region");
+        create("survival_nether/DIM-1");
+        level("survival");
+        level("survival_nether");
+        create("logs");
+
+        let worlds = list_worlds(instance.path(), "survival").unwrap();
+        assert_eq!(worlds.len(), 2);
+        assert_eq!(worlds[0].name, "survival");
+        assert!(worlds[0].active);
+        assert_eq!(
+            worlds[0].dimensions,
+            vec![Dimension::Overworld, Dimension::Nether]
+        );
+        assert_eq!(worlds[1].name, "world");
+        assert_eq!(
+            worlds[1].dimensions,
+            vec![Dimension::Overworld, Dimension::End]
+        );
+        assert_eq!(
+            dimension_paths(instance.path(), "survival", Dimension::Nether),
+            vec![instance.path().join("survival_nether/DIM-1")]
+        );
+        assert!(dimension_paths(instance.path(), "world", Dimension::Nether).is_empty());
+    }
+
+    #[test]
+    fn test_validate_world_name() {
+        assert!(validate_world_name("world").is_ok());
+        assert!(validate_world_name("").is_err());
+        assert!(validate_world_name("..").is_err());
+        assert!(validate_world_name("../world").is_err());
+        assert!(validate_world_name("a/b").is_err());
+    }
+}
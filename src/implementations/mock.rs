@@ -0,0 +1,468 @@
+//! An instance that runs nothing, for testing handlers and lifecycles without downloading a
+//! server. It goes through the same states and sends the same events as a real instance, but
+//! changes state right away, and records the commands sent to it instead of running them.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use indexmap::IndexMap;
+use tokio::sync::Mutex;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::readiness::ReadinessTracker;
+use crate::traits::t_configurable::manifest::{ConfigurableManifest, ConfigurableValue};
+use crate::traits::t_configurable::{
+    DisplayMetadata, Game, LaunchOverrides, PublicAddress, ResourceLimits, TConfigurable,
+};
+use crate::traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry};
+use crate::traits::t_player::{Player, TPlayerManagement};
+use crate::traits::t_resource::TResourceManagement;
+use crate::traits::t_server::{MonitorReport, Readiness, State, StateAction, TServer};
+use crate::traits::TInstance;
+use crate::types::{InstanceUuid, Snowflake};
+
+use super::generic::player::GenericPlayer;
+
+#[derive(Clone, Debug)]
+struct MockConfig {
+    name: String,
+    description: String,
+    port: u32,
+    auto_start: bool,
+    restart_on_crash: bool,
+    backup_period: Option<u32>,
+    resource_limits: ResourceLimits,
+    display: DisplayMetadata,
+    public_address: PublicAddress,
+    launch_overrides: LaunchOverrides,
+    max_players: u32,
+}
+
+#[derive(Clone)]
+pub struct MockInstance {
+    config: Arc<Mutex<MockConfig>>,
+    uuid: InstanceUuid,
+    creation_time: i64,
+    path_to_instance: PathBuf,
+    state: Arc<Mutex<State>>,
+    event_broadcaster: EventBroadcaster,
+    readiness: ReadinessTracker,
+    commands: Arc<Mutex<Vec<String>>>,
+    players: Arc<Mutex<HashSet<GenericPlayer>>>,
+}
+
+impl MockInstance {
+    /// A stopped instance, its directory is created if it doesn't exist
+    pub async fn new(
+        name: impl Into<String>,
+        port: u32,
+        path_to_instance: PathBuf,
+        event_broadcaster: EventBroadcaster,
+    ) -> Result<MockInstance, Error> {
+        crate::util::fs::create_dir_all(&path_to_instance).await?;
+        Ok(MockInstance {
+            config: Arc::new(Mutex::new(MockConfig {
+                name: name.into(),
+                description: String::new(),
+                port,
+                auto_start: false,
+                restart_on_crash: false,
+                backup_period: None,
+                resource_limits: ResourceLimits::default(),
+                display: DisplayMetadata::default(),
+                public_address: PublicAddress::default(),
+                launch_overrides: LaunchOverrides::default(),
+                max_players: 20,
+            })),
+            uuid: InstanceUuid::default(),
+            creation_time: chrono::Utc::now().timestamp(),
+            path_to_instance,
+            state: Arc::new(Mutex::new(State::Stopped)),
+            event_broadcaster,
+            readiness: ReadinessTracker::default(),
+            commands: Arc::new(Mutex::new(Vec::new())),
+            players: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// The commands sent to the instance, oldest first
+    pub async fn sent_commands(&self) -> Vec<String> {
+        self.commands.lock().await.clone()
+    }
+
+    /// Replace the players online, as if they joined and left
+    pub async fn set_players(&self, names: &[&str]) {
+        *self.players.lock().await = names
+            .iter()
+            .map(|name| GenericPlayer {
+                id: name.to_string(),
+                name: name.to_string(),
+            })
+            .collect();
+    }
+
+    /// Stop as if the server crashed, restarts follow the crash restart policy of the caller
+    pub async fn crash(&self) -> Result<(), Error> {
+        self.transition(
+            StateAction::InstanceCrash,
+            "Server crashed",
+            &CausedBy::System,
+        )
+        .await?;
+        self.readiness.stopped();
+        self.players.lock().await.clear();
+        Ok(())
+    }
+
+    async fn transition(
+        &self,
+        action: StateAction,
+        details: &str,
+        caused_by: &CausedBy,
+    ) -> Result<(), Error> {
+        let name = self.config.lock().await.name.clone();
+        self.state.lock().await.try_transition(
+            action,
+            Some(&|state| {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_name: name.clone(),
+                        instance_uuid: self.uuid.clone(),
+                        instance_event_inner: InstanceEventInner::StateTransition { to: state },
+                    }),
+                    snowflake: Snowflake::default(),
+                    details: details.to_string(),
+                    caused_by: caused_by.clone(),
+                });
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl TConfigurable for MockInstance {
+    async fn uuid(&self) -> InstanceUuid {
+        self.uuid.clone()
+    }
+
+    async fn name(&self) -> String {
+        self.config.lock().await.name.clone()
+    }
+
+    async fn game_type(&self) -> Game {
+        Game::Process {
+            game_display_name: "Mock".to_string(),
+        }
+    }
+
+    async fn version(&self) -> String {
+        "".to_string()
+    }
+
+    async fn description(&self) -> String {
+        self.config.lock().await.description.clone()
+    }
+
+    async fn port(&self) -> u32 {
+        self.config.lock().await.port
+    }
+
+    async fn creation_time(&self) -> i64 {
+        self.creation_time
+    }
+
+    async fn path(&self) -> std::path::PathBuf {
+        self.path_to_instance.clone()
+    }
+
+    async fn auto_start(&self) -> bool {
+        self.config.lock().await.auto_start
+    }
+
+    async fn restart_on_crash(&self) -> bool {
+        self.config.lock().await.restart_on_crash
+    }
+
+    async fn backup_period(&self) -> Option<u32> {
+        self.config.lock().await.backup_period
+    }
+
+    async fn resource_limits(&self) -> ResourceLimits {
+        self.config.lock().await.resource_limits
+    }
+
+    async fn display_metadata(&self) -> DisplayMetadata {
+        self.config.lock().await.display.clone()
+    }
+
+    async fn public_address(&self) -> PublicAddress {
+        self.config.lock().await.public_address.clone()
+    }
+
+    async fn launch_overrides(&self) -> LaunchOverrides {
+        self.config.lock().await.launch_overrides.clone()
+    }
+
+    async fn set_name(&mut self, name: String) -> Result<(), Error> {
+        if name.is_empty() || name.len() > 100 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name must be between 1 and 100 characters"),
+            });
+        }
+        self.config.lock().await.name = name;
+        Ok(())
+    }
+
+    async fn set_description(&mut self, description: String) -> Result<(), Error> {
+        self.config.lock().await.description = description;
+        Ok(())
+    }
+
+    async fn set_port(&mut self, port: u32) -> Result<(), Error> {
+        self.config.lock().await.port = port;
+        Ok(())
+    }
+
+    async fn set_auto_start(&mut self, auto_start: bool) -> Result<(), Error> {
+        self.config.lock().await.auto_start = auto_start;
+        Ok(())
+    }
+
+    async fn set_restart_on_crash(&mut self, restart_on_crash: bool) -> Result<(), Error> {
+        self.config.lock().await.restart_on_crash = restart_on_crash;
+        Ok(())
+    }
+
+    async fn set_backup_period(&mut self, backup_period: Option<u32>) -> Result<(), Error> {
+        if backup_period == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backup period cannot be 0"),
+            });
+        }
+        self.config.lock().await.backup_period = backup_period;
+        Ok(())
+    }
+
+    async fn set_resource_limits(&mut self, limits: ResourceLimits) -> Result<(), Error> {
+        limits.validate()?;
+        self.config.lock().await.resource_limits = limits;
+        Ok(())
+    }
+
+    async fn set_display_metadata(&mut self, display: DisplayMetadata) -> Result<(), Error> {
+        display.validate()?;
+        self.config.lock().await.display = display;
+        Ok(())
+    }
+
+    async fn set_public_address(&mut self, address: PublicAddress) -> Result<(), Error> {
+        address.validate()?;
+        self.config.lock().await.public_address = address;
+        Ok(())
+    }
+
+    async fn set_launch_overrides(&mut self, overrides: LaunchOverrides) -> Result<(), Error> {
+        overrides.validate()?;
+        self.config.lock().await.launch_overrides = overrides;
+        Ok(())
+    }
+
+    async fn configurable_manifest(&mut self) -> ConfigurableManifest {
+        let config = self.config.lock().await;
+        ConfigurableManifest::new(config.auto_start, config.restart_on_crash, IndexMap::new())
+    }
+
+    async fn update_configurable(
+        &mut self,
+        _section_id: &str,
+        _setting_id: &str,
+        _value: ConfigurableValue,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have any configurable settings"),
+        })
+    }
+}
+
+#[async_trait]
+impl TServer for MockInstance {
+    async fn start(&mut self, caused_by: CausedBy, _block: bool) -> Result<(), Error> {
+        self.transition(StateAction::UserStart, "Starting server", &caused_by)
+            .await?;
+        self.readiness.starting();
+        self.transition(StateAction::InstanceStart, "Starting server", &caused_by)
+            .await?;
+        if let Some(startup_duration) = self.readiness.mark_ready() {
+            self.event_broadcaster.send(Event::new_instance_ready(
+                self.uuid.clone(),
+                self.name().await,
+                startup_duration,
+            ));
+        }
+        Ok(())
+    }
+
+    async fn stop(&mut self, caused_by: CausedBy, _block: bool) -> Result<(), Error> {
+        self.transition(StateAction::UserStop, "Stopping server", &caused_by)
+            .await?;
+        self.readiness.stopped();
+        self.players.lock().await.clear();
+        self.transition(StateAction::InstanceStop, "Server stopped", &caused_by)
+            .await
+    }
+
+    async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        self.stop(caused_by.clone(), block).await?;
+        self.start(caused_by, block).await
+    }
+
+    async fn kill(&mut self, caused_by: CausedBy) -> Result<(), Error> {
+        if self.state().await.is_stopped() {
+            return Err(eyre!("Instance is already stopped").into());
+        }
+        self.readiness.stopped();
+        self.players.lock().await.clear();
+        self.transition(StateAction::InstanceStop, "Server killed", &caused_by)
+            .await
+    }
+
+    async fn state(&self) -> State {
+        *self.state.lock().await
+    }
+
+    async fn readiness(&self) -> Readiness {
+        self.readiness.readiness()
+    }
+
+    /// Recorded and echoed to the console, `stop` stops the instance
+    async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error> {
+        if self.state().await.is_stopped() {
+            return Err(eyre!("Instance is stopped").into());
+        }
+        self.commands.lock().await.push(command.to_string());
+        self.event_broadcaster.send(Event::new_instance_output(
+            self.uuid.clone(),
+            self.name().await,
+            command.to_string(),
+        ));
+        if command == "stop" {
+            self.clone().stop(caused_by, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn monitor(&self) -> MonitorReport {
+        MonitorReport::default()
+    }
+}
+
+#[async_trait]
+impl TPlayerManagement for MockInstance {
+    async fn get_player_count(&self) -> Result<u32, Error> {
+        Ok(self.players.lock().await.len() as u32)
+    }
+
+    async fn get_max_player_count(&self) -> Result<u32, Error> {
+        Ok(self.config.lock().await.max_players)
+    }
+
+    async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
+        Ok(self
+            .players
+            .lock()
+            .await
+            .iter()
+            .cloned()
+            .map(Player::GenericPlayer)
+            .collect())
+    }
+
+    async fn set_max_player_count(&mut self, max_player_count: u32) -> Result<(), Error> {
+        self.config.lock().await.max_players = max_player_count;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TMacro for MockInstance {
+    async fn get_macro_list(&self) -> Result<Vec<MacroEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn get_task_list(&self) -> Result<Vec<TaskEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn get_history_list(&self) -> Result<Vec<HistoryEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn delete_macro(&mut self, _name: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support macros"),
+        })
+    }
+    async fn create_macro(&mut self, _name: &str, _content: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support macros"),
+        })
+    }
+}
+
+impl TResourceManagement for MockInstance {}
+
+impl TInstance for MockInstance {}
+
+#[cfg(test)]
+mod tests {
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::events::CausedBy;
+    use crate::traits::t_player::TPlayerManagement;
+    use crate::traits::t_server::{State, TServer};
+
+    use super::MockInstance;
+
+    #[tokio::test]
+    async fn test_mock_instance_lifecycle() {
+        let temp_dir = tempdir::TempDir::new("test_mock_instance_lifecycle").unwrap();
+        let (event_broadcaster, _rx) = EventBroadcaster::new(16);
+        let mut instance = MockInstance::new(
+            "mock",
+            25565,
+            temp_dir.path().join("mock"),
+            event_broadcaster,
+        )
+        .await
+        .unwrap();
+
+        assert!(instance
+            .send_command("list", CausedBy::System)
+            .await
+            .is_err());
+        instance.start(CausedBy::System, true).await.unwrap();
+        assert_eq!(instance.state().await, State::Running);
+        assert!(instance.readiness().await.ready);
+        assert!(instance.start(CausedBy::System, true).await.is_err());
+
+        instance.set_players(&["Steve", "Alex"]).await;
+        assert_eq!(instance.get_player_count().await.unwrap(), 2);
+        instance
+            .send_command("list", CausedBy::System)
+            .await
+            .unwrap();
+        instance
+            .send_command("stop", CausedBy::System)
+            .await
+            .unwrap();
+        assert_eq!(instance.sent_commands().await, vec!["list", "stop"]);
+        assert_eq!(instance.state().await, State::Stopped);
+        assert_eq!(instance.get_player_count().await.unwrap(), 0);
+    }
+}
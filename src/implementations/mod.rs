@@ -1,2 +1,5 @@
 pub mod generic;
 pub mod minecraft;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod mock;
+pub mod process;
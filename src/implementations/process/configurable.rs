@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+use indexmap::IndexMap;
+
+use crate::error::{Error, ErrorKind};
+use crate::traits::t_configurable::manifest::{ConfigurableManifest, ConfigurableValue};
+use crate::traits::t_configurable::{
+    DisplayMetadata, Game, LaunchOverrides, PublicAddress, ResourceLimits, TConfigurable,
+};
+use crate::types::InstanceUuid;
+
+use super::ProcessInstance;
+
+#[async_trait]
+impl TConfigurable for ProcessInstance {
+    async fn uuid(&self) -> InstanceUuid {
+        self.uuid.clone()
+    }
+
+    async fn name(&self) -> String {
+        self.config.lock().await.name.clone()
+    }
+
+    async fn game_type(&self) -> Game {
+        Game::Process {
+            game_display_name: self.config.lock().await.game_display_name.clone(),
+        }
+    }
+
+    async fn version(&self) -> String {
+        "".to_string()
+    }
+
+    async fn description(&self) -> String {
+        self.config.lock().await.description.clone()
+    }
+
+    async fn port(&self) -> u32 {
+        self.config.lock().await.port
+    }
+
+    async fn creation_time(&self) -> i64 {
+        self.creation_time
+    }
+
+    async fn path(&self) -> std::path::PathBuf {
+        self.path_to_instance.clone()
+    }
+
+    async fn auto_start(&self) -> bool {
+        self.config.lock().await.auto_start
+    }
+
+    async fn restart_on_crash(&self) -> bool {
+        self.config.lock().await.restart_on_crash
+    }
+
+    async fn backup_period(&self) -> Option<u32> {
+        self.config.lock().await.backup_period
+    }
+
+    async fn resource_limits(&self) -> ResourceLimits {
+        self.config.lock().await.resource_limits
+    }
+
+    async fn display_metadata(&self) -> DisplayMetadata {
+        self.config.lock().await.display.clone()
+    }
+
+    async fn public_address(&self) -> PublicAddress {
+        self.config.lock().await.public_address.clone()
+    }
+
+    async fn launch_overrides(&self) -> LaunchOverrides {
+        self.config.lock().await.launch_overrides.clone()
+    }
+
+    async fn set_name(&mut self, name: String) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be empty"),
+            });
+        }
+        if name.len() > 100 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be longer than 100 characters"),
+            });
+        }
+        self.config.lock().await.name = name;
+        self.write_config_to_file().await
+    }
+
+    async fn set_description(&mut self, description: String) -> Result<(), Error> {
+        self.config.lock().await.description = description;
+        self.write_config_to_file().await
+    }
+
+    async fn set_port(&mut self, port: u32) -> Result<(), Error> {
+        // the port is only used for bookkeeping, the server is configured through its own files
+        self.config.lock().await.port = port;
+        self.write_config_to_file().await
+    }
+
+    async fn set_auto_start(&mut self, auto_start: bool) -> Result<(), Error> {
+        self.config.lock().await.auto_start = auto_start;
+        self.write_config_to_file().await
+    }
+
+    async fn set_restart_on_crash(&mut self, restart_on_crash: bool) -> Result<(), Error> {
+        self.config.lock().await.restart_on_crash = restart_on_crash;
+        self.write_config_to_file().await
+    }
+
+    async fn set_backup_period(&mut self, backup_period: Option<u32>) -> Result<(), Error> {
+        if backup_period == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backup period cannot be 0"),
+            });
+        }
+        self.config.lock().await.backup_period = backup_period;
+        self.write_config_to_file().await
+    }
+
+    async fn set_resource_limits(&mut self, limits: ResourceLimits) -> Result<(), Error> {
+        limits.validate()?;
+        self.config.lock().await.resource_limits = limits;
+        self.write_config_to_file().await
+    }
+
+    async fn set_display_metadata(&mut self, display: DisplayMetadata) -> Result<(), Error> {
+        display.validate()?;
+        self.config.lock().await.display = display;
+        self.write_config_to_file().await
+    }
+
+    async fn set_public_address(&mut self, address: PublicAddress) -> Result<(), Error> {
+        address.validate()?;
+        self.config.lock().await.public_address = address;
+        self.write_config_to_file().await
+    }
+
+    async fn set_launch_overrides(&mut self, overrides: LaunchOverrides) -> Result<(), Error> {
+        overrides.validate()?;
+        self.config.lock().await.launch_overrides = overrides;
+        self.write_config_to_file().await
+    }
+
+    async fn configurable_manifest(&mut self) -> ConfigurableManifest {
+        let config = self.config.lock().await;
+        ConfigurableManifest::new(config.auto_start, config.restart_on_crash, IndexMap::new())
+    }
+
+    async fn update_configurable(
+        &mut self,
+        _section_id: &str,
+        _setting_id: &str,
+        _value: ConfigurableValue,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not have any configurable settings"),
+        })
+    }
+}
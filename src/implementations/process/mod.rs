@@ -0,0 +1,246 @@
+pub mod configurable;
+pub mod server;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::to_string_pretty;
+use sysinfo::SystemExt;
+use tokio::process::Child;
+use tokio::sync::Mutex;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::network_usage::NetworkUsageTracker;
+use crate::readiness::ReadinessTracker;
+use crate::traits::t_configurable::{
+    DisplayMetadata, LaunchOverrides, PublicAddress, ResourceLimits,
+};
+use crate::traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry};
+use crate::traits::t_player::TPlayerManagement;
+use crate::traits::t_resource::TResourceManagement;
+use crate::traits::t_server::State;
+use crate::traits::TInstance;
+use crate::types::{DotLodestoneConfig, InstanceUuid};
+
+/// A parameter for constructor of `ProcessInstance`
+#[derive(Clone, Debug, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ProcessSetupConfig {
+    pub name: String,
+    /// displayed to the user as the game of the instance, e.g. "Terraria"
+    pub game_display_name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    /// relative to the instance directory, defaults to the instance directory
+    pub working_dir: Option<PathBuf>,
+    pub port: u32,
+    /// written to stdin to stop the server gracefully, the process is terminated if not set
+    pub stop_command: Option<String>,
+    pub description: Option<String>,
+    pub auto_start: Option<bool>,
+    pub restart_on_crash: Option<bool>,
+    pub backup_period: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreConfig {
+    pub name: String,
+    pub game_display_name: String,
+    pub description: String,
+    pub command: String,
+    pub args: Vec<String>,
+    pub working_dir: Option<PathBuf>,
+    pub port: u32,
+    pub stop_command: Option<String>,
+    pub auto_start: bool,
+    pub restart_on_crash: bool,
+    pub backup_period: Option<u32>,
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    #[serde(default)]
+    pub display: DisplayMetadata,
+    #[serde(default)]
+    pub public_address: PublicAddress,
+    #[serde(default)]
+    pub launch_overrides: LaunchOverrides,
+}
+
+/// An instance that wraps an arbitrary server executable.
+///
+/// Lodestone has no knowledge of the game, the console is the stdin and stdout of the process,
+/// and the instance is considered running as soon as the process is spawned. It is ready once
+/// something accepts connections on its port.
+#[derive(Clone)]
+pub struct ProcessInstance {
+    config: Arc<Mutex<RestoreConfig>>,
+    uuid: InstanceUuid,
+    creation_time: i64,
+    state: Arc<Mutex<State>>,
+    event_broadcaster: EventBroadcaster,
+    path_to_instance: PathBuf,
+    path_to_config: PathBuf,
+    process: Arc<Mutex<Option<Child>>>,
+    stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
+    system: Arc<Mutex<sysinfo::System>>,
+    network_usage_tracker: Arc<Mutex<NetworkUsageTracker>>,
+    readiness: ReadinessTracker,
+}
+
+impl ProcessInstance {
+    pub async fn new(
+        setup_config: ProcessSetupConfig,
+        dot_lodestone_config: DotLodestoneConfig,
+        path_to_instance: PathBuf,
+        event_broadcaster: EventBroadcaster,
+    ) -> Result<ProcessInstance, Error> {
+        if setup_config.name.is_empty() || setup_config.name.len() > 100 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name must be between 1 and 100 characters"),
+            });
+        }
+        if setup_config.command.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Command cannot be empty"),
+            });
+        }
+        if setup_config
+            .working_dir
+            .as_ref()
+            .map_or(false, |dir| dir.is_absolute())
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Working directory must be relative to the instance directory"),
+            });
+        }
+        let path_to_config = path_to_instance.join(".lodestone_process_config.json");
+        let restore_config = RestoreConfig {
+            name: setup_config.name,
+            game_display_name: setup_config.game_display_name,
+            description: setup_config.description.unwrap_or_default(),
+            command: setup_config.command,
+            args: setup_config.args,
+            working_dir: setup_config.working_dir,
+            port: setup_config.port,
+            stop_command: setup_config.stop_command,
+            auto_start: setup_config.auto_start.unwrap_or(false),
+            restart_on_crash: setup_config.restart_on_crash.unwrap_or(false),
+            backup_period: setup_config.backup_period,
+            resource_limits: ResourceLimits::default(),
+            display: DisplayMetadata::default(),
+            public_address: PublicAddress::default(),
+            launch_overrides: LaunchOverrides::default(),
+        };
+        tokio::fs::create_dir_all(&path_to_instance)
+            .await
+            .context(format!(
+                "Failed to create directory for instance at {}",
+                &path_to_instance.display()
+            ))?;
+        if let Some(working_dir) = &restore_config.working_dir {
+            tokio::fs::create_dir_all(path_to_instance.join(working_dir))
+                .await
+                .context("Failed to create working directory")?;
+        }
+        tokio::fs::write(
+            &path_to_config,
+            to_string_pretty(&restore_config)
+                .context("Failed to serialize config to string, this is a bug, please report it")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config to file at {}",
+            &path_to_config.display()
+        ))?;
+        Self::restore(path_to_instance, dot_lodestone_config, event_broadcaster).await
+    }
+
+    pub async fn restore(
+        path_to_instance: PathBuf,
+        dot_lodestone_config: DotLodestoneConfig,
+        event_broadcaster: EventBroadcaster,
+    ) -> Result<ProcessInstance, Error> {
+        let path_to_config = path_to_instance.join(".lodestone_process_config.json");
+        let restore_config: RestoreConfig =
+            serde_json::from_reader(std::fs::File::open(&path_to_config).context(format!(
+                "Failed to open config file at {}",
+                &path_to_config.display()
+            ))?)
+            .context(
+                "Failed to deserialize config from string. Was the config file modified manually?",
+            )?;
+        Ok(ProcessInstance {
+            config: Arc::new(Mutex::new(restore_config)),
+            uuid: dot_lodestone_config.uuid().clone(),
+            creation_time: dot_lodestone_config.creation_time(),
+            state: Arc::new(Mutex::new(State::Stopped)),
+            event_broadcaster,
+            path_to_instance,
+            path_to_config,
+            process: Arc::new(Mutex::new(None)),
+            stdin: Arc::new(Mutex::new(None)),
+            system: Arc::new(Mutex::new(sysinfo::System::new_all())),
+            network_usage_tracker: Arc::new(Mutex::new(NetworkUsageTracker::new())),
+            readiness: ReadinessTracker::default(),
+        })
+    }
+
+    async fn write_config_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_config,
+            to_string_pretty(&*self.config.lock().await)
+                .context("Failed to serialize config to string, this is a bug, please report it")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config to file at {}",
+            &self.path_to_config.display()
+        ))?;
+        Ok(())
+    }
+
+    fn working_dir(&self, config: &RestoreConfig) -> PathBuf {
+        match &config.working_dir {
+            Some(working_dir) => self.path_to_instance.join(working_dir),
+            None => self.path_to_instance.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TMacro for ProcessInstance {
+    async fn get_macro_list(&self) -> Result<Vec<MacroEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn get_task_list(&self) -> Result<Vec<TaskEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn get_history_list(&self) -> Result<Vec<HistoryEntry>, Error> {
+        Ok(Vec::new())
+    }
+    async fn delete_macro(&mut self, _name: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support macros"),
+        })
+    }
+    async fn create_macro(&mut self, _name: &str, _content: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support macros"),
+        })
+    }
+}
+
+impl TPlayerManagement for ProcessInstance {}
+
+impl TResourceManagement for ProcessInstance {}
+
+impl TInstance for ProcessInstance {}
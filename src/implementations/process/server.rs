@@ -0,0 +1,447 @@
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use sysinfo::{Pid, PidExt, ProcessExt, Signal, SystemExt};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tracing::{error, info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, CRASH_OUTPUT_LINES,
+};
+use crate::readiness::port_accepts_connections;
+use crate::traits::t_server::{MonitorReport, Readiness, State, StateAction, TServer};
+use crate::types::Snowflake;
+use crate::util::dont_spawn_terminal;
+
+use super::ProcessInstance;
+
+/// How often the port of a running process is checked until it accepts connections
+static READINESS_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+impl ProcessInstance {
+    fn state_transition_event(
+        &self,
+        instance_name: &str,
+        state: State,
+        details: &str,
+        caused_by: &CausedBy,
+    ) -> Event {
+        Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: instance_name.to_string(),
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::StateTransition { to: state },
+            }),
+            snowflake: Snowflake::default(),
+            details: details.to_string(),
+            caused_by: caused_by.clone(),
+        }
+    }
+
+    async fn wait_for_state(&self, target: State) -> Result<(), Error> {
+        let mut rx = self.event_broadcaster.subscribe();
+        while let Ok(event) = rx.recv().await {
+            if let EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_event_inner: InstanceEventInner::StateTransition { to },
+                ..
+            }) = event.event_inner
+            {
+                if instance_uuid == self.uuid && to == target {
+                    return Ok(());
+                }
+            }
+        }
+        Err(eyre!("Sender shutdown").into())
+    }
+}
+
+#[async_trait::async_trait]
+impl TServer for ProcessInstance {
+    async fn start(&mut self, caused_by: CausedBy, _block: bool) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        self.state.lock().await.try_transition(
+            StateAction::UserStart,
+            Some(&|state| {
+                self.event_broadcaster.send(self.state_transition_event(
+                    &config.name,
+                    state,
+                    "Starting server",
+                    &caused_by,
+                ));
+            }),
+        )?;
+
+        if config.port != 0 && !port_scanner::local_port_available(config.port as u16) {
+            self.state.lock().await.try_transition(
+                StateAction::InstanceStop,
+                Some(&|state| {
+                    self.event_broadcaster.send(self.state_transition_event(
+                        &config.name,
+                        state,
+                        "Port already in use",
+                        &caused_by,
+                    ));
+                }),
+            )?;
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Port {} is already in use", config.port),
+            });
+        }
+
+        let mut server_start_command = Command::new(&config.command);
+        let server_start_command = server_start_command
+            .args(&config.args)
+            .args(&config.launch_overrides.program_args)
+            .envs(
+                config
+                    .launch_overrides
+                    .env
+                    .iter()
+                    .map(|(name, var)| (name, &var.value)),
+            )
+            .current_dir(self.working_dir(&config));
+
+        let mut proc = match dont_spawn_terminal(server_start_command)
+            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(proc) => proc,
+            Err(e) => {
+                error!("[{}] Failed to start server, {}", config.name, e);
+                self.state.lock().await.try_transition(
+                    StateAction::InstanceStop,
+                    Some(&|state| {
+                        self.event_broadcaster.send(self.state_transition_event(
+                            &config.name,
+                            state,
+                            "Failed to start server",
+                            &caused_by,
+                        ));
+                    }),
+                )?;
+                return Err(e)
+                    .context(format!("Failed to run {}", config.command))
+                    .map_err(Into::into);
+            }
+        };
+        let stdin = proc
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stdin during startup"))?;
+        let stdout = proc
+            .stdout
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stdout during startup"))?;
+        let stderr = proc
+            .stderr
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stderr during startup"))?;
+        self.stdin.lock().await.replace(stdin);
+        *self.process.lock().await = Some(proc);
+        self.readiness.starting();
+        self.network_usage_tracker.lock().await.reset();
+        // there is no way to tell when an arbitrary server is ready, consider it running once spawned
+        self.state.lock().await.try_transition(
+            StateAction::InstanceStart,
+            Some(&|state| {
+                self.event_broadcaster.send(self.state_transition_event(
+                    &config.name,
+                    state,
+                    "Server process started",
+                    &caused_by,
+                ));
+            }),
+        )?;
+
+        tokio::task::spawn({
+            let __self = self.clone();
+            let name = config.name.clone();
+            let port = config.port as u16;
+            async move {
+                while self.state().await == State::Running && !self.readiness.readiness().ready {
+                    if port_accepts_connections(port).await {
+                        if let Some(startup_duration) = self.readiness.mark_ready() {
+                            self.event_broadcaster.send(Event::new_instance_ready(
+                                self.uuid.clone(),
+                                name,
+                                startup_duration,
+                            ));
+                        }
+                        break;
+                    }
+                    tokio::time::sleep(READINESS_PROBE_INTERVAL).await;
+                }
+            }
+        });
+
+        tokio::task::spawn({
+            let __self = self.clone();
+            let name = config.name.clone();
+            async move {
+                let mut stdout_reader = BufReader::new(stdout);
+                let mut stderr_reader = BufReader::new(stderr);
+                // read_until keeps the partial line in the buffer if the other branch wins
+                let mut stdout_line = Vec::new();
+                let mut stderr_line = Vec::new();
+                let mut stdout_closed = false;
+                let mut stderr_closed = false;
+                let mut last_output = VecDeque::with_capacity(CRASH_OUTPUT_LINES);
+                while !(stdout_closed && stderr_closed) {
+                    let (line_res, is_stdout) = tokio::select!(
+                        res = stdout_reader.read_until(b'\n', &mut stdout_line), if !stdout_closed => (res, true),
+                        res = stderr_reader.read_until(b'\n', &mut stderr_line), if !stderr_closed => (res, false),
+                    );
+                    let line = std::mem::take(if is_stdout {
+                        &mut stdout_line
+                    } else {
+                        &mut stderr_line
+                    });
+                    match line_res {
+                        Ok(0) | Err(_) => {
+                            if let Err(e) = line_res {
+                                error!("[{}] Failed to read from stdout/stderr: {}", name, e);
+                            }
+                            if is_stdout {
+                                stdout_closed = true;
+                            } else {
+                                stderr_closed = true;
+                            }
+                        }
+                        Ok(_) => {
+                            let line = String::from_utf8_lossy(&line).to_string();
+                            if !is_stdout {
+                                warn!("[{}] {}", name, line);
+                            }
+                            if last_output.len() == CRASH_OUTPUT_LINES {
+                                last_output.pop_front();
+                            }
+                            last_output.push_back(line.trim_end().to_string());
+                            self.event_broadcaster.send(Event {
+                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                    instance_uuid: self.uuid.clone(),
+                                    instance_event_inner: InstanceEventInner::InstanceOutput {
+                                        message: line,
+                                    },
+                                    instance_name: name.clone(),
+                                }),
+                                details: "".to_string(),
+                                snowflake: Snowflake::default(),
+                                caused_by: CausedBy::System,
+                            });
+                        }
+                    }
+                }
+                let exit_status = match self.process.lock().await.as_mut() {
+                    Some(proc) => proc.wait().await.ok(),
+                    None => None,
+                };
+                info!("Instance {} process shutdown", name);
+                let is_stopping = *self.state.lock().await == State::Stopping;
+                let crashed = !is_stopping && exit_status.is_some_and(|status| !status.success());
+                if crashed {
+                    let exit_code = exit_status.and_then(|status| status.code());
+                    error!("[{}] Process crashed with exit code {:?}", name, exit_code);
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_uuid: self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::InstanceCrashed {
+                                exit_code,
+                                last_output: last_output.into_iter().collect(),
+                            },
+                            instance_name: name.clone(),
+                        }),
+                        details: "".to_string(),
+                        snowflake: Snowflake::default(),
+                        caused_by: CausedBy::System,
+                    });
+                }
+                self.process.lock().await.take();
+                self.stdin.lock().await.take();
+                self.readiness.stopped();
+                self.state
+                    .lock()
+                    .await
+                    .try_transition(
+                        if crashed {
+                            StateAction::InstanceCrash
+                        } else {
+                            StateAction::InstanceStop
+                        },
+                        Some(&|state| {
+                            self.event_broadcaster.send(self.state_transition_event(
+                                &name,
+                                state,
+                                "Instance stopping as server process exited",
+                                &caused_by,
+                            ));
+                        }),
+                    )
+                    .unwrap();
+            }
+        });
+        Ok(())
+    }
+
+    async fn stop(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        self.state.lock().await.try_transition(
+            StateAction::UserStop,
+            Some(&|state| {
+                self.event_broadcaster.send(self.state_transition_event(
+                    &config.name,
+                    state,
+                    "Stopping server",
+                    &caused_by,
+                ));
+            }),
+        )?;
+        match &config.stop_command {
+            Some(stop_command) => {
+                self.stdin
+                    .lock()
+                    .await
+                    .as_mut()
+                    .ok_or_else(|| eyre!("Failed to stop instance: stdin not available"))?
+                    .write_all(format!("{}\n", stop_command).as_bytes())
+                    .await
+                    .context("Failed to write to stdin")
+                    .map_err(|e| {
+                        error!("[{}] Failed to stop instance: {}", config.name, e);
+                        e
+                    })?;
+            }
+            None => {
+                let mut process = self.process.lock().await;
+                let process = process
+                    .as_mut()
+                    .ok_or_else(|| eyre!("Failed to stop instance: process not available"))?;
+                let terminated = process.id().map_or(false, |pid| {
+                    let mut sys = sysinfo::System::new();
+                    sys.refresh_process(Pid::from_u32(pid));
+                    sys.process(Pid::from_u32(pid))
+                        .and_then(|proc| proc.kill_with(Signal::Term))
+                        .unwrap_or(false)
+                });
+                // SIGTERM is not available on every platform
+                if !terminated {
+                    process.start_kill().context("Failed to kill process")?;
+                }
+            }
+        }
+        if block {
+            self.wait_for_state(State::Stopped).await
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        if block {
+            self.stop(caused_by.clone(), block).await?;
+            self.start(caused_by, block).await
+        } else {
+            self.state
+                .lock()
+                .await
+                .try_new_state(StateAction::UserStop, None)?;
+
+            let name = self.config.lock().await.name.clone();
+            let mut __self = self.clone();
+            tokio::task::spawn(async move {
+                // the process may fail to come back, which is no reason to panic
+                if let Err(e) = self.stop(caused_by.clone(), true).await {
+                    error!("[{name}] Failed to stop instance for a restart : {e}");
+                    return;
+                }
+                if let Err(e) = self.start(caused_by, block).await {
+                    error!("[{name}] Failed to start instance after a restart : {e}");
+                }
+            });
+            Ok(())
+        }
+    }
+
+    async fn kill(&mut self, _caused_by: CausedBy) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        if self.state().await.is_stopped() {
+            warn!("[{}] Instance is already stopped", config.name);
+            return Err(eyre!("Instance is already stopped").into());
+        }
+        // taken out so the exit is not mistaken for a crash
+        self.process
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| eyre!("Failed to kill instance: process not available"))?
+            .kill()
+            .await
+            .context("Failed to kill process")
+            .map_err(|e| {
+                error!("[{}] Failed to kill instance: {}", config.name, e);
+                e
+            })?;
+        Ok(())
+    }
+
+    async fn state(&self) -> State {
+        *self.state.lock().await
+    }
+
+    async fn readiness(&self) -> Readiness {
+        self.readiness.readiness()
+    }
+
+    async fn send_command(&self, command: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        if self.state().await.is_stopped() {
+            return Err(eyre!("Instance is stopped").into());
+        }
+        self.stdin
+            .lock()
+            .await
+            .as_mut()
+            .ok_or_else(|| eyre!("Failed to send command to instance: stdin not available"))?
+            .write_all(format!("{}\n", command).as_bytes())
+            .await
+            .context("Failed to send command to instance")?;
+        Ok(())
+    }
+
+    async fn pid(&self) -> Option<u32> {
+        self.process.lock().await.as_ref().and_then(|p| p.id())
+    }
+
+    async fn monitor(&self) -> MonitorReport {
+        let pid = match self.process.lock().await.as_ref().and_then(|p| p.id()) {
+            Some(pid) => Pid::from_u32(pid),
+            None => return MonitorReport::default(),
+        };
+        let mut sys = self.system.lock().await;
+        sys.refresh_process(pid);
+        let report = match sys.process(pid) {
+            Some(proc) => MonitorReport {
+                memory_usage: Some(proc.memory()),
+                disk_usage: Some(proc.disk_usage().into()),
+                cpu_usage: Some(proc.cpu_usage() / sys.cpus().len() as f32),
+                start_time: Some(proc.start_time()),
+                network_usage: None,
+                directory_size: None,
+                game_metrics: None,
+            },
+            None => return MonitorReport::default(),
+        };
+        drop(sys);
+        let port = self.config.lock().await.port;
+        MonitorReport {
+            network_usage: self.network_usage_tracker.lock().await.sample(port).await,
+            ..report
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Archiving takes an instance out of the instance list without deleting it. Its directory is moved
+//! out of the instances directory so it isn't restored at startup, and its port is released, until
+//! it is unarchived.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::t_configurable::Game,
+    types::InstanceUuid,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct ArchivedInstance {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub game_type: Game,
+    /// the port the instance had, it is taken back on unarchive
+    pub port: u32,
+    /// the name of its directory, in the archived instances directory while archived and in the
+    /// instances directory once unarchived
+    pub directory_name: String,
+    pub archived_at: i64,
+    pub archived_by: CausedBy,
+}
+
+/// The archived instances, persisted in the stores directory
+pub struct ArchivedInstances {
+    path_to_store: PathBuf,
+    archived: HashMap<InstanceUuid, ArchivedInstance>,
+}
+
+impl ArchivedInstances {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            archived: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.archived = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read archived instances file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.archived = serde_json::from_slice(&content).context(format!(
+            "Failed to parse archived instances file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.archived)
+                .context("Failed to serialize archived instances")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write archived instances file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Every archived instance, most recently archived first
+    pub fn list(&self) -> Vec<ArchivedInstance> {
+        let mut archived: Vec<ArchivedInstance> = self.archived.values().cloned().collect();
+        archived.sort_by(|a, b| b.archived_at.cmp(&a.archived_at));
+        archived
+    }
+
+    pub fn get(&self, uuid: &InstanceUuid) -> Result<&ArchivedInstance, Error> {
+        self.archived.get(uuid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Archived instance not found"),
+        })
+    }
+
+    pub async fn insert(&mut self, archived_instance: ArchivedInstance) -> Result<(), Error> {
+        self.archived
+            .insert(archived_instance.uuid.clone(), archived_instance);
+        self.write_to_file().await
+    }
+
+    pub async fn remove(&mut self, uuid: &InstanceUuid) -> Result<ArchivedInstance, Error> {
+        let archived_instance = self.archived.remove(uuid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Archived instance not found"),
+        })?;
+        self.write_to_file().await?;
+        Ok(archived_instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchivedInstance, ArchivedInstances};
+    use crate::{events::CausedBy, traits::t_configurable::Game, types::InstanceUuid};
+
+    fn archived_instance(uuid: &InstanceUuid, archived_at: i64) -> ArchivedInstance {
+        ArchivedInstance {
+            uuid: uuid.clone(),
+            name: "survival".to_string(),
+            game_type: Game::MinecraftBedrock,
+            port: 25565,
+            directory_name: format!("survival-{}", uuid.no_prefix()),
+            archived_at,
+            archived_by: CausedBy::System,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archived_instances() {
+        let temp_dir = tempdir::TempDir::new("test_archived_instances").unwrap();
+        let path = temp_dir.path().join("archived_instances.json");
+        let mut archived_instances = ArchivedInstances::new(path.clone());
+        let a: InstanceUuid = "INSTANCE_a".to_string().into();
+        let b: InstanceUuid = "INSTANCE_b".to_string().into();
+
+        archived_instances
+            .insert(archived_instance(&a, 1))
+            .await
+            .unwrap();
+        archived_instances
+            .insert(archived_instance(&b, 2))
+            .await
+            .unwrap();
+
+        let mut reloaded = ArchivedInstances::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(
+            reloaded
+                .list()
+                .into_iter()
+                .map(|archived| archived.uuid)
+                .collect::<Vec<_>>(),
+            vec![b.clone(), a.clone()]
+        );
+
+        assert_eq!(reloaded.remove(&a).await.unwrap().port, 25565);
+        assert!(reloaded.get(&a).is_err());
+        assert!(reloaded.remove(&a).await.is_err());
+        assert!(reloaded.get(&b).is_ok());
+    }
+}
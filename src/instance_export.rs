@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue},
+    jobs::JobHandle,
+    prelude::path_to_tmp,
+    types::InstanceUuid,
+    util::format_byte_download,
+};
+
+/// top level directories holding logs
+const LOG_DIRS: [&str; 3] = ["logs", "crash-reports", "debug"];
+/// top level directories holding caches the server rebuilds on its own
+const CACHE_DIRS: [&str; 3] = ["cache", ".cache", ".fabric"];
+/// exports are downloaded right after they are written, older ones are removed
+const EXPORT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// number of progression updates sent over an export
+const PROGRESS_STEPS: u64 = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Zip,
+    TarGz,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Zip => "zip",
+            ExportFormat::TarGz => "tar.gz",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, TS)]
+#[ts(export)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub format: ExportFormat,
+    #[serde(default)]
+    pub exclude_logs: bool,
+    #[serde(default)]
+    pub exclude_cache: bool,
+}
+
+pub(crate) fn path_to_exports() -> PathBuf {
+    path_to_tmp().join("exports")
+}
+
+fn is_excluded(relative_path: &Path, options: &ExportOptions) -> bool {
+    let top_level = match relative_path.components().next() {
+        Some(component) => component.as_os_str().to_string_lossy(),
+        None => return false,
+    };
+    (options.exclude_logs && LOG_DIRS.contains(&top_level.as_ref()))
+        || (options.exclude_cache && CACHE_DIRS.contains(&top_level.as_ref()))
+}
+
+/// The files under `src` to export, relative to it, with their size
+fn files_to_export(src: &Path, options: &ExportOptions) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let mut ret = Vec::new();
+    let mut walker = walkdir::WalkDir::new(src).min_depth(1).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.context(format!("Failed to read directory {}", src.display()))?;
+        let relative_path = entry
+            .path()
+            .strip_prefix(src)
+            .context("Failed to get relative path")?
+            .to_owned();
+        if is_excluded(&relative_path, options) {
+            if entry.file_type().is_dir() {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        if entry.file_type().is_file() {
+            let size = entry
+                .metadata()
+                .context(format!(
+                    "Failed to read metadata of {}",
+                    entry.path().display()
+                ))?
+                .len();
+            ret.push((relative_path, size));
+        }
+    }
+    Ok(ret)
+}
+
+/// Write the files under `src` into an archive at `dest`, `on_progress` is called with the bytes
+/// archived after each file, and writing stops with its error if it returns one
+fn write_archive(
+    src: &Path,
+    files: &[(PathBuf, u64)],
+    dest: &Path,
+    format: ExportFormat,
+    on_progress: &mut dyn FnMut(u64) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let file =
+        File::create(dest).context(format!("Failed to create archive {}", dest.display()))?;
+    match format {
+        ExportFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .large_file(true);
+            for (relative_path, size) in files {
+                // zip entries always use forward slashes
+                let name = relative_path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                zip.start_file(name, options)
+                    .context("Failed to write archive")?;
+                let mut source = File::open(src.join(relative_path)).context(format!(
+                    "Failed to open {}",
+                    src.join(relative_path).display()
+                ))?;
+                std::io::copy(&mut source, &mut zip)
+                    .context(format!("Failed to archive {}", relative_path.display()))?;
+                on_progress(*size)?;
+            }
+            zip.finish()
+                .context("Failed to finish archive")?
+                .flush()
+                .context("Failed to finish archive")?;
+        }
+        ExportFormat::TarGz => {
+            let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            builder.follow_symlinks(false);
+            for (relative_path, size) in files {
+                builder
+                    .append_path_with_name(src.join(relative_path), relative_path)
+                    .context(format!("Failed to archive {}", relative_path.display()))?;
+                on_progress(*size)?;
+            }
+            builder
+                .into_inner()
+                .context("Failed to finish archive")?
+                .finish()
+                .context("Failed to finish archive")?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the exports that were left behind for longer than `EXPORT_LIFETIME`
+pub(crate) fn remove_stale_exports() {
+    let entries = match std::fs::read_dir(path_to_exports()) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let is_stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > EXPORT_LIFETIME);
+        if is_stale {
+            if let Err(e) = std::fs::remove_file(entry.path()) {
+                warn!(
+                    "Failed to remove stale export {} : {e}",
+                    entry.path().display()
+                );
+            }
+        }
+    }
+}
+
+/// Package some files of an instance, relative to `instance_path`, into a tar.gz in the tmp
+/// directory, e.g. the files a mirror of the instance is missing
+pub fn export_selected_files(
+    instance_path: &Path,
+    relative_paths: &[PathBuf],
+    name: &str,
+) -> Result<PathBuf, Error> {
+    let mut files = Vec::new();
+    for relative_path in relative_paths {
+        let path = instance_path.join(relative_path);
+        let size = std::fs::metadata(&path)
+            .context(format!("Failed to read metadata of {}", path.display()))?
+            .len();
+        files.push((relative_path.clone(), size));
+    }
+    remove_stale_exports();
+    std::fs::create_dir_all(path_to_exports()).context("Failed to create the export directory")?;
+    let dest = path_to_exports().join(format!(
+        "{}-{}.{}",
+        sanitize_filename::sanitize(name),
+        chrono::Utc::now().format("%Y-%m-%d-%H%M%S"),
+        ExportFormat::TarGz.extension()
+    ));
+    if let Err(e) = write_archive(
+        instance_path,
+        &files,
+        &dest,
+        ExportFormat::TarGz,
+        &mut |_| Ok(()),
+    ) {
+        let _ = std::fs::remove_file(&dest);
+        return Err(e);
+    }
+    Ok(dest)
+}
+
+/// Package the instance directory into an archive in the tmp directory.
+///
+/// Emits a progression event for the duration of the export and reports its progress to `job`,
+/// cancelling the job stops the export. Once the archive is written it is added to
+/// `download_urls` under `download_key`, which the end event carries.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_instance(
+    instance_uuid: InstanceUuid,
+    instance_name: String,
+    instance_path: PathBuf,
+    options: ExportOptions,
+    download_key: String,
+    download_urls: Arc<Mutex<HashMap<String, PathBuf>>>,
+    event_broadcaster: EventBroadcaster,
+    caused_by: CausedBy,
+    job: JobHandle,
+) -> Result<PathBuf, Error> {
+    let dest = path_to_exports().join(format!(
+        "{}-{}.{}",
+        sanitize_filename::sanitize(&instance_name),
+        chrono::Utc::now().format("%Y-%m-%d-%H%M%S"),
+        options.format.extension()
+    ));
+    let files = {
+        let instance_path = instance_path.clone();
+        tokio::task::spawn_blocking(move || files_to_export(&instance_path, &options))
+            .await
+            .context("Failed to list the files to export")??
+    };
+    let total: u64 = files.iter().map(|(_, size)| size).sum();
+    let (progression_start_event, event_id) = Event::new_progression_event_start(
+        format!("Exporting {instance_name}"),
+        Some(total as f64),
+        Some(ProgressionStartValue::InstanceExport {
+            instance_uuid: instance_uuid.clone(),
+        }),
+        caused_by,
+    );
+    event_broadcaster.send(progression_start_event);
+    // the archive is written on a blocking thread, which reports the bytes archived so far
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+    let task = tokio::task::spawn_blocking({
+        let dest = dest.clone();
+        let cancellation_token = job.cancellation_token();
+        move || {
+            remove_stale_exports();
+            std::fs::create_dir_all(path_to_exports())
+                .context("Failed to create the export directory")?;
+            let step = (total / PROGRESS_STEPS).max(1);
+            let mut archived = 0;
+            let mut reported = 0;
+            write_archive(&instance_path, &files, &dest, options.format, &mut |size| {
+                if cancellation_token.is_cancelled() {
+                    return Err(eyre!("Export cancelled").into());
+                }
+                archived += size;
+                if archived - reported >= step {
+                    let _ = progress_tx.send(archived);
+                    reported = archived;
+                }
+                Ok(())
+            })
+        }
+    });
+    let mut reported = 0;
+    while let Some(archived) = progress_rx.recv().await {
+        let message = format!("Exporting, {}", format_byte_download(archived, total));
+        job.set_progress(
+            archived as f64 / total.max(1) as f64 * 100.0,
+            Some(message.clone()),
+        );
+        event_broadcaster.send(Event::new_progression_event_update(
+            &event_id,
+            message,
+            (archived - reported) as f64,
+        ));
+        reported = archived;
+    }
+    let result = task
+        .await
+        .context("Export task panicked")
+        .map_err(Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(()) => {
+            download_urls
+                .lock()
+                .await
+                .insert(download_key.clone(), dest.clone());
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some("Export complete"),
+                Some(ProgressionEndValue::InstanceExport {
+                    instance_uuid,
+                    download_key,
+                }),
+            ));
+            Ok(dest)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&dest);
+            event_broadcaster.send(Event::new_progression_event_end(
+                event_id,
+                false,
+                Some(&format!("Export failed: {e}")),
+                None,
+            ));
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, io::Read, path::PathBuf};
+
+    use flate2::read::GzDecoder;
+
+    use super::{files_to_export, write_archive, ExportFormat, ExportOptions};
+
+    #[test]
+    fn test_export() {
+        let temp_dir = tempdir::TempDir::new("test_export").unwrap();
+        let src = temp_dir.path().join("instance");
+        for (path, content) in [
+            ("server.properties", "server-port=25565"),
+            ("world/level.dat", "level"),
+            ("logs/latest.log", "log"),
+            ("cache/mojang.jar", "cache"),
+        ] {
+            let path = src.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let options = ExportOptions {
+            format: ExportFormat::Zip,
+            exclude_logs: true,
+            exclude_cache: false,
+        };
+        let files = files_to_export(&src, &options).unwrap();
+        let names: BTreeSet<PathBuf> = files.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            names,
+            BTreeSet::from([
+                PathBuf::from("server.properties"),
+                PathBuf::from("world/level.dat"),
+                PathBuf::from("cache/mojang.jar"),
+            ])
+        );
+
+        let dest = temp_dir.path().join("export.zip");
+        let mut archived = 0;
+        write_archive(&src, &files, &dest, options.format, &mut |size| {
+            archived += size;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(archived, files.iter().map(|(_, size)| size).sum::<u64>());
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&dest).unwrap()).unwrap();
+        let mut level = String::new();
+        zip.by_name("world/level.dat")
+            .unwrap()
+            .read_to_string(&mut level)
+            .unwrap();
+        assert_eq!(level, "level");
+        assert!(zip.by_name("logs/latest.log").is_err());
+
+        let options = ExportOptions {
+            format: ExportFormat::TarGz,
+            exclude_logs: true,
+            exclude_cache: true,
+        };
+        let files = files_to_export(&src, &options).unwrap();
+        assert_eq!(files.len(), 2);
+        let dest = temp_dir.path().join("export.tar.gz");
+        write_archive(&src, &files, &dest, options.format, &mut |_| Ok(())).unwrap();
+        let mut tar = tar::Archive::new(GzDecoder::new(std::fs::File::open(&dest).unwrap()));
+        let names: BTreeSet<PathBuf> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(
+            names,
+            BTreeSet::from([
+                PathBuf::from("server.properties"),
+                PathBuf::from("world/level.dat"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    types::InstanceUuid,
+};
+
+const MAX_GROUPS_PER_INSTANCE: usize = 32;
+const MAX_GROUP_NAME_LEN: usize = 32;
+
+/// The outcome of a bulk operation on one instance of a group
+#[derive(Serialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct BulkOperationResult {
+    pub instance_uuid: InstanceUuid,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl BulkOperationResult {
+    pub fn new(instance_uuid: InstanceUuid, result: Result<(), Error>) -> Self {
+        Self {
+            instance_uuid,
+            success: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Group names are lowercase so `Lobby` and `lobby` can't be two groups
+fn validate_group_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.len() > MAX_GROUP_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Group names must be 1 to {MAX_GROUP_NAME_LEN} lowercase letters, digits, - or _"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The groups each instance is in, persisted in the stores directory. A group exists as long as
+/// an instance is in it.
+pub struct InstanceGroups {
+    path_to_store: PathBuf,
+    groups: HashMap<InstanceUuid, BTreeSet<String>>,
+}
+
+impl InstanceGroups {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            groups: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.groups = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read instance groups file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.groups = serde_json::from_slice(&content).context(format!(
+            "Failed to parse instance groups file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.groups)
+                .context("Failed to serialize instance groups")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write instance groups file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The groups an instance is in
+    pub fn groups_of(&self, instance_uuid: &InstanceUuid) -> BTreeSet<String> {
+        self.groups.get(instance_uuid).cloned().unwrap_or_default()
+    }
+
+    /// Every group with its instances
+    pub fn groups(&self) -> BTreeMap<String, Vec<InstanceUuid>> {
+        let mut groups: BTreeMap<String, Vec<InstanceUuid>> = BTreeMap::new();
+        for (instance_uuid, names) in &self.groups {
+            for name in names {
+                groups
+                    .entry(name.clone())
+                    .or_default()
+                    .push(instance_uuid.clone());
+            }
+        }
+        for members in groups.values_mut() {
+            members.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        }
+        groups
+    }
+
+    /// The instances in a group, an unknown group has none
+    pub fn members(&self, name: &str) -> Vec<InstanceUuid> {
+        self.groups().remove(name).unwrap_or_default()
+    }
+
+    /// Replace the groups of an instance
+    pub async fn set_groups(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        names: BTreeSet<String>,
+    ) -> Result<BTreeSet<String>, Error> {
+        if names.len() > MAX_GROUPS_PER_INSTANCE {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("An instance can be in at most {MAX_GROUPS_PER_INSTANCE} groups"),
+            });
+        }
+        for name in &names {
+            validate_group_name(name)?;
+        }
+        let old = if names.is_empty() {
+            self.groups.remove(instance_uuid)
+        } else {
+            self.groups.insert(instance_uuid.clone(), names.clone())
+        };
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.groups.insert(instance_uuid.clone(), old),
+                None => self.groups.remove(instance_uuid),
+            };
+            return Err(e);
+        }
+        Ok(names)
+    }
+
+    /// Take a deleted instance out of its groups
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.groups.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.groups.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{validate_group_name, InstanceGroups};
+    use crate::types::InstanceUuid;
+
+    #[test]
+    fn test_validate_group_name() {
+        assert!(validate_group_name("minigames").is_ok());
+        assert!(validate_group_name("bed_wars-2").is_ok());
+        assert!(validate_group_name("").is_err());
+        assert!(validate_group_name("Lobby").is_err());
+        assert!(validate_group_name("a b").is_err());
+        assert!(validate_group_name(&"a".repeat(33)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_instance_groups() {
+        let temp_dir = tempdir::TempDir::new("test_instance_groups").unwrap();
+        let path = temp_dir.path().join("instance_groups.json");
+        let mut instance_groups = InstanceGroups::new(path.clone());
+        let a: InstanceUuid = "INSTANCE_a".to_string().into();
+        let b: InstanceUuid = "INSTANCE_b".to_string().into();
+        let names = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(ToString::to_string).collect()
+        };
+
+        instance_groups
+            .set_groups(&a, names(&["minigames", "eu"]))
+            .await
+            .unwrap();
+        instance_groups
+            .set_groups(&b, names(&["minigames"]))
+            .await
+            .unwrap();
+        assert!(instance_groups
+            .set_groups(&b, names(&["Bad Name"]))
+            .await
+            .is_err());
+        assert_eq!(
+            instance_groups.members("minigames"),
+            vec![a.clone(), b.clone()]
+        );
+        assert_eq!(instance_groups.members("eu"), vec![a.clone()]);
+        assert!(instance_groups.members("unknown").is_empty());
+
+        let mut reloaded = InstanceGroups::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.groups_of(&a), names(&["eu", "minigames"]));
+
+        reloaded.remove_instance(&a).await.unwrap();
+        reloaded.set_groups(&b, BTreeSet::new()).await.unwrap();
+        assert!(reloaded.groups().is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+
+use crate::{prelude::GameInstance, types::InstanceUuid};
+
+/// The instances of the core.
+///
+/// Instances are handed out as clones, which share their state with the instance in the map, and
+/// each guards its own state with its own locks. The map is only locked for as long as it takes
+/// to look an instance up, never while calling into one, so a slow `stop()` of one instance
+/// doesn't hold up calls to the others.
+#[derive(Clone, Default)]
+pub struct InstanceMap {
+    instances: Arc<DashMap<InstanceUuid, GameInstance>>,
+}
+
+impl InstanceMap {
+    pub fn new(instances: impl IntoIterator<Item = (InstanceUuid, GameInstance)>) -> Self {
+        Self {
+            instances: Arc::new(instances.into_iter().collect()),
+        }
+    }
+
+    pub fn get(&self, uuid: &InstanceUuid) -> Option<GameInstance> {
+        self.instances
+            .get(uuid)
+            .map(|instance| instance.value().clone())
+    }
+
+    pub fn contains_key(&self, uuid: &InstanceUuid) -> bool {
+        self.instances.contains_key(uuid)
+    }
+
+    pub fn insert(&self, uuid: InstanceUuid, instance: GameInstance) -> Option<GameInstance> {
+        self.instances.insert(uuid, instance)
+    }
+
+    pub fn remove(&self, uuid: &InstanceUuid) -> Option<GameInstance> {
+        self.instances.remove(uuid).map(|(_, instance)| instance)
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn uuids(&self) -> Vec<InstanceUuid> {
+        self.instances
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Every instance at the time of the call, safe to hold across awaits
+    pub fn snapshot(&self) -> Vec<(InstanceUuid, GameInstance)> {
+        self.instances
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    pub fn values(&self) -> Vec<GameInstance> {
+        self.instances
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+}
@@ -0,0 +1,346 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    events::{
+        Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEndValue,
+        ProgressionEventInner,
+    },
+    instance_map::InstanceMap,
+    prelude::GameInstance,
+    traits::{
+        t_configurable::{DisplayMetadata, Game, TConfigurable},
+        t_server::State,
+        InstanceInfo, TInstance,
+    },
+    types::InstanceUuid,
+};
+
+/// Instances are re-read this often, for the changes that don't come with an event
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An instance busy with a long operation is skipped rather than holding up the others
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the instance list of the dashboard shows of an instance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct InstanceSummary {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub game_type: Game,
+    pub state: State,
+    pub port: u32,
+    pub player_count: Option<u32>,
+    pub max_player_count: Option<u32>,
+    pub creation_time: i64,
+    pub display: DisplayMetadata,
+}
+
+impl From<&InstanceInfo> for InstanceSummary {
+    fn from(info: &InstanceInfo) -> Self {
+        InstanceSummary {
+            uuid: info.uuid.clone(),
+            name: info.name.clone(),
+            game_type: info.game_type.clone(),
+            state: info.state,
+            port: info.port,
+            player_count: info.player_count,
+            max_player_count: info.max_player_count,
+            creation_time: info.creation_time,
+            display: info.display.clone(),
+        }
+    }
+}
+
+impl InstanceSummary {
+    /// Same order as `InstanceInfo::list_order`
+    fn list_key(&self) -> (bool, Option<i32>, i64) {
+        (
+            self.display.sort_order.is_none(),
+            self.display.sort_order,
+            self.creation_time,
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct InstanceSummaryQuery {
+    /// number of instances to skip, in list order
+    pub offset: Option<u32>,
+    pub limit: Option<u32>,
+    pub state: Option<State>,
+    /// only instances whose name contains this, ignoring case
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, TS, JsonSchema)]
+#[ts(export)]
+pub struct InstanceSummaryPage {
+    pub summaries: Vec<InstanceSummary>,
+    /// number of instances matching the query, across all pages
+    pub total: u32,
+}
+
+/// A projection of every instance kept up to date from events, so listing instances doesn't
+/// lock each of them.
+///
+/// State and player changes are applied as their events go by, other changes made through the
+/// API refresh the instance they touch, and every instance is re-read every
+/// `RECONCILE_INTERVAL` for the rest.
+#[derive(Clone, Default)]
+pub struct InstanceSummaries {
+    summaries: Arc<DashMap<InstanceUuid, InstanceSummary>>,
+}
+
+impl InstanceSummaries {
+    pub fn get(&self, uuid: &InstanceUuid) -> Option<InstanceSummary> {
+        self.summaries.get(uuid).map(|summary| summary.clone())
+    }
+
+    /// The summaries `can_view` lets through matching `query`, in list order
+    pub fn page(
+        &self,
+        query: &InstanceSummaryQuery,
+        can_view: impl Fn(&InstanceUuid) -> bool,
+    ) -> InstanceSummaryPage {
+        let name = query.name.as_ref().map(|name| name.to_lowercase());
+        let mut summaries: Vec<InstanceSummary> = self
+            .summaries
+            .iter()
+            .filter(|summary| can_view(&summary.uuid))
+            .filter(|summary| query.state.map_or(true, |state| summary.state == state))
+            .filter(|summary| {
+                name.as_ref()
+                    .map_or(true, |name| summary.name.to_lowercase().contains(name))
+            })
+            .map(|summary| summary.clone())
+            .collect();
+        summaries.sort_by_key(InstanceSummary::list_key);
+        let total = summaries.len() as u32;
+        let summaries = summaries
+            .into_iter()
+            .skip(query.offset.unwrap_or(0) as usize)
+            .take(query.limit.unwrap_or(50).min(1000) as usize)
+            .collect();
+        InstanceSummaryPage { summaries, total }
+    }
+
+    pub fn insert(&self, summary: InstanceSummary) {
+        self.summaries.insert(summary.uuid.clone(), summary);
+    }
+
+    pub fn remove(&self, uuid: &InstanceUuid) {
+        self.summaries.remove(uuid);
+    }
+
+    /// Re-read an instance, for changes that don't come with an event
+    pub async fn refresh(&self, instance: &GameInstance) {
+        match tokio::time::timeout(REFRESH_TIMEOUT, instance.get_instance_info()).await {
+            Ok(info) => self.insert(InstanceSummary::from(&info)),
+            Err(_) => warn!(
+                "Timed out reading instance {} for its summary",
+                instance.uuid().await
+            ),
+        }
+    }
+
+    /// Re-read every instance and drop the summaries of the ones that are gone
+    pub async fn reconcile(&self, instances: &InstanceMap) {
+        self.summaries
+            .retain(|uuid, _| instances.contains_key(uuid));
+        for (_, instance) in instances.snapshot() {
+            self.refresh(&instance).await;
+        }
+    }
+
+    pub fn apply(&self, event: &Event) {
+        match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_event_inner,
+                ..
+            }) => {
+                let mut summary = match self.summaries.get_mut(instance_uuid) {
+                    Some(summary) => summary,
+                    None => return,
+                };
+                match instance_event_inner {
+                    InstanceEventInner::StateTransition { to } => {
+                        summary.state = *to;
+                        // nobody is left on a server that isn't running
+                        if !matches!(to, State::Running) && summary.player_count.is_some() {
+                            summary.player_count = Some(0);
+                        }
+                    }
+                    InstanceEventInner::PlayerChange { player_list, .. } => {
+                        summary.player_count = Some(player_list.len() as u32);
+                    }
+                    _ => {}
+                }
+            }
+            EventInner::ProgressionEvent(progression_event) => {
+                if let ProgressionEventInner::ProgressionEnd {
+                    success: true,
+                    inner: Some(inner),
+                    ..
+                } = progression_event.progression_event_inner()
+                {
+                    match inner {
+                        ProgressionEndValue::InstanceCreation(info) => {
+                            self.insert(InstanceSummary::from(info))
+                        }
+                        ProgressionEndValue::InstanceDelete { instance_uuid }
+                        | ProgressionEndValue::InstanceArchive { instance_uuid } => {
+                            self.remove(instance_uuid)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+pub async fn instance_summary_task(
+    mut event_receiver: Receiver<Event>,
+    summaries: InstanceSummaries,
+    instances: InstanceMap,
+) {
+    let mut reconcile_interval = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        tokio::select! {
+            result = event_receiver.recv() => match result {
+                Ok(event) => {
+                    if !event.is_event_console_message() {
+                        summaries.apply(&event);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {
+                    warn!("Instance summary task lagged");
+                    // a missed event may have changed any instance
+                    summaries.reconcile(&instances).await;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // the first tick is immediate, which fills the summaries on start
+            _ = reconcile_interval.tick() => summaries.reconcile(&instances).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstanceSummaries, InstanceSummary, InstanceSummaryQuery};
+    use crate::{
+        events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+        traits::{
+            t_configurable::{DisplayMetadata, Game},
+            t_server::State,
+        },
+        types::{InstanceUuid, Snowflake},
+    };
+
+    fn summary(name: &str, creation_time: i64, sort_order: Option<i32>) -> InstanceSummary {
+        InstanceSummary {
+            uuid: InstanceUuid::from(name.to_string()),
+            name: name.to_string(),
+            game_type: Game::Process {
+                game_display_name: "Terraria".to_string(),
+            },
+            state: State::Stopped,
+            port: 7777,
+            player_count: Some(0),
+            max_player_count: None,
+            creation_time,
+            display: DisplayMetadata {
+                sort_order,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_page() {
+        let summaries = InstanceSummaries::default();
+        summaries.insert(summary("survival", 3, None));
+        summaries.insert(summary("creative", 1, None));
+        summaries.insert(summary("lobby", 2, Some(0)));
+        summaries.insert(summary("hidden", 0, None));
+        let can_view = |uuid: &InstanceUuid| uuid.as_ref() != "hidden";
+
+        let names = |query: &InstanceSummaryQuery| {
+            let page = summaries.page(query, can_view);
+            (
+                page.summaries
+                    .into_iter()
+                    .map(|summary| summary.name)
+                    .collect::<Vec<_>>(),
+                page.total,
+            )
+        };
+        assert_eq!(
+            names(&InstanceSummaryQuery::default()),
+            (
+                vec!["lobby".into(), "creative".into(), "survival".into()],
+                3
+            )
+        );
+        assert_eq!(
+            names(&InstanceSummaryQuery {
+                offset: Some(1),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            (vec!["creative".into()], 3)
+        );
+        assert_eq!(
+            names(&InstanceSummaryQuery {
+                name: Some("SURV".to_string()),
+                ..Default::default()
+            }),
+            (vec!["survival".into()], 1)
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let summaries = InstanceSummaries::default();
+        summaries.insert(InstanceSummary {
+            state: State::Running,
+            player_count: Some(2),
+            ..summary("survival", 0, None)
+        });
+        let state_transition = |uuid: &str, to| Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: InstanceUuid::from(uuid.to_string()),
+                instance_name: uuid.to_string(),
+                instance_event_inner: InstanceEventInner::StateTransition { to },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        };
+
+        summaries.apply(&state_transition("survival", State::Stopping));
+        let stopping = summaries
+            .get(&InstanceUuid::from("survival".to_string()))
+            .unwrap();
+        assert_eq!(stopping.state, State::Stopping);
+        assert_eq!(stopping.player_count, Some(0));
+
+        // events of instances without a summary don't make one up
+        summaries.apply(&state_transition("creative", State::Running));
+        assert!(summaries
+            .get(&InstanceUuid::from("creative".to_string()))
+            .is_none());
+    }
+}
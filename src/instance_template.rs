@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    handlers::instance_setup_configs::HandlerGameType,
+    prelude::path_to_stores,
+    traits::t_configurable::manifest::SetupValue,
+    util::rand_alphanumeric,
+};
+
+/// A saved setup config that new instances can be created from
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct InstanceTemplate {
+    pub id: String,
+    pub name: String,
+    pub game_type: HandlerGameType,
+    pub setup_value: SetupValue,
+    pub creation_time: i64,
+}
+
+fn path_to_templates() -> PathBuf {
+    path_to_stores().join("templates")
+}
+
+fn path_to_template(id: &str) -> Result<PathBuf, Error> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid template id"),
+        });
+    }
+    Ok(path_to_templates().join(format!("{id}.json")))
+}
+
+pub async fn list_templates() -> Result<Vec<InstanceTemplate>, Error> {
+    let path = path_to_templates();
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    let mut entries = tokio::fs::read_dir(&path)
+        .await
+        .context("Failed to read templates directory")?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read templates directory")?
+    {
+        let content = crate::util::fs::read_to_string(entry.path()).await?;
+        match serde_json::from_str::<InstanceTemplate>(&content) {
+            Ok(template) => ret.push(template),
+            Err(e) => warn!("Skipping invalid template {} : {e}", entry.path().display()),
+        }
+    }
+    ret.sort_by(|a, b| a.creation_time.cmp(&b.creation_time));
+    Ok(ret)
+}
+
+pub async fn get_template(id: &str) -> Result<InstanceTemplate, Error> {
+    let path = path_to_template(id)?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Template not found"),
+        });
+    }
+    let content = crate::util::fs::read_to_string(&path).await?;
+    Ok(serde_json::from_str(&content).context(format!("Failed to parse template {id}"))?)
+}
+
+pub async fn save_template(
+    name: String,
+    game_type: HandlerGameType,
+    setup_value: SetupValue,
+) -> Result<InstanceTemplate, Error> {
+    let template = InstanceTemplate {
+        id: rand_alphanumeric(12),
+        name,
+        game_type,
+        setup_value,
+        creation_time: chrono::Utc::now().timestamp(),
+    };
+    crate::util::fs::create_dir_all(path_to_templates()).await?;
+    crate::util::fs::write_all(
+        path_to_template(&template.id)?,
+        serde_json::to_string_pretty(&template).context("Failed to serialize template")?,
+    )
+    .await?;
+    Ok(template)
+}
+
+pub async fn delete_template(id: &str) -> Result<(), Error> {
+    let path = path_to_template(id)?;
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Template not found"),
+        });
+    }
+    crate::util::fs::remove_file(path).await
+}
@@ -0,0 +1,459 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_map::InstanceMap,
+    traits::{t_configurable::TConfigurable, t_server::State},
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+/// webhooks that take longer than this to answer are given up on
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[ts(export)]
+pub enum InstanceTransition {
+    Starting,
+    /// the instance is done starting, see `InstanceEventInner::InstanceReady`
+    Ready,
+    Stopped,
+    Crashed,
+}
+
+impl InstanceTransition {
+    /// The transition `event` is, with the details specific to it
+    fn from_event(event: &InstanceEventInner) -> Option<(InstanceTransition, Value)> {
+        match event {
+            InstanceEventInner::StateTransition {
+                to: State::Starting,
+            } => Some((InstanceTransition::Starting, json!({}))),
+            InstanceEventInner::InstanceReady { startup_duration } => Some((
+                InstanceTransition::Ready,
+                json!({ "startup_duration": startup_duration }),
+            )),
+            InstanceEventInner::StateTransition { to: State::Stopped } => {
+                Some((InstanceTransition::Stopped, json!({})))
+            }
+            InstanceEventInner::InstanceCrashed { exit_code, .. } => Some((
+                InstanceTransition::Crashed,
+                json!({ "exit_code": exit_code }),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A callback url of one instance, posted to on the transitions it subscribes to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct InstanceWebhook {
+    pub id: String,
+    pub url: String,
+    pub transitions: HashSet<InstanceTransition>,
+    /// sent as is with every call, e.g. to tell a matchmaker which pool the server belongs to
+    #[ts(type = "Record<string, unknown>")]
+    pub extra: Map<String, Value>,
+    pub enabled: bool,
+    pub creation_time: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct InstanceWebhookConfig {
+    pub url: String,
+    pub transitions: HashSet<InstanceTransition>,
+    #[serde(default)]
+    #[ts(type = "Record<string, unknown>")]
+    pub extra: Map<String, Value>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl InstanceWebhookConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.transitions.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A webhook must subscribe to at least one transition"),
+            });
+        }
+        match url::Url::parse(&self.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Webhook url must be a valid http or https url"),
+            }),
+        }
+    }
+}
+
+/// The body posted to a webhook of `instance_uuid` on `transition`
+fn payload(
+    webhook: &InstanceWebhook,
+    instance_uuid: &InstanceUuid,
+    instance_name: &str,
+    port: Option<u32>,
+    transition: InstanceTransition,
+    details: Value,
+    timestamp: i64,
+) -> Value {
+    json!({
+        "transition": transition,
+        "instance_uuid": instance_uuid,
+        "instance_name": instance_name,
+        "port": port,
+        "timestamp": timestamp,
+        "details": details,
+        "extra": webhook.extra,
+    })
+}
+
+/// Webhooks of every instance, persisted in the stores directory
+pub struct InstanceWebhooks {
+    path_to_store: PathBuf,
+    webhooks: HashMap<InstanceUuid, Vec<InstanceWebhook>>,
+    http: reqwest::Client,
+}
+
+impl InstanceWebhooks {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            webhooks: HashMap::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.webhooks = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read instance webhooks file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.webhooks = serde_json::from_slice(&content).context(format!(
+            "Failed to parse instance webhooks file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.webhooks)
+                .context("Failed to serialize instance webhooks")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write instance webhooks file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn webhooks(&self, instance_uuid: &InstanceUuid) -> &[InstanceWebhook] {
+        self.webhooks
+            .get(instance_uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn not_found() -> Error {
+        Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Webhook not found"),
+        }
+    }
+
+    pub async fn add_webhook(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        config: InstanceWebhookConfig,
+    ) -> Result<InstanceWebhook, Error> {
+        config.validate()?;
+        let webhook = InstanceWebhook {
+            id: rand_alphanumeric(16),
+            url: config.url,
+            transitions: config.transitions,
+            extra: config.extra,
+            enabled: config.enabled,
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        let old_webhooks = self.webhooks.clone();
+        self.webhooks
+            .entry(instance_uuid.clone())
+            .or_default()
+            .push(webhook.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.webhooks = old_webhooks;
+            return Err(e);
+        }
+        Ok(webhook)
+    }
+
+    pub async fn update_webhook(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+        config: InstanceWebhookConfig,
+    ) -> Result<InstanceWebhook, Error> {
+        config.validate()?;
+        let old_webhooks = self.webhooks.clone();
+        let webhook = self
+            .webhooks
+            .get_mut(instance_uuid)
+            .and_then(|webhooks| webhooks.iter_mut().find(|webhook| webhook.id == id))
+            .ok_or_else(Self::not_found)?;
+        webhook.url = config.url;
+        webhook.transitions = config.transitions;
+        webhook.extra = config.extra;
+        webhook.enabled = config.enabled;
+        let webhook = webhook.clone();
+        if let Err(e) = self.write_to_file().await {
+            self.webhooks = old_webhooks;
+            return Err(e);
+        }
+        Ok(webhook)
+    }
+
+    pub async fn remove_webhook(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+    ) -> Result<(), Error> {
+        let old_webhooks = self.webhooks.clone();
+        let webhooks = self
+            .webhooks
+            .get_mut(instance_uuid)
+            .ok_or_else(Self::not_found)?;
+        let index = webhooks
+            .iter()
+            .position(|webhook| webhook.id == id)
+            .ok_or_else(Self::not_found)?;
+        webhooks.remove(index);
+        if webhooks.is_empty() {
+            self.webhooks.remove(instance_uuid);
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.webhooks = old_webhooks;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Forget the webhooks of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.webhooks.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.webhooks.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn deliver(http: &reqwest::Client, url: &str, payload: &Value) -> Result<(), Error> {
+    http.post(url)
+        .json(payload)
+        .timeout(DELIVERY_TIMEOUT)
+        .send()
+        .await
+        .context(format!("Failed to reach webhook {url}"))?
+        .error_for_status()
+        .context(format!("Webhook {url} rejected the call"))?;
+    Ok(())
+}
+
+/// Calls the webhooks of an instance as it goes through the transitions they subscribe to
+pub async fn instance_webhook_task(
+    mut event_receiver: Receiver<Event>,
+    instance_webhooks: Arc<Mutex<InstanceWebhooks>>,
+    instances: InstanceMap,
+) {
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Instance webhook task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let (instance_uuid, instance_name, transition, details) = match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner,
+            }) => match InstanceTransition::from_event(instance_event_inner) {
+                Some((transition, details)) => (instance_uuid, instance_name, transition, details),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let (webhooks, http) = {
+            let instance_webhooks = instance_webhooks.lock().await;
+            let webhooks: Vec<InstanceWebhook> = instance_webhooks
+                .webhooks(instance_uuid)
+                .iter()
+                .filter(|webhook| webhook.enabled && webhook.transitions.contains(&transition))
+                .cloned()
+                .collect();
+            (webhooks, instance_webhooks.http.clone())
+        };
+        if webhooks.is_empty() {
+            continue;
+        }
+        let port = match instances.get(instance_uuid) {
+            Some(instance) => Some(instance.port().await),
+            None => None,
+        };
+        let timestamp = chrono::Utc::now().timestamp();
+        for webhook in webhooks {
+            let payload = payload(
+                &webhook,
+                instance_uuid,
+                instance_name,
+                port,
+                transition,
+                details.clone(),
+                timestamp,
+            );
+            let http = http.clone();
+            // a slow webhook should not hold up the others
+            tokio::spawn(async move {
+                if let Err(e) = deliver(&http, &webhook.url, &payload).await {
+                    warn!("Failed to call instance webhook : {e}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::{json, Map};
+
+    use super::{payload, InstanceTransition, InstanceWebhookConfig, InstanceWebhooks};
+    use crate::{events::InstanceEventInner, traits::t_server::State, types::InstanceUuid};
+
+    #[test]
+    fn test_transition_from_event() {
+        assert_eq!(
+            InstanceTransition::from_event(&InstanceEventInner::InstanceReady {
+                startup_duration: 12500
+            }),
+            Some((
+                InstanceTransition::Ready,
+                json!({ "startup_duration": 12500 })
+            ))
+        );
+        assert_eq!(
+            InstanceTransition::from_event(&InstanceEventInner::StateTransition {
+                to: State::Stopped
+            })
+            .map(|(transition, _)| transition),
+            Some(InstanceTransition::Stopped)
+        );
+        assert!(
+            InstanceTransition::from_event(&InstanceEventInner::StateTransition {
+                to: State::Stopping
+            })
+            .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instance_webhooks_store() {
+        let temp_dir = tempdir::TempDir::new("test_instance_webhooks").unwrap();
+        let path = temp_dir.path().join("instance_webhooks.json");
+        let instance_uuid = InstanceUuid::from("INSTANCE_a".to_string());
+        let mut instance_webhooks = InstanceWebhooks::new(path.clone());
+        let config = |url: &str, transitions: HashSet<InstanceTransition>| InstanceWebhookConfig {
+            url: url.to_string(),
+            transitions,
+            extra: Map::from_iter([("pool".to_string(), json!("eu-1"))]),
+            enabled: true,
+        };
+        assert!(instance_webhooks
+            .add_webhook(
+                &instance_uuid,
+                config(
+                    "ftp://example.com",
+                    HashSet::from([InstanceTransition::Ready])
+                )
+            )
+            .await
+            .is_err());
+        assert!(instance_webhooks
+            .add_webhook(
+                &instance_uuid,
+                config("https://example.com", HashSet::new())
+            )
+            .await
+            .is_err());
+        let webhook = instance_webhooks
+            .add_webhook(
+                &instance_uuid,
+                config(
+                    "https://example.com/ready",
+                    HashSet::from([InstanceTransition::Ready]),
+                ),
+            )
+            .await
+            .unwrap();
+
+        let mut reloaded = InstanceWebhooks::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.webhooks(&instance_uuid), &[webhook.clone()]);
+
+        let body = payload(
+            &webhook,
+            &instance_uuid,
+            "survival",
+            Some(25565),
+            InstanceTransition::Ready,
+            json!({ "startup_duration": 12500 }),
+            0,
+        );
+        assert_eq!(body["transition"], "Ready");
+        assert_eq!(body["port"], 25565);
+        assert_eq!(body["extra"]["pool"], "eu-1");
+
+        instance_webhooks
+            .remove_webhook(&instance_uuid, &webhook.id)
+            .await
+            .unwrap();
+        assert!(instance_webhooks.webhooks(&instance_uuid).is_empty());
+        assert!(instance_webhooks
+            .remove_webhook(&instance_uuid, &webhook.id)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Every long running task of the core, e.g. a backup or an export, runs as a job. A job has an
+//! id, a progress and a cancellation token while it runs, and is kept in a bounded history once it
+//! is done, so clients that weren't listening to the event stream can still ask how it went.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, JobEvent, JobEventInner},
+    types::{InstanceUuid, Snowflake},
+};
+
+/// finished jobs kept, the oldest are dropped first
+const HISTORY_LEN: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum JobKind {
+    Setup,
+    Backup,
+    Restore,
+    Export,
+    Move,
+    Update,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct JobInfo {
+    pub id: Snowflake,
+    pub kind: JobKind,
+    pub name: String,
+    /// the instance the job works on, if any
+    pub instance_uuid: Option<InstanceUuid>,
+    pub state: JobState,
+    /// in percent, `None` if the job can't tell how far along it is
+    pub progress: Option<f64>,
+    /// what the job is doing, or why it failed once it is done
+    pub message: Option<String>,
+    pub cancellable: bool,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub caused_by: CausedBy,
+}
+
+impl JobInfo {
+    fn is_started_by(&self, user: &User) -> bool {
+        matches!(&self.caused_by, CausedBy::User { user_id, .. } if *user_id == user.uid)
+    }
+
+    /// Jobs on an instance are visible to the users who can view the instance, other jobs to the
+    /// owner and the user who started them
+    pub fn visible_to(&self, user: &User) -> bool {
+        match &self.instance_uuid {
+            Some(instance_uuid) => {
+                user.can_perform_action(&UserAction::ViewInstance(instance_uuid.clone()))
+            }
+            None => user.is_owner || self.is_started_by(user),
+        }
+    }
+
+    /// Jobs on an instance can be cancelled by the users who can write its files, other jobs only by
+    /// the owner
+    pub fn can_be_cancelled_by(&self, user: &User) -> bool {
+        match &self.instance_uuid {
+            Some(instance_uuid) => {
+                user.can_perform_action(&UserAction::WriteInstanceFile(instance_uuid.clone()))
+            }
+            None => user.is_owner,
+        }
+    }
+}
+
+pub struct NewJob {
+    pub kind: JobKind,
+    pub name: String,
+    pub instance_uuid: Option<InstanceUuid>,
+    pub caused_by: CausedBy,
+    /// whether the task stops when its cancellation token is cancelled
+    pub cancellable: bool,
+}
+
+struct RunningJob {
+    info: JobInfo,
+    cancellation_token: CancellationToken,
+}
+
+#[derive(Default)]
+struct JobsInner {
+    running: HashMap<Snowflake, RunningJob>,
+    /// most recently finished first
+    history: VecDeque<JobInfo>,
+}
+
+/// The running jobs and the history of finished ones, kept in memory
+#[derive(Clone)]
+pub struct Jobs {
+    inner: Arc<Mutex<JobsInner>>,
+    event_broadcaster: EventBroadcaster,
+}
+
+impl Jobs {
+    pub fn new(event_broadcaster: EventBroadcaster) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(JobsInner::default())),
+            event_broadcaster,
+        }
+    }
+
+    fn send(&self, job: JobInfo, job_event_inner: JobEventInner) {
+        let caused_by = job.caused_by.clone();
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::JobEvent(JobEvent {
+                job,
+                job_event_inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+    }
+
+    /// Register a job, the task running it reports through the returned handle
+    pub fn start(&self, new_job: NewJob) -> JobHandle {
+        let info = JobInfo {
+            id: Snowflake::default(),
+            kind: new_job.kind,
+            name: new_job.name,
+            instance_uuid: new_job.instance_uuid,
+            state: JobState::Running,
+            progress: None,
+            message: None,
+            cancellable: new_job.cancellable,
+            started_at: chrono::Utc::now().timestamp(),
+            finished_at: None,
+            caused_by: new_job.caused_by,
+        };
+        let cancellation_token = CancellationToken::new();
+        self.inner.lock().unwrap().running.insert(
+            info.id,
+            RunningJob {
+                info: info.clone(),
+                cancellation_token: cancellation_token.clone(),
+            },
+        );
+        let handle = JobHandle {
+            jobs: self.clone(),
+            id: info.id,
+            cancellation_token,
+        };
+        self.send(info, JobEventInner::Started);
+        handle
+    }
+
+    /// Run `task` as a job in the background, it is finished with the result of the task, or as
+    /// failed if the task panics
+    pub fn spawn<F>(&self, new_job: NewJob, task: impl FnOnce(JobHandle) -> F) -> Snowflake
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let handle = self.start(new_job);
+        let id = handle.id();
+        let task = tokio::spawn(task(handle.clone()));
+        tokio::spawn(async move {
+            let result = match task.await {
+                Ok(result) => result,
+                Err(e) => Err(eyre!("Job panicked : {e}").into()),
+            };
+            handle.finish(&result);
+        });
+        id
+    }
+
+    /// The running jobs, then the finished ones, most recent first
+    pub fn list(&self) -> Vec<JobInfo> {
+        let inner = self.inner.lock().unwrap();
+        let mut running: Vec<JobInfo> = inner
+            .running
+            .values()
+            .map(|running| running.info.clone())
+            .collect();
+        running.sort_by(|a, b| b.id.cmp(&a.id));
+        running.extend(inner.history.iter().cloned());
+        running
+    }
+
+    pub fn get(&self, id: &Snowflake) -> Result<JobInfo, Error> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .running
+            .get(id)
+            .map(|running| running.info.clone())
+            .or_else(|| inner.history.iter().find(|job| job.id == *id).cloned())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Job not found"),
+            })
+    }
+
+    /// Ask a running job to stop, it is recorded as cancelled once its task gives up
+    pub fn cancel(&self, id: &Snowflake) -> Result<JobInfo, Error> {
+        let inner = self.inner.lock().unwrap();
+        let running = match inner.running.get(id) {
+            Some(running) => running,
+            None if inner.history.iter().any(|job| job.id == *id) => {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!("Job already finished"),
+                })
+            }
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::NotFound,
+                    source: eyre!("Job not found"),
+                })
+            }
+        };
+        if !running.info.cancellable {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("This job can't be cancelled"),
+            });
+        }
+        running.cancellation_token.cancel();
+        Ok(running.info.clone())
+    }
+}
+
+/// What the task running a job reports its progress and result through
+#[derive(Clone)]
+pub struct JobHandle {
+    jobs: Jobs,
+    id: Snowflake,
+    cancellation_token: CancellationToken,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Snowflake {
+        self.id
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token.is_cancelled()
+    }
+
+    /// Set the progress of the job in percent, an event is sent each time it moves by a whole
+    /// percent
+    pub fn set_progress(&self, progress: f64, message: Option<String>) {
+        let progress = progress.clamp(0.0, 100.0);
+        let info = {
+            let mut inner = self.jobs.inner.lock().unwrap();
+            let info = match inner.running.get_mut(&self.id) {
+                Some(running) => &mut running.info,
+                None => return,
+            };
+            let moved = !matches!(
+                info.progress,
+                Some(previous) if previous.floor() == progress.floor()
+            );
+            info.progress = Some(progress);
+            if message.is_some() {
+                info.message = message;
+            }
+            if !moved {
+                return;
+            }
+            info.clone()
+        };
+        self.jobs.send(info, JobEventInner::Progress);
+    }
+
+    /// Record how the job ended and move it to the history. A job that fails after it was asked to
+    /// stop counts as cancelled
+    pub fn finish<T>(&self, result: &Result<T, Error>) {
+        let info = {
+            let mut inner = self.jobs.inner.lock().unwrap();
+            let mut info = match inner.running.remove(&self.id) {
+                Some(running) => running.info,
+                None => return,
+            };
+            match result {
+                Ok(_) => {
+                    info.state = JobState::Succeeded;
+                    if info.progress.is_some() {
+                        info.progress = Some(100.0);
+                    }
+                }
+                Err(_) if self.is_cancelled() => {
+                    info.state = JobState::Cancelled;
+                    info.message = Some("Cancelled".to_string());
+                }
+                Err(e) => {
+                    info.state = JobState::Failed;
+                    info.message = Some(e.source.to_string());
+                }
+            }
+            info.finished_at = Some(chrono::Utc::now().timestamp());
+            inner.history.push_front(info.clone());
+            inner.history.truncate(HISTORY_LEN);
+            info
+        };
+        self.jobs.send(info, JobEventInner::Finished);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+
+    use super::{JobKind, JobState, Jobs, NewJob, HISTORY_LEN};
+    use crate::{
+        error::Error,
+        event_broadcaster::EventBroadcaster,
+        events::{CausedBy, EventInner, JobEventInner},
+    };
+
+    fn new_job(cancellable: bool) -> NewJob {
+        NewJob {
+            kind: JobKind::Other,
+            name: "test".to_string(),
+            instance_uuid: None,
+            caused_by: CausedBy::System,
+            cancellable,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let (event_broadcaster, mut rx) = EventBroadcaster::new(64);
+        let jobs = Jobs::new(event_broadcaster);
+
+        let job = jobs.start(new_job(false));
+        job.set_progress(10.2, Some("Working".to_string()));
+        // within the same percent, no event
+        job.set_progress(10.8, None);
+        job.set_progress(150.0, None);
+        assert_eq!(jobs.get(&job.id()).unwrap().progress, Some(100.0));
+        assert_eq!(
+            jobs.get(&job.id()).unwrap().message.as_deref(),
+            Some("Working")
+        );
+        assert!(jobs.cancel(&job.id()).is_err());
+        job.finish::<()>(&Err(eyre!("Disk full").into()));
+
+        let info = jobs.get(&job.id()).unwrap();
+        assert_eq!(info.state, JobState::Failed);
+        assert_eq!(info.message.as_deref(), Some("Disk full"));
+        assert!(info.finished_at.is_some());
+
+        let mut inners = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let EventInner::JobEvent(job_event) = event.event_inner {
+                inners.push(job_event.job_event_inner);
+            }
+        }
+        assert_eq!(
+            inners,
+            vec![
+                JobEventInner::Started,
+                JobEventInner::Progress,
+                JobEventInner::Progress,
+                JobEventInner::Finished
+            ]
+        );
+
+        let job = jobs.start(new_job(true));
+        jobs.cancel(&job.id()).unwrap();
+        assert!(job.is_cancelled());
+        job.finish::<()>(&Err(eyre!("Stopped").into()));
+        assert_eq!(jobs.get(&job.id()).unwrap().state, JobState::Cancelled);
+        // most recent first
+        assert_eq!(jobs.list()[0].id, job.id());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_and_history() {
+        let (event_broadcaster, _rx) = EventBroadcaster::new(1024);
+        let jobs = Jobs::new(event_broadcaster);
+        let id = jobs.spawn(new_job(false), |_| async { Ok::<(), Error>(()) });
+        for _ in 0..100 {
+            if jobs.get(&id).unwrap().state != JobState::Running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(jobs.get(&id).unwrap().state, JobState::Succeeded);
+
+        for _ in 0..HISTORY_LEN {
+            jobs.start(new_job(false)).finish(&Ok(()));
+        }
+        assert_eq!(jobs.list().len(), HISTORY_LEN);
+        assert!(jobs.get(&id).is_err());
+    }
+}
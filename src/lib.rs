@@ -6,43 +6,99 @@ use crate::prelude::{
     init_paths, lodestone_path, path_to_global_settings, path_to_stores, path_to_users, VERSION,
 };
 use crate::traits::t_configurable::GameType;
-use crate::traits::t_server::State;
 use crate::{
-    db::write::write_event_to_db_task,
-    global_settings::GlobalSettingsData,
+    db::{
+        read::{read_recent_events, search_console_events},
+        write::{init_client_events_table, prune_events_task, write_event_to_db_task},
+    },
+    global_settings::{BufferSettings, GlobalSettingsData},
     handlers::{
-        checks::get_checks_routes, core_info::get_core_info_routes, events::get_events_routes,
-        gateway::get_gateway_routes, global_fs::get_global_fs_routes,
-        global_settings::get_global_settings_routes, instance::*,
-        instance_config::get_instance_config_routes, instance_fs::get_instance_fs_routes,
-        instance_macro::get_instance_macro_routes, instance_players::get_instance_players_routes,
-        instance_server::get_instance_server_routes,
-        instance_setup_configs::get_instance_setup_config_routes, monitor::get_monitor_routes,
-        setup::get_setup_route, system::get_system_routes, users::get_user_routes,
+        advisories::get_advisories_routes, backup_destinations::get_backup_destination_routes,
+        checks::get_checks_routes, console_matchers::get_console_matcher_routes,
+        console_profiles::get_console_profile_routes, console_snippets::get_console_snippet_routes,
+        control_channel::get_control_channel_routes, core_info::get_core_info_routes,
+        diagnostics::get_diagnostics_routes, downloads::get_download_routes,
+        events::get_events_routes, federation::get_federation_routes, gateway::get_gateway_routes,
+        global_fs::get_global_fs_routes, global_settings::get_global_settings_routes,
+        health::get_health_routes, instance::*, instance_archive::get_instance_archive_routes,
+        instance_backup::get_instance_backup_routes, instance_config::get_instance_config_routes,
+        instance_export::get_instance_export_routes, instance_fs::get_instance_fs_routes,
+        instance_groups::get_instance_group_routes, instance_logs::get_instance_logs_routes,
+        instance_macro::get_instance_macro_routes, instance_mods::get_instance_mods_routes,
+        instance_players::get_instance_players_routes, instance_server::get_instance_server_routes,
+        instance_setup_configs::get_instance_setup_config_routes,
+        instance_sync::get_instance_sync_routes, instance_template::get_instance_template_routes,
+        instance_webhooks::get_instance_webhook_routes,
+        instance_worlds::get_instance_worlds_routes, java_runtimes::get_java_runtime_routes,
+        jobs::get_jobs_routes, metrics::get_metrics_routes, monitor::get_monitor_routes,
+        network_isolation::get_network_isolation_routes, notifications::get_notifications_routes,
+        openapi::get_openapi_routes, overview::get_overview_routes,
+        read_only::get_read_only_routes, reservation::get_reservation_routes,
+        setup::get_setup_route, start_dependencies::get_start_dependency_routes,
+        status_page::get_status_page_routes, storage_roots::get_storage_roots_routes,
+        suspicious_activity::get_suspicious_activity_routes, system::get_system_routes,
+        trash::get_trash_routes, uploads::get_upload_routes,
+        usage_accounting::get_usage_accounting_routes, user_quotas::get_user_quota_routes,
+        users::get_user_routes, webdav::get_webdav_routes,
     },
-    util::rand_alphanumeric,
 };
 
+use audit::record_audit_event;
 use auth::user::UsersManager;
 use axum::Router;
+use federation::proxy_to_peer;
 
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
 use color_eyre::Report;
-use error::Error;
+use command_filter::CommandFilters;
+use command_history::CommandHistory;
+use command_queue::CommandQueues;
+use command_sequence::CommandSequences;
+use console_matchers::ConsoleMatchers;
+use console_snippets::ConsoleSnippets;
+use disk_usage::DirectorySizes;
+use download_manager::DownloadManager;
+use error::{structured_rejections, Error, ErrorKind};
 use events::{CausedBy, Event};
+use fs_locations::FsLocations;
 use futures::Future;
 use global_settings::GlobalSettings;
-use implementations::{generic, minecraft};
+use health::HealthChecks;
+use implementations::minecraft::console_profile::ConsoleProfiles;
+#[cfg(any(test, feature = "test-harness"))]
+use implementations::mock;
+use implementations::{generic, minecraft, process};
+use instance_archive::ArchivedInstances;
+use instance_groups::InstanceGroups;
+use instance_map::InstanceMap;
+use instance_summaries::InstanceSummaries;
+use instance_sync::InstanceSyncs;
+use instance_webhooks::InstanceWebhooks;
+use jobs::Jobs;
 use macro_executor::MacroExecutor;
+use macro_triggers::MacroTriggers;
+use metrics::{count_api_requests, ApiRequestCounter};
+use network_isolation::NetworkPolicies;
+use notifications::Notifications;
 use port_manager::PortManager;
 use prelude::GameInstance;
-use reqwest::{header, Method};
-use ringbuffer::{AllocRingBuffer, RingBufferWrite};
+use public_address::PublicIp;
+use ringbuffer::{AllocRingBuffer, RingBuffer, RingBufferExt, RingBufferWrite};
 
+use backup_destinations::BackupDestinations;
+use federation::Peers;
+use player_database::PlayerDatabase;
+use rate_limit::{rate_limit, RateLimiter};
+use security_headers::security_headers;
+use self_update::{Updater, RELEASE_FEED_URL};
 use semver::Version;
+use server_config::{ServerConfig, DEFAULT_PORT};
+use setup_key::{SetupKey, DEFAULT_SETUP_KEY_TTL_HOURS};
 use sqlx::{sqlite::SqliteConnectOptions, Pool};
+use start_dependencies::{start_in_order, StartDependencies};
+use status_page::StatusPage;
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
@@ -51,69 +107,238 @@ use std::{
     sync::Arc,
     time::Duration,
 };
+use storage_roots::StorageRoots;
+use suspicious_activity::SuspiciousActivityPolicies;
 use sysinfo::{CpuExt, SystemExt};
 use tokio::{
     select,
     sync::{broadcast::error::RecvError, Mutex, RwLock},
+    task::JoinSet,
 };
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
+use tower_http::trace::TraceLayer;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter};
 use traits::{t_configurable::TConfigurable, t_server::MonitorReport, t_server::TServer};
 use types::{DotLodestoneConfig, InstanceUuid};
+use upload_sessions::UploadSessions;
+use usage_accounting::UsageLedger;
+use usage_history::{init_usage_rollups_table, UsageHistory};
+use user_quotas::UserQuotas;
 use uuid::Uuid;
+mod advisories;
+mod audit;
 pub mod auth;
+mod backup;
+mod backup_destinations;
+mod command_filter;
+mod command_history;
+mod command_queue;
+mod command_sequence;
+mod config_editor;
+mod console_matchers;
+mod console_snippets;
+mod data_relocation;
 pub mod db;
 mod deno_ops;
+mod diagnostics;
+mod disk_usage;
+mod download_manager;
 pub mod error;
 mod event_broadcaster;
 mod events;
+mod federation;
+mod file_search;
+mod file_stream;
+mod file_sync;
+mod fs_locations;
+mod geoip;
 pub mod global_settings;
 mod handlers;
+mod health;
+mod host_pressure;
 pub mod implementations;
+mod instance_archive;
+mod instance_export;
+mod instance_groups;
+mod instance_map;
+mod instance_summaries;
+mod instance_sync;
+mod instance_template;
+mod instance_webhooks;
+mod jobs;
+mod log_housekeeping;
 pub mod macro_executor;
+mod macro_sandbox;
+mod macro_triggers;
+mod metrics;
 mod migration;
+mod monitor_task;
+mod network_isolation;
+mod network_usage;
+mod notifications;
 mod output_types;
+mod player_database;
 mod port_manager;
+mod port_remap;
 pub mod prelude;
+mod public_address;
+mod rate_limit;
+mod readiness;
+mod reservation;
+mod schedule;
+mod security_headers;
+mod self_update;
+mod server_config;
+mod server_logs;
+mod server_probe;
+mod setup_key;
+mod shutdown;
+mod start_dependencies;
+mod status_page;
+mod storage_roots;
+mod suspicious_activity;
 pub mod tauri_export;
+mod telemetry;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod test_harness;
+mod text_patch;
 mod traits;
+mod trash;
 pub mod types;
+mod upload_sessions;
+mod usage_accounting;
+mod usage_history;
+mod user_quotas;
 pub mod util;
+mod webdav;
+
+pub use shutdown::{restart_process, restart_requested};
 
 #[derive(Clone)]
 pub struct AppState {
-    instances: Arc<Mutex<HashMap<InstanceUuid, GameInstance>>>,
+    instances: InstanceMap,
+    instance_summaries: InstanceSummaries,
     users_manager: Arc<RwLock<UsersManager>>,
     events_buffer: Arc<Mutex<AllocRingBuffer<Event>>>,
     console_out_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<Event>>>>,
     monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
+    usage_history: UsageHistory,
     event_broadcaster: EventBroadcaster,
     uuid: String,
     up_since: i64,
     global_settings: Arc<Mutex<GlobalSettings>>,
+    fs_locations: Arc<Mutex<FsLocations>>,
+    notifications: Arc<Mutex<Notifications>>,
+    status_page: Arc<Mutex<StatusPage>>,
+    macro_triggers: Arc<Mutex<MacroTriggers>>,
+    command_queues: Arc<Mutex<CommandQueues>>,
+    command_filters: Arc<Mutex<CommandFilters>>,
+    command_sequences: CommandSequences,
+    command_history: CommandHistory,
+    jobs: Jobs,
+    updater: Updater,
+    console_snippets: Arc<Mutex<ConsoleSnippets>>,
+    console_profiles: Arc<Mutex<ConsoleProfiles>>,
+    console_matchers: Arc<Mutex<ConsoleMatchers>>,
+    instance_webhooks: Arc<Mutex<InstanceWebhooks>>,
+    network_policies: Arc<Mutex<NetworkPolicies>>,
+    instance_syncs: Arc<Mutex<InstanceSyncs>>,
+    instance_groups: Arc<Mutex<InstanceGroups>>,
+    archived_instances: Arc<Mutex<ArchivedInstances>>,
+    start_dependencies: Arc<Mutex<StartDependencies>>,
+    upload_sessions: Arc<Mutex<UploadSessions>>,
+    user_quotas: Arc<Mutex<UserQuotas>>,
+    usage_ledger: Arc<Mutex<UsageLedger>>,
+    player_database: Arc<Mutex<PlayerDatabase>>,
+    peers: Arc<Mutex<Peers>>,
+    backup_destinations: Arc<Mutex<BackupDestinations>>,
+    suspicious_activity_policies: Arc<Mutex<SuspiciousActivityPolicies>>,
+    storage_roots: Arc<Mutex<StorageRoots>>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
     system: Arc<Mutex<sysinfo::System>>,
     port_manager: Arc<Mutex<PortManager>>,
-    first_time_setup_key: Arc<Mutex<Option<String>>>,
+    public_ip: PublicIp,
+    first_time_setup_key: Arc<Mutex<Option<SetupKey>>>,
     download_urls: Arc<Mutex<HashMap<String, PathBuf>>>,
     macro_executor: MacroExecutor,
     sqlite_pool: sqlx::SqlitePool,
+    api_requests: ApiRequestCounter,
+    directory_sizes: DirectorySizes,
+    health_checks: HealthChecks,
 }
+
+/// Load the instance in `path` as described by its `.lodestone_config`
+pub(crate) async fn restore_instance(
+    path: &Path,
+    dot_lodestone_config: &DotLodestoneConfig,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> Result<GameInstance, Error> {
+    match dot_lodestone_config.game_type() {
+        GameType::MinecraftJava => minecraft::MinecraftInstance::restore(
+            path.to_owned(),
+            dot_lodestone_config.clone(),
+            event_broadcaster,
+            macro_executor,
+        )
+        .await
+        .map(Into::into),
+        GameType::Generic => generic::GenericInstance::restore(
+            path.to_owned(),
+            dot_lodestone_config.clone(),
+            event_broadcaster,
+            macro_executor,
+        )
+        .await
+        .map(Into::into),
+        GameType::Process => process::ProcessInstance::restore(
+            path.to_owned(),
+            dot_lodestone_config.clone(),
+            event_broadcaster,
+        )
+        .await
+        .map(Into::into),
+        GameType::MinecraftBedrock => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Minecraft Bedrock instances are not supported"),
+        }),
+    }
+}
+
+/// How many instances are restored at once
+static MAX_CONCURRENT_RESTORES: usize = 8;
+
 async fn restore_instances(
-    instances_path: &Path,
+    storage_roots: &[PathBuf],
     event_broadcaster: EventBroadcaster,
     macro_executor: MacroExecutor,
 ) -> Result<HashMap<InstanceUuid, GameInstance>, Error> {
     let mut ret: HashMap<InstanceUuid, GameInstance> = HashMap::new();
 
-    for entry in instances_path
-        .read_dir()
-        .context("Failed to read instances directory")?
-    {
+    let mut entries = Vec::new();
+    for (i, root) in storage_roots.iter().enumerate() {
+        // the instances directory of the data directory is always there
+        if i == 0 {
+            entries.extend(
+                root.read_dir()
+                    .context("Failed to read instances directory")?,
+            );
+            continue;
+        }
+        match root.read_dir() {
+            Ok(read_dir) => entries.extend(read_dir),
+            // a drive that isn't mounted shouldn't keep the other instances from loading
+            Err(e) => {
+                error!(
+                    "Failed to read storage root {}, its instances won't be restored : {e}",
+                    root.display()
+                );
+            }
+        }
+    }
+    let mut to_restore = Vec::new();
+    for entry in entries {
         let path = match entry {
             Ok(v) => v.path(),
             Err(e) => {
@@ -121,6 +346,13 @@ async fn restore_instances(
                 continue;
             }
         };
+        // hidden directories are staging areas, e.g. for backup restores
+        if path
+            .file_name()
+            .map_or(false, |name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
         let dot_lodestone_config_file = match std::fs::File::open(path.join(".lodestone_config")) {
             Ok(v) => v,
             Err(e) => {
@@ -137,27 +369,223 @@ async fn restore_instances(
                 continue;
             }
         };
-        debug!("restoring instance: {}", path.display());
-        if let GameType::MinecraftJava = dot_lodestone_config.game_type() {
-            let instance = match minecraft::MinecraftInstance::restore(
-                path.to_owned(),
-                dot_lodestone_config.clone(),
-                event_broadcaster.clone(),
-                macro_executor.clone(),
-            )
-            .await
-            {
-                Ok(v) => v,
+        to_restore.push((path, dot_lodestone_config));
+    }
+    if to_restore.is_empty() {
+        return Ok(ret);
+    }
+
+    let total = to_restore.len();
+    let (progression_start, event_id) = Event::new_progression_event_start(
+        "Restoring instances",
+        Some(total as f64),
+        None,
+        CausedBy::System,
+    );
+    event_broadcaster.send(progression_start);
+    let mut join_set = JoinSet::new();
+    let mut to_restore = to_restore.into_iter();
+    loop {
+        // keep at most MAX_CONCURRENT_RESTORES restores going
+        while join_set.len() < MAX_CONCURRENT_RESTORES {
+            let (path, dot_lodestone_config) = match to_restore.next() {
+                Some(next) => next,
+                None => break,
+            };
+            let event_broadcaster = event_broadcaster.clone();
+            let macro_executor = macro_executor.clone();
+            join_set.spawn(async move {
+                debug!("restoring instance: {}", path.display());
+                let instance = restore_instance(
+                    &path,
+                    &dot_lodestone_config,
+                    event_broadcaster,
+                    macro_executor,
+                )
+                .await;
+                (path, dot_lodestone_config, instance)
+            });
+        }
+        let (path, dot_lodestone_config, instance) = match join_set.join_next().await {
+            Some(Ok(restored)) => restored,
+            Some(Err(e)) => {
+                error!("Error while restoring instance, restore task failed : {e}");
+                continue;
+            }
+            None => break,
+        };
+        match instance {
+            Ok(instance) => {
+                debug!("Restored {} successfully", path.display());
+                event_broadcaster.send(Event::new_progression_event_update(
+                    &event_id,
+                    format!("Restored {}", instance.name().await),
+                    1.0,
+                ));
+                ret.insert(dot_lodestone_config.uuid().to_owned(), instance);
+            }
+            Err(e) => {
+                error!("Error while restoring instance {} : {e}", path.display());
+                event_broadcaster.send(Event::new_progression_event_update(
+                    &event_id,
+                    format!("Failed to restore {}", path.display()),
+                    1.0,
+                ));
+            }
+        }
+    }
+    event_broadcaster.send(Event::new_progression_event_end(
+        event_id,
+        ret.len() == total,
+        Some(format!("Restored {} of {total} instances", ret.len())),
+        None,
+    ));
+    Ok(ret)
+}
+
+/// Start the instances set to start with the core, each once its start dependencies are ready
+async fn auto_start_instances(
+    instances: InstanceMap,
+    event_broadcaster: EventBroadcaster,
+    start_dependencies: Arc<Mutex<StartDependencies>>,
+) {
+    let mut to_start = Vec::new();
+    let mut names = HashMap::new();
+    for (uuid, instance) in instances.snapshot() {
+        if instance.auto_start().await {
+            names.insert(uuid.clone(), instance.name().await);
+            to_start.push(uuid);
+        }
+    }
+    if to_start.is_empty() {
+        return;
+    }
+    let total = to_start.len();
+    let (progression_start, event_id) = Event::new_progression_event_start(
+        "Starting instances",
+        Some(total as f64),
+        None,
+        CausedBy::System,
+    );
+    event_broadcaster.send(progression_start);
+    let dependencies = start_dependencies.lock().await.all();
+    let result = start_in_order(
+        &instances,
+        &event_broadcaster,
+        &dependencies,
+        to_start,
+        |uuid| {
+            let instances = instances.clone();
+            async move {
+                let mut instance = instances.get(&uuid).ok_or_else(|| Error {
+                    kind: ErrorKind::NotFound,
+                    source: eyre!("Instance not found"),
+                })?;
+                info!("Auto starting instance {}", instance.name().await);
+                instance.start(CausedBy::System, false).await
+            }
+        },
+        |uuid, result| {
+            let name = names.get(uuid).map_or(uuid.as_ref(), String::as_str);
+            let progress_message = match result {
+                Ok(()) => format!("Started {name}"),
                 Err(e) => {
-                    error!("Error while restoring instance {} : {e}", path.display());
-                    continue;
+                    error!("Failed to start instance {name}: {e:?}");
+                    format!("Failed to start {name}")
                 }
             };
-            debug!("Restored successfully");
-            ret.insert(dot_lodestone_config.uuid().to_owned(), instance.into());
+            event_broadcaster.send(Event::new_progression_event_update(
+                &event_id,
+                progress_message,
+                1.0,
+            ));
+        },
+    )
+    .await;
+    let (started, message) = match result {
+        Ok(results) => {
+            let started = results.iter().filter(|(_, result)| result.is_ok()).count();
+            (started, format!("Started {started} of {total} instances"))
+        }
+        Err(e) => {
+            error!("Failed to auto start instances : {e}");
+            (0, format!("Failed to start instances: {e}"))
+        }
+    };
+    event_broadcaster.send(Event::new_progression_event_end(
+        event_id,
+        started == total,
+        Some(message),
+        None,
+    ));
+}
+
+/// Refill the event and console buffers from the database so history survives a restart
+async fn restore_event_buffers(state: &AppState) -> Result<(), Error> {
+    init_client_events_table(&state.sqlite_pool).await?;
+    let mut events_buffer = state.events_buffer.lock().await;
+    for client_event in
+        read_recent_events(&state.sqlite_pool, events_buffer.capacity() as u32).await?
+    {
+        events_buffer.push(client_event.into());
+    }
+    drop(events_buffer);
+    let instance_uuids = state.instances.uuids();
+    let console_buffer_size = state
+        .global_settings
+        .lock()
+        .await
+        .buffer_settings()
+        .console_buffer_size;
+    let mut console_out_buffer = state.console_out_buffer.lock().await;
+    for uuid in instance_uuids {
+        let buffer = console_out_buffer
+            .entry(uuid.clone())
+            .or_insert_with(|| AllocRingBuffer::with_capacity(console_buffer_size));
+        for client_event in search_console_events(
+            &state.sqlite_pool,
+            Some(&uuid),
+            None,
+            buffer.capacity() as u32,
+        )
+        .await?
+        {
+            buffer.push(client_event.into());
+        }
+    }
+    Ok(())
+}
+
+/// A copy of `buffer` holding up to `capacity` events, keeping the most recent ones
+fn resize_ring_buffer<T: Clone>(
+    buffer: &AllocRingBuffer<T>,
+    capacity: usize,
+) -> AllocRingBuffer<T> {
+    let mut resized = AllocRingBuffer::with_capacity(capacity);
+    for item in buffer.iter() {
+        resized.push(item.clone());
+    }
+    resized
+}
+
+/// Resize the in-memory event, console and monitor buffers to new settings, keeping the most
+/// recent entries
+async fn resize_event_buffers(state: &AppState, buffer_settings: BufferSettings) {
+    let mut events_buffer = state.events_buffer.lock().await;
+    if events_buffer.capacity() != buffer_settings.event_buffer_size {
+        *events_buffer = resize_ring_buffer(&events_buffer, buffer_settings.event_buffer_size);
+    }
+    drop(events_buffer);
+    for buffer in state.console_out_buffer.lock().await.values_mut() {
+        if buffer.capacity() != buffer_settings.console_buffer_size {
+            *buffer = resize_ring_buffer(buffer, buffer_settings.console_buffer_size);
+        }
+    }
+    for buffer in state.monitor_buffer.lock().await.values_mut() {
+        if buffer.capacity() != buffer_settings.monitor_buffer_size {
+            *buffer = resize_ring_buffer(buffer, buffer_settings.monitor_buffer_size);
         }
     }
-    Ok(ret)
 }
 
 fn setup_tracing() -> tracing_appender::non_blocking::WorkerGuard {
@@ -322,6 +750,22 @@ pub struct Args {
     pub is_desktop: bool,
     #[arg(short, long)]
     pub lodestone_path: Option<PathBuf>,
+    /// copy the data directory to this path before starting, and use it from then on
+    #[arg(long)]
+    pub relocate_to: Option<PathBuf>,
+    /// with `--relocate-to`, delete the old data directory once the copy is in use
+    #[arg(long, default_value = "false")]
+    pub remove_old_data: bool,
+    /// also serve the read only API on this port, the full API then stays on localhost
+    #[arg(long)]
+    pub read_only_port: Option<u16>,
+    /// also write the first time setup key to this file, readable by the user running the core
+    /// only. Defaults to the `LODESTONE_SETUP_KEY_FILE` environment variable
+    #[arg(long)]
+    pub setup_key_file: Option<PathBuf>,
+    /// how long the first time setup key can be used for
+    #[arg(long, default_value_t = DEFAULT_SETUP_KEY_TTL_HOURS)]
+    pub setup_key_ttl_hours: u64,
 }
 
 pub async fn run(
@@ -334,6 +778,10 @@ pub async fn run(
     let _ = color_eyre::install().map_err(|e| {
         error!("Failed to install color_eyre: {}", e);
     });
+    // before an update can rename the binary
+    let _ = shutdown::executable().map_err(|e| {
+        error!("Failed to find the running binary, restarting won't work : {e}");
+    });
     let lodestone_path_ = if let Some(path) = args.lodestone_path {
         path
     } else {
@@ -349,11 +797,42 @@ pub async fn run(
                 .to_string(),
         })
     };
+    let (mut lodestone_path_, mut relocation_messages) =
+        data_relocation::resolve_lodestone_path(lodestone_path_);
+    if let Some(relocate_to) = args.relocate_to {
+        // logging is not set up yet, so progress goes straight to the terminal
+        let mut last_percent = None;
+        match data_relocation::relocate(
+            &lodestone_path_,
+            &relocate_to,
+            args.remove_old_data,
+            |copied, total| {
+                let percent = (copied * 100).checked_div(total).unwrap_or(100);
+                if last_percent != Some(percent) {
+                    println!("Relocating data directory: {percent}%");
+                    last_percent = Some(percent);
+                }
+            },
+        ) {
+            Ok(()) => {
+                let (path, messages) = data_relocation::resolve_lodestone_path(lodestone_path_);
+                lodestone_path_ = path;
+                relocation_messages.extend(messages);
+            }
+            Err(e) => {
+                eprintln!("Failed to relocate the data directory : {e}");
+                std::process::exit(1);
+            }
+        }
+    }
     init_paths(lodestone_path_);
     let lodestone_path = lodestone_path();
     info!("Lodestone path: {}", lodestone_path.display());
     std::env::set_current_dir(lodestone_path).unwrap();
     let guard = setup_tracing();
+    for message in relocation_messages {
+        info!("{message}");
+    }
     if args.is_desktop {
         info!("Lodestone Core running in Tauri");
     }
@@ -369,38 +848,154 @@ pub async fn run(
     });
     let path_to_instances = lodestone_path.join("instances");
 
-    let (tx, _rx) = EventBroadcaster::new(512);
+    // the broadcast channel is sized by the settings, which need it to be created
+    let global_settings_data = GlobalSettingsData::load_from_file(path_to_global_settings())
+        .await
+        .unwrap();
+    let (tx, _rx) = EventBroadcaster::new(global_settings_data.buffer_settings.broadcast_capacity);
+    DownloadManager::init(tx.clone());
 
     let mut users_manager = UsersManager::new(tx.clone(), HashMap::new(), path_to_users().clone());
 
     users_manager.load_users().await.unwrap();
 
-    let mut global_settings = GlobalSettings::new(
+    let global_settings = GlobalSettings::new(
         path_to_global_settings().clone(),
         tx.clone(),
-        GlobalSettingsData::default(),
+        global_settings_data,
+    );
+    users_manager.set_two_factor_required(global_settings.require_two_factor());
+    // a missing database shouldn't keep the core from starting, players just aren't located
+    if let Err(e) = geoip::set_database(global_settings.geoip_database().as_deref()).await {
+        error!("Failed to load the GeoIP database, players won't be located : {e}");
+    }
+    if let Err(e) = advisories::load_cached(&path_to_stores().join("advisories.json")).await {
+        error!("Failed to load the fetched advisories, using the bundled ones : {e}");
+    }
+    if let Err(e) =
+        minecraft::version_cache::load(&path_to_stores().join("version_cache.json")).await
+    {
+        error!("Failed to load the cached version lists, they will be fetched again : {e}");
+    }
+
+    let mut fs_locations = FsLocations::new(path_to_stores().join("fs_locations.json"));
+
+    fs_locations.load_from_file().await.unwrap();
+
+    let mut notifications = Notifications::new(path_to_stores().join("notifications.json"));
+
+    notifications.load_from_file().await.unwrap();
+
+    let mut status_page = StatusPage::new(path_to_stores().join("status_page.json"));
+
+    status_page.load_from_file().await.unwrap();
+
+    let mut macro_triggers = MacroTriggers::new(path_to_stores().join("macro_triggers.json"));
+
+    macro_triggers.load_from_file().await.unwrap();
+
+    let mut command_queues = CommandQueues::new(path_to_stores().join("command_queues.json"));
+
+    command_queues.load_from_file().await.unwrap();
+
+    let mut command_filters = CommandFilters::new(path_to_stores().join("command_filters.json"));
+
+    command_filters.load_from_file().await.unwrap();
+
+    let mut console_snippets = ConsoleSnippets::new(path_to_stores().join("console_snippets.json"));
+
+    console_snippets.load_from_file().await.unwrap();
+
+    let mut console_profiles = ConsoleProfiles::new(path_to_stores().join("console_profiles.json"));
+
+    console_profiles.load_from_file().await.unwrap();
+
+    let mut console_matchers = ConsoleMatchers::new(path_to_stores().join("console_matchers.json"));
+
+    console_matchers.load_from_file().await.unwrap();
+
+    let mut instance_webhooks =
+        InstanceWebhooks::new(path_to_stores().join("instance_webhooks.json"));
+
+    instance_webhooks.load_from_file().await.unwrap();
+
+    let mut network_policies = NetworkPolicies::new(path_to_stores().join("network_policies.json"));
+
+    network_policies.load_from_file().await.unwrap();
+
+    let mut instance_syncs = InstanceSyncs::new(
+        path_to_stores().join("instance_sync.json"),
+        path_to_stores().join("sync"),
+    );
+
+    instance_syncs.load_from_file().await.unwrap();
+
+    let mut instance_groups = InstanceGroups::new(path_to_stores().join("instance_groups.json"));
+
+    instance_groups.load_from_file().await.unwrap();
+
+    let mut archived_instances =
+        ArchivedInstances::new(path_to_stores().join("archived_instances.json"));
+
+    archived_instances.load_from_file().await.unwrap();
+
+    let mut start_dependencies =
+        StartDependencies::new(path_to_stores().join("start_dependencies.json"));
+
+    start_dependencies.load_from_file().await.unwrap();
+
+    let mut upload_sessions = UploadSessions::new(path_to_stores().join("upload_sessions.json"));
+
+    upload_sessions.load_from_file().await.unwrap();
+
+    let mut user_quotas = UserQuotas::new(path_to_stores().join("user_quotas.json"));
+
+    user_quotas.load_from_file().await.unwrap();
+
+    let mut usage_ledger = UsageLedger::new(path_to_stores().join("usage_ledger.json"));
+
+    usage_ledger.load_from_file().await.unwrap();
+
+    let mut player_database = PlayerDatabase::new(path_to_stores().join("player_database.json"));
+
+    player_database.load_from_file().await.unwrap();
+
+    let mut peers = Peers::new(path_to_stores().join("peers.json"));
+
+    peers.load_from_file().await.unwrap();
+
+    let mut backup_destinations =
+        BackupDestinations::new(path_to_stores().join("backup_destinations.json"));
+
+    backup_destinations.load_from_file().await.unwrap();
+
+    let mut suspicious_activity_policies =
+        SuspiciousActivityPolicies::new(path_to_stores().join("suspicious_activity.json"));
+
+    suspicious_activity_policies.load_from_file().await.unwrap();
+
+    let mut storage_roots = StorageRoots::new(
+        path_to_stores().join("storage_roots.json"),
+        path_to_instances,
     );
 
-    global_settings.load_from_file().await.unwrap();
+    storage_roots.load_from_file().await.unwrap();
 
     let first_time_setup_key = if !users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
-        let key = rand_alphanumeric(16);
-        // log the first time setup key in green so it's easy to find
-        info!(
-            "First time setup key: {}",
-            ansi_term::Color::Green.paint(key.clone())
-        );
-        info!("This is a one-time, in-memory randomly generated key that allows you to create the owner account.");
-        info!(
-            "{}",
-            ansi_term::Color::Red.paint("DO NOT SHARE THIS KEY WITH ANYONE!")
-        );
-        Some(key)
+        let setup_key_file = args.setup_key_file.clone().or_else(|| {
+            std::env::var("LODESTONE_SETUP_KEY_FILE")
+                .ok()
+                .map(PathBuf::from)
+        });
+        Some(SetupKey::generate(args.setup_key_ttl_hours, setup_key_file).await)
     } else {
         None
     };
     let macro_executor = MacroExecutor::new(tx.clone());
-    let mut instances = restore_instances(&path_to_instances, tx.clone(), macro_executor.clone())
+    // subscribed before restoring so that the restore progress makes it to the history
+    let event_buffer_receiver = tx.subscribe();
+    let db_event_receiver = tx.subscribe();
+    let instances = restore_instances(&storage_roots.paths(), tx.clone(), macro_executor.clone())
         .await
         .map_err(|e| {
             error!(
@@ -409,36 +1004,60 @@ pub async fn run(
             );
         })
         .unwrap();
-    for (_, instance) in instances.iter_mut() {
-        if instance.auto_start().await {
-            info!("Auto starting instance {}", instance.name().await);
-            if let Err(e) = instance.start(CausedBy::System, false).await {
-                error!(
-                    "Failed to start instance {}: {:?}",
-                    instance.name().await,
-                    e
-                );
-            }
-        }
-    }
     let mut allocated_ports = HashSet::new();
     for (_, instance) in instances.iter() {
         allocated_ports.insert(instance.port().await);
     }
+    let buffer_settings = global_settings.buffer_settings();
     let shared_state = AppState {
-        instances: Arc::new(Mutex::new(instances)),
+        instances: InstanceMap::new(instances),
+        instance_summaries: InstanceSummaries::default(),
         users_manager: Arc::new(RwLock::new(users_manager)),
-        events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(512))),
+        events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(
+            buffer_settings.event_buffer_size,
+        ))),
         console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
         monitor_buffer: Arc::new(Mutex::new(HashMap::new())),
+        usage_history: UsageHistory::default(),
         event_broadcaster: tx.clone(),
         uuid: Uuid::new_v4().to_string(),
         up_since: chrono::Utc::now().timestamp(),
         port_manager: Arc::new(Mutex::new(PortManager::new(allocated_ports))),
+        public_ip: PublicIp::default(),
         first_time_setup_key: Arc::new(Mutex::new(first_time_setup_key)),
         system: Arc::new(Mutex::new(sysinfo::System::new_all())),
         download_urls: Arc::new(Mutex::new(HashMap::new())),
+        rate_limiter: Arc::new(Mutex::new(RateLimiter::new(
+            global_settings.rate_limit_settings(),
+        ))),
         global_settings: Arc::new(Mutex::new(global_settings)),
+        fs_locations: Arc::new(Mutex::new(fs_locations)),
+        notifications: Arc::new(Mutex::new(notifications)),
+        status_page: Arc::new(Mutex::new(status_page)),
+        macro_triggers: Arc::new(Mutex::new(macro_triggers)),
+        command_queues: Arc::new(Mutex::new(command_queues)),
+        command_filters: Arc::new(Mutex::new(command_filters)),
+        command_sequences: CommandSequences::default(),
+        command_history: CommandHistory::default(),
+        jobs: Jobs::new(tx.clone()),
+        updater: Updater::new(RELEASE_FEED_URL.to_string()),
+        console_snippets: Arc::new(Mutex::new(console_snippets)),
+        console_profiles: Arc::new(Mutex::new(console_profiles)),
+        console_matchers: Arc::new(Mutex::new(console_matchers)),
+        instance_webhooks: Arc::new(Mutex::new(instance_webhooks)),
+        network_policies: Arc::new(Mutex::new(network_policies)),
+        instance_syncs: Arc::new(Mutex::new(instance_syncs)),
+        instance_groups: Arc::new(Mutex::new(instance_groups)),
+        archived_instances: Arc::new(Mutex::new(archived_instances)),
+        start_dependencies: Arc::new(Mutex::new(start_dependencies)),
+        upload_sessions: Arc::new(Mutex::new(upload_sessions)),
+        user_quotas: Arc::new(Mutex::new(user_quotas)),
+        usage_ledger: Arc::new(Mutex::new(usage_ledger)),
+        player_database: Arc::new(Mutex::new(player_database)),
+        peers: Arc::new(Mutex::new(peers)),
+        backup_destinations: Arc::new(Mutex::new(backup_destinations)),
+        suspicious_activity_policies: Arc::new(Mutex::new(suspicious_activity_policies)),
+        storage_roots: Arc::new(Mutex::new(storage_roots)),
         macro_executor,
         sqlite_pool: Pool::connect_with(
             SqliteConnectOptions::from_str(&format!(
@@ -450,12 +1069,23 @@ pub async fn run(
         )
         .await
         .unwrap(),
+        api_requests: ApiRequestCounter::default(),
+        directory_sizes: DirectorySizes::default(),
+        health_checks: HealthChecks::default(),
     };
 
+    if let Err(e) = restore_event_buffers(&shared_state).await {
+        error!("Failed to restore event history from the database : {e}");
+    }
+    if let Err(e) = init_usage_rollups_table(&shared_state.sqlite_pool).await {
+        error!("Failed to create the usage history table : {e}");
+    }
+
     let event_buffer_task = {
         let event_buffer = shared_state.events_buffer.clone();
         let console_out_buffer = shared_state.console_out_buffer.clone();
-        let mut event_receiver = tx.subscribe();
+        let global_settings = shared_state.global_settings.clone();
+        let mut event_receiver = event_buffer_receiver;
         async move {
             loop {
                 let result = event_receiver.recv().await;
@@ -473,12 +1103,22 @@ pub async fn run(
                 }
                 let event = result.unwrap();
                 if event.is_event_console_message() {
-                    console_out_buffer
-                        .lock()
-                        .await
-                        .entry(event.get_instance_uuid().unwrap())
-                        .or_insert_with(|| AllocRingBuffer::with_capacity(1024))
-                        .push(event.clone());
+                    let instance_uuid = event.get_instance_uuid().unwrap();
+                    let mut console_out_buffer = console_out_buffer.lock().await;
+                    if !console_out_buffer.contains_key(&instance_uuid) {
+                        let console_buffer_size = global_settings
+                            .lock()
+                            .await
+                            .buffer_settings()
+                            .console_buffer_size;
+                        console_out_buffer.insert(
+                            instance_uuid.clone(),
+                            AllocRingBuffer::with_capacity(console_buffer_size),
+                        );
+                    }
+                    if let Some(buffer) = console_out_buffer.get_mut(&instance_uuid) {
+                        buffer.push(event.clone());
+                    }
                 } else {
                     event_buffer.lock().await.push(event.clone());
                 }
@@ -486,139 +1126,408 @@ pub async fn run(
         }
     };
 
-    let write_to_db_task = write_event_to_db_task(tx.subscribe(), shared_state.sqlite_pool.clone());
+    let write_to_db_task =
+        write_event_to_db_task(db_event_receiver, shared_state.sqlite_pool.clone());
 
-    let monitor_report_task = {
-        let monitor_buffer = shared_state.monitor_buffer.clone();
-        let instances = shared_state.instances.clone();
-        async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(1));
-            loop {
-                for (uuid, instance) in instances.lock().await.iter() {
-                    let report = instance.monitor().await;
-                    monitor_buffer
-                        .lock()
-                        .await
-                        .entry(uuid.to_owned())
-                        .or_insert_with(|| AllocRingBuffer::with_capacity(64))
-                        .push(report);
+    let prune_events_task = prune_events_task(
+        shared_state.sqlite_pool.clone(),
+        shared_state.global_settings.clone(),
+    );
+
+    let notification_task =
+        notifications::notification_task(tx.subscribe(), shared_state.notifications.clone());
+
+    let instance_summary_task = instance_summaries::instance_summary_task(
+        tx.subscribe(),
+        shared_state.instance_summaries.clone(),
+        shared_state.instances.clone(),
+    );
+
+    let status_page_task = status_page::status_page_task(
+        tx.subscribe(),
+        shared_state.status_page.clone(),
+        shared_state.instances.clone(),
+        shared_state.global_settings.clone(),
+    );
+
+    let macro_trigger_task = macro_triggers::macro_trigger_task(
+        tx.subscribe(),
+        shared_state.macro_triggers.clone(),
+        shared_state.instances.clone(),
+        shared_state.global_settings.clone(),
+    );
+
+    let console_matcher_task = console_matchers::console_matcher_task(
+        tx.subscribe(),
+        shared_state.console_matchers.clone(),
+        tx.clone(),
+    );
+
+    let command_queue_task = command_queue::command_queue_task(
+        tx.subscribe(),
+        shared_state.command_queues.clone(),
+        shared_state.instances.clone(),
+    );
+
+    let instance_webhook_task = instance_webhooks::instance_webhook_task(
+        tx.subscribe(),
+        shared_state.instance_webhooks.clone(),
+        shared_state.instances.clone(),
+    );
+
+    let network_isolation_task = network_isolation::network_isolation_task(
+        tx.subscribe(),
+        shared_state.network_policies.clone(),
+        shared_state.instances.clone(),
+    );
+
+    let upnp_task = public_address::upnp_task(tx.subscribe(), shared_state.instances.clone());
+
+    let player_database_task = player_database::player_database_task(
+        tx.subscribe(),
+        shared_state.player_database.clone(),
+        shared_state.event_broadcaster.clone(),
+    );
+
+    let federation_task = federation::federation_task(
+        shared_state.peers.clone(),
+        shared_state.event_broadcaster.clone(),
+    );
+
+    let backup_upload_task = backup_destinations::backup_upload_task(
+        tx.subscribe(),
+        shared_state.backup_destinations.clone(),
+        shared_state.event_broadcaster.clone(),
+    );
+
+    let suspicious_activity_task = suspicious_activity::suspicious_activity_task(
+        tx.subscribe(),
+        shared_state.suspicious_activity_policies.clone(),
+        shared_state.instances.clone(),
+        shared_state.event_broadcaster.clone(),
+    );
+
+    let monitor_report_task = monitor_task::monitor_report_task(
+        shared_state.instances.clone(),
+        shared_state.monitor_buffer.clone(),
+        shared_state.system.clone(),
+        shared_state.event_broadcaster.clone(),
+        shared_state.directory_sizes.clone(),
+        shared_state.global_settings.clone(),
+        shared_state.usage_history.clone(),
+        shared_state.sqlite_pool.clone(),
+    );
+
+    let disk_usage_task = disk_usage::disk_usage_task(
+        shared_state.instances.clone(),
+        shared_state.directory_sizes.clone(),
+        shared_state.event_broadcaster.clone(),
+    );
+
+    let usage_accounting_task = usage_accounting::usage_accounting_task(
+        shared_state.usage_ledger.clone(),
+        shared_state.instances.clone(),
+        shared_state.directory_sizes.clone(),
+        shared_state.user_quotas.clone(),
+    );
+
+    let backup_scheduler_task = backup::backup_scheduler_task(
+        shared_state.instances.clone(),
+        shared_state.event_broadcaster.clone(),
+        shared_state.global_settings.clone(),
+        shared_state.jobs.clone(),
+    );
+
+    let log_housekeeping_task =
+        log_housekeeping::log_housekeeping_task(shared_state.instances.clone());
+
+    let trash_purge_task = trash::trash_purge_task(
+        shared_state.instances.clone(),
+        shared_state.global_settings.clone(),
+    );
+
+    let health_check_task = health::health_check_task(
+        shared_state.health_checks.clone(),
+        shared_state.event_broadcaster.clone(),
+        shared_state.sqlite_pool.clone(),
+    );
+
+    let update_check_task = self_update::update_check_task(shared_state.updater.clone());
+
+    let telemetry_task = telemetry::telemetry_task(
+        shared_state.global_settings.clone(),
+        shared_state.instances.clone(),
+    );
+
+    let server_config =
+        match ServerConfig::load(lodestone_path)
+            .await
+            .and_then(|mut server_config| {
+                if args.read_only_port.is_some() {
+                    server_config.read_only_port = args.read_only_port;
                 }
-                interval.tick().await;
+                server_config.validate().map(|_| server_config)
+            }) {
+            Ok(server_config) => server_config,
+            Err(e) => {
+                error!("Invalid server config : {e}, exiting");
+                std::process::exit(1);
             }
+        };
+    let (tls_cert_path, tls_key_path) = server_config.tls_paths(lodestone_path);
+    let tls_config_result = RustlsConfig::from_pem_file(tls_cert_path, tls_key_path).await;
+    if let Err(e) = &tls_config_result {
+        if server_config.tls_configured() {
+            error!("Failed to load the configured TLS certificate : {e}, exiting");
+            std::process::exit(1);
         }
-    };
-
-    let tls_config_result = RustlsConfig::from_pem_file(
-        lodestone_path.join("tls").join("cert.pem"),
-        lodestone_path.join("tls").join("key.pem"),
-    )
-    .await;
+    }
 
     (
         {
             let shared_state = shared_state.clone();
             async move {
-                let cors = CorsLayer::new()
-                    .allow_methods([
-                        Method::GET,
-                        Method::POST,
-                        Method::PATCH,
-                        Method::PUT,
-                        Method::DELETE,
-                        Method::OPTIONS,
-                    ])
-                    .allow_headers([header::ORIGIN, header::CONTENT_TYPE, header::AUTHORIZATION]) // Note I can't find X-Auth-Token but it was in the original rocket version, hope it's fine
-                    .allow_origin(Any);
-
-                let trace = TraceLayer::new_for_http();
-
-                let api_routes = Router::new()
-                    .merge(get_events_routes(shared_state.clone()))
-                    .merge(get_instance_setup_config_routes(shared_state.clone()))
-                    .merge(get_instance_server_routes(shared_state.clone()))
-                    .merge(get_instance_config_routes(shared_state.clone()))
-                    .merge(get_instance_players_routes(shared_state.clone()))
-                    .merge(get_instance_routes(shared_state.clone()))
-                    .merge(get_system_routes(shared_state.clone()))
-                    .merge(get_checks_routes(shared_state.clone()))
-                    .merge(get_user_routes(shared_state.clone()))
-                    .merge(get_core_info_routes(shared_state.clone()))
-                    .merge(get_setup_route(shared_state.clone()))
-                    .merge(get_monitor_routes(shared_state.clone()))
-                    .merge(get_instance_macro_routes(shared_state.clone()))
-                    .merge(get_instance_fs_routes(shared_state.clone()))
-                    .merge(get_global_fs_routes(shared_state.clone()))
-                    .merge(get_global_settings_routes(shared_state.clone()))
-                    .merge(get_gateway_routes(shared_state.clone()))
-                    .layer(cors)
-                    .layer(trace);
-                let app = Router::new().nest("/api/v1", api_routes);
-                #[allow(unused_variables, unused_mut)]
-                let mut port = 16_662_u16;
-                #[cfg(not(debug_assertions))]
+                let hsts = tls_config_result.is_ok();
+
+                let api_routes = api_routes(&shared_state);
+                let app = Router::new().nest(
+                    "/api/v1",
+                    with_http_layers(api_routes, &server_config, hsts),
+                );
+                let read_only_app = Router::new().nest(
+                    "/api/v1",
+                    with_http_layers(
+                        get_read_only_routes(shared_state.clone())
+                            .layer(axum::middleware::from_fn_with_state(
+                                shared_state.clone(),
+                                rate_limit,
+                            ))
+                            .layer(axum::middleware::from_fn(structured_rejections))
+                            .layer(axum::middleware::from_fn_with_state(
+                                shared_state.api_requests.clone(),
+                                count_api_requests,
+                            )),
+                        &server_config,
+                        hsts,
+                    ),
+                );
+                #[allow(unused_mut)]
+                let mut port = server_config.port.unwrap_or(DEFAULT_PORT);
+                // only move off the default port, an explicitly configured port is expected to be used as is
+                #[cfg(debug_assertions)]
+                if server_config.port.is_none() {
+                    while port_scanner::scan_port(port) {
+                        debug!("Port {port} is already in use, trying next port");
+                        port += 1;
+                    }
+                }
                 if port_scanner::scan_port(port) {
                     error!("Port {port} is already in use, exiting");
                     std::process::exit(1);
                 }
-                #[cfg(debug_assertions)]
-                while port_scanner::scan_port(port) {
-                    debug!("Port {port} is already in use, trying next port");
-                    port += 1;
-                }
-                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                let addr = SocketAddr::new(server_config.api_bind_address(), port);
+                let tls_config = match tls_config_result {
+                    Ok(config) => {
+                        info!("TLS enabled");
+                        Some(config)
+                    }
+                    Err(e) => {
+                        warn!("Invalid TLS config : {e}, using HTTP");
+                        None
+                    }
+                };
                 let axum_server_handle = axum_server::Handle::new();
-                tokio::spawn({
-                    let axum_server_handle = axum_server_handle.clone();
-                    async move {
-                        match tls_config_result {
-                            Ok(config) => {
-                                info!("TLS enabled");
-                                info!("Lodestone Core live on {addr}");
-                                info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
-                                axum_server::bind_rustls(addr, config)
-                                    .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
-                                    .await
-                            }
-                            Err(e) => {
-                                warn!("Invalid TLS config : {e}, using HTTP");
-                                info!("Lodestone Core live on {addr}");
-                                info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
-                                axum_server::bind(addr)
-                                    .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
-                                    .await
-                            }
-                        }
-                        .unwrap();
+                info!("Lodestone Core live on {addr}");
+                info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
+                tokio::spawn(serve(
+                    addr,
+                    app,
+                    axum_server_handle.clone(),
+                    tls_config.clone(),
+                ));
+                let read_only_server_handle = axum_server::Handle::new();
+                if let Some(read_only_port) = server_config.read_only_port {
+                    if port_scanner::scan_port(read_only_port) {
+                        error!("Port {read_only_port} is already in use, exiting");
+                        std::process::exit(1);
                     }
-                });
+                    let read_only_addr =
+                        SocketAddr::new(server_config.read_only_bind_address, read_only_port);
+                    info!("Read only API live on {read_only_addr}");
+                    tokio::spawn(serve(
+                        read_only_addr,
+                        read_only_app,
+                        read_only_server_handle.clone(),
+                        tls_config,
+                    ));
+                }
+                // started once the server is up so the frontend can follow along
+                tokio::spawn(auto_start_instances(
+                    shared_state.instances.clone(),
+                    shared_state.event_broadcaster.clone(),
+                    shared_state.start_dependencies.clone(),
+                ));
                 select! {
                     _ = write_to_db_task => info!("Write to db task exited"),
+                    _ = prune_events_task => info!("Prune events task exited"),
                     _ = event_buffer_task => info!("Event buffer task exited"),
+                    _ = notification_task => info!("Notification task exited"),
+                    _ = instance_summary_task => info!("Instance summary task exited"),
+                    _ = status_page_task => info!("Status page task exited"),
+                    _ = macro_trigger_task => info!("Macro trigger task exited"),
+                    _ = console_matcher_task => info!("Console matcher task exited"),
+                    _ = command_queue_task => info!("Command queue task exited"),
+                    _ = instance_webhook_task => info!("Instance webhook task exited"),
+                    _ = network_isolation_task => info!("Network isolation task exited"),
+                    _ = upnp_task => info!("UPnP task exited"),
+                    _ = player_database_task => info!("Player database task exited"),
+                    _ = federation_task => info!("Federation task exited"),
+                    _ = backup_upload_task => info!("Backup upload task exited"),
+                    _ = suspicious_activity_task => info!("Suspicious activity task exited"),
                     _ = monitor_report_task => info!("Monitor report task exited"),
-                    _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+                    _ = disk_usage_task => info!("Disk usage task exited"),
+                    _ = usage_accounting_task => info!("Usage accounting task exited"),
+                    _ = backup_scheduler_task => info!("Backup scheduler task exited"),
+                    _ = log_housekeeping_task => info!("Log housekeeping task exited"),
+                    _ = trash_purge_task => info!("Trash purge task exited"),
+                    _ = health_check_task => info!("Health check task exited"),
+                    _ = telemetry_task => info!("Telemetry task exited"),
+                    _ = update_check_task => info!("Update check task exited"),
+                    _ = shutdown::shutdown_signal() => {},
+                    _ = shutdown::restart_signal() => info!("Restarting Lodestone Core"),
                 }
                 info!("Shutting down web server");
                 axum_server_handle.shutdown();
+                read_only_server_handle.shutdown();
                 info!("Signalling all instances to stop");
-                // cleanup
-                let mut instances = shared_state.instances.lock().await;
-                for (_, instance) in instances.iter_mut() {
-                    if instance.state().await == State::Stopped {
-                        continue;
-                    }
-                    if let Err(e) = instance.stop(CausedBy::System, false).await {
-                        error!(
-                            "Failed to stop instance {} : {}. Instance may need manual cleanup",
-                            instance.uuid().await,
-                            e
-                        );
-                    }
-                }
+                shutdown::stop_all_instances(
+                    &shared_state,
+                    Duration::from_secs(server_config.shutdown_grace_period),
+                )
+                .await;
+                shutdown::flush_stores(&shared_state).await;
             }
         },
         shared_state,
         guard,
     )
 }
+
+/// The routes of the main API, with the middleware every request goes through
+pub(crate) fn api_routes(shared_state: &AppState) -> Router {
+    Router::new()
+        .merge(get_events_routes(shared_state.clone()))
+        .merge(get_instance_setup_config_routes(shared_state.clone()))
+        .merge(get_instance_server_routes(shared_state.clone()))
+        .merge(get_console_snippet_routes(shared_state.clone()))
+        .merge(get_console_profile_routes(shared_state.clone()))
+        .merge(get_console_matcher_routes(shared_state.clone()))
+        .merge(get_control_channel_routes(shared_state.clone()))
+        .merge(get_instance_config_routes(shared_state.clone()))
+        .merge(get_instance_players_routes(shared_state.clone()))
+        .merge(get_instance_routes(shared_state.clone()))
+        .merge(get_instance_backup_routes(shared_state.clone()))
+        .merge(get_instance_export_routes(shared_state.clone()))
+        .merge(get_instance_logs_routes(shared_state.clone()))
+        .merge(get_system_routes(shared_state.clone()))
+        .merge(get_checks_routes(shared_state.clone()))
+        .merge(get_user_routes(shared_state.clone()))
+        .merge(get_core_info_routes(shared_state.clone()))
+        .merge(get_health_routes(shared_state.clone()))
+        .merge(get_download_routes(shared_state.clone()))
+        .merge(get_java_runtime_routes(shared_state.clone()))
+        .merge(get_setup_route(shared_state.clone()))
+        .merge(get_monitor_routes(shared_state.clone()))
+        .merge(get_instance_macro_routes(shared_state.clone()))
+        .merge(get_instance_mods_routes(shared_state.clone()))
+        .merge(get_instance_template_routes(shared_state.clone()))
+        .merge(get_instance_fs_routes(shared_state.clone()))
+        .merge(get_global_fs_routes(shared_state.clone()))
+        .merge(get_upload_routes(shared_state.clone()))
+        .merge(get_trash_routes(shared_state.clone()))
+        .merge(get_global_settings_routes(shared_state.clone()))
+        .merge(get_gateway_routes(shared_state.clone()))
+        .merge(get_overview_routes(shared_state.clone()))
+        .merge(get_notifications_routes(shared_state.clone()))
+        .merge(get_instance_webhook_routes(shared_state.clone()))
+        .merge(get_instance_worlds_routes(shared_state.clone()))
+        .merge(get_network_isolation_routes(shared_state.clone()))
+        .merge(get_instance_sync_routes(shared_state.clone()))
+        .merge(get_instance_group_routes(shared_state.clone()))
+        .merge(get_instance_archive_routes(shared_state.clone()))
+        .merge(get_start_dependency_routes(shared_state.clone()))
+        .merge(get_user_quota_routes(shared_state.clone()))
+        .merge(get_usage_accounting_routes(shared_state.clone()))
+        .merge(get_metrics_routes(shared_state.clone()))
+        .merge(get_status_page_routes(shared_state.clone()))
+        .merge(get_storage_roots_routes(shared_state.clone()))
+        .merge(get_webdav_routes(shared_state.clone()))
+        .merge(get_reservation_routes(shared_state.clone()))
+        .merge(get_federation_routes(shared_state.clone()))
+        .merge(get_backup_destination_routes(shared_state.clone()))
+        .merge(get_suspicious_activity_routes(shared_state.clone()))
+        .merge(get_advisories_routes(shared_state.clone()))
+        .merge(get_diagnostics_routes(shared_state.clone()))
+        .merge(get_jobs_routes(shared_state.clone()))
+        .merge(get_openapi_routes(shared_state.clone()))
+        .fallback(|| async {
+            Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("No such endpoint"),
+            }
+        })
+        // requests forwarded to peers are throttled and their errors structured too
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            proxy_to_peer,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            rate_limit,
+        ))
+        .layer(axum::middleware::from_fn(structured_rejections))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            record_audit_event,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.api_requests.clone(),
+            count_api_requests,
+        ))
+}
+
+/// The layers around every router the core serves: security headers, CORS and tracing, the
+/// latter being the outermost
+fn with_http_layers(router: Router, server_config: &ServerConfig, hsts: bool) -> Router {
+    let mut router = router;
+    if server_config.security_headers {
+        router = router.layer(axum::middleware::from_fn_with_state(hsts, security_headers));
+    }
+    if let Some(cors) = server_config.cors_layer() {
+        router = router.layer(cors);
+    }
+    router.layer(TraceLayer::new_for_http())
+}
+
+async fn serve(
+    addr: SocketAddr,
+    app: Router,
+    handle: axum_server::Handle,
+    tls_config: Option<RustlsConfig>,
+) {
+    match tls_config {
+        Some(config) => {
+            axum_server::bind_rustls(addr, config)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+        None => {
+            axum_server::bind(addr)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+    }
+    .unwrap();
+}
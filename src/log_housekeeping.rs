@@ -0,0 +1,252 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    instance_map::InstanceMap,
+    traits::t_configurable::TConfigurable,
+};
+
+static LOG_RETENTION_FILE_NAME: &str = ".lodestone_log_retention.json";
+
+/// the log file the server is currently writing to, never touched
+static ACTIVE_LOG_FILE_NAME: &str = "latest.log";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Retention rules for the files in the `logs/` directory of an instance
+#[derive(Serialize, Deserialize, Clone, Debug, TS, Default, PartialEq, Eq)]
+#[ts(export)]
+pub struct LogRetentionRule {
+    /// Plain log files older than this are gzipped
+    pub compress_after_days: Option<u32>,
+    /// Log files, compressed or not, older than this are removed
+    pub delete_after_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, Default, PartialEq, Eq)]
+#[ts(export)]
+pub struct LogHousekeepingReport {
+    pub compressed: Vec<String>,
+    pub deleted: Vec<String>,
+    /// bytes of disk space freed
+    pub freed: u64,
+}
+
+fn path_to_rule(instance_path: &Path) -> PathBuf {
+    instance_path.join(LOG_RETENTION_FILE_NAME)
+}
+
+pub async fn read_log_retention_rule(instance_path: &Path) -> Result<LogRetentionRule, Error> {
+    let path = path_to_rule(instance_path);
+    if !path.is_file() {
+        return Ok(LogRetentionRule::default());
+    }
+    let content = crate::util::fs::read_to_string(&path).await?;
+    Ok(serde_json::from_str(&content).context(format!(
+        "Failed to parse log retention rule {}",
+        path.display()
+    ))?)
+}
+
+pub async fn write_log_retention_rule(
+    instance_path: &Path,
+    rule: &LogRetentionRule,
+) -> Result<(), Error> {
+    if let (Some(compress_after_days), Some(delete_after_days)) =
+        (rule.compress_after_days, rule.delete_after_days)
+    {
+        if compress_after_days >= delete_after_days {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Logs must be compressed before they are deleted"),
+            });
+        }
+    }
+    crate::util::fs::write_all(
+        path_to_rule(instance_path),
+        serde_json::to_string_pretty(rule).context("Failed to serialize log retention rule")?,
+    )
+    .await
+}
+
+/// Gzip `path` to `<path>.gz` with the same modification time and remove the original
+fn compress_log_file(path: &Path, modified: SystemTime) -> Result<u64, Error> {
+    let mut dest_name = path.file_name().unwrap_or_default().to_owned();
+    dest_name.push(".gz");
+    let dest = path.with_file_name(dest_name);
+    let compress = || -> Result<u64, Error> {
+        let mut encoder = GzEncoder::new(
+            File::create(&dest).context(format!("Failed to create {}", dest.display()))?,
+            Compression::default(),
+        );
+        std::io::copy(
+            &mut BufReader::new(
+                File::open(path).context(format!("Failed to open {}", path.display()))?,
+            ),
+            &mut encoder,
+        )
+        .context(format!("Failed to compress {}", path.display()))?;
+        let file = encoder
+            .finish()
+            .context(format!("Failed to compress {}", path.display()))?;
+        // keep the age of the log so it is deleted on time
+        file.set_modified(modified).context(format!(
+            "Failed to set modification time of {}",
+            dest.display()
+        ))?;
+        Ok(file
+            .metadata()
+            .context(format!("Failed to read metadata of {}", dest.display()))?
+            .len())
+    };
+    let compressed_size = compress().map_err(|e| {
+        let _ = std::fs::remove_file(&dest);
+        e
+    })?;
+    std::fs::remove_file(path).context(format!("Failed to remove {}", path.display()))?;
+    Ok(compressed_size)
+}
+
+/// Apply `rule` to the files directly inside `logs_dir`, files are aged by their modification time
+pub fn housekeep_logs(
+    logs_dir: &Path,
+    rule: &LogRetentionRule,
+    now: SystemTime,
+) -> Result<LogHousekeepingReport, Error> {
+    let mut report = LogHousekeepingReport::default();
+    if !logs_dir.is_dir() {
+        return Ok(report);
+    }
+    let older_than = |modified: SystemTime, days: Option<u32>| {
+        days.is_some_and(|days| {
+            now.duration_since(modified).unwrap_or_default()
+                > Duration::from_secs(days as u64 * SECONDS_PER_DAY)
+        })
+    };
+    for entry in std::fs::read_dir(logs_dir)
+        .context(format!("Failed to read directory {}", logs_dir.display()))?
+    {
+        let entry = entry.context(format!("Failed to read directory {}", logs_dir.display()))?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if file_name == ACTIVE_LOG_FILE_NAME {
+            continue;
+        }
+        let modified = metadata.modified().context(format!(
+            "Failed to read modification time of {}",
+            path.display()
+        ))?;
+        if older_than(modified, rule.delete_after_days) {
+            std::fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+            report.freed += metadata.len();
+            report.deleted.push(file_name);
+        } else if path.extension().is_some_and(|ext| ext == "log")
+            && older_than(modified, rule.compress_after_days)
+        {
+            let compressed_size = compress_log_file(&path, modified)?;
+            report.freed += metadata.len().saturating_sub(compressed_size);
+            report.compressed.push(file_name);
+        }
+    }
+    Ok(report)
+}
+
+pub async fn housekeep_instance_logs(
+    instance_path: PathBuf,
+) -> Result<LogHousekeepingReport, Error> {
+    let rule = read_log_retention_rule(&instance_path).await?;
+    if rule == LogRetentionRule::default() {
+        return Ok(LogHousekeepingReport::default());
+    }
+    tokio::task::spawn_blocking(move || {
+        housekeep_logs(&instance_path.join("logs"), &rule, SystemTime::now())
+    })
+    .await
+    .context("Log housekeeping task panicked")?
+}
+
+/// Periodically applies the log retention rules of every instance
+pub async fn log_housekeeping_task(instances: InstanceMap) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let mut paths = Vec::new();
+        for (uuid, instance) in instances.snapshot() {
+            paths.push((uuid, instance.path().await));
+        }
+        for (uuid, path) in paths {
+            match housekeep_instance_logs(path).await {
+                Ok(report) if !report.compressed.is_empty() || !report.deleted.is_empty() => {
+                    info!(
+                        "Log housekeeping for {uuid} compressed {} and deleted {} files, freeing {}",
+                        report.compressed.len(),
+                        report.deleted.len(),
+                        crate::util::format_byte(report.freed)
+                    )
+                }
+                Ok(_) => {}
+                Err(e) => error!("Log housekeeping for {uuid} failed : {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{housekeep_logs, LogRetentionRule, SECONDS_PER_DAY};
+
+    #[test]
+    fn test_housekeep_logs() {
+        let temp = tempfile::tempdir().unwrap();
+        let logs_dir = temp.path();
+        std::fs::write(logs_dir.join("latest.log"), "current").unwrap();
+        std::fs::write(logs_dir.join("old.log"), "a".repeat(4096)).unwrap();
+        std::fs::write(logs_dir.join("older.log.gz"), "gzipped").unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(logs_dir.join("older.log.gz"))
+            .unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(20 * SECONDS_PER_DAY))
+            .unwrap();
+        drop(file);
+        let rule = LogRetentionRule {
+            compress_after_days: Some(2),
+            delete_after_days: Some(10),
+        };
+
+        let report = housekeep_logs(logs_dir, &rule, SystemTime::now()).unwrap();
+        assert!(report.compressed.is_empty());
+        assert_eq!(report.deleted, vec!["older.log.gz".to_string()]);
+
+        let in_five_days = SystemTime::now() + Duration::from_secs(5 * SECONDS_PER_DAY);
+        let report = housekeep_logs(logs_dir, &rule, in_five_days).unwrap();
+        assert_eq!(report.compressed, vec!["old.log".to_string()]);
+        assert!(report.freed > 0);
+        assert!(logs_dir.join("old.log.gz").is_file());
+        assert!(!logs_dir.join("old.log").exists());
+        assert!(logs_dir.join("latest.log").is_file());
+
+        // the compressed log keeps its age
+        let in_eleven_days = SystemTime::now() + Duration::from_secs(11 * SECONDS_PER_DAY);
+        let report = housekeep_logs(logs_dir, &rule, in_eleven_days).unwrap();
+        assert_eq!(report.deleted, vec!["old.log.gz".to_string()]);
+        assert!(logs_dir.join("latest.log").is_file());
+    }
+}
@@ -12,6 +12,7 @@ use color_eyre::eyre::Context;
 use dashmap::DashMap;
 use deno_runtime::permissions::Permissions;
 use futures_util::Future;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::{runtime::Builder, sync::mpsc, task::LocalSet};
@@ -23,6 +24,7 @@ use crate::{
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, EventInner, MacroEvent, MacroEventInner},
+    macro_sandbox::MacroGrant,
     traits::t_macro::ExitStatus,
     types::InstanceUuid,
 };
@@ -53,7 +55,7 @@ pub struct TypescriptModuleLoader {
     http: reqwest::Client,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, TS)]
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, TS, JsonSchema)]
 #[serde(transparent)]
 #[ts(export)]
 pub struct MacroPID(pub usize); // todo remove pub
@@ -253,6 +255,9 @@ impl MacroExecutor {
     /// Note that this does not terminate the process, it just stops the handle from waiting for it.
     ///
     /// It is up to the caller to terminate the process if it is still running.
+    ///
+    /// The process is sandboxed by `grant` if there is one, and killed once it runs past the
+    /// timeout or heap limit of the grant. Without a grant it runs with every permission.
     #[allow(clippy::too_many_arguments)]
     pub async fn spawn(
         &self,
@@ -260,10 +265,15 @@ impl MacroExecutor {
         args: Vec<String>,
         _caused_by: CausedBy,
         worker_options_generator: Box<dyn WorkerOptionGenerator>,
-        permissions: Option<Permissions>,
+        grant: Option<MacroGrant>,
         instance_uuid: Option<InstanceUuid>,
         timeout: Option<Duration>,
     ) -> Result<SpawnResult, Error> {
+        let permissions = match &grant {
+            Some(grant) => grant.permissions()?,
+            None => Permissions::allow_all(),
+        };
+        let run_timeout = grant.as_ref().and_then(|grant| grant.timeout);
         let pid = MacroPID(self.next_process_id.fetch_add(1, Ordering::SeqCst));
         let exit_future = Box::pin({
             let __self = self.clone();
@@ -288,19 +298,37 @@ impl MacroExecutor {
                 let local = LocalSet::new();
                 local.spawn_local(async move {
                     let mut worker_option = worker_options_generator.generate();
-                    register_all_event_ops(&mut worker_option, event_broadcaster.clone());
+                    let max_heap_mb = grant.as_ref().and_then(|grant| grant.max_heap_mb);
+                    register_all_event_ops(&mut worker_option, event_broadcaster.clone(), grant);
                     worker_option.bootstrap.args = args;
+                    if let Some(max_heap_mb) = max_heap_mb {
+                        worker_option.create_params = Some(
+                            deno_core::v8::CreateParams::default()
+                                .heap_limits(0, max_heap_mb as usize * 1024 * 1024),
+                        );
+                    }
 
                     let mut main_worker = deno_runtime::worker::MainWorker::from_options(
                         main_module,
-                        deno_runtime::permissions::PermissionsContainer::new(
-                            permissions.unwrap_or_else(Permissions::allow_all),
-                        ),
+                        deno_runtime::permissions::PermissionsContainer::new(permissions),
                         worker_option,
                     );
 
                     let isolate_handle = main_worker.js_runtime.v8_isolate().thread_safe_handle();
 
+                    if max_heap_mb.is_some() {
+                        // V8 aborts the whole process once the heap is full, so the macro is
+                        // terminated first and given enough room to unwind
+                        let isolate_handle = isolate_handle.clone();
+                        main_worker.js_runtime.add_near_heap_limit_callback(
+                            move |current_limit, _| {
+                                warn!("Macro {pid} reached its heap limit, terminating");
+                                isolate_handle.terminate_execution();
+                                current_limit * 2
+                            },
+                        );
+                    }
+
                     process_table.insert(pid, isolate_handle);
 
                     let main_module = match deno_core::resolve_path(
@@ -451,6 +479,16 @@ impl MacroExecutor {
         tokio::time::timeout(Duration::from_secs(1), fut)
             .await
             .context("Failed to spawn macro")??;
+        if let Some(run_timeout) = run_timeout {
+            let __self = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(run_timeout)).await;
+                if !__self.exit_status_table.contains_key(&pid) {
+                    warn!("Macro {pid} ran past its timeout of {run_timeout}s, terminating");
+                    let _ = __self.abort_macro(pid);
+                }
+            });
+        }
         Ok(SpawnResult {
             macro_pid: pid,
             main_module_future,
@@ -0,0 +1,182 @@
+//! What a macro is allowed to do.
+//!
+//! Macros are TypeScript or JavaScript modules run by the macro executor. Besides the standard
+//! Deno APIs, e.g. `setTimeout` to sleep, they can call these ops through `Deno.core`:
+//!
+//! | op | capability | |
+//! |---|---|---|
+//! | `opAsync("send_stdin", command)` | `SendCommand` | send a console command to the instance |
+//! | `opAsync("send_rcon", command)` | `SendCommand` | send a command over RCON, resolves to the answer |
+//! | `opAsync("on_event", name)` | `ReadEvents` | wait for `playerMessage`, `playersJoined`, `playersLeft` or `playersChanged` |
+//! | `opAsync("next_event")` | `ReadEvents` | wait for the next event of the core |
+//! | `ops.broadcast_event(event)` | `HostAccess` | send an event as the core |
+//! | `ops.emit_console_out(line, name, uuid)` | `HostAccess` | write a line to the console of an instance |
+//!
+//! `fetch` reaches the hosts of the allowlist with `HttpFetch`. Files, subprocesses, environment
+//! variables and any host can only be reached with `HostAccess`, which is also what macros without
+//! a grant get, as macros could do all of this before grants existed.
+
+use std::collections::HashSet;
+
+use color_eyre::eyre::eyre;
+use deno_core::OpState;
+use deno_runtime::permissions::{Permissions, PermissionsOptions};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+/// the smallest heap limit a macro can be given, V8 can't start with much less
+const MIN_HEAP_MB: u64 = 16;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[ts(export)]
+pub enum MacroCapability {
+    SendCommand,
+    ReadEvents,
+    HttpFetch,
+    /// everything else, the macro runs with the permissions of the core
+    HostAccess,
+}
+
+/// The capabilities and limits of a macro
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct MacroGrant {
+    pub capabilities: HashSet<MacroCapability>,
+    /// hosts `fetch` can reach with `HttpFetch`, e.g. `api.example.com` or `example.com:8080`
+    pub http_allowlist: Vec<String>,
+    /// seconds the macro can run before it is killed
+    pub timeout: Option<u64>,
+    /// megabytes of heap the macro can use before it is killed
+    pub max_heap_mb: Option<u64>,
+}
+
+impl Default for MacroGrant {
+    fn default() -> Self {
+        Self {
+            capabilities: HashSet::from([
+                MacroCapability::SendCommand,
+                MacroCapability::ReadEvents,
+                MacroCapability::HostAccess,
+            ]),
+            http_allowlist: Vec::new(),
+            timeout: None,
+            max_heap_mb: None,
+        }
+    }
+}
+
+impl MacroGrant {
+    pub fn allows(&self, capability: MacroCapability) -> bool {
+        self.capabilities.contains(&capability)
+            || (capability == MacroCapability::HttpFetch
+                && self.capabilities.contains(&MacroCapability::HostAccess))
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.timeout == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The timeout of a macro must be at least 1 second"),
+            });
+        }
+        if self
+            .max_heap_mb
+            .is_some_and(|max_heap_mb| max_heap_mb < MIN_HEAP_MB)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The heap of a macro must be at least {MIN_HEAP_MB} MB"),
+            });
+        }
+        if let Some(host) = self
+            .http_allowlist
+            .iter()
+            .find(|host| host.is_empty() || host.contains(['/', '*', ' ']))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Invalid host \"{host}\", expected a host name with an optional port"
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// The Deno permissions of the macro, which is all of them with `HostAccess`
+    pub fn permissions(&self) -> Result<Permissions, Error> {
+        if self.allows(MacroCapability::HostAccess) {
+            return Ok(Permissions::allow_all());
+        }
+        let allow_net =
+            match self.allows(MacroCapability::HttpFetch) && !self.http_allowlist.is_empty() {
+                true => Some(self.http_allowlist.clone()),
+                false => None,
+            };
+        Ok(Permissions::from_options(&PermissionsOptions {
+            allow_net,
+            prompt: false,
+            ..Default::default()
+        })
+        .map_err(|e| eyre!("Failed to set up the permissions of the macro : {e}"))?)
+    }
+}
+
+/// Fail an op unless the macro running it was granted `capability`.
+///
+/// Macros spawned without a grant, like the core macros of generic instances, can do anything.
+pub fn require_capability(
+    state: &OpState,
+    capability: MacroCapability,
+) -> Result<(), deno_core::anyhow::Error> {
+    match state.try_borrow::<MacroGrant>() {
+        Some(grant) if !grant.allows(capability) => Err(deno_core::anyhow::anyhow!(
+            "This macro is not allowed to use {capability:?}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{MacroCapability, MacroGrant};
+
+    #[test]
+    fn test_macro_grant() {
+        let default = MacroGrant::default();
+        assert!(default.validate().is_ok());
+        assert!(default.allows(MacroCapability::HttpFetch));
+
+        let sandboxed = MacroGrant {
+            capabilities: HashSet::from([MacroCapability::SendCommand]),
+            http_allowlist: vec!["api.mojang.com".to_string()],
+            timeout: Some(60),
+            max_heap_mb: Some(64),
+        };
+        assert!(sandboxed.validate().is_ok());
+        assert!(sandboxed.allows(MacroCapability::SendCommand));
+        assert!(!sandboxed.allows(MacroCapability::ReadEvents));
+        assert!(!sandboxed.allows(MacroCapability::HttpFetch));
+
+        for invalid in [
+            MacroGrant {
+                timeout: Some(0),
+                ..sandboxed.clone()
+            },
+            MacroGrant {
+                max_heap_mb: Some(1),
+                ..sandboxed.clone()
+            },
+            MacroGrant {
+                http_allowlist: vec!["https://api.mojang.com/".to_string()],
+                ..sandboxed.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err());
+        }
+    }
+}
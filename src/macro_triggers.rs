@@ -0,0 +1,656 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    console_matchers::ConsoleMatchKind,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    global_settings::GlobalSettings,
+    instance_map::InstanceMap,
+    schedule::WallClockSchedule,
+    traits::{
+        t_macro::TMacro,
+        t_player::TPlayer,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    util::rand_alphanumeric,
+};
+
+/// the shortest period of a timer trigger, in seconds
+pub const MIN_TRIGGER_PERIOD: u64 = 10;
+/// a trigger does not fire again this soon, so a macro that prints what it matches can't loop
+const TRIGGER_COOLDOWN: Duration = Duration::from_secs(1);
+/// how often timer and schedule triggers are checked
+const TIMER_RESOLUTION: Duration = Duration::from_secs(1);
+
+/// What makes a macro run
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum TriggerCondition {
+    /// the macro gets the name of the player as its argument
+    PlayerJoined,
+    /// the macro gets the name of the player as its argument
+    PlayerLeft,
+    InstanceStarted,
+    InstanceStopped,
+    /// a line of console output matches `pattern`, the macro gets the match and its capture
+    /// groups as arguments
+    ConsoleMatch {
+        pattern: String,
+    },
+    /// a console matcher of `kind` matches, only the one named `matcher` if set. The macro gets
+    /// the line and the capture groups of the match as arguments
+    ConsoleEvent {
+        kind: ConsoleMatchKind,
+        matcher: Option<String>,
+    },
+    /// every `period` seconds while the instance is running
+    Timer {
+        period: u64,
+    },
+    /// at the times of `schedule` while the instance is running, e.g. a daily restart
+    Schedule {
+        schedule: WallClockSchedule,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct MacroTrigger {
+    pub id: String,
+    pub macro_name: String,
+    pub condition: TriggerCondition,
+    pub enabled: bool,
+    pub creation_time: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct MacroTriggerConfig {
+    pub macro_name: String,
+    pub condition: TriggerCondition,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl MacroTriggerConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.macro_name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Macro name cannot be empty"),
+            });
+        }
+        match &self.condition {
+            TriggerCondition::ConsoleMatch { pattern } => {
+                Regex::new(pattern).map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid pattern : {e}"),
+                })?;
+            }
+            TriggerCondition::Timer { period } if *period < MIN_TRIGGER_PERIOD => {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("A timer must be at least {MIN_TRIGGER_PERIOD} seconds"),
+                });
+            }
+            TriggerCondition::Schedule { schedule } => schedule.validate()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Macro triggers of every instance, persisted in the stores directory
+pub struct MacroTriggers {
+    path_to_store: PathBuf,
+    triggers: HashMap<InstanceUuid, Vec<MacroTrigger>>,
+}
+
+impl MacroTriggers {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            triggers: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.triggers = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read macro triggers file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.triggers = serde_json::from_slice(&content).context(format!(
+            "Failed to parse macro triggers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.triggers)
+                .context("Failed to serialize macro triggers")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write macro triggers file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn triggers(&self, instance_uuid: &InstanceUuid) -> &[MacroTrigger] {
+        self.triggers
+            .get(instance_uuid)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn not_found() -> Error {
+        Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Macro trigger not found"),
+        }
+    }
+
+    pub async fn add_trigger(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        config: MacroTriggerConfig,
+    ) -> Result<MacroTrigger, Error> {
+        config.validate()?;
+        let trigger = MacroTrigger {
+            id: rand_alphanumeric(16),
+            macro_name: config.macro_name,
+            condition: config.condition,
+            enabled: config.enabled,
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        let old_triggers = self.triggers.clone();
+        self.triggers
+            .entry(instance_uuid.clone())
+            .or_default()
+            .push(trigger.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.triggers = old_triggers;
+            return Err(e);
+        }
+        Ok(trigger)
+    }
+
+    pub async fn update_trigger(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+        config: MacroTriggerConfig,
+    ) -> Result<MacroTrigger, Error> {
+        config.validate()?;
+        let old_triggers = self.triggers.clone();
+        let trigger = self
+            .triggers
+            .get_mut(instance_uuid)
+            .and_then(|triggers| triggers.iter_mut().find(|trigger| trigger.id == id))
+            .ok_or_else(Self::not_found)?;
+        trigger.macro_name = config.macro_name;
+        trigger.condition = config.condition;
+        trigger.enabled = config.enabled;
+        let trigger = trigger.clone();
+        if let Err(e) = self.write_to_file().await {
+            self.triggers = old_triggers;
+            return Err(e);
+        }
+        Ok(trigger)
+    }
+
+    pub async fn remove_trigger(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        id: &str,
+    ) -> Result<(), Error> {
+        let old_triggers = self.triggers.clone();
+        let triggers = self
+            .triggers
+            .get_mut(instance_uuid)
+            .ok_or_else(Self::not_found)?;
+        let index = triggers
+            .iter()
+            .position(|trigger| trigger.id == id)
+            .ok_or_else(Self::not_found)?;
+        triggers.remove(index);
+        if triggers.is_empty() {
+            self.triggers.remove(instance_uuid);
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.triggers = old_triggers;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Forget the triggers of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.triggers.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.triggers.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The arguments to run the macro of `trigger` with if `event` fires it
+fn fired_by(
+    trigger: &MacroTrigger,
+    event: &InstanceEvent,
+    regexes: &mut HashMap<String, Regex>,
+) -> Vec<Vec<String>> {
+    match (&trigger.condition, &event.instance_event_inner) {
+        (
+            TriggerCondition::PlayerJoined,
+            InstanceEventInner::PlayerChange { players_joined, .. },
+        ) => players_joined
+            .iter()
+            .map(|player| vec![player.get_name()])
+            .collect(),
+        (TriggerCondition::PlayerLeft, InstanceEventInner::PlayerChange { players_left, .. }) => {
+            players_left
+                .iter()
+                .map(|player| vec![player.get_name()])
+                .collect()
+        }
+        (
+            TriggerCondition::InstanceStarted,
+            InstanceEventInner::StateTransition { to: State::Running },
+        )
+        | (
+            TriggerCondition::InstanceStopped,
+            InstanceEventInner::StateTransition {
+                to: State::Stopped | State::Crashed,
+            },
+        ) => vec![Vec::new()],
+        (
+            TriggerCondition::ConsoleMatch { pattern },
+            InstanceEventInner::InstanceOutput { message },
+        ) => {
+            if !regexes.contains_key(pattern) {
+                match Regex::new(pattern) {
+                    Ok(regex) => {
+                        regexes.insert(pattern.clone(), regex);
+                    }
+                    Err(_) => return Vec::new(),
+                }
+            }
+            match regexes[pattern].captures(message) {
+                Ok(Some(captures)) => vec![captures
+                    .iter()
+                    .map(|group| {
+                        group
+                            .map(|group| group.as_str().to_string())
+                            .unwrap_or_default()
+                    })
+                    .collect()],
+                _ => Vec::new(),
+            }
+        }
+        (
+            TriggerCondition::ConsoleEvent { kind, matcher },
+            InstanceEventInner::ConsoleMatch {
+                matcher: matched_by,
+                kind: matched_kind,
+                line,
+                groups,
+                ..
+            },
+        ) if kind == matched_kind
+            && (matcher.is_none() || matcher.as_ref() == Some(matched_by)) =>
+        {
+            vec![std::iter::once(line.clone())
+                .chain(groups.iter().cloned())
+                .collect()]
+        }
+        _ => Vec::new(),
+    }
+}
+
+async fn run_triggered_macro(
+    instances: &InstanceMap,
+    instance_uuid: &InstanceUuid,
+    trigger: &MacroTrigger,
+    args: Vec<String>,
+) {
+    let mut instance = match instances.get(instance_uuid) {
+        Some(instance) => instance,
+        None => return,
+    };
+    if let Err(e) = instance
+        .run_macro(&trigger.macro_name, args, CausedBy::System)
+        .await
+    {
+        error!(
+            "Failed to run macro {} triggered for {instance_uuid} : {e}",
+            trigger.macro_name
+        );
+    }
+}
+
+/// Runs the macros of triggers as the events they wait for happen, and those of timers as they
+/// come due
+pub async fn macro_trigger_task(
+    mut event_receiver: Receiver<Event>,
+    macro_triggers: Arc<Mutex<MacroTriggers>>,
+    instances: InstanceMap,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+) {
+    let mut regexes: HashMap<String, Regex> = HashMap::new();
+    let mut last_fired: HashMap<String, Instant> = HashMap::new();
+    let mut last_scheduled: HashMap<String, DateTime<Utc>> = HashMap::new();
+    let mut timer = tokio::time::interval(TIMER_RESOLUTION);
+    loop {
+        tokio::select! {
+            event = event_receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        warn!("Macro trigger task lagged");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let instance_event = match &event.event_inner {
+                    EventInner::InstanceEvent(instance_event) => instance_event,
+                    _ => continue,
+                };
+                // macros run by triggers can't fire other triggers through their own events
+                if matches!(event.caused_by, CausedBy::Macro { .. }) {
+                    continue;
+                }
+                let triggers = macro_triggers
+                    .lock()
+                    .await
+                    .triggers(&instance_event.instance_uuid)
+                    .to_vec();
+                for trigger in triggers.iter().filter(|trigger| trigger.enabled) {
+                    if last_fired
+                        .get(&trigger.id)
+                        .is_some_and(|last| last.elapsed() < TRIGGER_COOLDOWN)
+                    {
+                        continue;
+                    }
+                    let runs = fired_by(trigger, instance_event, &mut regexes);
+                    if runs.is_empty() {
+                        continue;
+                    }
+                    last_fired.insert(trigger.id.clone(), Instant::now());
+                    for args in runs {
+                        run_triggered_macro(&instances, &instance_event.instance_uuid, trigger, args)
+                            .await;
+                    }
+                }
+            }
+            _ = timer.tick() => {
+                let timers: Vec<(InstanceUuid, MacroTrigger)> = macro_triggers
+                    .lock()
+                    .await
+                    .triggers
+                    .iter()
+                    .flat_map(|(instance_uuid, triggers)| {
+                        triggers.iter().filter_map(|trigger| match trigger.condition {
+                            TriggerCondition::Timer { .. } | TriggerCondition::Schedule { .. }
+                                if trigger.enabled =>
+                            {
+                                Some((instance_uuid.clone(), trigger.clone()))
+                            }
+                            _ => None,
+                        })
+                    })
+                    .collect();
+                let default_timezone = global_settings.lock().await.default_timezone();
+                for (instance_uuid, trigger) in timers {
+                    let is_due = match &trigger.condition {
+                        TriggerCondition::Timer { period } => {
+                            // timers start counting once they are first seen
+                            let last = *last_fired
+                                .entry(trigger.id.clone())
+                                .or_insert_with(Instant::now);
+                            let is_due = last.elapsed() >= Duration::from_secs(*period);
+                            if is_due {
+                                last_fired.insert(trigger.id.clone(), Instant::now());
+                            }
+                            is_due
+                        }
+                        TriggerCondition::Schedule { schedule } => {
+                            // schedules don't catch up on runs from before they were seen
+                            let now = Utc::now();
+                            let last = *last_scheduled.entry(trigger.id.clone()).or_insert(now);
+                            match schedule.is_due(last, now, default_timezone) {
+                                Ok(is_due) => {
+                                    if is_due {
+                                        last_scheduled.insert(trigger.id.clone(), now);
+                                    }
+                                    is_due
+                                }
+                                Err(e) => {
+                                    error!("Invalid schedule of trigger {} : {e}", trigger.id);
+                                    false
+                                }
+                            }
+                        }
+                        _ => false,
+                    };
+                    if !is_due {
+                        continue;
+                    }
+                    let is_running = match instances.get(&instance_uuid) {
+                        Some(instance) => instance.state().await == State::Running,
+                        None => false,
+                    };
+                    if is_running {
+                        run_triggered_macro(&instances, &instance_uuid, &trigger, Vec::new()).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{fired_by, MacroTrigger, MacroTriggerConfig, MacroTriggers, TriggerCondition};
+    use crate::{
+        console_matchers::{ConsoleMatch, ConsoleMatchKind},
+        events::{InstanceEvent, InstanceEventInner},
+        schedule::WallClockSchedule,
+        traits::t_server::State,
+    };
+
+    fn trigger(condition: TriggerCondition) -> MacroTrigger {
+        MacroTrigger {
+            id: "trigger".to_string(),
+            macro_name: "announce".to_string(),
+            condition,
+            enabled: true,
+            creation_time: 0,
+        }
+    }
+
+    fn instance_event(instance_event_inner: InstanceEventInner) -> InstanceEvent {
+        InstanceEvent {
+            instance_uuid: "INSTANCE_a".to_string().into(),
+            instance_name: "a".to_string(),
+            instance_event_inner,
+        }
+    }
+
+    #[test]
+    fn test_fired_by() {
+        let mut regexes = HashMap::new();
+        let console = trigger(TriggerCondition::ConsoleMatch {
+            pattern: r"(\w+) has made the advancement \[(.+)\]".to_string(),
+        });
+        let output = |message: &str| {
+            instance_event(InstanceEventInner::InstanceOutput {
+                message: message.to_string(),
+            })
+        };
+        assert_eq!(
+            fired_by(
+                &console,
+                &output("Steve has made the advancement [Stone Age]"),
+                &mut regexes
+            ),
+            vec![vec![
+                "Steve has made the advancement [Stone Age]".to_string(),
+                "Steve".to_string(),
+                "Stone Age".to_string()
+            ]]
+        );
+        assert!(fired_by(&console, &output("Steve joined the game"), &mut regexes).is_empty());
+
+        let joined_event =
+            ConsoleMatch::builtin(ConsoleMatchKind::PlayerJoin, &[("player", "Steve")]).into_event(
+                "INSTANCE_a".to_string().into(),
+                "a".to_string(),
+                "Steve joined the game",
+            );
+        let joined_event = match joined_event.event_inner {
+            crate::events::EventInner::InstanceEvent(instance_event) => instance_event,
+            _ => unreachable!(),
+        };
+        let on_join = trigger(TriggerCondition::ConsoleEvent {
+            kind: ConsoleMatchKind::PlayerJoin,
+            matcher: None,
+        });
+        assert_eq!(
+            fired_by(&on_join, &joined_event, &mut regexes),
+            vec![vec![
+                "Steve joined the game".to_string(),
+                "Steve".to_string()
+            ]]
+        );
+        let on_other_matcher = trigger(TriggerCondition::ConsoleEvent {
+            kind: ConsoleMatchKind::PlayerJoin,
+            matcher: Some("vip_join".to_string()),
+        });
+        assert!(fired_by(&on_other_matcher, &joined_event, &mut regexes).is_empty());
+
+        let started = trigger(TriggerCondition::InstanceStarted);
+        assert_eq!(
+            fired_by(
+                &started,
+                &instance_event(InstanceEventInner::StateTransition { to: State::Running }),
+                &mut regexes
+            ),
+            vec![Vec::<String>::new()]
+        );
+        assert!(fired_by(
+            &started,
+            &instance_event(InstanceEventInner::StateTransition { to: State::Stopped }),
+            &mut regexes
+        )
+        .is_empty());
+
+        let joined = trigger(TriggerCondition::PlayerJoined);
+        assert!(fired_by(
+            &joined,
+            &instance_event(InstanceEventInner::PlayerChange {
+                player_list: HashSet::new(),
+                players_joined: HashSet::new(),
+                players_left: HashSet::new(),
+                joined_from: HashMap::new(),
+            }),
+            &mut regexes
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_macro_triggers_store() {
+        let temp_dir = tempdir::TempDir::new("test_macro_triggers").unwrap();
+        let path = temp_dir.path().join("macro_triggers.json");
+        let instance_uuid = "INSTANCE_a".to_string().into();
+        let mut macro_triggers = MacroTriggers::new(path.clone());
+        assert!(macro_triggers
+            .add_trigger(
+                &instance_uuid,
+                MacroTriggerConfig {
+                    macro_name: "announce".to_string(),
+                    condition: TriggerCondition::Timer { period: 1 },
+                    enabled: true,
+                },
+            )
+            .await
+            .is_err());
+        assert!(macro_triggers
+            .add_trigger(
+                &instance_uuid,
+                MacroTriggerConfig {
+                    macro_name: "restart".to_string(),
+                    condition: TriggerCondition::Schedule {
+                        schedule: WallClockSchedule {
+                            time: "04:00".to_string(),
+                            weekdays: Vec::new(),
+                            timezone: Some("Nowhere/Special".to_string()),
+                        },
+                    },
+                    enabled: true,
+                },
+            )
+            .await
+            .is_err());
+        let trigger = macro_triggers
+            .add_trigger(
+                &instance_uuid,
+                MacroTriggerConfig {
+                    macro_name: "announce".to_string(),
+                    condition: TriggerCondition::Timer { period: 600 },
+                    enabled: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut reloaded = MacroTriggers::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.triggers(&instance_uuid), &[trigger.clone()]);
+
+        reloaded
+            .remove_trigger(&instance_uuid, &trigger.id)
+            .await
+            .unwrap();
+        assert!(reloaded.triggers(&instance_uuid).is_empty());
+        assert!(reloaded
+            .remove_trigger(&instance_uuid, &trigger.id)
+            .await
+            .is_err());
+    }
+}
@@ -7,4 +7,9 @@ use lodestone_core::Args;
 async fn main() {
     let args = Args::parse();
     lodestone_core::run(args).await.0.await;
+    if lodestone_core::restart_requested() {
+        let e = lodestone_core::restart_process();
+        eprintln!("Failed to restart Lodestone Core : {e}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,318 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    traits::t_server::{MonitorReport, State as InstanceState},
+    types::InstanceUuid,
+};
+
+/// Counts the API requests served, by method and status code
+#[derive(Clone, Default)]
+pub struct ApiRequestCounter {
+    requests: Arc<Mutex<BTreeMap<(String, u16), u64>>>,
+}
+
+impl ApiRequestCounter {
+    pub fn record(&self, method: &Method, status: u16) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), status))
+            .or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<(String, u16), u64> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+pub async fn count_api_requests<B>(
+    State(counter): State<ApiRequestCounter>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().clone();
+    let response = next.run(request).await;
+    counter.record(&method, response.status().as_u16());
+    response
+}
+
+pub struct InstanceMetrics {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub state: InstanceState,
+    /// the latest report of the monitor task
+    pub report: Option<MonitorReport>,
+    /// `None` for instances that don't track players
+    pub player_count: Option<u32>,
+    /// milliseconds the current run took to be ready, `None` until it is
+    pub startup_duration: Option<u64>,
+}
+
+pub struct CoreMetrics {
+    pub instances: Vec<InstanceMetrics>,
+    pub api_requests: BTreeMap<(String, u16), u64>,
+    pub events_sent: u64,
+    pub events_queued: usize,
+    pub event_subscribers: usize,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes metrics in the Prometheus text exposition format
+#[derive(Default)]
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    fn metric<'a>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (Vec<(&'a str, String)>, f64)>,
+    ) {
+        let _ = writeln!(self.out, "# HELP {name} {help}");
+        let _ = writeln!(self.out, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            if labels.is_empty() {
+                let _ = writeln!(self.out, "{name} {value}");
+            } else {
+                let _ = writeln!(self.out, "{name}{{{labels}}} {value}");
+            }
+        }
+    }
+}
+
+fn instance_labels(instance: &InstanceMetrics) -> Vec<(&'static str, String)> {
+    vec![
+        ("instance_uuid", instance.uuid.to_string()),
+        ("instance_name", instance.name.clone()),
+    ]
+}
+
+/// Per instance samples of the values `f` returns something for
+fn instance_samples(
+    instances: &[InstanceMetrics],
+    f: impl Fn(&InstanceMetrics) -> Option<f64>,
+) -> Vec<(Vec<(&'static str, String)>, f64)> {
+    instances
+        .iter()
+        .filter_map(|instance| f(instance).map(|value| (instance_labels(instance), value)))
+        .collect()
+}
+
+pub fn render_metrics(metrics: &CoreMetrics) -> String {
+    let mut writer = MetricsWriter::default();
+    let instances = &metrics.instances;
+
+    let mut state_samples = Vec::new();
+    for instance in instances {
+        for state in [
+            InstanceState::Starting,
+            InstanceState::Running,
+            InstanceState::Stopping,
+            InstanceState::Stopped,
+            InstanceState::Error,
+            InstanceState::Crashed,
+            InstanceState::Installing,
+        ] {
+            let mut labels = instance_labels(instance);
+            labels.push(("state", state.to_string()));
+            state_samples.push((labels, (instance.state == state) as u8 as f64));
+        }
+    }
+    writer.metric(
+        "lodestone_instance_state",
+        "gauge",
+        "Whether the instance is in the given state",
+        state_samples,
+    );
+    writer.metric(
+        "lodestone_instance_cpu_usage_percent",
+        "gauge",
+        "CPU usage of the instance, 100 per fully used core",
+        instance_samples(instances, |i| {
+            i.report.as_ref()?.cpu_usage.map(|cpu| cpu as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_memory_usage_bytes",
+        "gauge",
+        "Memory used by the instance",
+        instance_samples(instances, |i| {
+            i.report.as_ref()?.memory_usage.map(|memory| memory as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_disk_read_bytes_total",
+        "counter",
+        "Bytes read from disk by the instance process",
+        instance_samples(instances, |i| {
+            i.report
+                .as_ref()?
+                .disk_usage
+                .as_ref()
+                .map(|disk| disk.total_read_bytes as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_disk_written_bytes_total",
+        "counter",
+        "Bytes written to disk by the instance process",
+        instance_samples(instances, |i| {
+            i.report
+                .as_ref()?
+                .disk_usage
+                .as_ref()
+                .map(|disk| disk.total_written_bytes as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_network_sent_bytes_total",
+        "counter",
+        "Bytes sent over the instance's connections since it started",
+        instance_samples(instances, |i| {
+            i.report
+                .as_ref()?
+                .network_usage
+                .as_ref()
+                .map(|network| network.total_bytes_sent as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_network_received_bytes_total",
+        "counter",
+        "Bytes received over the instance's connections since it started",
+        instance_samples(instances, |i| {
+            i.report
+                .as_ref()?
+                .network_usage
+                .as_ref()
+                .map(|network| network.total_bytes_received as f64)
+        }),
+    );
+    writer.metric(
+        "lodestone_instance_players_online",
+        "gauge",
+        "Players currently online",
+        instance_samples(instances, |i| i.player_count.map(|count| count as f64)),
+    );
+    writer.metric(
+        "lodestone_instance_startup_duration_seconds",
+        "gauge",
+        "Time the current run of the instance took to be ready",
+        instance_samples(instances, |i| {
+            i.startup_duration.map(|duration| duration as f64 / 1000.0)
+        }),
+    );
+    writer.metric(
+        "lodestone_api_requests_total",
+        "counter",
+        "API requests served",
+        metrics
+            .api_requests
+            .iter()
+            .map(|((method, status), count)| {
+                (
+                    vec![("method", method.clone()), ("status", status.to_string())],
+                    *count as f64,
+                )
+            }),
+    );
+    writer.metric(
+        "lodestone_events_sent_total",
+        "counter",
+        "Events sent through the event broadcaster",
+        [(vec![], metrics.events_sent as f64)],
+    );
+    writer.metric(
+        "lodestone_events_queued",
+        "gauge",
+        "Events the slowest event subscriber has yet to receive",
+        [(vec![], metrics.events_queued as f64)],
+    );
+    writer.metric(
+        "lodestone_event_subscribers",
+        "gauge",
+        "Subscribers of the event broadcaster",
+        [(vec![], metrics.event_subscribers as f64)],
+    );
+    writer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::traits::t_server::{MonitorReport, State};
+
+    use super::{render_metrics, CoreMetrics, InstanceMetrics};
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = CoreMetrics {
+            instances: vec![
+                InstanceMetrics {
+                    uuid: "a".to_string().into(),
+                    name: "survival \"main\"".to_string(),
+                    state: State::Running,
+                    report: Some(MonitorReport {
+                        memory_usage: Some(1024),
+                        cpu_usage: Some(12.5),
+                        ..Default::default()
+                    }),
+                    player_count: Some(3),
+                    startup_duration: Some(12500),
+                },
+                InstanceMetrics {
+                    uuid: "b".to_string().into(),
+                    name: "proxy".to_string(),
+                    state: State::Stopped,
+                    report: None,
+                    player_count: None,
+                    startup_duration: None,
+                },
+            ],
+            api_requests: BTreeMap::from([(("GET".to_string(), 200), 7)]),
+            events_sent: 42,
+            events_queued: 2,
+            event_subscribers: 4,
+        };
+        let out = render_metrics(&metrics);
+        assert!(out.contains(
+            "lodestone_instance_state{instance_uuid=\"a\",instance_name=\"survival \\\"main\\\"\",state=\"Running\"} 1"
+        ));
+        assert!(out.contains(
+            "lodestone_instance_state{instance_uuid=\"b\",instance_name=\"proxy\",state=\"Running\"} 0"
+        ));
+        assert!(out.contains("lodestone_instance_cpu_usage_percent{instance_uuid=\"a\",instance_name=\"survival \\\"main\\\"\"} 12.5"));
+        assert!(!out.contains("lodestone_instance_memory_usage_bytes{instance_uuid=\"b\""));
+        assert!(out.contains("lodestone_instance_startup_duration_seconds{instance_uuid=\"a\",instance_name=\"survival \\\"main\\\"\"} 12.5"));
+        assert!(out.contains("lodestone_api_requests_total{method=\"GET\",status=\"200\"} 7"));
+        assert!(out.contains("lodestone_events_sent_total 42"));
+        assert!(out.contains("# TYPE lodestone_events_queued gauge"));
+    }
+}
@@ -1,5 +1,6 @@
 mod v042_to_v044;
 pub mod v043_to_v044;
+pub mod versioned;
 
 use std::path::{Path, PathBuf};
 
@@ -99,7 +100,7 @@ fn determine_legacy_version(lodestone_path: &Path) -> Result<Option<LegacyVersio
 /// If it is, then we are at v0.4.3 and thus migrate to 0.4.4 by creating the version file
 /// and rewrite all the `.lodestone_config` files to remove the `lodestone_version` field
 ///
-///
+/// From there on the format of each file is versioned on its own, see `versioned`
 
 pub fn migrate(lodestone_path: &Path) -> Result<(), Error> {
     let legacy_version = determine_legacy_version(lodestone_path)?;
@@ -118,15 +119,23 @@ pub fn migrate(lodestone_path: &Path) -> Result<(), Error> {
         }
     }
     let version_path = lodestone_path.join(".lodestone_metadata.json");
+    let store_versions = std::fs::read_to_string(&version_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<LodestoneMetadata>(&content).ok())
+        .map(|metadata| metadata.store_versions)
+        .unwrap_or_default();
+    let backup_dir = versioned::backup_dir(lodestone_path);
+    let store_versions = versioned::migrate_stores(lodestone_path, &store_versions, &backup_dir);
+    versioned::migrate_instances(lodestone_path, &backup_dir);
     let version_file =
         std::fs::File::create(version_path).context("Failed to create version file")?;
     serde_json::to_writer_pretty(
         version_file,
         &LodestoneMetadata {
             semver: VERSION.with(|v| v.clone()),
+            store_versions,
         },
     )
     .context("Failed to write version file")?;
     Ok(())
 }
-
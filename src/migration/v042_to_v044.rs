@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use color_eyre::eyre::Context;
 use serde_json::{json, Value};
@@ -25,6 +25,16 @@ impl From<RestoreConfigV042> for RestoreConfig {
             jre_major_version: config.jre_major_version,
             has_started: config.has_started,
             java_cmd: None,
+            resource_limits: Default::default(),
+            crash_restart_policy: Default::default(),
+            display: Default::default(),
+            public_address: Default::default(),
+            launch_overrides: Default::default(),
+            launch_target: None,
+            macro_grants: HashMap::new(),
+            console_profile: Default::default(),
+            control_channel: Default::default(),
+            scheduling: Default::default(),
         }
     }
 }
@@ -0,0 +1,392 @@
+//! Ordered migrations of the json files lodestone keeps its state in, so a change to their format
+//! is a migration rather than a file that no longer parses.
+//!
+//! Every file is stamped with the schema version it was written with, starting at 1. The
+//! `.lodestone_config` of an instance carries its own `schema_version` as instances move between
+//! cores, the stores are bare maps and lists so theirs are kept in `.lodestone_metadata.json`. A
+//! file without a stamp was written before stamping and is at version 1.
+//!
+//! Migrations run at startup before anything is loaded, each file is copied to
+//! `migration_backups/<unix timestamp>/` before it is rewritten.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde_json::Value;
+use tracing::{error, info, warn};
+
+use crate::{error::Error, storage_roots};
+
+/// A change of the format of a file, from one schema version to the next
+pub struct Migration {
+    pub description: &'static str,
+    pub migrate: fn(&mut Value) -> Result<(), Error>,
+}
+
+/// A json file and the migrations of its format, in order
+pub struct VersionedFile {
+    /// path relative to the lodestone path, the file name for `.lodestone_config`
+    pub path: &'static str,
+    /// the migration from version `n` to `n + 1` is at index `n - 1`
+    pub migrations: &'static [Migration],
+}
+
+impl VersionedFile {
+    pub const fn current_version(&self) -> u32 {
+        self.migrations.len() as u32 + 1
+    }
+
+    /// Bring `value` from `from_version` to the current version
+    pub fn migrate(&self, value: &mut Value, from_version: u32) -> Result<(), Error> {
+        if from_version > self.current_version() {
+            return Err(eyre!(
+                "{} is at schema version {from_version}, written by a newer version of lodestone which supports up to {}",
+                self.path,
+                self.current_version()
+            )
+            .into());
+        }
+        for (version, migration) in self
+            .migrations
+            .iter()
+            .enumerate()
+            .map(|(i, migration)| (i as u32 + 1, migration))
+            .skip_while(|(version, _)| *version < from_version)
+        {
+            info!(
+                "Migrating {} from schema version {version} to {}: {}",
+                self.path,
+                version + 1,
+                migration.description
+            );
+            (migration.migrate)(value).context(format!(
+                "Failed to migrate {} from schema version {version}",
+                self.path
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+pub const DOT_LODESTONE_CONFIG: VersionedFile = VersionedFile {
+    path: ".lodestone_config",
+    migrations: &[],
+};
+
+/// The stores holding state that can't be rebuilt, caches are dropped rather than migrated
+pub const STORES: &[VersionedFile] = &[
+    VersionedFile {
+        path: "global_settings.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/users.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/archived_instances.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/backup_destinations.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/command_filters.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/command_queues.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/console_matchers.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/console_profiles.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/console_snippets.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/fs_locations.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/instance_groups.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/instance_sync.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/instance_webhooks.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/macro_triggers.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/network_policies.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/notifications.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/peers.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/player_database.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/start_dependencies.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/status_page.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/storage_roots.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/suspicious_activity.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/upload_sessions.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/usage_ledger.json",
+        migrations: &[],
+    },
+    VersionedFile {
+        path: "stores/user_quotas.json",
+        migrations: &[],
+    },
+];
+
+/// Where the files rewritten by the migrations of this run are backed up
+pub fn backup_dir(lodestone_path: &Path) -> PathBuf {
+    lodestone_path
+        .join("migration_backups")
+        .join(chrono::Utc::now().timestamp().to_string())
+}
+
+fn back_up(lodestone_path: &Path, path: &Path, backup_dir: &Path) -> Result<(), Error> {
+    let backup_path = backup_dir.join(path.strip_prefix(lodestone_path).unwrap_or(path));
+    if let Some(parent) = backup_path.parent() {
+        std::fs::create_dir_all(parent).context(format!(
+            "Failed to create migration backup directory at {}",
+            parent.display()
+        ))?;
+    }
+    std::fs::copy(path, &backup_path).context(format!(
+        "Failed to back up {} before migrating it",
+        path.display()
+    ))?;
+    Ok(())
+}
+
+fn read_json(path: &Path) -> Result<Value, Error> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    Ok(serde_json::from_str(&content).context(format!("Failed to parse {}", path.display()))?)
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<(), Error> {
+    std::fs::write(
+        path,
+        serde_json::to_string_pretty(value)
+            .context(format!("Failed to serialize {}", path.display()))?,
+    )
+    .context(format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Migrate the stores that are behind, `store_versions` being the versions stamped in the
+/// metadata file. Returns the versions to stamp
+pub fn migrate_stores(
+    lodestone_path: &Path,
+    store_versions: &BTreeMap<String, u32>,
+    backup_dir: &Path,
+) -> BTreeMap<String, u32> {
+    let mut stamped = store_versions.clone();
+    for store in STORES {
+        let path = lodestone_path.join(store.path);
+        let from_version = store_versions.get(store.path).copied().unwrap_or(1);
+        // a store that doesn't exist yet will be written in the current format
+        if !path.is_file() || from_version == store.current_version() {
+            stamped.insert(store.path.to_string(), store.current_version());
+            continue;
+        }
+        let result = read_json(&path).and_then(|mut value| {
+            store.migrate(&mut value, from_version)?;
+            back_up(lodestone_path, &path, backup_dir)?;
+            write_json(&path, &value)
+        });
+        match result {
+            Ok(()) => {
+                stamped.insert(store.path.to_string(), store.current_version());
+            }
+            // left as is, the migration is tried again on the next start
+            Err(e) => error!("Failed to migrate {} : {e}", path.display()),
+        }
+    }
+    stamped
+}
+
+/// Migrate the `.lodestone_config` of an instance if it is behind, e.g. one restored from an old
+/// backup
+pub fn migrate_dot_lodestone_config(
+    lodestone_path: &Path,
+    path_to_instance: &Path,
+    backup_dir: &Path,
+) -> Result<(), Error> {
+    let path = path_to_instance.join(DOT_LODESTONE_CONFIG.path);
+    let mut value = read_json(&path)?;
+    let from_version = value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1) as u32;
+    if from_version == DOT_LODESTONE_CONFIG.current_version() {
+        return Ok(());
+    }
+    DOT_LODESTONE_CONFIG.migrate(&mut value, from_version)?;
+    let object = value.as_object_mut().ok_or_else(|| {
+        Error::from(eyre!(
+            "{} is not a json object after migrating it",
+            path.display()
+        ))
+    })?;
+    object.insert(
+        "schema_version".to_string(),
+        DOT_LODESTONE_CONFIG.current_version().into(),
+    );
+    back_up(lodestone_path, &path, backup_dir)?;
+    write_json(&path, &value)
+}
+
+/// Migrate the `.lodestone_config` of every instance in every storage root, the ones that fail
+/// are logged and left for the restore to report
+pub fn migrate_instances(lodestone_path: &Path, backup_dir: &Path) {
+    let roots = std::iter::once(lodestone_path.join("instances")).chain(
+        storage_roots::read_registered_paths(&lodestone_path.join("stores/storage_roots.json")),
+    );
+    for path in roots
+        .filter_map(|root| root.read_dir().ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        // hidden directories are staging areas, e.g. for backup restores
+        if path
+            .file_name()
+            .map_or(true, |name| name.to_string_lossy().starts_with('.'))
+            || !path.join(DOT_LODESTONE_CONFIG.path).is_file()
+        {
+            continue;
+        }
+        if let Err(e) = migrate_dot_lodestone_config(lodestone_path, &path, backup_dir) {
+            warn!("Failed to migrate instance at {} : {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{Migration, VersionedFile};
+    use crate::error::Error;
+
+    fn rename_port(value: &mut Value) -> Result<(), Error> {
+        if let Some(object) = value.as_object_mut() {
+            if let Some(port) = object.remove("server_port") {
+                object.insert("port".to_string(), port);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_motd(value: &mut Value) -> Result<(), Error> {
+        value["motd"] = json!("A Minecraft Server");
+        Ok(())
+    }
+
+    const TEST_FILE: VersionedFile = VersionedFile {
+        path: "test.json",
+        migrations: &[
+            Migration {
+                description: "rename server_port to port",
+                migrate: rename_port,
+            },
+            Migration {
+                description: "add motd",
+                migrate: add_motd,
+            },
+        ],
+    };
+
+    #[test]
+    fn test_migrate() {
+        assert_eq!(TEST_FILE.current_version(), 3);
+
+        let mut value = json!({ "server_port": 25565 });
+        TEST_FILE.migrate(&mut value, 1).unwrap();
+        assert_eq!(
+            value,
+            json!({ "port": 25565, "motd": "A Minecraft Server" })
+        );
+
+        // only the migrations after the version of the file run
+        let mut value = json!({ "server_port": 25565 });
+        TEST_FILE.migrate(&mut value, 2).unwrap();
+        assert_eq!(
+            value,
+            json!({ "server_port": 25565, "motd": "A Minecraft Server" })
+        );
+
+        // a file from a newer lodestone isn't touched
+        let mut value = json!({ "port": 25565 });
+        assert!(TEST_FILE.migrate(&mut value, 4).is_err());
+        assert_eq!(value, json!({ "port": 25565 }));
+    }
+
+    #[test]
+    fn test_migrate_dot_lodestone_config() {
+        let lodestone_path = tempfile::tempdir().unwrap();
+        let path_to_instance = lodestone_path.path().join("instances/survival");
+        std::fs::create_dir_all(&path_to_instance).unwrap();
+        let config = json!({
+            "game_type": "MinecraftJava",
+            "uuid": "INSTANCE_survival",
+            "creation_time": 0,
+            "schema_version": 1,
+        });
+        std::fs::write(
+            path_to_instance.join(".lodestone_config"),
+            config.to_string(),
+        )
+        .unwrap();
+        let backup_dir = lodestone_path.path().join("migration_backups/0");
+        super::migrate_dot_lodestone_config(lodestone_path.path(), &path_to_instance, &backup_dir)
+            .unwrap();
+        // already current, nothing to back up
+        assert!(!backup_dir.exists());
+    }
+}
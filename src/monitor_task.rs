@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ringbuffer::{AllocRingBuffer, RingBufferWrite};
+use sqlx::SqlitePool;
+use tokio::{
+    sync::{broadcast::error::RecvError, Mutex},
+    task::JoinSet,
+};
+use tracing::{error, warn};
+
+use crate::{
+    disk_usage::DirectorySizes,
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    global_settings::GlobalSettings,
+    host_pressure::HostPressureWatcher,
+    instance_map::InstanceMap,
+    prelude::GameInstance,
+    traits::{
+        t_configurable::{LimitedResource, ResourceLimits, TConfigurable},
+        t_server::{MonitorReport, State, TServer},
+    },
+    types::{InstanceUuid, Snowflake},
+    usage_history::{prune_usage_rollups, write_minute_rollup, UsageHistory},
+};
+
+/// the interval doubles every idle round up to this
+const MAX_IDLE_INTERVAL: Duration = Duration::from_secs(30);
+/// disks are expensive to refresh
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// an instance that takes longer than this to report is skipped for the round
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(2);
+/// how often usage rollups past their retention are deleted
+const USAGE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// consecutive samples over a limit before it counts as exceeded, so short spikes are ignored
+const VIOLATION_SAMPLES: u32 = 5;
+
+/// Tracks how long each instance has been over its resource limits
+#[derive(Default)]
+pub struct ResourceLimitWatcher {
+    streaks: HashMap<(InstanceUuid, LimitedResource), u32>,
+}
+
+impl ResourceLimitWatcher {
+    /// The resources that just went over their limit for `VIOLATION_SAMPLES` samples in a row,
+    /// with the usage and the limit. A violation is reported once until usage drops under the limit
+    pub fn check(
+        &mut self,
+        uuid: &InstanceUuid,
+        limits: &ResourceLimits,
+        report: &MonitorReport,
+    ) -> Vec<(LimitedResource, f64, f64)> {
+        let mut ret = Vec::new();
+        let samples = [
+            (
+                LimitedResource::Memory,
+                report
+                    .memory_usage
+                    .map(|bytes| bytes as f64 / 1024.0 / 1024.0),
+                limits.memory_limit.map(|limit| limit as f64),
+            ),
+            (
+                LimitedResource::Cpu,
+                report.cpu_usage.map(f64::from),
+                limits.cpu_limit.map(f64::from),
+            ),
+        ];
+        for (resource, usage, limit) in samples {
+            let key = (uuid.clone(), resource);
+            match (usage, limit) {
+                (Some(usage), Some(limit)) if usage > limit => {
+                    let streak = self.streaks.entry(key).or_default();
+                    *streak += 1;
+                    if *streak == VIOLATION_SAMPLES {
+                        ret.push((resource, usage, limit));
+                    }
+                }
+                _ => {
+                    self.streaks.remove(&key);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Forget the streaks of an instance that is no longer running
+    pub fn reset(&mut self, uuid: &InstanceUuid) {
+        self.streaks
+            .retain(|(streak_uuid, _), _| streak_uuid != uuid);
+    }
+}
+
+/// Emit an event for a limit that was exceeded, and kill the instance if the limits ask for it
+async fn enforce_limit(
+    instance: &mut GameInstance,
+    limits: &ResourceLimits,
+    (resource, usage, limit): (LimitedResource, f64, f64),
+    event_broadcaster: &EventBroadcaster,
+) {
+    let name = instance.name().await;
+    let killed = limits.kill_on_violation;
+    let details = match resource {
+        LimitedResource::Memory => {
+            format!(
+                "Instance {name} is using {usage:.0} MB of memory, over its limit of {limit:.0} MB"
+            )
+        }
+        LimitedResource::Cpu => {
+            format!("Instance {name} is using {usage:.1}% CPU, over its limit of {limit:.1}%")
+        }
+        LimitedResource::Disk => {
+            format!(
+                "Instance {name} is using {usage:.0} MB of disk, over its quota of {limit:.0} MB"
+            )
+        }
+    };
+    warn!("{details}");
+    event_broadcaster.send(Event {
+        event_inner: EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: instance.uuid().await,
+            instance_name: name.clone(),
+            instance_event_inner: InstanceEventInner::ResourceLimitExceeded {
+                resource,
+                usage,
+                limit,
+                killed,
+            },
+        }),
+        details,
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::System,
+    });
+    if killed {
+        if let Err(e) = instance.kill(CausedBy::System).await {
+            error!("Failed to kill instance {name} after it exceeded its resource limits : {e}");
+        }
+    }
+}
+
+/// Interval until the next round, `any_active` is whether an instance was not stopped this round
+pub fn next_interval(current: Duration, any_active: bool, active_interval: Duration) -> Duration {
+    if any_active {
+        active_interval
+    } else {
+        (current * 2).min(MAX_IDLE_INTERVAL)
+    }
+}
+
+/// Resolves on the next state transition of any instance, or if events were missed
+async fn state_changed(receiver: &mut tokio::sync::broadcast::Receiver<crate::events::Event>) {
+    loop {
+        match receiver.recv().await {
+            Ok(event) if event.is_state_transition() => return,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(_)) => return,
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+/// The usage and limits of an instance, `None` if it is stopped
+async fn sample_instance(instance: &GameInstance) -> Option<(MonitorReport, ResourceLimits)> {
+    if instance.state().await.is_stopped() {
+        return None;
+    }
+    Some((instance.monitor().await, instance.resource_limits().await))
+}
+
+/// Write the minute rollups that are over to the database, off the monitor loop
+fn flush_usage_history(
+    usage_history: &UsageHistory,
+    sqlite_pool: &SqlitePool,
+    now: i64,
+    prune: bool,
+) {
+    let usage_history = usage_history.clone();
+    let sqlite_pool = sqlite_pool.clone();
+    tokio::spawn(async move {
+        for (uuid, rollup) in usage_history.close_finished(now).await {
+            if let Err(e) = write_minute_rollup(&sqlite_pool, &uuid, &rollup).await {
+                error!("Failed to write the usage history of {uuid} : {e}");
+            }
+        }
+        if prune {
+            if let Err(e) = prune_usage_rollups(&sqlite_pool, now).await {
+                error!("Failed to delete usage history past its retention : {e}");
+            }
+        }
+    });
+}
+
+/// Samples the host pressure and the resource usage of instances that are not stopped, and
+/// records the samples in the usage history.
+///
+/// While every instance is stopped the loop backs off to `MAX_IDLE_INTERVAL`, a state transition
+/// brings it back to the monitor interval of the buffer settings right away. Instances are
+/// sampled concurrently, so one that is busy, e.g. stopping, only misses its own sample.
+pub async fn monitor_report_task(
+    instances: InstanceMap,
+    monitor_buffer: Arc<Mutex<HashMap<InstanceUuid, AllocRingBuffer<MonitorReport>>>>,
+    system: Arc<Mutex<sysinfo::System>>,
+    event_broadcaster: EventBroadcaster,
+    directory_sizes: DirectorySizes,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+    usage_history: UsageHistory,
+    sqlite_pool: SqlitePool,
+) {
+    let mut state_change_receiver = event_broadcaster.subscribe();
+    let mut host_pressure_watcher = HostPressureWatcher::new();
+    let mut resource_limit_watcher = ResourceLimitWatcher::default();
+    let mut last_disk_check: Option<Instant> = None;
+    let mut last_usage_prune: Option<Instant> = None;
+    let mut interval = Duration::from_secs(
+        global_settings
+            .lock()
+            .await
+            .buffer_settings()
+            .monitor_interval_secs,
+    );
+    loop {
+        let buffer_settings = global_settings.lock().await.buffer_settings();
+        let active_interval = Duration::from_secs(buffer_settings.monitor_interval_secs);
+        let check_disks = last_disk_check.is_none_or(|last| last.elapsed() >= DISK_CHECK_INTERVAL);
+        if check_disks {
+            last_disk_check = Some(Instant::now());
+        }
+        for event in host_pressure_watcher.check(&mut *system.lock().await, check_disks) {
+            warn!("{}", event.details);
+            event_broadcaster.send(event);
+        }
+        let now = chrono::Utc::now().timestamp();
+        let prune_usage =
+            last_usage_prune.is_none_or(|last| last.elapsed() >= USAGE_PRUNE_INTERVAL);
+        if prune_usage {
+            last_usage_prune = Some(Instant::now());
+        }
+        flush_usage_history(&usage_history, &sqlite_pool, now, prune_usage);
+
+        let mut samples = JoinSet::new();
+        for (uuid, instance) in instances.snapshot() {
+            samples.spawn(async move {
+                let sample = tokio::time::timeout(SAMPLE_TIMEOUT, sample_instance(&instance)).await;
+                (uuid, instance, sample)
+            });
+        }
+        let mut any_active = false;
+        while let Some(result) = samples.join_next().await {
+            let (uuid, instance, sample) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    error!("Failed to sample an instance, sample task failed : {e}");
+                    continue;
+                }
+            };
+            let (mut report, limits) = match sample {
+                Ok(Some(sample)) => sample,
+                Ok(None) => {
+                    resource_limit_watcher.reset(&uuid);
+                    continue;
+                }
+                Err(_) => {
+                    warn!("Instance {uuid} took too long to report its usage, skipping it");
+                    any_active = true;
+                    continue;
+                }
+            };
+            any_active = true;
+            report.directory_size = directory_sizes.get(&uuid);
+            usage_history.record(&uuid, &report, now).await;
+            for violation in resource_limit_watcher.check(&uuid, &limits, &report) {
+                let mut instance = instance.clone();
+                let event_broadcaster = event_broadcaster.clone();
+                tokio::spawn(async move {
+                    enforce_limit(&mut instance, &limits, violation, &event_broadcaster).await;
+                });
+            }
+            monitor_buffer
+                .lock()
+                .await
+                .entry(uuid)
+                .or_insert_with(|| {
+                    AllocRingBuffer::with_capacity(buffer_settings.monitor_buffer_size)
+                })
+                .push(report);
+        }
+        interval = next_interval(interval, any_active, active_interval);
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = state_changed(&mut state_change_receiver) => interval = active_interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{next_interval, ResourceLimitWatcher, MAX_IDLE_INTERVAL, VIOLATION_SAMPLES};
+    use crate::{
+        traits::{
+            t_configurable::{LimitedResource, ResourceLimits},
+            t_server::MonitorReport,
+        },
+        types::InstanceUuid,
+    };
+
+    #[test]
+    fn test_resource_limit_watcher() {
+        let mut watcher = ResourceLimitWatcher::default();
+        let uuid = InstanceUuid::default();
+        let limits = ResourceLimits {
+            memory_limit: Some(1024),
+            cpu_limit: Some(50.0),
+            kill_on_violation: false,
+            disk_quota: None,
+        };
+        let report = MonitorReport {
+            memory_usage: Some(2048 * 1024 * 1024),
+            cpu_usage: Some(10.0),
+            ..Default::default()
+        };
+        for _ in 1..VIOLATION_SAMPLES {
+            assert!(watcher.check(&uuid, &limits, &report).is_empty());
+        }
+        let violations = watcher.check(&uuid, &limits, &report);
+        assert_eq!(violations, vec![(LimitedResource::Memory, 2048.0, 1024.0)]);
+        // reported once per streak
+        assert!(watcher.check(&uuid, &limits, &report).is_empty());
+
+        watcher.reset(&uuid);
+        for _ in 1..VIOLATION_SAMPLES {
+            assert!(watcher.check(&uuid, &limits, &report).is_empty());
+        }
+        // a sample under the limit breaks the streak
+        assert!(watcher
+            .check(&uuid, &limits, &MonitorReport::default())
+            .is_empty());
+        assert!(watcher.check(&uuid, &limits, &report).is_empty());
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let active_interval = Duration::from_secs(1);
+        let mut interval = active_interval;
+        for _ in 0..10 {
+            interval = next_interval(interval, false, active_interval);
+        }
+        assert_eq!(interval, MAX_IDLE_INTERVAL);
+        assert_eq!(
+            next_interval(interval, true, active_interval),
+            active_interval
+        );
+        assert_eq!(
+            next_interval(Duration::from_secs(2), false, active_interval),
+            Duration::from_secs(4)
+        );
+    }
+}
@@ -0,0 +1,590 @@
+//! Firewall rules that restrict who can reach an instance and what it can reach.
+//!
+//! Only available on Linux, with `iptables` and `ip6tables`, a cgroup v2 hierarchy and the core
+//! running as root. Inbound rules match the port of the instance. Outbound rules match the
+//! process: it is moved to its own cgroup once started, so everything it spawns is restricted too.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_map::InstanceMap,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+};
+
+const MAX_RULES: usize = 64;
+/// where the cgroups of isolated instances are created
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_PARENT: &str = "lodestone";
+/// how long a starting instance is waited on for its process
+const PID_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The traffic allowed for an instance, a list left out doesn't restrict anything
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default, TS)]
+#[ts(export)]
+pub struct NetworkPolicy {
+    /// addresses or CIDR ranges that can connect to the port of the instance, e.g. the proxy in
+    /// front of it. Loopback is always allowed
+    #[serde(default)]
+    pub allowed_sources: Option<Vec<String>>,
+    /// addresses or CIDR ranges the instance can open connections to. Loopback and replies to
+    /// incoming connections are always allowed
+    #[serde(default)]
+    pub allowed_destinations: Option<Vec<String>>,
+}
+
+/// An address with a prefix length, a plain address being its own range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpRange {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl std::str::FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{s} is not an IP address or CIDR range"),
+        };
+        let (address, prefix) = match s.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+        Ok(Self { address, prefix })
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn binary(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables",
+            Family::V6 => "ip6tables",
+        }
+    }
+
+    fn contains(self, range: &IpRange) -> bool {
+        match self {
+            Family::V4 => range.address.is_ipv4(),
+            Family::V6 => range.address.is_ipv6(),
+        }
+    }
+}
+
+fn parse_ranges(ranges: &Option<Vec<String>>) -> Result<Option<Vec<IpRange>>, Error> {
+    ranges
+        .as_ref()
+        .map(|ranges| ranges.iter().map(|range| range.parse()).collect())
+        .transpose()
+}
+
+impl NetworkPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !cfg!(target_os = "linux") {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Network isolation is only supported on Linux"),
+            });
+        }
+        for ranges in [&self.allowed_sources, &self.allowed_destinations]
+            .into_iter()
+            .flatten()
+        {
+            if ranges.len() > MAX_RULES {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("A network policy can list at most {MAX_RULES} ranges"),
+                });
+            }
+        }
+        parse_ranges(&self.allowed_sources)?;
+        parse_ranges(&self.allowed_destinations)?;
+        Ok(())
+    }
+}
+
+/// The chains of an instance, iptables caps chain names at 28 characters
+fn chain_names(instance_uuid: &InstanceUuid) -> (String, String) {
+    let id: String = instance_uuid.no_prefix().chars().take(12).collect();
+    (format!("LS-IN-{id}"), format!("LS-OUT-{id}"))
+}
+
+/// Tags the jumps to the chains of an instance so they can be found again
+fn rule_comment(instance_uuid: &InstanceUuid) -> String {
+    format!("lodestone:{instance_uuid}")
+}
+
+fn cgroup_path(instance_uuid: &InstanceUuid) -> String {
+    format!("{CGROUP_PARENT}/{}", instance_uuid.no_prefix())
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(ToString::to_string).collect()
+}
+
+/// The iptables invocations setting up the chains of a policy for one address family, once the
+/// old ones are cleared. Outbound rules are left out without `cgroup`, i.e. when the instance
+/// isn't running.
+fn policy_rules(
+    instance_uuid: &InstanceUuid,
+    policy: &NetworkPolicy,
+    port: u32,
+    cgroup: Option<&str>,
+    family: Family,
+) -> Result<Vec<Vec<String>>, Error> {
+    let (in_chain, out_chain) = chain_names(instance_uuid);
+    let comment = rule_comment(instance_uuid);
+    let port = port.to_string();
+    let mut rules = Vec::new();
+    if let Some(sources) = parse_ranges(&policy.allowed_sources)? {
+        rules.push(args(&["-N", &in_chain]));
+        rules.push(args(&["-A", &in_chain, "-i", "lo", "-j", "RETURN"]));
+        for source in sources.iter().filter(|range| family.contains(range)) {
+            rules.push(args(&[
+                "-A",
+                &in_chain,
+                "-s",
+                &source.to_string(),
+                "-j",
+                "RETURN",
+            ]));
+        }
+        rules.push(args(&["-A", &in_chain, "-j", "DROP"]));
+        for protocol in ["tcp", "udp"] {
+            rules.push(args(&[
+                "-I",
+                "INPUT",
+                "-p",
+                protocol,
+                "--dport",
+                &port,
+                "-m",
+                "comment",
+                "--comment",
+                &comment,
+                "-j",
+                &in_chain,
+            ]));
+        }
+    }
+    if let (Some(destinations), Some(cgroup)) =
+        (parse_ranges(&policy.allowed_destinations)?, cgroup)
+    {
+        rules.push(args(&["-N", &out_chain]));
+        rules.push(args(&["-A", &out_chain, "-o", "lo", "-j", "RETURN"]));
+        rules.push(args(&[
+            "-A",
+            &out_chain,
+            "-m",
+            "conntrack",
+            "--ctstate",
+            "ESTABLISHED,RELATED",
+            "-j",
+            "RETURN",
+        ]));
+        for destination in destinations.iter().filter(|range| family.contains(range)) {
+            rules.push(args(&[
+                "-A",
+                &out_chain,
+                "-d",
+                &destination.to_string(),
+                "-j",
+                "RETURN",
+            ]));
+        }
+        rules.push(args(&["-A", &out_chain, "-j", "DROP"]));
+        rules.push(args(&[
+            "-I",
+            "OUTPUT",
+            "-m",
+            "cgroup",
+            "--path",
+            cgroup,
+            "-m",
+            "comment",
+            "--comment",
+            &comment,
+            "-j",
+            &out_chain,
+        ]));
+    }
+    Ok(rules)
+}
+
+/// The deletions of the rules tagged with `comment` in the output of `iptables -S <chain>`
+fn tagged_rule_deletions(listing: &str, comment: &str) -> Vec<Vec<String>> {
+    listing
+        .lines()
+        .filter(|line| line.split_whitespace().any(|arg| arg == comment))
+        .filter_map(|line| {
+            let mut rule: Vec<String> = line.split_whitespace().map(ToOwned::to_owned).collect();
+            (rule.first()? == "-A").then(|| {
+                rule[0] = "-D".to_string();
+                rule
+            })
+        })
+        .collect()
+}
+
+async fn run(family: Family, args: &[String]) -> Result<String, Error> {
+    let output = tokio::process::Command::new(family.binary())
+        .args(args)
+        .output()
+        .await
+        .context(format!(
+            "Failed to run {}, is it installed and is the core running as root?",
+            family.binary()
+        ))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{} {} failed : {}",
+            family.binary(),
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Remove the chains of an instance and the jumps to them, whatever was set up before
+async fn clear_rules(instance_uuid: &InstanceUuid, family: Family) -> Result<(), Error> {
+    let comment = rule_comment(instance_uuid);
+    for chain in ["INPUT", "OUTPUT"] {
+        let listing = run(family, &args(&["-S", chain])).await?;
+        for deletion in tagged_rule_deletions(&listing, &comment) {
+            run(family, &deletion).await?;
+        }
+    }
+    let (in_chain, out_chain) = chain_names(instance_uuid);
+    for chain in [in_chain, out_chain] {
+        // the chain doesn't exist if the policy didn't need it
+        if run(family, &args(&["-F", &chain])).await.is_ok() {
+            run(family, &args(&["-X", &chain])).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a process to the cgroup of its instance, returning the path the firewall matches it by
+async fn isolate_process(instance_uuid: &InstanceUuid, pid: u32) -> Result<String, Error> {
+    let cgroup = cgroup_path(instance_uuid);
+    let path = Path::new(CGROUP_ROOT).join(&cgroup);
+    tokio::fs::create_dir_all(&path)
+        .await
+        .context(format!("Failed to create cgroup {}", path.display()))?;
+    tokio::fs::write(path.join("cgroup.procs"), pid.to_string())
+        .await
+        .context(format!("Failed to move process {pid} to cgroup {cgroup}"))?;
+    Ok(cgroup)
+}
+
+/// Replace the firewall rules of an instance with the ones of `policy`, or just remove them with
+/// `None`. The process is only restricted when `pid` is given.
+pub async fn apply_policy(
+    instance_uuid: &InstanceUuid,
+    policy: Option<&NetworkPolicy>,
+    port: u32,
+    pid: Option<u32>,
+) -> Result<(), Error> {
+    let cgroup = match (policy, pid) {
+        (Some(policy), Some(pid)) if policy.allowed_destinations.is_some() => {
+            Some(isolate_process(instance_uuid, pid).await?)
+        }
+        _ => None,
+    };
+    for family in [Family::V4, Family::V6] {
+        clear_rules(instance_uuid, family).await?;
+        if let Some(policy) = policy {
+            for rule in policy_rules(instance_uuid, policy, port, cgroup.as_deref(), family)? {
+                run(family, &rule).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove the rules and cgroup of a deleted instance
+pub async fn remove_isolation(instance_uuid: &InstanceUuid) -> Result<(), Error> {
+    apply_policy(instance_uuid, None, 0, None).await?;
+    let path = Path::new(CGROUP_ROOT).join(cgroup_path(instance_uuid));
+    if path.exists() {
+        tokio::fs::remove_dir(&path)
+            .await
+            .context(format!("Failed to remove cgroup {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Network policies of every instance, persisted in the stores directory
+pub struct NetworkPolicies {
+    path_to_store: PathBuf,
+    policies: HashMap<InstanceUuid, NetworkPolicy>,
+}
+
+impl NetworkPolicies {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            policies: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.policies = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read network policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.policies = serde_json::from_slice(&content).context(format!(
+            "Failed to parse network policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.policies)
+                .context("Failed to serialize network policies")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write network policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn get(&self, instance_uuid: &InstanceUuid) -> Option<NetworkPolicy> {
+        self.policies.get(instance_uuid).cloned()
+    }
+
+    pub async fn set(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        policy: NetworkPolicy,
+    ) -> Result<(), Error> {
+        policy.validate()?;
+        let old = self.policies.insert(instance_uuid.clone(), policy);
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.policies.insert(instance_uuid.clone(), old),
+                None => self.policies.remove(instance_uuid),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Forget the policy of an instance, also used for deleted instances
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.policies.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.policies.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply the policy of a starting instance once its process is up
+async fn isolate_starting_instance(
+    instances: InstanceMap,
+    instance_uuid: InstanceUuid,
+    policy: NetworkPolicy,
+) {
+    let started = tokio::time::Instant::now();
+    let (port, pid) = loop {
+        let instance = match instances.get(&instance_uuid) {
+            Some(instance) => instance,
+            None => return,
+        };
+        if let Some(pid) = instance.pid().await {
+            break (instance.port().await, Some(pid));
+        }
+        if instance.state().await.is_stopped() || started.elapsed() >= PID_TIMEOUT {
+            warn!("Instance {instance_uuid} has no process, only restricting inbound traffic");
+            break (instance.port().await, None);
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    };
+    if let Err(e) = apply_policy(&instance_uuid, Some(&policy), port, pid).await {
+        error!("Failed to apply the network policy of {instance_uuid} : {e}");
+    }
+}
+
+/// Sets up the firewall rules of the instances with a policy, and again every time one starts
+/// since its port or process may have changed
+pub async fn network_isolation_task(
+    mut event_receiver: Receiver<Event>,
+    network_policies: Arc<Mutex<NetworkPolicies>>,
+    instances: InstanceMap,
+) {
+    let policies = network_policies.lock().await.policies.clone();
+    for (instance_uuid, policy) in policies {
+        if let Some(instance) = instances.get(&instance_uuid) {
+            let port = instance.port().await;
+            let pid = instance.pid().await;
+            if let Err(e) = apply_policy(&instance_uuid, Some(&policy), port, pid).await {
+                error!("Failed to apply the network policy of {instance_uuid} : {e}");
+            }
+        }
+    }
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Network isolation task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if let EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_event_inner:
+                InstanceEventInner::StateTransition {
+                    to: State::Starting,
+                },
+            ..
+        }) = event.event_inner
+        {
+            if let Some(policy) = network_policies.lock().await.get(&instance_uuid) {
+                tokio::spawn(isolate_starting_instance(
+                    instances.clone(),
+                    instance_uuid,
+                    policy,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        policy_rules, tagged_rule_deletions, Family, InstanceUuid, IpRange, NetworkPolicy,
+    };
+
+    #[test]
+    fn test_ip_range() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<IpRange>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            "192.168.1.5".parse::<IpRange>().unwrap().to_string(),
+            "192.168.1.5/32"
+        );
+        assert_eq!("::1".parse::<IpRange>().unwrap().to_string(), "::1/128");
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("example.com".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_policy_rules() {
+        let uuid: InstanceUuid = "INSTANCE_0123456789abcdef".to_string().into();
+        let policy = NetworkPolicy {
+            allowed_sources: Some(vec!["10.0.0.2".to_string(), "fd00::/8".to_string()]),
+            allowed_destinations: Some(vec![]),
+        };
+        let rules = policy_rules(&uuid, &policy, 25565, None, Family::V4).unwrap();
+        let rules: Vec<String> = rules.iter().map(|rule| rule.join(" ")).collect();
+        assert_eq!(
+            rules,
+            vec![
+                "-N LS-IN-0123456789ab",
+                "-A LS-IN-0123456789ab -i lo -j RETURN",
+                "-A LS-IN-0123456789ab -s 10.0.0.2/32 -j RETURN",
+                "-A LS-IN-0123456789ab -j DROP",
+                "-I INPUT -p tcp --dport 25565 -m comment --comment lodestone:INSTANCE_0123456789abcdef -j LS-IN-0123456789ab",
+                "-I INPUT -p udp --dport 25565 -m comment --comment lodestone:INSTANCE_0123456789abcdef -j LS-IN-0123456789ab",
+            ]
+        );
+
+        let rules = policy_rules(
+            &uuid,
+            &policy,
+            25565,
+            Some("lodestone/0123456789abcdef"),
+            Family::V6,
+        )
+        .unwrap();
+        let rules: Vec<String> = rules.iter().map(|rule| rule.join(" ")).collect();
+        assert!(rules.contains(&"-A LS-IN-0123456789ab -s fd00::/8 -j RETURN".to_string()));
+        assert!(rules.contains(&"-A LS-OUT-0123456789ab -j DROP".to_string()));
+        assert!(rules.contains(
+            &"-I OUTPUT -m cgroup --path lodestone/0123456789abcdef -m comment --comment lodestone:INSTANCE_0123456789abcdef -j LS-OUT-0123456789ab"
+                .to_string()
+        ));
+
+        assert!(
+            policy_rules(&uuid, &NetworkPolicy::default(), 25565, None, Family::V4)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_tagged_rule_deletions() {
+        let listing = "-P INPUT ACCEPT\n\
+            -A INPUT -p tcp -m tcp --dport 25565 -m comment --comment lodestone:INSTANCE_a -j LS-IN-a\n\
+            -A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n\
+            -A INPUT -p tcp -m tcp --dport 25566 -m comment --comment lodestone:INSTANCE_ab -j LS-IN-ab\n";
+        let deletions = tagged_rule_deletions(listing, "lodestone:INSTANCE_a");
+        assert_eq!(deletions.len(), 1);
+        assert_eq!(
+            deletions[0].join(" "),
+            "-D INPUT -p tcp -m tcp --dport 25565 -m comment --comment lodestone:INSTANCE_a -j LS-IN-a"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use tracing::debug;
+
+use crate::traits::t_server::NetworkUsage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct ConnectionUsage {
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+/// Accounts the network traffic going through the port of an instance.
+///
+/// Per process accounting is not available on most platforms, so the tracker samples the byte
+/// counters of the established TCP connections on the instance's port instead.
+/// Connections come and go, so the counters of closed connections are folded into a running total.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkUsageTracker {
+    closed_connections_usage: ConnectionUsage,
+    open_connections: HashMap<String, ConnectionUsage>,
+}
+
+impl NetworkUsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn update(&mut self, connections: HashMap<String, ConnectionUsage>) {
+        for (peer, usage) in self.open_connections.drain() {
+            match connections.get(&peer) {
+                // counters only go up, if they went down the peer reconnected from the same address
+                Some(new_usage) if new_usage.bytes_sent >= usage.bytes_sent => {}
+                _ => {
+                    self.closed_connections_usage.bytes_sent += usage.bytes_sent;
+                    self.closed_connections_usage.bytes_received += usage.bytes_received;
+                }
+            }
+        }
+        self.open_connections = connections;
+    }
+
+    fn usage(&self) -> NetworkUsage {
+        let open_connections_usage =
+            self.open_connections
+                .values()
+                .fold(ConnectionUsage::default(), |acc, usage| ConnectionUsage {
+                    bytes_sent: acc.bytes_sent + usage.bytes_sent,
+                    bytes_received: acc.bytes_received + usage.bytes_received,
+                });
+        NetworkUsage {
+            total_bytes_sent: self.closed_connections_usage.bytes_sent
+                + open_connections_usage.bytes_sent,
+            total_bytes_received: self.closed_connections_usage.bytes_received
+                + open_connections_usage.bytes_received,
+            connection_count: self.open_connections.len() as u32,
+        }
+    }
+
+    /// Sample the connections on `port` and return the accumulated usage.
+    ///
+    /// Returns `None` if the platform does not support sampling
+    pub async fn sample(&mut self, port: u32) -> Option<NetworkUsage> {
+        let connections = sample_connections(port).await?;
+        self.update(connections);
+        Some(self.usage())
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn sample_connections(port: u32) -> Option<HashMap<String, ConnectionUsage>> {
+    let output = tokio::process::Command::new("ss")
+        .arg("-tinH")
+        .arg("state")
+        .arg("established")
+        .arg(format!("( sport = :{port} )"))
+        .output()
+        .await
+        .map_err(|e| debug!("Failed to run ss to sample network usage : {e}"))
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_ss_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn sample_connections(_port: u32) -> Option<HashMap<String, ConnectionUsage>> {
+    None
+}
+
+/// Parse the output of `ss -tinH state established`
+///
+/// Each connection is a line with the socket addresses followed by an indented line with the tcp info
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_ss_output(output: &str) -> HashMap<String, ConnectionUsage> {
+    let mut ret = HashMap::new();
+    let mut current_peer: Option<String> = None;
+    for line in output.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            // Recv-Q Send-Q Local-Address:Port Peer-Address:Port
+            current_peer = line.split_whitespace().nth(3).map(|s| s.to_string());
+            continue;
+        }
+        let peer = match current_peer.take() {
+            Some(peer) => peer,
+            None => continue,
+        };
+        let mut usage = ConnectionUsage::default();
+        let mut bytes_acked = None;
+        for token in line.split_whitespace() {
+            if let Some((key, value)) = token.split_once(':') {
+                match key {
+                    "bytes_sent" => usage.bytes_sent = value.parse().unwrap_or(0),
+                    "bytes_acked" => bytes_acked = value.parse().ok(),
+                    "bytes_received" => usage.bytes_received = value.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+        // older versions of ss don't report bytes_sent
+        if usage.bytes_sent == 0 {
+            usage.bytes_sent = bytes_acked.unwrap_or(0);
+        }
+        ret.insert(peer, usage);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{parse_ss_output, ConnectionUsage, NetworkUsageTracker};
+
+    #[test]
+    fn test_parse_ss_output() {
+        let output = "0      0      127.0.0.1:25565      127.0.0.1:53422
+\t cubic wscale:7,7 rto:204 rtt:0.05/0.025 mss:32768 bytes_sent:1200 bytes_acked:1201 bytes_received:300 segs_out:10
+0      0      [::ffff:10.0.0.2]:25565      [::ffff:10.0.0.3]:41000
+\t cubic wscale:7,7 rto:204 bytes_acked:50 bytes_received:70
+";
+        let parsed = parse_ss_output(output);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(
+            parsed["127.0.0.1:53422"],
+            ConnectionUsage {
+                bytes_sent: 1200,
+                bytes_received: 300
+            }
+        );
+        assert_eq!(
+            parsed["[::ffff:10.0.0.3]:41000"],
+            ConnectionUsage {
+                bytes_sent: 50,
+                bytes_received: 70
+            }
+        );
+    }
+
+    #[test]
+    fn test_tracker_accumulates_closed_connections() {
+        let mut tracker = NetworkUsageTracker::new();
+        tracker.update(HashMap::from([(
+            "a".to_string(),
+            ConnectionUsage {
+                bytes_sent: 100,
+                bytes_received: 10,
+            },
+        )]));
+        tracker.update(HashMap::from([(
+            "b".to_string(),
+            ConnectionUsage {
+                bytes_sent: 5,
+                bytes_received: 1,
+            },
+        )]));
+        let usage = tracker.usage();
+        assert_eq!(usage.total_bytes_sent, 105);
+        assert_eq!(usage.total_bytes_received, 11);
+        assert_eq!(usage.connection_count, 1);
+    }
+}
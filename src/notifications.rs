@@ -0,0 +1,747 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    console_matchers::ConsoleMatchKind,
+    error::{Error, ErrorKind},
+    events::{
+        Event, EventInner, EventLevel, InstanceEvent, InstanceEventInner, ProgressionEventInner,
+        ProgressionStartValue, SystemEventInner,
+    },
+    output_types::ClientEvent,
+    traits::{t_player::TPlayer, t_server::State},
+    types::{InstanceUuid, Snowflake},
+    util::rand_alphanumeric,
+};
+
+/// webhooks that take longer than this to answer are given up on
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum WebhookKind {
+    Discord,
+    Slack,
+    /// the notification and the event that caused it are posted as json
+    Generic,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+#[ts(export)]
+pub enum NotificationTrigger {
+    InstanceStarted,
+    InstanceStopped,
+    InstanceCrashed,
+    InstanceOutOfMemory,
+    ResourceLimitExceeded,
+    PlayerJoined,
+    PlayerLeft,
+    BackupCompleted,
+    BackupFailed,
+    HostPressure,
+    /// new device logins and failed login attempts
+    SecurityAlert,
+    /// an error logged by the server
+    ConsoleError,
+    /// a line matched by a console matcher added by a user
+    ConsoleMatch,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct WebhookTarget {
+    pub id: String,
+    pub name: String,
+    pub kind: WebhookKind,
+    pub url: String,
+    pub triggers: HashSet<NotificationTrigger>,
+    /// only notify about these instances, every instance if empty
+    pub instances: HashSet<InstanceUuid>,
+    pub enabled: bool,
+    pub creation_time: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct WebhookTargetConfig {
+    pub name: String,
+    pub kind: WebhookKind,
+    pub url: String,
+    pub triggers: HashSet<NotificationTrigger>,
+    #[serde(default)]
+    pub instances: HashSet<InstanceUuid>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl WebhookTargetConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be empty"),
+            });
+        }
+        match url::Url::parse(&self.url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+            _ => Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Webhook url must be a valid http or https url"),
+            }),
+        }
+    }
+}
+
+/// What a user wants to be alerted about, the events of other instances or under `min_level`
+/// are left out
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct NotificationPreferences {
+    #[serde(default = "default_min_level")]
+    pub min_level: EventLevel,
+    /// every instance if empty, events of no instance are always included
+    #[serde(default)]
+    pub instances: HashSet<InstanceUuid>,
+}
+
+fn default_min_level() -> EventLevel {
+    EventLevel::Warning
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        Self {
+            min_level: default_min_level(),
+            instances: HashSet::new(),
+        }
+    }
+}
+
+impl NotificationPreferences {
+    pub fn wants(&self, event: &Event) -> bool {
+        event.level() >= self.min_level
+            && (self.instances.is_empty()
+                || event
+                    .get_instance_uuid()
+                    .map_or(true, |uuid| self.instances.contains(&uuid)))
+    }
+}
+
+/// Something worth telling a webhook about
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub trigger: NotificationTrigger,
+    pub instance_uuid: Option<InstanceUuid>,
+    pub message: String,
+}
+
+impl WebhookTarget {
+    fn wants(&self, notification: &Notification) -> bool {
+        self.enabled
+            && self.triggers.contains(&notification.trigger)
+            && (self.instances.is_empty()
+                || notification
+                    .instance_uuid
+                    .as_ref()
+                    .is_some_and(|uuid| self.instances.contains(uuid)))
+    }
+
+    /// The body to post, in the format the kind of webhook expects
+    fn payload(&self, notification: &Notification, event: Option<&Event>) -> Value {
+        match self.kind {
+            WebhookKind::Discord => json!({
+                "username": "Lodestone",
+                "content": notification.message,
+            }),
+            WebhookKind::Slack => json!({ "text": notification.message }),
+            WebhookKind::Generic => json!({
+                "trigger": notification.trigger,
+                "instance_uuid": notification.instance_uuid,
+                "message": notification.message,
+                "event": event.map(|event| ClientEvent::from(event.clone())),
+            }),
+        }
+    }
+}
+
+/// Turns events into notifications, remembering which running progressions are backups
+#[derive(Default)]
+pub struct NotificationClassifier {
+    /// progression event id to the instance being backed up
+    backups: HashMap<Snowflake, (InstanceUuid, String)>,
+}
+
+impl NotificationClassifier {
+    pub fn classify(&mut self, event: &Event) -> Vec<Notification> {
+        let instance_notification = |trigger, uuid: &InstanceUuid, message: String| Notification {
+            trigger,
+            instance_uuid: Some(uuid.clone()),
+            message,
+        };
+        match &event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner,
+            }) => match instance_event_inner {
+                InstanceEventInner::StateTransition { to: State::Running } => {
+                    vec![instance_notification(
+                        NotificationTrigger::InstanceStarted,
+                        instance_uuid,
+                        format!("{instance_name} started"),
+                    )]
+                }
+                InstanceEventInner::StateTransition { to: State::Stopped } => {
+                    vec![instance_notification(
+                        NotificationTrigger::InstanceStopped,
+                        instance_uuid,
+                        format!("{instance_name} stopped"),
+                    )]
+                }
+                InstanceEventInner::InstanceCrashed { exit_code, .. } => {
+                    vec![instance_notification(
+                        NotificationTrigger::InstanceCrashed,
+                        instance_uuid,
+                        match exit_code {
+                            Some(exit_code) => {
+                                format!("{instance_name} crashed with exit code {exit_code}")
+                            }
+                            None => format!("{instance_name} crashed"),
+                        },
+                    )]
+                }
+                InstanceEventInner::InstanceOutOfMemory { message, .. } => {
+                    vec![instance_notification(
+                        NotificationTrigger::InstanceOutOfMemory,
+                        instance_uuid,
+                        format!("{instance_name} ran out of memory: {message}"),
+                    )]
+                }
+                InstanceEventInner::ResourceLimitExceeded { .. } => {
+                    vec![instance_notification(
+                        NotificationTrigger::ResourceLimitExceeded,
+                        instance_uuid,
+                        event.details.clone(),
+                    )]
+                }
+                InstanceEventInner::PlayerChange {
+                    players_joined,
+                    players_left,
+                    joined_from,
+                    ..
+                } => players_joined
+                    .iter()
+                    .map(|player| {
+                        let from = joined_from
+                            .get(&player.get_name())
+                            .map(|location| match &location.region {
+                                Some(region) => format!(" from {region}, {}", location.country),
+                                None => format!(" from {}", location.country),
+                            })
+                            .unwrap_or_default();
+                        instance_notification(
+                            NotificationTrigger::PlayerJoined,
+                            instance_uuid,
+                            format!("{} joined {instance_name}{from}", player.get_name()),
+                        )
+                    })
+                    .chain(players_left.iter().map(|player| {
+                        instance_notification(
+                            NotificationTrigger::PlayerLeft,
+                            instance_uuid,
+                            format!("{} left {instance_name}", player.get_name()),
+                        )
+                    }))
+                    .collect(),
+                InstanceEventInner::ConsoleMatch {
+                    kind: ConsoleMatchKind::Error,
+                    line,
+                    named_groups,
+                    ..
+                } => {
+                    vec![instance_notification(
+                        NotificationTrigger::ConsoleError,
+                        instance_uuid,
+                        format!(
+                            "{instance_name} logged an error: {}",
+                            named_groups.get("message").unwrap_or(line)
+                        ),
+                    )]
+                }
+                InstanceEventInner::ConsoleMatch {
+                    matcher,
+                    kind: ConsoleMatchKind::Custom,
+                    line,
+                    ..
+                } => vec![instance_notification(
+                    NotificationTrigger::ConsoleMatch,
+                    instance_uuid,
+                    format!("{matcher} matched on {instance_name}: {line}"),
+                )],
+                _ => Vec::new(),
+            },
+            EventInner::ProgressionEvent(progression_event) => {
+                match progression_event.progression_event_inner() {
+                    ProgressionEventInner::ProgressionStart {
+                        progression_name,
+                        inner: Some(ProgressionStartValue::InstanceBackup { instance_uuid }),
+                        ..
+                    } => {
+                        self.backups.insert(
+                            progression_event.event_id(),
+                            (instance_uuid.clone(), progression_name.clone()),
+                        );
+                        Vec::new()
+                    }
+                    ProgressionEventInner::ProgressionEnd {
+                        success, message, ..
+                    } => match self.backups.remove(&progression_event.event_id()) {
+                        Some((instance_uuid, progression_name)) => {
+                            let (trigger, outcome) = if *success {
+                                (NotificationTrigger::BackupCompleted, "completed")
+                            } else {
+                                (NotificationTrigger::BackupFailed, "failed")
+                            };
+                            let message = match message {
+                                Some(message) => format!("{progression_name} {outcome}: {message}"),
+                                None => format!("{progression_name} {outcome}"),
+                            };
+                            vec![instance_notification(trigger, &instance_uuid, message)]
+                        }
+                        None => Vec::new(),
+                    },
+                    _ => Vec::new(),
+                }
+            }
+            EventInner::SystemEvent(system_event) => match system_event.system_event_inner {
+                SystemEventInner::HostMemoryPressure { .. }
+                | SystemEventInner::HostDiskPressure { .. } => vec![Notification {
+                    trigger: NotificationTrigger::HostPressure,
+                    instance_uuid: None,
+                    message: event.details.clone(),
+                }],
+                _ => Vec::new(),
+            },
+            EventInner::SecurityEvent(security_event)
+                if security_event.security_event_inner.is_alert() =>
+            {
+                vec![Notification {
+                    trigger: NotificationTrigger::SecurityAlert,
+                    instance_uuid: None,
+                    message: event.details.clone(),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Webhook targets, persisted in the stores directory
+pub struct Notifications {
+    path_to_store: PathBuf,
+    targets: Vec<WebhookTarget>,
+    http: reqwest::Client,
+}
+
+impl Notifications {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            targets: Vec::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.targets = Vec::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read notifications file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.targets = serde_json::from_slice(&content).context(format!(
+            "Failed to parse notifications file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.targets)
+                .context("Failed to serialize notifications")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write notifications file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn targets(&self) -> &[WebhookTarget] {
+        &self.targets
+    }
+
+    pub fn target(&self, id: &str) -> Result<&WebhookTarget, Error> {
+        self.targets
+            .iter()
+            .find(|target| target.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Webhook target not found"),
+            })
+    }
+
+    pub async fn add_target(
+        &mut self,
+        config: WebhookTargetConfig,
+    ) -> Result<WebhookTarget, Error> {
+        config.validate()?;
+        let target = WebhookTarget {
+            id: rand_alphanumeric(16),
+            name: config.name,
+            kind: config.kind,
+            url: config.url,
+            triggers: config.triggers,
+            instances: config.instances,
+            enabled: config.enabled,
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        self.targets.push(target.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.targets.pop();
+            return Err(e);
+        }
+        Ok(target)
+    }
+
+    pub async fn update_target(
+        &mut self,
+        id: &str,
+        config: WebhookTargetConfig,
+    ) -> Result<WebhookTarget, Error> {
+        config.validate()?;
+        let old = self.target(id)?.clone();
+        let target = WebhookTarget {
+            id: old.id.clone(),
+            name: config.name,
+            kind: config.kind,
+            url: config.url,
+            triggers: config.triggers,
+            instances: config.instances,
+            enabled: config.enabled,
+            creation_time: old.creation_time,
+        };
+        self.replace_target(id, target.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.replace_target(id, old);
+            return Err(e);
+        }
+        Ok(target)
+    }
+
+    fn replace_target(&mut self, id: &str, target: WebhookTarget) {
+        if let Some(existing) = self.targets.iter_mut().find(|target| target.id == id) {
+            *existing = target;
+        }
+    }
+
+    pub async fn remove_target(&mut self, id: &str) -> Result<(), Error> {
+        self.target(id)?;
+        let old_targets = self.targets.clone();
+        self.targets.retain(|target| target.id != id);
+        if let Err(e) = self.write_to_file().await {
+            self.targets = old_targets;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Send a test notification to a target, regardless of its triggers
+pub async fn send_test_notification(target: &WebhookTarget) -> Result<(), Error> {
+    let notification = Notification {
+        trigger: NotificationTrigger::InstanceStarted,
+        instance_uuid: None,
+        message: format!("Test notification for {}", target.name),
+    };
+    deliver(&reqwest::Client::new(), target, &notification, None).await
+}
+
+async fn deliver(
+    http: &reqwest::Client,
+    target: &WebhookTarget,
+    notification: &Notification,
+    event: Option<&Event>,
+) -> Result<(), Error> {
+    http.post(&target.url)
+        .json(&target.payload(notification, event))
+        .timeout(DELIVERY_TIMEOUT)
+        .send()
+        .await
+        .context(format!("Failed to reach webhook {}", target.name))?
+        .error_for_status()
+        .context(format!("Webhook {} rejected the notification", target.name))?;
+    Ok(())
+}
+
+/// Post a notification to every matching webhook target for each relevant event
+pub async fn notification_task(
+    mut event_receiver: Receiver<Event>,
+    notifications: Arc<Mutex<Notifications>>,
+) {
+    let mut classifier = NotificationClassifier::default();
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Notification task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if event.is_event_console_message() {
+            continue;
+        }
+        for notification in classifier.classify(&event) {
+            let notifications = notifications.lock().await;
+            for target in notifications
+                .targets()
+                .iter()
+                .filter(|target| target.wants(&notification))
+            {
+                let http = notifications.http.clone();
+                let target = target.clone();
+                let notification = notification.clone();
+                let event = event.clone();
+                // a slow webhook should not hold up the others
+                tokio::spawn(async move {
+                    if let Err(e) = deliver(&http, &target, &notification, Some(&event)).await {
+                        warn!("Failed to deliver notification : {e}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use super::{
+        NotificationClassifier, NotificationPreferences, NotificationTrigger, Notifications,
+        WebhookKind, WebhookTargetConfig,
+    };
+    use crate::{
+        events::{
+            CausedBy, Event, EventInner, EventLevel, InstanceEvent, InstanceEventInner,
+            ProgressionStartValue, SecurityEvent, SecurityEventInner, SystemEventInner,
+        },
+        types::{InstanceUuid, Snowflake},
+    };
+
+    fn instance_event(uuid: &InstanceUuid, inner: InstanceEventInner) -> Event {
+        Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: uuid.clone(),
+                instance_name: "survival".to_string(),
+                instance_event_inner: inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let mut classifier = NotificationClassifier::default();
+        let uuid = InstanceUuid::default();
+        let crashed = classifier.classify(&instance_event(
+            &uuid,
+            InstanceEventInner::InstanceCrashed {
+                exit_code: Some(1),
+                last_output: Vec::new(),
+            },
+        ));
+        assert_eq!(crashed.len(), 1);
+        assert_eq!(crashed[0].trigger, NotificationTrigger::InstanceCrashed);
+        assert_eq!(crashed[0].message, "survival crashed with exit code 1");
+        assert!(classifier
+            .classify(&instance_event(
+                &uuid,
+                InstanceEventInner::InstanceOutput {
+                    message: "hello".to_string()
+                },
+            ))
+            .is_empty());
+
+        let (start, event_id) = Event::new_progression_event_start(
+            "Backing up survival",
+            None,
+            Some(ProgressionStartValue::InstanceBackup {
+                instance_uuid: uuid.clone(),
+            }),
+            CausedBy::System,
+        );
+        assert!(classifier.classify(&start).is_empty());
+        let failed = classifier.classify(&Event::new_progression_event_end(
+            event_id,
+            false,
+            Some("disk full"),
+            None,
+        ));
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].trigger, NotificationTrigger::BackupFailed);
+        assert_eq!(failed[0].instance_uuid, Some(uuid));
+        assert_eq!(failed[0].message, "Backing up survival failed: disk full");
+
+        // progressions that are not backups are ignored
+        let (_, event_id) =
+            Event::new_progression_event_start("Downloading", None, None, CausedBy::System);
+        assert!(classifier
+            .classify(&Event::new_progression_event_end(
+                event_id,
+                false,
+                None::<&str>,
+                None,
+            ))
+            .is_empty());
+
+        let security_event = |security_event_inner| Event {
+            event_inner: EventInner::SecurityEvent(SecurityEvent {
+                user_id: None,
+                security_event_inner,
+            }),
+            details: "Failed login attempt for unknown user admin".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::Unknown,
+        };
+        let failed_login = classifier.classify(&security_event(SecurityEventInner::LoginFailed {
+            username: "admin".to_string(),
+        }));
+        assert_eq!(failed_login.len(), 1);
+        assert_eq!(failed_login[0].trigger, NotificationTrigger::SecurityAlert);
+        assert!(classifier
+            .classify(&security_event(SecurityEventInner::TokenCreated {
+                session_id: Snowflake::default()
+            }))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_notification_preferences() {
+        let uuid = InstanceUuid::default();
+        let other_uuid = InstanceUuid::default();
+        let crashed = |uuid: &InstanceUuid| {
+            instance_event(
+                uuid,
+                InstanceEventInner::InstanceCrashed {
+                    exit_code: Some(1),
+                    last_output: Vec::new(),
+                },
+            )
+        };
+        let output = instance_event(
+            &uuid,
+            InstanceEventInner::InstanceOutput {
+                message: "Done".to_string(),
+            },
+        );
+        let disk_pressure: Event = SystemEventInner::HostDiskPressure {
+            mount_point: "/".into(),
+            available_space: 0,
+            total_space: 1,
+        }
+        .into();
+
+        let preferences = NotificationPreferences::default();
+        assert!(preferences.wants(&crashed(&uuid)));
+        assert!(!preferences.wants(&output));
+
+        let preferences = NotificationPreferences {
+            min_level: EventLevel::Critical,
+            instances: HashSet::from([uuid.clone()]),
+        };
+        assert!(!preferences.wants(&crashed(&uuid)));
+        assert!(preferences.wants(&disk_pressure));
+
+        let preferences = NotificationPreferences {
+            min_level: EventLevel::Error,
+            instances: HashSet::from([uuid.clone()]),
+        };
+        assert!(preferences.wants(&crashed(&uuid)));
+        assert!(!preferences.wants(&crashed(&other_uuid)));
+    }
+
+    #[tokio::test]
+    async fn test_targets() {
+        let temp_dir = tempdir::TempDir::new("test_notifications").unwrap();
+        let path = temp_dir.path().join("notifications.json");
+        let mut notifications = Notifications::new(path.clone());
+        let config = WebhookTargetConfig {
+            name: "discord".to_string(),
+            kind: WebhookKind::Discord,
+            url: "https://discord.com/api/webhooks/1/abc".to_string(),
+            triggers: HashSet::from([NotificationTrigger::InstanceCrashed]),
+            instances: HashSet::new(),
+            enabled: true,
+        };
+        assert!(notifications
+            .add_target(WebhookTargetConfig {
+                url: "discord.com/api/webhooks".to_string(),
+                ..config.clone()
+            })
+            .await
+            .is_err());
+        let target = notifications.add_target(config).await.unwrap();
+
+        let mut loaded = Notifications::new(path);
+        loaded.load_from_file().await.unwrap();
+        assert_eq!(loaded.targets(), &[target.clone()]);
+
+        let notification = super::Notification {
+            trigger: NotificationTrigger::InstanceCrashed,
+            instance_uuid: Some(InstanceUuid::default()),
+            message: "survival crashed".to_string(),
+        };
+        assert!(target.wants(&notification));
+        assert!(!target.wants(&super::Notification {
+            trigger: NotificationTrigger::PlayerJoined,
+            ..notification.clone()
+        }));
+        assert_eq!(
+            target.payload(&notification, None),
+            json!({"username": "Lodestone", "content": "survival crashed"})
+        );
+
+        loaded.remove_target(&target.id).await.unwrap();
+        assert!(loaded.targets().is_empty());
+        assert!(loaded.remove_target(&target.id).await.is_err());
+    }
+}
@@ -1,15 +1,13 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::{
-    events::{
-        CausedBy, Event, EventInner, EventLevel, InstanceEventInner, MacroEventInner,
-        ProgressionEventInner,
-    },
+    events::{CausedBy, Event, EventInner, EventLevel},
     types::Snowflake,
 };
 
-#[derive(Deserialize, Serialize, Clone, Debug, TS)]
+#[derive(Deserialize, Serialize, Clone, Debug, TS, JsonSchema)]
 #[ts(export)]
 pub struct ClientEvent {
     pub event_inner: EventInner,
@@ -21,42 +19,11 @@ pub struct ClientEvent {
 
 impl From<&Event> for ClientEvent {
     fn from(event: &Event) -> Self {
-        let level = match &event.event_inner {
-            EventInner::InstanceEvent(i) => match i.instance_event_inner {
-                InstanceEventInner::InstanceError { .. } => EventLevel::Error,
-                InstanceEventInner::InstanceWarning { .. } => EventLevel::Warning,
-                _ => EventLevel::Info,
-            },
-            EventInner::UserEvent(_) => EventLevel::Info,
-            EventInner::MacroEvent(m) => match m.macro_event_inner {
-                MacroEventInner::Started => EventLevel::Info,
-                MacroEventInner::Stopped { ref exit_status } => {
-                    if exit_status.is_success() {
-                        EventLevel::Info
-                    } else {
-                        EventLevel::Error
-                    }
-                }
-                MacroEventInner::MainModuleExecuted => EventLevel::Info,
-            },
-            EventInner::ProgressionEvent(p) => match p.progression_event_inner() {
-                ProgressionEventInner::ProgressionStart { .. } => EventLevel::Info,
-                ProgressionEventInner::ProgressionUpdate { .. } => EventLevel::Info,
-                ProgressionEventInner::ProgressionEnd { success, .. } => {
-                    if *success {
-                        EventLevel::Info
-                    } else {
-                        EventLevel::Error
-                    }
-                }
-            },
-            EventInner::FSEvent(_) => EventLevel::Info,
-        };
         ClientEvent {
             event_inner: event.event_inner.clone(),
             details: event.details.clone(),
             snowflake: event.snowflake,
-            level,
+            level: event.level(),
             caused_by: event.caused_by.clone(),
         }
     }
@@ -68,6 +35,17 @@ impl From<Event> for ClientEvent {
     }
 }
 
+impl From<ClientEvent> for Event {
+    fn from(client_event: ClientEvent) -> Self {
+        Event {
+            event_inner: client_event.event_inner,
+            details: client_event.details,
+            snowflake: client_event.snowflake,
+            caused_by: client_event.caused_by,
+        }
+    }
+}
+
 impl AsRef<ClientEvent> for ClientEvent {
     fn as_ref(&self) -> &ClientEvent {
         self
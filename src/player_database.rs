@@ -0,0 +1,284 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    geoip::GeoLocation,
+    traits::t_player::{Player, TPlayer},
+    types::{InstanceUuid, Snowflake},
+};
+
+/// countries remembered per player, enough to tell a new one from where they usually join
+const MAX_COUNTRIES: usize = 16;
+
+/// A player who joined an instance
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PlayerRecord {
+    pub name: String,
+    pub id: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub joins: u32,
+    /// where they last joined from, if GeoIP was on and knew
+    pub last_location: Option<GeoLocation>,
+    /// every country they joined from
+    pub countries: BTreeSet<String>,
+}
+
+/// The players who joined each instance, persisted in the stores directory
+pub struct PlayerDatabase {
+    path_to_store: PathBuf,
+    /// by instance, then by player id
+    players: HashMap<InstanceUuid, HashMap<String, PlayerRecord>>,
+}
+
+impl PlayerDatabase {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            players: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.players = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read player database file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.players = serde_json::from_slice(&content).context(format!(
+            "Failed to parse player database file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.players)
+                .context("Failed to serialize player database")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write player database file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The players of an instance, the most recently seen first
+    pub fn players(&self, instance_uuid: &InstanceUuid) -> Vec<PlayerRecord> {
+        let mut players: Vec<PlayerRecord> = self
+            .players
+            .get(instance_uuid)
+            .map(|players| players.values().cloned().collect())
+            .unwrap_or_default();
+        players.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        players
+    }
+
+    /// Record a join, returns the countries the player joined from before if this one is new
+    /// to them
+    pub async fn record_join(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        player: &Player,
+        location: Option<GeoLocation>,
+        timestamp: i64,
+    ) -> Result<Option<BTreeSet<String>>, Error> {
+        let players = self.players.entry(instance_uuid.clone()).or_default();
+        let old_record = players.get(&player.get_id()).cloned();
+        let record = players
+            .entry(player.get_id())
+            .or_insert_with(|| PlayerRecord {
+                name: player.get_name(),
+                id: player.get_id(),
+                first_seen: timestamp,
+                last_seen: timestamp,
+                joins: 0,
+                last_location: None,
+                countries: BTreeSet::new(),
+            });
+        // players can change their name
+        record.name = player.get_name();
+        record.last_seen = timestamp;
+        record.joins = record.joins.saturating_add(1);
+        let mut previous_countries = None;
+        if let Some(location) = location {
+            if !record.countries.contains(&location.country) {
+                if !record.countries.is_empty() {
+                    previous_countries = Some(record.countries.clone());
+                }
+                if record.countries.len() < MAX_COUNTRIES {
+                    record.countries.insert(location.country.clone());
+                }
+            }
+            record.last_location = Some(location);
+        }
+        if let Err(e) = self.write_to_file().await {
+            let players = self.players.entry(instance_uuid.clone()).or_default();
+            match old_record {
+                Some(old_record) => players.insert(player.get_id(), old_record),
+                None => players.remove(&player.get_id()),
+            };
+            return Err(e);
+        }
+        Ok(previous_countries)
+    }
+
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(players) = self.players.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.players.insert(instance_uuid.clone(), players);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records the players joining instances, and warns when one joins from a country they never
+/// joined from before, which can be someone else on their account
+pub async fn player_database_task(
+    mut event_receiver: Receiver<Event>,
+    player_database: Arc<Mutex<PlayerDatabase>>,
+    event_broadcaster: EventBroadcaster,
+) {
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Player database task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let (instance_uuid, instance_name, players_joined, joined_from) = match event.event_inner {
+            EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner:
+                    InstanceEventInner::PlayerChange {
+                        players_joined,
+                        joined_from,
+                        ..
+                    },
+            }) => (instance_uuid, instance_name, players_joined, joined_from),
+            _ => continue,
+        };
+        let timestamp = chrono::Utc::now().timestamp();
+        for player in players_joined {
+            let location = joined_from.get(&player.get_name()).cloned();
+            let country = location.as_ref().map(|location| location.country.clone());
+            let previous_countries = match player_database
+                .lock()
+                .await
+                .record_join(&instance_uuid, &player, location, timestamp)
+                .await
+            {
+                Ok(previous_countries) => previous_countries,
+                Err(e) => {
+                    error!("Failed to record {} joining : {e}", player.get_name());
+                    continue;
+                }
+            };
+            if let (Some(country), Some(previous_countries)) = (country, previous_countries) {
+                event_broadcaster.send(Event {
+                    event_inner: EventInner::InstanceEvent(InstanceEvent {
+                        instance_uuid: instance_uuid.clone(),
+                        instance_name: instance_name.clone(),
+                        instance_event_inner: InstanceEventInner::InstanceWarning {
+                            message: format!(
+                                "{} joined from {country}, they joined from {} before",
+                                player.get_name(),
+                                previous_countries
+                                    .into_iter()
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ),
+                        },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by: CausedBy::System,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerDatabase;
+    use crate::{
+        geoip::GeoLocation, implementations::minecraft::player::MinecraftPlayer,
+        traits::t_player::Player, types::InstanceUuid,
+    };
+
+    fn location(country: &str) -> Option<GeoLocation> {
+        Some(GeoLocation {
+            country: country.to_string(),
+            region: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_player_database() {
+        let temp_dir = tempdir::TempDir::new("test_player_database").unwrap();
+        let path = temp_dir.path().join("player_database.json");
+        let mut player_database = PlayerDatabase::new(path.clone());
+        let uuid = InstanceUuid::default();
+        let steve = Player::MinecraftPlayer(MinecraftPlayer::new(
+            "Steve".to_string(),
+            Some("uuid-steve".to_string()),
+        ));
+
+        let new_country = player_database
+            .record_join(&uuid, &steve, location("FR"), 100)
+            .await
+            .unwrap();
+        assert!(new_country.is_none());
+        assert!(player_database
+            .record_join(&uuid, &steve, None, 200)
+            .await
+            .unwrap()
+            .is_none());
+        let new_country = player_database
+            .record_join(&uuid, &steve, location("DE"), 300)
+            .await
+            .unwrap();
+        assert_eq!(new_country.unwrap().into_iter().collect::<Vec<_>>(), ["FR"]);
+
+        let mut player_database = PlayerDatabase::new(path);
+        player_database.load_from_file().await.unwrap();
+        let players = player_database.players(&uuid);
+        assert_eq!(players.len(), 1);
+        assert_eq!(players[0].joins, 3);
+        assert_eq!(players[0].first_seen, 100);
+        assert_eq!(players[0].last_seen, 300);
+        assert_eq!(players[0].last_location, location("DE"));
+        assert_eq!(players[0].countries.len(), 2);
+
+        player_database.remove_instance(&uuid).await.unwrap();
+        assert!(player_database.players(&uuid).is_empty());
+    }
+}
@@ -1,9 +1,8 @@
-use std::{collections::HashSet, net::SocketAddrV4};
+use std::{collections::HashSet, time::Duration};
 
-use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{error::Error, public_address::add_port_mapping};
 
 pub struct PortManager {
     allocated_ports: HashSet<u32>,
@@ -52,31 +51,6 @@ impl PortManager {
     }
 
     pub async fn open_port(&self, port: u16) -> Result<(), Error> {
-        tokio::task::spawn_blocking(move || {
-            if let Ok(local_ip) = local_ip_address::local_ip() {
-                // convert local_ip to a SocketAddrV4
-                let local_ip = if let std::net::IpAddr::V4(ipv4) = local_ip {
-                    SocketAddrV4::new(ipv4, port)
-                } else {
-                    panic!();
-                };
-
-                igd::search_gateway(Default::default())
-                    .context("Could not find gateway")?
-                    .add_port(
-                        igd::PortMappingProtocol::TCP,
-                        port,
-                        local_ip,
-                        0,
-                        "Port opened by Lodestone",
-                    )
-                    .context("Could not open port")?;
-                Ok(())
-            } else {
-                Err(eyre!("Could not find local ip address").into())
-            }
-        })
-        .await
-        .unwrap()
+        add_port_mapping(port, Duration::ZERO, "Port opened by Lodestone".to_string()).await
     }
 }
@@ -0,0 +1,306 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Context;
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::{
+    config_editor::{edit_config, parse_config, ConfigEdit, ConfigFormat, ConfigValue},
+    error::Error,
+};
+
+/// A port rewritten in a config file of an instance
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct PortChange {
+    /// relative to the instance directory
+    pub file: String,
+    /// keys from the root of the file, list items are addressed by their index
+    pub path: Vec<String>,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+impl std::fmt::Display for PortChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} -> {}",
+            self.file,
+            self.path.join("."),
+            self.old_value,
+            self.new_value
+        )
+    }
+}
+
+/// How a port is written at a path of a config file
+#[derive(Clone, Copy)]
+enum PortValue {
+    /// `25565`
+    Port,
+    /// `0.0.0.0:25565`
+    Address,
+}
+
+/// The ports that follow the port of the server, by file and path. `*` matches every item of a
+/// list.
+static KNOWN_PORTS: &[(&str, &[&str], PortValue)] = &[
+    ("server.properties", &["server-port"], PortValue::Port),
+    ("server.properties", &["query.port"], PortValue::Port),
+    // Velocity
+    ("velocity.toml", &["bind"], PortValue::Address),
+    // BungeeCord and Waterfall
+    (
+        "config.yml",
+        &["listeners", "*", "host"],
+        PortValue::Address,
+    ),
+    (
+        "config.yml",
+        &["listeners", "*", "query_port"],
+        PortValue::Port,
+    ),
+    // Geyser standalone
+    ("config.yml", &["remote", "port"], PortValue::Port),
+];
+
+/// The Geyser plugin keeps the port of the server it runs in
+static GEYSER_PORTS: &[(&str, &[&str], PortValue)] =
+    &[("config.yml", &["remote", "port"], PortValue::Port)];
+
+fn entry<'a>(value: &'a ConfigValue, key: &str) -> Option<&'a ConfigValue> {
+    match value {
+        ConfigValue::Map(entries) => entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.value),
+        ConfigValue::List(items) => items.get(key.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// The concrete paths matching `pattern` and the values found there
+fn find_values(root: &ConfigValue, pattern: &[&str]) -> Vec<(Vec<String>, ConfigValue)> {
+    let (key, rest) = match pattern.split_first() {
+        Some(split) => split,
+        None => return vec![(Vec::new(), root.clone())],
+    };
+    let children: Vec<(String, &ConfigValue)> = match (*key, root) {
+        ("*", ConfigValue::List(items)) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (i.to_string(), item))
+            .collect(),
+        _ => entry(root, key)
+            .map(|child| vec![(key.to_string(), child)])
+            .unwrap_or_default(),
+    };
+    children
+        .into_iter()
+        .flat_map(|(key, child)| {
+            find_values(child, rest)
+                .into_iter()
+                .map(move |(mut path, value)| {
+                    path.insert(0, key.clone());
+                    (path, value)
+                })
+        })
+        .collect()
+}
+
+/// The value to write in place of `value`, `None` if it is not `old_port`
+fn remapped(value: &ConfigValue, kind: PortValue, old_port: u32, new_port: u32) -> Option<String> {
+    match (kind, value) {
+        (PortValue::Port, ConfigValue::Integer(port)) if *port == old_port as i64 => {
+            Some(new_port.to_string())
+        }
+        (PortValue::Port, ConfigValue::String(port)) if port.trim() == old_port.to_string() => {
+            Some(new_port.to_string())
+        }
+        (PortValue::Address, ConfigValue::String(address)) => {
+            let (host, port) = address.rsplit_once(':')?;
+            (port == old_port.to_string()).then(|| format!("{host}:{new_port}"))
+        }
+        _ => None,
+    }
+}
+
+fn scalar_string(value: &ConfigValue) -> String {
+    match value {
+        ConfigValue::String(s) => s.clone(),
+        ConfigValue::Integer(i) => i.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Rewrite the ports of one file, returning the changes made
+fn remap_file(
+    path_to_instance: &Path,
+    file: &Path,
+    rules: &[(&[&str], PortValue)],
+    old_port: u32,
+    new_port: u32,
+) -> Result<Vec<PortChange>, Error> {
+    let format = match ConfigFormat::from_path(file) {
+        Some(format) => format,
+        None => return Ok(Vec::new()),
+    };
+    let content =
+        std::fs::read_to_string(file).context(format!("Failed to read {}", file.display()))?;
+    // a file that is not valid is left to the user rather than failing the whole remap
+    let parsed = match parse_config(format, &content) {
+        Ok(parsed) => parsed,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let relative_path = file
+        .strip_prefix(path_to_instance)
+        .unwrap_or(file)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let mut changes = Vec::new();
+    let mut edits = Vec::new();
+    for (pattern, kind) in rules {
+        for (path, value) in find_values(&parsed.root, pattern) {
+            if let Some(new_value) = remapped(&value, *kind, old_port, new_port) {
+                edits.push(ConfigEdit {
+                    path: path.clone(),
+                    value: match (kind, &value) {
+                        (PortValue::Port, ConfigValue::Integer(_)) => {
+                            ConfigValue::Integer(new_port as i64)
+                        }
+                        _ => ConfigValue::String(new_value.clone()),
+                    },
+                });
+                changes.push(PortChange {
+                    file: relative_path.clone(),
+                    path,
+                    old_value: scalar_string(&value),
+                    new_value,
+                });
+            }
+        }
+    }
+    if edits.is_empty() {
+        return Ok(changes);
+    }
+    let content = edit_config(format, &content, &edits)?;
+    std::fs::write(file, content).context(format!("Failed to write {}", file.display()))?;
+    Ok(changes)
+}
+
+/// The `name` config files of the Geyser plugin, its directory is named after the platform
+fn geyser_config_files(path_to_instance: &Path, name: &str) -> Vec<PathBuf> {
+    std::fs::read_dir(path_to_instance.join("plugins"))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("Geyser-"))
+                .map(|entry| entry.path().join(name))
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Rewrite `old_port` to `new_port` in the known config files of a server moved to a new port,
+/// like `server.properties` and the configs of Velocity, BungeeCord and Geyser
+pub fn remap_ports(
+    path_to_instance: &Path,
+    old_port: u32,
+    new_port: u32,
+) -> Result<Vec<PortChange>, Error> {
+    let mut changes = Vec::new();
+    if old_port == new_port {
+        return Ok(changes);
+    }
+    let mut files: Vec<(PathBuf, Vec<(&[&str], PortValue)>)> = Vec::new();
+    for (name, pattern, kind) in KNOWN_PORTS {
+        let file = path_to_instance.join(name);
+        match files.iter_mut().find(|(path, _)| path == &file) {
+            Some((_, rules)) => rules.push((pattern, *kind)),
+            None => files.push((file, vec![(pattern, *kind)])),
+        }
+    }
+    for (name, pattern, kind) in GEYSER_PORTS {
+        for file in geyser_config_files(path_to_instance, name) {
+            files.push((file, vec![(pattern, *kind)]));
+        }
+    }
+    for (file, rules) in files {
+        if file.is_file() {
+            changes.extend(remap_file(
+                path_to_instance,
+                &file,
+                &rules,
+                old_port,
+                new_port,
+            )?);
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remap_ports;
+
+    #[test]
+    fn test_remap_ports() {
+        let temp_dir = tempdir::TempDir::new("test_remap_ports").unwrap();
+        let root = temp_dir.path();
+        std::fs::write(
+            root.join("server.properties"),
+            "motd=hi\nserver-port=25565\nquery.port=25565\nrcon.port=25575\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("velocity.toml"),
+            "# proxy\nbind = \"0.0.0.0:25565\"\nmotd = \"hi\"\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("plugins/Geyser-Spigot")).unwrap();
+        std::fs::write(
+            root.join("plugins/Geyser-Spigot/config.yml"),
+            "bedrock:\n  port: 19132\nremote:\n  address: auto\n  port: 25565\n",
+        )
+        .unwrap();
+
+        let changes = remap_ports(root, 25565, 25570).unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(
+            std::fs::read_to_string(root.join("server.properties")).unwrap(),
+            "motd=hi\nserver-port=25570\nquery.port=25570\nrcon.port=25575\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("velocity.toml")).unwrap(),
+            "# proxy\nbind = \"0.0.0.0:25570\"\nmotd = \"hi\"\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("plugins/Geyser-Spigot/config.yml")).unwrap(),
+            "bedrock:\n  port: 19132\nremote:\n  address: auto\n  port: 25570\n"
+        );
+        assert!(changes
+            .iter()
+            .any(|change| change.file == "velocity.toml" && change.new_value == "0.0.0.0:25570"));
+
+        assert!(remap_ports(root, 25565, 25570).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remap_bungeecord_listeners() {
+        let temp_dir = tempdir::TempDir::new("test_remap_bungeecord").unwrap();
+        let root = temp_dir.path();
+        std::fs::write(
+            root.join("config.yml"),
+            "listeners:\n- query_port: 25577\n  host: 0.0.0.0:25577\n  motd: hi\n",
+        )
+        .unwrap();
+        let changes = remap_ports(root, 25577, 25580).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(root.join("config.yml")).unwrap(),
+            "listeners:\n- query_port: 25580\n  host: 0.0.0.0:25580\n  motd: hi\n"
+        );
+    }
+}
@@ -46,6 +46,26 @@ pub fn path_to_tmp() -> &'static PathBuf {
     PATH_TO_TMP.get().unwrap()
 }
 
+static PATH_TO_BACKUPS: OnceCell<PathBuf> = OnceCell::new();
+
+pub fn path_to_backups() -> &'static PathBuf {
+    PATH_TO_BACKUPS.get().unwrap()
+}
+
+static PATH_TO_TRASH: OnceCell<PathBuf> = OnceCell::new();
+
+/// where files deleted outside of instances are kept until purged
+pub fn path_to_trash() -> &'static PathBuf {
+    PATH_TO_TRASH.get().unwrap()
+}
+
+static PATH_TO_ARCHIVED_INSTANCES: OnceCell<PathBuf> = OnceCell::new();
+
+/// where archived instances are kept, out of reach of the restore at startup
+pub fn path_to_archived_instances() -> &'static PathBuf {
+    PATH_TO_ARCHIVED_INSTANCES.get().unwrap()
+}
+
 /// Initialize the paths for the lodestone instance.
 /// This function should only be called once.
 ///
@@ -57,11 +77,16 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let path_to_global_settings = lodestone_path.join("global_settings.json");
     let path_to_users = lodestone_path.join("stores").join("users.json");
     let path_to_tmp = lodestone_path.join("tmp");
+    let path_to_backups = lodestone_path.join("backups");
+    let path_to_trash = lodestone_path.join(".trash");
+    let path_to_archived_instances = lodestone_path.join("archived_instances");
 
     std::fs::create_dir_all(&path_to_instances).unwrap();
     std::fs::create_dir_all(&path_to_binaries).unwrap();
     std::fs::create_dir_all(&path_to_stores).unwrap();
     std::fs::create_dir_all(&path_to_tmp).unwrap();
+    std::fs::create_dir_all(&path_to_backups).unwrap();
+    std::fs::create_dir_all(&path_to_archived_instances).unwrap();
     // std::fs::File::create(&path_to_global_settings).unwrap();
     // std::fs::File::create(&path_to_users).unwrap();
     // std::fs::File::create(&path_to_tmp).unwrap();
@@ -73,6 +98,9 @@ pub fn init_paths(lodestone_path: PathBuf) {
     let _ = PATH_TO_GLOBAL_SETTINGS.set(path_to_global_settings);
     let _ = PATH_TO_USERS.set(path_to_users);
     let _ = PATH_TO_TMP.set(path_to_tmp);
+    let _ = PATH_TO_BACKUPS.set(path_to_backups);
+    let _ = PATH_TO_TRASH.set(path_to_trash);
+    let _ = PATH_TO_ARCHIVED_INSTANCES.set(path_to_archived_instances);
 }
 
 thread_local! {
@@ -99,6 +127,9 @@ lazy_static! {
 
 use crate::generic::GenericInstance;
 use crate::minecraft::MinecraftInstance;
+#[cfg(any(test, feature = "test-harness"))]
+use crate::mock::MockInstance;
+use crate::process::ProcessInstance;
 #[enum_dispatch::enum_dispatch(
     TInstance,
     TConfigurable,
@@ -112,4 +143,7 @@ use crate::minecraft::MinecraftInstance;
 pub enum GameInstance {
     MinecraftInstance,
     GenericInstance,
+    ProcessInstance,
+    #[cfg(any(test, feature = "test-harness"))]
+    MockInstance,
 }
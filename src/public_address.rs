@@ -0,0 +1,305 @@
+//! The address players outside the local network use to reach an instance, and UPnP port
+//! mappings on the router for the instances that ask for them.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::{Event, EventInner, InstanceEvent, InstanceEventInner},
+    instance_map::InstanceMap,
+    traits::{
+        t_configurable::{Game, PublicAddress, TConfigurable},
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+};
+
+/// the public ip rarely changes, and the services answering it are rate limited
+const PUBLIC_IP_TTL: Duration = Duration::from_secs(10 * 60);
+const PUBLIC_IP_TIMEOUT: Duration = Duration::from_secs(5);
+const PUBLIC_IP_URL: &str = "https://api.ipify.org";
+/// mappings are leased so a router doesn't keep forwarding to a core that went away
+const UPNP_LEASE: Duration = Duration::from_secs(60 * 60);
+const UPNP_RENEW_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const MINECRAFT_DEFAULT_PORT: u32 = 25565;
+
+/// Map `port` of this host on the router, a `lease` of 0 maps it until the router restarts
+pub async fn add_port_mapping(
+    port: u16,
+    lease: Duration,
+    description: String,
+) -> Result<(), Error> {
+    tokio::task::spawn_blocking(move || {
+        let local_ip =
+            match local_ip_address::local_ip().context("Could not find local ip address")? {
+                IpAddr::V4(ipv4) => ipv4,
+                IpAddr::V6(_) => return Err(eyre!("UPnP needs an IPv4 local address").into()),
+            };
+        igd::search_gateway(Default::default())
+            .context("Could not find gateway")?
+            .add_port(
+                igd::PortMappingProtocol::TCP,
+                port,
+                SocketAddrV4::new(local_ip, port),
+                lease.as_secs() as u32,
+                &description,
+            )
+            .context("Could not open port")?;
+        Ok(())
+    })
+    .await
+    .context("UPnP task failed")?
+}
+
+pub async fn remove_port_mapping(port: u16) -> Result<(), Error> {
+    tokio::task::spawn_blocking(move || {
+        igd::search_gateway(Default::default())
+            .context("Could not find gateway")?
+            .remove_port(igd::PortMappingProtocol::TCP, port)
+            .context("Could not close port")?;
+        Ok(())
+    })
+    .await
+    .context("UPnP task failed")?
+}
+
+async fn gateway_external_ip() -> Result<IpAddr, Error> {
+    tokio::task::spawn_blocking(|| {
+        Ok(IpAddr::V4(
+            igd::search_gateway(Default::default())
+                .context("Could not find gateway")?
+                .get_external_ip()
+                .context("Gateway did not report its external ip")?,
+        ))
+    })
+    .await
+    .context("UPnP task failed")?
+}
+
+async fn fetch_public_ip(http: &reqwest::Client) -> Result<IpAddr, Error> {
+    let body = http
+        .get(PUBLIC_IP_URL)
+        .timeout(PUBLIC_IP_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to reach the public ip service")?
+        .text()
+        .await
+        .context("Failed to read the public ip")?;
+    Ok(body
+        .trim()
+        .parse()
+        .context("The public ip service answered with something else than an ip")?)
+}
+
+/// 100.64.0.0/10
+fn is_carrier_grade_nat(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    a == 100 && (b & 0b1100_0000) == 64
+}
+
+/// The public ip of the host, as the router sees it or else as the internet does
+#[derive(Clone, Default)]
+pub struct PublicIp {
+    http: reqwest::Client,
+    cached: Arc<Mutex<Option<(IpAddr, Instant)>>>,
+}
+
+impl PublicIp {
+    pub async fn get(&self) -> Result<IpAddr, Error> {
+        let mut cached = self.cached.lock().await;
+        if let Some((ip, fetched_at)) = *cached {
+            if fetched_at.elapsed() < PUBLIC_IP_TTL {
+                return Ok(ip);
+            }
+        }
+        let ip = match gateway_external_ip().await {
+            // a router behind another NAT reports a private or carrier grade NAT address
+            Ok(IpAddr::V4(ip)) if !ip.is_private() && !is_carrier_grade_nat(&ip) => IpAddr::V4(ip),
+            _ => fetch_public_ip(&self.http).await?,
+        };
+        *cached = Some((ip, Instant::now()));
+        Ok(ip)
+    }
+}
+
+/// What to give players to connect to an instance
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct InstanceAddress {
+    /// `host:port`, minecraft instances on the default port leave the port out
+    pub address: String,
+    pub host: String,
+    pub port: u32,
+    /// false if the host is the public ip because no hostname is set
+    pub hostname_configured: bool,
+    pub upnp: bool,
+}
+
+pub fn instance_address(
+    public_address: &PublicAddress,
+    public_ip: Option<IpAddr>,
+    game: &Game,
+    port: u32,
+) -> Option<InstanceAddress> {
+    let host = match (&public_address.hostname, public_ip) {
+        (Some(hostname), _) => hostname.clone(),
+        (None, Some(IpAddr::V6(ip))) => format!("[{ip}]"),
+        (None, Some(ip)) => ip.to_string(),
+        (None, None) => return None,
+    };
+    let is_minecraft = matches!(game, Game::MinecraftJava { .. });
+    let address = if is_minecraft && port == MINECRAFT_DEFAULT_PORT {
+        host.clone()
+    } else {
+        format!("{host}:{port}")
+    };
+    Some(InstanceAddress {
+        address,
+        host,
+        port,
+        hostname_configured: public_address.hostname.is_some(),
+        upnp: public_address.upnp,
+    })
+}
+
+pub async fn map_instance_port(instances: &InstanceMap, instance_uuid: &InstanceUuid) {
+    let instance = match instances.get(instance_uuid) {
+        Some(instance) => instance,
+        None => return,
+    };
+    if !instance.public_address().await.upnp {
+        return;
+    }
+    let port = instance.port().await;
+    let name = instance.name().await;
+    if let Err(e) = add_port_mapping(port as u16, UPNP_LEASE, format!("Lodestone {name}")).await {
+        warn!("Failed to map port {port} of instance {name} with UPnP : {e}");
+    }
+}
+
+pub async fn unmap_instance_port(instances: &InstanceMap, instance_uuid: &InstanceUuid) {
+    let instance = match instances.get(instance_uuid) {
+        Some(instance) => instance,
+        None => return,
+    };
+    if !instance.public_address().await.upnp {
+        return;
+    }
+    let port = instance.port().await;
+    if let Err(e) = remove_port_mapping(port as u16).await {
+        error!("Failed to remove the UPnP mapping of port {port} : {e}");
+    }
+}
+
+/// Maps the ports of the instances with UPnP on while they run, renewing the leases, and
+/// removes the mappings once they stop
+pub async fn upnp_task(mut event_receiver: Receiver<Event>, instances: InstanceMap) {
+    let mut renew = tokio::time::interval(UPNP_RENEW_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = renew.tick() => {
+                let instances = instances.clone();
+                tokio::spawn(async move {
+                    for (instance_uuid, instance) in instances.snapshot() {
+                        if !instance.state().await.is_stopped() {
+                            map_instance_port(&instances, &instance_uuid).await;
+                        }
+                    }
+                });
+            }
+            event = event_receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => {
+                        warn!("UPnP task lagged");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid,
+                    instance_event_inner: InstanceEventInner::StateTransition { to },
+                    ..
+                }) = event.event_inner
+                {
+                    // finding the gateway takes seconds, the task keeps up with events meanwhile
+                    match to {
+                        State::Starting => {
+                            let instances = instances.clone();
+                            tokio::spawn(async move {
+                                map_instance_port(&instances, &instance_uuid).await;
+                            });
+                        }
+                        State::Stopped | State::Crashed => {
+                            let instances = instances.clone();
+                            tokio::spawn(async move {
+                                unmap_instance_port(&instances, &instance_uuid).await;
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{instance_address, is_carrier_grade_nat, PublicAddress};
+    use crate::traits::t_configurable::Game;
+
+    #[test]
+    fn test_instance_address() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let minecraft = Game::MinecraftJava {
+            variant: crate::traits::t_configurable::MinecraftVariant::Vanilla,
+        };
+        let address =
+            instance_address(&PublicAddress::default(), Some(ip), &minecraft, 25565).unwrap();
+        assert_eq!(address.address, "203.0.113.7");
+        assert!(!address.hostname_configured);
+
+        let public_address = PublicAddress {
+            hostname: Some("play.example.com".to_string()),
+            upnp: true,
+        };
+        let address = instance_address(&public_address, None, &minecraft, 25566).unwrap();
+        assert_eq!(address.address, "play.example.com:25566");
+        assert!(address.upnp);
+
+        let ipv6: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            instance_address(&PublicAddress::default(), Some(ipv6), &minecraft, 25566)
+                .unwrap()
+                .address,
+            "[2001:db8::1]:25566"
+        );
+        assert!(instance_address(&PublicAddress::default(), None, &minecraft, 25565).is_none());
+    }
+
+    #[test]
+    fn test_is_carrier_grade_nat() {
+        assert!(is_carrier_grade_nat(&"100.64.0.1".parse().unwrap()));
+        assert!(is_carrier_grade_nat(&"100.127.255.254".parse().unwrap()));
+        assert!(!is_carrier_grade_nat(&"100.128.0.1".parse().unwrap()));
+        assert!(!is_carrier_grade_nat(&"203.0.113.7".parse().unwrap()));
+    }
+}
@@ -0,0 +1,386 @@
+//! Rate limits on the api, per address and per user, and lockouts after failed logins.
+//!
+//! Requests are counted in windows of a minute. The login and setup endpoints have a stricter
+//! limit of their own, and repeated failed logins for a user or from an address lock them out for
+//! a time that doubles with every further failure.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, OriginalUri, State},
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::eyre;
+use tracing::warn;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    events::{CausedBy, Event, EventInner, SecurityEvent, SecurityEventInner},
+    global_settings::RateLimitSettings,
+    types::Snowflake,
+    AppState,
+};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum RateLimitKey {
+    Ip(IpAddr),
+    User(String),
+    AuthIp(IpAddr),
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum LockoutKey {
+    Ip(IpAddr),
+    Username(String),
+}
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+struct LoginFailures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    windows: HashMap<RateLimitKey, Window>,
+    failures: HashMap<LockoutKey, LoginFailures>,
+    last_prune: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            windows: HashMap::new(),
+            failures: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: RateLimitSettings) {
+        self.settings = settings;
+    }
+
+    /// Count a request against `key`, the time until the window ends if it is over `limit`.
+    /// A limit of 0 is no limit
+    fn count(&mut self, key: RateLimitKey, limit: u32, now: Instant) -> Result<(), Duration> {
+        if limit == 0 {
+            return Ok(());
+        }
+        let window = self.windows.entry(key).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.count = 0;
+        }
+        if window.count >= limit {
+            return Err(WINDOW - now.duration_since(window.start));
+        }
+        window.count += 1;
+        Ok(())
+    }
+
+    /// Forget the windows that are over and the failures that are too old to matter
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.last_prune) < WINDOW {
+            return;
+        }
+        self.last_prune = now;
+        self.windows
+            .retain(|_, window| now.duration_since(window.start) < WINDOW);
+        let memory = Duration::from_secs(self.settings.max_lockout_seconds);
+        self.failures.retain(|_, failures| {
+            failures.locked_until.map_or(false, |until| until > now)
+                || now.duration_since(failures.last_failure) < memory
+        });
+    }
+
+    pub fn check_request(
+        &mut self,
+        ip: Option<IpAddr>,
+        user: Option<&UserId>,
+        is_auth_route: bool,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        self.prune(now);
+        if let Some(ip) = ip {
+            if is_auth_route {
+                self.count(
+                    RateLimitKey::AuthIp(ip),
+                    self.settings.auth_requests_per_minute,
+                    now,
+                )?;
+            }
+            self.count(
+                RateLimitKey::Ip(ip),
+                self.settings.requests_per_minute_per_ip,
+                now,
+            )?;
+        }
+        if let Some(user) = user {
+            let user: &str = user.as_ref();
+            self.count(
+                RateLimitKey::User(user.to_string()),
+                self.settings.requests_per_minute_per_user,
+                now,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn lockout_keys(ip: Option<IpAddr>, username: &str) -> Vec<LockoutKey> {
+        let mut keys = vec![LockoutKey::Username(username.to_lowercase())];
+        keys.extend(ip.map(LockoutKey::Ip));
+        keys
+    }
+
+    /// The time left if the user or the address is locked out
+    pub fn check_lockout(
+        &self,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        match Self::lockout_keys(ip, username)
+            .iter()
+            .filter_map(|key| self.failures.get(key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+        {
+            Some(until) => Err(until - now),
+            None => Ok(()),
+        }
+    }
+
+    /// Count a failed login, returns how long the user or the address is locked out for if this
+    /// failure locks them out
+    pub fn record_login_failure(
+        &mut self,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) -> Option<Duration> {
+        if !self.settings.enabled {
+            return None;
+        }
+        let memory = Duration::from_secs(self.settings.max_lockout_seconds);
+        let mut lockout = None;
+        for key in Self::lockout_keys(ip, username) {
+            let failures = self.failures.entry(key).or_insert(LoginFailures {
+                count: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            if now.duration_since(failures.last_failure) >= memory {
+                failures.count = 0;
+            }
+            failures.count += 1;
+            failures.last_failure = now;
+            if failures.count >= self.settings.lockout_threshold {
+                let duration = lockout_duration(&self.settings, failures.count);
+                failures.locked_until = Some(now + duration);
+                lockout = lockout.max(Some(duration));
+            }
+        }
+        lockout
+    }
+
+    pub fn record_login_success(&mut self, ip: Option<IpAddr>, username: &str) {
+        for key in Self::lockout_keys(ip, username) {
+            self.failures.remove(&key);
+        }
+    }
+}
+
+/// The lockout after `failures` failed logins in a row, doubled for every failure past the
+/// threshold
+fn lockout_duration(settings: &RateLimitSettings, failures: u32) -> Duration {
+    let doublings = failures.saturating_sub(settings.lockout_threshold).min(31);
+    Duration::from_secs(
+        settings
+            .lockout_seconds
+            .saturating_mul(1 << doublings)
+            .min(settings.max_lockout_seconds),
+    )
+}
+
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs().max(1);
+    let mut response = Error {
+        kind: ErrorKind::TooManyRequests,
+        source: eyre!("Too many requests, try again in {seconds} seconds"),
+    }
+    .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// The address the request came from, `None` if the server wasn't started with connect info
+pub fn client_ip<B>(request: &Request<B>) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+fn is_auth_route(path: &str) -> bool {
+    path.ends_with("/user/login")
+        || path.ends_with("/user/refresh")
+        || path.ends_with("/user/password/reset")
+        || path.contains("/setup/")
+}
+
+pub async fn rate_limit<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let user = match token {
+        Some(token) => state.users_manager.read().await.try_auth(token),
+        None => None,
+    };
+    let checked = state.rate_limiter.lock().await.check_request(
+        client_ip(&request),
+        user.as_ref().map(|user| &user.uid),
+        is_auth_route(&path),
+        Instant::now(),
+    );
+    match checked {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => too_many_requests(retry_after),
+    }
+}
+
+/// Tell the owner about a lockout, they are what credential stuffing looks like
+pub fn send_lockout_event(
+    state: &AppState,
+    ip: Option<IpAddr>,
+    username: &str,
+    user_id: Option<UserId>,
+    duration: Duration,
+) {
+    let details = format!(
+        "Locked out logins for {username}{} for {} seconds after repeated failures",
+        ip.map(|ip| format!(" and from {ip}")).unwrap_or_default(),
+        duration.as_secs()
+    );
+    warn!("{details}");
+    state.event_broadcaster.send(Event {
+        event_inner: EventInner::SecurityEvent(SecurityEvent {
+            user_id,
+            security_event_inner: SecurityEventInner::LoginLockout {
+                username: username.to_string(),
+                ip: ip.map(|ip| ip.to_string()),
+                seconds: duration.as_secs(),
+            },
+        }),
+        details,
+        snowflake: Snowflake::default(),
+        caused_by: CausedBy::Unknown,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::RateLimiter;
+    use crate::global_settings::RateLimitSettings;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+
+    #[test]
+    fn test_request_limits() {
+        let mut limiter = RateLimiter::new(RateLimitSettings {
+            requests_per_minute_per_ip: 3,
+            auth_requests_per_minute: 2,
+            ..RateLimitSettings::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.check_request(Some(IP), None, true, now).is_ok());
+        assert!(limiter.check_request(Some(IP), None, true, now).is_ok());
+        // the auth limit is stricter
+        assert!(limiter.check_request(Some(IP), None, true, now).is_err());
+        assert!(limiter.check_request(Some(IP), None, false, now).is_ok());
+        assert_eq!(
+            limiter.check_request(Some(IP), None, false, now + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        // a new window starts after a minute
+        assert!(limiter
+            .check_request(Some(IP), None, false, now + Duration::from_secs(60))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_exponential_lockout() {
+        let mut limiter = RateLimiter::new(RateLimitSettings {
+            lockout_threshold: 3,
+            lockout_seconds: 10,
+            max_lockout_seconds: 35,
+            ..RateLimitSettings::default()
+        });
+        let now = Instant::now();
+        assert_eq!(limiter.record_login_failure(Some(IP), "Steve", now), None);
+        assert_eq!(limiter.record_login_failure(Some(IP), "steve", now), None);
+        assert_eq!(
+            limiter.record_login_failure(Some(IP), "steve", now),
+            Some(Duration::from_secs(10))
+        );
+        assert!(limiter.check_lockout(None, "STEVE", now).is_err());
+        assert!(limiter
+            .check_lockout(Some(IP), "alex", now + Duration::from_secs(5))
+            .is_err());
+        assert!(limiter
+            .check_lockout(Some(IP), "steve", now + Duration::from_secs(10))
+            .is_ok());
+        assert_eq!(
+            limiter.record_login_failure(Some(IP), "steve", now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            limiter.record_login_failure(Some(IP), "steve", now),
+            Some(Duration::from_secs(35))
+        );
+
+        limiter.record_login_success(Some(IP), "steve");
+        assert!(limiter.check_lockout(Some(IP), "steve", now).is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event_broadcaster::EventBroadcaster,
+    events::{EventInner, InstanceEvent, InstanceEventInner},
+    traits::t_server::{Readiness, State},
+    types::InstanceUuid,
+};
+
+#[derive(Default)]
+struct Run {
+    started_at: Option<Instant>,
+    startup_duration: Option<Duration>,
+}
+
+/// Tracks when the current run of an instance started and when it became ready
+#[derive(Clone, Default)]
+pub struct ReadinessTracker {
+    run: Arc<Mutex<Run>>,
+}
+
+impl ReadinessTracker {
+    pub fn starting(&self) {
+        *self.run.lock().unwrap() = Run {
+            started_at: Some(Instant::now()),
+            startup_duration: None,
+        };
+    }
+
+    /// Returns how long the start took the first time it is called in a run, so whichever
+    /// detection notices the readiness first reports it
+    pub fn mark_ready(&self) -> Option<Duration> {
+        let mut run = self.run.lock().unwrap();
+        if run.startup_duration.is_some() {
+            return None;
+        }
+        let startup_duration = run.started_at?.elapsed();
+        run.startup_duration = Some(startup_duration);
+        Some(startup_duration)
+    }
+
+    pub fn stopped(&self) {
+        *self.run.lock().unwrap() = Run::default();
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let run = self.run.lock().unwrap();
+        Readiness {
+            ready: run.startup_duration.is_some(),
+            startup_duration: run
+                .startup_duration
+                .map(|duration| duration.as_millis() as u64),
+        }
+    }
+}
+
+/// Whether something listens on `port` of the host
+pub async fn port_accepts_connections(port: u16) -> bool {
+    matches!(
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            tokio::net::TcpStream::connect(("127.0.0.1", port))
+        )
+        .await,
+        Ok(Ok(_))
+    )
+}
+
+/// Wait for the instance to be ready or to stop, whichever comes first. `None` on timeout.
+///
+/// The subscription is made before checking `current`, so a readiness that happens in between
+/// is not missed.
+pub async fn wait_until_ready<F>(
+    event_broadcaster: &EventBroadcaster,
+    instance_uuid: &InstanceUuid,
+    current: F,
+    timeout: Duration,
+) -> Option<Readiness>
+where
+    F: std::future::Future<Output = (State, Readiness)>,
+{
+    let mut rx = event_broadcaster.subscribe();
+    let (state, readiness) = current.await;
+    if readiness.ready || state.is_stopped() {
+        return Some(readiness);
+    }
+    tokio::time::timeout(timeout, async {
+        while let Ok(event) = rx.recv().await {
+            if let EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: event_instance_uuid,
+                instance_event_inner,
+                ..
+            }) = event.event_inner
+            {
+                if &event_instance_uuid != instance_uuid {
+                    continue;
+                }
+                match instance_event_inner {
+                    InstanceEventInner::InstanceReady { startup_duration } => {
+                        return Readiness {
+                            ready: true,
+                            startup_duration: Some(startup_duration),
+                        }
+                    }
+                    InstanceEventInner::StateTransition {
+                        to: State::Stopped | State::Crashed,
+                    } => return Readiness::default(),
+                    _ => {}
+                }
+            }
+        }
+        Readiness::default()
+    })
+    .await
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadinessTracker;
+
+    #[test]
+    fn test_readiness_tracker() {
+        let tracker = ReadinessTracker::default();
+        // not started, so there is nothing to be ready
+        assert!(tracker.mark_ready().is_none());
+        assert!(!tracker.readiness().ready);
+
+        tracker.starting();
+        assert!(!tracker.readiness().ready);
+        assert!(tracker.mark_ready().is_some());
+        assert!(tracker.mark_ready().is_none());
+        let readiness = tracker.readiness();
+        assert!(readiness.ready);
+        assert!(readiness.startup_duration.is_some());
+
+        tracker.stopped();
+        assert!(!tracker.readiness().ready);
+        assert_eq!(tracker.readiness().startup_duration, None);
+    }
+}
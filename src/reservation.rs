@@ -0,0 +1,227 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+use crate::types::InstanceUuid;
+
+/// auto start sets are only enumerated up to this many auto start instances, past it a single
+/// set is picked greedily
+const MAX_ENUMERATED_AUTO_START: usize = 16;
+/// number of safe auto start sets returned
+const MAX_SAFE_SETS: usize = 32;
+/// memory left for the operating system and the core unless given, in megabytes
+pub const DEFAULT_MEMORY_HEADROOM: u64 = 1024;
+
+#[derive(Serialize, Clone, Copy, Debug, TS)]
+#[ts(export)]
+pub struct HostSpecs {
+    /// in megabytes
+    pub total_memory: u64,
+    /// memory left for the operating system and the core, in megabytes
+    pub memory_headroom: u64,
+}
+
+impl HostSpecs {
+    pub fn usable_memory(&self) -> u64 {
+        self.total_memory.saturating_sub(self.memory_headroom)
+    }
+}
+
+/// What an instance is expected to use once started
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct InstanceReservation {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub auto_start: bool,
+    /// in megabytes, `None` if neither a memory limit nor a heap is configured
+    pub memory: Option<u64>,
+    /// percentage of the total CPU capacity of the host, `None` without a CPU limit
+    pub cpu: Option<f32>,
+}
+
+impl InstanceReservation {
+    /// Reservation of a JVM with a `max_heap` megabyte heap, the JVM uses about a third on top of
+    /// the heap, the inverse of how `heap_sizes` caps the heap to a memory limit
+    pub fn jvm_memory(max_heap: u32, memory_limit: Option<u64>) -> u64 {
+        memory_limit.unwrap_or(max_heap as u64 * 4 / 3)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ReservationPlan {
+    pub host: HostSpecs,
+    pub instances: Vec<InstanceReservation>,
+    /// in megabytes, summed over all instances
+    pub reserved_memory: u64,
+    /// megabytes reserved past the usable memory of the host, 0 if everything fits
+    pub memory_oversubscription: u64,
+    pub reserved_cpu: f32,
+    /// percentage of the host CPU capacity reserved past 100, 0 if everything fits
+    pub cpu_oversubscription: f32,
+    pub oversubscribed: bool,
+    /// instances with nothing to reserve, they are left out of the sums
+    pub unknown: Vec<InstanceUuid>,
+    /// whether every auto start instance can run at once
+    pub auto_start_fits: bool,
+    /// the largest sets of auto start instances that fit together, no set is contained in another
+    pub safe_auto_start_sets: Vec<Vec<InstanceUuid>>,
+}
+
+fn fits<'a>(
+    host: &HostSpecs,
+    instances: impl IntoIterator<Item = &'a InstanceReservation>,
+) -> bool {
+    let (memory, cpu) = instances.into_iter().fold((0, 0.0), |(memory, cpu), i| {
+        (memory + i.memory.unwrap_or(0), cpu + i.cpu.unwrap_or(0.0))
+    });
+    memory <= host.usable_memory() && cpu <= 100.0
+}
+
+/// Every maximal subset of `candidates` that fits, largest first
+fn safe_sets(host: &HostSpecs, candidates: &[&InstanceReservation]) -> Vec<Vec<InstanceUuid>> {
+    let to_uuids = |set: Vec<&InstanceReservation>| set.iter().map(|i| i.uuid.clone()).collect();
+    if candidates.len() > MAX_ENUMERATED_AUTO_START {
+        // greedily add the smallest instances first
+        let mut sorted = candidates.to_vec();
+        sorted.sort_by_key(|i| i.memory.unwrap_or(0));
+        let mut set = Vec::new();
+        for candidate in sorted {
+            set.push(candidate);
+            if !fits(host, set.iter().copied()) {
+                set.pop();
+            }
+        }
+        return vec![to_uuids(set)];
+    }
+    let members = |mask: u32| {
+        candidates
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| mask & (1 << index) != 0)
+            .map(|(_, i)| *i)
+    };
+    let mut fitting: Vec<u32> = (0..1u32 << candidates.len())
+        .filter(|mask| fits(host, members(*mask)))
+        .collect();
+    fitting.sort_by_key(|mask| std::cmp::Reverse(mask.count_ones()));
+    let mut maximal: Vec<u32> = Vec::new();
+    for mask in fitting {
+        if !maximal.iter().any(|larger| larger & mask == mask) {
+            maximal.push(mask);
+        }
+    }
+    maximal
+        .into_iter()
+        .take(MAX_SAFE_SETS)
+        .map(|mask| to_uuids(members(mask).collect()))
+        .collect()
+}
+
+pub fn plan_reservations(host: HostSpecs, instances: Vec<InstanceReservation>) -> ReservationPlan {
+    let reserved_memory = instances.iter().filter_map(|i| i.memory).sum();
+    let reserved_cpu = instances.iter().filter_map(|i| i.cpu).sum();
+    let memory_oversubscription = u64::saturating_sub(reserved_memory, host.usable_memory());
+    let cpu_oversubscription = (reserved_cpu - 100.0_f32).max(0.0);
+    let auto_start: Vec<&InstanceReservation> = instances.iter().filter(|i| i.auto_start).collect();
+    let auto_start_fits = fits(&host, auto_start.iter().copied());
+    let safe_auto_start_sets = if auto_start_fits {
+        vec![auto_start.iter().map(|i| i.uuid.clone()).collect()]
+    } else {
+        safe_sets(&host, &auto_start)
+    };
+    ReservationPlan {
+        host,
+        reserved_memory,
+        memory_oversubscription,
+        reserved_cpu,
+        cpu_oversubscription,
+        oversubscribed: memory_oversubscription > 0 || cpu_oversubscription > 0.0,
+        unknown: instances
+            .iter()
+            .filter(|i| i.memory.is_none() && i.cpu.is_none())
+            .map(|i| i.uuid.clone())
+            .collect(),
+        auto_start_fits,
+        safe_auto_start_sets,
+        instances,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan_reservations, HostSpecs, InstanceReservation};
+
+    fn instance(uuid: &str, auto_start: bool, memory: Option<u64>) -> InstanceReservation {
+        InstanceReservation {
+            uuid: uuid.to_string().into(),
+            name: uuid.to_string(),
+            auto_start,
+            memory,
+            cpu: None,
+        }
+    }
+
+    #[test]
+    fn test_jvm_memory() {
+        assert_eq!(InstanceReservation::jvm_memory(3072, None), 4096);
+        assert_eq!(InstanceReservation::jvm_memory(3072, Some(2048)), 2048);
+    }
+
+    #[test]
+    fn test_plan_fits() {
+        let host = HostSpecs {
+            total_memory: 16384,
+            memory_headroom: 2048,
+        };
+        let plan = plan_reservations(
+            host,
+            vec![
+                instance("a", true, Some(4096)),
+                instance("b", false, Some(4096)),
+                instance("c", true, None),
+            ],
+        );
+        assert_eq!(plan.reserved_memory, 8192);
+        assert!(!plan.oversubscribed);
+        assert!(plan.auto_start_fits);
+        assert_eq!(plan.unknown, vec!["c"]);
+        assert_eq!(plan.safe_auto_start_sets, vec![vec!["a", "c"]]);
+    }
+
+    #[test]
+    fn test_plan_oversubscribed() {
+        let host = HostSpecs {
+            total_memory: 8192,
+            memory_headroom: 1024,
+        };
+        let mut cpu_heavy = instance("d", true, Some(1024));
+        cpu_heavy.cpu = Some(80.0);
+        let mut cpu_heavy_too = instance("e", false, None);
+        cpu_heavy_too.cpu = Some(50.0);
+        let plan = plan_reservations(
+            host,
+            vec![
+                instance("a", true, Some(4096)),
+                instance("b", true, Some(3072)),
+                instance("c", true, Some(2048)),
+                cpu_heavy,
+                cpu_heavy_too,
+            ],
+        );
+        assert_eq!(plan.reserved_memory, 10240);
+        assert_eq!(plan.memory_oversubscription, 3072);
+        assert_eq!(plan.cpu_oversubscription, 30.0);
+        assert!(plan.oversubscribed);
+        assert!(!plan.auto_start_fits);
+        let sets: Vec<Vec<String>> = plan
+            .safe_auto_start_sets
+            .iter()
+            .map(|set| set.iter().map(|uuid| uuid.to_string()).collect())
+            .collect();
+        assert_eq!(sets.len(), 3);
+        assert!(sets.contains(&vec!["a".to_string(), "b".to_string()]));
+        assert!(sets.contains(&vec!["a".to_string(), "c".to_string(), "d".to_string()]));
+        assert!(sets.contains(&vec!["b".to_string(), "c".to_string(), "d".to_string()]));
+    }
+}
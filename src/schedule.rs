@@ -0,0 +1,195 @@
+//! Schedules on the wall clock, e.g. every day at 04:00 in `Europe/Berlin`.
+//!
+//! Runs are worked out in the timezone of the schedule, so they stay at the same local time
+//! across daylight saving changes: a time skipped when the clocks go forward runs right after
+//! the gap, and a time repeated when they go back runs once.
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+/// how far past a time skipped by the clocks going forward is looked at, gaps are an hour or less
+/// almost everywhere
+const MAX_GAP_MINUTES: i64 = 3 * 60;
+
+pub fn parse_timezone(name: &str) -> Result<Tz, Error> {
+    name.parse::<Tz>().map_err(|_| Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!("Unknown timezone {name}, expected an IANA name like Europe/Berlin"),
+    })
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct WallClockSchedule {
+    /// `HH:MM`, on a 24 hour clock
+    pub time: String,
+    /// ISO weekdays it runs on, 1 is Monday and 7 is Sunday. Every day if empty
+    #[serde(default)]
+    pub weekdays: Vec<u32>,
+    /// IANA name, e.g. `Europe/Berlin`, the default timezone of the core if `None`
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+/// The instant a local time happens: the first one if the clocks go back over it, and the end
+/// of the gap if they go forward over it
+fn local_to_utc(timezone: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    (0..=MAX_GAP_MINUTES).step_by(15).find_map(|minutes| {
+        match timezone.from_local_datetime(&(local + Duration::minutes(minutes))) {
+            LocalResult::Single(run) => Some(run.with_timezone(&Utc)),
+            LocalResult::Ambiguous(earliest, _) => Some(earliest.with_timezone(&Utc)),
+            LocalResult::None => None,
+        }
+    })
+}
+
+impl WallClockSchedule {
+    fn parse_time(&self) -> Result<NaiveTime, Error> {
+        NaiveTime::parse_from_str(&self.time, "%H:%M").map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid time {}, expected HH:MM", self.time),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        self.parse_time()?;
+        if let Some(weekday) = self.weekdays.iter().find(|day| !(1..=7).contains(*day)) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid weekday {weekday}, expected 1 (Monday) to 7 (Sunday)"),
+            });
+        }
+        if let Some(timezone) = &self.timezone {
+            parse_timezone(timezone)?;
+        }
+        Ok(())
+    }
+
+    /// The first run strictly after `after`
+    pub fn next_run(
+        &self,
+        after: DateTime<Utc>,
+        default_timezone: Tz,
+    ) -> Result<DateTime<Utc>, Error> {
+        let time = self.parse_time()?;
+        let timezone = match &self.timezone {
+            Some(timezone) => parse_timezone(timezone)?,
+            None => default_timezone,
+        };
+        let today = after.with_timezone(&timezone).date_naive();
+        // today's run may have passed, so a week and a day covers every weekday
+        for days in 0..=7 {
+            let date = today + Duration::days(days);
+            if !self.weekdays.is_empty()
+                && !self.weekdays.contains(&date.weekday().number_from_monday())
+            {
+                continue;
+            }
+            if let Some(run) = local_to_utc(timezone, date.and_time(time)) {
+                if run > after {
+                    return Ok(run);
+                }
+            }
+        }
+        Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The schedule never runs"),
+        })
+    }
+
+    /// Whether a run came due after `last_run` and no later than `now`
+    pub fn is_due(
+        &self,
+        last_run: DateTime<Utc>,
+        now: DateTime<Utc>,
+        default_timezone: Tz,
+    ) -> Result<bool, Error> {
+        Ok(self.next_run(last_run, default_timezone)? <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{parse_timezone, WallClockSchedule};
+
+    fn schedule(time: &str, weekdays: &[u32], timezone: Option<&str>) -> WallClockSchedule {
+        WallClockSchedule {
+            time: time.to_string(),
+            weekdays: weekdays.to_vec(),
+            timezone: timezone.map(ToString::to_string),
+        }
+    }
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_validate_schedule() {
+        assert!(schedule("04:00", &[1, 7], Some("Europe/Berlin"))
+            .validate()
+            .is_ok());
+        assert!(schedule("24:00", &[], None).validate().is_err());
+        assert!(schedule("4am", &[], None).validate().is_err());
+        assert!(schedule("04:00", &[0], None).validate().is_err());
+        assert!(schedule("04:00", &[], Some("Mars/Olympus"))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_next_run_across_dst() {
+        let utc_tz = parse_timezone("UTC").unwrap();
+        let berlin = schedule("02:30", &[], Some("Europe/Berlin"));
+        // 02:30 doesn't exist on 2023-03-26, the run happens when the clocks reach 03:00 CEST
+        assert_eq!(
+            berlin.next_run(utc(2023, 3, 25, 12, 0), utc_tz).unwrap(),
+            utc(2023, 3, 26, 1, 0)
+        );
+        assert_eq!(
+            berlin.next_run(utc(2023, 3, 26, 1, 0), utc_tz).unwrap(),
+            utc(2023, 3, 27, 0, 30)
+        );
+        // 02:30 happens twice on 2023-10-29, only the first one runs
+        assert_eq!(
+            berlin.next_run(utc(2023, 10, 28, 12, 0), utc_tz).unwrap(),
+            utc(2023, 10, 29, 0, 30)
+        );
+        assert_eq!(
+            berlin.next_run(utc(2023, 10, 29, 0, 30), utc_tz).unwrap(),
+            utc(2023, 10, 30, 1, 30)
+        );
+    }
+
+    #[test]
+    fn test_next_run_weekdays_and_default_timezone() {
+        let new_york = parse_timezone("America/New_York").unwrap();
+        // 2023-10-25 is a Wednesday
+        assert_eq!(
+            schedule("04:00", &[1], Some("UTC"))
+                .next_run(utc(2023, 10, 25, 12, 0), new_york)
+                .unwrap(),
+            utc(2023, 10, 30, 4, 0)
+        );
+        assert_eq!(
+            schedule("12:00", &[], None)
+                .next_run(utc(2023, 7, 1, 0, 0), new_york)
+                .unwrap(),
+            utc(2023, 7, 1, 16, 0)
+        );
+        assert!(schedule("12:00", &[], None)
+            .is_due(utc(2023, 7, 1, 0, 0), utc(2023, 7, 1, 16, 0), new_york)
+            .unwrap());
+        assert!(!schedule("12:00", &[], None)
+            .is_due(utc(2023, 7, 1, 16, 0), utc(2023, 7, 2, 15, 59), new_york)
+            .unwrap());
+    }
+}
@@ -0,0 +1,78 @@
+//! Standard security headers on every response of the API. It only serves JSON and files, so
+//! nothing it sends needs to be framed, sniffed or allowed to load anything.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+
+/// a year, the usual for HSTS
+const HSTS_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/// Add the security headers a response doesn't set itself. `hsts` only over TLS, a browser
+/// told to use https for a core only serving http could no longer reach it
+fn apply_security_headers(headers: &mut HeaderMap, hsts: bool) {
+    let mut defaults = vec![
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (header::X_FRAME_OPTIONS, "DENY".to_string()),
+        (header::REFERRER_POLICY, "no-referrer".to_string()),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; frame-ancestors 'none'".to_string(),
+        ),
+    ];
+    if hsts {
+        defaults.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            format!("max-age={HSTS_MAX_AGE}"),
+        ));
+    }
+    for (name, value) in defaults {
+        if !headers.contains_key(&name) {
+            // the values are ascii
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+pub async fn security_headers<B>(
+    State(hsts): State<bool>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    apply_security_headers(response.headers_mut(), hsts);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::apply_security_headers;
+
+    #[test]
+    fn test_apply_security_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        );
+        apply_security_headers(&mut headers, false);
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        // set by the handler
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "sandbox");
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+        apply_security_headers(&mut headers, true);
+        assert_eq!(
+            headers[header::STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000"
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! Updating the core to a newer release.
+//!
+//! The releases of the core on GitHub are checked for a newer version with a build for this
+//! platform. Its binary is downloaded into the binaries directory, checked against the SHA-256
+//! checksum published next to it, and swapped in for the running one before a graceful restart.
+//! The previous binary is kept next to it with an `.old` extension. If the release also ships a
+//! dashboard bundle, it is unpacked into `web` in the binaries directory.
+//!
+//! The checksum comes from the same release as the binary, so it only catches a corrupted or
+//! truncated download. It is an integrity check, not a proof of where the binary comes from, the
+//! release itself is trusted as much as the GitHub account publishing it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    download_manager::{Checksum, DownloadManager, DownloadRequest},
+    error::{Error, ErrorKind},
+    jobs::JobHandle,
+    prelude::{path_to_binaries, VERSION},
+    util::{unzip_file_async, UnzipOption},
+};
+
+/// where the releases of the core are listed
+pub const RELEASE_FEED_URL: &str =
+    "https://api.github.com/repos/Lodestone-Team/lodestone_core/releases";
+/// how often the feed is checked in the background
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const DASHBOARD_ASSET_PREFIX: &str = "lodestone_dashboard_";
+
+#[derive(Deserialize, Clone, Debug)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+    html_url: String,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    published_at: Option<String>,
+    #[serde(default)]
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+    #[serde(default)]
+    size: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct AvailableUpdate {
+    pub version: String,
+    pub prerelease: bool,
+    pub published_at: Option<String>,
+    pub release_notes: Option<String>,
+    /// the page of the release
+    pub url: String,
+    /// of the binary for this platform, in bytes
+    pub size: u64,
+    /// the release also ships a dashboard bundle
+    pub includes_dashboard: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct UpdateStatus {
+    pub current_version: String,
+    /// the newest release with a build for this platform, if it is newer than the running core
+    pub available: Option<AvailableUpdate>,
+    /// when the feed was last checked, never if `None`
+    pub checked_at: Option<i64>,
+    /// why the last check failed
+    pub last_error: Option<String>,
+}
+
+/// What has to be downloaded to install an update, the checksums are separate assets named after
+/// the file they are of with a `.sha256` extension
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct UpdatePlan {
+    update: AvailableUpdate,
+    core: (ReleaseAsset, ReleaseAsset),
+    dashboard: Option<(ReleaseAsset, ReleaseAsset)>,
+}
+
+/// The name of the binary of `version` for a platform, as the release workflow names them
+fn core_asset_name(os: &str, arch: &str, version: &Version) -> String {
+    let postfix = if os == "windows" { ".exe" } else { "" };
+    format!("lodestone_core_{os}_{arch}_v{version}{postfix}")
+}
+
+/// The os of the platform the core runs on, as used in the names of the binaries
+fn current_os() -> Option<&'static str> {
+    match std::env::consts::OS {
+        "windows" => Some("windows"),
+        "linux" => Some("linux"),
+        "macos" => Some("macos"),
+        _ => None,
+    }
+}
+
+/// The hash of a checksum file, either only the hash or the `sha256sum` format
+fn parse_checksum(content: &str) -> Option<String> {
+    let hash = content.split_whitespace().next()?.to_lowercase();
+    (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())).then_some(hash)
+}
+
+fn with_checksum(assets: &[ReleaseAsset], name: &str) -> Option<(ReleaseAsset, ReleaseAsset)> {
+    let asset = assets.iter().find(|asset| asset.name == name)?;
+    let checksum = assets
+        .iter()
+        .find(|asset| asset.name == format!("{name}.sha256"))?;
+    Some((asset.clone(), checksum.clone()))
+}
+
+/// The newest release newer than `current` with a build for the platform that has a checksum
+fn plan_update(
+    releases: &[Release],
+    current: &Version,
+    include_prereleases: bool,
+    os: &str,
+    arch: &str,
+) -> Option<UpdatePlan> {
+    releases
+        .iter()
+        .filter(|release| !release.draft && (include_prereleases || !release.prerelease))
+        .filter_map(|release| {
+            let version = Version::parse(release.tag_name.trim_start_matches('v')).ok()?;
+            if &version <= current {
+                return None;
+            }
+            let core = with_checksum(&release.assets, &core_asset_name(os, arch, &version))?;
+            let dashboard = release
+                .assets
+                .iter()
+                .find(|asset| {
+                    asset.name.starts_with(DASHBOARD_ASSET_PREFIX) && asset.name.ends_with(".zip")
+                })
+                .and_then(|asset| with_checksum(&release.assets, &asset.name));
+            Some((version, release, core, dashboard))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(version, release, core, dashboard)| UpdatePlan {
+            update: AvailableUpdate {
+                version: version.to_string(),
+                prerelease: release.prerelease,
+                published_at: release.published_at.clone(),
+                release_notes: release.body.clone(),
+                url: release.html_url.clone(),
+                size: core.0.size,
+                includes_dashboard: dashboard.is_some(),
+            },
+            core,
+            dashboard,
+        })
+}
+
+struct UpdaterInner {
+    status: UpdateStatus,
+    plan: Option<UpdatePlan>,
+}
+
+/// Checks the release feed and installs updates, one at a time
+#[derive(Clone)]
+pub struct Updater {
+    http: reqwest::Client,
+    feed_url: String,
+    inner: Arc<Mutex<UpdaterInner>>,
+    installing: Arc<Mutex<()>>,
+}
+
+impl Updater {
+    pub fn new(feed_url: String) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                // required by the GitHub api
+                .user_agent(format!(
+                    "lodestone_core/{}",
+                    VERSION.with(|v| v.to_string())
+                ))
+                .build()
+                .unwrap_or_default(),
+            feed_url,
+            inner: Arc::new(Mutex::new(UpdaterInner {
+                status: UpdateStatus {
+                    current_version: VERSION.with(|v| v.to_string()),
+                    available: None,
+                    checked_at: None,
+                    last_error: None,
+                },
+                plan: None,
+            })),
+            installing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The result of the last check
+    pub async fn status(&self) -> UpdateStatus {
+        self.inner.lock().await.status.clone()
+    }
+
+    async fn fetch_plan(&self, include_prereleases: bool) -> Result<Option<UpdatePlan>, Error> {
+        let releases: Vec<Release> = self
+            .http
+            .get(&self.feed_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to fetch the release feed")?
+            .json()
+            .await
+            .context("Failed to parse the release feed")?;
+        let os = current_os().ok_or_else(|| Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("No builds are published for this platform"),
+        })?;
+        Ok(plan_update(
+            &releases,
+            &VERSION.with(|v| v.clone()),
+            include_prereleases,
+            os,
+            std::env::consts::ARCH,
+        ))
+    }
+
+    /// Check the feed for a newer release
+    pub async fn check(&self, include_prereleases: bool) -> Result<UpdateStatus, Error> {
+        let result = self.fetch_plan(include_prereleases).await;
+        let mut inner = self.inner.lock().await;
+        inner.status.checked_at = Some(chrono::Utc::now().timestamp());
+        match result {
+            Ok(plan) => {
+                inner.status.available = plan.as_ref().map(|plan| plan.update.clone());
+                inner.status.last_error = None;
+                inner.plan = plan;
+                Ok(inner.status.clone())
+            }
+            Err(e) => {
+                inner.status.last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Download `asset` into `dir`, failing if it doesn't match its checksum
+    async fn download_checked(
+        &self,
+        (asset, checksum): &(ReleaseAsset, ReleaseAsset),
+        dir: &Path,
+        job: &JobHandle,
+        progress: (f64, f64),
+    ) -> Result<PathBuf, Error> {
+        let content = self
+            .http
+            .get(&checksum.browser_download_url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context(format!("Failed to fetch the checksum of {}", asset.name))?
+            .text()
+            .await
+            .context(format!("Failed to fetch the checksum of {}", asset.name))?;
+        let hash = parse_checksum(&content)
+            .ok_or_else(|| eyre!("The checksum of {} is malformed", asset.name))?;
+        let (from, to) = progress;
+        let name = asset.name.clone();
+        DownloadManager::global()
+            .download(
+                DownloadRequest::new(&asset.browser_download_url, dir)
+                    .name(&asset.name)
+                    .checksum(Some(Checksum::Sha256(hash))),
+                &|download| {
+                    let fraction = download
+                        .total
+                        .map(|total| download.downloaded as f64 / total.max(1) as f64)
+                        .unwrap_or(0.0);
+                    job.set_progress(
+                        from + (to - from) * fraction,
+                        Some(format!("Downloading {name}")),
+                    );
+                },
+            )
+            .await
+    }
+
+    /// Download the release `version`, which must be the one found by the last check, and swap it
+    /// in for the running binary. The core has to be restarted for it to take effect
+    pub async fn install(&self, version: &str, job: &JobHandle) -> Result<(), Error> {
+        let _installing = self.installing.try_lock().map_err(|_| Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("An update is already being installed"),
+        })?;
+        let plan = self
+            .inner
+            .lock()
+            .await
+            .plan
+            .clone()
+            .filter(|plan| plan.update.version == version)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{version} is not the update found by the last check"),
+            })?;
+        let dir = path_to_binaries().join("core").join(version);
+        crate::util::fs::create_dir_all(&dir).await?;
+        let binary = self
+            .download_checked(&plan.core, &dir, job, (0.0, 70.0))
+            .await?;
+        if job.is_cancelled() {
+            return Err(eyre!("Update cancelled").into());
+        }
+        if let Some(dashboard) = &plan.dashboard {
+            let bundle = self
+                .download_checked(dashboard, &dir, job, (70.0, 90.0))
+                .await?;
+            if job.is_cancelled() {
+                return Err(eyre!("Update cancelled").into());
+            }
+            job.set_progress(90.0, Some("Installing the dashboard".to_string()));
+            install_dashboard(&bundle, &path_to_binaries().join("web")).await?;
+        }
+        job.set_progress(95.0, Some("Replacing the core binary".to_string()));
+        let exe = crate::shutdown::executable().context("Failed to find the running binary")?;
+        replace_binary(&binary, &exe).await?;
+        info!("Updated Lodestone Core to {version}, restart to run it");
+        Ok(())
+    }
+}
+
+/// Unpack the dashboard bundle into `web`, the previous dashboard is only removed once the new
+/// one is in place
+async fn install_dashboard(bundle: &Path, web: &Path) -> Result<(), Error> {
+    let staged = web.with_extension("new");
+    let old = web.with_extension("old");
+    for dir in [&staged, &old] {
+        if dir.exists() {
+            crate::util::fs::remove_dir_all(dir).await?;
+        }
+    }
+    unzip_file_async(bundle, UnzipOption::ToDir(staged.clone())).await?;
+    if web.exists() {
+        crate::util::fs::rename(web, &old).await?;
+    }
+    if let Err(e) = crate::util::fs::rename(&staged, web).await {
+        if old.exists() {
+            let _ = crate::util::fs::rename(&old, web).await;
+        }
+        return Err(e);
+    }
+    if old.exists() {
+        if let Err(e) = crate::util::fs::remove_dir_all(&old).await {
+            error!("Failed to remove the previous dashboard : {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Put `binary` in place of `exe`, keeping `exe` with an `.old` extension. A running binary can
+/// be renamed but not overwritten on Windows, so it is moved away rather than copied
+async fn replace_binary(binary: &Path, exe: &Path) -> Result<(), Error> {
+    let file_name = exe
+        .file_name()
+        .ok_or_else(|| eyre!("The running binary has no name"))?
+        .to_string_lossy()
+        .to_string();
+    let staged = exe.with_file_name(format!("{file_name}.new"));
+    let old = exe.with_file_name(format!("{file_name}.old"));
+    tokio::fs::copy(binary, &staged).await.context(format!(
+        "Failed to copy the new binary next to {}",
+        exe.display()
+    ))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))
+            .await
+            .context("Failed to make the new binary executable")?;
+    }
+    if old.exists() {
+        crate::util::fs::remove_file(&old).await?;
+    }
+    crate::util::fs::rename(exe, &old).await?;
+    if let Err(e) = crate::util::fs::rename(&staged, exe).await {
+        let _ = crate::util::fs::rename(&old, exe).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Checks the feed once a day so the dashboard can tell the owner about new releases
+pub async fn update_check_task(updater: Updater) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match updater.check(false).await {
+            Ok(UpdateStatus {
+                available: Some(update),
+                ..
+            }) => info!(
+                "Lodestone Core {} is available, see {}",
+                update.version, update.url
+            ),
+            Ok(_) => {}
+            Err(e) => error!("Failed to check for updates : {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::{core_asset_name, parse_checksum, plan_update, Release, ReleaseAsset};
+
+    fn asset(name: &str) -> ReleaseAsset {
+        ReleaseAsset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{name}"),
+            size: 1024,
+        }
+    }
+
+    fn release(tag: &str, prerelease: bool, assets: &[&str]) -> Release {
+        Release {
+            tag_name: tag.to_string(),
+            body: None,
+            html_url: format!("https://example.com/releases/{tag}"),
+            prerelease,
+            draft: false,
+            published_at: None,
+            assets: assets.iter().map(|name| asset(name)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_plan_update() {
+        let current = Version::new(0, 4, 4);
+        assert_eq!(
+            core_asset_name("windows", "x86_64", &Version::new(0, 5, 0)),
+            "lodestone_core_windows_x86_64_v0.5.0.exe"
+        );
+        let releases = vec![
+            release(
+                "v0.4.4",
+                false,
+                &[
+                    "lodestone_core_linux_x86_64_v0.4.4",
+                    "lodestone_core_linux_x86_64_v0.4.4.sha256",
+                ],
+            ),
+            release(
+                "v0.5.0",
+                false,
+                &[
+                    "lodestone_core_linux_x86_64_v0.5.0",
+                    "lodestone_core_linux_x86_64_v0.5.0.sha256",
+                    "lodestone_dashboard_v0.5.0.zip",
+                    "lodestone_dashboard_v0.5.0.zip.sha256",
+                ],
+            ),
+            // no checksum, so the download can't be checked
+            release("v0.5.1", false, &["lodestone_core_linux_x86_64_v0.5.1"]),
+            release(
+                "v0.6.0-beta.1",
+                true,
+                &[
+                    "lodestone_core_linux_x86_64_v0.6.0-beta.1",
+                    "lodestone_core_linux_x86_64_v0.6.0-beta.1.sha256",
+                ],
+            ),
+        ];
+
+        let plan = plan_update(&releases, &current, false, "linux", "x86_64").unwrap();
+        assert_eq!(plan.update.version, "0.5.0");
+        assert!(plan.update.includes_dashboard);
+        assert_eq!(plan.core.0.name, "lodestone_core_linux_x86_64_v0.5.0");
+        assert_eq!(
+            plan.core.1.name,
+            "lodestone_core_linux_x86_64_v0.5.0.sha256"
+        );
+
+        let plan = plan_update(&releases, &current, true, "linux", "x86_64").unwrap();
+        assert_eq!(plan.update.version, "0.6.0-beta.1");
+        assert!(!plan.update.includes_dashboard);
+
+        assert_eq!(
+            plan_update(&releases, &current, false, "macos", "x86_64"),
+            None
+        );
+        assert_eq!(
+            plan_update(&releases, &Version::new(0, 5, 0), false, "linux", "x86_64"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(parse_checksum(hash).unwrap(), hash);
+        assert_eq!(
+            parse_checksum(&format!(
+                "{}  lodestone_core_linux_x86_64_v0.5.0\n",
+                hash.to_uppercase()
+            ))
+            .unwrap(),
+            hash
+        );
+        assert_eq!(parse_checksum("not a hash"), None);
+        assert_eq!(parse_checksum(""), None);
+    }
+}
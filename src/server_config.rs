@@ -0,0 +1,335 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+};
+
+use axum::http::{header, HeaderValue, Method};
+use color_eyre::eyre::{eyre, Context};
+use serde::Deserialize;
+use tower_http::cors::{Any, CorsLayer};
+
+use crate::error::{Error, ErrorKind};
+
+static CONFIG_FILE_NAME: &str = "lodestone_config.json";
+
+pub const DEFAULT_PORT: u16 = 16_662;
+
+/// Startup configuration of the API server, read from `lodestone_config.json` in the lodestone path.
+///
+/// Every field can be overridden by an environment variable: `LODESTONE_BIND_ADDRESS`,
+/// `LODESTONE_PORT`, `LODESTONE_TLS_CERT`, `LODESTONE_TLS_KEY`, `LODESTONE_SHUTDOWN_GRACE_PERIOD`,
+/// `LODESTONE_READ_ONLY_BIND_ADDRESS`, `LODESTONE_READ_ONLY_PORT`, `LODESTONE_CORS_ENABLED`,
+/// `LODESTONE_CORS_ALLOWED_ORIGINS` (comma separated) and `LODESTONE_SECURITY_HEADERS`.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    /// defaults to 16662, in debug builds the next free port is used if it is taken
+    pub port: Option<u16>,
+    /// PEM certificate chain, relative paths are resolved from the lodestone path
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key, relative paths are resolved from the lodestone path
+    pub tls_key: Option<PathBuf>,
+    /// seconds running instances are given to stop on shutdown before they are killed
+    pub shutdown_grace_period: u64,
+    pub read_only_bind_address: IpAddr,
+    /// serve the read only routes, like status and monitoring, on this port as well.
+    /// The full API is then only bound to localhost unless `bind_address` says otherwise
+    pub read_only_port: Option<u16>,
+    /// answer cross origin requests, turned off when a reverse proxy in front of the core
+    /// handles CORS itself
+    pub cors_enabled: bool,
+    /// origins the API can be called from, e.g. `https://www.lodestone.cc`, any origin if not set
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// send headers like `X-Content-Type-Options`, and `Strict-Transport-Security` over TLS
+    pub security_headers: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: None,
+            tls_cert: None,
+            tls_key: None,
+            shutdown_grace_period: 30,
+            read_only_bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            read_only_port: None,
+            cors_enabled: true,
+            cors_allowed_origins: None,
+            security_headers: true,
+        }
+    }
+}
+
+fn bad_config(msg: String) -> Error {
+    Error {
+        kind: ErrorKind::BadRequest,
+        source: eyre!(msg),
+    }
+}
+
+impl ServerConfig {
+    pub async fn load(lodestone_path: &Path) -> Result<Self, Error> {
+        let path = lodestone_path.join(CONFIG_FILE_NAME);
+        let config = if path.is_file() {
+            let content = crate::util::fs::read_to_string(&path).await?;
+            serde_json::from_str(&content).context(format!("Failed to parse {}", path.display()))?
+        } else {
+            ServerConfig::default()
+        };
+        config.with_overrides(|key| std::env::var(key).ok())
+    }
+
+    fn with_overrides(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        if let Some(bind_address) = var("LODESTONE_BIND_ADDRESS") {
+            self.bind_address = bind_address
+                .parse()
+                .map_err(|_| bad_config(format!("Invalid bind address {bind_address}")))?;
+        }
+        if let Some(port) = var("LODESTONE_PORT") {
+            self.port = Some(
+                port.parse()
+                    .map_err(|_| bad_config(format!("Invalid port {port}")))?,
+            );
+        }
+        if let Some(tls_cert) = var("LODESTONE_TLS_CERT") {
+            self.tls_cert = Some(PathBuf::from(tls_cert));
+        }
+        if let Some(tls_key) = var("LODESTONE_TLS_KEY") {
+            self.tls_key = Some(PathBuf::from(tls_key));
+        }
+        if let Some(grace_period) = var("LODESTONE_SHUTDOWN_GRACE_PERIOD") {
+            self.shutdown_grace_period = grace_period
+                .parse()
+                .map_err(|_| bad_config(format!("Invalid shutdown grace period {grace_period}")))?;
+        }
+        if let Some(bind_address) = var("LODESTONE_READ_ONLY_BIND_ADDRESS") {
+            self.read_only_bind_address = bind_address.parse().map_err(|_| {
+                bad_config(format!("Invalid read only bind address {bind_address}"))
+            })?;
+        }
+        if let Some(port) = var("LODESTONE_READ_ONLY_PORT") {
+            self.read_only_port = Some(
+                port.parse()
+                    .map_err(|_| bad_config(format!("Invalid read only port {port}")))?,
+            );
+        }
+        if let Some(cors_enabled) = var("LODESTONE_CORS_ENABLED") {
+            self.cors_enabled = cors_enabled
+                .parse()
+                .map_err(|_| bad_config(format!("Invalid CORS toggle {cors_enabled}")))?;
+        }
+        if let Some(origins) = var("LODESTONE_CORS_ALLOWED_ORIGINS") {
+            self.cors_allowed_origins = Some(
+                origins
+                    .split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect(),
+            );
+        }
+        if let Some(security_headers) = var("LODESTONE_SECURITY_HEADERS") {
+            self.security_headers = security_headers.parse().map_err(|_| {
+                bad_config(format!(
+                    "Invalid security headers toggle {security_headers}"
+                ))
+            })?;
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Also checked after the command line overrides the config
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(bad_config(
+                "Both a TLS certificate and a key are required".to_string(),
+            ));
+        }
+        if self.read_only_port.is_some()
+            && self.read_only_port == Some(self.port.unwrap_or(DEFAULT_PORT))
+        {
+            return Err(bad_config(
+                "The read only API needs a port of its own".to_string(),
+            ));
+        }
+        for origin in self.cors_allowed_origins.iter().flatten() {
+            // browsers send the origin without a path, so one with a path never matches
+            let is_origin = origin
+                .trim_end_matches('/')
+                .strip_prefix("https://")
+                .or_else(|| origin.trim_end_matches('/').strip_prefix("http://"))
+                .is_some_and(|host| !host.is_empty() && !host.contains(['/', '?', '#']));
+            if !is_origin || HeaderValue::from_str(origin).is_err() {
+                return Err(bad_config(format!("Invalid CORS origin {origin}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the full API listens, localhost when the read only API is what faces the network
+    /// and no address was picked
+    pub fn api_bind_address(&self) -> IpAddr {
+        if self.read_only_port.is_some() && self.bind_address.is_unspecified() {
+            match self.bind_address {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            }
+        } else {
+            self.bind_address
+        }
+    }
+
+    /// The CORS layer of the API, `None` if the core doesn't answer cross origin requests
+    pub fn cors_layer(&self) -> Option<CorsLayer> {
+        if !self.cors_enabled {
+            return None;
+        }
+        let cors = CorsLayer::new()
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PATCH,
+                Method::PUT,
+                Method::DELETE,
+                Method::OPTIONS,
+            ])
+            .allow_headers([header::ORIGIN, header::CONTENT_TYPE, header::AUTHORIZATION]);
+        Some(match &self.cors_allowed_origins {
+            Some(origins) => cors.allow_origin(
+                origins
+                    .iter()
+                    // checked in `validate`
+                    .filter_map(|origin| HeaderValue::from_str(origin.trim_end_matches('/')).ok())
+                    .collect::<Vec<_>>(),
+            ),
+            None => cors.allow_origin(Any),
+        })
+    }
+
+    /// Whether TLS was configured explicitly, in which case failing to load it is fatal
+    pub fn tls_configured(&self) -> bool {
+        self.tls_cert.is_some()
+    }
+
+    /// Paths to the certificate and key, `tls/cert.pem` and `tls/key.pem` if not configured
+    pub fn tls_paths(&self, lodestone_path: &Path) -> (PathBuf, PathBuf) {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (lodestone_path.join(cert), lodestone_path.join(key)),
+            _ => (
+                lodestone_path.join("tls").join("cert.pem"),
+                lodestone_path.join("tls").join("key.pem"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr},
+        path::{Path, PathBuf},
+    };
+
+    use super::{ServerConfig, DEFAULT_PORT};
+
+    #[test]
+    fn test_overrides() {
+        let config: ServerConfig =
+            serde_json::from_str(r#"{"port": 8080, "tls_cert": "/etc/cert.pem"}"#).unwrap();
+        assert_eq!(config.port, Some(8080));
+        assert_eq!(config.shutdown_grace_period, 30);
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        // a certificate without a key
+        assert!(config.clone().with_overrides(|_| None).is_err());
+
+        let env = HashMap::from([
+            ("LODESTONE_BIND_ADDRESS", "127.0.0.1"),
+            ("LODESTONE_TLS_KEY", "key.pem"),
+        ]);
+        let config = config
+            .with_overrides(|key| env.get(key).map(|s| s.to_string()))
+            .unwrap();
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(config.tls_configured());
+        assert_eq!(
+            config.tls_paths(Path::new("/lodestone")),
+            (
+                PathBuf::from("/etc/cert.pem"),
+                PathBuf::from("/lodestone/key.pem")
+            )
+        );
+
+        assert!(ServerConfig::default()
+            .with_overrides(|key| (key == "LODESTONE_PORT").then(|| "abc".to_string()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_only_api() {
+        let config = ServerConfig::default();
+        assert_eq!(config.api_bind_address(), IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        let env = HashMap::from([("LODESTONE_READ_ONLY_PORT", "8080")]);
+        let config = config
+            .with_overrides(|key| env.get(key).map(|s| s.to_string()))
+            .unwrap();
+        assert_eq!(config.read_only_port, Some(8080));
+        assert_eq!(config.api_bind_address(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let config = ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            ..config
+        };
+        assert_eq!(
+            config.api_bind_address(),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))
+        );
+
+        let config = ServerConfig {
+            read_only_port: Some(DEFAULT_PORT),
+            ..ServerConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_cors() {
+        let config = ServerConfig::default();
+        assert!(config.cors_enabled && config.security_headers);
+        assert!(config.cors_layer().is_some());
+
+        let env = HashMap::from([
+            ("LODESTONE_CORS_ENABLED", "false"),
+            (
+                "LODESTONE_CORS_ALLOWED_ORIGINS",
+                "https://www.lodestone.cc, http://localhost:3000/",
+            ),
+        ]);
+        let config = config
+            .with_overrides(|key| env.get(key).map(|s| s.to_string()))
+            .unwrap();
+        assert_eq!(
+            config.cors_allowed_origins,
+            Some(vec![
+                "https://www.lodestone.cc".to_string(),
+                "http://localhost:3000/".to_string()
+            ])
+        );
+        assert!(config.cors_layer().is_none());
+
+        for origin in [
+            "www.lodestone.cc",
+            "https://www.lodestone.cc/dashboard",
+            "https://",
+        ] {
+            let config = ServerConfig {
+                cors_allowed_origins: Some(vec![origin.to_string()]),
+                ..ServerConfig::default()
+            };
+            assert!(config.validate().is_err(), "{origin}");
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! The log files game servers write to the `logs/` directory of their instance, e.g.
+//! `latest.log` and the rotated `2023-01-01-1.log.gz` of minecraft servers.
+//!
+//! Unlike the console buffer these keep everything the server wrote, so long crash reports
+//! can be read in full.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+pub const LOGS_DIR_NAME: &str = "logs";
+/// the log file the server is currently writing to
+pub const LATEST_LOG_FILE_NAME: &str = "latest.log";
+pub const DEFAULT_TAIL_LINES: usize = 200;
+pub const MAX_TAIL_LINES: usize = 10_000;
+
+/// chunk size when reading a plain log file backwards
+const TAIL_CHUNK_SIZE: u64 = 8192;
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct LogFileEntry {
+    pub name: String,
+    pub size: u64,
+    /// seconds since the unix epoch
+    pub modified: i64,
+    /// gzipped by the server when it rotated the log, or by log housekeeping
+    pub compressed: bool,
+}
+
+fn is_log_file(name: &str) -> bool {
+    name.ends_with(".log") || name.ends_with(".log.gz")
+}
+
+/// The log files of an instance, the latest log first then the most recently modified
+pub fn list_log_files(instance_path: &Path) -> Result<Vec<LogFileEntry>, Error> {
+    let logs_dir = instance_path.join(LOGS_DIR_NAME);
+    if !logs_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&logs_dir)
+        .context(format!("Failed to read directory {}", logs_dir.display()))?
+    {
+        let entry = entry.context(format!("Failed to read directory {}", logs_dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_file() && is_log_file(&name) => metadata,
+            _ => continue,
+        };
+        entries.push(LogFileEntry {
+            compressed: name.ends_with(".gz"),
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs() as i64)
+                .unwrap_or_default(),
+            name,
+        });
+    }
+    entries.sort_by(|a, b| {
+        (b.name == LATEST_LOG_FILE_NAME)
+            .cmp(&(a.name == LATEST_LOG_FILE_NAME))
+            .then_with(|| b.modified.cmp(&a.modified))
+            .then_with(|| b.name.cmp(&a.name))
+    });
+    Ok(entries)
+}
+
+/// The path of the log file `name` of an instance, which must be a log file directly inside
+/// its logs directory
+pub fn path_to_log_file(instance_path: &Path, name: &str) -> Result<PathBuf, Error> {
+    if name.contains(['/', '\\']) || name.starts_with('.') || !is_log_file(name) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{name} is not a log file"),
+        });
+    }
+    let path = instance_path.join(LOGS_DIR_NAME).join(name);
+    if !path.is_file() {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Log file {name} not found"),
+        });
+    }
+    Ok(path)
+}
+
+fn split_lines(bytes: &[u8]) -> impl Iterator<Item = String> + '_ {
+    bytes
+        .split(|byte| *byte == b'\n')
+        .map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned())
+}
+
+/// The last `lines` lines of a log file, gzipped ones are decompressed
+pub fn tail_log_file(path: &Path, lines: usize) -> Result<Vec<String>, Error> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        // there is no reading a gzip backwards
+        let mut tail = VecDeque::with_capacity(lines);
+        for line in BufReader::new(GzDecoder::new(file)).split(b'\n') {
+            let line = line.context(format!("Failed to decompress {}", path.display()))?;
+            if tail.len() == lines {
+                tail.pop_front();
+            }
+            tail.push_back(split_lines(&line).next().unwrap_or_default());
+        }
+        return Ok(tail.into());
+    }
+    tail_plain_file(file, lines)
+        .context(format!("Failed to read {}", path.display()))
+        .map_err(Into::into)
+}
+
+/// Read chunks from the end of the file until there are enough lines
+fn tail_plain_file(mut file: File, lines: usize) -> std::io::Result<Vec<String>> {
+    let mut end = file.seek(SeekFrom::End(0))?;
+    let mut buffer = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(TAIL_CHUNK_SIZE);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buffer);
+        buffer = chunk;
+        end = start;
+        // one more than the lines as the file ends with a newline
+        if buffer.iter().filter(|byte| **byte == b'\n').count() > lines {
+            break;
+        }
+    }
+    let content = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+    if content.is_empty() {
+        return Ok(Vec::new());
+    }
+    let all: Vec<String> = split_lines(content).collect();
+    // the first line may be cut off if the file wasn't read from the start
+    Ok(all[all.len().saturating_sub(lines)..].to_vec())
+}
+
+/// Follows a log file as the server writes to it, starting from its current end
+pub struct LogFollower {
+    path: PathBuf,
+    offset: u64,
+    /// the start of a line the server hasn't finished writing
+    partial_line: Vec<u8>,
+}
+
+impl LogFollower {
+    pub async fn new(path: PathBuf) -> Self {
+        let offset = tokio::fs::metadata(&path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        Self {
+            path,
+            offset,
+            partial_line: Vec::new(),
+        }
+    }
+
+    /// The lines written since the last poll. The file starting over, e.g. when the server
+    /// rotates its log on start, is followed from its beginning
+    pub async fn poll(&mut self) -> Result<Vec<String>, Error> {
+        let mut file = match tokio::fs::File::open(&self.path).await {
+            Ok(file) => file,
+            // the server hasn't created the new log yet
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e)
+                    .context(format!("Failed to open {}", self.path.display()))
+                    .map_err(Into::into)
+            }
+        };
+        let len = file
+            .metadata()
+            .await
+            .context(format!(
+                "Failed to read metadata of {}",
+                self.path.display()
+            ))?
+            .len();
+        if len < self.offset {
+            self.offset = 0;
+            self.partial_line.clear();
+        }
+        if len == self.offset {
+            return Ok(Vec::new());
+        }
+        file.seek(SeekFrom::Start(self.offset))
+            .await
+            .context(format!("Failed to read {}", self.path.display()))?;
+        let mut written = Vec::new();
+        file.take(len - self.offset)
+            .read_to_end(&mut written)
+            .await
+            .context(format!("Failed to read {}", self.path.display()))?;
+        self.offset += written.len() as u64;
+        self.partial_line.extend_from_slice(&written);
+        let complete = match self.partial_line.iter().rposition(|byte| *byte == b'\n') {
+            Some(last_newline) => last_newline,
+            None => return Ok(Vec::new()),
+        };
+        let rest = self.partial_line.split_off(complete + 1);
+        let lines = split_lines(&self.partial_line[..complete]).collect();
+        self.partial_line = rest;
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::{list_log_files, path_to_log_file, tail_log_file, LogFollower};
+
+    #[test]
+    fn test_list_and_tail() {
+        let temp_dir = tempdir::TempDir::new("test_server_logs").unwrap();
+        let logs_dir = temp_dir.path().join("logs");
+        std::fs::create_dir_all(&logs_dir).unwrap();
+        let content: String = (1..=20_000).map(|i| format!("line {i}\n")).collect();
+        std::fs::write(logs_dir.join("latest.log"), &content).unwrap();
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(logs_dir.join("2023-01-01-1.log.gz")).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(b"old 1\r\nold 2\nold 3\n").unwrap();
+        encoder.finish().unwrap();
+        std::fs::write(logs_dir.join("notes.txt"), "not a log").unwrap();
+
+        let files = list_log_files(temp_dir.path()).unwrap();
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>(),
+            ["latest.log", "2023-01-01-1.log.gz"]
+        );
+        assert!(files[1].compressed);
+
+        let latest = path_to_log_file(temp_dir.path(), "latest.log").unwrap();
+        assert_eq!(
+            tail_log_file(&latest, 3).unwrap(),
+            ["line 19998", "line 19999", "line 20000"]
+        );
+        assert_eq!(tail_log_file(&latest, 5000).unwrap().len(), 5000);
+        assert_eq!(tail_log_file(&latest, 5000).unwrap()[0], "line 15001");
+        let old = path_to_log_file(temp_dir.path(), "2023-01-01-1.log.gz").unwrap();
+        assert_eq!(tail_log_file(&old, 2).unwrap(), ["old 2", "old 3"]);
+
+        assert!(path_to_log_file(temp_dir.path(), "../latest.log").is_err());
+        assert!(path_to_log_file(temp_dir.path(), "notes.txt").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_follow() {
+        let temp_dir = tempdir::TempDir::new("test_server_logs_follow").unwrap();
+        let path = temp_dir.path().join("latest.log");
+        std::fs::write(&path, "before\n").unwrap();
+        let mut follower = LogFollower::new(path.clone()).await;
+        assert!(follower.poll().await.unwrap().is_empty());
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"first\nsecond\nthi").unwrap();
+        assert_eq!(follower.poll().await.unwrap(), ["first", "second"]);
+        file.write_all(b"rd\n").unwrap();
+        assert_eq!(follower.poll().await.unwrap(), ["third"]);
+
+        // rotated on restart
+        std::fs::write(&path, "new\n").unwrap();
+        assert_eq!(follower.poll().await.unwrap(), ["new"]);
+    }
+}
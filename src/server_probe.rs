@@ -0,0 +1,181 @@
+//! Probing game servers on any host the way a game client lists them, e.g. to check that a port
+//! forward works from outside before handing the address out.
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::status_ping::status_ping_host,
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// the longest a DNS name can be
+const MAX_HOST_LEN: usize = 253;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, TS, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ProbeProtocol {
+    /// the server list ping of Minecraft Java Edition
+    #[default]
+    Minecraft,
+}
+
+impl ProbeProtocol {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            ProbeProtocol::Minecraft => 25565,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct ServerStatus {
+    pub protocol: ProbeProtocol,
+    pub host: String,
+    pub port: u16,
+    /// without formatting codes
+    pub motd: String,
+    pub version: Option<String>,
+    pub players_online: Option<u32>,
+    pub players_max: Option<u32>,
+    /// in milliseconds
+    pub latency: u64,
+}
+
+/// Remove the `§` formatting codes of a Minecraft text
+fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// The plain text of a Minecraft text component, which is either a string, a list of components
+/// or an object with `text` and `extra` components
+fn component_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(components) => components.iter().map(component_text).collect(),
+        Value::Object(object) => {
+            let mut text = object
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if let Some(Value::Array(extra)) = object.get("extra") {
+                text.extend(extra.iter().map(component_text));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+fn minecraft_status(host: String, port: u16, status: &Value, latency: Duration) -> ServerStatus {
+    let count = |key: &str| {
+        status["players"][key]
+            .as_u64()
+            .map(|count| count.min(u32::MAX as u64) as u32)
+    };
+    ServerStatus {
+        protocol: ProbeProtocol::Minecraft,
+        host,
+        port,
+        motd: strip_formatting(&component_text(&status["description"])),
+        version: status["version"]["name"].as_str().map(strip_formatting),
+        players_online: count("online"),
+        players_max: count("max"),
+        latency: latency.as_millis() as u64,
+    }
+}
+
+/// Ask the server at `host:port` for its status
+pub async fn probe(
+    protocol: ProbeProtocol,
+    host: &str,
+    port: Option<u16>,
+) -> Result<ServerStatus, Error> {
+    let host = host.trim();
+    if host.is_empty() || host.len() > MAX_HOST_LEN || host.contains(char::is_whitespace) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid host"),
+        });
+    }
+    let port = port.unwrap_or_else(|| protocol.default_port());
+    match protocol {
+        ProbeProtocol::Minecraft => {
+            let (status, latency) = status_ping_host(host, port, PROBE_TIMEOUT).await?;
+            Ok(minecraft_status(host.to_string(), port, &status, latency))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{component_text, minecraft_status, strip_formatting};
+
+    #[test]
+    fn test_component_text() {
+        assert_eq!(
+            component_text(&json!("A Minecraft Server")),
+            "A Minecraft Server"
+        );
+        assert_eq!(
+            component_text(&json!({
+                "text": "Welcome to ",
+                "extra": [{ "text": "Lodestone", "bold": true }, "!"]
+            })),
+            "Welcome to Lodestone!"
+        );
+        assert_eq!(component_text(&json!(null)), "");
+        assert_eq!(strip_formatting("§aGreen §lbold§r"), "Green bold");
+    }
+
+    #[test]
+    fn test_minecraft_status() {
+        let status = json!({
+            "version": { "name": "Paper 1.20.1", "protocol": 763 },
+            "players": { "max": 20, "online": 3 },
+            "description": { "text": "§6Survival" },
+        });
+        let status = minecraft_status(
+            "play.example.com".to_string(),
+            25565,
+            &status,
+            Duration::from_millis(42),
+        );
+        assert_eq!(status.motd, "Survival");
+        assert_eq!(status.version.as_deref(), Some("Paper 1.20.1"));
+        assert_eq!(status.players_online, Some(3));
+        assert_eq!(status.players_max, Some(20));
+        assert_eq!(status.latency, 42);
+
+        // servers hiding their player count
+        let status = minecraft_status(
+            "play.example.com".to_string(),
+            25565,
+            &json!({ "description": "" }),
+            Duration::ZERO,
+        );
+        assert_eq!(status.players_online, None);
+    }
+}
@@ -0,0 +1,263 @@
+//! The first time setup key lets whoever holds it create the owner account. It is printed to the
+//! log, and written to a file when one is configured so headless deployments can read it.
+//!
+//! A key can be used once and expires, a holder of the key can rotate or invalidate it. An
+//! invalidated key stays invalid until the core restarts, so a leaked one can't rotate itself back.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{TimeZone, Utc};
+use color_eyre::eyre::{eyre, Context};
+use serde::Serialize;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, SystemEventInner},
+    util::rand_alphanumeric,
+};
+
+pub const DEFAULT_SETUP_KEY_TTL_HOURS: u64 = 24;
+const SETUP_KEY_LENGTH: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct SetupKey {
+    key: String,
+    expires_at: i64,
+    ttl_secs: i64,
+    invalidated: bool,
+    /// where the key is written, if anywhere
+    file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SetupKeyInfo {
+    pub key: String,
+    pub expires_at: i64,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct SetupStatus {
+    pub is_setup: bool,
+    /// when the current key expires, in the past if it was invalidated
+    pub key_expires_at: Option<i64>,
+}
+
+/// Compare without returning early, so the time taken says nothing of how much of the key matched
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+impl SetupKey {
+    pub async fn generate(ttl_hours: u64, file: Option<PathBuf>) -> Self {
+        let ttl_secs = (ttl_hours * 60 * 60) as i64;
+        let setup_key = Self {
+            key: rand_alphanumeric(SETUP_KEY_LENGTH),
+            expires_at: Utc::now().timestamp() + ttl_secs,
+            ttl_secs,
+            invalidated: false,
+            file,
+        };
+        setup_key.announce().await;
+        setup_key
+    }
+
+    async fn announce(&self) {
+        // log the first time setup key in green so it's easy to find
+        info!(
+            "First time setup key: {}",
+            ansi_term::Color::Green.paint(self.key.clone())
+        );
+        info!(
+            "This is a one-time, in-memory randomly generated key that allows you to create the owner account. It expires at {}.",
+            Utc.timestamp_opt(self.expires_at, 0)
+                .single()
+                .map_or_else(|| self.expires_at.to_string(), |time| time.to_rfc2822())
+        );
+        info!(
+            "{}",
+            ansi_term::Color::Red.paint("DO NOT SHARE THIS KEY WITH ANYONE!")
+        );
+        if let Some(file) = &self.file {
+            match write_key_file(file, &self.key).await {
+                Ok(()) => info!("The setup key was written to {}", file.display()),
+                Err(e) => error!("Failed to write the setup key to {} : {e}", file.display()),
+            }
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn info(&self) -> SetupKeyInfo {
+        SetupKeyInfo {
+            key: self.key.clone(),
+            expires_at: self.expires_at,
+        }
+    }
+
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() >= self.expires_at
+    }
+
+    /// Whether `key` is this key, expired or not
+    pub fn is(&self, key: &str) -> bool {
+        constant_time_eq(&self.key, key)
+    }
+
+    /// Check that `key` can be rotated, which it can once expired but not once invalidated
+    pub fn verify_rotation(&self, key: &str) -> Result<(), Error> {
+        if !self.is(key) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Invalid setup key."),
+            });
+        }
+        if self.invalidated {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Setup key invalidated, restart the core for a new one."),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that `key` can be used to set the core up
+    pub fn verify(&self, key: &str) -> Result<(), Error> {
+        self.verify_rotation(key)?;
+        if self.is_expired() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("Setup key expired, rotate it or restart the core for a new one."),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replace the key by a new one with a fresh expiry
+    pub async fn rotate(&mut self) {
+        self.key = rand_alphanumeric(SETUP_KEY_LENGTH);
+        self.expires_at = Utc::now().timestamp() + self.ttl_secs;
+        info!("The first time setup key was rotated");
+        self.announce().await;
+    }
+
+    /// Expire the key right away, for good until the core restarts
+    pub async fn invalidate(&mut self) {
+        self.expires_at = Utc::now().timestamp();
+        self.invalidated = true;
+        info!("The first time setup key was invalidated");
+        self.remove_file().await;
+    }
+
+    /// Remove the file the key is written to, once it is used or invalidated
+    pub async fn remove_file(&self) {
+        if let Some(file) = &self.file {
+            if let Err(e) = tokio::fs::remove_file(file).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!(
+                        "Failed to remove the setup key file {} : {e}",
+                        file.display()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Sent once the owner account is created, so scripts setting the core up know it is done
+pub fn setup_completed_event(owner_name: &str) -> Event {
+    let mut event: Event = SystemEventInner::SetupCompleted {
+        owner_name: owner_name.to_string(),
+    }
+    .into();
+    event.details = format!("Lodestone Core was set up by {owner_name}");
+    event
+}
+
+/// Write the key readable by the user running the core only
+async fn write_key_file(path: &Path, key: &str) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context(format!("Failed to create directory {}", parent.display()))?;
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .await
+        .context(format!("Failed to open {}", path.display()))?;
+    // the mode only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .context(format!(
+                "Failed to restrict the permissions of {}",
+                path.display()
+            ))?;
+    }
+    tokio::io::AsyncWriteExt::write_all(&mut file, key.as_bytes())
+        .await
+        .context(format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, SetupKey};
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("abcd", "abcd"));
+        assert!(!constant_time_eq("abcd", "abce"));
+        assert!(!constant_time_eq("abcd", "abc"));
+    }
+
+    #[tokio::test]
+    async fn test_setup_key_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("setup_key");
+        let mut setup_key = SetupKey::generate(1, Some(file.clone())).await;
+        let key = setup_key.key().to_string();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(setup_key.verify(&key).is_ok());
+        assert!(setup_key.verify("wrong").is_err());
+
+        assert!(setup_key.verify_rotation(&key).is_ok());
+        setup_key.rotate().await;
+        assert!(setup_key.verify(&key).is_err());
+        let key = setup_key.key().to_string();
+        assert!(setup_key.verify(&key).is_ok());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), key);
+
+        setup_key.invalidate().await;
+        assert!(setup_key.verify(&key).is_err());
+        assert!(setup_key.is(&key));
+        assert!(!file.exists());
+        // a leaked key can't get itself a replacement
+        assert!(setup_key.verify_rotation(&key).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    events::{CausedBy, Event, SystemEventInner},
+    traits::{
+        t_configurable::{Game, TConfigurable},
+        t_server::{State, TServer},
+    },
+    AppState,
+};
+
+static RESTART_REQUESTED: AtomicBool = AtomicBool::new(false);
+static EXECUTABLE: OnceCell<PathBuf> = OnceCell::new();
+
+lazy_static! {
+    static ref RESTART: Notify = Notify::new();
+}
+
+/// Shut the core down gracefully and start it again with the same arguments
+pub fn request_restart() {
+    RESTART_REQUESTED.store(true, Ordering::SeqCst);
+    RESTART.notify_one();
+}
+
+pub fn restart_requested() -> bool {
+    RESTART_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Resolves once a restart is requested
+pub async fn restart_signal() {
+    RESTART.notified().await;
+}
+
+/// The binary the core was started from, read on the first call which `run` makes at startup.
+/// An update renames the running binary, which `current_exe` follows on Linux
+pub fn executable() -> std::io::Result<PathBuf> {
+    EXECUTABLE.get_or_try_init(std::env::current_exe).cloned()
+}
+
+/// Replace the current process with a fresh one, to be called once the core has shut down
+pub fn restart_process() -> std::io::Error {
+    let exe = match executable() {
+        Ok(exe) => exe,
+        Err(e) => return e,
+    };
+    let mut command = std::process::Command::new(exe);
+    command.args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.exec()
+    }
+    #[cfg(not(unix))]
+    match command.spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(e) => e,
+    }
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on unix
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
+                    _ = sigterm.recv() => info!("SIGTERM received"),
+                }
+                return;
+            }
+            Err(e) => error!("Failed to listen for SIGTERM : {e}"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Ctrl+C received");
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum HostPowerAction {
+    Reboot,
+    Shutdown,
+}
+
+/// The command that reboots or shuts down the host, the core needs the privileges to run it
+fn host_power_command(action: HostPowerAction) -> (&'static str, &'static [&'static str]) {
+    match (action, cfg!(windows)) {
+        (HostPowerAction::Reboot, true) => ("shutdown", &["/r", "/t", "0"]),
+        (HostPowerAction::Shutdown, true) => ("shutdown", &["/s", "/t", "0"]),
+        (HostPowerAction::Reboot, false) => ("shutdown", &["-r", "now"]),
+        (HostPowerAction::Shutdown, false) => ("shutdown", &["-h", "now"]),
+    }
+}
+
+/// Reboot or shut down the host, to be called once the instances are stopped and the stores flushed
+pub async fn run_host_power_action(action: HostPowerAction) -> Result<(), Error> {
+    let (program, args) = host_power_command(action);
+    info!("Running {program} {} for a host {action:?}", args.join(" "));
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .context(format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{program} exited with {} : {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Tell the players of every running minecraft instance, other games have no common way to
+/// broadcast a message
+pub async fn warn_running_instances(state: &AppState, message: &str) {
+    for instance in state.instances.values() {
+        if instance.state().await != State::Running
+            || !matches!(
+                instance.game_type().await,
+                Game::MinecraftJava { .. } | Game::MinecraftBedrock
+            )
+        {
+            continue;
+        }
+        if let Err(e) = instance
+            .send_command(&format!("say {message}"), CausedBy::System)
+            .await
+        {
+            warn!(
+                "Failed to warn the players of {} : {e}",
+                instance.name().await
+            );
+        }
+    }
+}
+
+/// Stop every running instance concurrently, instances that have not stopped after `grace_period`
+/// or that failed to stop are killed
+pub async fn stop_all_instances(state: &AppState, grace_period: Duration) {
+    let mut running = Vec::new();
+    for instance in state.instances.values() {
+        if !instance.state().await.is_stopped() {
+            running.push(instance);
+        }
+    }
+    let mut event: Event = SystemEventInner::CoreShutdown {
+        grace_period: grace_period.as_secs(),
+        running_instances: running.len() as u32,
+    }
+    .into();
+    event.details = format!(
+        "Lodestone Core is shutting down, stopping {} instances",
+        running.len()
+    );
+    state.event_broadcaster.send(event);
+    info!(
+        "Stopping {} instances with a grace period of {}s",
+        running.len(),
+        grace_period.as_secs()
+    );
+
+    join_all(running.into_iter().map(|mut instance| {
+        let event_broadcaster = state.event_broadcaster.clone();
+        async move {
+            let uuid = instance.uuid().await;
+            let name = instance.name().await;
+            match tokio::time::timeout(grace_period, instance.stop(CausedBy::System, true)).await {
+                Ok(Ok(())) => {
+                    info!("Instance {name} stopped");
+                    return;
+                }
+                Ok(Err(e)) => error!("Failed to stop instance {name} : {e}, killing it"),
+                Err(_) => warn!("Instance {name} did not stop in time, killing it"),
+            }
+            let mut event: Event = SystemEventInner::InstanceKilledOnShutdown {
+                instance_uuid: uuid,
+                instance_name: name.clone(),
+            }
+            .into();
+            event.details = format!("Instance {name} was killed during shutdown");
+            event_broadcaster.send(event);
+            if let Err(e) = instance.kill(CausedBy::System).await {
+                error!("Failed to kill instance {name} : {e}. Instance may need manual cleanup");
+            }
+        }
+    }))
+    .await;
+}
+
+/// Write every store to disk so nothing is lost if the last write raced with the shutdown
+pub async fn flush_stores(state: &AppState) {
+    if let Err(e) = state.users_manager.read().await.write_to_file().await {
+        error!("Failed to flush users : {e}");
+    }
+    if let Err(e) = state.global_settings.lock().await.write_to_file().await {
+        error!("Failed to flush global settings : {e}");
+    }
+    if let Err(e) = state.fs_locations.lock().await.write_to_file().await {
+        error!("Failed to flush file manager locations : {e}");
+    }
+    if let Err(e) = state.notifications.lock().await.write_to_file().await {
+        error!("Failed to flush notifications : {e}");
+    }
+    if let Err(e) = state.status_page.lock().await.write_to_file().await {
+        error!("Failed to flush status page config : {e}");
+    }
+    if let Err(e) = state.macro_triggers.lock().await.write_to_file().await {
+        error!("Failed to flush macro triggers : {e}");
+    }
+    if let Err(e) = state.command_queues.lock().await.write_to_file().await {
+        error!("Failed to flush command queues : {e}");
+    }
+    if let Err(e) = state.console_snippets.lock().await.write_to_file().await {
+        error!("Failed to flush console snippets : {e}");
+    }
+    if let Err(e) = state.console_profiles.lock().await.write_to_file().await {
+        error!("Failed to flush console profiles : {e}");
+    }
+    if let Err(e) = state.instance_webhooks.lock().await.write_to_file().await {
+        error!("Failed to flush instance webhooks : {e}");
+    }
+    if let Err(e) = state.network_policies.lock().await.write_to_file().await {
+        error!("Failed to flush network policies : {e}");
+    }
+    if let Err(e) = state.instance_syncs.lock().await.write_to_file().await {
+        error!("Failed to flush instance syncs : {e}");
+    }
+    if let Err(e) = state.instance_groups.lock().await.write_to_file().await {
+        error!("Failed to flush instance groups : {e}");
+    }
+    if let Err(e) = state.start_dependencies.lock().await.write_to_file().await {
+        error!("Failed to flush start dependencies : {e}");
+    }
+    if let Err(e) = state.upload_sessions.lock().await.write_to_file().await {
+        error!("Failed to flush upload sessions : {e}");
+    }
+    if let Err(e) = state.user_quotas.lock().await.write_to_file().await {
+        error!("Failed to flush user quotas : {e}");
+    }
+    if let Err(e) = state.usage_ledger.lock().await.write_to_file().await {
+        error!("Failed to flush usage ledger : {e}");
+    }
+    if let Err(e) = state.player_database.lock().await.write_to_file().await {
+        error!("Failed to flush player database : {e}");
+    }
+    if let Err(e) = state.peers.lock().await.write_to_file().await {
+        error!("Failed to flush peers : {e}");
+    }
+    if let Err(e) = state.backup_destinations.lock().await.write_to_file().await {
+        error!("Failed to flush backup destinations : {e}");
+    }
+    if let Err(e) = state
+        .suspicious_activity_policies
+        .lock()
+        .await
+        .write_to_file()
+        .await
+    {
+        error!("Failed to flush suspicious activity policies : {e}");
+    }
+    if let Err(e) = state.archived_instances.lock().await.write_to_file().await {
+        error!("Failed to flush archived instances : {e}");
+    }
+    if let Err(e) = state.command_filters.lock().await.write_to_file().await {
+        error!("Failed to flush command filters : {e}");
+    }
+    if let Err(e) = state.storage_roots.lock().await.write_to_file().await {
+        error!("Failed to flush storage roots : {e}");
+    }
+    if let Err(e) = state.console_matchers.lock().await.write_to_file().await {
+        error!("Failed to flush console matchers : {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host_power_command, HostPowerAction};
+
+    #[test]
+    fn test_host_power_command() {
+        let (program, reboot) = host_power_command(HostPowerAction::Reboot);
+        let (_, shutdown) = host_power_command(HostPowerAction::Shutdown);
+        assert_eq!(program, "shutdown");
+        assert_ne!(reboot, shutdown);
+        if cfg!(windows) {
+            assert_eq!(reboot, ["/r", "/t", "0"]);
+        } else {
+            assert_eq!(shutdown, ["-h", "now"]);
+        }
+    }
+}
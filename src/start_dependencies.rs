@@ -0,0 +1,383 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use futures::{future::join_all, Future};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    instance_map::InstanceMap,
+    readiness::wait_until_ready,
+    traits::{t_configurable::TConfigurable, t_server::TServer},
+    types::InstanceUuid,
+};
+
+const MAX_DEPENDENCIES: usize = 32;
+const DEFAULT_TIMEOUT_SECS: u64 = 5 * 60;
+const MAX_TIMEOUT_SECS: u64 = 60 * 60;
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+/// An instance that has to be ready before another one starts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct StartDependency {
+    pub instance_uuid: InstanceUuid,
+    /// how long to wait for the dependency to be ready before giving up on the start
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Whether `target` is reached by following the dependencies from `from`
+fn reaches(
+    dependencies: &HashMap<InstanceUuid, Vec<StartDependency>>,
+    from: &InstanceUuid,
+    target: &InstanceUuid,
+) -> bool {
+    let mut to_visit = vec![from.clone()];
+    let mut visited = HashSet::new();
+    while let Some(uuid) = to_visit.pop() {
+        if &uuid == target {
+            return true;
+        }
+        if !visited.insert(uuid.clone()) {
+            continue;
+        }
+        if let Some(next) = dependencies.get(&uuid) {
+            to_visit.extend(
+                next.iter()
+                    .map(|dependency| dependency.instance_uuid.clone()),
+            );
+        }
+    }
+    false
+}
+
+/// Split the instances to start into layers, each only depending on the layers before it or
+/// on instances that aren't started with them. Keeps the given order within a layer.
+pub fn start_layers(
+    to_start: &[InstanceUuid],
+    dependencies: &HashMap<InstanceUuid, Vec<StartDependency>>,
+) -> Result<Vec<Vec<InstanceUuid>>, Error> {
+    let in_batch: HashSet<&InstanceUuid> = to_start.iter().collect();
+    let mut remaining: Vec<&InstanceUuid> = to_start.iter().collect();
+    let mut placed: HashSet<&InstanceUuid> = HashSet::new();
+    let mut layers = Vec::new();
+    while !remaining.is_empty() {
+        let (layer, rest): (Vec<&InstanceUuid>, Vec<&InstanceUuid>) =
+            remaining.into_iter().partition(|uuid| {
+                dependencies.get(*uuid).map_or(true, |deps| {
+                    deps.iter().all(|dependency| {
+                        !in_batch.contains(&dependency.instance_uuid)
+                            || placed.contains(&dependency.instance_uuid)
+                    })
+                })
+            });
+        if layer.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(
+                    "The start dependencies of {} form a cycle",
+                    rest.iter()
+                        .map(|uuid| uuid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+        placed.extend(layer.iter().copied());
+        layers.push(layer.into_iter().cloned().collect());
+        remaining = rest;
+    }
+    Ok(layers)
+}
+
+/// Wait for a dependency to be ready, starting with it already running or on its way up
+async fn wait_for_dependency(
+    instances: &InstanceMap,
+    event_broadcaster: &EventBroadcaster,
+    dependency: &StartDependency,
+) -> Result<(), Error> {
+    let instance = instances
+        .get(&dependency.instance_uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Dependency {} not found", dependency.instance_uuid),
+        })?;
+    let name = instance.name().await;
+    let readiness = wait_until_ready(
+        event_broadcaster,
+        &dependency.instance_uuid,
+        async { (instance.state().await, instance.readiness().await) },
+        Duration::from_secs(dependency.timeout_secs),
+    )
+    .await;
+    match readiness {
+        Some(readiness) if readiness.ready => Ok(()),
+        Some(_) => Err(Error {
+            kind: ErrorKind::Conflict,
+            source: eyre!("Dependency {name} is not running"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::Internal,
+            source: eyre!(
+                "Dependency {name} was not ready after {} seconds",
+                dependency.timeout_secs
+            ),
+        }),
+    }
+}
+
+/// Start the instances layer by layer, each once its dependencies are ready. `on_done` is
+/// called as soon as the start of an instance succeeds or fails.
+pub async fn start_in_order<F, Fut>(
+    instances: &InstanceMap,
+    event_broadcaster: &EventBroadcaster,
+    dependencies: &HashMap<InstanceUuid, Vec<StartDependency>>,
+    to_start: Vec<InstanceUuid>,
+    start: F,
+    on_done: impl Fn(&InstanceUuid, &Result<(), Error>),
+) -> Result<Vec<(InstanceUuid, Result<(), Error>)>, Error>
+where
+    F: Fn(InstanceUuid) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let mut results = Vec::new();
+    for layer in start_layers(&to_start, dependencies)? {
+        results.extend(
+            join_all(layer.into_iter().map(|uuid| {
+                let start = &start;
+                let on_done = &on_done;
+                async move {
+                    let result = async {
+                        for dependency in dependencies.get(&uuid).into_iter().flatten() {
+                            wait_for_dependency(instances, event_broadcaster, dependency).await?;
+                        }
+                        start(uuid.clone()).await
+                    }
+                    .await;
+                    on_done(&uuid, &result);
+                    (uuid, result)
+                }
+            }))
+            .await,
+        );
+    }
+    Ok(results)
+}
+
+/// The instances each instance waits for before it starts, persisted in the stores directory
+pub struct StartDependencies {
+    path_to_store: PathBuf,
+    dependencies: HashMap<InstanceUuid, Vec<StartDependency>>,
+}
+
+impl StartDependencies {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            dependencies: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.dependencies = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read start dependencies file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.dependencies = serde_json::from_slice(&content).context(format!(
+            "Failed to parse start dependencies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.dependencies)
+                .context("Failed to serialize start dependencies")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write start dependencies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn dependencies_of(&self, instance_uuid: &InstanceUuid) -> Vec<StartDependency> {
+        self.dependencies
+            .get(instance_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn all(&self) -> HashMap<InstanceUuid, Vec<StartDependency>> {
+        self.dependencies.clone()
+    }
+
+    /// Replace the dependencies of an instance, refusing any that would make a cycle
+    pub async fn set_dependencies(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        dependencies: Vec<StartDependency>,
+    ) -> Result<Vec<StartDependency>, Error> {
+        if dependencies.len() > MAX_DEPENDENCIES {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("An instance can depend on at most {MAX_DEPENDENCIES} instances"),
+            });
+        }
+        let mut seen = HashSet::new();
+        for dependency in &dependencies {
+            if !seen.insert(&dependency.instance_uuid) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("{} is listed twice", dependency.instance_uuid),
+                });
+            }
+            if !(1..=MAX_TIMEOUT_SECS).contains(&dependency.timeout_secs) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Timeouts must be between 1 and {MAX_TIMEOUT_SECS} seconds"),
+                });
+            }
+            if &dependency.instance_uuid == instance_uuid
+                || reaches(&self.dependencies, &dependency.instance_uuid, instance_uuid)
+            {
+                return Err(Error {
+                    kind: ErrorKind::Conflict,
+                    source: eyre!(
+                        "Depending on {} would make a cycle",
+                        dependency.instance_uuid
+                    ),
+                });
+            }
+        }
+        let old = if dependencies.is_empty() {
+            self.dependencies.remove(instance_uuid)
+        } else {
+            self.dependencies
+                .insert(instance_uuid.clone(), dependencies.clone())
+        };
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.dependencies.insert(instance_uuid.clone(), old),
+                None => self.dependencies.remove(instance_uuid),
+            };
+            return Err(e);
+        }
+        Ok(dependencies)
+    }
+
+    /// Drop the dependencies of a deleted instance and the dependencies on it
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        let old = self.dependencies.clone();
+        self.dependencies.remove(instance_uuid);
+        for dependencies in self.dependencies.values_mut() {
+            dependencies.retain(|dependency| &dependency.instance_uuid != instance_uuid);
+        }
+        self.dependencies
+            .retain(|_, dependencies| !dependencies.is_empty());
+        if self.dependencies != old {
+            if let Err(e) = self.write_to_file().await {
+                self.dependencies = old;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{start_layers, StartDependencies, StartDependency};
+    use crate::types::InstanceUuid;
+
+    fn uuid(name: &str) -> InstanceUuid {
+        format!("INSTANCE_{name}").into()
+    }
+
+    fn on(name: &str) -> StartDependency {
+        StartDependency {
+            instance_uuid: uuid(name),
+            timeout_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_start_layers() {
+        let mut dependencies = HashMap::new();
+        dependencies.insert(uuid("proxy"), vec![on("lobby"), on("survival")]);
+        dependencies.insert(uuid("survival"), vec![on("database")]);
+        // not started with the others, so it is waited for rather than ordered
+        dependencies.insert(uuid("lobby"), vec![on("auth")]);
+        let to_start = [
+            uuid("proxy"),
+            uuid("lobby"),
+            uuid("survival"),
+            uuid("database"),
+        ];
+        assert_eq!(
+            start_layers(&to_start, &dependencies).unwrap(),
+            vec![
+                vec![uuid("lobby"), uuid("database")],
+                vec![uuid("survival")],
+                vec![uuid("proxy")],
+            ]
+        );
+
+        dependencies.insert(uuid("database"), vec![on("proxy")]);
+        assert!(start_layers(&to_start, &dependencies).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_start_dependencies() {
+        let temp_dir = tempdir::TempDir::new("test_start_dependencies").unwrap();
+        let path = temp_dir.path().join("start_dependencies.json");
+        let mut start_dependencies = StartDependencies::new(path.clone());
+
+        start_dependencies
+            .set_dependencies(&uuid("proxy"), vec![on("lobby")])
+            .await
+            .unwrap();
+        start_dependencies
+            .set_dependencies(&uuid("lobby"), vec![on("database")])
+            .await
+            .unwrap();
+        assert!(start_dependencies
+            .set_dependencies(&uuid("database"), vec![on("proxy")])
+            .await
+            .is_err());
+        assert!(start_dependencies
+            .set_dependencies(&uuid("database"), vec![on("database")])
+            .await
+            .is_err());
+        assert!(start_dependencies
+            .set_dependencies(&uuid("database"), vec![on("auth"), on("auth")])
+            .await
+            .is_err());
+
+        let mut reloaded = StartDependencies::new(path);
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.dependencies_of(&uuid("proxy")), vec![on("lobby")]);
+
+        reloaded.remove_instance(&uuid("lobby")).await.unwrap();
+        assert!(reloaded.all().is_empty());
+    }
+}
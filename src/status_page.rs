@@ -0,0 +1,437 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        Mutex,
+    },
+    time::Instant,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{Event, EventInner, InstanceEvent, InstanceEventInner},
+    global_settings::GlobalSettings,
+    instance_map::InstanceMap,
+    traits::{
+        t_configurable::{Game, TConfigurable},
+        t_player::{TPlayer, TPlayerManagement},
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+};
+
+/// publishing is given up on after this long, the next change tries again
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub enum StatusPageMethod {
+    Put,
+    Post,
+}
+
+/// Where the status is published to
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum StatusPageDestination {
+    /// sent as the json body of a request, a presigned S3 url works with `Put`
+    Url {
+        url: String,
+        method: StatusPageMethod,
+        /// sent along with every request, for example an authorization header
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// written to a file, for example in the root of a static site
+    File { path: PathBuf },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct StatusPageConfig {
+    pub enabled: bool,
+    pub destination: StatusPageDestination,
+    /// only publish these instances, every instance if empty
+    #[serde(default)]
+    pub instances: HashSet<InstanceUuid>,
+    #[serde(default = "default_include_players")]
+    pub include_players: bool,
+    /// seconds to wait after publishing before publishing again, changes in between are batched
+    #[serde(default = "default_min_interval")]
+    pub min_interval: u64,
+}
+
+fn default_include_players() -> bool {
+    true
+}
+
+fn default_min_interval() -> u64 {
+    10
+}
+
+impl StatusPageConfig {
+    fn validate(&self) -> Result<(), Error> {
+        match &self.destination {
+            StatusPageDestination::Url { url, headers, .. } => {
+                match url::Url::parse(url) {
+                    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                    _ => {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Status page url must be a valid http or https url"),
+                        })
+                    }
+                }
+                for (name, value) in headers {
+                    if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+                        || reqwest::header::HeaderValue::from_str(value).is_err()
+                    {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!("Invalid header {name}"),
+                        });
+                    }
+                }
+                Ok(())
+            }
+            StatusPageDestination::File { path } => {
+                if !path.is_absolute() {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Status page file path must be absolute"),
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What is published, only what a public page should show
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct ServerStatus {
+    pub core_name: String,
+    pub updated_at: i64,
+    pub instances: Vec<InstanceStatus>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct InstanceStatus {
+    pub uuid: InstanceUuid,
+    pub name: String,
+    pub game_type: Game,
+    pub version: String,
+    pub state: State,
+    pub player_count: Option<u32>,
+    pub max_player_count: Option<u32>,
+    pub players: Option<Vec<String>>,
+}
+
+/// Whether an event changes what the status page shows
+fn changes_status(event: &Event) -> bool {
+    matches!(
+        &event.event_inner,
+        EventInner::InstanceEvent(InstanceEvent {
+            instance_event_inner: InstanceEventInner::StateTransition { .. }
+                | InstanceEventInner::PlayerChange { .. },
+            ..
+        })
+    )
+}
+
+/// The status page config, persisted in the stores directory
+pub struct StatusPage {
+    path_to_store: PathBuf,
+    config: Option<StatusPageConfig>,
+    http: reqwest::Client,
+}
+
+impl StatusPage {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            config: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.config = None;
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read status page file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.config = serde_json::from_slice(&content).context(format!(
+            "Failed to parse status page file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.config)
+                .context("Failed to serialize status page config")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write status page file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> Option<&StatusPageConfig> {
+        self.config.as_ref()
+    }
+
+    /// Replace the config, `None` turns the status page off
+    pub async fn set_config(&mut self, config: Option<StatusPageConfig>) -> Result<(), Error> {
+        if let Some(config) = &config {
+            config.validate()?;
+        }
+        let old = std::mem::replace(&mut self.config, config);
+        if let Err(e) = self.write_to_file().await {
+            self.config = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+pub async fn server_status(
+    config: &StatusPageConfig,
+    instances: &InstanceMap,
+    global_settings: &Mutex<GlobalSettings>,
+) -> ServerStatus {
+    let mut statuses = Vec::new();
+    for (uuid, instance) in instances.snapshot() {
+        if !config.instances.is_empty() && !config.instances.contains(&uuid) {
+            continue;
+        }
+        let players = if config.include_players {
+            instance.get_player_list().await.ok().map(|players| {
+                let mut names: Vec<String> =
+                    players.iter().map(|player| player.get_name()).collect();
+                names.sort();
+                names
+            })
+        } else {
+            None
+        };
+        statuses.push(InstanceStatus {
+            name: instance.name().await,
+            game_type: instance.game_type().await,
+            version: instance.version().await,
+            state: instance.state().await,
+            player_count: instance.get_player_count().await.ok(),
+            max_player_count: instance.get_max_player_count().await.ok(),
+            players,
+            uuid,
+        });
+    }
+    statuses.sort_by(|a, b| a.name.cmp(&b.name));
+    ServerStatus {
+        core_name: global_settings.lock().await.core_name(),
+        updated_at: chrono::Utc::now().timestamp(),
+        instances: statuses,
+    }
+}
+
+pub async fn publish(
+    http: &reqwest::Client,
+    destination: &StatusPageDestination,
+    status: &ServerStatus,
+) -> Result<(), Error> {
+    match destination {
+        StatusPageDestination::Url {
+            url,
+            method,
+            headers,
+        } => {
+            let mut request = match method {
+                StatusPageMethod::Put => http.put(url),
+                StatusPageMethod::Post => http.post(url),
+            };
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            request
+                .json(status)
+                .timeout(PUBLISH_TIMEOUT)
+                .send()
+                .await
+                .context("Failed to reach the status page")?
+                .error_for_status()
+                .context("The status page rejected the update")?;
+        }
+        StatusPageDestination::File { path } => {
+            // written next to the file and renamed so readers never see half of it
+            let temp_path = path.with_extension("lodestone_tmp");
+            crate::util::fs::write_all(
+                &temp_path,
+                serde_json::to_vec_pretty(status).context("Failed to serialize status")?,
+            )
+            .await?;
+            crate::util::fs::rename(&temp_path, path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Publish the status whenever instances change state or players come and go
+pub async fn status_page_task(
+    mut event_receiver: Receiver<Event>,
+    status_page: Arc<Mutex<StatusPage>>,
+    instances: InstanceMap,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+) {
+    // publish once on start, the page may be stale from before the core went down
+    let mut publish_at = Some(Instant::now());
+    let mut last_published: Option<Instant> = None;
+    loop {
+        let event = tokio::select! {
+            event = event_receiver.recv() => Some(event),
+            _ = tokio::time::sleep_until(publish_at.unwrap_or_else(Instant::now)), if publish_at.is_some() => None,
+        };
+        match event {
+            Some(Ok(event)) => {
+                if publish_at.is_none() && changes_status(&event) {
+                    let min_interval = match status_page.lock().await.config() {
+                        Some(config) if config.enabled => Duration::from_secs(config.min_interval),
+                        _ => continue,
+                    };
+                    publish_at = Some(match last_published {
+                        Some(last_published) => Instant::now().max(last_published + min_interval),
+                        None => Instant::now(),
+                    });
+                }
+            }
+            Some(Err(RecvError::Lagged(_))) => {
+                warn!("Status page task lagged");
+                // a missed event may have changed the status
+                if publish_at.is_none() {
+                    publish_at = Some(Instant::now());
+                }
+            }
+            Some(Err(RecvError::Closed)) => break,
+            None => {
+                publish_at = None;
+                let (config, http) = {
+                    let status_page = status_page.lock().await;
+                    match status_page.config() {
+                        Some(config) if config.enabled => {
+                            (config.clone(), status_page.http.clone())
+                        }
+                        _ => continue,
+                    }
+                };
+                last_published = Some(Instant::now());
+                let status = server_status(&config, &instances, &global_settings).await;
+                if let Err(e) = publish(&http, &config.destination, &status).await {
+                    error!("Failed to publish status page : {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::PathBuf};
+
+    use super::{
+        changes_status, publish, ServerStatus, StatusPage, StatusPageConfig, StatusPageDestination,
+    };
+    use crate::{
+        events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+        traits::t_server::State,
+        types::{InstanceUuid, Snowflake},
+    };
+
+    fn instance_event(inner: InstanceEventInner) -> Event {
+        Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: InstanceUuid::default(),
+                instance_name: "survival".to_string(),
+                instance_event_inner: inner,
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        }
+    }
+
+    #[test]
+    fn test_changes_status() {
+        assert!(changes_status(&instance_event(
+            InstanceEventInner::StateTransition { to: State::Running }
+        )));
+        assert!(!changes_status(&instance_event(
+            InstanceEventInner::InstanceOutput {
+                message: "hello".to_string()
+            }
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_config_and_file_destination() {
+        let temp_dir = tempdir::TempDir::new("test_status_page").unwrap();
+        let mut status_page = StatusPage::new(temp_dir.path().join("status_page.json"));
+        status_page.load_from_file().await.unwrap();
+        assert!(status_page.config().is_none());
+
+        let relative = StatusPageConfig {
+            enabled: true,
+            destination: StatusPageDestination::File {
+                path: PathBuf::from("status.json"),
+            },
+            instances: HashSet::new(),
+            include_players: true,
+            min_interval: 10,
+        };
+        assert!(status_page.set_config(Some(relative)).await.is_err());
+
+        let path = temp_dir.path().join("status.json");
+        let config = StatusPageConfig {
+            enabled: true,
+            destination: StatusPageDestination::File { path: path.clone() },
+            instances: HashSet::new(),
+            include_players: true,
+            min_interval: 10,
+        };
+        status_page.set_config(Some(config.clone())).await.unwrap();
+        let mut reloaded = StatusPage::new(temp_dir.path().join("status_page.json"));
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.config(), Some(&config));
+
+        let status = ServerStatus {
+            core_name: "core".to_string(),
+            updated_at: 0,
+            instances: Vec::new(),
+        };
+        publish(&reqwest::Client::new(), &config.destination, &status)
+            .await
+            .unwrap();
+        let written: ServerStatus =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written, status);
+    }
+}
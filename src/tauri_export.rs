@@ -4,18 +4,28 @@ use crate::{
     auth::{jwt_token::JwtToken, permission::UserPermission, user::User},
     error::{Error, ErrorKind},
     events::CausedBy,
+    setup_key::setup_completed_event,
     AppState,
 };
 
+/// Start a session for the owner, the token expires like any other so call this again to renew it
 pub async fn get_owner_jwt(app_state: &AppState) -> Option<JwtToken> {
-    app_state
-        .users_manager
-        .read()
-        .await
+    let mut users_manager = app_state.users_manager.write().await;
+    let owner_uid = users_manager
         .as_ref()
-        .iter()
-        .find(|(_, user)| user.is_owner)
-        .and_then(|(_, user)| user.create_jwt().ok())
+        .values()
+        .find(|user| user.is_owner)?
+        .uid
+        .clone();
+    users_manager
+        .create_session(
+            &owner_uid,
+            Some("Lodestone Desktop".to_string()),
+            CausedBy::System,
+        )
+        .await
+        .ok()
+        .map(|(token, _)| token)
 }
 
 pub async fn is_owner_account_present(app_state: &AppState) -> bool {
@@ -40,16 +50,27 @@ pub async fn setup_owner_account(
         });
     }
     let user = User::new(username, password, true, false, UserPermission::default());
+    let owner_name = user.username.clone();
     app_state
         .users_manager
         .write()
         .await
         .add_user(user, CausedBy::System)
         .await?;
-    app_state.first_time_setup_key.lock().await.take();
+    if let Some(setup_key) = app_state.first_time_setup_key.lock().await.take() {
+        setup_key.remove_file().await;
+    }
+    app_state
+        .event_broadcaster
+        .send(setup_completed_event(&owner_name));
     Ok(())
 }
 
 pub async fn get_first_time_setup_key(app_state: &AppState) -> Option<String> {
-    app_state.first_time_setup_key.lock().await.clone()
+    app_state
+        .first_time_setup_key
+        .lock()
+        .await
+        .as_ref()
+        .map(|setup_key| setup_key.key().to_string())
 }
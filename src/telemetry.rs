@@ -0,0 +1,156 @@
+//! Anonymous usage reports, sent once a day by cores whose owner opted in.
+//!
+//! A report only holds aggregates: the version and platform of the core and how many instances
+//! of each game it runs. No names, addresses, identifiers or anything typed in by a user is
+//! sent, and the preview endpoint shows a report exactly as it would be sent.
+
+use std::{collections::BTreeMap, env, sync::Arc, time::Duration};
+
+use color_eyre::eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use ts_rs::TS;
+
+use crate::{
+    error::Error,
+    global_settings::GlobalSettings,
+    instance_map::InstanceMap,
+    prelude::VERSION,
+    traits::t_configurable::{Game, MinecraftVariant, TConfigurable},
+};
+
+pub const TELEMETRY_URL: &str = "https://telemetry.lodestone.cc/v1/report";
+/// bumped when the fields of a report change
+const REPORT_SCHEMA: u32 = 1;
+const REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct TelemetryReport {
+    pub schema: u32,
+    pub core_version: String,
+    pub os: String,
+    pub arch: String,
+    pub instance_count: u32,
+    /// instances by game, e.g. `minecraft_java_paper`
+    pub instances_by_game: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct TelemetryPreview {
+    pub enabled: bool,
+    pub url: String,
+    pub report: TelemetryReport,
+}
+
+/// The game of an instance as it's counted, names users gave to their games are left out
+fn game_key(game: &Game) -> &'static str {
+    match game {
+        Game::MinecraftJava { variant } => match variant {
+            MinecraftVariant::Vanilla => "minecraft_java_vanilla",
+            MinecraftVariant::Forge => "minecraft_java_forge",
+            MinecraftVariant::Fabric => "minecraft_java_fabric",
+            MinecraftVariant::Paper => "minecraft_java_paper",
+            MinecraftVariant::Spigot => "minecraft_java_spigot",
+            MinecraftVariant::Quilt => "minecraft_java_quilt",
+            MinecraftVariant::Other { .. } => "minecraft_java_other",
+        },
+        Game::MinecraftBedrock => "minecraft_bedrock",
+        Game::Generic { .. } => "generic",
+        Game::Process { .. } => "process",
+    }
+}
+
+pub fn build_report<'a>(games: impl IntoIterator<Item = &'a Game>) -> TelemetryReport {
+    let mut instances_by_game = BTreeMap::new();
+    let mut instance_count = 0;
+    for game in games {
+        *instances_by_game
+            .entry(game_key(game).to_string())
+            .or_insert(0) += 1;
+        instance_count += 1;
+    }
+    TelemetryReport {
+        schema: REPORT_SCHEMA,
+        core_version: VERSION.with(|v| v.to_string()),
+        os: env::consts::OS.to_string(),
+        arch: env::consts::ARCH.to_string(),
+        instance_count,
+        instances_by_game,
+    }
+}
+
+pub async fn current_report(instances: &InstanceMap) -> TelemetryReport {
+    let mut games = Vec::new();
+    for instance in instances.values() {
+        games.push(instance.game_type().await);
+    }
+    build_report(&games)
+}
+
+pub async fn send_report(report: &TelemetryReport) -> Result<(), Error> {
+    reqwest::Client::new()
+        .post(TELEMETRY_URL)
+        .json(report)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .context("Failed to reach the telemetry server")?
+        .error_for_status()
+        .context("The telemetry server refused the report")?;
+    Ok(())
+}
+
+/// Send a report a day for as long as the owner is opted in
+pub async fn telemetry_task(global_settings: Arc<Mutex<GlobalSettings>>, instances: InstanceMap) {
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !global_settings.lock().await.telemetry() {
+            continue;
+        }
+        let report = current_report(&instances).await;
+        match send_report(&report).await {
+            Ok(()) => debug!("Sent telemetry report"),
+            Err(e) => warn!("Failed to send telemetry report : {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_report;
+    use crate::traits::t_configurable::{Game, MinecraftVariant};
+
+    #[test]
+    fn test_build_report() {
+        let games = [
+            Game::MinecraftJava {
+                variant: MinecraftVariant::Paper,
+            },
+            Game::MinecraftJava {
+                variant: MinecraftVariant::Other {
+                    name: "my secret fork".to_string(),
+                },
+            },
+            Game::MinecraftJava {
+                variant: MinecraftVariant::Paper,
+            },
+            Game::Process {
+                game_display_name: "Steve's Terraria".to_string(),
+            },
+        ];
+        let report = build_report(&games);
+        assert_eq!(report.instance_count, 4);
+        assert_eq!(
+            report.instances_by_game.into_iter().collect::<Vec<_>>(),
+            [
+                ("minecraft_java_other".to_string(), 1),
+                ("minecraft_java_paper".to_string(), 2),
+                ("process".to_string(), 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! A core for integration tests, enabled with the `test-harness` feature.
+//!
+//! Only the API is served, on an ephemeral port of localhost, with empty stores in a temporary
+//! directory and the event history in an in-memory database. None of the background tasks run,
+//! so what a request does can be checked as soon as it returns. Instances are added with
+//! `TestCore::add_mock_instance` rather than set up, so nothing is downloaded.
+
+use std::{collections::HashMap, net::SocketAddr, str::FromStr, sync::Arc, sync::Once};
+
+use axum::Router;
+use color_eyre::eyre::Context;
+use ringbuffer::AllocRingBuffer;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sysinfo::SystemExt;
+use tempdir::TempDir;
+use tokio::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::{
+    api_routes,
+    auth::{permission::UserPermission, user::User},
+    command_history::CommandHistory,
+    command_sequence::CommandSequences,
+    db::write::init_client_events_table,
+    disk_usage::DirectorySizes,
+    error::Error,
+    event_broadcaster::EventBroadcaster,
+    events::CausedBy,
+    global_settings::GlobalSettingsData,
+    health::HealthChecks,
+    instance_map::InstanceMap,
+    instance_summaries::InstanceSummaries,
+    jobs::Jobs,
+    metrics::ApiRequestCounter,
+    mock::MockInstance,
+    port_manager::PortManager,
+    prelude::{init_paths, path_to_instances, GameInstance},
+    public_address::PublicIp,
+    rate_limit::RateLimiter,
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
+    usage_history::{init_usage_rollups_table, UsageHistory},
+    AppState, ArchivedInstances, BackupDestinations, CommandFilters, CommandQueues,
+    ConsoleMatchers, ConsoleProfiles, ConsoleSnippets, FsLocations, GlobalSettings, InstanceGroups,
+    InstanceSyncs, InstanceWebhooks, MacroExecutor, MacroTriggers, NetworkPolicies, Notifications,
+    Peers, PlayerDatabase, StartDependencies, StatusPage, StorageRoots, SuspiciousActivityPolicies,
+    Updater, UploadSessions, UsageLedger, UserQuotas, UsersManager, RELEASE_FEED_URL,
+};
+
+pub const OWNER_USERNAME: &str = "owner";
+pub const OWNER_PASSWORD: &str = "test-harness-password";
+
+static INIT_PATHS: Once = Once::new();
+
+/// The data directory is global to the process, so every core of a test binary shares one
+fn init_test_paths() {
+    INIT_PATHS.call_once(|| {
+        let lodestone_path = TempDir::new("lodestone_test_core")
+            .expect("Failed to create the data directory of the test core")
+            .into_path();
+        init_paths(lodestone_path);
+    });
+}
+
+pub struct TestCore {
+    pub state: AppState,
+    /// where the API listens, requests go to `/api/v1` on it
+    pub address: SocketAddr,
+    /// a session of `OWNER_USERNAME`
+    pub owner_token: String,
+    _stores: TempDir,
+}
+
+impl TestCore {
+    pub async fn start() -> Result<TestCore, Error> {
+        init_test_paths();
+        let stores = TempDir::new("lodestone_test_stores").context("Failed to create stores")?;
+        let path = |file: &str| stores.path().join(file);
+        let global_settings_data = GlobalSettingsData::default();
+        let (tx, _rx) =
+            EventBroadcaster::new(global_settings_data.buffer_settings.broadcast_capacity);
+
+        let mut users_manager = UsersManager::new(tx.clone(), HashMap::new(), path("users.json"));
+        users_manager
+            .add_user(
+                User::new(
+                    OWNER_USERNAME.to_string(),
+                    OWNER_PASSWORD,
+                    true,
+                    true,
+                    UserPermission::default(),
+                ),
+                CausedBy::System,
+            )
+            .await?;
+        let (owner_token, _) = users_manager
+            .login(OWNER_USERNAME, OWNER_PASSWORD, None, None)
+            .await?;
+
+        let global_settings = GlobalSettings::new(
+            path("global_settings.json"),
+            tx.clone(),
+            global_settings_data,
+        );
+        let buffer_settings = global_settings.buffer_settings();
+        let sqlite_pool = SqlitePoolOptions::new()
+            // every connection would get its own in-memory database
+            .max_connections(1)
+            .connect_with(
+                SqliteConnectOptions::from_str("sqlite::memory:")
+                    .context("Invalid database url")?,
+            )
+            .await
+            .context("Failed to open the in-memory database")?;
+        init_client_events_table(&sqlite_pool).await?;
+        init_usage_rollups_table(&sqlite_pool).await?;
+
+        let state = AppState {
+            instances: InstanceMap::new(HashMap::new()),
+            instance_summaries: InstanceSummaries::default(),
+            users_manager: Arc::new(RwLock::new(users_manager)),
+            events_buffer: Arc::new(Mutex::new(AllocRingBuffer::with_capacity(
+                buffer_settings.event_buffer_size,
+            ))),
+            console_out_buffer: Arc::new(Mutex::new(HashMap::new())),
+            monitor_buffer: Arc::new(Mutex::new(HashMap::new())),
+            usage_history: UsageHistory::default(),
+            event_broadcaster: tx.clone(),
+            uuid: Uuid::new_v4().to_string(),
+            up_since: chrono::Utc::now().timestamp(),
+            port_manager: Arc::new(Mutex::new(PortManager::new(Default::default()))),
+            public_ip: PublicIp::default(),
+            first_time_setup_key: Arc::new(Mutex::new(None)),
+            system: Arc::new(Mutex::new(sysinfo::System::new())),
+            download_urls: Arc::new(Mutex::new(HashMap::new())),
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::new(
+                global_settings.rate_limit_settings(),
+            ))),
+            global_settings: Arc::new(Mutex::new(global_settings)),
+            fs_locations: Arc::new(Mutex::new(FsLocations::new(path("fs_locations.json")))),
+            notifications: Arc::new(Mutex::new(Notifications::new(path("notifications.json")))),
+            status_page: Arc::new(Mutex::new(StatusPage::new(path("status_page.json")))),
+            macro_triggers: Arc::new(Mutex::new(MacroTriggers::new(path("macro_triggers.json")))),
+            command_queues: Arc::new(Mutex::new(CommandQueues::new(path("command_queues.json")))),
+            command_filters: Arc::new(Mutex::new(CommandFilters::new(path(
+                "command_filters.json",
+            )))),
+            command_sequences: CommandSequences::default(),
+            command_history: CommandHistory::default(),
+            jobs: Jobs::new(tx.clone()),
+            updater: Updater::new(RELEASE_FEED_URL.to_string()),
+            console_snippets: Arc::new(Mutex::new(ConsoleSnippets::new(path(
+                "console_snippets.json",
+            )))),
+            console_profiles: Arc::new(Mutex::new(ConsoleProfiles::new(path(
+                "console_profiles.json",
+            )))),
+            console_matchers: Arc::new(Mutex::new(ConsoleMatchers::new(path(
+                "console_matchers.json",
+            )))),
+            instance_webhooks: Arc::new(Mutex::new(InstanceWebhooks::new(path(
+                "instance_webhooks.json",
+            )))),
+            network_policies: Arc::new(Mutex::new(NetworkPolicies::new(path(
+                "network_policies.json",
+            )))),
+            instance_syncs: Arc::new(Mutex::new(InstanceSyncs::new(
+                path("instance_sync.json"),
+                path("sync"),
+            ))),
+            instance_groups: Arc::new(Mutex::new(InstanceGroups::new(path(
+                "instance_groups.json",
+            )))),
+            archived_instances: Arc::new(Mutex::new(ArchivedInstances::new(path(
+                "archived_instances.json",
+            )))),
+            start_dependencies: Arc::new(Mutex::new(StartDependencies::new(path(
+                "start_dependencies.json",
+            )))),
+            upload_sessions: Arc::new(Mutex::new(UploadSessions::new(path(
+                "upload_sessions.json",
+            )))),
+            user_quotas: Arc::new(Mutex::new(UserQuotas::new(path("user_quotas.json")))),
+            usage_ledger: Arc::new(Mutex::new(UsageLedger::new(path("usage_ledger.json")))),
+            player_database: Arc::new(Mutex::new(PlayerDatabase::new(path(
+                "player_database.json",
+            )))),
+            peers: Arc::new(Mutex::new(Peers::new(path("peers.json")))),
+            backup_destinations: Arc::new(Mutex::new(BackupDestinations::new(path(
+                "backup_destinations.json",
+            )))),
+            suspicious_activity_policies: Arc::new(Mutex::new(SuspiciousActivityPolicies::new(
+                path("suspicious_activity.json"),
+            ))),
+            storage_roots: Arc::new(Mutex::new(StorageRoots::new(
+                path("storage_roots.json"),
+                path_to_instances().clone(),
+            ))),
+            macro_executor: MacroExecutor::new(tx),
+            sqlite_pool,
+            api_requests: ApiRequestCounter::default(),
+            directory_sizes: DirectorySizes::default(),
+            health_checks: HealthChecks::default(),
+        };
+
+        let app = Router::new().nest("/api/v1", api_routes(&state));
+        let server = axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .context("Failed to bind the API of the test core")?
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+        let address = server.local_addr();
+        tokio::spawn(server);
+
+        Ok(TestCore {
+            state,
+            address,
+            owner_token: owner_token.to_string(),
+            _stores: stores,
+        })
+    }
+
+    /// The url of `path` on the API, e.g. `/instance/list`
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/api/v1{}", self.address, path)
+    }
+
+    /// A stopped mock instance on a free port, as if it had been set up
+    pub async fn add_mock_instance(&self, name: &str) -> Result<MockInstance, Error> {
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .context("Failed to find a free port")?
+            .port() as u32;
+        self.state.port_manager.lock().await.add_port(port);
+        let instance = MockInstance::new(
+            name,
+            port,
+            path_to_instances().join(Uuid::new_v4().to_string()),
+            self.state.event_broadcaster.clone(),
+        )
+        .await?;
+        let game_instance = GameInstance::MockInstance(instance.clone());
+        self.state.instance_summaries.refresh(&game_instance).await;
+        self.state
+            .instances
+            .insert(instance.uuid().await, game_instance);
+        Ok(instance)
+    }
+
+    pub fn instance(&self, uuid: &InstanceUuid) -> Option<GameInstance> {
+        self.state.instances.get(uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::traits::t_configurable::TConfigurable;
+    use crate::traits::t_server::{State, TServer};
+
+    use super::TestCore;
+
+    #[tokio::test]
+    async fn test_start_and_command_through_api() {
+        let core = TestCore::start().await.unwrap();
+        let instance = core.add_mock_instance("mock").await.unwrap();
+        let uuid = instance.uuid().await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .put(core.url(&format!("/instance/{uuid}/start")))
+            .send()
+            .await
+            .unwrap();
+        assert!(!response.status().is_success());
+        assert_eq!(instance.state().await, State::Stopped);
+
+        let response = client
+            .put(core.url(&format!("/instance/{uuid}/start")))
+            .bearer_auth(&core.owner_token)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(instance.state().await, State::Running);
+
+        let response = client
+            .post(core.url(&format!("/instance/{uuid}/console")))
+            .bearer_auth(&core.owner_token)
+            .json("say hello")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(instance.sent_commands().await, vec!["say hello"]);
+    }
+}
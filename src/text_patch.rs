@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::error::Error;
+
+/// An edit to a text file.
+///
+/// Line numbers start at 1 and refer to the file as left by the previous operations of the patch.
+#[derive(Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[serde(tag = "type")]
+#[ts(export)]
+pub enum TextPatchOperation {
+    /// Insert lines before `line`, one past the last line appends to the file
+    InsertLines { line: usize, lines: Vec<String> },
+    /// Replace the lines from `start` to `end` inclusive
+    ReplaceLines {
+        start: usize,
+        end: usize,
+        lines: Vec<String>,
+    },
+    /// Delete the lines from `start` to `end` inclusive
+    DeleteLines { start: usize, end: usize },
+    /// Set `key=value` in a properties-style file, the entry is appended if the key is missing
+    SetProperty { key: String, value: String },
+    /// Remove every entry of `key` in a properties-style file
+    RemoveProperty { key: String },
+}
+
+fn check_range(start: usize, end: usize, line_count: usize) -> Result<(), Error> {
+    if start == 0 || start > end || end > line_count {
+        return Err(Error::bad_request(format!(
+            "Invalid line range {start}-{end}, the file has {line_count} lines"
+        )));
+    }
+    Ok(())
+}
+
+/// The key of a properties-style line, `None` for comments and lines without a separator
+pub(crate) fn property_key(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with('!') {
+        return None;
+    }
+    line.split_once(['=', ':']).map(|(key, _)| key.trim())
+}
+
+fn apply_operation(lines: &mut Vec<String>, operation: &TextPatchOperation) -> Result<(), Error> {
+    match operation {
+        TextPatchOperation::InsertLines { line, lines: new } => {
+            if *line == 0 || *line > lines.len() + 1 {
+                return Err(Error::bad_request(format!(
+                    "Cannot insert at line {line}, the file has {} lines",
+                    lines.len()
+                )));
+            }
+            lines.splice(line - 1..line - 1, new.iter().cloned());
+        }
+        TextPatchOperation::ReplaceLines {
+            start,
+            end,
+            lines: new,
+        } => {
+            check_range(*start, *end, lines.len())?;
+            lines.splice(start - 1..*end, new.iter().cloned());
+        }
+        TextPatchOperation::DeleteLines { start, end } => {
+            check_range(*start, *end, lines.len())?;
+            lines.drain(start - 1..*end);
+        }
+        TextPatchOperation::SetProperty { key, value } => {
+            if key.trim().is_empty() || key.contains(['=', ':', '\n']) {
+                return Err(Error::bad_request(format!("Invalid property key {key}")));
+            }
+            let entry = format!("{}={}", key.trim(), value);
+            match lines
+                .iter_mut()
+                .find(|line| property_key(line) == Some(key.trim()))
+            {
+                Some(line) => *line = entry,
+                None => lines.push(entry),
+            }
+        }
+        TextPatchOperation::RemoveProperty { key } => {
+            lines.retain(|line| property_key(line) != Some(key.trim()));
+        }
+    }
+    Ok(())
+}
+
+/// Apply the operations in order, either all of them apply or an error is returned.
+///
+/// The line endings of the file and its trailing newline are preserved.
+pub fn apply_text_patch(content: &str, operations: &[TextPatchOperation]) -> Result<String, Error> {
+    let line_ending = if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let has_trailing_newline = content.ends_with('\n') || content.is_empty();
+    let mut lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
+    for operation in operations {
+        apply_operation(&mut lines, operation)?;
+    }
+    let mut ret = lines.join(line_ending);
+    if has_trailing_newline && !lines.is_empty() {
+        ret.push_str(line_ending);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_text_patch, TextPatchOperation};
+
+    #[test]
+    fn test_line_operations() {
+        let content = "a\nb\nc\n";
+        let patched = apply_text_patch(
+            content,
+            &[
+                TextPatchOperation::InsertLines {
+                    line: 1,
+                    lines: vec!["first".to_string()],
+                },
+                TextPatchOperation::ReplaceLines {
+                    start: 2,
+                    end: 3,
+                    lines: vec!["x".to_string()],
+                },
+                TextPatchOperation::InsertLines {
+                    line: 4,
+                    lines: vec!["last".to_string()],
+                },
+                TextPatchOperation::DeleteLines { start: 3, end: 3 },
+            ],
+        )
+        .unwrap();
+        assert_eq!(patched, "first\nx\nlast\n");
+    }
+
+    #[test]
+    fn test_invalid_range_leaves_content_untouched() {
+        assert!(apply_text_patch(
+            "a\nb\n",
+            &[TextPatchOperation::DeleteLines { start: 2, end: 3 }]
+        )
+        .is_err());
+        assert!(apply_text_patch(
+            "a\nb\n",
+            &[TextPatchOperation::InsertLines {
+                line: 0,
+                lines: vec![]
+            }]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_property_operations() {
+        let content = "#comment=1\r\nmotd=hello\r\nserver-port = 25565\r\npvp=true";
+        let patched = apply_text_patch(
+            content,
+            &[
+                TextPatchOperation::SetProperty {
+                    key: "server-port".to_string(),
+                    value: "25566".to_string(),
+                },
+                TextPatchOperation::SetProperty {
+                    key: "comment".to_string(),
+                    value: "2".to_string(),
+                },
+                TextPatchOperation::RemoveProperty {
+                    key: "pvp".to_string(),
+                },
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            patched,
+            "#comment=1\r\nmotd=hello\r\nserver-port=25566\r\ncomment=2"
+        );
+    }
+}
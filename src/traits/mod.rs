@@ -2,13 +2,14 @@ use std::collections::HashSet;
 
 use async_trait::async_trait;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
-use self::t_configurable::Game;
+use self::t_configurable::{DisplayMetadata, Game};
 use self::t_player::Player;
-use self::t_server::State;
+use self::t_server::{Readiness, State};
 use self::{
     t_configurable::TConfigurable, t_macro::TMacro, t_player::TPlayerManagement,
     t_resource::TResourceManagement, t_server::TServer,
@@ -20,7 +21,7 @@ pub mod t_player;
 pub mod t_resource;
 pub mod t_server;
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 pub struct InstanceInfo {
     pub uuid: InstanceUuid,
@@ -34,13 +35,35 @@ pub struct InstanceInfo {
     pub auto_start: bool,
     pub restart_on_crash: bool,
     pub state: State,
+    pub readiness: Readiness,
     pub player_count: Option<u32>,
     pub max_player_count: Option<u32>,
     pub player_list: Option<HashSet<Player>>,
+    #[serde(default)]
+    pub display: DisplayMetadata,
+    /// known vulnerabilities of the version the instance is on that it hasn't mitigated
+    #[serde(default)]
+    pub advisories: Vec<InstanceAdvisory>,
 }
-use crate::minecraft::MinecraftInstance;
+
+impl InstanceInfo {
+    /// Order for instance lists, by sort order with the instances without one last, then by
+    /// creation time
+    pub fn list_order(&self, other: &Self) -> std::cmp::Ordering {
+        match (self.display.sort_order, other.display.sort_order) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        }
+        .then_with(|| self.creation_time.cmp(&other.creation_time))
+    }
+}
+use crate::advisories::InstanceAdvisory;
 use crate::generic::GenericInstance;
+use crate::minecraft::MinecraftInstance;
 use crate::prelude::GameInstance;
+use crate::process::ProcessInstance;
 use crate::types::InstanceUuid;
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
@@ -60,9 +83,16 @@ pub trait TInstance:
             auto_start: self.auto_start().await,
             restart_on_crash: self.restart_on_crash().await,
             state: self.state().await,
+            readiness: self.readiness().await,
             player_count: self.get_player_count().await.ok(),
             max_player_count: self.get_max_player_count().await.ok(),
             player_list: self.get_player_list().await.ok(),
+            display: self.display_metadata().await,
+            advisories: self.advisories().await,
         }
     }
+
+    async fn advisories(&self) -> Vec<InstanceAdvisory> {
+        Vec::new()
+    }
 }
@@ -571,6 +571,36 @@ impl SetupValue {
         }
         None
     }
+
+    /// Set a setting of a section, adding the setting or the section if the value doesn't have
+    /// them yet
+    pub fn insert_setting(
+        &mut self,
+        section_id: &str,
+        setting_id: &str,
+        value: Option<ConfigurableValue>,
+    ) {
+        self.setting_sections
+            .entry(section_id.to_string())
+            .or_insert_with(|| SectionManifestValue::new(IndexMap::new()))
+            .settings
+            .insert(setting_id.to_string(), SettingManifestValue::new(value));
+    }
+
+    /// Returns false if no section has the setting
+    pub fn set_unique_setting(
+        &mut self,
+        setting_id: &str,
+        value: Option<ConfigurableValue>,
+    ) -> bool {
+        for section in self.setting_sections.values_mut() {
+            if let Some(setting) = section.settings.get_mut(setting_id) {
+                setting.value = value;
+                return true;
+            }
+        }
+        false
+    }
 }
 
 // A setting manifest indicates if the instance has implemented functionalities for smart, lodestone controlled feature
@@ -733,6 +763,10 @@ pub struct SettingManifestValue {
 }
 
 impl SettingManifestValue {
+    pub fn new(value: Option<ConfigurableValue>) -> Self {
+        Self { value }
+    }
+
     pub fn get_value(&self) -> Option<&ConfigurableValue> {
         self.value.as_ref()
     }
@@ -745,6 +779,10 @@ pub struct SectionManifestValue {
 }
 
 impl SectionManifestValue {
+    pub fn new(settings: IndexMap<String, SettingManifestValue>) -> Self {
+        Self { settings }
+    }
+
     pub fn get_setting(&self, setting_id: &str) -> Option<&SettingManifestValue> {
         self.settings.get(setting_id)
     }
@@ -4,6 +4,8 @@ pub use std::path::PathBuf;
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
 use enum_kinds::EnumKind;
+use indexmap::IndexMap;
+use schemars::JsonSchema;
 pub use serde::{Deserialize, Serialize};
 pub use serde_json;
 use ts_rs::TS;
@@ -16,10 +18,11 @@ use crate::implementations::minecraft::Flavour;
 use crate::traits::GameInstance;
 use crate::traits::GenericInstance;
 use crate::traits::MinecraftInstance;
+use crate::traits::ProcessInstance;
 
 use crate::types::InstanceUuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(tag = "type")]
 #[ts(export)]
 pub enum MinecraftVariant {
@@ -28,14 +31,15 @@ pub enum MinecraftVariant {
     Fabric,
     Paper,
     Spigot,
+    Quilt,
     Other { name: String },
 }
 
 /// The type of game this instance is
-/// 
+///
 /// Meant to be consumed by frontend to display the correct icon
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, EnumKind)]
-#[enum_kind(GameType, derive(Serialize, Deserialize, TS))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, EnumKind, JsonSchema)]
+#[enum_kind(GameType, derive(Serialize, Deserialize, TS, JsonSchema))]
 #[serde(tag = "type")]
 #[ts(export)]
 pub enum Game {
@@ -47,6 +51,9 @@ pub enum Game {
         game_name: GameType,       //used for identifying the "game" ("Minecraft")
         game_display_name: String, //displaying to the user what on earth this is ("MinecraftGlowstone")
     },
+    Process {
+        game_display_name: String, // supplied by the user when creating the instance ("Terraria")
+    },
 }
 
 #[test]
@@ -72,7 +79,278 @@ impl From<Flavour> for Game {
             Flavour::Forge { .. } => Self::MinecraftJava {
                 variant: MinecraftVariant::Forge,
             },
+            Flavour::Quilt { .. } => Self::MinecraftJava {
+                variant: MinecraftVariant::Quilt,
+            },
+        }
+    }
+}
+
+/// Limits on the resources used by the server process of an instance.
+///
+/// The monitor task checks the limits against the monitor reports, minecraft instances also have
+/// their JVM heap capped to the memory limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ResourceLimits {
+    /// in megabytes
+    pub memory_limit: Option<u64>,
+    /// percentage of the total CPU capacity of the host
+    pub cpu_limit: Option<f32>,
+    /// kill the instance when a limit is exceeded, otherwise only an event is emitted
+    pub kill_on_violation: bool,
+    /// in megabytes, uploads to the instance are refused once its directory is over it. Going over
+    /// the quota only emits an event, the instance is never killed for it
+    #[serde(default)]
+    pub disk_quota: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS, JsonSchema)]
+#[ts(export)]
+pub enum LimitedResource {
+    Memory,
+    Cpu,
+    Disk,
+}
+
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.memory_limit == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Memory limit cannot be 0"),
+            });
+        }
+        if self
+            .cpu_limit
+            .is_some_and(|cpu_limit| !(cpu_limit > 0.0 && cpu_limit <= 100.0))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("CPU limit must be a percentage between 0 and 100"),
+            });
+        }
+        if self.disk_quota == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Disk quota cannot be 0"),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// How an instance with `restart_on_crash` is restarted after its server process crashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(default)]
+#[ts(export)]
+pub struct CrashRestartPolicy {
+    /// restarts in a row before giving up
+    pub max_retries: u32,
+    /// seconds before the first restart, doubling with every retry
+    pub backoff: u32,
+    /// upper bound of the backoff in seconds
+    pub max_backoff: u32,
+    /// seconds a run has to last for it to reset the retries
+    pub stable_after: u32,
+}
+
+impl Default for CrashRestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff: 5,
+            max_backoff: 300,
+            stable_after: 600,
+        }
+    }
+}
+
+impl CrashRestartPolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.backoff > self.max_backoff {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Backoff cannot be longer than the maximum backoff"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Seconds to wait before the next restart after `retries` restarts in a row,
+    /// `None` once the retries are used up
+    pub fn restart_delay(&self, retries: u32) -> Option<u32> {
+        if retries >= self.max_retries {
+            return None;
+        }
+        Some(
+            self.backoff
+                .saturating_mul(2_u32.saturating_pow(retries))
+                .min(self.max_backoff),
+        )
+    }
+}
+
+const MAX_ICON_LEN: usize = 64;
+
+/// How an instance is shown on dashboards, the same for every client
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS, JsonSchema)]
+#[serde(default)]
+#[ts(export)]
+pub struct DisplayMetadata {
+    /// `#rrggbb`
+    pub color: Option<String>,
+    /// the name of an icon or an emoji, clients fall back to the game icon for ones they don't know
+    pub icon: Option<String>,
+    /// instances are listed by ascending sort order, the ones without one last by creation time
+    pub sort_order: Option<i32>,
+}
+
+impl DisplayMetadata {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(color) = &self.color {
+            if !(color.len() == 7
+                && color.starts_with('#')
+                && color[1..].chars().all(|c| c.is_ascii_hexdigit()))
+            {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Color must be written as #rrggbb"),
+                });
+            }
+        }
+        if let Some(icon) = &self.icon {
+            if icon.trim().is_empty()
+                || icon.chars().count() > MAX_ICON_LEN
+                || icon.chars().any(char::is_control)
+            {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Icon must be 1 to {MAX_ICON_LEN} characters"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+const MAX_HOSTNAME_LEN: usize = 253;
+
+/// How players outside the local network reach an instance
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[serde(default)]
+#[ts(export)]
+pub struct PublicAddress {
+    /// a domain or an address pointing at the host, the public ip of the host is used without one
+    pub hostname: Option<String>,
+    /// map the port of the instance on the router with UPnP while it runs
+    pub upnp: bool,
+}
+
+impl PublicAddress {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(hostname) = &self.hostname {
+            let is_ip = hostname.parse::<std::net::IpAddr>().is_ok();
+            let is_domain = hostname.len() <= MAX_HOSTNAME_LEN
+                && hostname.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.len() <= 63
+                        && !label.starts_with('-')
+                        && !label.ends_with('-')
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+            if !is_ip && !is_domain {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Hostname must be a domain name or an ip address"),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// what the value of a sensitive environment variable is replaced by in API responses
+pub const MASKED_ENV_VALUE: &str = "********";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct EnvVar {
+    pub value: String,
+    /// masked in API responses, e.g. a token a plugin reads
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+/// What the server process is spawned with on top of what the instance is configured to run
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, TS)]
+#[serde(default)]
+#[ts(export)]
+pub struct LaunchOverrides {
+    pub env: IndexMap<String, EnvVar>,
+    /// passed to the JVM before the server jar, e.g. GC flags. Ignored by instances without a JVM
+    pub jvm_args: Vec<String>,
+    /// passed to the server after its own arguments
+    pub program_args: Vec<String>,
+}
+
+impl LaunchOverrides {
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, var) in &self.env {
+            if name.is_empty()
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Invalid environment variable name {name}, use letters, digits and underscores"
+                    ),
+                });
+            }
+            if var.value.contains('\0') {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("The value of {name} cannot contain a null character"),
+                });
+            }
         }
+        if self
+            .jvm_args
+            .iter()
+            .chain(&self.program_args)
+            .any(|arg| arg.contains('\0'))
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Arguments cannot contain a null character"),
+            });
+        }
+        Ok(())
+    }
+
+    /// The overrides with the values of sensitive variables replaced, to send to clients
+    pub fn masked(&self) -> Self {
+        let mut masked = self.clone();
+        for var in masked.env.values_mut() {
+            if var.sensitive {
+                var.value = MASKED_ENV_VALUE.to_string();
+            }
+        }
+        masked
+    }
+
+    /// Keep the values of sensitive variables sent back masked, so clients can save the
+    /// overrides they got without knowing the secrets in them
+    pub fn unmask(mut self, current: &LaunchOverrides) -> Self {
+        for (name, var) in self.env.iter_mut() {
+            if var.sensitive && var.value == MASKED_ENV_VALUE {
+                if let Some(current_var) = current.env.get(name) {
+                    var.value = current_var.value.clone();
+                }
+            }
+        }
+        self
     }
 }
 
@@ -91,6 +369,25 @@ pub trait TConfigurable {
     /// does start when lodestone starts
     async fn auto_start(&self) -> bool;
     async fn restart_on_crash(&self) -> bool;
+    /// seconds between scheduled backups, `None` if scheduled backups are disabled
+    async fn backup_period(&self) -> Option<u32> {
+        None
+    }
+    async fn resource_limits(&self) -> ResourceLimits {
+        ResourceLimits::default()
+    }
+    async fn crash_restart_policy(&self) -> CrashRestartPolicy {
+        CrashRestartPolicy::default()
+    }
+    async fn display_metadata(&self) -> DisplayMetadata {
+        DisplayMetadata::default()
+    }
+    async fn public_address(&self) -> PublicAddress {
+        PublicAddress::default()
+    }
+    async fn launch_overrides(&self) -> LaunchOverrides {
+        LaunchOverrides::default()
+    }
     // setters
     async fn set_name(&mut self, name: String) -> Result<(), Error>;
     async fn set_description(&mut self, description: String) -> Result<(), Error>;
@@ -119,6 +416,42 @@ pub trait TConfigurable {
         })
     }
 
+    async fn set_resource_limits(&mut self, _limits: ResourceLimits) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support resource limits"),
+        })
+    }
+
+    async fn set_crash_restart_policy(&mut self, _policy: CrashRestartPolicy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support crash restart policies"),
+        })
+    }
+
+    async fn set_display_metadata(&mut self, _display: DisplayMetadata) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support display metadata"),
+        })
+    }
+
+    async fn set_public_address(&mut self, _address: PublicAddress) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support setting a public address"),
+        })
+    }
+
+    /// Takes effect on the next start
+    async fn set_launch_overrides(&mut self, _overrides: LaunchOverrides) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support launch overrides"),
+        })
+    }
+
     async fn change_version(&mut self, _version: String) -> Result<(), Error> {
         Err(Error {
             kind: ErrorKind::UnsupportedOperation,
@@ -135,3 +468,132 @@ pub trait TConfigurable {
         value: ConfigurableValue,
     ) -> Result<(), Error>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CrashRestartPolicy, DisplayMetadata, EnvVar, LaunchOverrides, PublicAddress,
+        MASKED_ENV_VALUE,
+    };
+
+    #[test]
+    fn test_restart_delay() {
+        let policy = CrashRestartPolicy {
+            max_retries: 4,
+            backoff: 10,
+            max_backoff: 60,
+            stable_after: 600,
+        };
+        assert_eq!(policy.restart_delay(0), Some(10));
+        assert_eq!(policy.restart_delay(1), Some(20));
+        assert_eq!(policy.restart_delay(2), Some(40));
+        assert_eq!(policy.restart_delay(3), Some(60));
+        assert_eq!(policy.restart_delay(4), None);
+        assert!(CrashRestartPolicy {
+            backoff: 120,
+            ..policy
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_display_metadata() {
+        let display = DisplayMetadata {
+            color: Some("#1a2B3c".to_string()),
+            icon: Some("⚔️".to_string()),
+            sort_order: Some(-1),
+        };
+        assert!(display.validate().is_ok());
+        assert!(DisplayMetadata::default().validate().is_ok());
+        for color in ["1a2b3c", "#1a2b3", "#1a2b3g", "#ééé"] {
+            assert!(DisplayMetadata {
+                color: Some(color.to_string()),
+                ..display.clone()
+            }
+            .validate()
+            .is_err());
+        }
+        for icon in [" ", "a\nb", &"a".repeat(65)] {
+            assert!(DisplayMetadata {
+                icon: Some(icon.to_string()),
+                ..display.clone()
+            }
+            .validate()
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_public_address() {
+        for hostname in [
+            "play.example.com",
+            "localhost",
+            "203.0.113.7",
+            "2001:db8::1",
+            "a-b.c",
+        ] {
+            assert!(PublicAddress {
+                hostname: Some(hostname.to_string()),
+                upnp: false,
+            }
+            .validate()
+            .is_ok());
+        }
+        for hostname in [
+            "",
+            "play..example.com",
+            "-a.com",
+            "a_b.com",
+            "a.com:25565",
+            "a b",
+        ] {
+            assert!(PublicAddress {
+                hostname: Some(hostname.to_string()),
+                upnp: true,
+            }
+            .validate()
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_launch_overrides() {
+        let mut overrides = LaunchOverrides {
+            jvm_args: vec!["-XX:+UseG1GC".to_string()],
+            ..Default::default()
+        };
+        overrides.env.insert(
+            "TZ".to_string(),
+            EnvVar {
+                value: "UTC".to_string(),
+                sensitive: false,
+            },
+        );
+        overrides.env.insert(
+            "API_TOKEN".to_string(),
+            EnvVar {
+                value: "secret".to_string(),
+                sensitive: true,
+            },
+        );
+        assert!(overrides.validate().is_ok());
+
+        let masked = overrides.masked();
+        assert_eq!(masked.env["TZ"].value, "UTC");
+        assert_eq!(masked.env["API_TOKEN"].value, MASKED_ENV_VALUE);
+        assert_eq!(masked.unmask(&overrides), overrides);
+
+        for name in ["", "1ABC", "A-B", "A=B"] {
+            let mut invalid = overrides.clone();
+            invalid.env.insert(
+                name.to_string(),
+                EnvVar {
+                    value: "".to_string(),
+                    sensitive: false,
+                },
+            );
+            assert!(invalid.validate().is_err());
+        }
+    }
+}
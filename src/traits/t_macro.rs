@@ -7,9 +7,11 @@ use crate::{
     error::{Error, ErrorKind},
     events::CausedBy,
     macro_executor::MacroPID,
+    macro_sandbox::MacroGrant,
     traits::GameInstance,
 };
 
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -37,7 +39,7 @@ pub struct HistoryEntry {
     pub exit_status: ExitStatus,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, JsonSchema)]
 #[ts(export)]
 #[serde(tag = "type")]
 pub enum ExitStatus {
@@ -87,4 +89,17 @@ pub trait TMacro {
             source: eyre!("This instance does not support killing macro"),
         })
     }
+    /// The grant a macro runs with, the default one if it was never set
+    async fn macro_grant(&self, _name: &str) -> Result<MacroGrant, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support sandboxing macro"),
+        })
+    }
+    async fn set_macro_grant(&mut self, _name: &str, _grant: MacroGrant) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support sandboxing macro"),
+        })
+    }
 }
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
@@ -14,7 +15,7 @@ pub trait TPlayer {
 }
 
 #[enum_dispatch::enum_dispatch(TPlayer)]
-#[derive(Serialize, Deserialize, Debug, Eq, TS, Clone)]
+#[derive(Serialize, Deserialize, Debug, Eq, TS, Clone, JsonSchema)]
 #[serde(tag = "type")]
 #[ts(export)]
 pub enum Player {
@@ -1,13 +1,15 @@
 use async_trait::async_trait;
 use color_eyre::eyre::eyre;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use ts_rs::TS;
 
+use crate::error::ErrorKind;
 use crate::events::CausedBy;
 use crate::Error;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS, Copy, JsonSchema)]
 #[serde(rename = "InstanceState")]
 #[ts(export)]
 pub enum State {
@@ -16,6 +18,10 @@ pub enum State {
     Stopping,
     Stopped,
     Error,
+    /// the process exited on its own with a failure, it can be started again
+    Crashed,
+    /// the server files are being changed, e.g. to another version, it can't be started meanwhile
+    Installing,
 }
 
 pub enum StateAction {
@@ -23,6 +29,31 @@ pub enum StateAction {
     UserStop,
     InstanceStart,
     InstanceStop,
+    /// the process exited without being asked to
+    InstanceCrash,
+    InstallStart,
+    InstallFinish,
+}
+
+/// Whether a running instance is done starting, for a minecraft server it accepts players
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, TS, JsonSchema)]
+#[ts(export)]
+pub struct Readiness {
+    pub ready: bool,
+    /// milliseconds from the start of the current run to its readiness
+    pub startup_duration: Option<u64>,
+}
+
+/// A console command the instance knows of, for autocomplete
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct CommandInfo {
+    /// without the leading slash
+    pub name: String,
+    /// the arguments or a description, as the server prints them
+    pub usage: Option<String>,
+    /// false for commands built into the game, true for ones learned from the server's output
+    pub learned: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -44,6 +75,14 @@ impl From<sysinfo::DiskUsage> for DiskUsage {
         }
     }
 }
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct NetworkUsage {
+    pub total_bytes_sent: u64,
+    pub total_bytes_received: u64,
+    pub connection_count: u32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS, Default)]
 #[serde(rename = "PerformanceReport")]
 #[ts(export)]
@@ -52,6 +91,24 @@ pub struct MonitorReport {
     pub disk_usage: Option<DiskUsage>,
     pub cpu_usage: Option<f32>,
     pub start_time: Option<u64>,
+    pub network_usage: Option<NetworkUsage>,
+    /// size of the instance directory in bytes, filled in by the core from its own measurements
+    #[serde(default)]
+    pub directory_size: Option<u64>,
+    /// what the game reports about itself, for games that can be asked
+    #[serde(default)]
+    pub game_metrics: Option<GameMetrics>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, Default, PartialEq)]
+#[ts(export)]
+pub struct GameMetrics {
+    /// ticks per second, lower than the target rate when the server is lagging
+    pub tps: Option<f64>,
+    /// average time a tick takes in milliseconds
+    pub tick_time_ms: Option<f64>,
+    pub loaded_chunks: Option<u32>,
+    pub entity_count: Option<u32>,
 }
 
 impl ToString for State {
@@ -62,11 +119,26 @@ impl ToString for State {
             State::Stopping => "Stopping".to_string(),
             State::Stopped => "Stopped".to_string(),
             State::Error => "Error".to_string(),
+            State::Crashed => "Crashed".to_string(),
+            State::Installing => "Installing".to_string(),
         }
     }
 }
 
 impl State {
+    /// No process of the instance is running, it may be installing though
+    pub fn is_stopped(&self) -> bool {
+        matches!(
+            self,
+            State::Stopped | State::Crashed | State::Error | State::Installing
+        )
+    }
+
+    /// Stopped and not installing either, so its files can be changed or it can be deleted
+    pub fn is_idle(&self) -> bool {
+        matches!(self, State::Stopped | State::Crashed | State::Error)
+    }
+
     pub fn try_new_state(
         &self,
         action: StateAction,
@@ -74,30 +146,50 @@ impl State {
     ) -> Result<State, Error> {
         let state = match (*self, action) {
             (State::Starting, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is already starting"))
+                Err("Cannot start an instance that is already starting")
             }
             (State::Starting, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is starting"))
+                Err("Cannot stop an instance that is starting")
+            }
+            (State::Installing, StateAction::UserStart) => {
+                Err("Cannot start an instance that is installing")
+            }
+            (State::Installing, StateAction::UserStop) => {
+                Err("Cannot stop an instance that is installing")
             }
             (_, StateAction::InstanceStart) => Ok(State::Running),
             (_, StateAction::InstanceStop) => Ok(State::Stopped),
+            (_, StateAction::InstanceCrash) => Ok(State::Crashed),
             (State::Running, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is already running"))
+                Err("Cannot start an instance that is already running")
             }
             (State::Running, StateAction::UserStop) => Ok(State::Stopping),
             (State::Stopping, StateAction::UserStart) => {
-                Err(eyre!("Cannot start an instance that is stopping"))
+                Err("Cannot start an instance that is stopping")
             }
             (State::Stopping, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is already stopping"))
+                Err("Cannot stop an instance that is already stopping")
+            }
+            (State::Stopped | State::Crashed | State::Error, StateAction::UserStart) => {
+                Ok(State::Starting)
             }
-            (State::Stopped, StateAction::UserStart) => Ok(State::Starting),
-            (State::Stopped, StateAction::UserStop) => {
-                Err(eyre!("Cannot stop an instance that is already stopped"))
+            (State::Stopped | State::Crashed | State::Error, StateAction::UserStop) => {
+                Err("Cannot stop an instance that is already stopped")
             }
-            (State::Error, StateAction::UserStart) => todo!(),
-            (State::Error, StateAction::UserStop) => todo!(),
-        }?;
+            (State::Stopped | State::Crashed | State::Error, StateAction::InstallStart) => {
+                Ok(State::Installing)
+            }
+            (State::Installing, StateAction::InstallStart) => {
+                Err("Cannot install on an instance that is already installing")
+            }
+            (_, StateAction::InstallStart) => Err("Stop the instance before installing"),
+            (State::Installing, StateAction::InstallFinish) => Ok(State::Stopped),
+            (_, StateAction::InstallFinish) => Err("The instance is not installing"),
+        }
+        .map_err(|message| Error {
+            kind: ErrorKind::InvalidStateTransition,
+            source: eyre!(message),
+        })?;
         if let Some(on_transit) = on_transit {
             on_transit(state);
         }
@@ -125,6 +217,71 @@ pub trait TServer {
     async fn restart(&mut self, caused_by: CausedBy, block: bool) -> Result<(), Error>;
     async fn kill(&mut self, caused_by: CausedBy) -> Result<(), Error>;
     async fn state(&self) -> State;
+    /// Instances that can't tell are ready as soon as they are running
+    async fn readiness(&self) -> Readiness {
+        Readiness {
+            ready: self.state().await == State::Running,
+            startup_duration: None,
+        }
+    }
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error>;
     async fn monitor(&self) -> MonitorReport;
+    /// The process of the instance on this host, if it runs one
+    async fn pid(&self) -> Option<u32> {
+        None
+    }
+    /// Commands to offer for autocomplete, sorted by name
+    async fn known_commands(&self) -> Vec<CommandInfo> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{State, StateAction};
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_state_transitions() {
+        let mut state = State::Stopped;
+        state.try_transition(StateAction::UserStart, None).unwrap();
+        assert_eq!(state, State::Starting);
+        // e.g. a double click on start
+        assert_eq!(
+            state
+                .try_new_state(StateAction::UserStart, None)
+                .unwrap_err()
+                .kind,
+            ErrorKind::InvalidStateTransition
+        );
+        state
+            .try_transition(StateAction::InstanceStart, None)
+            .unwrap();
+        state
+            .try_transition(StateAction::InstanceCrash, None)
+            .unwrap();
+        assert_eq!(state, State::Crashed);
+        assert!(state.is_stopped());
+
+        state
+            .try_transition(StateAction::InstallStart, None)
+            .unwrap();
+        assert_eq!(state, State::Installing);
+        assert!(state.is_stopped());
+        assert!(!state.is_idle());
+        assert!(state.try_new_state(StateAction::UserStart, None).is_err());
+        assert!(state
+            .try_new_state(StateAction::InstallStart, None)
+            .is_err());
+        state
+            .try_transition(StateAction::InstallFinish, None)
+            .unwrap();
+        assert_eq!(state, State::Stopped);
+        assert!(state
+            .try_new_state(StateAction::InstallFinish, None)
+            .is_err());
+        assert!(State::Running
+            .try_new_state(StateAction::InstallStart, None)
+            .is_err());
+    }
 }
@@ -0,0 +1,358 @@
+//! Files deleted from the file manager are moved to a trash rather than removed, so a wrong click
+//! can be undone. Every instance has its own trash in its directory, which counts towards its disk
+//! quota, and files outside of instances go to the trash of the core.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    disk_usage::directory_size,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    global_settings::GlobalSettings,
+    instance_map::InstanceMap,
+    prelude::path_to_trash,
+    traits::t_configurable::TConfigurable,
+    types::Snowflake,
+    util::{resolve_path_conflict, scoped_join_win_safe},
+};
+
+pub const TRASH_DIR_NAME: &str = ".trash";
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct TrashEntry {
+    pub id: Snowflake,
+    /// relative to the instance in the trash of an instance, absolute otherwise
+    pub original_path: PathBuf,
+    pub is_dir: bool,
+    /// in bytes, of every file under it for a directory
+    pub size: u64,
+    pub deleted_by: CausedBy,
+    /// unix timestamp in seconds
+    pub deleted_at: i64,
+}
+
+/// A trash directory, each deleted file or directory is kept in it under the id of its entry,
+/// next to the entry as `<id>.json`
+pub struct Trash {
+    dir: PathBuf,
+    /// original paths are relative to this
+    root: Option<PathBuf>,
+}
+
+/// Move a file or directory, copying it if it is on another file system
+async fn move_path(from: &Path, to: &Path) -> Result<(), Error> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    let (from, to) = (from.to_owned(), to.to_owned());
+    tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        if from.is_dir() {
+            std::fs::create_dir_all(&to)
+                .context(format!("Failed to create directory {}", to.display()))?;
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.content_only = true;
+            fs_extra::dir::move_dir(&from, &to, &options).map_err(|e| {
+                eyre!(
+                    "Failed to move {} to {} : {e}",
+                    from.display(),
+                    to.display()
+                )
+            })?;
+        } else {
+            std::fs::copy(&from, &to).context(format!(
+                "Failed to copy {} to {}",
+                from.display(),
+                to.display()
+            ))?;
+            std::fs::remove_file(&from).context(format!("Failed to remove {}", from.display()))?;
+        }
+        Ok(())
+    })
+    .await
+    .context("Move task panicked")?
+}
+
+async fn remove_path(path: &Path) -> Result<(), Error> {
+    if path.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    }
+    .context(format!("Failed to remove {}", path.display()))?;
+    Ok(())
+}
+
+impl Trash {
+    pub fn for_instance(instance_path: &Path) -> Self {
+        Self {
+            dir: instance_path.join(TRASH_DIR_NAME),
+            root: Some(instance_path.to_owned()),
+        }
+    }
+
+    pub fn global() -> Self {
+        Self {
+            dir: path_to_trash().clone(),
+            root: None,
+        }
+    }
+
+    /// Whether `path` is the trash or in it, what is deleted from there is gone for good
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.dir)
+    }
+
+    fn path_to_entry(&self, id: &Snowflake) -> PathBuf {
+        self.dir.join(format!("{}.json", id.to_string()))
+    }
+
+    /// Where the deleted file or directory of an entry is kept
+    pub fn path_to_content(&self, id: &Snowflake) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Move `path` to the trash, a directory with the trash in it can't be
+    pub async fn move_to_trash(
+        &self,
+        path: &Path,
+        deleted_by: CausedBy,
+    ) -> Result<TrashEntry, Error> {
+        if self.dir.starts_with(path) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} has the trash in it", path.display()),
+            });
+        }
+        let metadata = tokio::fs::symlink_metadata(path)
+            .await
+            .context(format!("Failed to read metadata of {}", path.display()))?;
+        let original_path = match &self.root {
+            Some(root) => path
+                .strip_prefix(root)
+                .context("Failed to get relative path")?
+                .to_owned(),
+            None => path.to_owned(),
+        };
+        let size = {
+            let path = path.to_owned();
+            tokio::task::spawn_blocking(move || directory_size(&path))
+                .await
+                .context("Size task panicked")?
+        };
+        let entry = TrashEntry {
+            id: Snowflake::default(),
+            original_path,
+            is_dir: metadata.is_dir(),
+            size,
+            deleted_by,
+            deleted_at: chrono::Utc::now().timestamp(),
+        };
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .context(format!("Failed to create trash {}", self.dir.display()))?;
+        move_path(path, &self.path_to_content(&entry.id)).await?;
+        crate::util::fs::write_all(
+            self.path_to_entry(&entry.id),
+            serde_json::to_string(&entry).context("Failed to serialize trash entry")?,
+        )
+        .await?;
+        Ok(entry)
+    }
+
+    /// Newest first
+    pub async fn list(&self) -> Result<Vec<TrashEntry>, Error> {
+        let mut entries = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(entries);
+        }
+        let mut read_dir = tokio::fs::read_dir(&self.dir)
+            .await
+            .context(format!("Failed to read trash {}", self.dir.display()))?;
+        while let Some(file) = read_dir
+            .next_entry()
+            .await
+            .context(format!("Failed to read trash {}", self.dir.display()))?
+        {
+            let path = file.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            match crate::util::fs::read_to_string(&path)
+                .await
+                .and_then(|content| {
+                    serde_json::from_str::<TrashEntry>(&content)
+                        .context(format!("Failed to parse trash entry {}", path.display()))
+                        .map_err(Error::from)
+                }) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => error!("Skipping trash entry : {e}"),
+            }
+        }
+        entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+        Ok(entries)
+    }
+
+    pub async fn get(&self, id: &Snowflake) -> Result<TrashEntry, Error> {
+        let path = self.path_to_entry(id);
+        if !path.is_file() {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Trash entry not found"),
+            });
+        }
+        let content = crate::util::fs::read_to_string(&path).await?;
+        Ok(serde_json::from_str(&content)
+            .context(format!("Failed to parse trash entry {}", path.display()))?)
+    }
+
+    /// Put an entry back where it was, next to it with a new name if the path was taken since.
+    /// Returns where it was restored to
+    pub async fn restore(&self, id: &Snowflake) -> Result<PathBuf, Error> {
+        let entry = self.get(id).await?;
+        let original_path = match &self.root {
+            // the entry is a file in the instance, anyone who can write there can edit it
+            Some(root) => scoped_join_win_safe(root, &entry.original_path)?,
+            None => entry.original_path.clone(),
+        };
+        let path = resolve_path_conflict(original_path, None);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context(format!("Failed to create directory {}", parent.display()))?;
+        }
+        move_path(&self.path_to_content(id), &path).await?;
+        crate::util::fs::remove_file(self.path_to_entry(id)).await?;
+        Ok(path)
+    }
+
+    pub async fn purge(&self, id: &Snowflake) -> Result<TrashEntry, Error> {
+        let entry = self.get(id).await?;
+        let content = self.path_to_content(id);
+        if tokio::fs::symlink_metadata(&content).await.is_ok() {
+            remove_path(&content).await?;
+        }
+        crate::util::fs::remove_file(self.path_to_entry(id)).await?;
+        Ok(entry)
+    }
+
+    /// Purge the entries deleted before `before`, a unix timestamp in seconds
+    pub async fn purge_before(&self, before: i64) -> Result<Vec<TrashEntry>, Error> {
+        let mut purged = Vec::new();
+        for entry in self.list().await? {
+            if entry.deleted_at < before {
+                purged.push(self.purge(&entry.id).await?);
+            }
+        }
+        Ok(purged)
+    }
+
+    pub async fn purge_all(&self) -> Result<Vec<TrashEntry>, Error> {
+        self.purge_before(i64::MAX).await
+    }
+}
+
+/// Empties the trashes of what was deleted longer ago than the retention of the global settings
+pub async fn trash_purge_task(instances: InstanceMap, global_settings: Arc<Mutex<GlobalSettings>>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let retention_days = match global_settings.lock().await.trash_retention_days() {
+            Some(retention_days) => retention_days,
+            None => continue,
+        };
+        let before = chrono::Utc::now().timestamp() - i64::from(retention_days) * SECONDS_PER_DAY;
+        let mut trashes = vec![("the core".to_string(), Trash::global())];
+        for (uuid, instance) in instances.snapshot() {
+            trashes.push((
+                uuid.to_string(),
+                Trash::for_instance(&instance.path().await),
+            ));
+        }
+        for (owner, trash) in trashes {
+            match trash.purge_before(before).await {
+                Ok(purged) if !purged.is_empty() => info!(
+                    "Purged {} entries from the trash of {owner}, freeing {}",
+                    purged.len(),
+                    crate::util::format_byte(purged.iter().map(|entry| entry.size).sum())
+                ),
+                Ok(_) => {}
+                Err(e) => error!("Failed to purge the trash of {owner} : {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trash;
+    use crate::events::CausedBy;
+
+    #[tokio::test]
+    async fn test_trash() {
+        let instance = tempfile::tempdir().unwrap();
+        let trash = Trash::for_instance(instance.path());
+        std::fs::create_dir_all(instance.path().join("world/region")).unwrap();
+        std::fs::write(instance.path().join("world/region/r.0.0.mca"), "region").unwrap();
+        std::fs::write(instance.path().join("server.properties"), "motd=hi").unwrap();
+
+        let world = trash
+            .move_to_trash(&instance.path().join("world"), CausedBy::System)
+            .await
+            .unwrap();
+        assert!(world.is_dir);
+        assert_eq!(world.size, 6);
+        assert_eq!(world.original_path, std::path::Path::new("world"));
+        assert!(!instance.path().join("world").exists());
+        let properties = trash
+            .move_to_trash(&instance.path().join("server.properties"), CausedBy::System)
+            .await
+            .unwrap();
+        assert_eq!(trash.list().await.unwrap().len(), 2);
+        assert!(trash.contains(&instance.path().join(".trash")));
+        assert!(trash
+            .move_to_trash(instance.path(), CausedBy::System)
+            .await
+            .is_err());
+
+        let restored = trash.restore(&world.id).await.unwrap();
+        assert_eq!(restored, instance.path().join("world"));
+        assert_eq!(
+            std::fs::read_to_string(instance.path().join("world/region/r.0.0.mca")).unwrap(),
+            "region"
+        );
+        // the path was taken since, so it is restored next to it
+        std::fs::write(instance.path().join("server.properties"), "motd=new").unwrap();
+        let restored = trash.restore(&properties.id).await.unwrap();
+        assert_ne!(restored, instance.path().join("server.properties"));
+        assert_eq!(std::fs::read_to_string(restored).unwrap(), "motd=hi");
+        assert!(trash.list().await.unwrap().is_empty());
+
+        let world = trash
+            .move_to_trash(&instance.path().join("world"), CausedBy::System)
+            .await
+            .unwrap();
+        assert!(trash
+            .purge_before(world.deleted_at)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(trash.purge_all().await.unwrap(), vec![world]);
+        assert!(std::fs::read_dir(instance.path().join(".trash"))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+}
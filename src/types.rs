@@ -1,15 +1,19 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
+use crate::migration::versioned::DOT_LODESTONE_CONFIG;
 use crate::migration::DotLodestoneConfigV043;
 use crate::traits::t_configurable::GameType;
 use crate::{
     implementations::minecraft::Flavour, migration::RestoreConfigV042, prelude::SNOWFLAKE_GENERATOR,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
 use ts_rs::TS;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, TS, Copy)]
+// snowflakes are generated from a timestamp, so ordering them orders by creation time
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, TS, Copy)]
 #[ts(export)]
 #[serde(into = "String")]
 #[derive(sqlx::Type)]
@@ -44,6 +48,17 @@ fn test_snowflake() {
     assert_eq!(snowflake1, snowflake2);
 }
 
+// serialized into a string, an i64 doesn't fit in a javascript number
+impl JsonSchema for Snowflake {
+    fn schema_name() -> String {
+        "Snowflake".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
 impl Default for Snowflake {
     fn default() -> Self {
         Self(get_snowflake())
@@ -66,7 +81,7 @@ fn get_snowflake() -> i64 {
     SNOWFLAKE_GENERATOR.lock().unwrap().real_time_generate()
 }
 
-#[derive(Debug, Clone, Eq, Serialize, Deserialize, TS)]
+#[derive(Debug, Clone, Eq, Serialize, Deserialize, TS, JsonSchema)]
 #[serde(transparent)]
 #[ts(export)]
 #[derive(sqlx::Type)]
@@ -121,6 +136,9 @@ impl Display for InstanceUuid {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LodestoneMetadata {
     pub semver: semver::Version,
+    /// the schema version of each store by its path relative to the lodestone path
+    #[serde(default)]
+    pub store_versions: BTreeMap<String, u32>,
 }
 
 /// A marker file to indicate to lodestone that the directory contains a lodestone instance
@@ -130,6 +148,13 @@ pub struct DotLodestoneConfig {
     game_type: GameType,
     uuid: InstanceUuid,
     creation_time: i64,
+    /// files written before the format was versioned are at 1
+    #[serde(default = "default_schema_version")]
+    schema_version: u32,
+}
+
+fn default_schema_version() -> u32 {
+    1
 }
 
 impl From<RestoreConfigV042> for DotLodestoneConfig {
@@ -145,6 +170,7 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
             game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            schema_version: DOT_LODESTONE_CONFIG.current_version(),
         }
     }
 }
@@ -155,6 +181,7 @@ impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
             game_type: config.game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            schema_version: DOT_LODESTONE_CONFIG.current_version(),
         }
     }
 }
@@ -165,6 +192,7 @@ impl DotLodestoneConfig {
             game_type,
             uuid,
             creation_time: chrono::Utc::now().timestamp(),
+            schema_version: DOT_LODESTONE_CONFIG.current_version(),
         }
     }
 
@@ -0,0 +1,431 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    error::{Error, ErrorKind},
+    prelude::path_to_tmp,
+    types::InstanceUuid,
+    util::{rand_alphanumeric, resolve_path_conflict},
+};
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+pub const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+/// uploads nothing was sent to for this long are dropped, in seconds
+const UPLOAD_LIFETIME: i64 = 24 * 60 * 60;
+
+/// Where the uploaded file goes once it is complete
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum UploadTarget {
+    /// `relative_path` is the directory in the instance
+    Instance {
+        instance_uuid: InstanceUuid,
+        relative_path: String,
+    },
+    Global {
+        path: PathBuf,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct NewUpload {
+    pub file_name: String,
+    /// size of the whole file in bytes
+    pub size: u64,
+    /// size of every part but the last, in bytes
+    pub chunk_size: Option<u64>,
+    /// hex encoded SHA-256 of the whole file, checked once every part is in
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS)]
+#[ts(export)]
+pub struct UploadSession {
+    pub id: String,
+    pub owner: UserId,
+    pub target: UploadTarget,
+    pub file_name: String,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub sha256: Option<String>,
+    /// indices of the parts written so far
+    pub received: HashSet<u64>,
+    pub creation_time: i64,
+    pub last_activity: i64,
+}
+
+impl UploadSession {
+    pub fn part_count(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size).max(1)
+    }
+
+    /// The parts still to be sent, this is what a client resumes from
+    pub fn missing_parts(&self) -> Vec<u64> {
+        (0..self.part_count())
+            .filter(|index| !self.received.contains(index))
+            .collect()
+    }
+
+    fn part_len(&self, index: u64) -> u64 {
+        if index + 1 == self.part_count() {
+            self.size - index * self.chunk_size
+        } else {
+            self.chunk_size
+        }
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        now - self.last_activity > UPLOAD_LIFETIME
+    }
+}
+
+fn path_to_uploads() -> PathBuf {
+    path_to_tmp().join("uploads")
+}
+
+fn path_to_part_file(id: &str) -> PathBuf {
+    path_to_uploads().join(format!("{id}.part"))
+}
+
+pub(crate) fn sha256_of_file(path: &Path) -> Result<String, Error> {
+    let mut file = std::fs::File::open(path)
+        .context(format!("Failed to open {} to hash it", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .context(format!("Failed to read {} to hash it", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Move a file, copying it if it is on another file system
+async fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if tokio::fs::rename(from, to).await.is_err() {
+        tokio::fs::copy(from, to).await.context(format!(
+            "Failed to move {} to {}",
+            from.display(),
+            to.display()
+        ))?;
+        tokio::fs::remove_file(from).await.ok();
+    }
+    Ok(())
+}
+
+/// Uploads sent in parts, persisted in the stores directory so interrupted uploads can resume
+/// after the core restarts
+pub struct UploadSessions {
+    path_to_store: PathBuf,
+    sessions: HashMap<String, UploadSession>,
+}
+
+impl UploadSessions {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.sessions = HashMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read upload sessions file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.sessions = serde_json::from_slice(&content).context(format!(
+            "Failed to parse upload sessions file at {}",
+            self.path_to_store.display()
+        ))?;
+        // the part file of an upload may be gone if the tmp directory was cleared
+        self.sessions.retain(|id, _| path_to_part_file(id).exists());
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.sessions)
+                .context("Failed to serialize upload sessions")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write upload sessions file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// The upload `id`, as long as it belongs to `owner`
+    pub fn session(&self, id: &str, owner: &UserId) -> Result<&UploadSession, Error> {
+        self.sessions
+            .get(id)
+            .filter(|session| &session.owner == owner)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Upload not found"),
+            })
+    }
+
+    async fn remove_expired(&mut self) {
+        let now = chrono::Utc::now().timestamp();
+        let expired: Vec<String> = self
+            .sessions
+            .values()
+            .filter(|session| session.is_expired(now))
+            .map(|session| session.id.clone())
+            .collect();
+        for id in expired {
+            self.sessions.remove(&id);
+            if let Err(e) = tokio::fs::remove_file(path_to_part_file(&id)).await {
+                warn!("Failed to remove the part file of expired upload {id} : {e}");
+            }
+        }
+    }
+
+    pub async fn create(
+        &mut self,
+        owner: UserId,
+        target: UploadTarget,
+        new_upload: NewUpload,
+    ) -> Result<UploadSession, Error> {
+        let chunk_size = new_upload.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Chunk size must be between 1 and {MAX_CHUNK_SIZE} bytes"),
+            });
+        }
+        let file_name = sanitize_filename::sanitize(&new_upload.file_name);
+        if file_name.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Missing file name"),
+            });
+        }
+        self.remove_expired().await;
+        let now = chrono::Utc::now().timestamp();
+        let session = UploadSession {
+            id: rand_alphanumeric(32),
+            owner,
+            target,
+            file_name,
+            size: new_upload.size,
+            chunk_size,
+            sha256: new_upload.sha256.map(|sha256| sha256.to_lowercase()),
+            received: HashSet::new(),
+            creation_time: now,
+            last_activity: now,
+        };
+        crate::util::fs::create_dir_all(path_to_uploads()).await?;
+        let part_file = crate::util::fs::create(path_to_part_file(&session.id)).await?;
+        part_file
+            .set_len(session.size)
+            .await
+            .context("Failed to allocate the upload")?;
+        self.sessions.insert(session.id.clone(), session.clone());
+        if let Err(e) = self.write_to_file().await {
+            self.sessions.remove(&session.id);
+            tokio::fs::remove_file(path_to_part_file(&session.id))
+                .await
+                .ok();
+            return Err(e);
+        }
+        Ok(session)
+    }
+
+    /// Check that `data` is the right size to be part `index` of the upload
+    pub fn check_part(
+        &self,
+        id: &str,
+        owner: &UserId,
+        index: u64,
+        data: &[u8],
+        sha256: Option<&str>,
+    ) -> Result<u64, Error> {
+        let session = self.session(id, owner)?;
+        if index >= session.part_count() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Part {index} is out of range, the upload has {} parts",
+                    session.part_count()
+                ),
+            });
+        }
+        if data.len() as u64 != session.part_len(index) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Part {index} should be {} bytes but {} were sent",
+                    session.part_len(index),
+                    data.len()
+                ),
+            });
+        }
+        if let Some(sha256) = sha256 {
+            if format!("{:x}", Sha256::digest(data)) != sha256.to_lowercase() {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Checksum mismatch for part {index}"),
+                });
+            }
+        }
+        Ok(index * session.chunk_size)
+    }
+
+    /// Record that part `index` was written, see `write_part`
+    pub async fn mark_received(&mut self, id: &str, index: u64) -> Result<UploadSession, Error> {
+        let session = self.sessions.get_mut(id).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Upload not found"),
+        })?;
+        session.received.insert(index);
+        session.last_activity = chrono::Utc::now().timestamp();
+        let session = session.clone();
+        // the part is on disk, worst case it is sent again after a restart
+        self.write_to_file().await?;
+        Ok(session)
+    }
+
+    /// Forget the upload and remove what was sent of it
+    pub async fn abort(&mut self, id: &str, owner: &UserId) -> Result<(), Error> {
+        self.session(id, owner)?;
+        self.sessions.remove(id);
+        tokio::fs::remove_file(path_to_part_file(id)).await.ok();
+        self.write_to_file().await
+    }
+
+    /// Take a finished upload out of the store, failing if parts are missing
+    pub async fn take_complete(
+        &mut self,
+        id: &str,
+        owner: &UserId,
+    ) -> Result<UploadSession, Error> {
+        let session = self.session(id, owner)?;
+        let missing = session.missing_parts();
+        if !missing.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("The upload is missing {} part(s)", missing.len()),
+            });
+        }
+        let session = self.sessions.remove(id).expect("session was just found");
+        if let Err(e) = self.write_to_file().await {
+            self.sessions.insert(id.to_string(), session);
+            return Err(e);
+        }
+        Ok(session)
+    }
+}
+
+/// Write part of an upload at `offset`, parts can be written concurrently
+pub async fn write_part(id: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path_to_part_file(id))
+        .await
+        .context("Failed to open the upload")?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .context("Failed to seek in the upload")?;
+    file.write_all(data)
+        .await
+        .context("Failed to write part of the upload")?;
+    file.flush()
+        .await
+        .context("Failed to write part of the upload")?;
+    Ok(())
+}
+
+/// Verify the checksum of a complete upload and move it into `dir`, returns where it ended up
+pub async fn finish_upload(session: &UploadSession, dir: &Path) -> Result<PathBuf, Error> {
+    let part_file = path_to_part_file(&session.id);
+    if let Some(expected) = &session.sha256 {
+        let actual = {
+            let part_file = part_file.clone();
+            tokio::task::spawn_blocking(move || sha256_of_file(&part_file))
+                .await
+                .context("Failed to hash the upload")??
+        };
+        if actual != *expected {
+            tokio::fs::remove_file(&part_file).await.ok();
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Checksum mismatch, expected {expected} but the upload hashes to {actual}, upload it again"
+                ),
+            });
+        }
+    }
+    crate::util::fs::create_dir_all(dir).await?;
+    let dest = resolve_path_conflict(dir.join(&session.file_name), None);
+    move_file(&part_file, &dest).await?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{sha256_of_file, UploadSession, UploadTarget};
+
+    #[test]
+    fn test_parts() {
+        let mut session = UploadSession {
+            id: "upload".to_string(),
+            owner: "USER_a".to_string().into(),
+            target: UploadTarget::Global {
+                path: "/tmp".into(),
+            },
+            file_name: "world.zip".to_string(),
+            size: 25,
+            chunk_size: 10,
+            sha256: None,
+            received: HashSet::new(),
+            creation_time: 0,
+            last_activity: 0,
+        };
+        assert_eq!(session.part_count(), 3);
+        assert_eq!(session.part_len(0), 10);
+        assert_eq!(session.part_len(2), 5);
+        session.received.insert(1);
+        assert_eq!(session.missing_parts(), vec![0, 2]);
+
+        session.size = 0;
+        assert_eq!(session.part_count(), 1);
+        assert_eq!(session.part_len(0), 0);
+    }
+
+    #[test]
+    fn test_sha256_of_file() {
+        let temp_dir = tempdir::TempDir::new("test_sha256").unwrap();
+        let path = temp_dir.path().join("file");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_of_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
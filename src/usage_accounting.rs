@@ -0,0 +1,432 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::error;
+use ts_rs::TS;
+
+use crate::{
+    auth::user_id::UserId,
+    disk_usage::DirectorySizes,
+    error::{Error, ErrorKind},
+    instance_map::InstanceMap,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+    user_quotas::UserQuotas,
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// a sample covers at most this much time, so a suspended host doesn't bill for the time it slept
+const MAX_SAMPLE_SPAN: Duration = Duration::from_secs(120);
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// months of usage kept, older ones are dropped
+const RETAINED_MONTHS: usize = 24;
+
+/// The resources an instance used over a month
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct InstanceUsage {
+    pub instance_uuid: InstanceUuid,
+    /// the name the instance had when it was last sampled
+    pub instance_name: String,
+    /// the user who created the instance, `None` for instances from before ownership was tracked
+    pub owner: Option<UserId>,
+    pub running_hours: f64,
+    /// memory used in megabytes times the hours it was used for
+    pub memory_mb_hours: f64,
+    /// cpu time, 100% of a core for a second is a cpu second
+    pub cpu_seconds: f64,
+    /// size of the instance directory in megabytes times hours, counted while stopped too
+    pub storage_mb_hours: f64,
+}
+
+impl InstanceUsage {
+    fn add(&mut self, other: &InstanceUsage) {
+        self.running_hours += other.running_hours;
+        self.memory_mb_hours += other.memory_mb_hours;
+        self.cpu_seconds += other.cpu_seconds;
+        self.storage_mb_hours += other.storage_mb_hours;
+    }
+}
+
+/// The resources the instances of a user used over a month
+#[derive(Serialize, Clone, Debug, Default, PartialEq, TS)]
+#[ts(export)]
+pub struct UserUsage {
+    /// `None` adds up the instances that belong to no one
+    pub user_id: Option<UserId>,
+    pub instance_count: u32,
+    pub running_hours: f64,
+    pub memory_mb_hours: f64,
+    pub cpu_seconds: f64,
+    pub storage_mb_hours: f64,
+}
+
+/// One sample of an instance, what it used is spread over the time since the last sample
+#[derive(Clone, Debug, Default, PartialEq)]
+struct UsageSample {
+    running: bool,
+    memory_bytes: Option<u64>,
+    cpu_percent: Option<f32>,
+    directory_bytes: Option<u64>,
+}
+
+impl UsageSample {
+    fn usage_over(&self, span: Duration) -> InstanceUsage {
+        let hours = span.as_secs_f64() / 3600.0;
+        let mut usage = InstanceUsage {
+            storage_mb_hours: self.directory_bytes.unwrap_or_default() as f64 / 1024.0 / 1024.0
+                * hours,
+            ..Default::default()
+        };
+        if self.running {
+            usage.running_hours = hours;
+            usage.memory_mb_hours =
+                self.memory_bytes.unwrap_or_default() as f64 / 1024.0 / 1024.0 * hours;
+            usage.cpu_seconds =
+                f64::from(self.cpu_percent.unwrap_or_default()) / 100.0 * span.as_secs_f64();
+        }
+        usage
+    }
+}
+
+/// A month as `YYYY-MM`, in UTC
+pub fn month_of(timestamp: chrono::DateTime<chrono::Utc>) -> String {
+    timestamp.format("%Y-%m").to_string()
+}
+
+fn validate_month(month: &str) -> Result<(), Error> {
+    if chrono::NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").is_err()
+        || month.len() != 7
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Months are written as YYYY-MM"),
+        });
+    }
+    Ok(())
+}
+
+/// Monthly resource usage of every instance, persisted in the stores directory so it outlives
+/// deleted instances and restarts
+pub struct UsageLedger {
+    path_to_store: PathBuf,
+    months: BTreeMap<String, HashMap<InstanceUuid, InstanceUsage>>,
+}
+
+impl UsageLedger {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            months: BTreeMap::new(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.months = BTreeMap::new();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read usage ledger file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.months = serde_json::from_slice(&content).context(format!(
+            "Failed to parse usage ledger file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.months)
+                .context("Failed to serialize usage ledger")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write usage ledger file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Months with usage, oldest first
+    pub fn months(&self) -> Vec<String> {
+        self.months.keys().cloned().collect()
+    }
+
+    fn record(
+        &mut self,
+        month: &str,
+        instance_uuid: &InstanceUuid,
+        instance_name: String,
+        owner: Option<UserId>,
+        usage: &InstanceUsage,
+    ) {
+        let entry = self
+            .months
+            .entry(month.to_string())
+            .or_default()
+            .entry(instance_uuid.clone())
+            .or_insert_with(|| InstanceUsage {
+                instance_uuid: instance_uuid.clone(),
+                ..Default::default()
+            });
+        entry.instance_name = instance_name;
+        if owner.is_some() {
+            entry.owner = owner;
+        }
+        entry.add(usage);
+        while self.months.len() > RETAINED_MONTHS {
+            self.months.pop_first();
+        }
+    }
+
+    /// The usage of every instance over a month, sorted by instance name
+    pub fn instances(&self, month: &str) -> Result<Vec<InstanceUsage>, Error> {
+        validate_month(month)?;
+        let mut ret: Vec<InstanceUsage> = self
+            .months
+            .get(month)
+            .map(|usages| usages.values().cloned().collect())
+            .unwrap_or_default();
+        ret.sort_by(|a, b| {
+            a.instance_name
+                .cmp(&b.instance_name)
+                .then_with(|| a.instance_uuid.as_ref().cmp(b.instance_uuid.as_ref()))
+        });
+        Ok(ret)
+    }
+}
+
+/// Add the usage of instances up per owner, sorted by user id with no owner last
+pub fn usage_per_user(instances: &[InstanceUsage]) -> Vec<UserUsage> {
+    let mut per_user: HashMap<Option<UserId>, UserUsage> = HashMap::new();
+    for usage in instances {
+        let entry = per_user
+            .entry(usage.owner.clone())
+            .or_insert_with(|| UserUsage {
+                user_id: usage.owner.clone(),
+                ..Default::default()
+            });
+        entry.instance_count += 1;
+        entry.running_hours += usage.running_hours;
+        entry.memory_mb_hours += usage.memory_mb_hours;
+        entry.cpu_seconds += usage.cpu_seconds;
+        entry.storage_mb_hours += usage.storage_mb_hours;
+    }
+    let mut ret: Vec<UserUsage> = per_user.into_values().collect();
+    ret.sort_by(|a, b| match (&a.user_id, &b.user_id) {
+        (Some(a), Some(b)) => a.as_ref().cmp(b.as_ref()),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    ret
+}
+
+/// Quote a CSV field if it needs it, and defuse fields a spreadsheet would run as a formula
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+pub fn render_instances_csv(instances: &[InstanceUsage]) -> String {
+    let mut ret = String::from(
+        "instance_uuid,instance_name,owner,running_hours,memory_mb_hours,cpu_seconds,storage_mb_hours\n",
+    );
+    for usage in instances {
+        ret.push_str(&format!(
+            "{},{},{},{:.3},{:.3},{:.3},{:.3}\n",
+            csv_field(usage.instance_uuid.as_ref()),
+            csv_field(&usage.instance_name),
+            csv_field(usage.owner.as_ref().map(AsRef::as_ref).unwrap_or_default()),
+            usage.running_hours,
+            usage.memory_mb_hours,
+            usage.cpu_seconds,
+            usage.storage_mb_hours,
+        ));
+    }
+    ret
+}
+
+pub fn render_users_csv(users: &[UserUsage]) -> String {
+    let mut ret = String::from(
+        "user_id,instance_count,running_hours,memory_mb_hours,cpu_seconds,storage_mb_hours\n",
+    );
+    for usage in users {
+        ret.push_str(&format!(
+            "{},{},{:.3},{:.3},{:.3},{:.3}\n",
+            csv_field(
+                usage
+                    .user_id
+                    .as_ref()
+                    .map(AsRef::as_ref)
+                    .unwrap_or_default()
+            ),
+            usage.instance_count,
+            usage.running_hours,
+            usage.memory_mb_hours,
+            usage.cpu_seconds,
+            usage.storage_mb_hours,
+        ));
+    }
+    ret
+}
+
+async fn sample_instance(
+    instance: &crate::prelude::GameInstance,
+    directory_bytes: Option<u64>,
+) -> UsageSample {
+    let mut sample = UsageSample {
+        directory_bytes,
+        ..Default::default()
+    };
+    if !instance.state().await.is_stopped() {
+        let report = instance.monitor().await;
+        sample.running = true;
+        sample.memory_bytes = report.memory_usage;
+        sample.cpu_percent = report.cpu_usage;
+    }
+    sample
+}
+
+/// Samples every instance once a minute and adds what they used to the month in the ledger
+pub async fn usage_accounting_task(
+    usage_ledger: Arc<Mutex<UsageLedger>>,
+    instances: InstanceMap,
+    directory_sizes: DirectorySizes,
+    user_quotas: Arc<Mutex<UserQuotas>>,
+) {
+    let mut last_sample = Instant::now();
+    loop {
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+        let span = last_sample.elapsed().min(MAX_SAMPLE_SPAN);
+        last_sample = Instant::now();
+        let month = month_of(chrono::Utc::now());
+        let mut samples = Vec::new();
+        for (uuid, instance) in instances.snapshot() {
+            let sample = match tokio::time::timeout(
+                SAMPLE_TIMEOUT,
+                sample_instance(&instance, directory_sizes.get(&uuid)),
+            )
+            .await
+            {
+                Ok(sample) => sample,
+                // a busy instance is still taking up its disk
+                Err(_) => UsageSample {
+                    directory_bytes: directory_sizes.get(&uuid),
+                    ..Default::default()
+                },
+            };
+            samples.push((uuid, instance.name().await, sample));
+        }
+        let user_quotas = user_quotas.lock().await;
+        let mut usage_ledger = usage_ledger.lock().await;
+        for (uuid, name, sample) in samples {
+            let owner = user_quotas.owner_of(&uuid);
+            usage_ledger.record(&month, &uuid, name, owner, &sample.usage_over(span));
+        }
+        drop(user_quotas);
+        if let Err(e) = usage_ledger.write_to_file().await {
+            error!("Failed to write usage ledger : {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        render_instances_csv, usage_per_user, validate_month, InstanceUsage, UsageLedger,
+        UsageSample, RETAINED_MONTHS,
+    };
+    use crate::{auth::user_id::UserId, types::InstanceUuid};
+
+    #[test]
+    fn test_usage_over() {
+        let sample = UsageSample {
+            running: true,
+            memory_bytes: Some(2048 * 1024 * 1024),
+            cpu_percent: Some(150.0),
+            directory_bytes: Some(512 * 1024 * 1024),
+        };
+        let usage = sample.usage_over(Duration::from_secs(1800));
+        assert_eq!(usage.running_hours, 0.5);
+        assert_eq!(usage.memory_mb_hours, 1024.0);
+        assert_eq!(usage.cpu_seconds, 2700.0);
+        assert_eq!(usage.storage_mb_hours, 256.0);
+
+        let stopped = UsageSample {
+            running: false,
+            ..sample
+        }
+        .usage_over(Duration::from_secs(1800));
+        assert_eq!(stopped.running_hours, 0.0);
+        assert_eq!(stopped.cpu_seconds, 0.0);
+        assert_eq!(stopped.storage_mb_hours, 256.0);
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut ledger = UsageLedger::new("unused".into());
+        let a: InstanceUuid = "INSTANCE_a".to_string().into();
+        let b: InstanceUuid = "INSTANCE_b".to_string().into();
+        let alice: UserId = "USER_alice".to_string().into();
+        let hour = InstanceUsage {
+            running_hours: 1.0,
+            cpu_seconds: 10.0,
+            ..Default::default()
+        };
+        ledger.record("2026-01", &a, "lobby".into(), Some(alice.clone()), &hour);
+        ledger.record("2026-01", &a, "hub".into(), None, &hour);
+        ledger.record("2026-01", &b, "=cmd".into(), None, &hour);
+
+        let instances = ledger.instances("2026-01").unwrap();
+        assert_eq!(instances.len(), 2);
+        // the name is the latest one and the owner is kept
+        assert_eq!(instances[1].instance_name, "hub");
+        assert_eq!(instances[1].owner, Some(alice.clone()));
+        assert_eq!(instances[1].running_hours, 2.0);
+
+        let users = usage_per_user(&instances);
+        assert_eq!(users[0].user_id, Some(alice));
+        assert_eq!(users[0].cpu_seconds, 20.0);
+        assert_eq!(users[1].user_id, None);
+        assert_eq!(users[1].instance_count, 1);
+
+        let csv = render_instances_csv(&instances);
+        assert!(csv.contains("INSTANCE_b,'=cmd,,1.000,0.000,10.000,0.000\n"));
+
+        assert!(ledger.instances("2026-02").unwrap().is_empty());
+        assert!(ledger.instances("2026-13").is_err());
+        assert!(validate_month("2026-1").is_err());
+
+        for month in 1..=RETAINED_MONTHS + 2 {
+            let month = format!("{}-{:02}", 2027 + month / 12, month % 12 + 1);
+            ledger.record(&month, &a, "hub".into(), None, &hour);
+        }
+        assert_eq!(ledger.months().len(), RETAINED_MONTHS);
+        assert!(!ledger.months().contains(&"2026-01".to_string()));
+    }
+}
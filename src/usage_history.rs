@@ -0,0 +1,559 @@
+//! The resource usage of instances over time, beyond the few samples of the monitor buffer.
+//!
+//! Samples are rolled up into one minute buckets in memory. Once a minute is over its rollup is
+//! written to the database and merged into the rollups of its hour and of its day, each
+//! resolution being kept for its own retention.
+
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqlitePool;
+use tokio::sync::Mutex;
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    traits::t_server::MonitorReport,
+    types::InstanceUuid,
+};
+
+/// the most rollups a query returns
+pub const MAX_POINTS: i64 = 1500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, TS, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum UsageResolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl UsageResolution {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            UsageResolution::Minute => 60,
+            UsageResolution::Hour => 60 * 60,
+            UsageResolution::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// how long rollups of this resolution are kept, in seconds
+    fn retention(&self) -> i64 {
+        match self {
+            UsageResolution::Minute => 2 * 24 * 60 * 60,
+            UsageResolution::Hour => 90 * 24 * 60 * 60,
+            UsageResolution::Day => 5 * 365 * 24 * 60 * 60,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            UsageResolution::Minute => "minute",
+            UsageResolution::Hour => "hour",
+            UsageResolution::Day => "day",
+        }
+    }
+
+    /// The start of the bucket holding `timestamp`, buckets are aligned on UTC
+    pub fn bucket_start(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.seconds())
+    }
+
+    /// The finest resolution still kept at `start` that covers the range in at most `MAX_POINTS`
+    /// rollups
+    pub fn for_range(start: i64, end: i64, now: i64) -> UsageResolution {
+        [UsageResolution::Minute, UsageResolution::Hour]
+            .into_iter()
+            .find(|resolution| {
+                start >= now - resolution.retention()
+                    && (end - start) / resolution.seconds() < MAX_POINTS
+            })
+            .unwrap_or(UsageResolution::Day)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct UsageStat {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub samples: u32,
+}
+
+impl UsageStat {
+    fn new(value: f64) -> Self {
+        Self {
+            min: value,
+            max: value,
+            avg: value,
+            samples: 1,
+        }
+    }
+
+    fn merge(&mut self, other: &UsageStat) {
+        let samples = self.samples + other.samples;
+        self.avg = (self.avg * self.samples as f64 + other.avg * other.samples as f64)
+            / samples.max(1) as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.samples = samples;
+    }
+}
+
+fn merge_stat(stat: &mut Option<UsageStat>, other: &Option<UsageStat>) {
+    match (stat.as_mut(), other) {
+        (Some(stat), Some(other)) => stat.merge(other),
+        (None, Some(other)) => *stat = Some(*other),
+        (_, None) => {}
+    }
+}
+
+/// The usage of an instance over the bucket starting at `start`, a stat is `None` if no sample
+/// of the bucket had it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct UsageRollup {
+    pub start: i64,
+    pub resolution: UsageResolution,
+    /// in percent of one core
+    pub cpu: Option<UsageStat>,
+    /// in bytes
+    pub memory: Option<UsageStat>,
+    /// size of the instance directory in bytes
+    pub disk: Option<UsageStat>,
+    /// ticks per second, for games that report it
+    pub tps: Option<UsageStat>,
+}
+
+impl UsageRollup {
+    fn empty(start: i64, resolution: UsageResolution) -> Self {
+        Self {
+            start,
+            resolution,
+            cpu: None,
+            memory: None,
+            disk: None,
+            tps: None,
+        }
+    }
+
+    fn from_report(start: i64, resolution: UsageResolution, report: &MonitorReport) -> Self {
+        Self {
+            start,
+            resolution,
+            cpu: report.cpu_usage.map(|cpu| UsageStat::new(cpu as f64)),
+            memory: report
+                .memory_usage
+                .map(|memory| UsageStat::new(memory as f64)),
+            disk: report
+                .directory_size
+                .map(|size| UsageStat::new(size as f64)),
+            tps: report
+                .game_metrics
+                .as_ref()
+                .and_then(|metrics| metrics.tps)
+                .map(UsageStat::new),
+        }
+    }
+
+    pub fn merge(&mut self, other: &UsageRollup) {
+        merge_stat(&mut self.cpu, &other.cpu);
+        merge_stat(&mut self.memory, &other.memory);
+        merge_stat(&mut self.disk, &other.disk);
+        merge_stat(&mut self.tps, &other.tps);
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, TS)]
+#[ts(export)]
+pub struct UsageHistoryQuery {
+    /// unix timestamp in seconds, a day before `end` by default
+    pub start: Option<i64>,
+    /// unix timestamp in seconds, now by default
+    pub end: Option<i64>,
+    /// picked from the range if not given
+    pub resolution: Option<UsageResolution>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, TS, JsonSchema)]
+#[ts(export)]
+pub struct UsageHistoryPage {
+    pub resolution: UsageResolution,
+    /// oldest first
+    pub points: Vec<UsageRollup>,
+    /// the usage over the whole range, `None` if there is no sample in it
+    pub summary: Option<UsageRollup>,
+}
+
+/// The minute rollups still being filled, per instance
+#[derive(Clone, Default)]
+pub struct UsageHistory {
+    open: Arc<Mutex<HashMap<InstanceUuid, UsageRollup>>>,
+}
+
+impl UsageHistory {
+    /// Add a sample taken at `now` to the minute rollup of the instance
+    pub async fn record(&self, uuid: &InstanceUuid, report: &MonitorReport, now: i64) {
+        let start = UsageResolution::Minute.bucket_start(now);
+        let sample = UsageRollup::from_report(start, UsageResolution::Minute, report);
+        let mut open = self.open.lock().await;
+        match open.get_mut(uuid) {
+            Some(rollup) if rollup.start == start => rollup.merge(&sample),
+            // only if the clock went back, the sample is dropped
+            Some(_) => {}
+            None => {
+                open.insert(uuid.clone(), sample);
+            }
+        }
+    }
+
+    /// Take the minute rollups that are over at `now`
+    pub async fn close_finished(&self, now: i64) -> Vec<(InstanceUuid, UsageRollup)> {
+        let current = UsageResolution::Minute.bucket_start(now);
+        let mut open = self.open.lock().await;
+        let finished: Vec<InstanceUuid> = open
+            .iter()
+            .filter(|(_, rollup)| rollup.start < current)
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        finished
+            .into_iter()
+            .filter_map(|uuid| open.remove(&uuid).map(|rollup| (uuid, rollup)))
+            .collect()
+    }
+
+    /// The usage of an instance over the range of the query, the minute still being filled
+    /// included
+    pub async fn query(
+        &self,
+        pool: &SqlitePool,
+        uuid: &InstanceUuid,
+        query: &UsageHistoryQuery,
+        now: i64,
+    ) -> Result<UsageHistoryPage, Error> {
+        let end = query.end.unwrap_or(now);
+        let start = query.start.unwrap_or(end - UsageResolution::Day.seconds());
+        let resolution = query
+            .resolution
+            .unwrap_or_else(|| UsageResolution::for_range(start, end, now));
+        let mut points = read_usage_rollups(pool, uuid, resolution, start, end).await?;
+        if resolution == UsageResolution::Minute {
+            if let Some(open) = self.open.lock().await.get(uuid) {
+                if open.start >= UsageResolution::Minute.bucket_start(start)
+                    && open.start < end
+                    && points.last().is_none_or(|last| last.start < open.start)
+                {
+                    points.push(open.clone());
+                }
+            }
+        }
+        let summary = points.iter().fold(None, |summary, point| {
+            let mut summary = summary.unwrap_or_else(|| UsageRollup::empty(start, resolution));
+            summary.merge(point);
+            Some(summary)
+        });
+        Ok(UsageHistoryPage {
+            resolution,
+            points,
+            summary,
+        })
+    }
+}
+
+pub async fn init_usage_rollups_table(pool: &SqlitePool) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS UsageRollups (
+            instance_id     TEXT        NOT NULL,
+            resolution      VARCHAR(10) NOT NULL,
+            start           BIGINT      NOT NULL,
+            value           TEXT        NOT NULL,
+            PRIMARY KEY (instance_id, resolution, start)
+        );
+        "#,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create table")?;
+    // pruning goes through every instance
+    sqlx::query(
+        r#"
+        CREATE INDEX IF NOT EXISTS UsageRollupsResolutionStart
+        ON UsageRollups (resolution, start);
+        "#,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+    Ok(())
+}
+
+/// Write a finished minute rollup and merge it into the rollups of its hour and day
+pub async fn write_minute_rollup(
+    pool: &SqlitePool,
+    uuid: &InstanceUuid,
+    minute: &UsageRollup,
+) -> Result<(), Error> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction")?;
+    for resolution in [
+        UsageResolution::Minute,
+        UsageResolution::Hour,
+        UsageResolution::Day,
+    ] {
+        let start = resolution.bucket_start(minute.start);
+        let existing: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT value FROM UsageRollups
+            WHERE instance_id = ?1 AND resolution = ?2 AND start = ?3
+            "#,
+        )
+        .bind(uuid.as_ref())
+        .bind(resolution.as_str())
+        .bind(start)
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to read usage rollup")?;
+        let mut rollup = match existing {
+            Some(value) => serde_json::from_str(&value).context("Failed to parse usage rollup")?,
+            None => UsageRollup::empty(start, resolution),
+        };
+        rollup.merge(minute);
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO UsageRollups (instance_id, resolution, start, value)
+            VALUES (?1, ?2, ?3, ?4)
+            "#,
+        )
+        .bind(uuid.as_ref())
+        .bind(resolution.as_str())
+        .bind(start)
+        .bind(serde_json::to_string(&rollup).context("Failed to serialize usage rollup")?)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to write usage rollup")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+    Ok(())
+}
+
+/// The rollups of an instance starting in `[start, end)`, oldest first
+pub async fn read_usage_rollups(
+    pool: &SqlitePool,
+    uuid: &InstanceUuid,
+    resolution: UsageResolution,
+    start: i64,
+    end: i64,
+) -> Result<Vec<UsageRollup>, Error> {
+    if end <= start {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("The end of the range must be after its start"),
+        });
+    }
+    if (end - start) / resolution.seconds() > MAX_POINTS {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "The range holds more than {MAX_POINTS} points at this resolution, use a coarser one"
+            ),
+        });
+    }
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT value FROM UsageRollups
+        WHERE instance_id = ?1 AND resolution = ?2 AND start >= ?3 AND start < ?4
+        ORDER BY start ASC
+        "#,
+    )
+    .bind(uuid.as_ref())
+    .bind(resolution.as_str())
+    .bind(resolution.bucket_start(start))
+    .bind(end)
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to read usage rollups")?;
+    rows.iter()
+        .map(|row| {
+            serde_json::from_str(row)
+                .context("Failed to parse usage rollup")
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+/// Delete the rollups past the retention of their resolution, returns the number deleted
+pub async fn prune_usage_rollups(pool: &SqlitePool, now: i64) -> Result<u64, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    let mut deleted = 0;
+    for resolution in [
+        UsageResolution::Minute,
+        UsageResolution::Hour,
+        UsageResolution::Day,
+    ] {
+        deleted += sqlx::query("DELETE FROM UsageRollups WHERE resolution = ?1 AND start < ?2")
+            .bind(resolution.as_str())
+            .bind(now - resolution.retention())
+            .execute(&mut connection)
+            .await
+            .context("Failed to delete old usage rollups")?
+            .rows_affected();
+    }
+    Ok(deleted)
+}
+
+pub async fn remove_instance_usage(pool: &SqlitePool, uuid: &InstanceUuid) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query("DELETE FROM UsageRollups WHERE instance_id = ?1")
+        .bind(uuid.as_ref())
+        .execute(&mut connection)
+        .await
+        .context("Failed to delete usage rollups")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        init_usage_rollups_table, prune_usage_rollups, read_usage_rollups, write_minute_rollup,
+        UsageHistory, UsageHistoryQuery, UsageResolution,
+    };
+    use crate::{traits::t_server::MonitorReport, types::InstanceUuid};
+
+    fn report(cpu: f32, memory: u64) -> MonitorReport {
+        MonitorReport {
+            cpu_usage: Some(cpu),
+            memory_usage: Some(memory),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolution() {
+        assert_eq!(UsageResolution::Minute.bucket_start(3_659), 3_600);
+        assert_eq!(UsageResolution::Hour.bucket_start(7_199), 3_600);
+        assert_eq!(UsageResolution::Day.bucket_start(-1), -86_400);
+
+        let now = 100 * 24 * 60 * 60;
+        assert_eq!(
+            UsageResolution::for_range(now - 6 * 60 * 60, now, now),
+            UsageResolution::Minute
+        );
+        assert_eq!(
+            UsageResolution::for_range(now - 7 * 24 * 60 * 60, now, now),
+            UsageResolution::Hour
+        );
+        // past the retention of hour rollups
+        assert_eq!(
+            UsageResolution::for_range(0, 24 * 60 * 60, now),
+            UsageResolution::Day
+        );
+    }
+
+    #[tokio::test]
+    async fn test_usage_history() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_usage_rollups_table(&pool).await.unwrap();
+        let uuid = InstanceUuid::default();
+        let history = UsageHistory::default();
+        let now = 1_000 * 24 * 60 * 60;
+
+        history.record(&uuid, &report(10.0, 1000), now).await;
+        history.record(&uuid, &report(30.0, 3000), now + 30).await;
+        assert!(history.close_finished(now + 59).await.is_empty());
+        let finished = history.close_finished(now + 60).await;
+        assert_eq!(finished.len(), 1);
+        let minute = &finished[0].1;
+        let cpu = minute.cpu.unwrap();
+        assert_eq!(
+            (cpu.min, cpu.max, cpu.avg, cpu.samples),
+            (10.0, 30.0, 20.0, 2)
+        );
+        write_minute_rollup(&pool, &uuid, minute).await.unwrap();
+
+        history.record(&uuid, &report(60.0, 8000), now + 60).await;
+        let (_, minute) = history.close_finished(now + 120).await.remove(0);
+        write_minute_rollup(&pool, &uuid, &minute).await.unwrap();
+
+        let minutes = read_usage_rollups(&pool, &uuid, UsageResolution::Minute, now, now + 3600)
+            .await
+            .unwrap();
+        assert_eq!(minutes.len(), 2);
+        let hours = read_usage_rollups(&pool, &uuid, UsageResolution::Hour, now, now + 3600)
+            .await
+            .unwrap();
+        assert_eq!(hours.len(), 1);
+        let memory = hours[0].memory.unwrap();
+        assert_eq!(
+            (memory.max, memory.avg, memory.samples),
+            (8000.0, 4000.0, 3)
+        );
+
+        // the open minute is part of the page
+        history.record(&uuid, &report(50.0, 2000), now + 150).await;
+        let page = history
+            .query(
+                &pool,
+                &uuid,
+                &UsageHistoryQuery {
+                    start: Some(now),
+                    end: None,
+                    resolution: None,
+                },
+                now + 170,
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.resolution, UsageResolution::Minute);
+        assert_eq!(page.points.len(), 3);
+        assert_eq!(page.summary.unwrap().cpu.unwrap().max, 60.0);
+
+        // minutes are kept for two days, hours for longer
+        assert_eq!(
+            prune_usage_rollups(&pool, now + 3 * 24 * 60 * 60)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            read_usage_rollups(&pool, &uuid, UsageResolution::Day, now, now + 3600)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(read_usage_rollups(
+            &pool,
+            &uuid,
+            UsageResolution::Minute,
+            now,
+            now + 365 * 86400
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    auth::{user::User, user_id::UserId},
+    disk_usage::DirectorySizes,
+    error::{Error, ErrorKind},
+    instance_map::InstanceMap,
+    traits::{
+        t_configurable::TConfigurable,
+        t_server::{State, TServer},
+    },
+    types::InstanceUuid,
+};
+
+/// how long an instance being set up counts against the quota of its owner before it shows up,
+/// setups that failed stop counting after that
+const SETUP_GRACE_PERIOD: i64 = 60 * 60;
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Limits on what a user can use of the core, `None` is unlimited. The owner has no quota.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct UserQuota {
+    pub max_instances: Option<u32>,
+    /// in megabytes, the memory limits of the running instances of the user added up. Instances
+    /// of a user with this quota need a memory limit to start
+    pub max_memory: Option<u64>,
+    /// in megabytes, the directories of the instances of the user added up
+    pub max_disk: Option<u64>,
+}
+
+impl UserQuota {
+    fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a user uses of their quota
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+#[ts(export)]
+pub struct UserQuotaUsage {
+    pub quota: UserQuota,
+    pub instances: u32,
+    /// in megabytes
+    pub memory: u64,
+    /// in megabytes
+    pub disk: u64,
+}
+
+impl UserQuotaUsage {
+    /// Fail if `instances` more instances using `memory` more megabytes would go over the quota.
+    /// Being over the disk quota blocks anything new.
+    fn check(&self, instances: u32, memory: u64) -> Result<(), Error> {
+        let exceeded = |message: String| {
+            Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("{message}"),
+            })
+        };
+        if let Some(max_instances) = self.quota.max_instances {
+            if instances > 0 && self.instances + instances > max_instances {
+                return exceeded(format!(
+                    "You can have at most {max_instances} instances, delete one first"
+                ));
+            }
+        }
+        if let Some(max_memory) = self.quota.max_memory {
+            if memory > 0 && self.memory + memory > max_memory {
+                return exceeded(format!(
+                    "Your running instances can use at most {max_memory} MB of memory, {} MB are in use",
+                    self.memory
+                ));
+            }
+        }
+        if let Some(max_disk) = self.quota.max_disk {
+            if self.disk >= max_disk {
+                return exceeded(format!(
+                    "Your instances use {} MB of disk, over your quota of {max_disk} MB",
+                    self.disk
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct InstanceOwner {
+    user_id: UserId,
+    created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct UserQuotasData {
+    quotas: HashMap<UserId, UserQuota>,
+    /// who created each instance, instances from before quotas existed belong to no one
+    owners: HashMap<InstanceUuid, InstanceOwner>,
+}
+
+/// Per user quotas and the owners of the instances they count, persisted in the stores directory
+pub struct UserQuotas {
+    path_to_store: PathBuf,
+    data: UserQuotasData,
+}
+
+impl UserQuotas {
+    pub fn new(path_to_store: PathBuf) -> Self {
+        Self {
+            path_to_store,
+            data: UserQuotasData::default(),
+        }
+    }
+
+    pub async fn load_from_file(&mut self) -> Result<(), Error> {
+        if !self.path_to_store.exists() {
+            self.data = UserQuotasData::default();
+            return Ok(());
+        }
+        let content = tokio::fs::read(&self.path_to_store).await.context(format!(
+            "Failed to read user quotas file at {}",
+            self.path_to_store.display()
+        ))?;
+        self.data = serde_json::from_slice(&content).context(format!(
+            "Failed to parse user quotas file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub(crate) async fn write_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_store,
+            serde_json::to_string_pretty(&self.data).context("Failed to serialize user quotas")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write user quotas file at {}",
+            self.path_to_store.display()
+        ))?;
+        Ok(())
+    }
+
+    pub fn quota(&self, user_id: &UserId) -> UserQuota {
+        self.data.quotas.get(user_id).copied().unwrap_or_default()
+    }
+
+    pub async fn set_quota(&mut self, user_id: &UserId, quota: UserQuota) -> Result<(), Error> {
+        if quota.max_memory == Some(0) || quota.max_disk == Some(0) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Memory and disk quotas cannot be 0"),
+            });
+        }
+        let old = if quota.is_unlimited() {
+            self.data.quotas.remove(user_id)
+        } else {
+            self.data.quotas.insert(user_id.clone(), quota)
+        };
+        if let Err(e) = self.write_to_file().await {
+            match old {
+                Some(old) => self.data.quotas.insert(user_id.clone(), old),
+                None => self.data.quotas.remove(user_id),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn owner_of(&self, instance_uuid: &InstanceUuid) -> Option<UserId> {
+        self.data
+            .owners
+            .get(instance_uuid)
+            .map(|owner| owner.user_id.clone())
+    }
+
+    /// The instances of a user, counting the ones still being set up
+    fn owned(&self, user_id: &UserId, instances: &InstanceMap, now: i64) -> Vec<InstanceUuid> {
+        self.data
+            .owners
+            .iter()
+            .filter(|(uuid, owner)| {
+                &owner.user_id == user_id
+                    && (instances.contains_key(uuid) || now - owner.created_at < SETUP_GRACE_PERIOD)
+            })
+            .map(|(uuid, _)| uuid.clone())
+            .collect()
+    }
+
+    pub async fn usage(
+        &self,
+        user_id: &UserId,
+        instances: &InstanceMap,
+        directory_sizes: &DirectorySizes,
+    ) -> UserQuotaUsage {
+        let owned = self.owned(user_id, instances, chrono::Utc::now().timestamp());
+        let mut memory = 0;
+        let mut disk = 0;
+        for uuid in &owned {
+            disk += directory_sizes.get(uuid).unwrap_or_default();
+            if let Some(instance) = instances.get(uuid) {
+                if !instance.state().await.is_stopped() {
+                    memory += instance
+                        .resource_limits()
+                        .await
+                        .memory_limit
+                        .unwrap_or_default();
+                }
+            }
+        }
+        UserQuotaUsage {
+            quota: self.quota(user_id),
+            instances: owned.len() as u32,
+            memory,
+            disk: disk / MEGABYTE,
+        }
+    }
+
+    /// Check that `user` can create another instance and make them the owner of it
+    pub async fn reserve_instance(
+        &mut self,
+        user: &User,
+        instance_uuid: &InstanceUuid,
+        instances: &InstanceMap,
+        directory_sizes: &DirectorySizes,
+    ) -> Result<(), Error> {
+        if !user.is_owner {
+            self.usage(&user.uid, instances, directory_sizes)
+                .await
+                .check(1, 0)?;
+        }
+        let now = chrono::Utc::now().timestamp();
+        // failed setups that stopped counting are forgotten
+        self.data.owners.retain(|uuid, owner| {
+            instances.contains_key(uuid) || now - owner.created_at < SETUP_GRACE_PERIOD
+        });
+        self.data.owners.insert(
+            instance_uuid.clone(),
+            InstanceOwner {
+                user_id: user.uid.clone(),
+                created_at: now,
+            },
+        );
+        if let Err(e) = self.write_to_file().await {
+            self.data.owners.remove(instance_uuid);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Check that starting an instance keeps its owner within their quota, whoever starts it
+    pub async fn check_start(
+        &self,
+        instance_uuid: &InstanceUuid,
+        instances: &InstanceMap,
+        directory_sizes: &DirectorySizes,
+    ) -> Result<(), Error> {
+        let (owner, instance) = match (self.owner_of(instance_uuid), instances.get(instance_uuid)) {
+            (Some(owner), Some(instance)) => (owner, instance),
+            _ => return Ok(()),
+        };
+        let quota = self.quota(&owner);
+        if quota.is_unlimited() || !instance.state().await.is_stopped() {
+            return Ok(());
+        }
+        let memory = instance.resource_limits().await.memory_limit;
+        if quota.max_memory.is_some() && memory.is_none() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!(
+                    "Set a memory limit on the instance to start it within the memory quota"
+                ),
+            });
+        }
+        self.usage(&owner, instances, directory_sizes)
+            .await
+            .check(0, memory.unwrap_or_default())
+    }
+
+    /// Forget the owner of a deleted instance
+    pub async fn remove_instance(&mut self, instance_uuid: &InstanceUuid) -> Result<(), Error> {
+        if let Some(old) = self.data.owners.remove(instance_uuid) {
+            if let Err(e) = self.write_to_file().await {
+                self.data.owners.insert(instance_uuid.clone(), old);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the quota of a deleted user, their instances then belong to no one
+    pub async fn remove_user(&mut self, user_id: &UserId) -> Result<(), Error> {
+        let old_quotas = self.data.quotas.clone();
+        let old_owners = self.data.owners.clone();
+        self.data.quotas.remove(user_id);
+        self.data
+            .owners
+            .retain(|_, owner| &owner.user_id != user_id);
+        if let Err(e) = self.write_to_file().await {
+            self.data.quotas = old_quotas;
+            self.data.owners = old_owners;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UserQuota, UserQuotaUsage};
+
+    #[test]
+    fn test_check_quota() {
+        let usage = UserQuotaUsage {
+            quota: UserQuota {
+                max_instances: Some(3),
+                max_memory: Some(8192),
+                max_disk: Some(10_000),
+            },
+            instances: 2,
+            memory: 4096,
+            disk: 5_000,
+        };
+        assert!(usage.check(1, 0).is_ok());
+        assert!(usage.check(2, 0).is_err());
+        assert!(usage.check(0, 4096).is_ok());
+        assert!(usage.check(0, 4097).is_err());
+
+        let full = UserQuotaUsage {
+            instances: 3,
+            disk: 10_000,
+            ..usage
+        };
+        // a full instance quota doesn't stop the instances from starting
+        assert!(full.check(1, 0).is_err());
+        assert!(UserQuotaUsage { disk: 0, ..full }.check(0, 1024).is_ok());
+        assert!(full.check(0, 1024).is_err());
+
+        assert!(UserQuotaUsage {
+            instances: 100,
+            memory: 100_000,
+            disk: 100_000,
+            ..Default::default()
+        }
+        .check(1, 1024)
+        .is_ok());
+    }
+}
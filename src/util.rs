@@ -6,10 +6,7 @@ use std::io::{Read, Write};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use std::path::{Path, PathBuf};
-use tokio::io::AsyncWriteExt;
 
-use futures_util::StreamExt;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
@@ -22,6 +19,7 @@ pub struct Authentication {
     password: String,
 }
 
+use crate::download_manager::{DownloadManager, DownloadRequest};
 use crate::error::Error;
 use crate::prelude::path_to_tmp;
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -38,6 +36,7 @@ pub struct DownloadProgress {
     pub step: u64,
     pub download_name: String,
 }
+/// Download through the download manager, see `DownloadManager::download`
 pub async fn download_file(
     url: &str,
     path: &Path,
@@ -45,86 +44,13 @@ pub async fn download_file(
     on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
     overwrite_old: bool,
 ) -> Result<PathBuf, Error> {
-    let lodestone_tmp = path_to_tmp().clone();
-    tokio::fs::create_dir_all(&lodestone_tmp)
-        .await
-        .context("Failed to create tmp dir")?;
-    let temp_file_path = tempfile::NamedTempFile::new_in(lodestone_tmp)
-        .context("Failed to create temporary file")?
-        .path()
-        .to_owned();
-    let mut temp_file = tokio::fs::File::create(&temp_file_path)
-        .await
-        .context("Failed to create temporary file")?;
-    let client = Client::new();
-    let response = client
-        .get(url)
-        .send()
-        .await
-        .context("Failed to send GET request")?;
-    response.error_for_status_ref().context(
-        "
-        Failed to download file
-    ",
-    )?;
-    tokio::fs::create_dir_all(path)
-        .await
-        .context(format!("Failed to create dir {}", &path.display()))?;
-
-    let file_name;
+    let mut request = DownloadRequest::new(url, path).overwrite_old(overwrite_old);
     if let Some(name) = name_override {
-        file_name = name.to_string();
-    } else {
-        file_name = response
-            .headers()
-            .get("Content-Disposition")
-            .map_or_else(
-                || "unknown".to_string(),
-                |h| {
-                    h.to_str()
-                        .map_or_else(|_| "unknown".to_string(), |s| s.to_string())
-                },
-            )
-            // parse filename's value from the header, remove the ""
-            .split(';')
-            .nth(1)
-            .unwrap_or("unknown")
-            .split('=')
-            .nth(1)
-            .unwrap_or("unknown")
-            .replace('\"', "");
-    }
-    if !overwrite_old && path.join(&file_name).exists() {
-        return Err(eyre!("File {} already exists", path.join(&file_name).display()).into());
-    }
-    let total_size = response.content_length();
-
-    let mut downloaded: u64 = 0;
-    let mut new_downloaded: u64 = 0;
-    let threshold = total_size.unwrap_or(500000) / 100;
-    let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let chunk = item.context("Failed to read response")?;
-        temp_file
-            .write_all(&chunk)
-            .await
-            .context(format!("Failed to write to file {}", &file_name))?;
-        new_downloaded += chunk.len() as u64;
-        let step = new_downloaded - downloaded;
-        if step > threshold {
-            on_download(DownloadProgress {
-                total: total_size,
-                downloaded,
-                step,
-                download_name: file_name.clone(),
-            });
-            downloaded = new_downloaded;
-        }
+        request = request.name(name);
     }
-    tokio::fs::rename(temp_file_path, path.join(&file_name))
+    DownloadManager::global()
+        .download(request, on_download)
         .await
-        .context(format!("Failed to rename file {}", &file_name))?;
-    Ok(path.join(&file_name))
 }
 
 /// List all files in a directory
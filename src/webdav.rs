@@ -0,0 +1,235 @@
+//! The WebDAV side of the file bridge: paths, headers and the multistatus responses of
+//! `PROPFIND`, so instance directories can be mounted in an IDE or a file manager.
+//!
+//! Only class 1 is spoken, there are no locks and no properties besides the live ones of files.
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+
+/// `Depth` of a `PROPFIND`, `infinity` is answered like `1` as most servers do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Zero,
+    One,
+}
+
+impl Depth {
+    pub fn from_header(header: Option<&str>) -> Self {
+        match header.map(str::trim) {
+            Some("0") => Depth::Zero,
+            _ => Depth::One,
+        }
+    }
+}
+
+/// A file or directory as listed by `PROPFIND`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DavEntry {
+    /// already percent encoded, ends with a `/` for directories
+    pub href: String,
+    pub display_name: String,
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl DavEntry {
+    pub async fn from_path(
+        href: String,
+        display_name: String,
+        path: &Path,
+    ) -> std::io::Result<Self> {
+        let metadata = tokio::fs::metadata(path).await?;
+        Ok(Self {
+            href,
+            display_name,
+            is_dir: metadata.is_dir(),
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~".contains(&byte)
+}
+
+/// Percent encode every segment of `path`, keeping the slashes between them
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte == b'/' || is_unreserved(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// `None` if an escape is malformed or the result isn't UTF-8
+pub fn decode_path(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// The path of a `Destination` header relative to `prefix`, the path the bridge is served
+/// under. The header may be a whole URL or only its path
+pub fn destination_path(destination: &str, prefix: &str) -> Option<String> {
+    let path = match url::Url::parse(destination) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => destination.to_string(),
+    };
+    let path = decode_path(&path)?;
+    let relative = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    if !relative.is_empty() && !relative.starts_with('/') {
+        return None;
+    }
+    Some(relative.trim_matches('/').to_string())
+}
+
+/// The instance uuid and the path inside its directory of a path under the bridge, `None` for
+/// the root that lists the instances
+pub fn split_instance_path(path: &str) -> Option<(String, PathBuf)> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return None;
+    }
+    let (uuid, relative) = path.split_once('/').unwrap_or((path, ""));
+    Some((uuid.to_string(), PathBuf::from(relative)))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// The `207 Multi-Status` body of a `PROPFIND`
+pub fn multistatus(entries: &[DavEntry]) -> String {
+    let mut xml =
+        String::from(r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:">"#);
+    for entry in entries {
+        xml.push_str("<D:response><D:href>");
+        xml.push_str(&escape_xml(&entry.href));
+        xml.push_str("</D:href><D:propstat><D:prop><D:displayname>");
+        xml.push_str(&escape_xml(&entry.display_name));
+        xml.push_str("</D:displayname>");
+        if entry.is_dir {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            xml.push_str(&format!(
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>",
+                entry.len
+            ));
+        }
+        if let Some(modified) = entry.modified {
+            xml.push_str(&format!(
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                http_date(modified)
+            ));
+        }
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>");
+    }
+    xml.push_str("</D:multistatus>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::UNIX_EPOCH};
+
+    use super::{
+        decode_path, destination_path, encode_path, multistatus, split_instance_path, DavEntry,
+        Depth,
+    };
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(encode_path("/dav/a b/ü&.txt"), "/dav/a%20b/%C3%BC%26.txt");
+        assert_eq!(
+            decode_path("/dav/a%20b/%C3%BC%26.txt").unwrap(),
+            "/dav/a b/ü&.txt"
+        );
+        assert_eq!(decode_path("/bad%2"), None);
+
+        assert_eq!(
+            destination_path(
+                "https://core.example:16662/api/v1/dav/INSTANCE_a/world%201/level.dat",
+                "/api/v1/dav"
+            )
+            .unwrap(),
+            "INSTANCE_a/world 1/level.dat"
+        );
+        assert_eq!(
+            destination_path("/api/v1/dav/INSTANCE_a/", "/api/v1/dav/").unwrap(),
+            "INSTANCE_a"
+        );
+        assert_eq!(destination_path("/api/v1/davx/a", "/api/v1/dav"), None);
+        assert_eq!(destination_path("/elsewhere/a", "/api/v1/dav"), None);
+
+        assert_eq!(split_instance_path("/"), None);
+        assert_eq!(
+            split_instance_path("INSTANCE_a"),
+            Some(("INSTANCE_a".to_string(), PathBuf::new()))
+        );
+        assert_eq!(
+            split_instance_path("/INSTANCE_a/config/server.properties"),
+            Some((
+                "INSTANCE_a".to_string(),
+                PathBuf::from("config/server.properties")
+            ))
+        );
+
+        assert_eq!(Depth::from_header(Some("0")), Depth::Zero);
+        assert_eq!(Depth::from_header(Some("infinity")), Depth::One);
+        assert_eq!(Depth::from_header(None), Depth::One);
+    }
+
+    #[test]
+    fn test_multistatus() {
+        let xml = multistatus(&[
+            DavEntry {
+                href: "/dav/INSTANCE_a/".to_string(),
+                display_name: "Survival & Co".to_string(),
+                is_dir: true,
+                len: 0,
+                modified: None,
+            },
+            DavEntry {
+                href: "/dav/INSTANCE_a/eula.txt".to_string(),
+                display_name: "eula.txt".to_string(),
+                is_dir: false,
+                len: 9,
+                modified: Some(UNIX_EPOCH),
+            },
+        ]);
+        assert!(xml.contains("<D:displayname>Survival &amp; Co</D:displayname><D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(xml.contains("<D:getcontentlength>9</D:getcontentlength><D:getlastmodified>Thu, 01 Jan 1970 00:00:00 GMT</D:getlastmodified>"));
+        assert_eq!(xml.matches("<D:response>").count(), 2);
+    }
+}